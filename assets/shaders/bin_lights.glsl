@@ -0,0 +1,84 @@
+// One thread per light grid cell, testing all lights against it; see `light_culling.rs`.
+
+#include "view_constants.inc"
+#include "light_grid.inc"
+
+layout(std430) readonly buffer view_constants {
+    ViewConstants view;
+};
+
+layout(std430) writeonly buffer outputBuf {
+    uint cell_data[];
+};
+
+// View space direction through `uv`, scaled to a view distance of 1
+vec3 view_dir_at(vec2 uv) {
+    vec4 p = view.clip_to_view * vec4(rtoy_uv_to_ndc(uv), RTOY_DEPTH_NEAR, 1.0);
+    vec3 dir = p.xyz / p.w;
+    return dir / -dir.z;
+}
+
+// Inverse of `light_grid_slice`; the first and last slices extend to 0 and infinity
+float slice_start_dist(uint slice) {
+    LightGridParams grid = light_grid;
+    if (slice == 0u) {
+        return 0.0;
+    } else if (slice >= grid.depth_slices) {
+        return 1e30;
+    }
+    float t = float(slice) / float(grid.depth_slices);
+    return grid.near_dist * pow(grid.far_dist / grid.near_dist, t);
+}
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+void main() {
+    LightGridParams grid = light_grid;
+    uvec3 cell_coord = gl_GlobalInvocationID;
+    uvec3 cell_count = uvec3(grid.tile_count_x, grid.tile_count_y, grid.depth_slices);
+    if (any(greaterThanEqual(cell_coord, cell_count))) {
+        return;
+    }
+
+    uint cell = (cell_coord.z * cell_count.y + cell_coord.y) * cell_count.x + cell_coord.x;
+    uint cell_offset = cell * (grid.max_lights_per_cell + 1u);
+
+    vec2 uv0 = vec2(cell_coord.xy * grid.tile_size) / grid.output_size;
+    vec2 uv1 = min(vec2((cell_coord.xy + 1u) * grid.tile_size) / grid.output_size, vec2(1.0));
+
+    vec3 corners[4] = vec3[4](
+        view_dir_at(uv0),
+        view_dir_at(vec2(uv1.x, uv0.y)),
+        view_dir_at(uv1),
+        view_dir_at(vec2(uv0.x, uv1.y))
+    );
+    vec3 center = view_dir_at((uv0 + uv1) * 0.5);
+
+    // Side planes through the eye, facing into the tile
+    vec3 planes[4];
+    for (int i = 0; i < 4; ++i) {
+        vec3 n = normalize(cross(corners[i], corners[(i + 1) % 4]));
+        planes[i] = dot(n, center) < 0.0 ? -n : n;
+    }
+
+    float min_dist = slice_start_dist(cell_coord.z);
+    float max_dist = slice_start_dist(cell_coord.z + 1u);
+
+    uint count = 0u;
+    for (uint light_idx = 0u; light_idx < grid.light_count; ++light_idx) {
+        vec4 position_radius = light_grid_light_list[light_idx].position_radius;
+        vec3 pos = (view.world_to_view * vec4(position_radius.xyz, 1.0)).xyz;
+        float radius = position_radius.w;
+
+        bool overlaps = -pos.z + radius >= min_dist && -pos.z - radius <= max_dist;
+        for (int i = 0; i < 4; ++i) {
+            overlaps = overlaps && dot(planes[i], pos) >= -radius;
+        }
+
+        if (overlaps && count < grid.max_lights_per_cell) {
+            cell_data[cell_offset + 1u + count] = light_idx;
+            ++count;
+        }
+    }
+
+    cell_data[cell_offset] = count;
+}
@@ -0,0 +1,52 @@
+uniform texture2D texA;
+uniform texture2D texB;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint mode;
+    float divider;
+    float diff_scale;
+};
+
+#define MODE_SIDE_BY_SIDE 0
+#define MODE_WIPE 1
+#define MODE_DIFFERENCE 2
+
+#include "color.inc"
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    int width = int(outputTex_size.x);
+    int divider_x = int(divider * outputTex_size.x);
+    vec4 col;
+
+    if (MODE_SIDE_BY_SIDE == mode) {
+        // Both halves show the same region of their respective images, centered on the divider
+        int half_width = width / 2;
+        bool is_right = pix.x >= half_width;
+        int src_x = (is_right ? pix.x - half_width : pix.x) + divider_x - half_width / 2;
+        ivec2 src = ivec2(clamp(src_x, 0, width - 1), pix.y);
+
+        col = is_right ? texelFetch(texB, src, 0) : texelFetch(texA, src, 0);
+        if (pix.x == half_width) {
+            col = vec4(1.0);
+        }
+    } else if (MODE_WIPE == mode) {
+        col = pix.x < divider_x ? texelFetch(texA, pix, 0) : texelFetch(texB, pix, 0);
+        if (pix.x == divider_x) {
+            col = vec4(1.0);
+        }
+    } else {
+        vec3 a = texelFetch(texA, pix, 0).rgb;
+        vec3 b = texelFetch(texB, pix, 0).rgb;
+        float diff = calculate_luma(abs(a - b)) * diff_scale;
+
+        // Blue for no difference, through green and yellow, to red
+        vec3 heat = hsv_to_rgb(vec3((1.0 - clamp(diff, 0.0, 1.0)) * 0.66, 1.0, 1.0));
+        col = vec4(heat, 1.0);
+    }
+
+    imageStore(outputTex, pix, col);
+}
@@ -0,0 +1,47 @@
+// Blends a layer over the image composited so far; see `composite.rs`.
+
+uniform texture2D dstTex;
+uniform texture2D layerTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint blend_mode;
+    float opacity;
+};
+
+#define BLEND_ALPHA 0
+#define BLEND_PREMULTIPLIED_ALPHA 1
+#define BLEND_ADD 2
+#define BLEND_MULTIPLY 3
+#define BLEND_SCREEN 4
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    // Layers of a different size get stretched over the output
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec4 dst = texelFetch(dstTex, pix, 0);
+    vec4 src = textureLod(sampler2D(layerTex, linear_clamp_sampler), uv, 0);
+    float coverage = clamp(src.a * opacity, 0.0, 1.0);
+
+    vec3 col;
+    if (BLEND_PREMULTIPLIED_ALPHA == blend_mode) {
+        col = dst.rgb * (1.0 - coverage) + src.rgb * opacity;
+    } else if (BLEND_ADD == blend_mode) {
+        col = dst.rgb + src.rgb * coverage;
+    } else if (BLEND_MULTIPLY == blend_mode) {
+        col = mix(dst.rgb, dst.rgb * src.rgb, coverage);
+    } else if (BLEND_SCREEN == blend_mode) {
+        col = mix(dst.rgb, 1.0 - (1.0 - dst.rgb) * (1.0 - src.rgb), coverage);
+    } else {
+        col = mix(dst.rgb, src.rgb, coverage);
+    }
+
+    imageStore(outputTex, pix, vec4(col, dst.a + coverage * (1.0 - dst.a)));
+}
@@ -0,0 +1,43 @@
+// Resamples an equirectangular panorama into the layers of a cube map, averaging
+// `supersample`^2 samples per texel; see `cube_map.rs`.
+
+uniform texture2D inputTex;
+uniform sampler linear_sampler;
+layout(rgba16f) uniform restrict writeonly image2DArray outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint supersample;
+};
+
+#include "cube_map.inc"
+
+// Inverse of the mapping in `equirect_from_cube.glsl`
+vec2 equirect_uv(vec3 dir) {
+    const float PI = 3.14159265359;
+    dir = normalize(dir);
+    float phi = atan(dir.x, -dir.z);
+    float theta = asin(clamp(dir.y, -1.0, 1.0));
+    return vec2(phi / (2.0 * PI) + 0.5, 0.5 - theta / PI);
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint face = gl_GlobalInvocationID.z;
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec4 sum = vec4(0.0);
+    for (uint y = 0u; y < supersample; ++y) {
+        for (uint x = 0u; x < supersample; ++x) {
+            vec2 offset = (vec2(x, y) + 0.5) / float(supersample);
+            vec2 uv = (vec2(pix) + offset) * outputTex_size.zw;
+            vec2 src_uv = equirect_uv(cube_face_dir(face, uv));
+            sum += textureLod(sampler2D(inputTex, linear_sampler), src_uv, 0);
+        }
+    }
+
+    imageStore(outputTex, ivec3(pix, face), sum / float(supersample * supersample));
+}
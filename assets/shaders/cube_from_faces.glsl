@@ -0,0 +1,63 @@
+// Copies the faces from `build_cube_faces` into the layers of a cube map; see `cube_map.rs`.
+
+uniform texture2D texFace0;
+uniform texture2D texFace1;
+uniform texture2D texFace2;
+uniform texture2D texFace3;
+uniform texture2D texFace4;
+uniform texture2D texFace5;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2DArray outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+// Must match `cube_face_basis` in panorama.rs
+const vec3 FACE_FORWARD[6] = vec3[6](
+    vec3(1, 0, 0), vec3(-1, 0, 0),
+    vec3(0, 1, 0), vec3(0, -1, 0),
+    vec3(0, 0, 1), vec3(0, 0, -1)
+);
+const vec3 FACE_UP[6] = vec3[6](
+    vec3(0, 1, 0), vec3(0, 1, 0),
+    vec3(0, 0, 1), vec3(0, 0, -1),
+    vec3(0, 1, 0), vec3(0, 1, 0)
+);
+
+#include "cube_map.inc"
+
+vec4 sample_face(uint face, vec2 uv) {
+    // Note: no dynamic indexing of non-bindless textures
+    switch (face) {
+        case 0u: return textureLod(sampler2D(texFace0, linear_clamp_sampler), uv, 0);
+        case 1u: return textureLod(sampler2D(texFace1, linear_clamp_sampler), uv, 0);
+        case 2u: return textureLod(sampler2D(texFace2, linear_clamp_sampler), uv, 0);
+        case 3u: return textureLod(sampler2D(texFace3, linear_clamp_sampler), uv, 0);
+        case 4u: return textureLod(sampler2D(texFace4, linear_clamp_sampler), uv, 0);
+        default: return textureLod(sampler2D(texFace5, linear_clamp_sampler), uv, 0);
+    }
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint face = gl_GlobalInvocationID.z;
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec3 dir = cube_face_dir(face, uv);
+
+    // The faces look down the same axes, but are oriented differently. With matching
+    // sizes, this lands on texel centers.
+    vec3 fwd = FACE_FORWARD[face];
+    vec3 up = FACE_UP[face];
+    vec3 right = cross(fwd, up);
+
+    vec2 ndc = vec2(dot(dir, right), dot(dir, up)) / dot(dir, fwd);
+    vec2 face_uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+    imageStore(outputTex, ivec3(pix, face), sample_face(face, face_uv));
+}
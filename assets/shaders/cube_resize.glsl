@@ -0,0 +1,32 @@
+// Resizes a cube map, averaging `supersample`^2 samples per texel; see `cube_map.rs`.
+
+uniform textureCube inputTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2DArray outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint supersample;
+};
+
+#include "cube_map.inc"
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint face = gl_GlobalInvocationID.z;
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec4 sum = vec4(0.0);
+    for (uint y = 0u; y < supersample; ++y) {
+        for (uint x = 0u; x < supersample; ++x) {
+            vec2 offset = (vec2(x, y) + 0.5) / float(supersample);
+            vec3 dir = cube_face_dir(face, (vec2(pix) + offset) * outputTex_size.zw);
+            sum += textureLod(samplerCube(inputTex, linear_clamp_sampler), dir, 0);
+        }
+    }
+
+    imageStore(outputTex, ivec3(pix, face), sum / float(supersample * supersample));
+}
@@ -0,0 +1,57 @@
+// Converts textures for the texture visualizer; see `gpu_debugger.rs`.
+// Compiled with one of SOURCE_UINT, SOURCE_SINT or SOURCE_DEPTH defined.
+
+#if defined(SOURCE_UINT)
+uniform utexture2D inputTex;
+#elif defined(SOURCE_SINT)
+uniform itexture2D inputTex;
+#else
+uniform texture2D inputTex;
+#endif
+
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+uint hash_uint(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+// Distinct colors for distinct values, e.g. object IDs. Zero stays black.
+vec3 id_color(uvec4 v) {
+    if (all(equal(v, uvec4(0)))) {
+        return vec3(0.0);
+    }
+
+    uint h = hash_uint(v.x ^ hash_uint(v.y ^ hash_uint(v.z ^ hash_uint(v.w))));
+    return vec3(uvec3(h, h >> 8, h >> 16) & 0xffu) / 255.0 * 0.8 + 0.2;
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+#if defined(SOURCE_UINT) || defined(SOURCE_SINT)
+    // Missing components read as (0, 0, 0, 1) for either signedness
+    vec3 col = id_color(uvec4(texelFetch(inputTex, pix, 0)));
+#else
+    // Reverse-Z, so 1/z: 16 octaves of distance from the near plane, and black at infinity
+    float depth = texelFetch(inputTex, pix, 0).x;
+#if !RTOY_REVERSE_Z
+    depth = 1.0 - depth;
+#endif
+    vec3 col = vec3(depth > 0.0 ? clamp(1.0 + log2(depth) / 16.0, 0.0, 1.0) : 0.0);
+#endif
+
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
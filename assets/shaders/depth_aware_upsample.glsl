@@ -0,0 +1,75 @@
+// Joint bilateral upsampling of a reduced resolution effect; see `upsample.rs`.
+
+uniform texture2D effectTex;
+uniform texture2D depthTex;
+uniform texture2D normalTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    vec4 effectTex_size;
+    uint use_normals;
+    float depth_sharpness;
+    float normal_power;
+};
+
+float depth_weight(float a, float b) {
+#if !RTOY_REVERSE_Z
+    a = 1.0 - a;
+    b = 1.0 - b;
+#endif
+    // Depth is proportional to 1/z here, so this is the relative difference in view distance
+    float rel_diff = abs(a - b) / max(max(a, b), 1e-8);
+    return exp(-rel_diff * depth_sharpness);
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    float depth = texelFetch(depthTex, pix, 0).x;
+    vec3 normal = use_normals != 0 ? texelFetch(normalTex, pix, 0).xyz : vec3(0.0);
+
+    vec2 src = uv * effectTex_size.xy - 0.5;
+    ivec2 base = ivec2(floor(src));
+    vec2 frac = src - vec2(base);
+    ivec2 effect_max = ivec2(effectTex_size.xy) - 1;
+
+    vec4 sum = vec4(0.0);
+    float weight_sum = 0.0;
+    // Used when no sample matches; e.g. thin features missed by the low resolution effect
+    vec4 best_sample = vec4(0.0);
+    float best_weight = -1.0;
+
+    for (int i = 0; i < 4; ++i) {
+        ivec2 offset = ivec2(i & 1, i >> 1);
+        ivec2 src_pix = clamp(base + offset, ivec2(0), effect_max);
+
+        // Effects are assumed to have sampled depth at the center of their texels
+        vec2 src_uv = (vec2(src_pix) + 0.5) * effectTex_size.zw;
+        ivec2 guide_pix = min(ivec2(src_uv * outputTex_size.xy), ivec2(outputTex_size.xy) - 1);
+
+        float weight = depth_weight(depth, texelFetch(depthTex, guide_pix, 0).x);
+        if (use_normals != 0) {
+            vec3 src_normal = texelFetch(normalTex, guide_pix, 0).xyz;
+            weight *= pow(max(dot(normal, src_normal), 0.0), normal_power);
+        }
+
+        vec4 value = texelFetch(effectTex, src_pix, 0);
+        if (weight > best_weight) {
+            best_weight = weight;
+            best_sample = value;
+        }
+
+        vec2 bilinear = mix(1.0 - frac, frac, vec2(offset));
+        weight *= bilinear.x * bilinear.y;
+        sum += value * weight;
+        weight_sum += weight;
+    }
+
+    imageStore(outputTex, pix, weight_sum > 1e-4 ? sum / weight_sum : best_sample);
+}
@@ -0,0 +1,52 @@
+// Split-sum BRDF lookup table: scale and bias to F0 of the GGX specular integral, with
+// `n_dot_v` along X and roughness along Y; see `env_map.rs`.
+
+layout(rg16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint sample_count;
+};
+
+#include "sampling.inc"
+
+// Smith-Schlick, with k = a / 2 as commonly used for image based lighting
+float geometry_smith(float n_dot_v, float n_dot_l, float a) {
+    float k = a * 0.5;
+    float gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    float n_dot_v = uv.x;
+    float roughness = uv.y;
+    float a = roughness * roughness;
+
+    vec3 v = vec3(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    vec2 sum = vec2(0.0);
+    for (uint i = 0u; i < sample_count; ++i) {
+        vec3 h = importance_sample_ggx(hammersley(i, sample_count), a);
+        vec3 l = reflect(-v, h);
+
+        float n_dot_l = l.z;
+        float n_dot_h = max(h.z, 0.0);
+        float v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            float g = geometry_smith(n_dot_v, n_dot_l, a);
+            float g_vis = g * v_dot_h / max(n_dot_h * n_dot_v, 1e-8);
+            float fc = pow(1.0 - v_dot_h, 5.0);
+            sum += vec2((1.0 - fc) * g_vis, fc * g_vis);
+        }
+    }
+
+    imageStore(outputTex, pix, vec4(sum / float(sample_count), 0.0, 0.0));
+}
@@ -0,0 +1,47 @@
+// Diffuse irradiance of a cube map, by brute force integration over all of its texels;
+// see `env_map.rs`. Stores irradiance / PI, so that shading only multiplies by albedo.
+
+uniform textureCube inputTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2DArray outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    vec4 inputTex_size;
+};
+
+#include "cube_map.inc"
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint face = gl_GlobalInvocationID.z;
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    const float PI = 3.14159265359;
+    vec3 normal = normalize(cube_face_dir(face, (vec2(pix) + 0.5) * outputTex_size.zw));
+    uint src_size = uint(inputTex_size.x);
+
+    // Solid angle of a texel at `st` in [-1, 1]^2 is about area / |(st, 1)|^3
+    float texel_area = 4.0 * inputTex_size.z * inputTex_size.w;
+
+    vec3 sum = vec3(0.0);
+    for (uint src_face = 0u; src_face < 6u; ++src_face) {
+        for (uint y = 0u; y < src_size; ++y) {
+            for (uint x = 0u; x < src_size; ++x) {
+                vec3 dir = cube_face_dir(src_face, (vec2(x, y) + 0.5) * inputTex_size.zw);
+                float len = length(dir);
+                float n_dot_l = dot(normal, dir) / len;
+                if (n_dot_l > 0.0) {
+                    float solid_angle = texel_area / (len * len * len);
+                    vec4 radiance = textureLod(samplerCube(inputTex, linear_clamp_sampler), dir, 0);
+                    sum += radiance.rgb * n_dot_l * solid_angle;
+                }
+            }
+        }
+    }
+
+    imageStore(outputTex, ivec3(pix, face), vec4(sum / PI, 1.0));
+}
@@ -0,0 +1,46 @@
+// One level of a prefiltered specular cube map: the environment convolved with a GGX lobe
+// of `roughness`, assuming the view direction equals the normal; see `env_map.rs`.
+
+uniform textureCube inputTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2DArray outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float roughness;
+    uint sample_count;
+};
+
+#include "cube_map.inc"
+#include "sampling.inc"
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint face = gl_GlobalInvocationID.z;
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec3 n = normalize(cube_face_dir(face, (vec2(pix) + 0.5) * outputTex_size.zw));
+    vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, n));
+    vec3 bitangent = cross(n, tangent);
+
+    float a = roughness * roughness;
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < sample_count; ++i) {
+        vec3 h_local = importance_sample_ggx(hammersley(i, sample_count), a);
+        vec3 h = tangent * h_local.x + bitangent * h_local.y + n * h_local.z;
+        vec3 l = reflect(-n, h);
+
+        float n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            sum += textureLod(samplerCube(inputTex, linear_clamp_sampler), l, 0).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    imageStore(outputTex, ivec3(pix, face), vec4(sum / max(weight, 1e-8), 1.0));
+}
@@ -0,0 +1,69 @@
+uniform texture2D texFace0;
+uniform texture2D texFace1;
+uniform texture2D texFace2;
+uniform texture2D texFace3;
+uniform texture2D texFace4;
+uniform texture2D texFace5;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+// Must match `cube_face_basis` in panorama.rs
+const vec3 FACE_FORWARD[6] = vec3[6](
+    vec3(1, 0, 0), vec3(-1, 0, 0),
+    vec3(0, 1, 0), vec3(0, -1, 0),
+    vec3(0, 0, 1), vec3(0, 0, -1)
+);
+const vec3 FACE_UP[6] = vec3[6](
+    vec3(0, 1, 0), vec3(0, 1, 0),
+    vec3(0, 0, 1), vec3(0, 0, -1),
+    vec3(0, 1, 0), vec3(0, 1, 0)
+);
+
+vec4 sample_face(int face, vec2 uv) {
+    // Note: no dynamic indexing of non-bindless textures
+    switch (face) {
+        case 0: return textureLod(sampler2D(texFace0, linear_clamp_sampler), uv, 0);
+        case 1: return textureLod(sampler2D(texFace1, linear_clamp_sampler), uv, 0);
+        case 2: return textureLod(sampler2D(texFace2, linear_clamp_sampler), uv, 0);
+        case 3: return textureLod(sampler2D(texFace3, linear_clamp_sampler), uv, 0);
+        case 4: return textureLod(sampler2D(texFace4, linear_clamp_sampler), uv, 0);
+        default: return textureLod(sampler2D(texFace5, linear_clamp_sampler), uv, 0);
+    }
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+
+    const float PI = 3.14159265359;
+    float phi = (uv.x - 0.5) * 2.0 * PI;
+    float theta = (0.5 - uv.y) * PI;
+
+    // The center of the panorama looks down -Z, like the default camera
+    vec3 dir = vec3(cos(theta) * sin(phi), sin(theta), -cos(theta) * cos(phi));
+    vec3 a = abs(dir);
+
+    int face;
+    if (a.x >= a.y && a.x >= a.z) {
+        face = dir.x > 0.0 ? 0 : 1;
+    } else if (a.y >= a.z) {
+        face = dir.y > 0.0 ? 2 : 3;
+    } else {
+        face = dir.z > 0.0 ? 4 : 5;
+    }
+
+    vec3 fwd = FACE_FORWARD[face];
+    vec3 up = FACE_UP[face];
+    vec3 right = cross(fwd, up);
+
+    float d = dot(dir, fwd);
+    vec2 ndc = vec2(dot(dir, right), dot(dir, up)) / d;
+    vec2 face_uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+    imageStore(outputTex, pix, sample_face(face, face_uv));
+}
@@ -0,0 +1,6 @@
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    imageStore(outputTex, ivec2(gl_GlobalInvocationID.xy), vec4(1.0, 0.0, 1.0, 1.0));
+}
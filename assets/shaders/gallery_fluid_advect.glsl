@@ -0,0 +1,21 @@
+uniform texture2D stateTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float dt;
+};
+
+// Semi-Lagrangian advection of both the velocity and the dye
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+
+    vec2 vel = texelFetch(stateTex, pix, 0).xy;
+    vec2 src_uv = uv - dt * vel * outputTex_size.zw;
+    vec4 state = textureLod(sampler2D(stateTex, linear_clamp_sampler), src_uv, 0);
+
+    imageStore(outputTex, pix, state);
+}
@@ -0,0 +1,19 @@
+uniform texture2D stateTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec4 state = texelFetch(stateTex, pix, 0);
+
+    // Dye, tinted by the speed in screen heights per second
+    float speed = length(state.xy) * outputTex_size.w;
+    vec3 col = mix(vec3(0.05, 0.1, 0.3), vec3(1.0, 0.8, 0.4), clamp(state.z, 0.0, 1.0));
+    col += vec3(0.2, 0.5, 1.0) * clamp(speed, 0.0, 1.0);
+
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
@@ -0,0 +1,22 @@
+uniform texture2D stateTex;
+layout(rg16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+vec2 velocity(ivec2 pix) {
+    return texelFetch(stateTex, clamp(pix, ivec2(0), ivec2(outputTex_size.xy) - 1), 0).xy;
+}
+
+// Divergence in x, and the initial pressure of zero in y
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    float div = 0.5 * (
+        velocity(pix + ivec2(1, 0)).x - velocity(pix - ivec2(1, 0)).x
+        + velocity(pix + ivec2(0, 1)).y - velocity(pix - ivec2(0, 1)).y
+    );
+
+    imageStore(outputTex, pix, vec4(div, 0.0, 0.0, 0.0));
+}
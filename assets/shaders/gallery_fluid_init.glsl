@@ -0,0 +1,23 @@
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+// Swirl around `d`, in pixels per second
+vec2 vortex(vec2 d, float strength) {
+    return strength * vec2(-d.y, d.x) * exp(-dot(d, d) * 60.0) * 8.0 * outputTex_size.y;
+}
+
+// Velocity in xy, and dye in z
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec2 p = (uv - 0.5) * vec2(outputTex_size.x * outputTex_size.w, 1.0);
+
+    vec2 vel = vortex(p - vec2(-0.15, 0.0), 1.0) + vortex(p - vec2(0.15, 0.05), -1.0);
+    float dye = float((int(floor(uv.x * 8.0)) + int(floor(uv.y * 8.0))) & 1);
+
+    imageStore(outputTex, pix, vec4(vel, dye, 0.0));
+}
@@ -0,0 +1,24 @@
+uniform texture2D pressureTex;
+layout(rg16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+float pressure(ivec2 pix) {
+    return texelFetch(pressureTex, clamp(pix, ivec2(0), ivec2(outputTex_size.xy) - 1), 0).y;
+}
+
+// One Jacobi iteration of the pressure solve; the divergence in x is passed through
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    float div = texelFetch(pressureTex, pix, 0).x;
+    float p = 0.25 * (
+        pressure(pix + ivec2(1, 0)) + pressure(pix - ivec2(1, 0))
+        + pressure(pix + ivec2(0, 1)) + pressure(pix - ivec2(0, 1))
+        - div
+    );
+
+    imageStore(outputTex, pix, vec4(div, p, 0.0, 0.0));
+}
@@ -0,0 +1,24 @@
+uniform texture2D stateTex;
+uniform texture2D pressureTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+float pressure(ivec2 pix) {
+    return texelFetch(pressureTex, clamp(pix, ivec2(0), ivec2(outputTex_size.xy) - 1), 0).y;
+}
+
+// Subtracts the pressure gradient, leaving the velocity divergence-free
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec4 state = texelFetch(stateTex, pix, 0);
+    vec2 grad = 0.5 * vec2(
+        pressure(pix + ivec2(1, 0)) - pressure(pix - ivec2(1, 0)),
+        pressure(pix + ivec2(0, 1)) - pressure(pix - ivec2(0, 1))
+    );
+
+    imageStore(outputTex, pix, vec4(state.xy - grad, state.zw));
+}
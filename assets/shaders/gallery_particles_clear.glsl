@@ -0,0 +1,6 @@
+layout(r32ui) uniform restrict writeonly uimage2D outputTex;
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    imageStore(outputTex, ivec2(gl_GlobalInvocationID.xy), uvec4(0));
+}
@@ -0,0 +1,31 @@
+layout(std430) buffer outputBuf {
+    // Position in xy, velocity in zw; in UV units
+    vec4 particles[];
+};
+
+layout(std140) uniform globals {
+    uint particle_count;
+};
+
+#include "random.inc"
+
+layout (local_size_x = 64) in;
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= particle_count) {
+        return;
+    }
+
+    float r0 = rand_float(hash(idx * 4u + 0u));
+    float r1 = rand_float(hash(idx * 4u + 1u));
+    float r2 = rand_float(hash(idx * 4u + 2u));
+    float r3 = rand_float(hash(idx * 4u + 3u));
+
+    // A fountain spraying upwards from the bottom center
+    float angle = 3.14159265 * (0.35 + 0.3 * r0);
+    float speed = 0.8 + 0.6 * r1;
+    vec2 pos = vec2(0.5 + 0.02 * (r2 - 0.5), 0.95 - 0.02 * r3);
+    vec2 vel = vec2(cos(angle), -sin(angle)) * speed;
+
+    particles[idx] = vec4(pos, vel);
+}
@@ -0,0 +1,16 @@
+uniform utexture2D densityTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    float density = log2(1.0 + float(texelFetch(densityTex, pix, 0).x));
+
+    // Black, through orange, to white
+    vec3 col = vec3(density * 0.5, density * density * 0.1, density * density * density * 0.02);
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
@@ -0,0 +1,24 @@
+layout(std430) readonly buffer particlesBuf {
+    vec4 particles[];
+};
+
+layout(r32ui) uniform restrict uimage2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint particle_count;
+};
+
+// Dispatched over the pixels of the density texture; each thread splats one particle
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 thread = ivec2(gl_GlobalInvocationID.xy);
+    uint idx = uint(thread.y) * uint(outputTex_size.x) + uint(thread.x);
+    if (thread.x >= int(outputTex_size.x) || idx >= particle_count) {
+        return;
+    }
+
+    vec2 pos = particles[idx].xy;
+    ivec2 pix = clamp(ivec2(pos * outputTex_size.xy), ivec2(0), ivec2(outputTex_size.xy) - 1);
+    imageAtomicAdd(outputTex, pix, 1u);
+}
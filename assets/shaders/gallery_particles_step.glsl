@@ -0,0 +1,38 @@
+layout(std430) readonly buffer particlesBuf {
+    vec4 particles_in[];
+};
+
+layout(std430) buffer outputBuf {
+    vec4 particles[];
+};
+
+layout(std140) uniform globals {
+    uint particle_count;
+    float dt;
+};
+
+// Gravity, and bounces off the edges of the screen losing some energy
+layout (local_size_x = 64) in;
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= particle_count) {
+        return;
+    }
+
+    vec2 pos = particles_in[idx].xy;
+    vec2 vel = particles_in[idx].zw;
+
+    vel.y += 1.5 * dt;
+    pos += vel * dt;
+
+    if (pos.x < 0.0 || pos.x > 1.0) {
+        pos.x = clamp(pos.x, 0.0, 1.0);
+        vel.x *= -0.8;
+    }
+    if (pos.y > 1.0) {
+        pos.y = 1.0;
+        vel.y *= -0.6;
+    }
+
+    particles[idx] = vec4(pos, vel);
+}
@@ -0,0 +1,24 @@
+uniform texture2D inputTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    ivec2 direction;
+};
+
+// One direction of a separable 9-tap Gaussian
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 max_pix = ivec2(outputTex_size.xy) - 1;
+    const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+    vec3 col = texelFetch(inputTex, pix, 0).rgb * weights[0];
+    for (int i = 1; i < 5; ++i) {
+        ivec2 a = clamp(pix + direction * i, ivec2(0), max_pix);
+        ivec2 b = clamp(pix - direction * i, ivec2(0), max_pix);
+        col += (texelFetch(inputTex, a, 0).rgb + texelFetch(inputTex, b, 0).rgb) * weights[i];
+    }
+
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
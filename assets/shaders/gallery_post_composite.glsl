@@ -0,0 +1,41 @@
+uniform texture2D inputTex;
+uniform texture2D bloomTex;
+uniform texture2D wideBloomTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float exposure;
+    float bloom_amount;
+};
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces_tonemap(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 sample_tex(texture2D tex, vec2 uv) {
+    return textureLod(sampler2D(tex, linear_clamp_sampler), uv, 0).rgb;
+}
+
+// Bloom, chromatic aberration, tone mapping and a vignette
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec2 from_center = uv - 0.5;
+
+    vec2 ca_offset = from_center * 0.004;
+    vec3 col = vec3(
+        sample_tex(inputTex, uv + ca_offset).r,
+        sample_tex(inputTex, uv).g,
+        sample_tex(inputTex, uv - ca_offset).b
+    );
+
+    col += bloom_amount * (sample_tex(bloomTex, uv) + sample_tex(wideBloomTex, uv));
+    col = aces_tonemap(col * exposure);
+    col *= 1.0 - 0.6 * dot(from_center, from_center);
+
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
@@ -0,0 +1,29 @@
+uniform texture2D inputTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float threshold;
+};
+
+vec3 sample_input(vec2 uv) {
+    return textureLod(sampler2D(inputTex, linear_clamp_sampler), uv, 0).rgb;
+}
+
+// Averages the input down to the output resolution, keeping what's above `threshold`
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec2 offset = 0.25 * outputTex_size.zw;
+
+    vec3 col = 0.25 * (
+        sample_input(uv + vec2(-offset.x, -offset.y))
+        + sample_input(uv + vec2(offset.x, -offset.y))
+        + sample_input(uv + vec2(-offset.x, offset.y))
+        + sample_input(uv + vec2(offset.x, offset.y))
+    );
+
+    imageStore(outputTex, pix, vec4(max(col - threshold, 0.0), 1.0));
+}
@@ -0,0 +1,102 @@
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float time;
+};
+
+float sd_sphere(vec3 p, float r) {
+    return length(p) - r;
+}
+
+float sd_box(vec3 p, vec3 b) {
+    vec3 q = abs(p) - b;
+    return length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+float smooth_min(float a, float b, float k) {
+    float h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+vec3 rotate_y(vec3 p, float a) {
+    float c = cos(a);
+    float s = sin(a);
+    return vec3(c * p.x + s * p.z, p.y, c * p.z - s * p.x);
+}
+
+float scene(vec3 p) {
+    float ground = p.y + 1.0;
+    float a = sd_sphere(p - vec3(0.8 * sin(time), 0.2 * sin(time * 1.7), 0.0), 0.6);
+    float b = sd_sphere(p - vec3(-0.8 * sin(time), 0.0, 0.3), 0.5);
+    float box = sd_box(rotate_y(p - vec3(0.0, -0.5, -1.2), time * 0.5), vec3(0.4)) - 0.05;
+    return min(ground, min(smooth_min(a, b, 0.4), box));
+}
+
+vec3 calculate_normal(vec3 p) {
+    vec2 e = vec2(1e-3, 0.0);
+    return normalize(vec3(
+        scene(p + e.xyy) - scene(p - e.xyy),
+        scene(p + e.yxy) - scene(p - e.yxy),
+        scene(p + e.yyx) - scene(p - e.yyx)
+    ));
+}
+
+float soft_shadow(vec3 ro, vec3 rd) {
+    float res = 1.0;
+    float t = 0.02;
+    for (int i = 0; i < 48 && t < 10.0; ++i) {
+        float d = scene(ro + rd * t);
+        res = min(res, 8.0 * d / t);
+        if (res < 1e-3) {
+            break;
+        }
+        t += clamp(d, 0.01, 0.5);
+    }
+    return clamp(res, 0.0, 1.0);
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    vec2 ndc = (uv * 2.0 - 1.0) * vec2(outputTex_size.x * outputTex_size.w, -1.0);
+
+    vec3 ro = vec3(3.0 * sin(time * 0.2), 1.0, 3.0 * cos(time * 0.2));
+    vec3 fwd = normalize(-ro);
+    vec3 right = normalize(cross(fwd, vec3(0.0, 1.0, 0.0)));
+    vec3 up = cross(right, fwd);
+    vec3 rd = normalize(fwd * 1.5 + ndc.x * right + ndc.y * up);
+
+    float t = 0.0;
+    bool hit = false;
+    for (int i = 0; i < 128 && t < 50.0; ++i) {
+        float d = scene(ro + rd * t);
+        if (d < 1e-3 * max(t, 1.0)) {
+            hit = true;
+            break;
+        }
+        t += d;
+    }
+
+    vec3 sky = mix(vec3(0.6, 0.7, 0.9), vec3(0.2, 0.3, 0.6), clamp(rd.y, 0.0, 1.0));
+    vec3 col = sky;
+
+    if (hit) {
+        vec3 p = ro + rd * t;
+        vec3 n = calculate_normal(p);
+        vec3 l = normalize(vec3(0.6, 0.8, 0.3));
+
+        // Checkerboard on the ground
+        vec3 albedo = vec3(0.7, 0.4, 0.3);
+        if (p.y < -0.99) {
+            albedo = vec3(0.3 + 0.4 * float((int(floor(p.x)) + int(floor(p.z))) & 1));
+        }
+
+        float diffuse = max(dot(n, l), 0.0) * soft_shadow(p + n * 1e-3, l);
+        col = albedo * (diffuse * vec3(3.0, 2.7, 2.4) + sky * (0.2 + 0.2 * n.y));
+        col = mix(col, sky, 1.0 - exp(-0.01 * t * t));
+    }
+
+    imageStore(outputTex, pix, vec4(col, 1.0));
+}
@@ -0,0 +1,77 @@
+// Scene meshes for `raster_gbuffer`; see `gbuffer.rs`.
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+struct Material {
+    vec4 base_color_mult;
+    // normal, metallic-roughness and albedo maps; see `load_gltf_material`
+    uvec4 maps;
+};
+
+layout(std430) readonly buffer mesh_materials_buf {
+    Material materials[];
+};
+
+#include "bindless.inc"
+#include "gbuffer.inc"
+
+uniform sampler linear_sampler;
+
+layout(location = 0) in vec3 in_normal;
+layout(location = 1) in vec4 in_tangent;
+layout(location = 2) in vec2 in_uv;
+layout(location = 3) in vec4 in_color;
+layout(location = 4) in flat uint in_material_id;
+layout(location = 5) in vec4 in_clip_pos;
+layout(location = 6) in vec4 in_prev_clip_pos;
+
+layout(location = 0) out vec4 out_albedo;
+layout(location = 1) out vec4 out_normal;
+layout(location = 2) out vec4 out_roughness_metalness;
+layout(location = 3) out vec4 out_depth;
+layout(location = 4) out vec4 out_velocity;
+
+vec4 sample_map(uint map) {
+    return texture(sampler2D(all_textures[nonuniformEXT(map)], linear_sampler), in_uv);
+}
+
+void main() {
+    Material material = materials[in_material_id];
+
+    vec3 geometric_normal = normalize(in_normal);
+    vec3 normal = geometric_normal;
+    // Meshes without tangents get a `w` of zero; normal maps need them
+    if (in_tangent.w != 0.0) {
+        vec3 tangent = in_tangent.xyz;
+        tangent = normalize(tangent - geometric_normal * dot(geometric_normal, tangent));
+        vec3 bitangent = cross(geometric_normal, tangent) * in_tangent.w;
+
+        vec3 ts_normal = sample_map(material.maps.x).xyz * 2.0 - 1.0;
+        normal = normalize(
+            tangent * ts_normal.x + bitangent * ts_normal.y + geometric_normal * ts_normal.z
+        );
+    }
+
+    vec4 metallic_roughness = sample_map(material.maps.y);
+    vec4 albedo = sample_map(material.maps.z);
+
+    vec2 uv = rtoy_ndc_to_uv(in_clip_pos.xy / in_clip_pos.w);
+    vec2 prev_uv = rtoy_ndc_to_uv(in_prev_clip_pos.xy / in_prev_clip_pos.w);
+
+    GBuffer gbuffer;
+    gbuffer.albedo = material.base_color_mult.rgb * albedo.rgb * in_color.rgb;
+    gbuffer.normal = normal;
+    gbuffer.geometric_normal = geometric_normal;
+    gbuffer.roughness = metallic_roughness.g;
+    gbuffer.metalness = metallic_roughness.b;
+    gbuffer.depth = gl_FragCoord.z;
+    gbuffer.velocity = uv - prev_uv;
+    gbuffer.has_geometry = true;
+
+    GBufferTexels texels = pack_gbuffer(gbuffer);
+    out_albedo = texels.albedo;
+    out_normal = texels.normal;
+    out_roughness_metalness = texels.roughness_metalness;
+    out_depth = texels.depth;
+    out_velocity = texels.velocity;
+}
@@ -0,0 +1,65 @@
+// Scene meshes for `raster_gbuffer`; see `gbuffer.rs`.
+
+#include "view_constants.inc"
+#include "mesh.inc"
+
+layout(std430) readonly buffer view_constants {
+    ViewConstants view;
+};
+
+layout(std430) readonly buffer prev_view_constants {
+    ViewConstants prev_view;
+};
+
+layout(std430) readonly buffer instance_transform {
+    mat4 model_to_world;
+};
+
+layout(std430) readonly buffer mesh_vertex_buf {
+    VertexPacked vertices[];
+};
+
+layout(std430) readonly buffer mesh_uv_buf {
+    vec2 uvs[];
+};
+
+layout(std430) readonly buffer mesh_color_buf {
+    vec4 colors[];
+};
+
+layout(std430) readonly buffer mesh_tangent_buf {
+    vec4 tangents[];
+};
+
+layout(std430) readonly buffer mesh_material_id_buf {
+    uint material_ids[];
+};
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec4 out_tangent;
+layout(location = 2) out vec2 out_uv;
+layout(location = 3) out vec4 out_color;
+layout(location = 4) out flat uint out_material_id;
+layout(location = 5) out vec4 out_clip_pos;
+layout(location = 6) out vec4 out_prev_clip_pos;
+
+void main() {
+    uint idx = uint(gl_VertexIndex);
+    VertexPacked vert = vertices[idx];
+
+    vec4 world_pos = model_to_world * vec4(vert.x, vert.y, vert.z, 1.0);
+    vec4 view_pos = view.world_to_view * world_pos;
+
+    mat3 model_to_world_rot = mat3(model_to_world);
+    out_normal = model_to_world_rot * unpack_unit_direction_11_10_11(vert.normal);
+    out_tangent = vec4(model_to_world_rot * tangents[idx].xyz, tangents[idx].w);
+    out_uv = uvs[idx];
+    out_color = colors[idx];
+    out_material_id = material_ids[idx];
+
+    // Velocity is measured without the jitter, which would otherwise show up as motion
+    out_clip_pos = view.view_to_clip * view_pos;
+    out_prev_clip_pos = prev_view.view_to_clip * (prev_view.world_to_view * world_pos);
+
+    gl_Position = view.view_to_sample * view_pos;
+}
@@ -0,0 +1,183 @@
+uniform texture2D texA;
+uniform texture2D texB;
+layout(r32f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    uint metric;
+    float pixels_per_degree;
+};
+
+#define METRIC_PSNR 0
+#define METRIC_SSIM 1
+#define METRIC_FLIP 2
+
+#include "color.inc"
+
+vec3 fetch_a(ivec2 pix) {
+    pix = clamp(pix, ivec2(0), ivec2(outputTex_size.xy) - 1);
+    return clamp(texelFetch(texA, pix, 0).rgb, 0.0, 1.0);
+}
+
+vec3 fetch_b(ivec2 pix) {
+    pix = clamp(pix, ivec2(0), ivec2(outputTex_size.xy) - 1);
+    return clamp(texelFetch(texB, pix, 0).rgb, 0.0, 1.0);
+}
+
+// Per-pixel squared error, averaged over the color channels. PSNR is computed from its mean.
+float squared_error(ivec2 pix) {
+    vec3 d = fetch_a(pix) - fetch_b(pix);
+    return dot(d, d) / 3.0;
+}
+
+// Luminance SSIM with the usual 11x11 Gaussian window, sigma = 1.5
+float ssim(ivec2 pix) {
+    const int radius = 5;
+    const float c1 = 0.01 * 0.01;
+    const float c2 = 0.03 * 0.03;
+
+    float mu_a = 0.0;
+    float mu_b = 0.0;
+    float a2 = 0.0;
+    float b2 = 0.0;
+    float ab = 0.0;
+    float wsum = 0.0;
+
+    for (int y = -radius; y <= radius; ++y) {
+        for (int x = -radius; x <= radius; ++x) {
+            float w = exp(-float(x * x + y * y) / (2.0 * 1.5 * 1.5));
+            float a = calculate_luma(fetch_a(pix + ivec2(x, y)));
+            float b = calculate_luma(fetch_b(pix + ivec2(x, y)));
+            mu_a += w * a;
+            mu_b += w * b;
+            a2 += w * a * a;
+            b2 += w * b * b;
+            ab += w * a * b;
+            wsum += w;
+        }
+    }
+
+    mu_a /= wsum;
+    mu_b /= wsum;
+    float var_a = a2 / wsum - mu_a * mu_a;
+    float var_b = b2 / wsum - mu_b * mu_b;
+    float cov = ab / wsum - mu_a * mu_b;
+
+    return ((2.0 * mu_a * mu_b + c1) * (2.0 * cov + c2))
+        / ((mu_a * mu_a + mu_b * mu_b + c1) * (var_a + var_b + c2));
+}
+
+// Linear sRGB to CIELAB, relative to the white point of the sRGB primaries
+vec3 linear_rgb_to_lab(vec3 rgb) {
+    const mat3 rgb_to_xyz = mat3(
+        0.4124564, 0.2126729, 0.0193339,
+        0.3575761, 0.7151522, 0.1191920,
+        0.1804375, 0.0721750, 0.9503041
+    );
+    const vec3 white = rgb_to_xyz * vec3(1.0);
+
+    vec3 xyz = (rgb_to_xyz * rgb) / white;
+    const float delta = 6.0 / 29.0;
+    vec3 f = mix(
+        xyz / (3.0 * delta * delta) + 4.0 / 29.0,
+        pow(xyz, vec3(1.0 / 3.0)),
+        greaterThan(xyz, vec3(delta * delta * delta))
+    );
+
+    return vec3(116.0 * f.y - 16.0, 500.0 * (f.x - f.y), 200.0 * (f.y - f.z));
+}
+
+vec3 hunt_adjust(vec3 lab) {
+    return vec3(lab.x, 0.01 * lab.x * lab.yz);
+}
+
+float hyab(vec3 a, vec3 b) {
+    vec3 d = a - b;
+    return abs(d.x) + length(d.yz);
+}
+
+// FLIP's color term, with the redistribution of errors towards the top of the range
+float flip_color_error(vec3 a, vec3 b) {
+    const float qc = 0.7;
+    const float pc = 0.4;
+    const float pt = 0.95;
+
+    float cmax = pow(hyab(
+        hunt_adjust(linear_rgb_to_lab(vec3(0.0, 1.0, 0.0))),
+        hunt_adjust(linear_rgb_to_lab(vec3(0.0, 0.0, 1.0)))
+    ), qc);
+
+    float e = pow(hyab(hunt_adjust(linear_rgb_to_lab(a)), hunt_adjust(linear_rgb_to_lab(b))), qc);
+
+    if (e < pc * cmax) {
+        return e * pt / (pc * cmax);
+    } else {
+        return pt + (e - pc * cmax) / (cmax - pc * cmax) * (1.0 - pt);
+    }
+}
+
+// Edge and point responses of normalized luminance, via first and second derivatives
+// of a Gaussian. Positive and negative kernel lobes are normalized separately.
+vec4 flip_features(ivec2 pix, bool use_a) {
+    float sd = 0.5 * 0.082 * pixels_per_degree;
+    int radius = int(ceil(3.0 * sd));
+
+    vec4 pos_sum = vec4(0.0);
+    vec4 neg_sum = vec4(0.0);
+    vec4 pos_val = vec4(0.0);
+    vec4 neg_val = vec4(0.0);
+
+    for (int y = -radius; y <= radius; ++y) {
+        for (int x = -radius; x <= radius; ++x) {
+            vec3 rgb = use_a ? fetch_a(pix + ivec2(x, y)) : fetch_b(pix + ivec2(x, y));
+            float l = linear_rgb_to_lab(rgb).x / 100.0;
+
+            float g = exp(-float(x * x + y * y) / (2.0 * sd * sd));
+            vec2 p = vec2(x, y);
+
+            // edge_x, edge_y, point_x, point_y
+            vec4 w = vec4(-p * g, (p * p / (sd * sd) - 1.0) * g);
+
+            pos_sum += max(w, 0.0);
+            neg_sum += max(-w, 0.0);
+            pos_val += max(w, 0.0) * l;
+            neg_val += max(-w, 0.0) * l;
+        }
+    }
+
+    return pos_val / max(pos_sum, 1e-10) - neg_val / max(neg_sum, 1e-10);
+}
+
+// A FLIP-style perceptual error, without the CSF prefilter of the reference implementation.
+float flip(ivec2 pix) {
+    const float qf = 0.5;
+
+    float color_error = flip_color_error(fetch_a(pix), fetch_b(pix));
+
+    vec4 fa = flip_features(pix, true);
+    vec4 fb = flip_features(pix, false);
+    float edge_diff = abs(length(fa.xy) - length(fb.xy));
+    float point_diff = abs(length(fa.zw) - length(fb.zw));
+    float feature_error = pow(max(edge_diff, point_diff) / sqrt(2.0), qf);
+
+    return pow(color_error, 1.0 - feature_error);
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    float err;
+    if (METRIC_PSNR == metric) {
+        err = squared_error(pix);
+    } else if (METRIC_SSIM == metric) {
+        err = ssim(pix);
+    } else {
+        err = flip(pix);
+    }
+
+    imageStore(outputTex, pix, vec4(err));
+}
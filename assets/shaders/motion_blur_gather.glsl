@@ -0,0 +1,73 @@
+uniform texture2D inputTex;
+uniform texture2D velocityTex;
+uniform texture2D neighborMaxTex;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    float shutter_fraction;
+    uint tile_size;
+    uint sample_count;
+};
+
+// Reconstruction filter from "A Reconstruction Filter for Plausible Motion Blur"
+// [McGuire et al. 2012], without the depth classification, as only velocity is available.
+
+vec2 pixel_velocity(ivec2 pix) {
+    vec2 v = texelFetch(velocityTex, pix, 0).rg * outputTex_size.xy * shutter_fraction;
+    float len = length(v);
+    return len > float(tile_size) ? v * (float(tile_size) / len) : v;
+}
+
+float cone(float dist, float vel_len) {
+    return clamp(1.0 - dist / vel_len, 0.0, 1.0);
+}
+
+float cylinder(float dist, float vel_len) {
+    return 1.0 - smoothstep(0.95 * vel_len, 1.05 * vel_len, dist);
+}
+
+float interleaved_gradient_noise(vec2 pix) {
+    return fract(52.9829189 * fract(dot(pix, vec2(0.06711056, 0.00583715))));
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = ivec2(outputTex_size.xy);
+
+    vec4 center_col = texelFetch(inputTex, pix, 0);
+    vec2 vn = texelFetch(neighborMaxTex, pix / int(tile_size), 0).rg;
+
+    if (length(vn) <= 0.5) {
+        imageStore(outputTex, pix, center_col);
+        return;
+    }
+
+    float center_vel_len = max(0.5, length(pixel_velocity(pix)));
+    float weight = 1.0 / center_vel_len;
+    vec4 sum = center_col * weight;
+
+    float jitter = interleaved_gradient_noise(vec2(pix)) - 0.5;
+
+    for (uint i = 0; i < sample_count; ++i) {
+        if (i == (sample_count - 1) / 2) {
+            continue;
+        }
+
+        float t = mix(-1.0, 1.0, (float(i) + jitter + 1.0) / float(sample_count + 1));
+        ivec2 sample_pix = clamp(ivec2(round(vec2(pix) + vn * t)), ivec2(0), size - 1);
+
+        float dist = length(vec2(sample_pix - pix));
+        float sample_vel_len = max(0.5, length(pixel_velocity(sample_pix)));
+
+        float alpha = cone(dist, sample_vel_len)
+            + cone(dist, center_vel_len)
+            + cylinder(dist, sample_vel_len) * cylinder(dist, center_vel_len) * 2.0;
+
+        weight += alpha;
+        sum += alpha * texelFetch(inputTex, sample_pix, 0);
+    }
+
+    imageStore(outputTex, pix, sum / weight);
+}
@@ -0,0 +1,28 @@
+uniform texture2D tileMaxTex;
+layout(rg16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 tile = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = ivec2(outputTex_size.xy);
+
+    vec2 max_v = vec2(0.0);
+    float max_len2 = 0.0;
+
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec2 v = texelFetch(tileMaxTex, clamp(tile + ivec2(x, y), ivec2(0), size - 1), 0).rg;
+            float len2 = dot(v, v);
+            if (len2 > max_len2) {
+                max_len2 = len2;
+                max_v = v;
+            }
+        }
+    }
+
+    imageStore(outputTex, tile, vec4(max_v, 0.0, 0.0));
+}
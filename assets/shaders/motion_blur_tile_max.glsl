@@ -0,0 +1,43 @@
+uniform texture2D velocityTex;
+layout(rg16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    vec4 velocityTex_size;
+    float shutter_fraction;
+    uint tile_size;
+};
+
+// Velocity in pixels covered while the shutter is open, limited to the tile size
+vec2 pixel_velocity(ivec2 pix) {
+    vec2 v = texelFetch(velocityTex, pix, 0).rg * velocityTex_size.xy * shutter_fraction;
+    float len = length(v);
+    return len > float(tile_size) ? v * (float(tile_size) / len) : v;
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 tile = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = ivec2(velocityTex_size.xy);
+
+    vec2 max_v = vec2(0.0);
+    float max_len2 = 0.0;
+
+    for (uint y = 0; y < tile_size; ++y) {
+        for (uint x = 0; x < tile_size; ++x) {
+            ivec2 pix = tile * int(tile_size) + ivec2(x, y);
+            if (any(greaterThanEqual(pix, size))) {
+                continue;
+            }
+
+            vec2 v = pixel_velocity(pix);
+            float len2 = dot(v, v);
+            if (len2 > max_len2) {
+                max_len2 = len2;
+                max_v = v;
+            }
+        }
+    }
+
+    imageStore(outputTex, tile, vec4(max_v, 0.0, 0.0));
+}
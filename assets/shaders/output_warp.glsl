@@ -0,0 +1,43 @@
+uniform texture2D inputTex;
+uniform texture2D warpTex;
+uniform texture2D maskTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    // left, right, top, bottom; in output UV units
+    vec4 blend_widths;
+    float blend_power;
+};
+
+// Ramp whose mirrored copy sums to one, so overlapping projectors add up to full brightness
+float blend_curve(float x) {
+    x = clamp(x, 0.0, 1.0);
+    float a = pow(x, blend_power);
+    float b = pow(1.0 - x, blend_power);
+    return a / max(1e-10, a + b);
+}
+
+float edge_blend(float dist, float width) {
+    return width > 0.0 ? blend_curve(dist / width) : 1.0;
+}
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+
+    // rg: content UV which lands at this output position; a: coverage
+    vec4 warp = textureLod(sampler2D(warpTex, linear_clamp_sampler), uv, 0);
+    vec2 src_uv = warp.rg / max(1e-10, warp.a);
+    vec4 col = textureLod(sampler2D(inputTex, linear_clamp_sampler), src_uv, 0) * warp.a;
+
+    float blend = edge_blend(uv.x, blend_widths.x)
+        * edge_blend(1.0 - uv.x, blend_widths.y)
+        * edge_blend(uv.y, blend_widths.z)
+        * edge_blend(1.0 - uv.y, blend_widths.w);
+    col.rgb *= blend * textureLod(sampler2D(maskTex, linear_clamp_sampler), uv, 0).r;
+
+    imageStore(outputTex, pix, col);
+}
@@ -0,0 +1,20 @@
+uniform texture2D inputTex;
+
+layout(std430) buffer outputBuf {
+    vec4 packed_texels[];
+};
+
+layout(std140) uniform globals {
+    vec4 inputTex_size;
+};
+
+// Row-major linear RGBA32F, for writing out HDR images.
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(inputTex_size.xy)))) {
+        return;
+    }
+
+    packed_texels[pix.y * int(inputTex_size.x) + pix.x] = texelFetch(inputTex, pix, 0);
+}
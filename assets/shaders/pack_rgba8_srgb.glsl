@@ -0,0 +1,27 @@
+uniform texture2D inputTex;
+
+layout(std430) buffer outputBuf {
+    uint packed_texels[];
+};
+
+layout(std140) uniform globals {
+    vec4 inputTex_size;
+};
+
+float linear_to_srgb(float v) {
+    return v <= 0.0031308 ? v * 12.92 : pow(v, 1.0 / 2.4) * 1.055 - 0.055;
+}
+
+// Row-major RGBA8 with sRGB encoding, for writing out images.
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(inputTex_size.xy)))) {
+        return;
+    }
+
+    vec4 col = clamp(texelFetch(inputTex, pix, 0), 0.0, 1.0);
+    col.rgb = vec3(linear_to_srgb(col.r), linear_to_srgb(col.g), linear_to_srgb(col.b));
+
+    packed_texels[pix.y * int(inputTex_size.x) + pix.x] = packUnorm4x8(col);
+}
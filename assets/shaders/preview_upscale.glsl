@@ -0,0 +1,20 @@
+// Upscales passes rendered at reduced resolution in the edit-time preview; see `edit_preview.rs`.
+
+uniform texture2D inputTex;
+uniform sampler linear_clamp_sampler;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+};
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(pix, ivec2(outputTex_size.xy)))) {
+        return;
+    }
+
+    vec2 uv = (vec2(pix) + 0.5) * outputTex_size.zw;
+    imageStore(outputTex, pix, textureLod(sampler2D(inputTex, linear_clamp_sampler), uv, 0));
+}
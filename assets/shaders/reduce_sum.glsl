@@ -0,0 +1,32 @@
+uniform texture2D inputTex;
+
+layout(std430) buffer outputBuf {
+    float partial_sums[];
+};
+
+layout(std140) uniform globals {
+    vec4 inputTex_size;
+};
+
+shared float sums[64];
+
+// Sums the red channel of `inputTex`, writing one partial sum per 8x8 group.
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    uint idx = gl_LocalInvocationIndex;
+
+    sums[idx] = all(lessThan(pix, ivec2(inputTex_size.xy))) ? texelFetch(inputTex, pix, 0).r : 0.0;
+    barrier();
+
+    for (uint stride = 32; stride > 0; stride /= 2) {
+        if (idx < stride) {
+            sums[idx] += sums[idx + stride];
+        }
+        barrier();
+    }
+
+    if (0 == idx) {
+        partial_sums[gl_WorkGroupID.y * gl_NumWorkGroups.x + gl_WorkGroupID.x] = sums[0];
+    }
+}
@@ -0,0 +1,41 @@
+// Scene meshes into the atlas tile of one caster, for `shadow_atlas`; see `shadow_map.rs`.
+
+#include "shadow_caster.inc"
+#include "mesh.inc"
+
+layout(std430) readonly buffer instance_transform {
+    mat4 model_to_world;
+};
+
+layout(std430) readonly buffer mesh_vertex_buf {
+    VertexPacked vertices[];
+};
+
+layout(std140) uniform globals {
+    uint caster_index;
+};
+
+out gl_PerVertex {
+    vec4 gl_Position;
+    float gl_ClipDistance[4];
+};
+
+void main() {
+    ShadowCaster caster = shadow_caster_list[caster_index];
+    VertexPacked vert = vertices[gl_VertexIndex];
+
+    vec4 world_pos = model_to_world * vec4(vert.x, vert.y, vert.z, 1.0);
+    vec4 clip_pos = caster.world_to_clip * world_pos;
+
+    // The tile is only a part of the render target, so clip to the caster's own frustum
+    gl_ClipDistance[0] = clip_pos.w + clip_pos.x;
+    gl_ClipDistance[1] = clip_pos.w - clip_pos.x;
+    gl_ClipDistance[2] = clip_pos.w + clip_pos.y;
+    gl_ClipDistance[3] = clip_pos.w - clip_pos.y;
+
+    vec2 tile_center = rtoy_uv_to_ndc(caster.atlas_uv_rect.xy + 0.5 * caster.atlas_uv_rect.zw);
+    gl_Position = vec4(
+        clip_pos.xy * caster.atlas_uv_rect.zw + tile_center * clip_pos.w,
+        clip_pos.zw
+    );
+}
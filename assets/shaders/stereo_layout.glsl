@@ -0,0 +1,31 @@
+uniform texture2D texLeft;
+uniform texture2D texRight;
+layout(rgba16f) uniform restrict writeonly image2D outputTex;
+
+layout(std140) uniform globals {
+    vec4 outputTex_size;
+    vec4 texLeft_size;
+    uint layout_mode;
+};
+
+#define LAYOUT_SIDE_BY_SIDE 0
+#define LAYOUT_TOP_BOTTOM 1
+
+layout (local_size_x = 8, local_size_y = 8) in;
+void main() {
+    ivec2 pix = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 eye_size = ivec2(texLeft_size.xy);
+
+    bool is_right;
+    ivec2 src;
+    if (LAYOUT_SIDE_BY_SIDE == layout_mode) {
+        is_right = pix.x >= eye_size.x;
+        src = ivec2(pix.x - (is_right ? eye_size.x : 0), pix.y);
+    } else {
+        is_right = pix.y >= eye_size.y;
+        src = ivec2(pix.x, pix.y - (is_right ? eye_size.y : 0));
+    }
+
+    vec4 col = is_right ? texelFetch(texRight, src, 0) : texelFetch(texLeft, src, 0);
+    imageStore(outputTex, pix, col);
+}
@@ -35,6 +35,13 @@ pub struct Buffer {
     _allocation: SharedTransientAllocation,
 }
 
+impl Buffer {
+    // Identifies the underlying allocation, which outlives this handle when pooled.
+    pub(crate) fn allocation_id(&self) -> u64 {
+        self._allocation.id()
+    }
+}
+
 impl TransientResource for Buffer {
     type Desc = BufferKey;
     type Allocation = BufferAllocation;
@@ -57,8 +64,10 @@ impl TransientResource for Buffer {
             let usage: vk::BufferUsageFlags = vk::BufferUsageFlags::UNIFORM_BUFFER
                 | vk::BufferUsageFlags::STORAGE_BUFFER
                 | vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
                 | vk::BufferUsageFlags::TRANSFER_DST
                 | vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::VERTEX_BUFFER
                 | vk::BufferUsageFlags::INDIRECT_BUFFER;
 
             let mem_info = vk_mem::AllocationCreateInfo {
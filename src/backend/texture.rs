@@ -1,11 +1,18 @@
 use super::transient_resource::*;
 use crate::{vk, vulkan::*};
 use ash::version::DeviceV1_0;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
 #[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
 pub enum TextureType {
     Type2D,
     Type3D,
+    // `depth` is the layer count
+    Type2DArray,
+    // Six square layers, in the order +X, -X, +Y, -Y, +Z, -Z. Sampled as a cube, and
+    // written as a 2D array; see `cube_map.rs`.
+    Cube,
 }
 
 #[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
@@ -15,6 +22,12 @@ pub struct TextureKey {
     pub depth: u32,
     pub format: i32,
     pub tex_type: TextureType,
+    // Allows views of other formats via `create_texture_view`. Opt-in, as listing the
+    // alias formats at creation can disable compression of the image.
+    pub aliasable: bool,
+    // Allows reading the texture via `subpassInput` in a later subpass of the same render
+    // pass. Set for `raster_tex` outputs, and the intermediate stages of `raster_chain_tex`.
+    pub input_attachment: bool,
 }
 
 impl TextureKey {
@@ -25,6 +38,8 @@ impl TextureKey {
             depth: 1,
             format: format.as_raw(),
             tex_type: TextureType::Type2D,
+            aliasable: false,
+            input_attachment: false,
         }
     }
 
@@ -35,6 +50,39 @@ impl TextureKey {
             depth,
             format: format.as_raw(),
             tex_type: TextureType::Type3D,
+            aliasable: false,
+            input_attachment: false,
+        }
+    }
+
+    pub fn new_2d_array(width: u32, height: u32, layers: u32, format: vk::Format) -> Self {
+        Self {
+            width,
+            height,
+            depth: layers,
+            format: format.as_raw(),
+            tex_type: TextureType::Type2DArray,
+            aliasable: false,
+            input_attachment: false,
+        }
+    }
+
+    pub fn new_cube(size: u32, format: vk::Format) -> Self {
+        Self {
+            width: size,
+            height: size,
+            depth: 6,
+            format: format.as_raw(),
+            tex_type: TextureType::Cube,
+            aliasable: false,
+            input_attachment: false,
+        }
+    }
+
+    pub fn array_layers(&self) -> u32 {
+        match self.tex_type {
+            TextureType::Type2DArray | TextureType::Cube => self.depth,
+            _ => 1,
         }
     }
 
@@ -61,7 +109,10 @@ impl TextureKey {
     }
 
     pub fn half_res(&self) -> Self {
-        self.res_div_round_up_3d(2, 2, 2)
+        match self.tex_type {
+            TextureType::Type2DArray | TextureType::Cube => self.res_div_round_up(2, 2),
+            _ => self.res_div_round_up_3d(2, 2, 2),
+        }
     }
 
     pub fn with_width(&self, v: u32) -> Self {
@@ -87,6 +138,18 @@ impl TextureKey {
         res.format = format.as_raw();
         res
     }
+
+    pub fn with_aliasing(&self) -> Self {
+        let mut res = self.clone();
+        res.aliasable = true;
+        res
+    }
+
+    pub fn with_input_attachment(&self) -> Self {
+        let mut res = self.clone();
+        res.input_attachment = true;
+        res
+    }
 }
 
 #[derive(Clone)]
@@ -108,6 +171,8 @@ pub struct ImageResource {
     storage_view: vk::ImageView,
     memory: vk::DeviceMemory,
     bindless_index: u32,
+    // After any fallback; see `find_supported_format`
+    format: vk::Format,
 
     // Validation layers retain pointers to those, so we must keep them valid :/
     format_list: Option<Box<vk::ImageFormatListCreateInfoKHR>>,
@@ -126,6 +191,7 @@ impl ImageResource {
             storage_view: vk::ImageView::null(),
             memory: vk::DeviceMemory::null(),
             bindless_index: std::u32::MAX,
+            format: vk::Format::UNDEFINED,
             format_list: None,
             view_formats: None,
         }
@@ -137,17 +203,28 @@ impl ImageResource {
         format: vk::Format,
         storage_format: vk::Format,
         extent: vk::Extent3D,
+        array_layers: u32,
         tiling: vk::ImageTiling,
         usage: vk::ImageUsageFlags,
+        flags: vk::ImageCreateFlags,
+        aliasable: bool,
     ) {
         let mem_info = vk_mem::AllocationCreateInfo {
             usage: vk_mem::MemoryUsage::GpuOnly,
             ..Default::default()
         };
 
+        let alias_formats = if aliasable {
+            alias_compatible_formats(format)
+        } else {
+            &[]
+        };
+
         let mut view_formats = vec![format];
-        if storage_format != format {
-            view_formats.push(storage_format);
+        for f in std::iter::once(storage_format).chain(alias_formats.iter().copied()) {
+            if !view_formats.contains(&f) {
+                view_formats.push(f);
+            }
         }
 
         let mut format_list = Box::new(
@@ -161,13 +238,13 @@ impl ImageResource {
             .format(storage_format)
             .extent(extent)
             .mip_levels(1)
-            .array_layers(1)
+            .array_layers(array_layers)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(tiling)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .flags(vk::ImageCreateFlags::MUTABLE_FORMAT)
+            .flags(vk::ImageCreateFlags::MUTABLE_FORMAT | flags)
             .push_next(&mut *format_list)
             .build();
 
@@ -181,9 +258,11 @@ impl ImageResource {
         self.image = image;
     }
 
+    // `view_type` is used for sampling, and `layered_view_type` for render targets and storage.
     fn create_view(
         &mut self,
         view_type: vk::ImageViewType,
+        layered_view_type: vk::ImageViewType,
         format: vk::Format,
         storage_format: vk::Format,
         readonly_usage: vk::ImageUsageFlags,
@@ -191,7 +270,7 @@ impl ImageResource {
         range: vk::ImageSubresourceRange,
     ) {
         let image = self.image;
-        let create_info = || {
+        let create_info = |view_type| {
             vk::ImageViewCreateInfo::builder()
                 .view_type(view_type)
                 .format(format)
@@ -209,18 +288,20 @@ impl ImageResource {
 
         {
             let mut view_usage = vk::ImageViewUsageCreateInfo::builder().usage(readonly_usage);
-            let create_info = create_info().push_next(&mut view_usage).build();
+            let create_info = create_info(view_type).push_next(&mut view_usage).build();
             self.view = unsafe { device.create_image_view(&create_info, None).unwrap() };
         }
 
         {
             let mut view_usage = vk::ImageViewUsageCreateInfo::builder().usage(rt_usage);
-            let create_info = create_info().push_next(&mut view_usage).build();
+            let create_info = create_info(layered_view_type)
+                .push_next(&mut view_usage)
+                .build();
             self.rt_view = unsafe { device.create_image_view(&create_info, None).unwrap() };
         }
 
         {
-            let create_info = create_info()
+            let create_info = create_info(layered_view_type)
                 .format(storage_format)
                 .image(self.image)
                 .build();
@@ -233,13 +314,400 @@ pub fn create_texture(key: TextureKey) -> Texture {
     create_transient(key)
 }
 
-fn get_storage_compatible_format(f: vk::Format) -> vk::Format {
+fn default_format_fallbacks() -> HashMap<vk::Format, vk::Format> {
+    use vk::Format as F;
+
+    [
+        (F::R16_SFLOAT, F::R32_SFLOAT),
+        (F::R16G16_SFLOAT, F::R32G32_SFLOAT),
+        (F::R16G16B16A16_SFLOAT, F::R32G32B32A32_SFLOAT),
+        (F::R32G32B32_SFLOAT, F::R32G32B32A32_SFLOAT),
+        (F::B10G11R11_UFLOAT_PACK32, F::R16G16B16A16_SFLOAT),
+        (F::E5B9G9R9_UFLOAT_PACK32, F::R16G16B16A16_SFLOAT),
+        (F::A2B10G10R10_UNORM_PACK32, F::R16G16B16A16_UNORM),
+        (F::R16G16B16A16_UNORM, F::R16G16B16A16_SFLOAT),
+        (F::BC7_UNORM_BLOCK, F::R8G8B8A8_UNORM),
+        (F::BC7_SRGB_BLOCK, F::R8G8B8A8_SRGB),
+        (F::X8_D24_UNORM_PACK32, F::D32_SFLOAT),
+    ]
+    .iter()
+    .copied()
+    .collect()
+}
+
+lazy_static! {
+    static ref FORMAT_FALLBACKS: Mutex<HashMap<vk::Format, vk::Format>> =
+        Mutex::new(default_format_fallbacks());
+    // Requested formats whose fallback was already reported
+    static ref WARNED_FORMAT_FALLBACKS: Mutex<HashSet<vk::Format>> = Mutex::new(HashSet::new());
+}
+
+// Configures the format to try when `format` is not supported for transient textures
+// on the current device. `None` removes the fallback, making such textures fail to allocate.
+pub fn set_texture_format_fallback(format: vk::Format, fallback: Option<vk::Format>) {
+    let mut fallbacks = FORMAT_FALLBACKS.lock().unwrap();
+    if let Some(fallback) = fallback {
+        fallbacks.insert(format, fallback);
+    } else {
+        fallbacks.remove(&format);
+    }
+}
+
+fn format_features(format: vk::Format) -> vk::FormatFeatureFlags {
+    use ash::version::InstanceV1_0;
+
+    let vk = vk();
+    unsafe {
+        vk.instance
+            .get_physical_device_format_properties(vk.pdevice, format)
+            .optimal_tiling_features
+    }
+}
+
+fn is_format_supported(format: vk::Format) -> bool {
+    let get_features = format_features;
+
+    if is_depth_format(format) {
+        return get_features(format).contains(
+            vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+    }
+
+    let required_features =
+        vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT;
+    let storage_format = get_storage_compatible_format(format);
+
+    get_features(format).contains(required_features)
+        && get_features(storage_format).contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+}
+
+// Walks the fallback chain until a format usable for transient textures is found.
+pub(crate) fn find_supported_format(requested: vk::Format) -> vk::Format {
+    let fallbacks = FORMAT_FALLBACKS.lock().unwrap();
+
+    let mut format = requested;
+    let mut visited = vec![format];
+
+    while !is_format_supported(format) {
+        match fallbacks.get(&format) {
+            Some(fallback) if !visited.contains(fallback) => {
+                format = *fallback;
+                visited.push(format);
+            }
+            _ => {
+                panic!(
+                    "Texture format {:?} is not supported by the device, and no fallback works (tried {:?})",
+                    requested, visited
+                );
+            }
+        }
+    }
+
+    if format != requested && WARNED_FORMAT_FALLBACKS.lock().unwrap().insert(requested) {
+        crate::rtoy_show_warning(format!(
+            "Texture format {:?} is not supported by the device; using {:?} instead",
+            requested, format
+        ));
+    }
+
+    format
+}
+
+// `key` with the format which textures created from it actually get on this device.
+pub(crate) fn resolve_texture_key(key: TextureKey) -> TextureKey {
+    key.with_format(find_supported_format(vk::Format::from_raw(key.format)))
+}
+
+// Depth textures are render targets of depth-only raster passes, and can be sampled,
+// but not used as storage images or color attachments.
+pub(crate) fn is_depth_format(f: vk::Format) -> bool {
+    match f {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn get_storage_compatible_format(f: vk::Format) -> vk::Format {
     match f {
         vk::Format::R8G8B8A8_SRGB => vk::Format::R8G8B8A8_UNORM,
         _ => f,
     }
 }
 
+// Formats which textures can be viewed as via `create_texture_view`. Grouped by texel size;
+// views of a texture can use any format from the group of the texture's own format.
+const ALIAS_FORMAT_CLASSES: &[&[vk::Format]] = {
+    use vk::Format as F;
+
+    &[
+        &[F::R8_UNORM, F::R8_SNORM, F::R8_UINT, F::R8_SINT],
+        &[
+            F::R16_SFLOAT,
+            F::R16_UNORM,
+            F::R16_UINT,
+            F::R16_SINT,
+            F::R8G8_UNORM,
+            F::R8G8_UINT,
+        ],
+        &[
+            F::R32_SFLOAT,
+            F::R32_UINT,
+            F::R32_SINT,
+            F::R16G16_SFLOAT,
+            F::R16G16_UNORM,
+            F::R16G16_UINT,
+            F::R16G16_SINT,
+            F::R8G8B8A8_UNORM,
+            F::R8G8B8A8_SNORM,
+            F::R8G8B8A8_UINT,
+            F::R8G8B8A8_SINT,
+            F::R8G8B8A8_SRGB,
+            F::B8G8R8A8_UNORM,
+            F::A2B10G10R10_UNORM_PACK32,
+            F::B10G11R11_UFLOAT_PACK32,
+            F::E5B9G9R9_UFLOAT_PACK32,
+        ],
+        &[
+            F::R32G32_SFLOAT,
+            F::R32G32_UINT,
+            F::R32G32_SINT,
+            F::R16G16B16A16_SFLOAT,
+            F::R16G16B16A16_UNORM,
+            F::R16G16B16A16_UINT,
+            F::R16G16B16A16_SINT,
+        ],
+        &[
+            F::R32G32B32A32_SFLOAT,
+            F::R32G32B32A32_UINT,
+            F::R32G32B32A32_SINT,
+        ],
+    ]
+};
+
+pub(crate) fn alias_compatible_formats(format: vk::Format) -> &'static [vk::Format] {
+    ALIAS_FORMAT_CLASSES
+        .iter()
+        .copied()
+        .find(|class| class.contains(&format))
+        .unwrap_or(&[])
+}
+
+// Reinterpretation of an existing texture: a different format from the same
+// alias class, and/or a component swizzle applied when sampling.
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub struct TextureViewDesc {
+    pub format: i32,
+    pub swizzle: [i32; 4],
+    // Views a single layer of an array texture as a regular 2D one
+    pub layer: Option<u32>,
+}
+
+impl TextureViewDesc {
+    pub fn new(format: vk::Format) -> Self {
+        Self {
+            format: format.as_raw(),
+            swizzle: [
+                vk::ComponentSwizzle::IDENTITY.as_raw(),
+                vk::ComponentSwizzle::IDENTITY.as_raw(),
+                vk::ComponentSwizzle::IDENTITY.as_raw(),
+                vk::ComponentSwizzle::IDENTITY.as_raw(),
+            ],
+            layer: None,
+        }
+    }
+
+    pub fn with_swizzle(&self, swizzle: [vk::ComponentSwizzle; 4]) -> Self {
+        let mut res = self.clone();
+        res.swizzle = [
+            swizzle[0].as_raw(),
+            swizzle[1].as_raw(),
+            swizzle[2].as_raw(),
+            swizzle[3].as_raw(),
+        ];
+        res
+    }
+
+    pub fn with_layer(&self, layer: u32) -> Self {
+        let mut res = self.clone();
+        res.layer = Some(layer);
+        res
+    }
+}
+
+struct TextureViewAlias {
+    view: vk::ImageView,
+    rt_view: vk::ImageView,
+    storage_view: vk::ImageView,
+    bindless_index: u32,
+}
+
+type TextureViewAliasKey = (vk::Image, TextureViewDesc);
+
+lazy_static! {
+    // Transient images are recycled rather than destroyed, so the views can live as long.
+    static ref TEXTURE_VIEW_ALIASES: Mutex<HashMap<TextureViewAliasKey, TextureViewAlias>> =
+        Mutex::new(HashMap::new());
+}
+
+// Returns a texture sharing the image of `tex`, but viewed according to `desc`.
+// Swizzles only apply to sampling; storage and render target views keep the identity mapping.
+pub fn create_texture_view(tex: &Texture, desc: TextureViewDesc) -> Result<Texture, String> {
+    let tex_format = vk::Format::from_raw(tex.key.format);
+    let format = vk::Format::from_raw(desc.format);
+
+    if is_depth_format(tex_format) {
+        return Err(format!(
+            "Depth textures ({:?}) can't be aliased",
+            tex_format
+        ));
+    }
+
+    if format != tex_format && !alias_compatible_formats(tex_format).contains(&format) {
+        return Err(format!(
+            "{:?} can't be viewed as {:?}; the texel sizes must match",
+            tex_format, format
+        ));
+    }
+
+    if format != tex_format && !tex.key.aliasable {
+        return Err(format!(
+            "{:?} can't be viewed as {:?}; create it from a key with `with_aliasing`",
+            tex_format, format
+        ));
+    }
+
+    // Only the views which the format supports get created; the others stay null
+    let storage_format = get_storage_compatible_format(format);
+    let features = format_features(format);
+    if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+        return Err(format!(
+            "{:?} views can't be sampled on this device",
+            format
+        ));
+    }
+    let supports_rt = features.contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT);
+    let supports_storage =
+        format_features(storage_format).contains(vk::FormatFeatureFlags::STORAGE_IMAGE);
+
+    if let Some(layer) = desc.layer {
+        if layer >= tex.key.array_layers() {
+            return Err(format!(
+                "Layer {} is out of range; the texture has {}",
+                layer,
+                tex.key.array_layers()
+            ));
+        }
+    }
+
+    let mut aliases = TEXTURE_VIEW_ALIASES.lock().unwrap();
+    let alias = aliases.entry((tex.image, desc)).or_insert_with(|| {
+        let device = &vk().device;
+        let view_type = match (tex.key.tex_type, desc.layer) {
+            (TextureType::Type2DArray, None) => vk::ImageViewType::TYPE_2D_ARRAY,
+            (TextureType::Cube, None) => vk::ImageViewType::CUBE,
+            (TextureType::Type3D, _) => vk::ImageViewType::TYPE_3D,
+            _ => vk::ImageViewType::TYPE_2D,
+        };
+        let layered_view_type = match view_type {
+            vk::ImageViewType::CUBE => vk::ImageViewType::TYPE_2D_ARRAY,
+            _ => view_type,
+        };
+        let (base_array_layer, layer_count) = match desc.layer {
+            Some(layer) => (layer, 1),
+            None => (0, tex.key.array_layers()),
+        };
+
+        let create_info = |view_type, format, components| {
+            vk::ImageViewCreateInfo::builder()
+                .view_type(view_type)
+                .format(format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer,
+                    layer_count,
+                })
+                .image(tex.image)
+                .components(components)
+        };
+
+        let swizzled = vk::ComponentMapping {
+            r: vk::ComponentSwizzle::from_raw(desc.swizzle[0]),
+            g: vk::ComponentSwizzle::from_raw(desc.swizzle[1]),
+            b: vk::ComponentSwizzle::from_raw(desc.swizzle[2]),
+            a: vk::ComponentSwizzle::from_raw(desc.swizzle[3]),
+        };
+
+        unsafe {
+            let create_view = |create_info: vk::ImageViewCreateInfoBuilder, usage| {
+                let mut view_usage = vk::ImageViewUsageCreateInfo::builder().usage(usage);
+                device
+                    .create_image_view(&create_info.push_next(&mut view_usage).build(), None)
+                    .unwrap()
+            };
+
+            let view = create_view(
+                create_info(view_type, format, swizzled),
+                vk::ImageUsageFlags::SAMPLED,
+            );
+
+            let rt_view = if supports_rt {
+                create_view(
+                    create_info(layered_view_type, format, vk::ComponentMapping::default()),
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                )
+            } else {
+                vk::ImageView::null()
+            };
+
+            let storage_view = if supports_storage {
+                create_view(
+                    create_info(
+                        layered_view_type,
+                        storage_format,
+                        vk::ComponentMapping::default(),
+                    ),
+                    vk::ImageUsageFlags::STORAGE,
+                )
+            } else {
+                vk::ImageView::null()
+            };
+
+            TextureViewAlias {
+                view,
+                rt_view,
+                storage_view,
+                bindless_index: if view_type == vk::ImageViewType::CUBE {
+                    std::u32::MAX
+                } else {
+                    vk_state().register_image_bindless_index(view)
+                },
+            }
+        }
+    });
+
+    let mut res = tex.clone();
+    res.view = alias.view;
+    res.rt_view = alias.rt_view;
+    res.storage_view = alias.storage_view;
+    res.bindless_index = alias.bindless_index;
+    res.key.format = desc.format;
+    if desc.layer.is_some() {
+        res.key.tex_type = TextureType::Type2D;
+        res.key.depth = 1;
+    }
+    Ok(res)
+}
+
+impl Texture {
+    // Identifies the underlying allocation, which outlives this handle when pooled.
+    pub(crate) fn allocation_id(&self) -> u64 {
+        self._allocation.id()
+    }
+}
+
 impl TransientResource for Texture {
     type Desc = TextureKey;
     type Allocation = ImageResource;
@@ -253,54 +721,99 @@ impl TransientResource for Texture {
             view: allocation.payload.view,
             rt_view: allocation.payload.rt_view,
             storage_view: allocation.payload.storage_view,
-            key: desc,
+            // Reflects format fallbacks, so readbacks and views see the real format
+            key: desc.with_format(allocation.payload.format),
             bindless_index: allocation.payload.bindless_index,
             _allocation: allocation,
         }
     }
 
     fn allocate_payload(key: TextureKey) -> Self::Allocation {
-        let format = vk::Format::from_raw(key.format);
+        let format = find_supported_format(vk::Format::from_raw(key.format));
         let mut img = ImageResource::new();
+        img.format = format;
         let storage_format = get_storage_compatible_format(format);
+
+        let (usage, rt_usage, aspect_mask) = if is_depth_format(format) {
+            (
+                vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH,
+            )
+        } else {
+            let input_attachment = if key.input_attachment {
+                vk::ImageUsageFlags::INPUT_ATTACHMENT
+            } else {
+                vk::ImageUsageFlags::empty()
+            };
+
+            (
+                // Copied out by readbacks, the GPU debugger, array layer copies and XR blits
+                vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | input_attachment,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | input_attachment,
+                vk::ImageAspectFlags::COLOR,
+            )
+        };
+
         img.create_image(
             match key.tex_type {
-                TextureType::Type2D => vk::ImageType::TYPE_2D,
                 TextureType::Type3D => vk::ImageType::TYPE_3D,
+                _ => vk::ImageType::TYPE_2D,
             },
             format,
             storage_format,
             vk::Extent3D::builder()
                 .width(key.width)
                 .height(key.height)
-                .depth(key.depth)
+                .depth(match key.tex_type {
+                    TextureType::Type3D => key.depth,
+                    _ => 1,
+                })
                 .build(),
+            key.array_layers(),
             vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::SAMPLED
-                | vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::STORAGE
-                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            usage,
+            match key.tex_type {
+                TextureType::Cube => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+                _ => vk::ImageCreateFlags::empty(),
+            },
+            key.aliasable,
         );
 
+        let view_type = match key.tex_type {
+            TextureType::Type2D => vk::ImageViewType::TYPE_2D,
+            TextureType::Type3D => vk::ImageViewType::TYPE_3D,
+            TextureType::Type2DArray => vk::ImageViewType::TYPE_2D_ARRAY,
+            TextureType::Cube => vk::ImageViewType::CUBE,
+        };
         img.create_view(
+            view_type,
             match key.tex_type {
-                TextureType::Type2D => vk::ImageViewType::TYPE_2D,
-                TextureType::Type3D => vk::ImageViewType::TYPE_3D,
+                TextureType::Cube => vk::ImageViewType::TYPE_2D_ARRAY,
+                _ => view_type,
             },
             format,
             storage_format,
             vk::ImageUsageFlags::SAMPLED,
-            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            rt_usage,
             vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask,
                 base_mip_level: 0,
                 level_count: 1,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: key.array_layers(),
             },
         );
 
-        img.bindless_index = vk_state().register_image_bindless_index(img.view);
+        // Bindless textures are all `texture2D`
+        if key.tex_type != TextureType::Cube {
+            img.bindless_index = vk_state().register_image_bindless_index(img.view);
+        }
 
         img
     }
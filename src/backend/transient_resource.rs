@@ -3,6 +3,7 @@ pub use std::any::Any;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use typemap::{ShareMap, TypeMap};
 
@@ -32,13 +33,18 @@ impl<T> TransientResourceDesc for T where
 pub trait TransientResourceAllocPayload: 'static + Send + Sync + Clone {}
 impl<T> TransientResourceAllocPayload for T where T: 'static + Send + Sync + Clone {}
 
-pub trait TransientAllocation: Send + Sync + Drop {}
+pub trait TransientAllocation: Send + Sync + Drop {
+    fn id(&self) -> u64;
+}
 
 impl<Desc, AllocPayload> TransientAllocation for TransientResourceAllocation<Desc, AllocPayload>
 where
     Desc: TransientResourceDesc + std::fmt::Debug,
     AllocPayload: TransientResourceAllocPayload,
 {
+    fn id(&self) -> u64 {
+        self.id
+    }
 }
 
 // Type-erased allocation for storage in resource structs
@@ -60,6 +66,8 @@ where
 {
     pub key: TransientResourceKey<Desc>,
     pub payload: AllocPayload,
+    // Unique per underlying allocation, and kept when it's reused
+    pub id: u64,
 }
 
 // https://stackoverflow.com/a/45893270
@@ -79,6 +87,8 @@ lazy_static! {
     static ref TRANSIENT_RESOURCE_CACHE: Mutex<ShareMap> = Mutex::new(TypeMap::custom());
 }
 
+static NEXT_ALLOCATION_ID: AtomicU64 = AtomicU64::new(1);
+
 pub fn create_transient<Res: TransientResource>(desc: Res::Desc) -> Res {
     let mut res_cache_lock = TRANSIENT_RESOURCE_CACHE.lock().unwrap();
     let res_cache = res_cache_lock
@@ -89,13 +99,18 @@ pub fn create_transient<Res: TransientResource>(desc: Res::Desc) -> Res {
 
     let alloc = if existing.is_empty() {
         tracing::info!("allocating new resource: {:?}", desc);
-        TransientResourceAllocation {
+        let alloc = TransientResourceAllocation {
             key: TransientResourceKey(desc),
             payload: Res::allocate_payload(desc),
-        }
+            id: NEXT_ALLOCATION_ID.fetch_add(1, Ordering::Relaxed),
+        };
+        crate::resource_lifetime::record_acquire(alloc.id, &desc, true);
+        alloc
     } else {
         //println!("reusing resource from cache");
-        existing.pop().unwrap()
+        let alloc = existing.pop().unwrap();
+        crate::resource_lifetime::record_acquire(alloc.id, &desc, false);
+        alloc
     };
 
     Res::new(desc, std::sync::Arc::new(alloc))
@@ -108,6 +123,8 @@ where
 {
     fn drop(&mut self) {
         //println!("putting resource into cache");
+        crate::resource_lifetime::record_release(self.id);
+
         let mut res_cache_lock = TRANSIENT_RESOURCE_CACHE.lock().unwrap();
         let res_cache = res_cache_lock
             .entry::<Key<Desc, P>>()
@@ -0,0 +1,69 @@
+// Rate limiting for "background" passes, such as progressive bakes, which should not
+// hog the GPU at the expense of the interactive view.
+//
+// Every frame grants background passes a slice of GPU time. A pass is only dispatched
+// when there's credit left, and is then charged its average duration as measured
+// by the GPU profiler. Expensive passes thus run every few frames rather than every frame.
+// Skipped passes are invalidated at the end of the frame, so they get another chance.
+
+use crate::gpu_profiler::{self, GpuProfilerScopeId};
+
+struct BackgroundComputeState {
+    budget_ms: f64,
+    credit_ms: f64,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref BACKGROUND_COMPUTE: std::sync::Mutex<BackgroundComputeState> =
+        std::sync::Mutex::new(BackgroundComputeState {
+            budget_ms: 2.0,
+            credit_ms: 2.0,
+            invalidation_triggers: Vec::new(),
+        });
+}
+
+pub fn set_background_compute_budget_ms(budget_ms: f32) {
+    let mut state = BACKGROUND_COMPUTE.lock().unwrap();
+    state.budget_ms = budget_ms.max(0.0) as f64;
+    state.credit_ms = state.credit_ms.min(state.budget_ms);
+}
+
+// Returns true if the pass should run this frame.
+pub(crate) fn try_begin_background_pass(name: &str) -> bool {
+    let mut state = BACKGROUND_COMPUTE.lock().unwrap();
+    if state.credit_ms <= 0.0 {
+        return false;
+    }
+
+    let mut estimated_ms = 0.0;
+    gpu_profiler::with_stats(|stats| {
+        if let Some(scope) = stats.scopes.get(&GpuProfilerScopeId::from(name.to_owned())) {
+            estimated_ms = scope.average_duration_millis();
+        }
+    });
+
+    state.credit_ms -= estimated_ms;
+    true
+}
+
+// Re-runs a skipped pass once the next frame's budget has been granted.
+pub(crate) fn retry_next_frame(trigger: Box<dyn Fn() + Send + Sync>) {
+    BACKGROUND_COMPUTE
+        .lock()
+        .unwrap()
+        .invalidation_triggers
+        .push(trigger);
+}
+
+pub(crate) fn end_frame() {
+    let triggers = {
+        let mut state = BACKGROUND_COMPUTE.lock().unwrap();
+        state.credit_ms = (state.credit_ms + state.budget_ms).min(state.budget_ms);
+        std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
@@ -1,6 +1,7 @@
-use super::package::get_cargo_package_dep_path;
+use super::package::{get_cargo_package_dep_path, get_cargo_package_dep_path_untracked};
 use snoozy::*;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 
 #[derive(Hash, Debug)]
@@ -15,32 +16,87 @@ pub async fn load_blob_snoozy(ctx: Context, path: &AssetPath) -> Result<Blob> {
     let mut buffer = Vec::new();
     let file_path = path.to_path_lossy(ctx.clone()).await?;
 
-    tracing::info!("Loading {}\n    -> {}", path, file_path);
+    match &path.identity {
+        Some(identity) => tracing::info!("Loading {} ({})\n    -> {}", path, identity, file_path),
+        None => tracing::info!("Loading {}\n    -> {}", path, file_path),
+    }
 
     std::io::Read::read_to_end(&mut File::open(&file_path)?, &mut buffer)?;
-    crate::backend::file::watch_file(&file_path, ctx.get_invalidation_trigger());
+    let invalidation_trigger = ctx.get_invalidation_trigger();
+    crate::backend::file::watch_file(&file_path, move || {
+        crate::shader_compile_queue::notify_asset_changed();
+        crate::edit_preview::notify_edit();
+        invalidation_trigger();
+        crate::shader_hot_swap::notify_asset_changed();
+    });
 
     Ok(Blob { contents: buffer })
 }
 
-#[derive(Serialize, Debug, Clone, Abomonation, Hash)]
+// Ops taking assets are identified by the location of the asset by default, so moving it
+// around invalidates anything cached for it, including on disk. Assets can instead be given
+// an identity of their own, via `with_identity` or `with_content_identity`, so that shader
+// directories can be reorganized without losing caches.
+#[derive(Serialize, Debug, Clone, Abomonation)]
 pub struct AssetPath {
     pub crate_name: String,
     pub asset_name: String,
+    // Stands in for the location in hashes when set
+    pub identity: Option<String>,
+}
+
+impl Hash for AssetPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.identity {
+            Some(identity) => identity.hash(state),
+            None => {
+                self.crate_name.hash(state);
+                self.asset_name.hash(state);
+            }
+        }
+    }
 }
 
 impl AssetPath {
     pub async fn to_path_lossy(&self, mut ctx: Context) -> Result<String> {
-        let mut file_path: PathBuf = (*ctx
+        let package_dir = (*ctx
             .get(get_cargo_package_dep_path(self.crate_name.clone()))
             .await?)
             .0
-            .clone()
-            .into();
+            .clone();
+
+        Ok(self.path_in_package(package_dir))
+    }
+
+    fn path_in_package(&self, package_dir: String) -> String {
+        let mut file_path: PathBuf = package_dir.into();
         file_path.push("assets");
         file_path.push(&self.asset_name);
 
-        Ok(file_path.to_string_lossy().to_string())
+        file_path.to_string_lossy().to_string()
+    }
+
+    // Identifies the asset by `identity` instead of its location. Assets with the same
+    // identity are considered the same, wherever they are.
+    pub fn with_identity(mut self, identity: &str) -> Self {
+        self.identity = Some(identity.to_owned());
+        self
+    }
+
+    // Identifies the asset by a hash of its current contents. The hash is stable across
+    // builds, as it ends up in on-disk caches. Beware that identical files, even in
+    // different crates, are then considered the same asset: only one of them is loaded
+    // and watched for changes, so editing the other one has no effect. Shaders including
+    // files relative to their own location should move along with those.
+    pub fn with_content_identity(self) -> Result<Self> {
+        let package_dir = get_cargo_package_dep_path_untracked(&self.crate_name)?;
+        let file_path = self.path_in_package(package_dir);
+        let contents = std::fs::read(&file_path)
+            .map_err(|err| format_err!("Could not read {}: {}", file_path, err))?;
+
+        let hash = crate::shader_cache::fnv1a(&[&contents]);
+        let identity = format!("content:{:016x}", hash);
+        Ok(self.with_identity(&identity))
     }
 }
 
@@ -58,11 +114,13 @@ pub fn rendertoy_asset_path(crate_name: &str, partial_path: &str) -> AssetPath {
         AssetPath {
             crate_name,
             asset_name,
+            identity: None,
         }
     } else {
         AssetPath {
             crate_name: crate_name.to_string(),
             asset_name: partial_path.to_string(),
+            identity: None,
         }
     }
 }
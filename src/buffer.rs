@@ -155,6 +155,111 @@ where
     Ok(res)
 }
 
+// Copies the contents of `buf` back to the CPU. The data becomes available once the GPU
+// has finished the frame this was recorded in, so it must not be awaited by anything
+// which that same frame depends on.
+pub fn read_back_buffer(buf: &Buffer) -> impl std::future::Future<Output = Result<Vec<u8>>> {
+    read_back_buffer_as::<u8>(buf)
+}
+
+// Like `read_back_buffer`, but copying straight into a `Vec<T>`, so it can be handed
+// to e.g. `ndarray` without converting. The buffer size must be a multiple of `T`'s.
+pub fn read_back_buffer_as<T: Copy + Default + Send + 'static>(
+    buf: &Buffer,
+) -> impl std::future::Future<Output = Result<Vec<T>>> {
+    let size_bytes = buf.key.size_bytes;
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    let size_ok = size_bytes % size_of::<T>() == 0;
+
+    // Keep the source alive until the copy has executed
+    let buf = buf.clone();
+    let sender = std::sync::Mutex::new(Some(sender));
+
+    vk_add_setup_command(move |vk, vk_frame| {
+        let cb = vk_frame.command_buffer.lock().unwrap();
+        let cb: vk::CommandBuffer = cb.cb;
+        let region = vk_frame.readback.allocate(size_bytes);
+
+        unsafe {
+            vk_sync::cmd::pipeline_barrier(
+                vk.device.fp_v1_0(),
+                cb,
+                Some(vk_sync::GlobalBarrier {
+                    previous_accesses: &[vk_sync::AccessType::General],
+                    next_accesses: &[vk_sync::AccessType::TransferRead],
+                }),
+                &[],
+                &[],
+            );
+
+            let buffer_copy_regions = vk::BufferCopy::builder()
+                .dst_offset(region.offset as u64)
+                .size(size_bytes as u64);
+            vk.device.cmd_copy_buffer(
+                cb,
+                buf.buffer,
+                region.buffer,
+                &[buffer_copy_regions.build()],
+            );
+
+            vk_sync::cmd::pipeline_barrier(
+                vk.device.fp_v1_0(),
+                cb,
+                Some(vk_sync::GlobalBarrier {
+                    previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                    next_accesses: &[vk_sync::AccessType::HostRead],
+                }),
+                &[],
+                &[],
+            );
+        }
+
+        vk_frame
+            .frame_cleanup
+            .lock()
+            .unwrap()
+            .push(Box::new(move |_vk| {
+                let _ = &buf;
+
+                if let Some(sender) = sender.lock().unwrap().take() {
+                    let _ = sender.send(region.read::<T>());
+                }
+            }));
+    });
+
+    async move {
+        if !size_ok {
+            bail!(
+                "The buffer size of {} is not a multiple of {} bytes",
+                size_bytes,
+                size_of::<T>()
+            );
+        }
+
+        receiver
+            .await
+            .map_err(|_| format_err!("Buffer readback was cancelled"))
+    }
+}
+
+// `read_back_buffer` as an op, for CPU-side processing of compute outputs within the graph.
+// As with `image_metric`, this must not be awaited by the frame which renders `buf`.
+#[snoozy]
+pub async fn read_buffer_snoozy(mut ctx: Context, buf: &SnoozyRef<Buffer>) -> Result<Vec<u8>> {
+    let buf = ctx.get(buf).await?;
+    read_back_buffer(&*buf).await
+}
+
+// Typed variant of `read_buffer`; see `read_back_buffer_as`.
+#[snoozy]
+pub async fn read_buffer_as_snoozy<T: Copy + Default + Send + Sync + 'static>(
+    mut ctx: Context,
+    buf: &SnoozyRef<Buffer>,
+) -> Result<Vec<T>> {
+    let buf = ctx.get(buf).await?;
+    read_back_buffer_as::<T>(&*buf).await
+}
+
 #[snoozy]
 pub async fn upload_array_buffer_snoozy<
     T: Sized + Copy + 'static,
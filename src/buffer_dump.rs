@@ -0,0 +1,245 @@
+// Pretty-printing of buffer contents, so that SSBO state can be inspected without
+// a graphics debugger. The element layout is given in GLSL syntax, the same way
+// the struct is declared in the shader, and is laid out according to std430 rules.
+
+use crate::buffer::{read_back_buffer, Buffer};
+use snoozy::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferFieldType {
+    Float,
+    Int,
+    Uint,
+    Vec2,
+    Vec3,
+    Vec4,
+    Ivec2,
+    Ivec3,
+    Ivec4,
+    Uvec2,
+    Uvec3,
+    Uvec4,
+    Mat4,
+}
+
+impl BufferFieldType {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "float" => BufferFieldType::Float,
+            "int" => BufferFieldType::Int,
+            "uint" => BufferFieldType::Uint,
+            "vec2" => BufferFieldType::Vec2,
+            "vec3" => BufferFieldType::Vec3,
+            "vec4" => BufferFieldType::Vec4,
+            "ivec2" => BufferFieldType::Ivec2,
+            "ivec3" => BufferFieldType::Ivec3,
+            "ivec4" => BufferFieldType::Ivec4,
+            "uvec2" => BufferFieldType::Uvec2,
+            "uvec3" => BufferFieldType::Uvec3,
+            "uvec4" => BufferFieldType::Uvec4,
+            "mat4" => BufferFieldType::Mat4,
+            _ => bail!("Unsupported buffer field type: {}", s),
+        })
+    }
+
+    fn component_count(self) -> usize {
+        match self {
+            BufferFieldType::Float | BufferFieldType::Int | BufferFieldType::Uint => 1,
+            BufferFieldType::Vec2 | BufferFieldType::Ivec2 | BufferFieldType::Uvec2 => 2,
+            BufferFieldType::Vec3 | BufferFieldType::Ivec3 | BufferFieldType::Uvec3 => 3,
+            BufferFieldType::Vec4 | BufferFieldType::Ivec4 | BufferFieldType::Uvec4 => 4,
+            BufferFieldType::Mat4 => 16,
+        }
+    }
+
+    fn size(self) -> usize {
+        self.component_count() * 4
+    }
+
+    // std430: vec3 aligns like vec4, and matrices like their column vectors
+    fn alignment(self) -> usize {
+        match self.component_count() {
+            1 => 4,
+            2 => 8,
+            _ => 16,
+        }
+    }
+
+    fn format_component(self, bytes: &[u8]) -> String {
+        let bits = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        match self {
+            BufferFieldType::Int
+            | BufferFieldType::Ivec2
+            | BufferFieldType::Ivec3
+            | BufferFieldType::Ivec4 => format!("{}", bits as i32),
+            BufferFieldType::Uint
+            | BufferFieldType::Uvec2
+            | BufferFieldType::Uvec3
+            | BufferFieldType::Uvec4 => format!("{}", bits),
+            _ => format!("{:?}", f32::from_bits(bits)),
+        }
+    }
+
+    fn format(self, bytes: &[u8]) -> String {
+        let components: Vec<String> = bytes
+            .chunks(4)
+            .take(self.component_count())
+            .map(|c| self.format_component(c))
+            .collect();
+
+        if components.len() == 1 {
+            components.into_iter().next().unwrap()
+        } else {
+            format!("({})", components.join(", "))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferField {
+    pub name: String,
+    pub ty: BufferFieldType,
+    pub array_len: Option<usize>,
+    pub offset: usize,
+}
+
+impl BufferField {
+    // std430 arrays of scalars and vectors are not padded to 16 bytes, but vec3s still are
+    fn array_stride(&self) -> usize {
+        let align = self.ty.alignment();
+        (self.ty.size() + align - 1) / align * align
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BufferLayout {
+    pub fields: Vec<BufferField>,
+    // Distance between consecutive elements
+    pub stride: usize,
+}
+
+impl BufferLayout {
+    // Parses struct members, e.g. `vec3 position; float radius; uint flags[4];`.
+    pub fn parse(desc: &str) -> Result<Self> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        let mut max_align = 4;
+
+        for decl in desc.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = decl.split_whitespace();
+            let (ty, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(ty), Some(name), None) => (BufferFieldType::parse(ty)?, name),
+                _ => bail!("Expected `type name`, got `{}`", decl),
+            };
+
+            let (name, array_len) = if let Some(bracket) = name.find('[') {
+                let len = name[bracket + 1..]
+                    .trim_end_matches(']')
+                    .parse::<usize>()
+                    .map_err(|_| format_err!("Invalid array length in `{}`", decl))?;
+                (&name[..bracket], Some(len))
+            } else {
+                (name, None)
+            };
+
+            let align = ty.alignment();
+            max_align = max_align.max(align);
+            offset = (offset + align - 1) / align * align;
+
+            let field = BufferField {
+                name: name.to_owned(),
+                ty,
+                array_len,
+                offset,
+            };
+
+            offset += match array_len {
+                Some(len) => field.array_stride() * len,
+                None => ty.size(),
+            };
+            fields.push(field);
+        }
+
+        if fields.is_empty() {
+            bail!("Empty buffer layout");
+        }
+
+        Ok(Self {
+            fields,
+            stride: (offset + max_align - 1) / max_align * max_align,
+        })
+    }
+
+    fn format_element(&self, bytes: &[u8]) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let value = match field.array_len {
+                    Some(len) => {
+                        let items: Vec<String> = (0..len)
+                            .map(|i| {
+                                let offset = field.offset + i * field.array_stride();
+                                field.ty.format(&bytes[offset..offset + field.ty.size()])
+                            })
+                            .collect();
+                        format!("[{}]", items.join(", "))
+                    }
+                    None => field
+                        .ty
+                        .format(&bytes[field.offset..field.offset + field.ty.size()]),
+                };
+                format!("{}: {}", field.name, value)
+            })
+            .collect();
+
+        format!("{{ {} }}", fields.join(", "))
+    }
+
+    // One line per element; a trailing partial element is ignored.
+    pub fn format_elements(&self, bytes: &[u8]) -> String {
+        bytes
+            .chunks(self.stride)
+            .filter(|element| element.len() == self.stride)
+            .enumerate()
+            .map(|(i, element)| format!("[{}] {}\n", i, self.format_element(element)))
+            .collect()
+    }
+}
+
+// Reads back `buf`, and writes its contents formatted according to `layout` (see
+// `BufferLayout::parse`) to `path`, or to the log if `None`. As with `read_back_buffer`,
+// this must not be awaited by the frame which writes `buf`.
+#[snoozy]
+pub async fn dump_buf_snoozy(
+    mut ctx: Context,
+    buf: &SnoozyRef<Buffer>,
+    layout: &String,
+    path: &Option<String>,
+) -> Result<()> {
+    let layout = BufferLayout::parse(layout)?;
+    let buf = ctx.get(buf).await?;
+    let contents = read_back_buffer(&buf).await?;
+    let text = layout.format_elements(&contents);
+
+    if let Some(path) = path {
+        std::fs::write(path, text)?;
+        tracing::info!("Saved {}", path);
+    } else {
+        tracing::info!("Buffer contents:\n{}", text);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_buffer_layout_std430() {
+    let layout = BufferLayout::parse("float a; vec3 b; uint c[3]; vec2 d;").unwrap();
+    let offsets: Vec<usize> = layout.fields.iter().map(|f| f.offset).collect();
+    assert_eq!(offsets, vec![0, 16, 28, 40]);
+    assert_eq!(layout.stride, 48);
+
+    let layout = BufferLayout::parse("vec3 a[2]; float b;").unwrap();
+    assert_eq!(layout.fields[1].offset, 32);
+    assert_eq!(layout.stride, 48);
+}
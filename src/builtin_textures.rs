@@ -0,0 +1,237 @@
+// Procedural textures which experiments commonly need, generated on the CPU the first
+// time they're used, so they don't have to be shipped as files or built with bespoke
+// compute passes:
+//
+// * `builtin_blue_noise_tex`: tileable blue noise, by void-and-cluster
+// * `builtin_bayer_tex`: an ordered dithering matrix
+// * `builtin_brdf_lut_tex`: the split-sum GGX environment BRDF
+//
+// The noise and dithering textures are `R32_SFLOAT`, with every texel holding a distinct
+// threshold `(rank + 0.5) / texel_count`, so they're uniformly distributed in (0, 1).
+
+use crate::host_interop::{upload_tex_from_slice, HostImageLayout};
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+// Void-and-cluster is quadratic in the texel count, so bigger sizes take too long.
+const MAX_BLUE_NOISE_SIZE: u32 = 128;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+const BRDF_LUT_SAMPLE_COUNT: u32 = 512;
+
+fn upload_thresholds(ranks: &[u32], width: u32, height: u32) -> Result<Texture> {
+    let count = ranks.len() as f32;
+    let thresholds: Vec<f32> = ranks.iter().map(|r| (*r as f32 + 0.5) / count).collect();
+    upload_tex_from_slice(
+        &thresholds,
+        &HostImageLayout::packed(width, height, vk::Format::R32_SFLOAT),
+    )
+}
+
+// Toroidal Gaussian energy of the set texels of a binary pattern.
+struct VoidAndCluster {
+    width: usize,
+    height: usize,
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+    pattern: Vec<bool>,
+}
+
+impl VoidAndCluster {
+    fn new(width: usize, height: usize) -> Self {
+        let mut kernel = vec![0.0; width * height];
+        for dy in 0..height {
+            for dx in 0..width {
+                let x = dx.min(width - dx) as f32;
+                let y = dy.min(height - dy) as f32;
+                kernel[dy * width + dx] =
+                    (-(x * x + y * y) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp();
+            }
+        }
+
+        Self {
+            width,
+            height,
+            kernel,
+            energy: vec![0.0; width * height],
+            pattern: vec![false; width * height],
+        }
+    }
+
+    fn set(&mut self, idx: usize, value: bool) {
+        if self.pattern[idx] == value {
+            return;
+        }
+        self.pattern[idx] = value;
+
+        let sign = if value { 1.0 } else { -1.0 };
+        let (px, py) = (idx % self.width, idx / self.width);
+        for y in 0..self.height {
+            let kernel_row = (y + self.height - py) % self.height * self.width;
+            for x in 0..self.width {
+                self.energy[y * self.width + x] +=
+                    sign * self.kernel[kernel_row + (x + self.width - px) % self.width];
+            }
+        }
+    }
+
+    // The set texel with the highest energy
+    fn tightest_cluster(&self) -> usize {
+        (0..self.pattern.len())
+            .filter(|i| self.pattern[*i])
+            .max_by(|a, b| self.energy[*a].partial_cmp(&self.energy[*b]).unwrap())
+            .unwrap()
+    }
+
+    // The unset texel with the lowest energy
+    fn largest_void(&self) -> usize {
+        (0..self.pattern.len())
+            .filter(|i| !self.pattern[*i])
+            .min_by(|a, b| self.energy[*a].partial_cmp(&self.energy[*b]).unwrap())
+            .unwrap()
+    }
+}
+
+fn blue_noise_ranks(size: usize) -> Vec<u32> {
+    let count = size * size;
+    let mut vc = VoidAndCluster::new(size, size);
+
+    // Deterministic random initial pattern, covering about a tenth of the texels
+    let mut seed = 0x9e37_79b9u32;
+    for _ in 0..(count / 10).max(1) {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        vc.set(seed as usize % count, true);
+    }
+
+    // Spread it out evenly by moving texels from clusters into voids
+    for _ in 0..count {
+        let cluster = vc.tightest_cluster();
+        vc.set(cluster, false);
+        let void = vc.largest_void();
+        vc.set(void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let prototype = vc.pattern.clone();
+    let initial_count = prototype.iter().filter(|b| **b).count();
+    let mut ranks = vec![0u32; count];
+
+    // Rank the initial texels by removing the tightest clusters first...
+    for rank in (0..initial_count).rev() {
+        let cluster = vc.tightest_cluster();
+        vc.set(cluster, false);
+        ranks[cluster] = rank as u32;
+    }
+
+    // ...and the rest by filling the largest voids.
+    for (idx, set) in prototype.iter().enumerate() {
+        vc.set(idx, *set);
+    }
+    for rank in initial_count..count {
+        let void = vc.largest_void();
+        vc.set(void, true);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
+
+// A `size` x `size` tileable blue noise texture; see the top of this file. At most 128 texels
+// wide, and generated in about a second at that size.
+#[snoozy]
+pub async fn builtin_blue_noise_tex_snoozy(_ctx: Context, size: &u32) -> Result<Texture> {
+    let size = *size;
+    if size == 0 || size > MAX_BLUE_NOISE_SIZE {
+        bail!(
+            "Blue noise textures can be 1 to {} texels wide, not {}",
+            MAX_BLUE_NOISE_SIZE,
+            size
+        );
+    }
+
+    upload_thresholds(&blue_noise_ranks(size as usize), size, size)
+}
+
+// A `size` x `size` Bayer matrix, for ordered dithering. `size` must be a power of two.
+#[snoozy]
+pub async fn builtin_bayer_tex_snoozy(_ctx: Context, size: &u32) -> Result<Texture> {
+    let size = *size;
+    if !size.is_power_of_two() {
+        bail!("Bayer matrices must be a power of two wide, not {}", size);
+    }
+
+    // The lowest bits of the coordinates pick the most significant digits of the rank
+    let bits = size.trailing_zeros();
+    let ranks: Vec<u32> = (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            (0..bits).fold(0, |rank, bit| {
+                let (xb, yb) = ((x >> bit) & 1, (y >> bit) & 1);
+                (rank << 2) | ((xb ^ yb) << 1) | yb
+            })
+        })
+        .collect();
+
+    upload_thresholds(&ranks, size, size)
+}
+
+fn brdf_lut_texel(n_dot_v: f32, roughness: f32) -> [f32; 2] {
+    let view = [(1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v];
+    let alpha = roughness * roughness;
+    // Schlick-Smith geometry term, with `k` as used for image based lighting
+    let k = alpha / 2.0;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+
+    let mut scale = 0.0;
+    let mut bias = 0.0;
+    for i in 0..BRDF_LUT_SAMPLE_COUNT {
+        // Hammersley point, importance sampling the GGX distribution
+        let u1 = i as f32 / BRDF_LUT_SAMPLE_COUNT as f32;
+        let u2 = i.reverse_bits() as f32 / 4_294_967_296.0;
+        let phi = 2.0 * std::f32::consts::PI * u1;
+        let cos_theta = ((1.0 - u2) / (1.0 + (alpha * alpha - 1.0) * u2)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let half = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta];
+
+        let v_dot_h = view[0] * half[0] + view[1] * half[1] + view[2] * half[2];
+        let n_dot_l = 2.0 * v_dot_h * half[2] - view[2];
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        let g_vis = g1(n_dot_v) * g1(n_dot_l) * v_dot_h / (half[2] * n_dot_v);
+        let fresnel = (1.0 - v_dot_h).powi(5);
+        scale += (1.0 - fresnel) * g_vis;
+        bias += fresnel * g_vis;
+    }
+
+    [
+        scale / BRDF_LUT_SAMPLE_COUNT as f32,
+        bias / BRDF_LUT_SAMPLE_COUNT as f32,
+    ]
+}
+
+// A `size` x `size` `R32G32_SFLOAT` lookup of the split-sum environment BRDF of GGX, by
+// N.V along x and roughness along y, both from 0 to 1. Specular image based lighting is
+// then `prefiltered * (f0 * lut.x + lut.y)`.
+#[snoozy]
+pub async fn builtin_brdf_lut_tex_snoozy(_ctx: Context, size: &u32) -> Result<Texture> {
+    let size = (*size).max(1);
+    let texels: Vec<[f32; 2]> = (0..size * size)
+        .map(|i| {
+            let n_dot_v = ((i % size) as f32 + 0.5) / size as f32;
+            let roughness = ((i / size) as f32 + 0.5) / size as f32;
+            brdf_lut_texel(n_dot_v, roughness)
+        })
+        .collect();
+
+    upload_tex_from_slice(
+        &texels,
+        &HostImageLayout::packed(size, size, vk::Format::R32G32_SFLOAT),
+    )
+}
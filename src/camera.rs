@@ -1,4 +1,9 @@
-use crate::{math::*, FrameState, VirtualKeyCode};
+use crate::math::*;
+use crate::sample_sequence::SampleSequence;
+use crate::shader::{ShaderUniformBundle, ShaderUniformHolder};
+use crate::shader_uniforms;
+#[cfg(feature = "window")]
+use crate::{FrameState, VirtualKeyCode};
 
 #[derive(PartialEq, Clone)]
 pub struct CameraMatrices {
@@ -47,6 +52,7 @@ pub struct FirstPersonCameraInput {
     dt: f32,
 }
 
+#[cfg(feature = "window")]
 impl<'a> From<&FrameState<'a>> for FirstPersonCameraInput {
     fn from(frame_state: &FrameState<'a>) -> FirstPersonCameraInput {
         let mut yaw_delta = 0.0;
@@ -286,3 +292,95 @@ impl<CameraType: Camera> Camera for CameraConvergenceEnforcer<CameraType> {
         self.frozen_matrices.clone()
     }
 }
+
+// Parameters of a physical camera, from which field of view, exposure and depth of field
+// are derived. Defaults to a 50mm lens on a full frame sensor.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalCamera {
+    // Meters
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    pub focal_length: f32,
+    pub focus_distance: f32,
+
+    pub f_stop: f32,
+    // Seconds
+    pub shutter_time: f32,
+    pub iso: f32,
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        Self {
+            sensor_width: 0.036,
+            sensor_height: 0.024,
+            focal_length: 0.05,
+            focus_distance: 5.0,
+            f_stop: 2.8,
+            shutter_time: 1.0 / 60.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    // Vertical; assign to `FirstPersonCamera::fov`
+    pub fn fov_degrees(&self) -> f32 {
+        (2.0 * (0.5 * self.sensor_height / self.focal_length).atan()).to_degrees()
+    }
+
+    pub fn aperture_diameter(&self) -> f32 {
+        self.focal_length / self.f_stop
+    }
+
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter_time * 100.0 / self.iso).log2()
+    }
+
+    // Multiplier taking scene luminance to [0, 1] sensor response, saturating at 1
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * 2.0f32.powf(self.ev100()))
+    }
+
+    // Circle of confusion diameter relative to the image height, for a point at distance d
+    // is `coc_scale * abs(1 - focus_distance / d)`.
+    pub fn coc_scale(&self) -> f32 {
+        let f = self.focal_length;
+        self.aperture_diameter() * f / (self.focus_distance - f).max(1e-5) / self.sensor_height
+    }
+
+    // The standard camera bundle for DOF, motion blur and TAA passes.
+    // `jitter` is the subpixel offset of this frame, e.g. from `taa_jitter`.
+    pub fn uniforms(&self, frame_index: u32, jitter: Vec2) -> ShaderUniformBundle {
+        shader_uniforms!(
+            camera_exposure: self.exposure(),
+            camera_ev100: self.ev100(),
+            camera_focal_length: self.focal_length,
+            camera_focus_distance: self.focus_distance,
+            camera_aperture_diameter: self.aperture_diameter(),
+            camera_coc_scale: self.coc_scale(),
+            camera_shutter_time: self.shutter_time,
+            camera_sensor_size: (self.sensor_width, self.sensor_height, 0.0f32, 0.0f32),
+            camera_jitter: (jitter.x(), jitter.y(), 0.0f32, 0.0f32),
+            camera_frame_index: frame_index,
+        )
+    }
+}
+
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut f = 1.0;
+    let mut res = 0.0;
+    while index > 0 {
+        f /= base as f32;
+        res += f * (index % base) as f32;
+        index /= base;
+    }
+    res
+}
+
+// Subpixel offset in [-0.5, 0.5] pixels, cycling through `sample_count` points
+// of the Halton (2, 3) sequence. Pass to `VieportConstantBuilder::pixel_offset`.
+// Shaders can get the same offsets via `SampleSequence::Halton23.uniforms`.
+pub fn taa_jitter(frame_index: u32, sample_count: u32) -> Vec2 {
+    SampleSequence::Halton23.jitter(frame_index, sample_count)
+}
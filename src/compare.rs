@@ -0,0 +1,179 @@
+use crate::blob::AssetPath;
+use crate::buffer::{read_back_buffer, BufferKey};
+use crate::package::set_asset_namespace_override;
+use crate::shader::{compute_buf, compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum CompareMode {
+    // Left half shows `a`, right half shows `b`; both centered on the divider
+    SideBySide,
+    // `a` to the left of the divider, `b` to the right
+    Wipe,
+    // Heatmap of the luminance difference
+    Difference,
+}
+
+// Composites two textures for visual comparison. `divider` is in [0, 1] across the width
+// of the image; `FrameState::mouse_uv` is handy for driving it interactively.
+#[snoozy]
+pub async fn compare_tex_snoozy(
+    mut ctx: Context,
+    a: &SnoozyRef<Texture>,
+    b: &SnoozyRef<Texture>,
+    mode: &CompareMode,
+    divider: &f32,
+) -> Result<Texture> {
+    let key = ctx
+        .get(a)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/compare.glsl").to_owned(),
+        "compare.glsl".to_owned(),
+    );
+
+    let mode = match mode {
+        CompareMode::SideBySide => 0u32,
+        CompareMode::Wipe => 1u32,
+        CompareMode::Difference => 2u32,
+    };
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                texA: a.clone(),
+                texB: b.clone(),
+                mode: mode,
+                divider: *divider,
+                diff_scale: 10.0f32,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Builds the same graph twice: once with assets from `namespace::`, and once with
+// the namespace remapped to `alt_root`, then wipes between them at `divider`.
+//
+// `build` receives the namespace to use for its asset paths.
+pub fn ab_compare_asset_namespace(
+    namespace: &str,
+    alt_root: &str,
+    divider: f32,
+    build: impl Fn(&str) -> SnoozyRef<Texture>,
+) -> SnoozyRef<Texture> {
+    let alias = format!("{}@b", namespace);
+    set_asset_namespace_override(&alias, Some(alt_root));
+
+    compare_tex(build(namespace), build(&alias), CompareMode::Wipe, divider)
+}
+
+// Returns `path` with its namespace replaced, for use in `ab_compare_asset_namespace` callbacks.
+pub fn with_asset_namespace(path: &AssetPath, namespace: &str) -> AssetPath {
+    AssetPath {
+        crate_name: namespace.to_owned(),
+        asset_name: path.asset_name.clone(),
+        identity: None,
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum ImageMetric {
+    // Peak signal-to-noise ratio in dB, assuming a [0, 1] range. Higher is better.
+    Psnr,
+    // Mean structural similarity of luminance. 1 means identical.
+    Ssim,
+    // Mean FLIP-style perceptual error in [0, 1], without the CSF prefilter
+    // of the reference implementation. 0 means identical.
+    Flip,
+}
+
+// Per-pixel error between `a` and `b` in the red channel: squared error for `Psnr`,
+// local SSIM for `Ssim`, and perceptual error for `Flip`. Inputs are treated as linear RGB.
+#[snoozy]
+pub async fn image_metric_error_tex_snoozy(
+    mut ctx: Context,
+    a: &SnoozyRef<Texture>,
+    b: &SnoozyRef<Texture>,
+    metric: &ImageMetric,
+) -> Result<Texture> {
+    let key = ctx.get(a).await?.key.with_format(vk::Format::R32_SFLOAT);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/image_metrics.glsl").to_owned(),
+        "image_metrics.glsl".to_owned(),
+    );
+
+    let metric = match metric {
+        ImageMetric::Psnr => 0u32,
+        ImageMetric::Ssim => 1u32,
+        ImageMetric::Flip => 2u32,
+    };
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                texA: a.clone(),
+                texB: b.clone(),
+                metric: metric,
+                // A 0.7m wide 4K monitor viewed from 0.7m
+                pixels_per_degree: 67.0f32,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Reduces `image_metric_error_tex` to a single number, e.g. for gating golden image tests.
+//
+// The value is read back from the GPU, and only resolves once the frame which computed it
+// has finished. Evaluate it separately from the frame's output, or it will never complete.
+#[snoozy]
+pub async fn image_metric_snoozy(
+    mut ctx: Context,
+    a: &SnoozyRef<Texture>,
+    b: &SnoozyRef<Texture>,
+    metric: &ImageMetric,
+) -> Result<f32> {
+    let error_tex = image_metric_error_tex(a.clone(), b.clone(), *metric);
+    let key = ctx.get(&error_tex).await?.key;
+
+    let group_count = ((key.width + 7) / 8) * ((key.height + 7) / 8);
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/reduce_sum.glsl").to_owned(),
+        "reduce_sum.glsl".to_owned(),
+    );
+
+    let partial_sums = ctx
+        .get(compute_buf(
+            BufferKey::new(group_count as usize * std::mem::size_of::<f32>(), None),
+            [key.width, key.height, 1],
+            cs,
+            shader_uniforms!(inputTex: error_tex),
+        ))
+        .await?;
+
+    let bytes = read_back_buffer(&partial_sums).await?;
+    let sum: f64 = bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]) as f64)
+        .sum();
+    let mean = sum / (key.width as f64 * key.height as f64);
+
+    Ok(match metric {
+        ImageMetric::Psnr => (-10.0 * mean.log10()) as f32,
+        ImageMetric::Ssim | ImageMetric::Flip => mean as f32,
+    })
+}
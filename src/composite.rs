@@ -0,0 +1,110 @@
+// Declarative compositing of the final image out of layers, such as effects, debug views
+// and UI, without a bespoke composite shader:
+//
+//   Composite::new(scene)
+//       .layer(bloom, CompositeBlend::Add, tweak_f32("bloom".to_owned(), 0.2))
+//       .layer(ui, CompositeBlend::PremultipliedAlpha, const_f32(1.0))
+//       .output()
+//
+// Layers are blended over the base one by one, in the order added. The output has the
+// size of the base, in `R16G16B16A16_SFLOAT`; layers of other sizes get stretched over it.
+// Opacity is an asset, so it can be tweaked or animated, and layers at zero opacity are
+// skipped without evaluating them.
+
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Abomonation)]
+pub enum CompositeBlend {
+    // Straight alpha: the layer covers the image by its alpha times the opacity
+    Alpha,
+    // As above, with the color of the layer already multiplied by its alpha
+    PremultipliedAlpha,
+    // Adds the color of the layer, weighted by its alpha and the opacity
+    Add,
+    Multiply,
+    Screen,
+}
+
+impl CompositeBlend {
+    fn shader_value(self) -> u32 {
+        match self {
+            CompositeBlend::Alpha => 0,
+            CompositeBlend::PremultipliedAlpha => 1,
+            CompositeBlend::Add => 2,
+            CompositeBlend::Multiply => 3,
+            CompositeBlend::Screen => 4,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Composite {
+    output: SnoozyRef<Texture>,
+}
+
+impl Composite {
+    pub fn new(base: SnoozyRef<Texture>) -> Self {
+        Self { output: base }
+    }
+
+    pub fn layer(
+        self,
+        tex: SnoozyRef<Texture>,
+        blend: CompositeBlend,
+        opacity: SnoozyRef<f32>,
+    ) -> Self {
+        Self {
+            output: composite_layer_tex(self.output, tex, blend, opacity),
+        }
+    }
+
+    // The base with all layers blended over it.
+    pub fn output(self) -> SnoozyRef<Texture> {
+        self.output
+    }
+}
+
+// A single layer of a `Composite`.
+#[snoozy]
+pub async fn composite_layer_tex_snoozy(
+    mut ctx: Context,
+    dst: &SnoozyRef<Texture>,
+    layer: &SnoozyRef<Texture>,
+    blend: &CompositeBlend,
+    opacity: &SnoozyRef<f32>,
+) -> Result<Texture> {
+    let opacity = *ctx.get(opacity).await?;
+    if opacity <= 0.0 {
+        return Ok((*ctx.get(dst).await?).clone());
+    }
+
+    let key = ctx
+        .get(dst)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/composite_layer.glsl").to_owned(),
+        "composite_layer.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                dstTex: dst.clone(),
+                layerTex: layer.clone(),
+                blend_mode: blend.shader_value(),
+                opacity: opacity,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
@@ -116,10 +116,27 @@ impl ImageFilterDesc {
                         ShaderUniformValue::Uint32(_) => "uint",
                         ShaderUniformValue::Int32(_) => "int",
                         ShaderUniformValue::Ivec2(_) => "ivec2",
+                        ShaderUniformValue::Vec2(_) => "vec2",
+                        ShaderUniformValue::Vec3(_) => "vec3",
                         ShaderUniformValue::Vec4(_) => "vec4",
+                        ShaderUniformValue::Mat3(_) => "mat3",
+                        ShaderUniformValue::Mat4(_) => "mat4",
+                        ShaderUniformValue::Float32Array(v) => {
+                            return Some(format!("float {}[{}];\n", name, v.len()))
+                        }
+                        ShaderUniformValue::Int32Array(v) => {
+                            return Some(format!("int {}[{}];\n", name, v.len()))
+                        }
+                        ShaderUniformValue::Vec4Array(v) => {
+                            return Some(format!("vec4 {}[{}];\n", name, v.len()))
+                        }
                         ShaderUniformValue::Float32Asset(_) => "float",
                         ShaderUniformValue::Uint32Asset(_) => "uint",
                         ShaderUniformValue::UsizeAsset(_) => "int", // TOOO
+                        ShaderUniformValue::Vec2Asset(_) => "vec2",
+                        ShaderUniformValue::Vec3Asset(_) => "vec3",
+                        ShaderUniformValue::Mat3Asset(_) => "mat3",
+                        ShaderUniformValue::Mat4Asset(_) => "mat4",
                         ShaderUniformValue::TextureAsset(_) => return None,
                         ShaderUniformValue::BufferAsset(_) => {
                             panic!("Buffer parameters not supported")
@@ -128,6 +145,9 @@ impl ImageFilterDesc {
                         ShaderUniformValue::BundleAsset(_) => {
                             panic!("Bundle asset parameters not supported")
                         }
+                        ShaderUniformValue::Tags(_) => return None,
+                        ShaderUniformValue::OcclusionQuery(_) => return None,
+                        ShaderUniformValue::OutputLoadOp(_) => return None,
                     };
 
                     Some(format!("{} {};\n", t, name))
@@ -150,10 +170,21 @@ impl ImageFilterDesc {
                         ShaderUniformValue::Uint32(_) => return None,
                         ShaderUniformValue::Int32(_) => return None,
                         ShaderUniformValue::Ivec2(_) => return None,
+                        ShaderUniformValue::Vec2(_) => return None,
+                        ShaderUniformValue::Vec3(_) => return None,
                         ShaderUniformValue::Vec4(_) => return None,
+                        ShaderUniformValue::Mat3(_) => return None,
+                        ShaderUniformValue::Mat4(_) => return None,
+                        ShaderUniformValue::Float32Array(_) => return None,
+                        ShaderUniformValue::Int32Array(_) => return None,
+                        ShaderUniformValue::Vec4Array(_) => return None,
                         ShaderUniformValue::Float32Asset(_) => return None,
                         ShaderUniformValue::Uint32Asset(_) => return None,
                         ShaderUniformValue::UsizeAsset(_) => return None,
+                        ShaderUniformValue::Vec2Asset(_) => return None,
+                        ShaderUniformValue::Vec3Asset(_) => return None,
+                        ShaderUniformValue::Mat3Asset(_) => return None,
+                        ShaderUniformValue::Mat4Asset(_) => return None,
                         ShaderUniformValue::TextureAsset(_) => "texture2D",
                         ShaderUniformValue::BufferAsset(_) => {
                             panic!("Buffer parameters not supported")
@@ -162,6 +193,9 @@ impl ImageFilterDesc {
                         ShaderUniformValue::BundleAsset(_) => {
                             panic!("Bundle asset parameters not supported")
                         }
+                        ShaderUniformValue::Tags(_) => return None,
+                        ShaderUniformValue::OcclusionQuery(_) => return None,
+                        ShaderUniformValue::OutputLoadOp(_) => return None,
                     };
 
                     binding += 1;
@@ -0,0 +1,163 @@
+// Coordinate conventions of raster and compute passes. By default, NDC +Y points up, as in
+// GL, through a flipped viewport, and depth is reverse-Z: 1 at the near plane, 0 at
+// infinity, tested with `GREATER_OR_EQUAL` and cleared to 0. Either can be switched to
+// the native Vulkan way, for shaders which assume it.
+//
+// Cameras produce matrices for the default convention; `ViewConstants` adjusts them to
+// the configured one. Shaders get it as defines:
+//
+//   RTOY_NDC_Y_UP      1 if NDC +Y points to the top of the image
+//   RTOY_REVERSE_Z     1 if depth decreases with distance
+//   RTOY_DEPTH_NEAR    depth at the near plane
+//   RTOY_DEPTH_FAR     depth at infinity
+//
+// along with the `rtoy_uv_to_ndc(uv)` and `rtoy_ndc_to_uv(ndc)` macros, with UV (0, 0)
+// at the top left texel.
+//
+// Must be set before any shaders are compiled or raster pipelines created.
+
+use crate::math::*;
+use ash::vk;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdcYDirection {
+    // GL style; the default
+    Up,
+    // Native Vulkan
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthConvention {
+    // Near plane at 1; the default
+    ReverseZ,
+    // Near plane at 0
+    Standard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoordConvention {
+    pub ndc_y: NdcYDirection,
+    pub depth: DepthConvention,
+}
+
+impl Default for CoordConvention {
+    fn default() -> Self {
+        Self {
+            ndc_y: NdcYDirection::Up,
+            depth: DepthConvention::ReverseZ,
+        }
+    }
+}
+
+lazy_static! {
+    static ref COORD_CONVENTION: Mutex<CoordConvention> = Mutex::new(Default::default());
+}
+
+pub fn set_coord_convention(convention: CoordConvention) {
+    *COORD_CONVENTION.lock().unwrap() = convention;
+}
+
+pub fn coord_convention() -> CoordConvention {
+    *COORD_CONVENTION.lock().unwrap()
+}
+
+impl CoordConvention {
+    // Takes clip space of the default convention to this one. It's its own inverse.
+    pub fn clip_adjustment(&self) -> Mat4 {
+        let y_scale = match self.ndc_y {
+            NdcYDirection::Up => 1.0,
+            NdcYDirection::Down => -1.0,
+        };
+
+        match self.depth {
+            DepthConvention::ReverseZ => Mat4::from_cols(
+                Vec4::new(1.0, 0.0, 0.0, 0.0),
+                Vec4::new(0.0, y_scale, 0.0, 0.0),
+                Vec4::new(0.0, 0.0, 1.0, 0.0),
+                Vec4::new(0.0, 0.0, 0.0, 1.0),
+            ),
+            // z' = w - z
+            DepthConvention::Standard => Mat4::from_cols(
+                Vec4::new(1.0, 0.0, 0.0, 0.0),
+                Vec4::new(0.0, y_scale, 0.0, 0.0),
+                Vec4::new(0.0, 0.0, -1.0, 0.0),
+                Vec4::new(0.0, 0.0, 1.0, 1.0),
+            ),
+        }
+    }
+
+    // Takes GL clip space, with +Y up and depth from -1 at the near plane to 1 at the far
+    // one, to this convention, e.g. to use projection matrices from GL-era code.
+    pub fn clip_from_gl_clip(&self) -> Mat4 {
+        // To the default convention first: z' = (w - z) / 2
+        let gl_to_default = Mat4::from_cols(
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, -0.5, 0.0),
+            Vec4::new(0.0, 0.0, 0.5, 1.0),
+        );
+        self.clip_adjustment() * gl_to_default
+    }
+
+    pub fn depth_near(&self) -> f32 {
+        match self.depth {
+            DepthConvention::ReverseZ => 1.0,
+            DepthConvention::Standard => 0.0,
+        }
+    }
+
+    pub fn depth_far(&self) -> f32 {
+        1.0 - self.depth_near()
+    }
+
+    pub(crate) fn depth_compare_op(&self) -> vk::CompareOp {
+        match self.depth {
+            DepthConvention::ReverseZ => vk::CompareOp::GREATER_OR_EQUAL,
+            DepthConvention::Standard => vk::CompareOp::LESS_OR_EQUAL,
+        }
+    }
+
+    // Covering all of a `width` by `height` target
+    pub(crate) fn viewport(&self, width: u32, height: u32) -> vk::Viewport {
+        let (y, viewport_height) = match self.ndc_y {
+            NdcYDirection::Up => (height as f32, -(height as f32)),
+            NdcYDirection::Down => (0.0, height as f32),
+        };
+
+        vk::Viewport {
+            x: 0.0,
+            y,
+            width: width as _,
+            height: viewport_height,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    // Only defines, as extensions in the shader source must come before any code
+    pub(crate) fn glsl_preamble(&self) -> String {
+        let ndc_y_up = self.ndc_y == NdcYDirection::Up;
+        let (ndc_y, uv_y) = if ndc_y_up {
+            ("1.0 - (uv).y * 2.0", "0.5 - (ndc).y * 0.5")
+        } else {
+            ("(uv).y * 2.0 - 1.0", "(ndc).y * 0.5 + 0.5")
+        };
+
+        format!(
+            "#define RTOY_NDC_Y_UP {}\n\
+             #define RTOY_REVERSE_Z {}\n\
+             #define RTOY_DEPTH_NEAR {:.1}\n\
+             #define RTOY_DEPTH_FAR {:.1}\n\
+             #define rtoy_uv_to_ndc(uv) vec2((uv).x * 2.0 - 1.0, {})\n\
+             #define rtoy_ndc_to_uv(ndc) vec2((ndc).x * 0.5 + 0.5, {})\n",
+            ndc_y_up as u32,
+            (self.depth == DepthConvention::ReverseZ) as u32,
+            self.depth_near(),
+            self.depth_far(),
+            ndc_y,
+            uv_y,
+        )
+    }
+}
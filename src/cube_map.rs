@@ -0,0 +1,130 @@
+// Cube maps, e.g. for skyboxes and prefiltered environment maps. Textures created with
+// `TextureKey::new_cube` are sampled via `samplerCube`, but written as `image2DArray`,
+// one layer per face, so:
+//
+// * Compute shaders write all faces at once: `compute_tex` dispatches over
+//   `gl_GlobalInvocationID.z`, and `shaders/cube_map.inc` has the direction of each texel.
+// * Raster passes render each face with `build_cube_faces`, and the results are
+//   assembled with `cube_from_faces_tex`.
+// * Equirectangular panoramas are converted with `cube_from_equirect_tex`.
+//
+// `texture_layer_tex` views a single face as a 2D texture. Cube maps aren't part of the
+// bindless texture table.
+
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// Builds a cube map out of the six faces from `build_cube_faces`, in
+// `R16G16B16A16_SFLOAT`. The faces must be square, and of the same size.
+#[snoozy]
+pub async fn cube_from_faces_tex_snoozy(
+    mut ctx: Context,
+    faces: &Vec<SnoozyRef<Texture>>,
+) -> Result<Texture> {
+    if faces.len() != 6 {
+        bail!("Expected 6 cube faces, got {}", faces.len());
+    }
+
+    let size = ctx.get(&faces[0]).await?.key.width;
+    for face in faces.iter() {
+        let key = ctx.get(face).await?.key;
+        if (key.width, key.height) != (size, size) {
+            bail!(
+                "Cube faces must all be {}x{}; got a {}x{} one",
+                size,
+                size,
+                key.width,
+                key.height
+            );
+        }
+    }
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/cube_from_faces.glsl").to_owned(),
+        "cube_from_faces.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new_cube(size, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(
+                texFace0: faces[0].clone(),
+                texFace1: faces[1].clone(),
+                texFace2: faces[2].clone(),
+                texFace3: faces[3].clone(),
+                texFace4: faces[4].clone(),
+                texFace5: faces[5].clone(),
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Resamples an equirectangular panorama, e.g. a `.hdr` environment map, into a cube map
+// with `size` texel faces, in `R16G16B16A16_SFLOAT`. Uses the same mapping as
+// `equirect_from_cube_faces_tex`. Texels average several samples when shrinking.
+#[snoozy]
+pub async fn cube_from_equirect_tex_snoozy(
+    mut ctx: Context,
+    equirect: &SnoozyRef<Texture>,
+    size: &u32,
+) -> Result<Texture> {
+    let equirect_width = ctx.get(equirect).await?.key.width;
+
+    // A face spans a quarter of the panorama's width
+    let face_width = equirect_width / 4;
+    let supersample = ((face_width + *size - 1) / *size).max(1).min(8);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/cube_from_equirect.glsl").to_owned(),
+        "cube_from_equirect.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new_cube(*size, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(
+                inputTex: equirect.clone(),
+                supersample: supersample,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Resamples `cube` to `size` texel faces, in `R16G16B16A16_SFLOAT`. Texels average several
+// samples when shrinking. Cube maps have no mip chains; use this for lower resolutions.
+#[snoozy]
+pub async fn cube_resize_tex_snoozy(
+    mut ctx: Context,
+    cube: &SnoozyRef<Texture>,
+    size: &u32,
+) -> Result<Texture> {
+    let src_size = ctx.get(cube).await?.key.width;
+    let supersample = ((src_size + *size - 1) / *size).max(1).min(8);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/cube_resize.glsl").to_owned(),
+        "cube_resize.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new_cube(*size, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(
+                inputTex: cube.clone(),
+                supersample: supersample,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
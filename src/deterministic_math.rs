@@ -0,0 +1,56 @@
+// Compile mode for bit-reproducible renders on a given GPU and driver, e.g. for golden
+// image tests. Shaders are compiled without SPIR-V optimization, vertex positions are
+// `invariant`, and `RTOY_PRECISE` expands to `precise`, forbidding the driver from
+// reassociating or fusing the qualified math. The `rtoy_rng_seed` uniform, available
+// to every pass, becomes a fixed value instead of changing from run to run.
+//
+// Must be set before any shaders are compiled.
+
+use crate::shader::ShaderKind;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+static DETERMINISTIC_MATH_ENABLED: AtomicBool = AtomicBool::new(false);
+static DETERMINISTIC_RNG_SEED: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) const RNG_SEED_UNIFORM_NAME: &str = "rtoy_rng_seed";
+
+pub fn set_deterministic_math_enabled(enabled: bool) {
+    DETERMINISTIC_MATH_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_deterministic_math_enabled() -> bool {
+    DETERMINISTIC_MATH_ENABLED.load(Ordering::Relaxed)
+}
+
+// The seed used in deterministic mode; zero by default.
+pub fn set_deterministic_rng_seed(seed: u32) {
+    DETERMINISTIC_RNG_SEED.store(seed, Ordering::Relaxed);
+}
+
+lazy_static! {
+    static ref PROCESS_RNG_SEED: u32 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|t| t.subsec_nanos() ^ t.as_secs() as u32)
+        .unwrap_or(0);
+}
+
+// Value of `rtoy_rng_seed`. Constant within a run either way, so it never invalidates passes.
+pub(crate) fn rng_seed() -> u32 {
+    if is_deterministic_math_enabled() {
+        DETERMINISTIC_RNG_SEED.load(Ordering::Relaxed)
+    } else {
+        *PROCESS_RNG_SEED
+    }
+}
+
+pub(crate) fn glsl_preamble(shader_kind: ShaderKind) -> String {
+    if !is_deterministic_math_enabled() {
+        return "#define RTOY_PRECISE\n".to_owned();
+    }
+
+    let mut preamble = "#define RTOY_DETERMINISTIC 1\n#define RTOY_PRECISE precise\n".to_owned();
+    if shader_kind == ShaderKind::Vertex {
+        preamble += "invariant gl_Position;\n";
+    }
+    preamble
+}
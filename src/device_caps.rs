@@ -0,0 +1,388 @@
+use crate::vulkan::vk;
+use ash::version::{EntryV1_0, InstanceV1_0, InstanceV1_1};
+use ash::{vk, Entry, Instance};
+use regex::Regex;
+use std::collections::HashSet;
+use std::ffi::{c_void, CStr, CString};
+
+// Cooperative matrices go through VK_KHR_cooperative_matrix where the device and the
+// GLSL compiler support it, and the NV flavor of the extension otherwise. The component
+// type and scope enums of both share their values, so shapes use the NV types.
+#[derive(Clone, Copy, Debug)]
+pub struct CooperativeMatrixShape {
+    pub m: u32,
+    pub n: u32,
+    pub k: u32,
+    pub a_type: vk::ComponentTypeNV,
+    pub b_type: vk::ComponentTypeNV,
+    pub c_type: vk::ComponentTypeNV,
+    pub d_type: vk::ComponentTypeNV,
+    pub scope: vk::ScopeNV,
+}
+
+// Optional features of the device which rendertoy enables when present.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCaps {
+    pub cooperative_matrix: bool,
+    // Set if `cooperative_matrix` uses VK_KHR_cooperative_matrix rather than the NV one
+    pub cooperative_matrix_khr: bool,
+    pub cooperative_matrix_shapes: Vec<CooperativeMatrixShape>,
+    // `float16_t`, `int16_t` etc. in storage buffers
+    pub storage_16bit: bool,
+    // `int8_t` and `uint8_t` in storage buffers
+    pub storage_8bit: bool,
+    // Pipeline creation durations and cache hits, as reported by the driver
+    pub pipeline_creation_feedback: bool,
+    // Storage buffer and image writes from vertex and fragment shaders
+    pub vertex_pipeline_stores_and_atomics: bool,
+    pub fragment_stores_and_atomics: bool,
+}
+
+pub fn device_caps() -> &'static DeviceCaps {
+    &vk().caps
+}
+
+// ash doesn't know about VK_KHR_cooperative_matrix yet, so it's declared here.
+const KHR_COOPERATIVE_MATRIX_NAME: &[u8] = b"VK_KHR_cooperative_matrix\0";
+
+fn khr_cooperative_matrix_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(KHR_COOPERATIVE_MATRIX_NAME).unwrap()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PhysicalDeviceCooperativeMatrixFeaturesKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub cooperative_matrix: vk::Bool32,
+    pub cooperative_matrix_robust_buffer_access: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceCooperativeMatrixFeaturesKHR {
+    fn default() -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(1_000_506_000),
+            p_next: std::ptr::null_mut(),
+            cooperative_matrix: vk::FALSE,
+            cooperative_matrix_robust_buffer_access: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsPhysicalDeviceFeatures2 for PhysicalDeviceCooperativeMatrixFeaturesKHR {}
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceCooperativeMatrixFeaturesKHR {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CooperativeMatrixPropertiesKHR {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    m_size: u32,
+    n_size: u32,
+    k_size: u32,
+    a_type: vk::ComponentTypeNV,
+    b_type: vk::ComponentTypeNV,
+    c_type: vk::ComponentTypeNV,
+    result_type: vk::ComponentTypeNV,
+    saturating_accumulation: vk::Bool32,
+    scope: vk::ScopeNV,
+}
+
+impl Default for CooperativeMatrixPropertiesKHR {
+    fn default() -> Self {
+        Self {
+            s_type: vk::StructureType::from_raw(1_000_506_001),
+            p_next: std::ptr::null_mut(),
+            m_size: 0,
+            n_size: 0,
+            k_size: 0,
+            a_type: vk::ComponentTypeNV::default(),
+            b_type: vk::ComponentTypeNV::default(),
+            c_type: vk::ComponentTypeNV::default(),
+            result_type: vk::ComponentTypeNV::default(),
+            saturating_accumulation: vk::FALSE,
+            scope: vk::ScopeNV::default(),
+        }
+    }
+}
+
+type GetPhysicalDeviceCooperativeMatrixPropertiesKHR = unsafe extern "system" fn(
+    vk::PhysicalDevice,
+    *mut u32,
+    *mut CooperativeMatrixPropertiesKHR,
+) -> vk::Result;
+
+unsafe fn query_khr_cooperative_matrix_shapes(
+    entry: &Entry,
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+) -> Option<Vec<CooperativeMatrixShape>> {
+    let mut coop_features = PhysicalDeviceCooperativeMatrixFeaturesKHR::default();
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut coop_features)
+        .build();
+    instance
+        .fp_v1_1()
+        .get_physical_device_features2(pdevice, &mut features2);
+
+    if coop_features.cooperative_matrix == 0 {
+        return None;
+    }
+
+    let get_props: GetPhysicalDeviceCooperativeMatrixPropertiesKHR =
+        std::mem::transmute(entry.get_instance_proc_addr(
+            instance.handle(),
+            b"vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR\0".as_ptr() as *const i8,
+        )?);
+
+    let mut count = 0u32;
+    get_props(pdevice, &mut count, std::ptr::null_mut());
+    let mut props = vec![CooperativeMatrixPropertiesKHR::default(); count as usize];
+    get_props(pdevice, &mut count, props.as_mut_ptr());
+
+    Some(
+        props
+            .iter()
+            .take(count as usize)
+            .map(|p| CooperativeMatrixShape {
+                m: p.m_size,
+                n: p.n_size,
+                k: p.k_size,
+                a_type: p.a_type,
+                b_type: p.b_type,
+                c_type: p.c_type,
+                d_type: p.result_type,
+                scope: p.scope,
+            })
+            .collect(),
+    )
+}
+
+// The GLSL side of the KHR extension is newer than the NV one, so it's only used
+// if the bundled glslang knows about it.
+#[cfg(feature = "shaderc")]
+fn glsl_supports_khr_cooperative_matrix() -> bool {
+    let source = "#version 450\n#extension GL_KHR_cooperative_matrix : require\nvoid main() {}\n";
+    shaderc::Compiler::new().map_or(false, |mut compiler| {
+        compiler
+            .compile_into_spirv(
+                source,
+                shaderc::ShaderKind::Compute,
+                "cooperative_matrix_probe",
+                "main",
+                None,
+            )
+            .is_ok()
+    })
+}
+
+// Only precompiled SPIR-V can be loaded, and its flavor is up to whoever built it.
+#[cfg(not(feature = "shaderc"))]
+fn glsl_supports_khr_cooperative_matrix() -> bool {
+    true
+}
+
+lazy_static! {
+    static ref COOPERATIVE_MATRIX_USE: Regex =
+        Regex::new(r"\bRTOY_COOPERATIVE_MATRIX|\w*[cC]oop[mM]at\w*").unwrap();
+    static ref STORAGE_16BIT_USE: Regex =
+        Regex::new(r"\bRTOY_STORAGE_16BIT\b|\b(float16_t|u?int16_t|[fiu]16(vec|mat)\d)").unwrap();
+    static ref STORAGE_8BIT_USE: Regex =
+        Regex::new(r"\bRTOY_STORAGE_8BIT\b|\b(u?int8_t|[iu]8vec\d)").unwrap();
+}
+
+impl DeviceCaps {
+    pub(crate) unsafe fn query(
+        entry: &Entry,
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+    ) -> Self {
+        let supported_extensions: HashSet<CString> = instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap_or_default()
+            .iter()
+            .map(|ext| CStr::from_ptr(ext.extension_name.as_ptr()).to_owned())
+            .collect();
+        let is_supported = |name: &CStr| supported_extensions.contains(name);
+
+        let mut caps = DeviceCaps::default();
+
+        // Core features are all enabled on the device when supported
+        let features = instance.get_physical_device_features(pdevice);
+        caps.vertex_pipeline_stores_and_atomics = features.vertex_pipeline_stores_and_atomics != 0;
+        caps.fragment_stores_and_atomics = features.fragment_stores_and_atomics != 0;
+
+        if is_supported(vk::KhrStorageBufferStorageClassFn::name()) {
+            if is_supported(vk::Khr16bitStorageFn::name()) {
+                let mut storage_features = vk::PhysicalDevice16BitStorageFeatures::default();
+                let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+                    .push_next(&mut storage_features)
+                    .build();
+                instance
+                    .fp_v1_1()
+                    .get_physical_device_features2(pdevice, &mut features2);
+
+                caps.storage_16bit = storage_features.storage_buffer16_bit_access != 0;
+            }
+
+            if is_supported(vk::Khr8bitStorageFn::name()) {
+                let mut storage_features = vk::PhysicalDevice8BitStorageFeaturesKHR::default();
+                let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+                    .push_next(&mut storage_features)
+                    .build();
+                instance
+                    .fp_v1_1()
+                    .get_physical_device_features2(pdevice, &mut features2);
+
+                caps.storage_8bit = storage_features.storage_buffer8_bit_access != 0;
+            }
+        }
+
+        caps.pipeline_creation_feedback = is_supported(vk::ExtPipelineCreationFeedbackFn::name());
+
+        if is_supported(khr_cooperative_matrix_name()) && glsl_supports_khr_cooperative_matrix() {
+            if let Some(shapes) = query_khr_cooperative_matrix_shapes(entry, instance, pdevice) {
+                caps.cooperative_matrix = true;
+                caps.cooperative_matrix_khr = true;
+                caps.cooperative_matrix_shapes = shapes;
+            }
+        }
+
+        if !caps.cooperative_matrix && is_supported(vk::NvCooperativeMatrixFn::name()) {
+            let mut coop_features = vk::PhysicalDeviceCooperativeMatrixFeaturesNV::default();
+            let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+                .push_next(&mut coop_features)
+                .build();
+            instance
+                .fp_v1_1()
+                .get_physical_device_features2(pdevice, &mut features2);
+
+            if coop_features.cooperative_matrix != 0 {
+                let coop_fn = vk::NvCooperativeMatrixFn::load(|name| {
+                    std::mem::transmute(
+                        entry.get_instance_proc_addr(instance.handle(), name.as_ptr()),
+                    )
+                });
+
+                let mut count = 0u32;
+                coop_fn.get_physical_device_cooperative_matrix_properties_nv(
+                    pdevice,
+                    &mut count,
+                    std::ptr::null_mut(),
+                );
+                let mut props = vec![vk::CooperativeMatrixPropertiesNV::default(); count as usize];
+                coop_fn.get_physical_device_cooperative_matrix_properties_nv(
+                    pdevice,
+                    &mut count,
+                    props.as_mut_ptr(),
+                );
+
+                caps.cooperative_matrix = true;
+                caps.cooperative_matrix_shapes = props
+                    .iter()
+                    .take(count as usize)
+                    .map(|p| CooperativeMatrixShape {
+                        m: p.m_size,
+                        n: p.n_size,
+                        k: p.k_size,
+                        a_type: p.a_type,
+                        b_type: p.b_type,
+                        c_type: p.c_type,
+                        d_type: p.d_type,
+                        scope: p.scope,
+                    })
+                    .collect();
+            }
+        }
+
+        caps
+    }
+
+    pub(crate) fn device_extension_names(&self) -> Vec<*const i8> {
+        let mut names = Vec::new();
+        if self.cooperative_matrix_khr {
+            names.push(khr_cooperative_matrix_name().as_ptr());
+        } else if self.cooperative_matrix {
+            names.push(vk::NvCooperativeMatrixFn::name().as_ptr());
+        }
+        if self.storage_16bit || self.storage_8bit {
+            names.push(vk::KhrStorageBufferStorageClassFn::name().as_ptr());
+        }
+        if self.storage_16bit {
+            names.push(vk::Khr16bitStorageFn::name().as_ptr());
+        }
+        if self.storage_8bit {
+            names.push(vk::Khr8bitStorageFn::name().as_ptr());
+        }
+        if self.pipeline_creation_feedback {
+            names.push(vk::ExtPipelineCreationFeedbackFn::name().as_ptr());
+        }
+        names
+    }
+
+    // Extension enables and feature defines for the features used by a shader's sources.
+    // Shaders which check e.g. `RTOY_STORAGE_16BIT` count as using the feature.
+    pub(crate) fn glsl_preamble<'a>(&self, sources: impl Iterator<Item = &'a str>) -> String {
+        let (mut cooperative_matrix, mut storage_16bit, mut storage_8bit) = (false, false, false);
+        for source in sources {
+            cooperative_matrix |=
+                self.cooperative_matrix && COOPERATIVE_MATRIX_USE.is_match(source);
+            storage_16bit |= self.storage_16bit && STORAGE_16BIT_USE.is_match(source);
+            storage_8bit |= self.storage_8bit && STORAGE_8BIT_USE.is_match(source);
+        }
+
+        let mut preamble = String::new();
+        if cooperative_matrix {
+            if self.cooperative_matrix_khr {
+                preamble += "#extension GL_KHR_cooperative_matrix : enable\n";
+                preamble += "#define RTOY_COOPERATIVE_MATRIX_KHR 1\n";
+            } else {
+                preamble += "#extension GL_NV_cooperative_matrix : enable\n";
+                preamble += "#define RTOY_COOPERATIVE_MATRIX_NV 1\n";
+            }
+            preamble += "#extension GL_KHR_memory_scope_semantics : enable\n";
+            preamble += "#define RTOY_COOPERATIVE_MATRIX 1\n";
+        }
+        if storage_16bit {
+            preamble += "#extension GL_EXT_shader_16bit_storage : enable\n";
+            preamble += "#define RTOY_STORAGE_16BIT 1\n";
+        }
+        if storage_8bit {
+            preamble += "#extension GL_EXT_shader_8bit_storage : enable\n";
+            preamble += "#define RTOY_STORAGE_8BIT 1\n";
+        }
+        preamble
+    }
+}
+
+#[test]
+fn test_glsl_preamble() {
+    let caps = DeviceCaps {
+        cooperative_matrix: true,
+        storage_16bit: true,
+        storage_8bit: true,
+        ..Default::default()
+    };
+
+    assert_eq!(caps.glsl_preamble(["void main() {}"].iter().cloned()), "");
+
+    let preamble = caps.glsl_preamble(
+        [
+            "float16_t a;",
+            "fcoopmatNV<16, gl_ScopeSubgroup, 16, 16> m;",
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert!(preamble.contains("GL_NV_cooperative_matrix"));
+    assert!(preamble.contains("RTOY_STORAGE_16BIT"));
+    assert!(!preamble.contains("RTOY_STORAGE_8BIT"));
+
+    let caps = DeviceCaps {
+        cooperative_matrix_khr: true,
+        ..caps
+    };
+    let preamble = caps.glsl_preamble(["#ifdef RTOY_COOPERATIVE_MATRIX\n#endif"].iter().cloned());
+    assert!(preamble.contains("GL_KHR_cooperative_matrix"));
+    assert!(!preamble.contains("GL_NV_cooperative_matrix"));
+}
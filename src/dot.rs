@@ -31,6 +31,8 @@ use petgraph::visit::GraphRef;
 pub struct Dot<G> {
     graph: G,
     dot_graph_attribs: Option<&'static str>,
+    // Slash-separated cluster paths, and the indices of nodes directly in them
+    clusters: Vec<(String, Vec<usize>)>,
 }
 
 static TYPE: [&'static str; 2] = ["graph", "digraph"];
@@ -64,8 +66,17 @@ where
         Dot {
             graph,
             dot_graph_attribs,
+            clusters: Vec::new(),
         }
     }
+
+    /// Draw nodes inside nested, labeled boxes. Paths are separated by slashes,
+    /// e.g. `GI/Diffuse` goes inside `GI`.
+    pub fn with_clusters(mut self, mut clusters: Vec<(String, Vec<usize>)>) -> Self {
+        clusters.sort_by(|a, b| a.0.cmp(&b.0));
+        self.clusters = clusters;
+        self
+    }
 }
 
 use petgraph::visit::{Data, GraphProp, NodeRef};
@@ -108,6 +119,8 @@ impl<G> Dot<G> {
 
             writeln!(f, "]")?;
         }
+        self.clusters_fmt(f)?;
+
         // output all edges
         for edge in g.edge_references() {
             write!(
@@ -128,6 +141,48 @@ impl<G> Dot<G> {
     }
 }
 
+impl<G> Dot<G> {
+    fn clusters_fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut open: Vec<&str> = Vec::new();
+
+        for (path, nodes) in self.clusters.iter() {
+            let components: Vec<&str> = path.split('/').collect();
+            let common = open
+                .iter()
+                .zip(components.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            while open.len() > common {
+                open.pop();
+                writeln!(f, "{}}}", INDENT.repeat(open.len() + 1))?;
+            }
+
+            for component in &components[common..] {
+                let indent = INDENT.repeat(open.len() + 1);
+                open.push(*component);
+                write!(f, "{}subgraph \"cluster_", indent)?;
+                Escaped(open.join("/")).fmt(f)?;
+                write!(f, "\" {{\n{}{}label=\"", indent, INDENT)?;
+                Escaped(component).fmt(f)?;
+                writeln!(f, "\"; color=\"#808080\"; fontcolor=\"#f0f0f0\";")?;
+            }
+
+            let indent = INDENT.repeat(open.len() + 1);
+            for node in nodes {
+                writeln!(f, "{}{};", indent, node)?;
+            }
+        }
+
+        while !open.is_empty() {
+            open.pop();
+            writeln!(f, "{}}}", INDENT.repeat(open.len() + 1))?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<G> fmt::Display for Dot<G>
 where
     G: IntoEdgeReferences + IntoNodeReferences + NodeIndexable + GraphProp,
@@ -0,0 +1,86 @@
+// Dry-run mode: the graph gets evaluated as usual, resolving uniforms and creating
+// resources, but passes are only validated instead of being recorded. Useful in tests,
+// and for checking graphs assembled at runtime from data before running them.
+
+use snoozy::*;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub struct DryRunIssue {
+    pub pass: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DryRunReport {
+    // Names of the passes which would have been recorded, in evaluation order
+    pub passes: Vec<String>,
+    pub issues: Vec<DryRunIssue>,
+}
+
+impl DryRunReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct DryRunState {
+    enabled: bool,
+    report: DryRunReport,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref DRY_RUN: Mutex<DryRunState> = Mutex::new(Default::default());
+}
+
+// Passes evaluated while enabled are cached like any others, so toggling the mode
+// re-evaluates everything which was affected by it.
+pub fn set_dry_run_enabled(enabled: bool) {
+    let triggers = {
+        let mut state = DRY_RUN.lock().unwrap();
+        if state.enabled == enabled {
+            return;
+        }
+        state.enabled = enabled;
+        std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Returns everything collected since the last call.
+pub fn take_dry_run_report() -> DryRunReport {
+    std::mem::replace(&mut DRY_RUN.lock().unwrap().report, Default::default())
+}
+
+// The mode as an op, which only gets re-evaluated when it changes. Ops depending on it
+// get invalidated along with it, without each registering a trigger of its own.
+#[snoozy]
+pub async fn dry_run_enabled_snoozy(ctx: Context) -> Result<bool> {
+    let mut state = DRY_RUN.lock().unwrap();
+    state
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+    Ok(state.enabled)
+}
+
+// The calling op gets invalidated when the mode changes.
+pub(crate) async fn is_dry_run_enabled(ctx: &Context) -> Result<bool> {
+    Ok(*ctx.clone().get(dry_run_enabled()).await?)
+}
+
+pub(crate) fn report_dry_run_pass(pass: &str, issues: Vec<String>) {
+    let mut state = DRY_RUN.lock().unwrap();
+    state.report.passes.push(pass.to_owned());
+    state
+        .report
+        .issues
+        .extend(issues.into_iter().map(|message| DryRunIssue {
+            pass: pass.to_owned(),
+            message,
+        }));
+}
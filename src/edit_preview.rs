@@ -0,0 +1,162 @@
+// Edit-time preview: while shaders are being edited and recompiled, or tweaks dragged
+// around, passes created through `preview_compute_tex` render at reduced resolution,
+// and get upscaled back to their full size. Full resolution is restored once edits
+// have settled, so the UI stays fluid during heavy iteration on expensive passes.
+
+use crate::backend::texture::TextureType;
+use crate::shader::{compute_tex, load_cs_from_string, ComputeShader, ShaderUniformHolder};
+use crate::shader_compile_queue;
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use snoozy::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct EditPreviewState {
+    enabled: bool,
+    res_div: u32,
+    settle_time: Duration,
+    last_edit: Option<Instant>,
+    active: bool,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref EDIT_PREVIEW: Mutex<EditPreviewState> = Mutex::new(EditPreviewState {
+        enabled: false,
+        res_div: 2,
+        settle_time: Duration::from_millis(500),
+        last_edit: None,
+        active: false,
+        invalidation_triggers: Vec::new(),
+    });
+}
+
+fn set_active(state: &mut EditPreviewState, active: bool) -> Vec<Box<dyn Fn() + Send + Sync>> {
+    if state.active == active {
+        return Vec::new();
+    }
+
+    state.active = active;
+    std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+}
+
+// Disabling restores full resolution right away.
+pub fn set_edit_preview_enabled(enabled: bool) {
+    let triggers = {
+        let mut state = EDIT_PREVIEW.lock().unwrap();
+        state.enabled = enabled;
+        if enabled {
+            Vec::new()
+        } else {
+            set_active(&mut state, false)
+        }
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Divides the resolution of preview passes; 2 by default.
+pub fn set_edit_preview_res_div(res_div: u32) {
+    let triggers = {
+        let mut state = EDIT_PREVIEW.lock().unwrap();
+        state.res_div = res_div.max(1);
+        if state.active {
+            std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+        } else {
+            Vec::new()
+        }
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// How long after the last edit full resolution is restored.
+pub fn set_edit_preview_settle_ms(ms: u32) {
+    EDIT_PREVIEW.lock().unwrap().settle_time = Duration::from_millis(ms as u64);
+}
+
+pub fn is_edit_preview_active() -> bool {
+    EDIT_PREVIEW.lock().unwrap().active
+}
+
+// Asset changes, tweaks and such.
+pub(crate) fn notify_edit() {
+    EDIT_PREVIEW.lock().unwrap().last_edit = Some(Instant::now());
+}
+
+pub(crate) fn end_frame() {
+    let compile_progress = shader_compile_queue::shader_compile_progress();
+    let compiling = compile_progress.pending + compile_progress.compiling > 0;
+
+    let triggers = {
+        let mut state = EDIT_PREVIEW.lock().unwrap();
+        let editing = state
+            .last_edit
+            .map_or(false, |t| t.elapsed() < state.settle_time);
+
+        let active = state.enabled && (editing || compiling);
+        set_active(&mut state, active)
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Like `compute_tex`, but rendering at reduced resolution while the preview is active.
+// Only 2D textures are downscaled, and the upscale is bilinear, so it's meant for passes
+// with filterable outputs. Shaders should use `outputTex_size` rather than assuming
+// the size of their output.
+#[snoozy]
+pub async fn preview_compute_tex_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let res_div = {
+        let mut state = EDIT_PREVIEW.lock().unwrap();
+        state
+            .invalidation_triggers
+            .push(Box::new(ctx.get_invalidation_trigger()));
+
+        if state.active {
+            state.res_div
+        } else {
+            1
+        }
+    };
+
+    if res_div == 1 || key.tex_type != TextureType::Type2D {
+        let tex = ctx
+            .get(compute_tex(*key, cs.clone(), uniforms.clone()))
+            .await?;
+        return Ok((*tex).clone());
+    }
+
+    let low_res = compute_tex(
+        key.res_div_round_up(res_div, res_div),
+        cs.clone(),
+        uniforms.clone(),
+    );
+
+    let upscale_cs = load_cs_from_string(
+        include_str!("../assets/shaders/preview_upscale.glsl").to_owned(),
+        "preview_upscale.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            *key,
+            upscale_cs,
+            shader_uniforms!(inputTex: low_res),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
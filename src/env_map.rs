@@ -0,0 +1,154 @@
+// Image based lighting: an HDR environment map prefiltered into diffuse irradiance,
+// a chain of specular cube maps of increasing GGX roughness, and a split-sum BRDF table:
+//
+//   let env = prefilter_env_map(load_tex(asset!("sky.hdr")), 256);
+//   let lit = compute_tex(key, load_cs(asset!("shaders/shading.glsl")), shader_uniforms!(
+//       :env.uniforms(),
+//   ));
+//
+// Shaders include `shaders/env_lighting.inc`. Each specular level is a separate cube map,
+// half the size of the previous one, since textures don't have mip chains.
+
+use crate::cube_map::{cube_from_equirect_tex, cube_resize_tex};
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformBundle, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// Must match `env_lighting.inc`
+pub const ENV_SPECULAR_LEVELS: u32 = 6;
+
+const ENV_IRRADIANCE_SIZE: u32 = 32;
+const ENV_SPECULAR_SAMPLE_COUNT: u32 = 256;
+const ENV_BRDF_LUT_SAMPLE_COUNT: u32 = 1024;
+
+// Diffuse irradiance of `cube`, divided by pi, in 32x32 faces. Integrates over every texel
+// of the source, so pass a small cube, e.g. from `cube_resize_tex`.
+#[snoozy]
+pub async fn env_irradiance_tex_snoozy(
+    mut ctx: Context,
+    cube: &SnoozyRef<Texture>,
+) -> Result<Texture> {
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/env_irradiance.glsl").to_owned(),
+        "env_irradiance.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new_cube(ENV_IRRADIANCE_SIZE, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(inputTex: cube.clone()),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Specular level `level` of `ENV_SPECULAR_LEVELS`, at the resolution of `cube`.
+// Roughness goes linearly from 0 at the first level, to 1 at the last one.
+#[snoozy]
+pub async fn env_specular_tex_snoozy(
+    mut ctx: Context,
+    cube: &SnoozyRef<Texture>,
+    level: &u32,
+) -> Result<Texture> {
+    if *level >= ENV_SPECULAR_LEVELS {
+        bail!(
+            "Specular level {} out of range; there are {}",
+            level,
+            ENV_SPECULAR_LEVELS
+        );
+    }
+
+    let size = ctx.get(cube).await?.key.width;
+    let roughness = *level as f32 / (ENV_SPECULAR_LEVELS - 1) as f32;
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/env_specular.glsl").to_owned(),
+        "env_specular.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new_cube(size, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(
+                inputTex: cube.clone(),
+                roughness: roughness,
+                sample_count: ENV_SPECULAR_SAMPLE_COUNT,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+// Scale and bias to F0 of the split-sum approximation, in `R16G16_SFLOAT`. The cosine
+// between the normal and view direction goes along X, and roughness along Y.
+#[snoozy]
+pub async fn env_brdf_lut_tex_snoozy(mut ctx: Context, size: &u32) -> Result<Texture> {
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/env_brdf_lut.glsl").to_owned(),
+        "env_brdf_lut.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new(*size, *size, vk::Format::R16G16_SFLOAT),
+            cs,
+            shader_uniforms!(sample_count: ENV_BRDF_LUT_SAMPLE_COUNT),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
+
+#[derive(Clone)]
+pub struct EnvMaps {
+    pub irradiance: SnoozyRef<Texture>,
+    // `ENV_SPECULAR_LEVELS` cube maps, from smooth to rough
+    pub specular: Vec<SnoozyRef<Texture>>,
+    pub brdf_lut: SnoozyRef<Texture>,
+}
+
+impl EnvMaps {
+    // Named as expected by `env_lighting.inc`.
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        shader_uniforms!(env: shader_uniforms!(
+            irradiance: self.irradiance.clone(),
+            specular0: self.specular[0].clone(),
+            specular1: self.specular[1].clone(),
+            specular2: self.specular[2].clone(),
+            specular3: self.specular[3].clone(),
+            specular4: self.specular[4].clone(),
+            specular5: self.specular[5].clone(),
+            brdf_lut: self.brdf_lut.clone(),
+        ))
+    }
+}
+
+// Prefilters an equirectangular environment map. The first specular level has
+// `specular_size` texel faces; the last ones don't go below 4 texels.
+pub fn prefilter_env_map(equirect: SnoozyRef<Texture>, specular_size: u32) -> EnvMaps {
+    let cube = cube_from_equirect_tex(equirect, specular_size);
+
+    // Each level is filtered from a cube of its own size, to avoid aliasing
+    let specular = (0..ENV_SPECULAR_LEVELS)
+        .map(|level| {
+            if level == 0 {
+                return cube.clone();
+            }
+
+            let size = (specular_size >> level).max(4);
+            env_specular_tex(cube_resize_tex(cube.clone(), size), level)
+        })
+        .collect();
+
+    EnvMaps {
+        irradiance: env_irradiance_tex(cube_resize_tex(cube, ENV_IRRADIANCE_SIZE)),
+        specular,
+        brdf_lut: env_brdf_lut_tex(128),
+    }
+}
@@ -0,0 +1,97 @@
+// Injection points for recording custom Vulkan commands into each frame, so that existing
+// Vulkan code, e.g. UI renderers or video encoders, can run alongside the graph.
+//
+// Callbacks record into the frame's command buffer, in the order they were added.
+// Resources they touch must be transitioned with `image_barrier`, the same way passes
+// do. Callbacks must not add or remove callbacks themselves. Only frames of the windowed
+// render loop run them.
+
+use crate::texture::Texture;
+use crate::vk_backend_state::{record_image_barrier, ImageBarrier};
+use ash::{vk, Device};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExternalCommandsPoint {
+    // Before any pass of the frame's graph
+    BeforeGraph,
+    // Once the graph's output is ready to be sampled
+    AfterGraph,
+    // After the output was composited into the swapchain image, which is then in
+    // the `ComputeShaderWrite` state
+    BeforePresent,
+}
+
+pub struct ExternalCommandsContext<'a> {
+    pub device: &'a Device,
+    pub cb: vk::CommandBuffer,
+    // The graph's output, from `AfterGraph` on. It's in the
+    // `AnyShaderReadSampledImageOrUniformTexelBuffer` state, and must be left that way.
+    pub final_texture: Option<&'a Texture>,
+    // Only at `BeforePresent`
+    pub swapchain_image: Option<vk::Image>,
+}
+
+impl<'a> ExternalCommandsContext<'a> {
+    pub fn image_barrier(
+        &self,
+        image: vk::Image,
+        prev_access: vk_sync::AccessType,
+        next_access: vk_sync::AccessType,
+        discard: bool,
+    ) {
+        record_image_barrier(
+            self.device,
+            self.cb,
+            ImageBarrier::new(image, prev_access, next_access).with_discard(discard),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExternalCommandsHandle(u64);
+
+type ExternalCommandsFn = Box<dyn FnMut(&ExternalCommandsContext) + Send>;
+
+#[derive(Default)]
+struct ExternalCommandsState {
+    next_handle: u64,
+    callbacks: Vec<(
+        ExternalCommandsHandle,
+        ExternalCommandsPoint,
+        ExternalCommandsFn,
+    )>,
+}
+
+lazy_static! {
+    static ref EXTERNAL_COMMANDS: Mutex<ExternalCommandsState> = Mutex::new(Default::default());
+}
+
+pub fn add_external_commands(
+    point: ExternalCommandsPoint,
+    callback: impl FnMut(&ExternalCommandsContext) + Send + 'static,
+) -> ExternalCommandsHandle {
+    let mut state = EXTERNAL_COMMANDS.lock().unwrap();
+    let handle = ExternalCommandsHandle(state.next_handle);
+    state.next_handle += 1;
+    state.callbacks.push((handle, point, Box::new(callback)));
+    handle
+}
+
+pub fn remove_external_commands(handle: ExternalCommandsHandle) {
+    EXTERNAL_COMMANDS
+        .lock()
+        .unwrap()
+        .callbacks
+        .retain(|(h, _, _)| *h != handle);
+}
+
+pub(crate) fn record_external_commands(
+    point: ExternalCommandsPoint,
+    ctx: &ExternalCommandsContext,
+) {
+    let mut state = EXTERNAL_COMMANDS.lock().unwrap();
+    for (_, _, callback) in state.callbacks.iter_mut().filter(|(_, p, _)| *p == point) {
+        callback(ctx);
+    }
+}
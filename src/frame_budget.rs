@@ -0,0 +1,184 @@
+// Frame time budgeting: when frames take longer than the target, passes marked as
+// less important get degraded, either skipped in favor of a cheaper fallback, or run
+// at half resolution. Degradation is stepped one priority at a time, and undone once
+// frames comfortably fit the budget again.
+
+use crate::shader::{compute_tex, ComputeShader, ShaderUniformHolder};
+use crate::texture::{Texture, TextureKey};
+use snoozy::*;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum PassPriority {
+    // Degraded first
+    Low,
+    Medium,
+    // Never degraded
+    High,
+}
+
+// Consecutive frames over the budget before more passes get degraded. Avoids reacting
+// to one-off hitches, such as the frame re-evaluating the graph after a level change.
+const OVERLOAD_FRAME_COUNT: u32 = 3;
+// Frames need to be this far under the budget before degradation is reduced
+const RECOVERY_HEADROOM: f32 = 0.8;
+// ... for this many frames in a row
+const RECOVERY_FRAME_COUNT: u32 = 30;
+
+struct FrameBudgetState {
+    budget_ms: Option<f32>,
+    last_frame_end: Option<Instant>,
+    // 0: nothing degraded; 1: `Low` passes degraded; 2: `Low` and `Medium`
+    level: u32,
+    frames_over_budget: u32,
+    frames_under_budget: u32,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref FRAME_BUDGET: Mutex<FrameBudgetState> = Mutex::new(FrameBudgetState {
+        budget_ms: None,
+        last_frame_end: None,
+        level: 0,
+        frames_over_budget: 0,
+        frames_under_budget: 0,
+        invalidation_triggers: Vec::new(),
+    });
+}
+
+fn set_level(state: &mut FrameBudgetState, level: u32) -> Vec<Box<dyn Fn() + Send + Sync>> {
+    state.frames_over_budget = 0;
+    state.frames_under_budget = 0;
+    if state.level == level {
+        return Vec::new();
+    }
+
+    tracing::info!("Frame budget degradation level: {}", level);
+    state.level = level;
+    std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+}
+
+// Target frame time, e.g. `Some(16.6)` for 60 Hz. `None` disables degradation.
+pub fn set_frame_time_budget(budget_ms: Option<f32>) {
+    let triggers = {
+        let mut state = FRAME_BUDGET.lock().unwrap();
+        state.budget_ms = budget_ms;
+        set_level(&mut state, 0)
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Whether passes of the given priority are currently being degraded.
+pub fn is_pass_degraded(priority: PassPriority) -> bool {
+    is_degraded(&FRAME_BUDGET.lock().unwrap(), priority)
+}
+
+fn is_degraded(state: &FrameBudgetState, priority: PassPriority) -> bool {
+    match priority {
+        PassPriority::Low => state.level >= 1,
+        PassPriority::Medium => state.level >= 2,
+        PassPriority::High => false,
+    }
+}
+
+// Like `is_pass_degraded`, as an op which gets invalidated when the result may change.
+// Ops using it get invalidated along with it, without registering triggers of their own.
+#[snoozy]
+pub async fn pass_degraded_snoozy(ctx: Context, priority: &PassPriority) -> Result<bool> {
+    let mut state = FRAME_BUDGET.lock().unwrap();
+    if *priority != PassPriority::High {
+        state
+            .invalidation_triggers
+            .push(Box::new(ctx.get_invalidation_trigger()));
+    }
+    Ok(is_degraded(&state, *priority))
+}
+
+pub(crate) fn end_frame() {
+    let triggers = {
+        let mut state = FRAME_BUDGET.lock().unwrap();
+        let now = Instant::now();
+        let frame_ms = state
+            .last_frame_end
+            .replace(now)
+            .map(|prev| (now - prev).as_secs_f32() * 1000.0);
+
+        match (state.budget_ms, frame_ms) {
+            (Some(budget_ms), Some(frame_ms)) => {
+                if frame_ms > budget_ms && state.level < 2 {
+                    state.frames_under_budget = 0;
+                    state.frames_over_budget += 1;
+                    if state.frames_over_budget >= OVERLOAD_FRAME_COUNT {
+                        let level = state.level + 1;
+                        set_level(&mut state, level)
+                    } else {
+                        Vec::new()
+                    }
+                } else if frame_ms < budget_ms * RECOVERY_HEADROOM && state.level > 0 {
+                    state.frames_over_budget = 0;
+                    state.frames_under_budget += 1;
+                    if state.frames_under_budget >= RECOVERY_FRAME_COUNT {
+                        let level = state.level - 1;
+                        set_level(&mut state, level)
+                    } else {
+                        Vec::new()
+                    }
+                } else {
+                    state.frames_over_budget = 0;
+                    state.frames_under_budget = 0;
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Evaluates to `fallback` instead of `tex` while `priority` passes are degraded,
+// so that `tex` doesn't get evaluated at all.
+#[snoozy]
+pub async fn budget_select_tex_snoozy(
+    mut ctx: Context,
+    tex: &SnoozyRef<Texture>,
+    fallback: &SnoozyRef<Texture>,
+    priority: &PassPriority,
+) -> Result<Texture> {
+    let selected = if *ctx.get(pass_degraded(*priority)).await? {
+        fallback
+    } else {
+        tex
+    };
+
+    Ok((*ctx.get(selected).await?).clone())
+}
+
+// Like `compute_tex`, but rendering at half resolution while `priority` passes are degraded.
+// Shaders should use `outputTex_size` rather than assuming the size of their output.
+#[snoozy]
+pub async fn budget_compute_tex_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    priority: &PassPriority,
+) -> Result<Texture> {
+    let key = if *ctx.get(pass_degraded(*priority)).await? {
+        key.res_div_round_up(2, 2)
+    } else {
+        *key
+    };
+
+    let tex = ctx
+        .get(compute_tex(key, cs.clone(), uniforms.clone()))
+        .await?;
+
+    Ok((*tex).clone())
+}
@@ -0,0 +1,67 @@
+// Single-step execution, for examining how feedback systems evolve. With stepping enabled,
+// the render loop halts: the frame callback only runs once per `step_frame`, or per press
+// of F10 (F9 toggles stepping). Halted frames keep showing the graph of the last frame,
+// so the GPU debugger and `frame.dot` dumps can be used on it.
+//
+// Stepped frames advance by a fixed delta time rather than the wall clock, so that
+// every run steps through the same states.
+
+use std::sync::Mutex;
+
+struct FrameStepState {
+    enabled: bool,
+    pending_steps: u32,
+    step_dt: f32,
+}
+
+lazy_static! {
+    static ref FRAME_STEP: Mutex<FrameStepState> = Mutex::new(FrameStepState {
+        enabled: false,
+        pending_steps: 0,
+        step_dt: 1.0 / 60.0,
+    });
+}
+
+pub(crate) enum FrameStep {
+    Run,
+    // Run with the given delta time
+    Step(f32),
+    Halt,
+}
+
+// Disabling resumes running right away; steps still pending are dropped.
+pub fn set_frame_stepping_enabled(enabled: bool) {
+    let mut state = FRAME_STEP.lock().unwrap();
+    state.enabled = enabled;
+    state.pending_steps = 0;
+}
+
+pub fn is_frame_stepping_enabled() -> bool {
+    FRAME_STEP.lock().unwrap().enabled
+}
+
+// Runs one more frame while stepping; does nothing otherwise.
+pub fn step_frame() {
+    let mut state = FRAME_STEP.lock().unwrap();
+    if state.enabled {
+        state.pending_steps += 1;
+    }
+}
+
+// Delta time of stepped frames; 1/60 s by default.
+pub fn set_frame_step_dt(dt: f32) {
+    FRAME_STEP.lock().unwrap().step_dt = dt;
+}
+
+// Called at the start of every frame of the render loop.
+pub(crate) fn next_frame_step() -> FrameStep {
+    let mut state = FRAME_STEP.lock().unwrap();
+    if !state.enabled {
+        FrameStep::Run
+    } else if state.pending_steps > 0 {
+        state.pending_steps -= 1;
+        FrameStep::Step(state.step_dt)
+    } else {
+        FrameStep::Halt
+    }
+}
@@ -0,0 +1,232 @@
+// Per-frame telemetry for unattended benchmarking runs: CPU frame times, GPU pass timings,
+// memory usage and op metrics are recorded for a given duration, then written to a file. Paths
+// ending in `.json` get JSON; anything else gets CSV, with one column per GPU pass and
+// one per metric. Metrics are only present in frames which reported them.
+//
+// GPU timings are those read back during the frame, so they lag a few frames behind.
+
+use crate::gpu_profiler;
+use crate::op_metrics;
+use crate::resource_lifetime::json_string;
+use crate::vulkan::vk;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct FrameSample {
+    cpu_ms: f64,
+    // Summed up per pass name
+    passes: BTreeMap<String, f64>,
+    metrics: BTreeMap<String, f64>,
+    memory_used_bytes: u64,
+    memory_allocation_count: u32,
+}
+
+impl FrameSample {
+    fn gpu_ms(&self) -> f64 {
+        self.passes.values().sum()
+    }
+}
+
+struct TelemetryCapture {
+    path: String,
+    duration: Option<Duration>,
+    start: Option<Instant>,
+    last_frame_end: Option<Instant>,
+    frames: Vec<FrameSample>,
+}
+
+#[derive(Default)]
+struct FrameTelemetryState {
+    capture: Option<TelemetryCapture>,
+    finished: bool,
+}
+
+lazy_static! {
+    static ref FRAME_TELEMETRY: Mutex<FrameTelemetryState> = Mutex::new(Default::default());
+}
+
+// Starts recording, discarding any capture in progress. With a `duration`, the file is
+// written once it elapses; otherwise on `finish_frame_telemetry`.
+pub fn start_frame_telemetry(path: &str, duration: Option<Duration>) {
+    let mut state = FRAME_TELEMETRY.lock().unwrap();
+    state.finished = false;
+    state.capture = Some(TelemetryCapture {
+        path: path.to_owned(),
+        duration,
+        start: None,
+        last_frame_end: None,
+        frames: Vec::new(),
+    });
+}
+
+// Writes out and stops the capture in progress, if any.
+pub fn finish_frame_telemetry() -> std::io::Result<()> {
+    let capture = {
+        let mut state = FRAME_TELEMETRY.lock().unwrap();
+        state.finished = true;
+        state.capture.take()
+    };
+
+    match capture {
+        Some(capture) => write_capture(&capture),
+        None => Ok(()),
+    }
+}
+
+// Whether a capture with a duration has been written out, e.g. to quit a benchmarking run.
+pub fn is_frame_telemetry_finished() -> bool {
+    FRAME_TELEMETRY.lock().unwrap().finished
+}
+
+pub(crate) fn end_frame() {
+    let finished_capture = {
+        let mut state = FRAME_TELEMETRY.lock().unwrap();
+        let capture = match state.capture.as_mut() {
+            Some(capture) => capture,
+            None => return,
+        };
+
+        let now = Instant::now();
+        if let Some(prev) = capture.last_frame_end.replace(now) {
+            let mut passes = BTreeMap::new();
+            for (name, ms) in gpu_profiler::last_frame_durations() {
+                *passes.entry(name).or_default() += ms;
+            }
+
+            let (memory_used_bytes, memory_allocation_count) =
+                match vk().allocator.calculate_stats() {
+                    Ok(stats) => (stats.total.usedBytes, stats.total.allocationCount),
+                    Err(_) => (0, 0),
+                };
+
+            capture.frames.push(FrameSample {
+                cpu_ms: (now - prev).as_secs_f64() * 1000.0,
+                passes,
+                metrics: op_metrics::last_frame_op_metrics().into_iter().collect(),
+                memory_used_bytes,
+                memory_allocation_count,
+            });
+        }
+
+        let start = *capture.start.get_or_insert(now);
+        match capture.duration {
+            Some(duration) if now - start >= duration => {
+                state.finished = true;
+                state.capture.take()
+            }
+            _ => None,
+        }
+    };
+
+    if let Some(capture) = finished_capture {
+        if let Err(err) = write_capture(&capture) {
+            tracing::error!(
+                "Failed to write frame telemetry to {}: {}",
+                capture.path,
+                err
+            );
+        }
+    }
+}
+
+fn write_capture(capture: &TelemetryCapture) -> std::io::Result<()> {
+    let text = if capture.path.ends_with(".json") {
+        to_json(&capture.frames)
+    } else {
+        to_csv(&capture.frames)
+    };
+
+    tracing::info!(
+        "Writing telemetry of {} frames to {}",
+        capture.frames.len(),
+        capture.path
+    );
+    std::fs::write(&capture.path, text)
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(|c: char| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn to_csv(frames: &[FrameSample]) -> String {
+    let pass_names: BTreeSet<&String> = frames.iter().flat_map(|f| f.passes.keys()).collect();
+    let metric_names: BTreeSet<&String> = frames.iter().flat_map(|f| f.metrics.keys()).collect();
+
+    let mut res = "frame,cpu_ms,gpu_ms,memory_used_bytes,memory_allocation_count".to_owned();
+    for name in pass_names.iter() {
+        res += ",";
+        res += &csv_field(name);
+    }
+    for name in metric_names.iter() {
+        res += ",";
+        res += &csv_field(&format!("metric:{}", name));
+    }
+    res += "\n";
+
+    for (i, frame) in frames.iter().enumerate() {
+        res += &format!(
+            "{},{:.4},{:.4},{},{}",
+            i,
+            frame.cpu_ms,
+            frame.gpu_ms(),
+            frame.memory_used_bytes,
+            frame.memory_allocation_count
+        );
+        for name in pass_names.iter() {
+            res += ",";
+            if let Some(ms) = frame.passes.get(*name) {
+                res += &format!("{:.4}", ms);
+            }
+        }
+        for name in metric_names.iter() {
+            res += ",";
+            if let Some(value) = frame.metrics.get(*name) {
+                res += &value.to_string();
+            }
+        }
+        res += "\n";
+    }
+
+    res
+}
+
+fn to_json(frames: &[FrameSample]) -> String {
+    let frames: Vec<String> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let passes: Vec<String> = frame
+                .passes
+                .iter()
+                .map(|(name, ms)| format!("{}:{:.4}", json_string(name), ms))
+                .collect();
+            let metrics: Vec<String> = frame
+                .metrics
+                .iter()
+                .map(|(name, value)| format!("{}:{}", json_string(name), value))
+                .collect();
+
+            format!(
+                concat!(
+                    r#"{{"frame":{},"cpu_ms":{:.4},"gpu_ms":{:.4},"#,
+                    r#""memory_used_bytes":{},"memory_allocation_count":{},"passes":{{{}}},"#,
+                    r#""metrics":{{{}}}}}"#
+                ),
+                i,
+                frame.cpu_ms,
+                frame.gpu_ms(),
+                frame.memory_used_bytes,
+                frame.memory_allocation_count,
+                passes.join(","),
+                metrics.join(",")
+            )
+        })
+        .collect();
+
+    format!("{{\"frames\":[\n{}\n]}}\n", frames.join(",\n"))
+}
@@ -0,0 +1,371 @@
+// Complete example graphs, each built with a single call. They double as documentation
+// of how ops are put together, and as a corpus for exercising the engine itself.
+//
+// All of them are compute-only, and deterministic for a given time, so they run under
+// `HeadlessCompute` as well. Simulations take a fixed number of steps to reach the requested
+// time, so the size of the graph doesn't depend on it.
+
+use crate::buffer::BufferKey;
+use crate::op_tags::op_tags;
+use crate::shader::{compute_buf, compute_tex, load_cs_from_string, recompute_tex};
+use crate::shader::{ComputeShader, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+const FLUID_STEP_COUNT: u32 = 8;
+const FLUID_PRESSURE_ITERATIONS: u32 = 16;
+const PARTICLE_STEP_COUNT: u32 = 16;
+const PARTICLE_COUNT: u32 = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GalleryExample {
+    // Signed distance field scene with soft shadows, in linear HDR
+    Raymarcher,
+    // Stable fluids: advection, then a Jacobi pressure solve and projection per step
+    FluidSim,
+    // Particles simulated in a buffer, and splatted with atomics
+    Particles,
+    // Bloom, chromatic aberration, tone mapping and a vignette on top of the raymarcher
+    PostChain,
+}
+
+impl GalleryExample {
+    pub const ALL: [GalleryExample; 4] = [
+        GalleryExample::Raymarcher,
+        GalleryExample::FluidSim,
+        GalleryExample::Particles,
+        GalleryExample::PostChain,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GalleryExample::Raymarcher => "raymarcher",
+            GalleryExample::FluidSim => "fluid_sim",
+            GalleryExample::Particles => "particles",
+            GalleryExample::PostChain => "post_chain",
+        }
+    }
+}
+
+macro_rules! gallery_cs {
+    ($file:expr) => {
+        load_cs_from_string(
+            include_str!(concat!("../assets/shaders/", $file)).to_owned(),
+            $file.to_owned(),
+        )
+    };
+}
+
+// `example` as of `time` seconds in, at the resolution of `key`. The format of `key`
+// is ignored; all examples output `R16G16B16A16_SFLOAT`.
+pub fn gallery_example_tex(
+    example: GalleryExample,
+    key: TextureKey,
+    time: f32,
+) -> SnoozyRef<Texture> {
+    let key = key.with_format(vk::Format::R16G16B16A16_SFLOAT);
+    match example {
+        GalleryExample::Raymarcher => raymarcher_tex(key, time),
+        GalleryExample::FluidSim => fluid_sim_tex(key, time),
+        GalleryExample::Particles => particles_tex(key, time),
+        GalleryExample::PostChain => post_chain_tex(key, time),
+    }
+}
+
+fn raymarcher_tex(key: TextureKey, time: f32) -> SnoozyRef<Texture> {
+    compute_tex(
+        key,
+        gallery_cs!("gallery_raymarcher.glsl"),
+        shader_uniforms!(time: time),
+    )
+}
+
+fn fluid_sim_tex(key: TextureKey, time: f32) -> SnoozyRef<Texture> {
+    let dt = time / FLUID_STEP_COUNT as f32;
+    let pressure_key = key.with_format(vk::Format::R16G16_SFLOAT);
+
+    let advect_cs = gallery_cs!("gallery_fluid_advect.glsl");
+    let divergence_cs = gallery_cs!("gallery_fluid_divergence.glsl");
+    let pressure_cs = gallery_cs!("gallery_fluid_pressure.glsl");
+    let project_cs = gallery_cs!("gallery_fluid_project.glsl");
+
+    let mut state = compute_tex(
+        key,
+        gallery_cs!("gallery_fluid_init.glsl"),
+        shader_uniforms!(),
+    );
+
+    for _ in 0..FLUID_STEP_COUNT {
+        let advected = compute_tex(
+            key,
+            advect_cs.clone(),
+            shader_uniforms!(stateTex: state, dt: dt),
+        );
+
+        let mut pressure = compute_tex(
+            pressure_key,
+            divergence_cs.clone(),
+            shader_uniforms!(stateTex: advected.clone()),
+        );
+        for _ in 0..FLUID_PRESSURE_ITERATIONS {
+            pressure = compute_tex(
+                pressure_key,
+                pressure_cs.clone(),
+                shader_uniforms!(pressureTex: pressure),
+            );
+        }
+
+        state = compute_tex(
+            key,
+            project_cs.clone(),
+            shader_uniforms!(stateTex: advected, pressureTex: pressure),
+        );
+    }
+
+    compute_tex(
+        key,
+        gallery_cs!("gallery_fluid_display.glsl"),
+        shader_uniforms!(stateTex: state),
+    )
+}
+
+fn particles_tex(key: TextureKey, time: f32) -> SnoozyRef<Texture> {
+    let dt = time / PARTICLE_STEP_COUNT as f32;
+    // Splatting uses a thread per particle, dispatched over the pixels of the output
+    let particle_count = PARTICLE_COUNT.min(key.width * key.height);
+    let buf_key = BufferKey::new(particle_count as usize * 16, None);
+    let thread_count = [particle_count, 1, 1];
+
+    let step_cs = gallery_cs!("gallery_particles_step.glsl");
+
+    let mut particles = compute_buf(
+        buf_key,
+        thread_count,
+        gallery_cs!("gallery_particles_init.glsl"),
+        shader_uniforms!(particle_count: particle_count),
+    );
+
+    for _ in 0..PARTICLE_STEP_COUNT {
+        particles = compute_buf(
+            buf_key,
+            thread_count,
+            step_cs.clone(),
+            shader_uniforms!(
+                particlesBuf: particles,
+                particle_count: particle_count,
+                dt: dt,
+            ),
+        );
+    }
+
+    let density_key = key.with_format(vk::Format::R32_UINT);
+    let density = compute_tex(
+        density_key,
+        gallery_cs!("gallery_particles_clear.glsl"),
+        shader_uniforms!(),
+    );
+    let density = recompute_tex(
+        density,
+        gallery_cs!("gallery_particles_splat.glsl"),
+        shader_uniforms!(
+            particlesBuf: particles,
+            particle_count: particle_count,
+        ),
+    );
+
+    compute_tex(
+        key,
+        gallery_cs!("gallery_particles_resolve.glsl"),
+        shader_uniforms!(densityTex: density),
+    )
+}
+
+fn bloom_tex(
+    blur_cs: &SnoozyRef<ComputeShader>,
+    key: TextureKey,
+    input: SnoozyRef<Texture>,
+) -> SnoozyRef<Texture> {
+    // Both directions share a shader, so they're tagged to get separate profiler scopes
+    let horizontal = compute_tex(
+        key,
+        blur_cs.clone(),
+        shader_uniforms!(
+            inputTex: input,
+            direction: (1i32, 0i32),
+            : op_tags(&["direction=horizontal"]),
+        ),
+    );
+
+    compute_tex(
+        key,
+        blur_cs.clone(),
+        shader_uniforms!(
+            inputTex: horizontal,
+            direction: (0i32, 1i32),
+            : op_tags(&["direction=vertical"]),
+        ),
+    )
+}
+
+fn post_chain_tex(key: TextureKey, time: f32) -> SnoozyRef<Texture> {
+    let scene = raymarcher_tex(key, time);
+
+    let downsample_cs = gallery_cs!("gallery_post_downsample.glsl");
+    let blur_cs = gallery_cs!("gallery_post_blur.glsl");
+
+    let half_key = key.res_div_round_up(2, 2);
+    let bright = compute_tex(
+        half_key,
+        downsample_cs.clone(),
+        shader_uniforms!(inputTex: scene.clone(), threshold: 1.0f32),
+    );
+    let bloom = bloom_tex(&blur_cs, half_key, bright);
+
+    let quarter_key = half_key.res_div_round_up(2, 2);
+    let wide_bright = compute_tex(
+        quarter_key,
+        downsample_cs,
+        shader_uniforms!(inputTex: bloom.clone(), threshold: 0.0f32),
+    );
+    let wide_bloom = bloom_tex(&blur_cs, quarter_key, wide_bright);
+
+    compute_tex(
+        key,
+        gallery_cs!("gallery_post_composite.glsl"),
+        shader_uniforms!(
+            inputTex: scene,
+            bloomTex: bloom,
+            wideBloomTex: wide_bloom,
+            exposure: 1.0f32,
+            bloom_amount: 0.3f32,
+        ),
+    )
+}
+
+// Reference renders for `test_gallery_examples`, one EXR per example and time.
+#[cfg(test)]
+const GALLERY_REFERENCE_DIR: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/assets/gallery_reference");
+
+// GPUs and drivers differ in their float math, so a few texels may be off by a bit more.
+#[cfg(test)]
+const GALLERY_TEXEL_TOLERANCE: f32 = 0.02;
+#[cfg(test)]
+const GALLERY_MAX_MISMATCH_FRACTION: f32 = 0.005;
+
+// Fraction of texels with a channel further than the tolerance from the reference,
+// relative to the reference value for HDR texels.
+#[cfg(test)]
+fn gallery_mismatch_fraction(texels: &[f32], reference: &[f32]) -> f32 {
+    let mismatched = texels
+        .chunks(4)
+        .zip(reference.chunks(4))
+        .filter(|(texel, reference)| {
+            texel.iter().zip(reference.iter()).any(|(v, r)| {
+                v.is_nan() || (v - r).abs() > GALLERY_TEXEL_TOLERANCE * r.abs().max(1.0)
+            })
+        })
+        .count();
+    mismatched as f32 / (reference.len() / 4).max(1) as f32
+}
+
+#[test]
+fn test_gallery_mismatch_fraction() {
+    let reference = [0.5, 0.5, 0.5, 1.0, 10.0, 0.0, 0.0, 1.0];
+    assert_eq!(gallery_mismatch_fraction(&reference, &reference), 0.0);
+    assert_eq!(
+        gallery_mismatch_fraction(&[0.51, 0.5, 0.5, 1.0, 10.1, 0.0, 0.0, 1.0], &reference),
+        0.0
+    );
+    assert_eq!(
+        gallery_mismatch_fraction(&[0.6, 0.5, 0.5, 1.0, 10.0, 0.0, 0.0, 1.0], &reference),
+        0.5
+    );
+    assert_eq!(
+        gallery_mismatch_fraction(
+            &[0.5, 0.5, 0.5, 1.0, std::f32::NAN, 0.0, 0.0, 1.0],
+            &reference
+        ),
+        0.5
+    );
+}
+
+// Needs a Vulkan device; run with `cargo test -- --ignored`. Compares readbacks against
+// the references in `GALLERY_REFERENCE_DIR`; to update those after an intended change,
+// run it with `RTOY_BLESS_GALLERY=1`, and check the new images before committing them.
+#[test]
+#[ignore]
+fn test_gallery_examples() {
+    use crate::headless::with_test_headless_compute;
+    use crate::packing::f16_bits_to_f32;
+    use crate::texture::{decode_exr, write_rgba_f32_exr};
+
+    let bless = std::env::var_os("RTOY_BLESS_GALLERY").is_some();
+    let (width, height) = (256, 144);
+
+    with_test_headless_compute(|compute| {
+        let mut failures = Vec::new();
+        for example in GalleryExample::ALL.iter() {
+            for time in [0.0f32, 1.5].iter() {
+                let tex = gallery_example_tex(
+                    *example,
+                    TextureKey::new(width, height, vk::Format::R8G8B8A8_UNORM),
+                    *time,
+                );
+                let texels: Vec<f32> = compute
+                    .read_texture::<u16>(&tex)
+                    .unwrap()
+                    .into_iter()
+                    .map(f16_bits_to_f32)
+                    .collect();
+                assert_eq!(texels.len(), (width * height * 4) as usize);
+
+                let path = format!(
+                    "{}/{}_{:.1}.exr",
+                    GALLERY_REFERENCE_DIR,
+                    example.name(),
+                    time
+                );
+                if bless {
+                    std::fs::create_dir_all(GALLERY_REFERENCE_DIR).unwrap();
+                    write_rgba_f32_exr(&path, &texels, width, height).unwrap();
+                    continue;
+                }
+
+                let reference = std::fs::read(&path).unwrap_or_else(|err| {
+                    panic!(
+                        "{}: {}; run with RTOY_BLESS_GALLERY=1 to create it",
+                        path, err
+                    )
+                });
+                let (reference, dims) = decode_exr(&reference).unwrap();
+                if dims != (width, height) {
+                    failures.push(format!("{}: reference is {}x{}", path, dims.0, dims.1));
+                    continue;
+                }
+
+                let mismatch = gallery_mismatch_fraction(&texels, &reference);
+                if mismatch > GALLERY_MAX_MISMATCH_FRACTION {
+                    // Saved next to the temp files for a look at what changed
+                    let actual_path = std::env::temp_dir().join(format!(
+                        "{}_{:.1}.actual.exr",
+                        example.name(),
+                        time
+                    ));
+                    let actual_path = actual_path.to_string_lossy();
+                    write_rgba_f32_exr(&actual_path, &texels, width, height).unwrap();
+                    failures.push(format!(
+                        "{}: {:.2}% of texels differ; got {}",
+                        path,
+                        mismatch * 100.0,
+                        actual_path
+                    ));
+                }
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    });
+}
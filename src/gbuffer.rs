@@ -0,0 +1,93 @@
+// A standard G-buffer layout, so lighting experiments can share producers and consumers:
+//
+//   let gbuffer = raster_gbuffer(width, height, view_constants, prev_view_constants, scene);
+//   let lit = compute_tex(key, load_cs(asset!("shaders/lighting.glsl")), shader_uniforms!(
+//       :gbuffer.uniforms(),
+//   ));
+//
+// The targets, and what's in them, are described in `shaders/gbuffer.inc`, which also has
+// the packing and unpacking functions. Consumers include `shaders/gbuffer_read.inc`, which
+// declares the textures of `GBuffer::uniforms`, and reads them back as a `GBuffer` struct.
+// Any pass writing targets according to `gbuffer.inc` can stand in for `raster_gbuffer`.
+
+use crate::buffer::Buffer;
+use crate::shader::{
+    compute_tex_output, load_ps_from_string, load_vs_from_string, make_raster_pipeline_n,
+    raster_tex_n, RasterPipelineDesc, ShaderUniformBundle, ShaderUniformHolder,
+};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// Raster targets are all of this format
+const GBUFFER_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+#[derive(Clone)]
+pub struct GBuffer {
+    pub albedo: SnoozyRef<Texture>,
+    pub normal: SnoozyRef<Texture>,
+    pub roughness_metalness: SnoozyRef<Texture>,
+    pub depth: SnoozyRef<Texture>,
+    pub velocity: SnoozyRef<Texture>,
+}
+
+impl GBuffer {
+    // Named as expected by `gbuffer_read.inc`.
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        shader_uniforms!(
+            gbuffer_albedo: self.albedo.clone(),
+            gbuffer_normal: self.normal.clone(),
+            gbuffer_roughness_metalness: self.roughness_metalness.clone(),
+            gbuffer_depth: self.depth.clone(),
+            gbuffer_velocity: self.velocity.clone(),
+        )
+    }
+}
+
+// Rasterizes `scene`, as from `upload_raster_scene`, into a `width` x `height` G-buffer.
+// `view_constants` and `prev_view_constants` are `ViewConstants` buffers for this and
+// the previous frame; velocity only accounts for camera motion.
+pub fn raster_gbuffer(
+    width: u32,
+    height: u32,
+    view_constants: SnoozyRef<Buffer>,
+    prev_view_constants: SnoozyRef<Buffer>,
+    scene: ShaderUniformBundle,
+) -> GBuffer {
+    let pipeline = make_raster_pipeline_n(
+        vec![
+            load_vs_from_string(
+                include_str!("../assets/shaders/gbuffer_vs.glsl").to_owned(),
+                "gbuffer_vs.glsl".to_owned(),
+            ),
+            load_ps_from_string(
+                include_str!("../assets/shaders/gbuffer_ps.glsl").to_owned(),
+                "gbuffer_ps.glsl".to_owned(),
+            ),
+        ],
+        5,
+        RasterPipelineDesc::default(),
+    );
+
+    let key = TextureKey::new(width, height, GBUFFER_FORMAT);
+    let outputs = raster_tex_n(
+        vec![key; 5],
+        pipeline,
+        shader_uniforms!(
+            view_constants: view_constants,
+            prev_view_constants: prev_view_constants,
+            :scene,
+        ),
+    );
+
+    let target = |idx: u32| compute_tex_output(outputs.clone(), format!("outputTex{}", idx));
+
+    GBuffer {
+        albedo: target(0),
+        normal: target(1),
+        roughness_metalness: target(2),
+        depth: target(3),
+        velocity: target(4),
+    }
+}
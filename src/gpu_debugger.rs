@@ -1,50 +1,559 @@
-use crate::vk;
+// Outputs of passes, for the texture visualizer. Formats which can't be displayed as they are
+// -- integers, like ID buffers, and depth -- are converted by `debugged_tex_display`. Raster
+// passes additionally capture their depth while being debugged.
+//
+// Pixel probes read back exact values of the debugged texture, decoded as per its format.
+
+use crate::host_interop::read_back_texel;
+use crate::packing::f16_bits_to_f32;
+use crate::shader::{compute_tex, load_cs_from_string};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use crate::vulkan::*;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use futures::future::{BoxFuture, FutureExt};
+use snoozy::*;
 use std::collections::HashMap;
 use std::default::Default;
 use std::sync::Mutex;
 
-pub fn report_texture(name: &str, texture_handle: vk::ImageView) {
-    GPU_DEBUGGER
-        .lock()
-        .unwrap()
-        .report_texture(name, texture_handle);
+pub fn report_texture(name: &str, texture: &Texture) {
+    GPU_DEBUGGER.lock().unwrap().report_texture(name, texture);
+}
+
+// Selects the pass whose output is shown, and whether that's its depth rather than color.
+pub fn set_debugged_texture(name: Option<String>, show_depth: bool) {
+    let mut state = GPU_DEBUGGER.lock().unwrap();
+    state.debugged_name = name;
+    state.show_depth = show_depth;
 }
 
-pub fn with_textures<F: FnOnce(&GpuDebuggerTextures)>(f: F) {
-    f(&GPU_DEBUGGER.lock().unwrap().textures);
+pub fn is_debugged(name: &str) -> bool {
+    GPU_DEBUGGER.lock().unwrap().debugged_name.as_deref() == Some(name)
 }
 
 pub fn end_frame() {
-    GPU_DEBUGGER.lock().unwrap().clear();
+    let triggers = {
+        let mut state = GPU_DEBUGGER.lock().unwrap();
+        state.clear();
+        state.poll_probe();
+        std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default)]
 pub struct GpuDebuggerTextures {
-    pub textures: HashMap<String, vk::ImageView>,
+    pub textures: HashMap<String, Texture>,
+    // Only captured for the debugged pass
+    pub depth_textures: HashMap<String, Texture>,
+}
+
+// How texels of a format are displayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexelKind {
+    Float,
+    // Hashed to distinct colors, so that IDs can be told apart
+    Uint,
+    Sint,
+    // Reverse-Z; shown on a log scale
+    Depth,
+}
+
+impl TexelKind {
+    pub fn from_format(format: vk::Format) -> Self {
+        use vk::Format as F;
+
+        match format {
+            F::R8_UINT
+            | F::R8G8_UINT
+            | F::R8G8B8A8_UINT
+            | F::A2B10G10R10_UINT_PACK32
+            | F::R16_UINT
+            | F::R16G16_UINT
+            | F::R16G16B16A16_UINT
+            | F::R32_UINT
+            | F::R32G32_UINT
+            | F::R32G32B32_UINT
+            | F::R32G32B32A32_UINT => TexelKind::Uint,
+            F::R8_SINT
+            | F::R8G8_SINT
+            | F::R8G8B8A8_SINT
+            | F::R16_SINT
+            | F::R16G16_SINT
+            | F::R16G16B16A16_SINT
+            | F::R32_SINT
+            | F::R32G32_SINT
+            | F::R32G32B32_SINT
+            | F::R32G32B32A32_SINT => TexelKind::Sint,
+            F::D16_UNORM | F::X8_D24_UNORM_PACK32 | F::D32_SFLOAT => TexelKind::Depth,
+            _ => TexelKind::Float,
+        }
+    }
+}
+
+// Exact value of a texel, as of a few frames ago.
+#[derive(Clone, Debug)]
+pub struct TexelProbe {
+    pub name: String,
+    pub pixel: (u32, u32),
+    pub format: vk::Format,
+    pub bytes: Vec<u8>,
+}
+
+impl TexelProbe {
+    // e.g. `R32_UINT (12, 34): 1729`
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} ({}, {}): {}",
+            self.format,
+            self.pixel.0,
+            self.pixel.1,
+            describe_texel(self.format, &self.bytes)
+        )
+    }
+}
+
+// Reads back a texel of the displayed texture; the result shows up in `last_texel_probe`
+// once the GPU gets to it. Requests made while one is in flight are dropped.
+pub fn probe_texel(pixel: (u32, u32)) {
+    let mut state = GPU_DEBUGGER.lock().unwrap();
+    if state.pending_probe.is_some() {
+        return;
+    }
+
+    let (name, tex) = match state.displayed_source() {
+        Some((name, tex, _)) => (name.clone(), tex.clone()),
+        None => return,
+    };
+
+    let pixel = (
+        pixel.0.min(tex.key.width.saturating_sub(1)),
+        pixel.1.min(tex.key.height.saturating_sub(1)),
+    );
+
+    let readback = read_back_texel(&tex, pixel).boxed();
+    state.pending_probe = Some((
+        TexelProbe {
+            name,
+            pixel,
+            format: vk::Format::from_raw(tex.key.format),
+            bytes: Vec::new(),
+        },
+        readback,
+    ));
+}
+
+pub fn last_texel_probe() -> Option<TexelProbe> {
+    GPU_DEBUGGER.lock().unwrap().last_probe.clone()
+}
+
+// Unless the kind is `Float`, the texture should be shown via `debugged_tex_display`.
+pub fn displayed_texture() -> Option<(Texture, TexelKind)> {
+    let state = GPU_DEBUGGER.lock().unwrap();
+    state
+        .displayed_source()
+        .map(|(_, tex, kind)| (tex.clone(), kind))
+}
+
+#[snoozy]
+pub async fn debugged_tex_snoozy(ctx: Context) -> Result<Texture> {
+    let mut state = GPU_DEBUGGER.lock().unwrap();
+    state
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+
+    state
+        .displayed_source()
+        .map(|(_, tex, _)| tex.clone())
+        .ok_or_else(|| format_err!("No texture is being debugged"))
+}
+
+// The displayed texture, converted to `R16G16B16A16_SFLOAT` as per its `TexelKind`.
+#[snoozy]
+pub async fn debugged_tex_display_snoozy(mut ctx: Context) -> Result<Texture> {
+    let source = ctx.get(debugged_tex()).await?;
+    let kind = GPU_DEBUGGER
+        .lock()
+        .unwrap()
+        .displayed_source()
+        .map_or(TexelKind::Float, |(_, _, kind)| kind);
+
+    let define = match kind {
+        TexelKind::Float => return Ok((*source).clone()),
+        TexelKind::Uint => "SOURCE_UINT",
+        TexelKind::Sint => "SOURCE_SINT",
+        TexelKind::Depth => "SOURCE_DEPTH",
+    };
+
+    let cs = load_cs_from_string(
+        format!(
+            "#define {}\n{}",
+            define,
+            include_str!("../assets/shaders/debug_display.glsl")
+        ),
+        format!("debug_display_{}.glsl", define.to_lowercase()),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            source.key.with_format(vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(inputTex: debugged_tex()),
+        ))
+        .await?;
+    Ok((*tex).clone())
+}
+
+// Copies the shared depth buffer into an `R32_SFLOAT` texture, as left by the raster pass
+// `name` which has just been recorded into `cb`. Only does anything if the pass is debugged.
+pub(crate) fn capture_raster_depth(cb: vk::CommandBuffer, name: &str, key: &TextureKey) {
+    if !is_debugged(name) {
+        return;
+    }
+
+    let (vk, vk_state) = vk_all();
+    let depth_tex =
+        crate::backend::texture::create_texture(key.with_format(vk::Format::R32_SFLOAT));
+    let size_bytes = key.width as u64 * key.height as u64 * 4;
+
+    let (staging_buffer, staging_allocation, _staging_allocation_info) = vk
+        .allocator
+        .create_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(size_bytes)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                ..Default::default()
+            },
+        )
+        .expect("vma::create_buffer");
+
+    let copy_region = |aspect_mask| {
+        vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(aspect_mask)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_extent(vk::Extent3D {
+                width: key.width,
+                height: key.height,
+                depth: 1,
+            })
+            .build()
+    };
+
+    unsafe {
+        record_image_aspect_barrier(
+            &vk.device,
+            cb,
+            vk::ImageAspectFlags::DEPTH,
+            ImageBarrier::new(
+                vk_state.depth_image,
+                vk_sync::AccessType::DepthAttachmentWriteStencilReadOnly,
+                vk_sync::AccessType::TransferRead,
+            ),
+        );
+
+        vk.device.cmd_copy_image_to_buffer(
+            cb,
+            vk_state.depth_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            staging_buffer,
+            &[copy_region(vk::ImageAspectFlags::DEPTH)],
+        );
+
+        record_image_aspect_barrier(
+            &vk.device,
+            cb,
+            vk::ImageAspectFlags::DEPTH,
+            ImageBarrier::new(
+                vk_state.depth_image,
+                vk_sync::AccessType::TransferRead,
+                vk_sync::AccessType::DepthAttachmentWriteStencilReadOnly,
+            ),
+        );
+
+        vk_sync::cmd::pipeline_barrier(
+            vk.device.fp_v1_0(),
+            cb,
+            Some(vk_sync::GlobalBarrier {
+                previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                next_accesses: &[vk_sync::AccessType::TransferRead],
+            }),
+            &[],
+            &[],
+        );
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                depth_tex.image,
+                vk_sync::AccessType::Nothing,
+                vk_sync::AccessType::TransferWrite,
+            )
+            .with_discard(true),
+        );
+
+        vk.device.cmd_copy_buffer_to_image(
+            cb,
+            staging_buffer,
+            depth_tex.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[copy_region(vk::ImageAspectFlags::COLOR)],
+        );
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                depth_tex.image,
+                vk_sync::AccessType::TransferWrite,
+                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            ),
+        );
+    }
+
+    vk_state
+        .current_frame()
+        .frame_cleanup
+        .lock()
+        .unwrap()
+        .push(Box::new(move |vk| {
+            vk.allocator
+                .destroy_buffer(staging_buffer, &staging_allocation)
+                .unwrap();
+        }));
+
+    GPU_DEBUGGER
+        .lock()
+        .unwrap()
+        .textures
+        .depth_textures
+        .insert(name.to_owned(), depth_tex);
 }
 
 struct GpuDebugger {
     textures: GpuDebuggerTextures,
+    debugged_name: Option<String>,
+    show_depth: bool,
+    pending_probe: Option<(TexelProbe, BoxFuture<'static, Result<Vec<u8>>>)>,
+    last_probe: Option<TexelProbe>,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
 }
 
 impl GpuDebugger {
     pub fn new() -> Self {
         Self {
             textures: Default::default(),
+            debugged_name: None,
+            show_depth: false,
+            pending_probe: None,
+            last_probe: None,
+            invalidation_triggers: Vec::new(),
         }
     }
 
     fn clear(&mut self) {
         self.textures.textures.clear();
+        self.textures.depth_textures.clear();
     }
 
-    fn report_texture(&mut self, name: &str, texture_handle: vk::ImageView) {
+    fn report_texture(&mut self, name: &str, texture: &Texture) {
         self.textures
             .textures
-            .insert(name.to_string(), texture_handle);
+            .insert(name.to_string(), texture.clone());
+    }
+
+    // The texture shown for the debugged pass, before any conversion.
+    fn displayed_source(&self) -> Option<(&String, &Texture, TexelKind)> {
+        let name = self.debugged_name.as_ref()?;
+        if self.show_depth {
+            let tex = self.textures.depth_textures.get(name)?;
+            Some((name, tex, TexelKind::Depth))
+        } else {
+            let tex = self.textures.textures.get(name)?;
+            let kind = TexelKind::from_format(vk::Format::from_raw(tex.key.format));
+            Some((name, tex, kind))
+        }
+    }
+
+    fn poll_probe(&mut self) {
+        let done = match self.pending_probe.as_mut() {
+            Some((_, readback)) => readback.now_or_never(),
+            None => None,
+        };
+
+        if let Some(res) = done {
+            let (mut probe, _) = self.pending_probe.take().unwrap();
+            match res {
+                Ok(bytes) => {
+                    probe.bytes = bytes;
+                    self.last_probe = Some(probe);
+                }
+                Err(err) => tracing::warn!("Pixel probe of {} failed: {}", probe.name, err),
+            }
+        }
     }
 }
 
 lazy_static! {
     static ref GPU_DEBUGGER: Mutex<GpuDebugger> = Mutex::new(GpuDebugger::new());
 }
+
+#[derive(Clone, Copy)]
+enum TexelComponent {
+    Unorm8,
+    Snorm8,
+    Uint8,
+    Sint8,
+    Unorm16,
+    Snorm16,
+    Uint16,
+    Sint16,
+    Float16,
+    Uint32,
+    Sint32,
+    Float32,
+}
+
+impl TexelComponent {
+    fn size_bytes(self) -> usize {
+        use TexelComponent::*;
+        match self {
+            Unorm8 | Snorm8 | Uint8 | Sint8 => 1,
+            Unorm16 | Snorm16 | Uint16 | Sint16 | Float16 => 2,
+            Uint32 | Sint32 | Float32 => 4,
+        }
+    }
+
+    // Normalized formats show the stored integer too, so no precision is lost.
+    fn describe(self, b: &[u8]) -> String {
+        use TexelComponent::*;
+        let u16_at = || u16::from_le_bytes([b[0], b[1]]);
+        let u32_at = || u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+
+        match self {
+            Unorm8 => format!("{} ({})", b[0] as f32 / 255.0, b[0]),
+            Snorm8 => format!("{} ({})", (b[0] as i8 as f32 / 127.0).max(-1.0), b[0] as i8),
+            Uint8 => format!("{}", b[0]),
+            Sint8 => format!("{}", b[0] as i8),
+            Unorm16 => format!("{} ({})", u16_at() as f32 / 65535.0, u16_at()),
+            Snorm16 => format!(
+                "{} ({})",
+                (u16_at() as i16 as f32 / 32767.0).max(-1.0),
+                u16_at() as i16
+            ),
+            Uint16 => format!("{}", u16_at()),
+            Sint16 => format!("{}", u16_at() as i16),
+            Float16 => format!("{}", f16_bits_to_f32(u16_at())),
+            Uint32 => format!("{}", u32_at()),
+            Sint32 => format!("{}", u32_at() as i32),
+            Float32 => format!("{}", f32::from_bits(u32_at())),
+        }
+    }
+}
+
+// Component type and count, in memory order.
+fn texel_components(format: vk::Format) -> Option<(TexelComponent, usize)> {
+    use vk::Format as F;
+    use TexelComponent::*;
+
+    Some(match format {
+        F::R8_UNORM | F::R8_SRGB => (Unorm8, 1),
+        F::R8G8_UNORM | F::R8G8_SRGB => (Unorm8, 2),
+        F::R8G8B8A8_UNORM | F::R8G8B8A8_SRGB | F::B8G8R8A8_UNORM | F::B8G8R8A8_SRGB => (Unorm8, 4),
+        F::R8_SNORM => (Snorm8, 1),
+        F::R8G8_SNORM => (Snorm8, 2),
+        F::R8G8B8A8_SNORM => (Snorm8, 4),
+        F::R8_UINT => (Uint8, 1),
+        F::R8G8_UINT => (Uint8, 2),
+        F::R8G8B8A8_UINT => (Uint8, 4),
+        F::R8_SINT => (Sint8, 1),
+        F::R8G8_SINT => (Sint8, 2),
+        F::R8G8B8A8_SINT => (Sint8, 4),
+        F::R16_UNORM | F::D16_UNORM => (Unorm16, 1),
+        F::R16G16_UNORM => (Unorm16, 2),
+        F::R16G16B16A16_UNORM => (Unorm16, 4),
+        F::R16_SNORM => (Snorm16, 1),
+        F::R16G16_SNORM => (Snorm16, 2),
+        F::R16G16B16A16_SNORM => (Snorm16, 4),
+        F::R16_UINT => (Uint16, 1),
+        F::R16G16_UINT => (Uint16, 2),
+        F::R16G16B16A16_UINT => (Uint16, 4),
+        F::R16_SINT => (Sint16, 1),
+        F::R16G16_SINT => (Sint16, 2),
+        F::R16G16B16A16_SINT => (Sint16, 4),
+        F::R16_SFLOAT => (Float16, 1),
+        F::R16G16_SFLOAT => (Float16, 2),
+        F::R16G16B16A16_SFLOAT => (Float16, 4),
+        F::R32_UINT => (Uint32, 1),
+        F::R32G32_UINT => (Uint32, 2),
+        F::R32G32B32_UINT => (Uint32, 3),
+        F::R32G32B32A32_UINT => (Uint32, 4),
+        F::R32_SINT => (Sint32, 1),
+        F::R32G32_SINT => (Sint32, 2),
+        F::R32G32B32_SINT => (Sint32, 3),
+        F::R32G32B32A32_SINT => (Sint32, 4),
+        F::R32_SFLOAT | F::D32_SFLOAT => (Float32, 1),
+        F::R32G32_SFLOAT => (Float32, 2),
+        F::R32G32B32_SFLOAT => (Float32, 3),
+        F::R32G32B32A32_SFLOAT => (Float32, 4),
+        _ => return None,
+    })
+}
+
+// Components separated by commas, e.g. `0.5 (128), 1 (255)`; packed formats as hex.
+pub fn describe_texel(format: vk::Format, bytes: &[u8]) -> String {
+    match texel_components(format) {
+        Some((component, count)) if bytes.len() >= component.size_bytes() * count => bytes
+            .chunks(component.size_bytes())
+            .take(count)
+            .map(|b| component.describe(b))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => bytes
+            .iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(""),
+    }
+}
+
+#[test]
+fn test_describe_texel() {
+    assert_eq!(
+        describe_texel(vk::Format::R32_UINT, &1729u32.to_le_bytes()),
+        "1729"
+    );
+    assert_eq!(
+        describe_texel(vk::Format::R16G16_SINT, &[0xff, 0xff, 0x02, 0x00]),
+        "-1, 2"
+    );
+    assert_eq!(
+        describe_texel(vk::Format::R8G8_UNORM, &[0, 255]),
+        "0 (0), 1 (255)"
+    );
+    assert_eq!(
+        describe_texel(vk::Format::R16_SFLOAT, &0x3c00u16.to_le_bytes()),
+        "1"
+    );
+    assert_eq!(
+        describe_texel(
+            vk::Format::B10G11R11_UFLOAT_PACK32,
+            &[0x01, 0x02, 0x03, 0x04]
+        ),
+        "04030201"
+    );
+}
@@ -1,7 +1,8 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use std::collections::HashMap;
+use crate::op_tags::OpTags;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::default::Default;
 use std::sync::Mutex;
 
@@ -15,7 +16,12 @@ impl Default for GpuProfilerQueryId {
 }
 
 pub fn create_gpu_query(name: &str) -> GpuProfilerQueryId {
-    GPU_PROFILER.lock().unwrap().create_gpu_query(name)
+    create_tagged_gpu_query(name, &OpTags::default())
+}
+
+// The tags of a scope are those of its first query.
+pub fn create_tagged_gpu_query(name: &str, tags: &OpTags) -> GpuProfilerQueryId {
+    GPU_PROFILER.lock().unwrap().create_gpu_query(name, tags)
 }
 
 pub fn report_durations_ticks(
@@ -44,6 +50,159 @@ pub fn get_stats() -> GpuProfilerStats {
     GPU_PROFILER.lock().unwrap().stats.clone()
 }
 
+// Number of most recent hits each scope keeps statistics over. Resets the history.
+pub fn set_gpu_timing_history_len(len: usize) {
+    let mut prof = GPU_PROFILER.lock().unwrap();
+    let len = len.max(1);
+    prof.stats.history_len = len;
+    for scope in prof.stats.scopes.values_mut() {
+        scope.hits = vec![0u64; len];
+        scope.write_head = 0;
+    }
+}
+
+pub(crate) fn gpu_timing_history_len() -> usize {
+    GPU_PROFILER.lock().unwrap().stats.history_len
+}
+
+// Durations reported during the last frame, in milliseconds. Those lag a few frames
+// behind recording, as they're only read back once the GPU is done with them.
+pub(crate) fn last_frame_durations() -> Vec<(String, f64)> {
+    GPU_PROFILER.lock().unwrap().last_frame_durations.clone()
+}
+
+// `last_frame_durations` summed up per value of the `key` tag. Scopes without the tag
+// are left out.
+pub(crate) fn last_frame_durations_by_tag(key: &str) -> HashMap<String, f64> {
+    let prof = GPU_PROFILER.lock().unwrap();
+    let mut res = HashMap::new();
+    for (name, ms) in prof.last_frame_durations.iter() {
+        let scope = prof.stats.scopes.get(&GpuProfilerScopeId(name.clone()));
+        if let Some(value) = scope.and_then(|s| s.tags.get(key)) {
+            *res.entry(value.to_owned()).or_insert(0.0) += *ms;
+        }
+    }
+    res
+}
+
+// Statistics of all scopes seen so far, sorted by name.
+pub fn gpu_pass_timing_stats() -> Vec<GpuPassTimingStats> {
+    let prof = GPU_PROFILER.lock().unwrap();
+    let mut stats: Vec<_> = prof
+        .stats
+        .scopes
+        .values()
+        .filter_map(GpuProfilerScope::timing_stats)
+        .collect();
+    stats.sort_by(|a, b| a.name.cmp(&b.name));
+    stats
+}
+
+// Average durations summed up per value of the `key` tag, the most expensive first.
+// Scopes without the tag are left out.
+pub fn gpu_timing_by_tag(key: &str) -> Vec<(String, f64)> {
+    let mut totals: HashMap<String, f64> = HashMap::new();
+    for stats in gpu_pass_timing_stats() {
+        if let Some(value) = stats.tags.get(key) {
+            *totals.entry(value.to_owned()).or_default() += stats.avg_ms;
+        }
+    }
+
+    let mut totals: Vec<_> = totals.into_iter().collect();
+    totals.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    totals
+}
+
+// Average durations summed up per pass group, sorted by path so that nested groups
+// follow their parent. Parent groups include the time of the groups nested in them.
+pub fn gpu_timing_by_group() -> Vec<(String, f64)> {
+    let mut totals: BTreeMap<String, f64> = BTreeMap::new();
+    for stats in gpu_pass_timing_stats() {
+        if let Some(group) = stats.tags.group() {
+            for (i, _) in group.match_indices('/') {
+                *totals.entry(group[..i].to_owned()).or_default() += stats.avg_ms;
+            }
+            *totals.entry(group.to_owned()).or_default() += stats.avg_ms;
+        }
+    }
+
+    totals.into_iter().collect()
+}
+
+// Writes the current average duration of every scope to `path`, for use with
+// `load_gpu_timing_baseline` in a later run.
+pub fn save_gpu_timing_baseline(path: &str) -> std::io::Result<()> {
+    let text: String = gpu_pass_timing_stats()
+        .iter()
+        .map(|s| format!("{}\t{}\n", s.name, s.avg_ms))
+        .collect();
+    std::fs::write(path, text)
+}
+
+// Logs a warning whenever the average duration of a scope exceeds its baseline
+// by more than `threshold`, e.g. 0.1 for 10%. Averages are only compared once
+// the full history is available.
+pub fn load_gpu_timing_baseline(path: &str, threshold: f32) -> std::io::Result<()> {
+    let baseline = std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.rsplitn(2, '\t');
+            let avg_ms = parts.next()?.trim().parse::<f64>().ok()?;
+            let name = parts.next()?;
+            Some((name.to_owned(), avg_ms))
+        })
+        .collect();
+
+    let mut prof = GPU_PROFILER.lock().unwrap();
+    prof.baseline = Some(GpuTimingBaseline {
+        avg_ms: baseline,
+        threshold: threshold as f64,
+        regressed: HashSet::new(),
+    });
+    Ok(())
+}
+
+// Pipelines created for a shader so far, e.g. through edits or workgroup size variants.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineCreationStats {
+    pub name: String,
+    pub count: usize,
+    pub total_ms: f64,
+    pub last_ms: f64,
+    // Only known when the driver supports pipeline creation feedback;
+    // otherwise neither is counted, and durations are measured on the CPU.
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+// Pipeline creation statistics per shader, the most time-consuming first.
+pub fn pipeline_creation_stats() -> Vec<PipelineCreationStats> {
+    let prof = GPU_PROFILER.lock().unwrap();
+    let mut stats: Vec<_> = prof.pipeline_creation.values().cloned().collect();
+    stats.sort_by(|a, b| b.total_ms.partial_cmp(&a.total_ms).unwrap());
+    stats
+}
+
+pub(crate) fn report_pipeline_creation(name: &str, duration_ms: f64, cache_hit: Option<bool>) {
+    let mut prof = GPU_PROFILER.lock().unwrap();
+    let stats = prof
+        .pipeline_creation
+        .entry(name.to_owned())
+        .or_insert_with(|| PipelineCreationStats {
+            name: name.to_owned(),
+            ..Default::default()
+        });
+
+    stats.count += 1;
+    stats.total_ms += duration_ms;
+    stats.last_ms = duration_ms;
+    match cache_hit {
+        Some(true) => stats.cache_hits += 1,
+        Some(false) => stats.cache_misses += 1,
+        None => {}
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct GpuProfilerScopeId(String);
 
@@ -58,16 +217,18 @@ impl From<String> for GpuProfilerScopeId {
 #[derive(Debug, Clone)]
 pub struct GpuProfilerScope {
     pub name: String,
+    pub tags: OpTags,
     pub hits: Vec<u64>, // nanoseconds
     pub write_head: u32,
 }
 
 impl GpuProfilerScope {
-    fn with_name(name: String) -> GpuProfilerScope {
+    fn with_name(name: String, tags: OpTags, history_len: usize) -> GpuProfilerScope {
         GpuProfilerScope {
-            hits: vec![0u64; 64],
+            hits: vec![0u64; history_len],
             write_head: 0,
             name,
+            tags,
         }
     }
 }
@@ -81,26 +242,79 @@ impl GpuProfilerScope {
         let count = (self.hit_count() as f64).max(1.0);
         self.hits.iter().sum::<u64>() as f64 / count / 1_000_000.0
     }
+
+    pub fn timing_stats(&self) -> Option<GpuPassTimingStats> {
+        let count = self.hit_count() as usize;
+        if count == 0 {
+            return None;
+        }
+
+        let mut hits: Vec<u64> = self.hits[..count].to_vec();
+        hits.sort();
+
+        let to_ms = |ns: u64| ns as f64 / 1_000_000.0;
+        let p99_idx = ((count as f64 * 0.99).ceil() as usize).max(1) - 1;
+
+        Some(GpuPassTimingStats {
+            name: self.name.clone(),
+            tags: self.tags.clone(),
+            sample_count: count,
+            min_ms: to_ms(hits[0]),
+            avg_ms: to_ms(hits.iter().sum::<u64>()) / count as f64,
+            max_ms: to_ms(hits[count - 1]),
+            p99_ms: to_ms(hits[p99_idx]),
+        })
+    }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
+pub struct GpuPassTimingStats {
+    pub name: String,
+    pub tags: OpTags,
+    pub sample_count: usize,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub p99_ms: f64,
+}
+
+#[derive(Debug, Clone)]
 pub struct GpuProfilerStats {
     pub scopes: HashMap<GpuProfilerScopeId, GpuProfilerScope>,
     pub order: Vec<GpuProfilerQueryId>,
+    history_len: usize,
+}
+
+impl Default for GpuProfilerStats {
+    fn default() -> Self {
+        Self {
+            scopes: Default::default(),
+            order: Default::default(),
+            history_len: 64,
+        }
+    }
 }
 
 struct ActiveQuery {
     id: GpuProfilerQueryId,
     name: String,
+    tags: OpTags,
 }
 
 impl GpuProfilerStats {
-    fn report_duration_nanos(&mut self, query_id: GpuProfilerQueryId, duration: u64, name: String) {
+    fn report_duration_nanos(
+        &mut self,
+        query_id: GpuProfilerQueryId,
+        duration: u64,
+        name: String,
+        tags: OpTags,
+    ) {
         let scope_id = GpuProfilerScopeId::from(name.clone());
+        let history_len = self.history_len;
         let mut entry = self
             .scopes
             .entry(scope_id)
-            .or_insert_with(|| GpuProfilerScope::with_name(name));
+            .or_insert_with(|| GpuProfilerScope::with_name(name, tags, history_len));
 
         let len = entry.hits.len();
         entry.hits[entry.write_head as usize % len] = duration;
@@ -108,11 +322,22 @@ impl GpuProfilerStats {
     }
 }
 
+struct GpuTimingBaseline {
+    avg_ms: HashMap<String, f64>,
+    threshold: f64,
+    // Scopes which have already been reported, until they recover
+    regressed: HashSet<String>,
+}
+
 struct GpuProfiler {
     active_queries: HashMap<GpuProfilerQueryId, ActiveQuery>,
     frame_query_ids: Vec<GpuProfilerQueryId>,
     next_query_id: u64,
     stats: GpuProfilerStats,
+    baseline: Option<GpuTimingBaseline>,
+    pipeline_creation: HashMap<String, PipelineCreationStats>,
+    frame_durations: Vec<(String, f64)>,
+    last_frame_durations: Vec<(String, f64)>,
 }
 
 impl GpuProfiler {
@@ -122,6 +347,10 @@ impl GpuProfiler {
             frame_query_ids: Default::default(),
             next_query_id: 0,
             stats: Default::default(),
+            baseline: None,
+            pipeline_creation: Default::default(),
+            frame_durations: Default::default(),
+            last_frame_durations: Default::default(),
         }
     }
 
@@ -134,7 +363,10 @@ impl GpuProfiler {
             // Remove the finished queries from the active list
             let q = self.active_queries.remove(&query_id).unwrap();
             let duration = (duration_ticks as f64 * ns_per_tick as f64) as u64;
-            self.stats.report_duration_nanos(query_id, duration, q.name);
+            self.frame_durations
+                .push((q.name.clone(), duration as f64 / 1_000_000.0));
+            self.stats
+                .report_duration_nanos(query_id, duration, q.name, q.tags);
         }
     }
 
@@ -147,9 +379,37 @@ impl GpuProfiler {
     fn end_frame(&mut self) {
         self.stats.order.clear();
         self.stats.order.extend(self.frame_query_ids.drain(..));
+        self.last_frame_durations = std::mem::replace(&mut self.frame_durations, Vec::new());
+
+        if let Some(baseline) = self.baseline.as_mut() {
+            for scope in self.stats.scopes.values() {
+                if (scope.hit_count() as usize) < scope.hits.len() {
+                    continue;
+                }
+
+                let baseline_ms = match baseline.avg_ms.get(&scope.name) {
+                    Some(ms) => *ms,
+                    None => continue,
+                };
+
+                let avg_ms = scope.average_duration_millis();
+                if avg_ms > baseline_ms * (1.0 + baseline.threshold) {
+                    if baseline.regressed.insert(scope.name.clone()) {
+                        tracing::warn!(
+                            "GPU pass {} regressed: {:.3}ms vs a baseline of {:.3}ms",
+                            scope.name,
+                            avg_ms,
+                            baseline_ms
+                        );
+                    }
+                } else {
+                    baseline.regressed.remove(&scope.name);
+                }
+            }
+        }
     }
 
-    fn create_gpu_query(&mut self, name: &str) -> GpuProfilerQueryId {
+    fn create_gpu_query(&mut self, name: &str, tags: &OpTags) -> GpuProfilerQueryId {
         let id = GpuProfilerQueryId(self.next_query_id);
         self.next_query_id += 1;
         self.frame_query_ids.push(id);
@@ -158,6 +418,7 @@ impl GpuProfiler {
             ActiveQuery {
                 id,
                 name: name.to_string(),
+                tags: tags.clone(),
             },
         );
         assert!(self.active_queries.len() < 8192);
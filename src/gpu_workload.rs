@@ -0,0 +1,111 @@
+// Rough GPU workload estimates, made from dispatch sizes rather than timings,
+// so that a runaway pass gets flagged before it's had a chance to stall the GPU.
+//
+// Compute passes count the threads they launch, including those in partially filled
+// groups; raster passes count the pixels of their render target.
+
+use crate::warnings::rtoy_show_warning;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub struct GpuPassWorkload {
+    pub name: String,
+    pub thread_count: u64,
+}
+
+struct GpuWorkloadState {
+    pass_budget: Option<u64>,
+    frame_budget: Option<u64>,
+    growth_warning_factor: f64,
+    // By op identity rather than pass name
+    last_pass_thread_counts: HashMap<String, u64>,
+    current_frame: Vec<GpuPassWorkload>,
+    last_frame: Vec<GpuPassWorkload>,
+    frame_over_budget: bool,
+}
+
+lazy_static! {
+    static ref GPU_WORKLOAD: Mutex<GpuWorkloadState> = Mutex::new(GpuWorkloadState {
+        pass_budget: None,
+        frame_budget: None,
+        growth_warning_factor: 4.0,
+        last_pass_thread_counts: HashMap::new(),
+        current_frame: Vec::new(),
+        last_frame: Vec::new(),
+        frame_over_budget: false,
+    });
+}
+
+// Thread counts above which a warning is shown, for a single pass and for all passes
+// recorded in a frame. `None` disables the respective check.
+pub fn set_gpu_workload_budget(pass_threads: Option<u64>, frame_threads: Option<u64>) {
+    let mut state = GPU_WORKLOAD.lock().unwrap();
+    state.pass_budget = pass_threads;
+    state.frame_budget = frame_threads;
+}
+
+// Warns when a pass launches this many times more threads than it did the last time it ran.
+pub fn set_gpu_workload_growth_warning_factor(factor: f32) {
+    GPU_WORKLOAD.lock().unwrap().growth_warning_factor = factor.max(1.0) as f64;
+}
+
+// Passes recorded in the last completed frame. Cached passes are not re-recorded,
+// so this only lists those which were recomputed.
+pub fn last_frame_gpu_workload() -> Vec<GpuPassWorkload> {
+    GPU_WORKLOAD.lock().unwrap().last_frame.clone()
+}
+
+// `op_id` tells apart ops sharing a pass name, e.g. the same shader writing different outputs;
+// `name` is what gets shown.
+pub(crate) fn report_pass_workload(op_id: &str, name: &str, thread_count: u64) {
+    let mut state = GPU_WORKLOAD.lock().unwrap();
+
+    let prev = state
+        .last_pass_thread_counts
+        .insert(op_id.to_owned(), thread_count);
+
+    if let Some(budget) = state.pass_budget {
+        // Only warn when crossing the budget, rather than every time the pass runs
+        if thread_count > budget && prev.map(|prev| prev <= budget).unwrap_or(true) {
+            rtoy_show_warning(format!(
+                "{} launches {} threads, over the budget of {}",
+                name, thread_count, budget
+            ));
+        }
+    }
+
+    if let Some(prev) = prev {
+        if prev > 0 && thread_count as f64 > prev as f64 * state.growth_warning_factor {
+            rtoy_show_warning(format!(
+                "{} grew from {} to {} threads",
+                name, prev, thread_count
+            ));
+        }
+    }
+
+    state.current_frame.push(GpuPassWorkload {
+        name: name.to_owned(),
+        thread_count,
+    });
+}
+
+pub(crate) fn end_frame() {
+    let mut state = GPU_WORKLOAD.lock().unwrap();
+
+    let total: u64 = state.current_frame.iter().map(|p| p.thread_count).sum();
+    let over_budget = state
+        .frame_budget
+        .map(|budget| total > budget)
+        .unwrap_or(false);
+    if over_budget && !state.frame_over_budget {
+        rtoy_show_warning(format!(
+            "The frame launches {} threads, over the budget of {}",
+            total,
+            state.frame_budget.unwrap()
+        ));
+    }
+    state.frame_over_budget = over_budget;
+
+    state.last_frame = std::mem::replace(&mut state.current_frame, Vec::new());
+}
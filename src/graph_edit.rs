@@ -0,0 +1,183 @@
+// Named nodes which can be rewired at runtime, for node-graph editors built on rendertoy.
+//
+// `graph_node("name")` evaluates whatever the node is currently defined as, and can be
+// used as an input of other nodes. Redefining a node or replacing one of its inputs only
+// invalidates that node and the ops downstream of it; everything else stays cached.
+//
+//   set_graph_pass("blur", key, load_cs(asset!("shaders/blur.glsl")), shader_uniforms!(
+//       inputTex: graph_node("src"),
+//   ));
+//   set_graph_node("src", load_tex(asset!("images/foo.png")));
+//   ...
+//   replace_graph_node_input("blur", ShaderUniformHolder::new("inputTex", other_tex))?;
+//
+// Edits and finished evaluations are reported through `take_graph_changes`.
+
+use crate::shader::{compute_tex, ComputeShader, ShaderUniformHolder};
+use crate::texture::{Texture, TextureKey};
+use snoozy::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphChangeKind {
+    Redefined,
+    InputReplaced(String),
+    Removed,
+    // A new result is available after an edit upstream of the node
+    Evaluated,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphChange {
+    pub node: String,
+    pub kind: GraphChangeKind,
+}
+
+#[derive(Clone)]
+enum GraphNodeDef {
+    Tex(SnoozyRef<Texture>),
+    Pass {
+        key: TextureKey,
+        cs: SnoozyRef<ComputeShader>,
+        uniforms: Vec<ShaderUniformHolder>,
+    },
+}
+
+impl GraphNodeDef {
+    fn output(&self) -> SnoozyRef<Texture> {
+        match self {
+            GraphNodeDef::Tex(tex) => tex.clone(),
+            GraphNodeDef::Pass { key, cs, uniforms } => {
+                compute_tex(*key, cs.clone(), uniforms.clone())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct GraphEditState {
+    nodes: HashMap<String, GraphNodeDef>,
+    changes: Vec<GraphChange>,
+    invalidation_triggers: HashMap<String, Vec<Box<dyn Fn() + Send + Sync>>>,
+}
+
+lazy_static! {
+    static ref GRAPH_EDIT: Mutex<GraphEditState> = Mutex::new(Default::default());
+}
+
+impl GraphEditState {
+    fn set_node(
+        &mut self,
+        name: &str,
+        def: Option<GraphNodeDef>,
+        kind: GraphChangeKind,
+    ) -> Vec<Box<dyn Fn() + Send + Sync>> {
+        match def {
+            Some(def) => self.nodes.insert(name.to_owned(), def),
+            None => self.nodes.remove(name),
+        };
+
+        self.changes.push(GraphChange {
+            node: name.to_owned(),
+            kind,
+        });
+
+        self.invalidation_triggers.remove(name).unwrap_or_default()
+    }
+}
+
+fn edit_node(name: &str, def: Option<GraphNodeDef>, kind: GraphChangeKind) {
+    let triggers = GRAPH_EDIT.lock().unwrap().set_node(name, def, kind);
+    crate::edit_preview::notify_edit();
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// The current output of the node `name`. Fails while the node is undefined.
+#[snoozy]
+pub async fn graph_node_snoozy(mut ctx: Context, name: &String) -> Result<Texture> {
+    let def = {
+        let mut state = GRAPH_EDIT.lock().unwrap();
+        state
+            .invalidation_triggers
+            .entry(name.clone())
+            .or_default()
+            .push(Box::new(ctx.get_invalidation_trigger()));
+
+        state.nodes.get(name).cloned()
+    };
+
+    let def = def.ok_or_else(|| format_err!("Graph node {} is not defined", name))?;
+    let tex = ctx.get(def.output()).await?;
+
+    GRAPH_EDIT.lock().unwrap().changes.push(GraphChange {
+        node: name.clone(),
+        kind: GraphChangeKind::Evaluated,
+    });
+
+    Ok((*tex).clone())
+}
+
+// Swaps the whole subgraph behind `name`.
+pub fn set_graph_node(name: &str, tex: SnoozyRef<Texture>) {
+    edit_node(
+        name,
+        Some(GraphNodeDef::Tex(tex)),
+        GraphChangeKind::Redefined,
+    );
+}
+
+// Defines `name` as a compute pass, whose inputs can later be replaced one by one.
+pub fn set_graph_pass(
+    name: &str,
+    key: TextureKey,
+    cs: SnoozyRef<ComputeShader>,
+    uniforms: Vec<ShaderUniformHolder>,
+) {
+    edit_node(
+        name,
+        Some(GraphNodeDef::Pass { key, cs, uniforms }),
+        GraphChangeKind::Redefined,
+    );
+}
+
+// Replaces the uniform of the same name on a pass node, or adds it if there's none.
+pub fn replace_graph_node_input(name: &str, input: ShaderUniformHolder) -> Result<()> {
+    let def = GRAPH_EDIT.lock().unwrap().nodes.get(name).cloned();
+    let (key, cs, mut uniforms) = match def {
+        Some(GraphNodeDef::Pass { key, cs, uniforms }) => (key, cs, uniforms),
+        Some(GraphNodeDef::Tex(_)) => bail!("Graph node {} is not a pass", name),
+        None => bail!("Graph node {} is not defined", name),
+    };
+
+    let input_name = input.name().to_owned();
+    match uniforms.iter_mut().find(|u| u.name() == input_name) {
+        Some(uniform) => *uniform = input,
+        None => uniforms.push(input),
+    }
+
+    edit_node(
+        name,
+        Some(GraphNodeDef::Pass { key, cs, uniforms }),
+        GraphChangeKind::InputReplaced(input_name),
+    );
+    Ok(())
+}
+
+// Dependents of the node fail to evaluate until it's defined again.
+pub fn remove_graph_node(name: &str) {
+    edit_node(name, None, GraphChangeKind::Removed);
+}
+
+pub fn graph_node_names() -> Vec<String> {
+    let mut names: Vec<String> = GRAPH_EDIT.lock().unwrap().nodes.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+// Returns everything which happened since the last call, in order.
+pub fn take_graph_changes() -> Vec<GraphChange> {
+    std::mem::replace(&mut GRAPH_EDIT.lock().unwrap().changes, Vec::new())
+}
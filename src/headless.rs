@@ -0,0 +1,143 @@
+// Compute-only use of the crate: no window, no swapchain, nothing ever presented.
+// Only an instance, a device and a queue get created, and each evaluation is run
+// as a frame of GPU work which is waited on before returning. Compute ops work as usual;
+// raster passes are not supported. Warnings are logged after each evaluation, and kept
+// for `take_warnings`.
+
+use crate::buffer::{read_back_buffer_as, Buffer};
+use crate::host_interop::{read_back_tex, HostImageLayout};
+use crate::texture::Texture;
+use crate::vulkan::*;
+use crate::{
+    background_compute, frame_telemetry, gpu_profiler, gpu_workload, op_metrics, resource_lifetime,
+    shader_ab, shader_cache, shader_compile_queue, texture_history, warnings, workgroup_autotune,
+};
+use ash::vk;
+use snoozy::{get_snapshot, Result, SnoozyRef};
+use tokio::runtime::Runtime;
+
+pub struct HeadlessCompute {
+    rt: Runtime,
+    warnings: Vec<String>,
+}
+
+impl HeadlessCompute {
+    // Only one of `HeadlessCompute` and `Rendertoy` may be created per process.
+    pub fn new(validation: ValidationOptions, device_index: usize) -> Self {
+        initialize_headless_vulkan_backend(validation, device_index);
+
+        Self {
+            rt: Runtime::new().unwrap(),
+            warnings: Vec::new(),
+        }
+    }
+
+    // Warnings logged since the last call, e.g. for tests to check for shader violations.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Evaluates `r`, and waits until the GPU has finished all work it required.
+    pub fn eval<T: Clone + Send + Sync + 'static>(&mut self, r: &SnoozyRef<T>) -> T {
+        let r = r.clone();
+        self.run_frame(move |rt| rt.block_on(Self::get(r)))
+    }
+
+    // Evaluates `buf`, and copies its contents back to the CPU.
+    pub fn read_buffer(&mut self, buf: &SnoozyRef<Buffer>) -> Result<Vec<u8>> {
+        self.read_buffer_as::<u8>(buf)
+    }
+
+    // Like `read_buffer`, but as a `Vec<T>`. The buffer size must be a multiple of `T`'s.
+    pub fn read_buffer_as<T: Copy + Default + Send + 'static>(
+        &mut self,
+        buf: &SnoozyRef<Buffer>,
+    ) -> Result<Vec<T>> {
+        let buf = buf.clone();
+        let contents = self.run_frame(move |rt| {
+            let buf = rt.block_on(Self::get(buf));
+            read_back_buffer_as::<T>(&buf)
+        });
+
+        // The frame has finished by now, so this resolves immediately
+        futures::executor::block_on(contents)
+    }
+
+    // Evaluates `tex`, and copies its contents back to the CPU with tightly packed rows.
+    // `T` needs to evenly divide the texel size, e.g. `f32` for `R32G32B32A32_SFLOAT`.
+    pub fn read_texture<T: Copy + Default + Send + 'static>(
+        &mut self,
+        tex: &SnoozyRef<Texture>,
+    ) -> Result<Vec<T>> {
+        let tex = tex.clone();
+        let contents = self.run_frame(move |rt| {
+            let tex = rt.block_on(Self::get(tex));
+            let layout = HostImageLayout::packed(
+                tex.key.width,
+                tex.key.height,
+                vk::Format::from_raw(tex.key.format),
+            );
+            read_back_tex(&tex, layout)
+        });
+
+        futures::executor::block_on(contents)
+    }
+
+    async fn get<T: Clone + Send + Sync + 'static>(r: SnoozyRef<T>) -> T {
+        let snapshot = get_snapshot(move |f| {
+            tokio::task::spawn(async move {
+                f();
+            });
+        });
+        (*snapshot.get(r).await).clone()
+    }
+
+    fn run_frame<R>(&mut self, f: impl FnOnce(&mut Runtime) -> R) -> R {
+        let fs = with_vk_state_mut(VkBackendState::begin_headless_frame);
+        begin_render_frame(&fs, |_, _, _, _| {});
+
+        let res = f(&mut self.rt);
+
+        end_render_frame(&fs);
+        vk_state().finish_headless_frame();
+
+        gpu_profiler::end_frame();
+        op_metrics::end_frame();
+        shader_ab::end_frame();
+        texture_history::end_frame();
+        frame_telemetry::end_frame();
+        gpu_workload::end_frame();
+        shader_compile_queue::end_frame();
+        workgroup_autotune::end_frame();
+        shader_cache::end_frame();
+        resource_lifetime::end_frame();
+        background_compute::end_frame();
+
+        // Nothing shows them otherwise, and they'd pile up
+        let mut drained = Vec::new();
+        warnings::with_drain_warnings(|warnings| {
+            for warning in warnings.iter() {
+                tracing::warn!("{}", warning);
+            }
+            drained = warnings.clone();
+        });
+        self.warnings.extend(drained);
+
+        res
+    }
+}
+
+// GPU tests share one instance, as only one `HeadlessCompute` may be created per process.
+#[cfg(test)]
+pub(crate) fn with_test_headless_compute<R>(f: impl FnOnce(&mut HeadlessCompute) -> R) -> R {
+    lazy_static! {
+        static ref TEST_HEADLESS_COMPUTE: std::sync::Mutex<HeadlessCompute> =
+            std::sync::Mutex::new(HeadlessCompute::new(ValidationOptions::standard(), 0));
+    }
+
+    // A failed test shouldn't fail the ones after it
+    let mut compute = TEST_HEADLESS_COMPUTE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    f(&mut compute)
+}
@@ -0,0 +1,354 @@
+// Moving images and arrays between the GPU and memory owned by the caller, e.g. `ndarray`
+// arrays or `image` crate buffers, without repacking them into intermediate `Vec`s.
+// Strided rows are described by a `HostImageLayout`, and handled by the copy itself.
+//
+// For a standard-layout `ndarray` of shape (height, width, channels), the row pitch is
+// `strides[0] * size_of::<T>()`, and the data is at `as_slice_memory_order()`. The other
+// way around, readbacks return `Vec`s which `Array::from_shape_vec` and
+// `ImageBuffer::from_raw` take over without copying.
+
+use crate::backend::texture::TextureType;
+use crate::buffer::{upload_array_buffer_impl, Buffer};
+use crate::texture::{upload_tex_impl, Texture};
+use crate::vulkan::*;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use snoozy::*;
+use std::mem::size_of;
+use std::ops::Deref;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HostImageLayout {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    // Bytes from the start of one row to the next; zero for tightly packed rows
+    pub row_pitch_bytes: usize,
+}
+
+impl HostImageLayout {
+    pub fn packed(width: u32, height: u32, format: vk::Format) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            row_pitch_bytes: 0,
+        }
+    }
+
+    fn texel_size_bytes(&self) -> Result<usize> {
+        format_texel_size_bytes(self.format)
+            .ok_or_else(|| format_err!("Unsupported host image format {:?}", self.format))
+    }
+
+    fn row_pitch(&self) -> Result<usize> {
+        let texel_size = self.texel_size_bytes()?;
+        let packed_pitch = self.width as usize * texel_size;
+
+        match self.row_pitch_bytes {
+            0 => Ok(packed_pitch),
+            pitch if pitch < packed_pitch => bail!(
+                "A row pitch of {} bytes is less than the {} bytes in a row",
+                pitch,
+                packed_pitch
+            ),
+            // The copy commands take row lengths in texels
+            pitch if pitch % texel_size != 0 => bail!(
+                "A row pitch of {} bytes is not a multiple of the texel size of {}",
+                pitch,
+                texel_size
+            ),
+            pitch => Ok(pitch),
+        }
+    }
+
+    // Row length in texels, as used by the copy commands
+    fn row_length(&self) -> Result<u32> {
+        Ok((self.row_pitch()? / self.texel_size_bytes()?) as u32)
+    }
+
+    // Bytes spanned by the image, not including any padding after the last row.
+    pub fn size_bytes(&self) -> Result<usize> {
+        if self.width == 0 || self.height == 0 {
+            return Ok(0);
+        }
+
+        Ok(self.row_pitch()? * (self.height as usize - 1)
+            + self.width as usize * self.texel_size_bytes()?)
+    }
+}
+
+// Only uncompressed color formats are supported.
+pub fn format_texel_size_bytes(format: vk::Format) -> Option<usize> {
+    use vk::Format as F;
+
+    match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB => Some(1),
+        F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_UINT | F::R8G8_SINT => Some(2),
+        F::R16_UNORM | F::R16_SNORM | F::R16_UINT | F::R16_SINT | F::R16_SFLOAT => Some(2),
+        F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R8G8B8A8_UINT
+        | F::R8G8B8A8_SINT
+        | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM
+        | F::B8G8R8A8_SRGB
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::B10G11R11_UFLOAT_PACK32
+        | F::E5B9G9R9_UFLOAT_PACK32 => Some(4),
+        F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_UINT | F::R16G16_SINT => Some(4),
+        F::R16G16_SFLOAT | F::R32_UINT | F::R32_SINT | F::R32_SFLOAT => Some(4),
+        F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::R16G16B16A16_UINT
+        | F::R16G16B16A16_SINT
+        | F::R16G16B16A16_SFLOAT => Some(8),
+        F::R32G32_UINT | F::R32G32_SINT | F::R32G32_SFLOAT => Some(8),
+        F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => Some(12),
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * size_of::<T>()) }
+}
+
+// Creates a texture from `data`, laid out as described by `layout`. To be called from ops;
+// for use in graphs, see `upload_host_tex`.
+pub fn upload_tex_from_slice<T: Copy>(data: &[T], layout: &HostImageLayout) -> Result<Texture> {
+    let size_bytes = layout.size_bytes()?;
+    let data = as_bytes(data);
+    if data.len() < size_bytes {
+        bail!(
+            "{} bytes of texel data were provided, but {:?} needs {}",
+            data.len(),
+            layout,
+            size_bytes
+        );
+    }
+
+    upload_tex_impl(
+        &data[..size_bytes],
+        (layout.width, layout.height),
+        layout.row_length()?,
+        layout.format,
+    )
+}
+
+// Like `upload_tex_from_slice`, for memory coming from elsewhere, e.g. across FFI.
+//
+// Safety: `data` must point to at least `layout.size_bytes()` readable bytes.
+pub unsafe fn upload_tex_from_ptr(data: *const u8, layout: &HostImageLayout) -> Result<Texture> {
+    let size_bytes = layout.size_bytes()?;
+    upload_tex_from_slice(std::slice::from_raw_parts(data, size_bytes), layout)
+}
+
+// Like `upload_buffer_from_slice`, for memory coming from elsewhere, e.g. across FFI.
+//
+// Safety: `data` must point to at least `size_bytes` readable bytes.
+pub unsafe fn upload_buffer_from_ptr(
+    data: *const u8,
+    size_bytes: usize,
+    texture_format: Option<vk::Format>,
+) -> Result<Buffer> {
+    upload_buffer_from_slice(std::slice::from_raw_parts(data, size_bytes), texture_format)
+}
+
+pub fn upload_buffer_from_slice<T: Copy + 'static>(
+    data: &[T],
+    texture_format: Option<vk::Format>,
+) -> Result<Buffer> {
+    upload_array_buffer_impl(&&data, texture_format)
+}
+
+// Graph counterpart of `upload_tex_from_slice`. Wrapping e.g. `Array::into_raw_vec()` or
+// `ImageBuffer::into_raw()` in an `Arc` hands it over without copying.
+#[snoozy]
+pub async fn upload_host_tex_snoozy<
+    T: Sized + Copy + 'static,
+    C: Deref<Target = Vec<T>> + Send + Sync + Sized + 'static,
+>(
+    _ctx: Context,
+    contents: &C,
+    layout: &HostImageLayout,
+) -> Result<Texture> {
+    upload_tex_from_slice(contents.as_slice(), layout)
+}
+
+// Copies `tex` back to the CPU, with rows laid out as described by `layout`. Its size
+// and format must match those of the texture, and the row pitch be a multiple of `T`'s size.
+// Padding between rows is left unspecified. Only single-layer 2D textures are supported.
+//
+// As with `read_back_buffer`, the data becomes available once the GPU has finished
+// the frame this was recorded in.
+pub fn read_back_tex<T: Copy + Default + Send + 'static>(
+    tex: &Texture,
+    layout: HostImageLayout,
+) -> impl std::future::Future<Output = Result<Vec<T>>> {
+    let receiver = record_tex_readback::<T>(tex, layout);
+    async move {
+        receiver?
+            .await
+            .map_err(|_| format_err!("Texture readback was cancelled"))
+    }
+}
+
+fn record_tex_readback<T: Copy + Default + Send + 'static>(
+    tex: &Texture,
+    layout: HostImageLayout,
+) -> Result<futures::channel::oneshot::Receiver<Vec<T>>> {
+    let key = tex.key;
+    if key.tex_type != TextureType::Type2D || key.depth != 1 {
+        bail!("Only single-layer 2D textures can be read back");
+    }
+    if (key.width, key.height, key.format) != (layout.width, layout.height, layout.format.as_raw())
+    {
+        bail!("{:?} doesn't match the texture, {:?}", layout, key);
+    }
+    if layout.row_pitch()? % size_of::<T>() != 0 {
+        bail!(
+            "The row pitch of {:?} is not a multiple of {} bytes",
+            layout,
+            size_of::<T>()
+        );
+    }
+
+    record_region_readback(
+        tex,
+        (0, 0),
+        (key.width, key.height),
+        layout.row_length()?,
+        layout.size_bytes()?,
+    )
+}
+
+// Exact contents of a single texel of `tex`, e.g. for pixel probes. Decoding the bytes
+// is up to the caller, as per the format of the texture.
+pub(crate) fn read_back_texel(
+    tex: &Texture,
+    pixel: (u32, u32),
+) -> impl std::future::Future<Output = Result<Vec<u8>>> {
+    let receiver = record_texel_readback(tex, pixel);
+    async move {
+        receiver?
+            .await
+            .map_err(|_| format_err!("Texel readback was cancelled"))
+    }
+}
+
+fn record_texel_readback(
+    tex: &Texture,
+    pixel: (u32, u32),
+) -> Result<futures::channel::oneshot::Receiver<Vec<u8>>> {
+    let key = tex.key;
+    if key.tex_type != TextureType::Type2D || key.depth != 1 {
+        bail!("Only single-layer 2D textures can be read back");
+    }
+    if pixel.0 >= key.width || pixel.1 >= key.height {
+        bail!("Texel {:?} is outside of the texture, {:?}", pixel, key);
+    }
+
+    let format = vk::Format::from_raw(key.format);
+    let texel_size = format_texel_size_bytes(format)
+        .ok_or_else(|| format_err!("Unsupported readback format {:?}", format))?;
+
+    record_region_readback(tex, pixel, (1, 1), 0, texel_size)
+}
+
+// Copies `extent` texels at `offset` into the readback ring, with rows `row_length` texels
+// apart (zero for packed), and sends them over once the frame has finished.
+fn record_region_readback<T: Copy + Default + Send + 'static>(
+    tex: &Texture,
+    offset: (u32, u32),
+    extent: (u32, u32),
+    row_length: u32,
+    size_bytes: usize,
+) -> Result<futures::channel::oneshot::Receiver<Vec<T>>> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    // Keep the source alive until the copy has executed
+    let tex = tex.clone();
+    let sender = std::sync::Mutex::new(Some(sender));
+
+    vk_add_setup_command(move |vk, vk_frame| {
+        let cb = vk_frame.command_buffer.lock().unwrap();
+        let cb: vk::CommandBuffer = cb.cb;
+        let region = vk_frame.readback.allocate(size_bytes);
+
+        unsafe {
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    tex.image,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                    vk_sync::AccessType::TransferRead,
+                ),
+            );
+
+            let buffer_copy_regions = vk::BufferImageCopy::builder()
+                .buffer_offset(region.offset as u64)
+                .buffer_row_length(row_length)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_offset(vk::Offset3D {
+                    x: offset.0 as i32,
+                    y: offset.1 as i32,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width: extent.0,
+                    height: extent.1,
+                    depth: 1,
+                });
+
+            vk.device.cmd_copy_image_to_buffer(
+                cb,
+                tex.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                region.buffer,
+                &[buffer_copy_regions.build()],
+            );
+
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    tex.image,
+                    vk_sync::AccessType::TransferRead,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                ),
+            );
+
+            vk_sync::cmd::pipeline_barrier(
+                vk.device.fp_v1_0(),
+                cb,
+                Some(vk_sync::GlobalBarrier {
+                    previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                    next_accesses: &[vk_sync::AccessType::HostRead],
+                }),
+                &[],
+                &[],
+            );
+        }
+
+        vk_frame
+            .frame_cleanup
+            .lock()
+            .unwrap()
+            .push(Box::new(move |_vk| {
+                let _ = &tex;
+
+                if let Some(sender) = sender.lock().unwrap().take() {
+                    let _ = sender.send(region.read::<T>());
+                }
+            }));
+    });
+
+    Ok(receiver)
+}
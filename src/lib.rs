@@ -15,108 +15,213 @@ extern crate snoozy_macros;
 extern crate abomonation_derive;
 
 mod backend;
+mod background_compute;
 mod blob;
 mod buffer;
+mod buffer_dump;
+mod builtin_textures;
 mod camera;
+mod compare;
+mod composite;
 mod consts;
+mod coord_convention;
+mod cube_map;
+mod deterministic_math;
+mod device_caps;
 mod dot;
+mod dry_run;
+mod edit_preview;
+mod env_map;
+mod external_commands;
+mod frame_budget;
+mod frame_step;
+mod frame_telemetry;
+mod gallery;
+mod gbuffer;
 mod gpu_debugger;
 mod gpu_profiler;
+mod gpu_workload;
+mod graph_edit;
+#[cfg(feature = "window")]
 mod gui;
+mod headless;
+mod host_interop;
+#[cfg(feature = "window")]
 mod keyboard;
+mod light_culling;
+mod material;
 mod math;
 mod mesh;
+mod motion_blur;
+mod net_sync;
+mod occlusion_query;
+mod op_metrics;
+mod op_tags;
+mod output_load_op;
+mod output_warp;
 mod package;
+mod packing;
+mod panorama;
+mod pass_chain;
+mod readback_ring;
+#[cfg(feature = "window")]
+mod render_pass;
+#[cfg(feature = "window")]
 mod renderer;
+#[cfg(feature = "window")]
 mod rendertoy;
+mod resource_desc;
+mod resource_lifetime;
 mod rgb9e5;
+mod sample_sequence;
+mod screenshot;
 mod shader;
+mod shader_ab;
+mod shader_cache;
+mod shader_compile_queue;
+mod shader_hot_swap;
+mod shader_instrumentation;
+mod shader_source;
+mod shadow_map;
+mod spirv_opt;
+mod stereo;
+mod subgraph_process;
 mod texture;
+mod texture_history;
+mod time_control;
+mod uniform_folding;
+mod upsample;
+mod video_capture;
 mod viewport;
 mod vk_backend_state;
 mod vk_render_device;
 mod vulkan;
 mod warnings;
+mod workgroup_autotune;
+#[cfg(feature = "openxr")]
+mod xr;
 
 pub mod compute_tex_macro;
+pub mod prelude;
 
+pub use self::background_compute::set_background_compute_budget_ms;
 pub use self::blob::*;
 pub use self::buffer::*;
+pub use self::buffer_dump::*;
+pub use self::builtin_textures::*;
 pub use self::camera::*;
+pub use self::compare::*;
+pub use self::composite::*;
 pub use self::consts::*;
+pub use self::coord_convention::*;
+pub use self::cube_map::*;
+pub use self::deterministic_math::{
+    is_deterministic_math_enabled, set_deterministic_math_enabled, set_deterministic_rng_seed,
+};
+pub use self::device_caps::*;
+pub use self::dry_run::*;
+pub use self::edit_preview::*;
+pub use self::env_map::*;
+pub use self::external_commands::{
+    add_external_commands, remove_external_commands, ExternalCommandsContext,
+    ExternalCommandsHandle, ExternalCommandsPoint,
+};
+pub use self::frame_budget::*;
+pub use self::frame_step::{
+    is_frame_stepping_enabled, set_frame_step_dt, set_frame_stepping_enabled, step_frame,
+};
+pub use self::frame_telemetry::{
+    finish_frame_telemetry, is_frame_telemetry_finished, start_frame_telemetry,
+};
+pub use self::gallery::{gallery_example_tex, GalleryExample};
+pub use self::gbuffer::*;
+pub use self::gpu_profiler::{
+    gpu_pass_timing_stats, gpu_timing_by_group, gpu_timing_by_tag, load_gpu_timing_baseline,
+    pipeline_creation_stats, save_gpu_timing_baseline, set_gpu_timing_history_len,
+    GpuPassTimingStats, PipelineCreationStats,
+};
+pub use self::gpu_workload::*;
+pub use self::graph_edit::*;
+pub use self::headless::HeadlessCompute;
+pub use self::host_interop::*;
+#[cfg(feature = "window")]
 pub use self::keyboard::*;
+pub use self::light_culling::*;
+pub use self::material::*;
 pub use self::mesh::*;
+pub use self::motion_blur::*;
+pub use self::net_sync::*;
+pub use self::occlusion_query::{occlusion_query, occlusion_query_samples, OcclusionQuery};
+pub use self::op_metrics::{op_metric_stats, report_op_metric, reset_op_metrics, OpMetricStats};
+pub use self::op_tags::{op_tags, pass_group, OpTags};
+pub use self::output_load_op::{reset_persistent_outputs, OutputLoadOp};
+pub use self::output_warp::*;
+pub use self::package::set_asset_namespace_override;
+pub use self::packing::*;
+pub use self::panorama::*;
+pub use self::pass_chain::*;
+#[cfg(feature = "window")]
+pub use self::render_pass::*;
+#[cfg(feature = "window")]
 pub use self::rendertoy::*;
+pub use self::resource_desc::*;
+pub use self::resource_lifetime::{save_resource_lifetime_trace, set_resource_lifetime_capture};
 pub use self::rgb9e5::*;
+pub use self::sample_sequence::*;
+pub use self::screenshot::{request_screenshot, set_screenshot_dir, set_screenshot_format};
 pub use self::shader::*;
+pub use self::shader_ab::{
+    ab_compute_tex, reset_shader_ab_test, shader_ab_results, ShaderAbMode, ShaderAbResult,
+    ShaderAbVariantStats,
+};
+pub use self::shader_cache::{purge_shader_caches, set_shader_cache_dir};
+pub use self::shader_compile_queue::{
+    set_shader_compile_concurrency, set_shader_recompile_settle_ms, shader_compile_progress,
+    ShaderCompileProgress,
+};
+pub use self::shader_hot_swap::{is_shader_hot_swap_enabled, set_shader_hot_swap_enabled};
+pub use self::shader_instrumentation::{
+    is_shader_instrumentation_enabled, set_shader_instrumentation_enabled, set_shader_robustness,
+};
+pub use self::shader_source::{
+    enable_magenta_shader_fallbacks, get_shader_asset_override, get_shader_diagnostics,
+    get_shader_source, get_shader_source_chunks, get_shader_source_override, loaded_shader_sources,
+    revert_shader_asset_override, set_fallback_compute_shader, set_fallback_pixel_shader,
+    set_shader_asset_override, set_shader_source_override, ShaderDiagnostic, ShaderSourceChunk,
+};
+pub use self::shadow_map::*;
+pub use self::spirv_opt::{
+    set_default_spirv_opt_preset, set_spirv_opt_path, set_spirv_opt_preset,
+    take_shader_compile_events, ShaderCompileEvent, SpirvOptPreset,
+};
+pub use self::stereo::*;
+pub use self::subgraph_process::{
+    is_subgraph_process, run_subgraph_process, start_subgraph_process, stop_subgraph_process,
+    subgraph_process_tex, SubgraphFrame,
+};
 pub use self::texture::*;
+pub use self::texture_history::{history_tex, keep_history_tex, reset_texture_history};
+pub use self::time_control::*;
+pub use self::uniform_folding::{
+    is_uniform_folding_enabled, set_uniform_folding_enabled, set_uniform_folding_frames,
+};
+pub use self::upsample::*;
+pub use self::video_capture::{
+    is_video_capture_active, set_ffmpeg_path, start_video_capture, stop_video_capture, VideoCodec,
+};
 pub use self::viewport::*;
+pub use self::vk_render_device::ValidationOptions;
+pub use self::workgroup_autotune::{
+    set_workgroup_autotune_cache_path, set_workgroup_autotune_candidates,
+    set_workgroup_autotune_sample_count, workgroup_autotune_choice,
+};
 pub use ash::{vk, vk::Format};
 pub use math::*;
 pub use snoozy::*;
+pub use vk_sync;
 pub use warnings::rtoy_show_warning;
+#[cfg(feature = "openxr")]
+pub use xr::{xr_eye_matrices, xr_eye_resolution};
 
 #[global_allocator]
 static ALLOC: rpmalloc::RpMalloc = rpmalloc::RpMalloc;
-
-pub trait RenderPass: Any {
-    fn prepare_frame(
-        &mut self,
-        view_constants: &ViewConstants,
-        frame_state: &FrameState,
-        frame_idx: u32,
-    );
-}
-
-use std::any::Any;
-pub trait AsAny {
-    fn as_any(&self) -> &dyn Any;
-}
-
-impl<T> AsAny for T
-where
-    T: RenderPass,
-{
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-}
-
-// Blanket-implement a trait combining RenderPass and AsAny
-// for anything that implements RenderPass + AsAny.
-pub trait RenderPassAny: RenderPass + AsAny {}
-impl<T> RenderPassAny for T where T: RenderPass + AsAny {}
-
-impl<F> RenderPass for F
-where
-    F: FnMut(&ViewConstants, &FrameState, u32),
-    F: 'static,
-{
-    fn prepare_frame(
-        &mut self,
-        view_constants: &ViewConstants,
-        frame_state: &FrameState,
-        frame_idx: u32,
-    ) {
-        (*self)(view_constants, frame_state, frame_idx)
-    }
-}
-
-pub type RenderPassList = Vec<Box<dyn RenderPassAny>>;
-
-// Convenient .add method which adds a trait object to the render pass list,
-// but returns a borrow of the concrete type just added. Supports the following pattern:
-//
-// let ao_tex = sub_passes
-//     .add(rtoy_samples::ssao::Ssao::new(tex_key, gbuffer_tex.clone()))
-//     .get_output_tex();
-pub trait AddRenderPass {
-    fn add<P: RenderPassAny + 'static>(&mut self, pass: P) -> &P;
-}
-
-impl AddRenderPass for RenderPassList {
-    fn add<P: RenderPassAny + 'static>(&mut self, pass: P) -> &P {
-        self.push(Box::new(pass));
-        self.last().unwrap().as_any().downcast_ref::<P>().unwrap()
-    }
-}
@@ -0,0 +1,211 @@
+// Binning of point lights into screen tiles or view space clusters, for forward+ and
+// clustered shading:
+//
+//   let lights = upload_point_lights(&lights);
+//   let grid = light_grid(lights, light_count, view_constants, width, height,
+//       LightGridDesc::clustered(16, 24, 0.1, 500.0));
+//   let lit = compute_tex(key, load_cs(asset!("shaders/shading.glsl")), shader_uniforms!(
+//       :grid.uniforms(),
+//   ));
+//
+// Shaders include `shaders/light_grid.inc` to look up the lights affecting a pixel. Each
+// cell is a tile of `tile_size` pixels, and one of `depth_slices` exponentially distributed
+// slices of view distance; tiled culling is the same with a single slice. Cells keep
+// up to `max_lights_per_cell` lights, and drop the rest.
+//
+// Only perspective projections are supported.
+
+use crate::buffer::{upload_array_buffer, Buffer, BufferKey};
+use crate::math::Vec3;
+use crate::shader::{compute_buf, load_cs_from_string, ShaderUniformBundle, ShaderUniformHolder};
+use crate::shader_uniforms;
+use snoozy::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+    // Lights have no effect beyond this distance
+    pub radius: f32,
+    pub color: Vec3,
+}
+
+// Must match `PointLight` in `light_grid.inc`
+#[derive(Clone, Copy, Abomonation, Serialize)]
+#[repr(C)]
+struct GpuPointLight {
+    position_radius: [f32; 4],
+    color: [f32; 4],
+}
+
+// World space lights for `light_grid`.
+pub fn upload_point_lights(lights: &[PointLight]) -> SnoozyRef<Buffer> {
+    let lights: Vec<GpuPointLight> = lights
+        .iter()
+        .map(|l| GpuPointLight {
+            position_radius: [l.position.x(), l.position.y(), l.position.z(), l.radius],
+            color: [l.color.x(), l.color.y(), l.color.z(), 0.0],
+        })
+        .collect();
+
+    upload_array_buffer(Box::new(lights))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Abomonation)]
+pub struct LightGridDesc {
+    pub tile_size: u32,
+    pub depth_slices: u32,
+    // Bounds of the slices. Lights closer than `near_dist` go to the first slice,
+    // and those beyond `far_dist` to the last one.
+    pub near_dist: f32,
+    pub far_dist: f32,
+    pub max_lights_per_cell: u32,
+}
+
+impl LightGridDesc {
+    pub fn tiled(tile_size: u32) -> Self {
+        Self {
+            tile_size,
+            depth_slices: 1,
+            near_dist: 1.0,
+            far_dist: 1.0,
+            max_lights_per_cell: 64,
+        }
+    }
+
+    pub fn clustered(tile_size: u32, depth_slices: u32, near_dist: f32, far_dist: f32) -> Self {
+        Self {
+            tile_size,
+            depth_slices,
+            near_dist,
+            far_dist,
+            max_lights_per_cell: 32,
+        }
+    }
+
+    pub fn with_max_lights_per_cell(mut self, v: u32) -> Self {
+        self.max_lights_per_cell = v;
+        self
+    }
+
+    fn tile_counts(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            (width + self.tile_size - 1) / self.tile_size,
+            (height + self.tile_size - 1) / self.tile_size,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct LightGrid {
+    pub desc: LightGridDesc,
+    pub width: u32,
+    pub height: u32,
+    pub lights: SnoozyRef<Buffer>,
+    pub light_count: u32,
+    // Per cell, the light count, followed by `max_lights_per_cell` light indices
+    pub cells: SnoozyRef<Buffer>,
+}
+
+impl LightGrid {
+    // Named as expected by `light_grid.inc`.
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        let mut uniforms = grid_uniforms(&self.desc, self.width, self.height, self.light_count);
+        uniforms.extend(shader_uniforms!(
+            lights: self.lights.clone(),
+            cells: self.cells.clone(),
+        ));
+
+        shader_uniforms!(light_grid: uniforms)
+    }
+}
+
+fn grid_uniforms(
+    desc: &LightGridDesc,
+    width: u32,
+    height: u32,
+    light_count: u32,
+) -> ShaderUniformBundle {
+    let (tile_count_x, tile_count_y) = desc.tile_counts(width, height);
+
+    shader_uniforms!(
+        light_count: light_count,
+        tile_size: desc.tile_size,
+        tile_count_x: tile_count_x,
+        tile_count_y: tile_count_y,
+        depth_slices: desc.depth_slices,
+        max_lights_per_cell: desc.max_lights_per_cell,
+        near_dist: desc.near_dist,
+        far_dist: desc.far_dist,
+        output_size: (width as f32, height as f32),
+    )
+}
+
+// Bins `light_count` lights from `upload_point_lights` for a `width` x `height` view.
+// `view_constants` is a `ViewConstants` buffer.
+pub fn light_grid(
+    lights: SnoozyRef<Buffer>,
+    light_count: u32,
+    view_constants: SnoozyRef<Buffer>,
+    width: u32,
+    height: u32,
+    desc: LightGridDesc,
+) -> LightGrid {
+    LightGrid {
+        desc,
+        width,
+        height,
+        lights: lights.clone(),
+        light_count,
+        cells: bin_lights(lights, light_count, view_constants, width, height, desc),
+    }
+}
+
+#[snoozy]
+pub async fn bin_lights_snoozy(
+    mut ctx: Context,
+    lights: &SnoozyRef<Buffer>,
+    light_count: &u32,
+    view_constants: &SnoozyRef<Buffer>,
+    width: &u32,
+    height: &u32,
+    desc: &LightGridDesc,
+) -> Result<Buffer> {
+    if desc.tile_size == 0 || desc.depth_slices == 0 {
+        bail!("Light grids need a non-zero tile size and slice count");
+    }
+    if desc.depth_slices > 1 && !(desc.near_dist > 0.0 && desc.far_dist > desc.near_dist) {
+        bail!(
+            "Invalid light grid slice range: {} to {}",
+            desc.near_dist,
+            desc.far_dist
+        );
+    }
+
+    let (tile_count_x, tile_count_y) = desc.tile_counts(*width, *height);
+    let cell_count = tile_count_x * tile_count_y * desc.depth_slices;
+    let cell_size = (desc.max_lights_per_cell + 1) as usize * std::mem::size_of::<u32>();
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/bin_lights.glsl").to_owned(),
+        "bin_lights.glsl".to_owned(),
+    );
+
+    // Bound like `LightGrid::uniforms`, as the shader uses `light_grid.inc` too
+    let mut grid = grid_uniforms(desc, *width, *height, *light_count);
+    grid.extend(shader_uniforms!(lights: lights.clone()));
+    let uniforms = shader_uniforms!(
+        light_grid: grid,
+        view_constants: view_constants.clone(),
+    );
+
+    let buf = ctx
+        .get(compute_buf(
+            BufferKey::new(cell_count as usize * cell_size, None),
+            [tile_count_x, tile_count_y, desc.depth_slices],
+            cs,
+            uniforms,
+        ))
+        .await?;
+
+    Ok((*buf).clone())
+}
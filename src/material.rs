@@ -0,0 +1,294 @@
+// Materials: named textures and scalar parameters, bound together as one bundle instead
+// of by hand for every mesh. Either built in code, or loaded from a text asset:
+//
+//   # bricks.mat; texture paths are relative to the file
+//   texture albedo bricks_albedo.png srgb
+//   texture normal bricks_normal.png
+//   texture mask solid 255 255 255 255
+//   float roughness 0.6
+//   vec3 tint 1.0 0.9 0.8
+//
+//   let scene = shader_uniform_bundle!(
+//       :material_uniforms(load_material(asset!("materials/bricks.mat"))),
+//       :upload_raster_scene(&[(mesh, Vec3::zero(), Quat::identity())]),
+//   );
+//
+// Textures are bound as `material_<name>`. Parameters are packed in declaration order
+// with std430 rules, into a storage buffer matching:
+//
+//   layout(std430) readonly buffer material_params {
+//       float roughness;
+//       vec3 tint;
+//   };
+//
+// Uniforms persist for nested scopes, so a material applies to all the draws inside
+// of its scope.
+
+use crate::blob::{load_blob, AssetPath};
+use crate::buffer::upload_array_buffer;
+use crate::mesh::MeshMaterialMap;
+use crate::shader::{ShaderUniformBundle, ShaderUniformHolder};
+use crate::texture::{load_tex_with_params, make_placeholder_rgba8_tex, TexGamma, TexParams};
+use relative_path::RelativePathBuf;
+use snoozy::*;
+
+#[derive(Clone, Copy, Debug, Abomonation)]
+pub enum MaterialParam {
+    Float(f32),
+    Uint(u32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+impl MaterialParam {
+    // Alignment and size in std430
+    fn layout(&self) -> (usize, usize) {
+        match self {
+            MaterialParam::Float(_) | MaterialParam::Uint(_) => (4, 4),
+            MaterialParam::Vec2(_) => (8, 8),
+            MaterialParam::Vec3(_) => (16, 12),
+            MaterialParam::Vec4(_) => (16, 16),
+        }
+    }
+
+    fn words(&self) -> Vec<u32> {
+        match self {
+            MaterialParam::Float(v) => vec![v.to_bits()],
+            MaterialParam::Uint(v) => vec![*v],
+            MaterialParam::Vec2(v) => v.iter().map(|v| v.to_bits()).collect(),
+            MaterialParam::Vec3(v) => v.iter().map(|v| v.to_bits()).collect(),
+            MaterialParam::Vec4(v) => v.iter().map(|v| v.to_bits()).collect(),
+        }
+    }
+}
+
+impl From<f32> for MaterialParam {
+    fn from(v: f32) -> Self {
+        MaterialParam::Float(v)
+    }
+}
+
+impl From<u32> for MaterialParam {
+    fn from(v: u32) -> Self {
+        MaterialParam::Uint(v)
+    }
+}
+
+impl From<[f32; 2]> for MaterialParam {
+    fn from(v: [f32; 2]) -> Self {
+        MaterialParam::Vec2(v)
+    }
+}
+
+impl From<[f32; 3]> for MaterialParam {
+    fn from(v: [f32; 3]) -> Self {
+        MaterialParam::Vec3(v)
+    }
+}
+
+impl From<[f32; 4]> for MaterialParam {
+    fn from(v: [f32; 4]) -> Self {
+        MaterialParam::Vec4(v)
+    }
+}
+
+#[derive(Clone, Default, Abomonation)]
+pub struct Material {
+    // In declaration order, which is also the order of the packed parameters
+    pub params: Vec<(String, MaterialParam)>,
+    pub textures: Vec<(String, MeshMaterialMap)>,
+}
+
+impl Material {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces any parameter of the same name, keeping its position.
+    pub fn with_param<T: Into<MaterialParam>>(mut self, name: &str, value: T) -> Self {
+        let value = value.into();
+        match self.params.iter().position(|(n, _)| n == name) {
+            Some(i) => self.params[i].1 = value,
+            None => self.params.push((name.to_owned(), value)),
+        }
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str, map: MeshMaterialMap) -> Self {
+        match self.textures.iter().position(|(n, _)| n == name) {
+            Some(i) => self.textures[i].1 = map,
+            None => self.textures.push((name.to_owned(), map)),
+        }
+        self
+    }
+
+    // Parameters laid out as `material_params` expects them.
+    pub fn packed_params(&self) -> Vec<u32> {
+        let mut words: Vec<u32> = Vec::new();
+        let mut struct_align = 4;
+
+        for (_, param) in self.params.iter() {
+            let (align, _) = param.layout();
+            struct_align = struct_align.max(align);
+
+            while words.len() * 4 % align != 0 {
+                words.push(0);
+            }
+            words.extend(param.words());
+        }
+
+        // Round up to the struct alignment; empty buffers can't be bound
+        while words.is_empty() || words.len() * 4 % struct_align != 0 {
+            words.push(0);
+        }
+
+        words
+    }
+
+    // Named as described at the top of `material.rs`.
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        let mut uniforms = vec![ShaderUniformHolder::new(
+            "params",
+            upload_array_buffer(Box::new(self.packed_params())),
+        )];
+
+        for (name, map) in self.textures.iter() {
+            let tex = match map {
+                MeshMaterialMap::Asset { path, params } => {
+                    load_tex_with_params(path.clone(), params.clone())
+                }
+                MeshMaterialMap::Placeholder(texel_value) => {
+                    make_placeholder_rgba8_tex(*texel_value)
+                }
+            };
+            uniforms.push(ShaderUniformHolder::new(name, tex));
+        }
+
+        vec![ShaderUniformHolder::new("material", uniforms)]
+    }
+}
+
+#[snoozy]
+pub async fn material_uniforms_snoozy(
+    mut ctx: Context,
+    material: &SnoozyRef<Material>,
+) -> Result<ShaderUniformBundle> {
+    Ok(ctx.get(material).await?.uniforms())
+}
+
+#[snoozy]
+pub async fn load_material_snoozy(mut ctx: Context, path: &AssetPath) -> Result<Material> {
+    let blob = ctx.get(load_blob(path.clone())).await?;
+    let text = String::from_utf8(blob.contents.clone())?;
+
+    parse_material(&text, path).map_err(|err| format_err!("{}: {}", path, err))
+}
+
+fn parse_material(text: &str, path: &AssetPath) -> Result<Material> {
+    let mut material = Material::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 3 {
+            bail!("line {}: expected `<type> <name> <value>`", line_idx + 1);
+        }
+
+        let (kind, name, values) = (tokens[0], tokens[1], &tokens[2..]);
+        let floats = || -> Result<Vec<f32>> {
+            values
+                .iter()
+                .map(|v| {
+                    v.parse::<f32>()
+                        .map_err(|_| format_err!("line {}: invalid number {}", line_idx + 1, v))
+                })
+                .collect()
+        };
+        let expect_count = |count: usize| -> Result<()> {
+            if values.len() != count {
+                bail!(
+                    "line {}: {} takes {} values, got {}",
+                    line_idx + 1,
+                    kind,
+                    count,
+                    values.len()
+                );
+            }
+            Ok(())
+        };
+
+        material = match kind {
+            "float" => {
+                expect_count(1)?;
+                material.with_param(name, floats()?[0])
+            }
+            "uint" => {
+                expect_count(1)?;
+                let v = values[0].parse::<u32>().map_err(|_| {
+                    format_err!("line {}: invalid uint {}", line_idx + 1, values[0])
+                })?;
+                material.with_param(name, v)
+            }
+            "vec2" => {
+                expect_count(2)?;
+                let v = floats()?;
+                material.with_param(name, [v[0], v[1]])
+            }
+            "vec3" => {
+                expect_count(3)?;
+                let v = floats()?;
+                material.with_param(name, [v[0], v[1], v[2]])
+            }
+            "vec4" => {
+                expect_count(4)?;
+                let v = floats()?;
+                material.with_param(name, [v[0], v[1], v[2], v[3]])
+            }
+            "texture" => material.with_texture(name, parse_texture(values, path, line_idx)?),
+            _ => bail!("line {}: unknown parameter type {}", line_idx + 1, kind),
+        };
+    }
+
+    Ok(material)
+}
+
+// `<path> [srgb|linear]`, or `solid <r> <g> <b> <a>` for a 1x1 RGBA8 texture
+fn parse_texture(values: &[&str], path: &AssetPath, line_idx: usize) -> Result<MeshMaterialMap> {
+    if values[0] == "solid" {
+        if values.len() != 5 {
+            bail!("line {}: solid textures take 4 values", line_idx + 1);
+        }
+
+        let mut texel_value = [0u8; 4];
+        for (dst, v) in texel_value.iter_mut().zip(values[1..].iter()) {
+            *dst = v
+                .parse::<u8>()
+                .map_err(|_| format_err!("line {}: invalid texel value {}", line_idx + 1, v))?;
+        }
+
+        return Ok(MeshMaterialMap::Placeholder(texel_value));
+    }
+
+    let gamma = match values.get(1) {
+        None | Some(&"linear") => TexGamma::Linear,
+        Some(&"srgb") => TexGamma::Srgb,
+        Some(other) => bail!("line {}: unknown texture gamma {}", line_idx + 1, other),
+    };
+
+    let mut folder: RelativePathBuf = path.asset_name.clone().into();
+    folder.pop();
+
+    Ok(MeshMaterialMap::Asset {
+        path: AssetPath {
+            crate_name: path.crate_name.clone(),
+            asset_name: folder.join(values[0]).as_str().to_string(),
+            identity: None,
+        },
+        params: TexParams { gamma },
+    })
+}
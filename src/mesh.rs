@@ -59,6 +59,7 @@ fn load_gltf_material(
         AssetPath {
             crate_name: parent_path.crate_name.clone(),
             asset_name: asset_name.to_string_lossy().to_string(),
+            identity: None,
         }
     };
 
@@ -251,6 +252,8 @@ fn pack_unit_direction_11_10_11(x: f32, y: f32, z: f32) -> u32 {
 #[derive(Clone, Abomonation)]
 pub struct RasterGpuMesh {
     verts: Vec<RasterGpuVertex>,
+    // Unpacked, for vertex input; see `VertexLayout::raster_mesh`
+    normals: Vec<[f32; 3]>,
     uvs: Vec<[f32; 2]>,
     tangents: Vec<[f32; 4]>,
     colors: Vec<[f32; 4]>,
@@ -280,6 +283,7 @@ pub async fn make_raster_mesh_snoozy(
 
     Ok(RasterGpuMesh {
         verts,
+        normals: mesh.normals.clone(),
         uvs: mesh.uvs.clone(),
         tangents: mesh.tangents.clone(),
         colors: mesh.colors.clone(),
@@ -330,6 +334,7 @@ pub async fn upload_raster_mesh_snoozy(
     let mesh = ctx.get(mesh).await?;
 
     let verts = ArcView::new(&mesh, |m| &m.verts);
+    let normals = ArcView::new(&mesh, |m| &m.normals);
     let uvs = ArcView::new(&mesh, |m| &m.uvs);
     let colors = ArcView::new(&mesh, |m| &m.colors);
     let tangents = ArcView::new(&mesh, |m| &m.tangents);
@@ -373,6 +378,7 @@ pub async fn upload_raster_mesh_snoozy(
 
     Ok(shader_uniforms!(
         mesh_vertex_buf: upload_array_buffer(verts),
+        mesh_normal_buf: upload_array_buffer(normals),
         mesh_uv_buf: upload_array_buffer(uvs),
         mesh_color_buf: upload_array_buffer(colors),
         mesh_tangent_buf: upload_array_buffer(tangents),
@@ -405,6 +411,38 @@ pub fn upload_raster_scene(scene: &[(SnoozyRef<TriangleMesh>, Vec3, Quat)]) -> S
         .collect()
 }
 
+// Draws `mesh` once per instance, in a single instanced draw. Each instance's model to world
+// matrix is in `mesh_instance_transform_buf`, indexed by `gl_InstanceIndex`, or read as
+// a per-instance `mat4` via `VertexLayout::with_instance_buffer`.
+pub fn instanced_raster_mesh(
+    mesh: SnoozyRef<TriangleMesh>,
+    instances: &[(Vec3, Quat)],
+) -> ShaderUniformBundle {
+    let transforms: Vec<Mat4> = instances
+        .iter()
+        .map(|(position, rotation)| Mat4::from_translation(*position) * Mat4::from_quat(*rotation))
+        .collect();
+
+    shader_uniforms!(
+        mesh_instance_count: instances.len() as u32,
+        mesh_instance_transform_buf: upload_array_buffer(Box::new(transforms)),
+        :upload_raster_mesh(make_raster_mesh(mesh))
+    )
+}
+
+// Draws `mesh` with the index and instance counts in `indirect_buf`, a single
+// `VkDrawIndexedIndirectCommand`, e.g. as written by a GPU culling pass. The index
+// and vertex offsets in the command are relative to the mesh's own buffers.
+pub fn indirect_raster_mesh(
+    mesh: SnoozyRef<TriangleMesh>,
+    indirect_buf: SnoozyRef<Buffer>,
+) -> ShaderUniformBundle {
+    shader_uniforms!(
+        mesh_indirect_buf: indirect_buf,
+        :upload_raster_mesh(make_raster_mesh(mesh))
+    )
+}
+
 #[snoozy]
 pub async fn upload_dynamic_raster_scene_snoozy(
     mut _ctx: Context,
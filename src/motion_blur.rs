@@ -0,0 +1,77 @@
+// Reconstruction filter motion blur. Velocities are screen-space UV offsets from
+// the previous frame to the current one, as written by the raster path or an
+// optical flow estimate.
+
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// Also the maximum blur radius in pixels
+const MOTION_BLUR_TILE_SIZE: u32 = 16;
+const MOTION_BLUR_SAMPLE_COUNT: u32 = 15;
+
+// `shutter_fraction` is the portion of the frame interval during which the shutter
+// is open, e.g. `camera.shutter_time * fps`; 0.5 for a 180 degree shutter.
+#[snoozy]
+pub async fn motion_blur_tex_snoozy(
+    mut ctx: Context,
+    color: &SnoozyRef<Texture>,
+    velocity: &SnoozyRef<Texture>,
+    shutter_fraction: &f32,
+) -> Result<Texture> {
+    let key = ctx
+        .get(color)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let tile_key = TextureKey::new(
+        (key.width + MOTION_BLUR_TILE_SIZE - 1) / MOTION_BLUR_TILE_SIZE,
+        (key.height + MOTION_BLUR_TILE_SIZE - 1) / MOTION_BLUR_TILE_SIZE,
+        vk::Format::R16G16_SFLOAT,
+    );
+
+    let tile_max = compute_tex(
+        tile_key,
+        load_cs_from_string(
+            include_str!("../assets/shaders/motion_blur_tile_max.glsl").to_owned(),
+            "motion_blur_tile_max.glsl".to_owned(),
+        ),
+        shader_uniforms!(
+            velocityTex: velocity.clone(),
+            shutter_fraction: *shutter_fraction,
+            tile_size: MOTION_BLUR_TILE_SIZE,
+        ),
+    );
+
+    let neighbor_max = compute_tex(
+        tile_key,
+        load_cs_from_string(
+            include_str!("../assets/shaders/motion_blur_neighbor_max.glsl").to_owned(),
+            "motion_blur_neighbor_max.glsl".to_owned(),
+        ),
+        shader_uniforms!(tileMaxTex: tile_max),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            load_cs_from_string(
+                include_str!("../assets/shaders/motion_blur_gather.glsl").to_owned(),
+                "motion_blur_gather.glsl".to_owned(),
+            ),
+            shader_uniforms!(
+                inputTex: color.clone(),
+                velocityTex: velocity.clone(),
+                neighborMaxTex: neighbor_max,
+                shutter_fraction: *shutter_fraction,
+                tile_size: MOTION_BLUR_TILE_SIZE,
+                sample_count: MOTION_BLUR_SAMPLE_COUNT,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
@@ -0,0 +1,221 @@
+// Keeps tweakable values and time in sync across machines, e.g. for installations driving
+// several projectors from separate PCs.
+//
+// One master periodically sends its full state to every client over UDP. Each datagram
+// is a complete snapshot, so a lost packet is simply superseded by the next one.
+//
+// Clients play back at the master's time, including its pauses, speed and scrubbing,
+// rather than advancing their own clock.
+
+use snoozy::*;
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SEND_INTERVAL: Duration = Duration::from_millis(8);
+
+// Clock corrections smaller than this are blended in, larger ones are applied immediately.
+const MAX_CLOCK_SLEW_SECONDS: f64 = 0.05;
+
+#[derive(Serialize, Deserialize)]
+struct SyncPacket {
+    time: f64,
+    // How fast `time` advances per second; 0 while paused
+    time_rate: f32,
+    tweaks: Vec<(String, f32)>,
+}
+
+// Playback time as of a local time, advancing at a rate from there
+#[derive(Clone, Copy)]
+struct ClockSample {
+    time: f64,
+    local_time: f64,
+    rate: f32,
+}
+
+impl ClockSample {
+    fn time_at(&self, local_time: f64) -> f64 {
+        self.time + (local_time - self.local_time) * self.rate as f64
+    }
+}
+
+struct Tweak {
+    value: f32,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+struct NetSyncState {
+    start_instant: Instant,
+    // This machine's playback time, as of its last frame
+    local_clock: ClockSample,
+    // The master's playback time, once a client has heard from it
+    synced_clock: Option<ClockSample>,
+    tweaks: HashMap<String, Tweak>,
+}
+
+lazy_static! {
+    static ref NET_SYNC: Mutex<NetSyncState> = Mutex::new(NetSyncState {
+        start_instant: Instant::now(),
+        local_clock: ClockSample {
+            time: 0.0,
+            local_time: 0.0,
+            rate: 0.0,
+        },
+        synced_clock: None,
+        tweaks: HashMap::new(),
+    });
+}
+
+impl NetSyncState {
+    fn local_time(&self) -> f64 {
+        self.start_instant.elapsed().as_secs_f64()
+    }
+
+    fn set_tweak(&mut self, name: &str, value: f32) -> Vec<Box<dyn Fn() + Send + Sync>> {
+        let tweak = self.tweaks.entry(name.to_owned()).or_insert_with(|| Tweak {
+            value,
+            invalidation_triggers: Vec::new(),
+        });
+
+        if tweak.value == value {
+            return Vec::new();
+        }
+
+        tweak.value = value;
+        std::mem::replace(&mut tweak.invalidation_triggers, Vec::new())
+    }
+}
+
+// A named value which can be changed at runtime via `set_tweak_f32`, and which
+// follows the master's value on synchronized clients.
+#[snoozy]
+pub async fn tweak_f32_snoozy(ctx: Context, name: &String, default: &f32) -> Result<f32> {
+    let mut state = NET_SYNC.lock().unwrap();
+    let tweak = state.tweaks.entry(name.clone()).or_insert_with(|| Tweak {
+        value: *default,
+        invalidation_triggers: Vec::new(),
+    });
+
+    tweak
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+
+    Ok(tweak.value)
+}
+
+pub fn set_tweak_f32(name: &str, value: f32) {
+    let triggers = NET_SYNC.lock().unwrap().set_tweak(name, value);
+    if !triggers.is_empty() {
+        crate::edit_preview::notify_edit();
+    }
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// The master's playback time in seconds, on clients which have received it.
+pub fn net_sync_time() -> Option<f64> {
+    let state = NET_SYNC.lock().unwrap();
+    let local_time = state.local_time();
+    state.synced_clock.map(|clock| clock.time_at(local_time))
+}
+
+// Called once per frame with the playback time, and how fast it currently advances.
+pub(crate) fn publish_playback_time(time: f64, rate: f32) {
+    let mut state = NET_SYNC.lock().unwrap();
+    state.local_clock = ClockSample {
+        time,
+        local_time: state.local_time(),
+        rate,
+    };
+}
+
+// Starts sending state from this machine to `clients`.
+pub fn start_net_sync_master(bind_addr: SocketAddr, clients: Vec<SocketAddr>) -> Result<()> {
+    let socket = UdpSocket::bind(bind_addr)?;
+
+    std::thread::spawn(move || loop {
+        let packet = {
+            let state = NET_SYNC.lock().unwrap();
+            SyncPacket {
+                time: state.local_clock.time_at(state.local_time()),
+                time_rate: state.local_clock.rate,
+                tweaks: state
+                    .tweaks
+                    .iter()
+                    .map(|(name, tweak)| (name.clone(), tweak.value))
+                    .collect(),
+            }
+        };
+
+        let packet = bincode::serialize(&packet).expect("bincode::serialize");
+        for client in clients.iter() {
+            if let Err(err) = socket.send_to(&packet, client) {
+                tracing::warn!("Failed to send sync packet to {}: {:?}", client, err);
+            }
+        }
+
+        std::thread::sleep(SEND_INTERVAL);
+    });
+
+    Ok(())
+}
+
+// Starts following the state sent by a master to `bind_addr`.
+pub fn start_net_sync_client(bind_addr: SocketAddr) -> Result<()> {
+    let socket = UdpSocket::bind(bind_addr)?;
+
+    std::thread::spawn(move || {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let size = match socket.recv_from(&mut buf) {
+                Ok((size, _)) => size,
+                Err(err) => {
+                    tracing::warn!("Failed to receive sync packet: {:?}", err);
+                    continue;
+                }
+            };
+
+            let packet: SyncPacket = match bincode::deserialize(&buf[..size]) {
+                Ok(packet) => packet,
+                Err(err) => {
+                    tracing::warn!("Malformed sync packet: {:?}", err);
+                    continue;
+                }
+            };
+
+            let mut triggers = Vec::new();
+            {
+                let mut state = NET_SYNC.lock().unwrap();
+
+                let local_time = state.local_time();
+                let mut clock = ClockSample {
+                    time: packet.time,
+                    local_time,
+                    rate: packet.time_rate,
+                };
+
+                // Pauses, speed changes and scrubbing are followed immediately
+                if let Some(prev) = state.synced_clock {
+                    let predicted = prev.time_at(local_time);
+                    let error = packet.time - predicted;
+                    if prev.rate == packet.time_rate && error.abs() <= MAX_CLOCK_SLEW_SECONDS {
+                        clock.time = predicted + error * 0.1;
+                    }
+                }
+                state.synced_clock = Some(clock);
+
+                for (name, value) in packet.tweaks.iter() {
+                    triggers.extend(state.set_tweak(name, *value));
+                }
+            }
+
+            for trigger in triggers {
+                trigger();
+            }
+        }
+    });
+
+    Ok(())
+}
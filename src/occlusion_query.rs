@@ -0,0 +1,221 @@
+// Occlusion queries around raster draws. Meshes drawn within a scope which has
+// `occlusion_query("name")` among its uniforms, or within bundles nested in it, are
+// wrapped in queries, e.g.
+//
+//   shader_uniforms!(
+//       : upload_raster_mesh(make_raster_mesh(mesh)),
+//       : occlusion_query("probe"),
+//   )
+//
+// Results are read back once the frame's fence is signaled, and published through
+// `occlusion_query_samples`, which gets invalidated whenever they change. Draws sharing
+// a name are summed up. Queries aren't precise: any non-zero count means visible.
+
+use crate::vulkan::vk;
+use ash::version::DeviceV1_0;
+use ash::{vk, Device};
+use snoozy::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OcclusionQuery(pub String);
+
+pub fn occlusion_query(name: &str) -> OcclusionQuery {
+    OcclusionQuery(name.to_owned())
+}
+
+// Samples which passed the depth test in the last frame to have finished executing.
+// Zero until the first result for `name` comes in.
+#[snoozy]
+pub async fn occlusion_query_samples_snoozy(ctx: Context, name: &String) -> Result<u32> {
+    let mut state = OCCLUSION_QUERIES.lock().unwrap();
+    state
+        .invalidation_triggers
+        .entry(name.clone())
+        .or_default()
+        .push(Box::new(ctx.get_invalidation_trigger()));
+
+    Ok(state.results.get(name).copied().unwrap_or(0))
+}
+
+#[derive(Default)]
+struct OcclusionQueryState {
+    results: HashMap<String, u32>,
+    invalidation_triggers: HashMap<String, Vec<Box<dyn Fn() + Send + Sync>>>,
+}
+
+lazy_static! {
+    static ref OCCLUSION_QUERIES: Mutex<OcclusionQueryState> = Mutex::new(Default::default());
+}
+
+fn publish_results(results: HashMap<String, u32>) {
+    let mut triggers = Vec::new();
+    {
+        let mut state = OCCLUSION_QUERIES.lock().unwrap();
+        for (name, samples) in results {
+            if state.results.insert(name.clone(), samples) != Some(samples) {
+                triggers.extend(
+                    state
+                        .invalidation_triggers
+                        .remove(&name)
+                        .unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+const MAX_QUERY_COUNT: usize = 1024;
+
+// Per-frame query pool, and a buffer the results get copied to.
+pub struct OcclusionQueryPool {
+    query_pool: vk::QueryPool,
+    buffer: vk::Buffer,
+    allocation: vk_mem::Allocation,
+    query_names: Mutex<Vec<String>>,
+}
+
+impl OcclusionQueryPool {
+    pub(crate) fn new(device: &Device, allocator: &vk_mem::Allocator) -> Self {
+        let mem_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            ..Default::default()
+        };
+
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(MAX_QUERY_COUNT as u64 * 8)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        let (buffer, allocation, _allocation_info) = allocator
+            .create_buffer(&buffer_info, &mem_info)
+            .expect("vma::create_buffer");
+
+        let pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(MAX_QUERY_COUNT as u32);
+
+        Self {
+            query_pool: unsafe { device.create_query_pool(&pool_info, None) }
+                .expect("create_query_pool"),
+            buffer,
+            allocation,
+            query_names: Default::default(),
+        }
+    }
+
+    // Must be called within a render pass. Queries over the per-frame limit are skipped.
+    pub(crate) fn begin_query(
+        &self,
+        device: &Device,
+        cb: vk::CommandBuffer,
+        name: &str,
+    ) -> Option<u32> {
+        let mut query_names = self.query_names.lock().unwrap();
+        if query_names.len() >= MAX_QUERY_COUNT {
+            tracing::warn!("Out of occlusion queries; skipping {}", name);
+            return None;
+        }
+
+        let query = query_names.len() as u32;
+        query_names.push(name.to_owned());
+
+        unsafe {
+            device.cmd_begin_query(cb, self.query_pool, query, vk::QueryControlFlags::empty());
+        }
+
+        Some(query)
+    }
+
+    pub(crate) fn end_query(&self, device: &Device, cb: vk::CommandBuffer, query: u32) {
+        unsafe {
+            device.cmd_end_query(cb, self.query_pool, query);
+        }
+    }
+
+    pub(crate) fn begin_frame(&self, device: &Device, cb: vk::CommandBuffer) {
+        self.query_names.lock().unwrap().clear();
+
+        unsafe {
+            device.cmd_reset_query_pool(cb, self.query_pool, 0, MAX_QUERY_COUNT as u32);
+        }
+    }
+
+    pub(crate) fn finish_frame(&self, device: &Device, cb: vk::CommandBuffer) {
+        let query_count = self.query_names.lock().unwrap().len() as u32;
+        if query_count == 0 {
+            return;
+        }
+
+        unsafe {
+            device.cmd_copy_query_pool_results(
+                cb,
+                self.query_pool,
+                0,
+                query_count,
+                self.buffer,
+                0,
+                8,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            );
+        }
+
+        vk_sync::cmd::pipeline_barrier(
+            device.fp_v1_0(),
+            cb,
+            Some(vk_sync::GlobalBarrier {
+                previous_accesses: &[vk_sync::AccessType::TransferWrite],
+                next_accesses: &[vk_sync::AccessType::HostRead],
+            }),
+            &[],
+            &[],
+        );
+    }
+
+    // Must only be called once the frame which last used this pool has finished executing.
+    pub(crate) fn report_previous_results(&self, allocator: &vk_mem::Allocator) {
+        let query_names = self.query_names.lock().unwrap();
+        if query_names.is_empty() {
+            return;
+        }
+
+        let mapped_ptr = allocator
+            .map_memory(&self.allocation)
+            .expect("mapping an occlusion query buffer failed")
+            as *const u64;
+
+        let samples =
+            unsafe { std::slice::from_raw_parts(mapped_ptr, query_names.len()) }.to_owned();
+
+        allocator
+            .unmap_memory(&self.allocation)
+            .expect("unmapping an occlusion query buffer failed");
+
+        let mut results: HashMap<String, u32> = HashMap::new();
+        for (name, samples) in query_names.iter().zip(samples) {
+            let total = results.entry(name.clone()).or_default();
+            *total = total.saturating_add(samples.min(std::u32::MAX as u64) as u32);
+        }
+
+        publish_results(results);
+    }
+}
+
+impl Drop for OcclusionQueryPool {
+    fn drop(&mut self) {
+        let vk = vk();
+        vk.allocator
+            .destroy_buffer(self.buffer, &self.allocation)
+            .unwrap();
+
+        unsafe {
+            vk.device.destroy_query_pool(self.query_pool, None);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Domain-specific numbers emitted by ops, e.g. rays traced, or particles alive as read back
+// from a buffer, for display next to the GPU pass timings and in frame telemetry. Values
+// reported under the same name during a frame are summed up.
+//
+// Ops only run when their inputs change, so a metric which isn't reported during a frame
+// keeps its last value rather than dropping to zero.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+// Number of most recent frames each metric keeps statistics over
+const METRIC_HISTORY_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct OpMetricStats {
+    pub name: String,
+    // Total of the last frame the metric was reported in
+    pub last: f64,
+    pub sample_count: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+#[derive(Default)]
+struct OpMetricsState {
+    frame_values: HashMap<String, f64>,
+    last_frame_values: Vec<(String, f64)>,
+    history: HashMap<String, VecDeque<f64>>,
+}
+
+lazy_static! {
+    static ref OP_METRICS: Mutex<OpMetricsState> = Mutex::new(Default::default());
+}
+
+// Adds `value` to the metric `name` for the current frame.
+pub fn report_op_metric(name: &str, value: f64) {
+    let mut state = OP_METRICS.lock().unwrap();
+    if let Some(total) = state.frame_values.get_mut(name) {
+        *total += value;
+    } else {
+        state.frame_values.insert(name.to_owned(), value);
+    }
+}
+
+// Statistics of all metrics reported so far, sorted by name.
+pub fn op_metric_stats() -> Vec<OpMetricStats> {
+    let state = OP_METRICS.lock().unwrap();
+    let sorted: BTreeMap<&String, &VecDeque<f64>> = state.history.iter().collect();
+
+    sorted
+        .into_iter()
+        .filter_map(|(name, history)| {
+            let last = *history.back()?;
+            Some(OpMetricStats {
+                name: name.clone(),
+                last,
+                sample_count: history.len(),
+                min: history.iter().cloned().fold(std::f64::INFINITY, f64::min),
+                avg: history.iter().sum::<f64>() / history.len() as f64,
+                max: history
+                    .iter()
+                    .cloned()
+                    .fold(std::f64::NEG_INFINITY, f64::max),
+            })
+        })
+        .collect()
+}
+
+// Totals reported during the last frame.
+pub(crate) fn last_frame_op_metrics() -> Vec<(String, f64)> {
+    OP_METRICS.lock().unwrap().last_frame_values.clone()
+}
+
+// Forgets all metrics, e.g. when switching between scenes which report different ones.
+pub fn reset_op_metrics() {
+    let mut state = OP_METRICS.lock().unwrap();
+    state.frame_values.clear();
+    state.last_frame_values.clear();
+    state.history.clear();
+}
+
+pub(crate) fn end_frame() {
+    let mut state = OP_METRICS.lock().unwrap();
+    let frame_values = std::mem::replace(&mut state.frame_values, HashMap::new());
+    state.last_frame_values = frame_values.into_iter().collect();
+
+    let OpMetricsState {
+        last_frame_values,
+        history,
+        ..
+    } = &mut *state;
+
+    for (name, value) in last_frame_values.iter() {
+        let history = history.entry(name.clone()).or_default();
+        if history.len() == METRIC_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(*value);
+    }
+}
@@ -0,0 +1,102 @@
+// Metadata attached to ops, e.g. `op_tags(&["owner=bloom", "quality=low"])`, passed along
+// with the uniforms of compute and raster ops. Tags don't reach the shader; they're appended
+// to the pass name in profiler scopes, debug labels and graph dumps, and GPU timings can be
+// aggregated by them through `gpu_timing_by_tag`.
+//
+// The `group` tag, set via `pass_group("GI")`, is special: it places passes in a named
+// scope. Groups nest through bundles, so a bundle tagged `pass_group("Diffuse")` passed to
+// an op tagged `pass_group("GI")` ends up in `GI/Diffuse`. Grouped passes are named e.g.
+// `[GI/Diffuse] blur`, and GPU timings can be summed up per group via `gpu_timing_by_group`.
+
+pub(crate) const GROUP_TAG: &str = "group";
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpTags(pub Vec<(String, String)>);
+
+impl OpTags {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Later entries override earlier ones with the same key.
+    pub(crate) fn extend(&mut self, other: OpTags) {
+        for (key, value) in other.0 {
+            match self.0.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => self.0.push((key, value)),
+            }
+        }
+    }
+
+    // Like `extend`, but for tags of a nested scope, whose group goes inside ours.
+    pub(crate) fn extend_nested(&mut self, mut inner: OpTags) {
+        if let (Some(outer_group), Some(inner_group)) = (self.group(), inner.group()) {
+            let group = format!("{}/{}", outer_group, inner_group);
+            if let Some(entry) = inner.0.iter_mut().find(|(k, _)| k == GROUP_TAG) {
+                entry.1 = group;
+            }
+        }
+
+        self.extend(inner);
+    }
+
+    // Slash-separated path of the group the op is in, e.g. `GI/Diffuse`.
+    pub fn group(&self) -> Option<&str> {
+        self.get(GROUP_TAG).filter(|g| !g.is_empty())
+    }
+
+    // e.g. `[GI] blur {owner=bloom, quality=low}`; just `name` without any tags.
+    pub(crate) fn tagged_name(&self, name: &str) -> String {
+        let name = match self.group() {
+            Some(group) => format!("[{}] {}", group, name),
+            None => name.to_owned(),
+        };
+
+        let tags: Vec<String> = self
+            .0
+            .iter()
+            .filter(|(k, _)| k != GROUP_TAG)
+            .map(|(k, v)| {
+                if v.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}={}", k, v)
+                }
+            })
+            .collect();
+
+        if tags.is_empty() {
+            name
+        } else {
+            format!("{} {{{}}}", name, tags.join(", "))
+        }
+    }
+}
+
+// Entries are `key=value`, or just `key` for tags without a value.
+pub fn op_tags(tags: &[&str]) -> OpTags {
+    let mut res = OpTags::default();
+    res.extend(OpTags(
+        tags.iter()
+            .map(|tag| {
+                let mut parts = tag.splitn(2, '=');
+                let key = parts.next().unwrap_or_default().trim().to_owned();
+                let value = parts.next().unwrap_or_default().trim().to_owned();
+                (key, value)
+            })
+            .collect(),
+    ));
+    res
+}
+
+// Places the op in the group `name`; see above.
+pub fn pass_group(name: &str) -> OpTags {
+    OpTags(vec![(GROUP_TAG.to_owned(), name.to_owned())])
+}
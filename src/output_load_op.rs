@@ -0,0 +1,97 @@
+// What `compute_tex` and `raster_tex` do with the previous contents of their output,
+// passed among the uniforms, e.g.
+//
+//   shader_uniforms!(
+//       inputTex: input,
+//       : OutputLoadOp::Load,
+//   )
+//
+// Outputs are discarded by default. `Load` keeps one texture per pass name and key around,
+// and keeps writing into it, so that incremental techniques can accumulate into
+// their output over several frames. It's cleared to zero when first created. Passes
+// running the same shader need different tags to get separate outputs.
+
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum OutputLoadOp {
+    Discard,
+    Clear([f32; 4]),
+    Load,
+}
+
+impl Default for OutputLoadOp {
+    fn default() -> Self {
+        OutputLoadOp::Discard
+    }
+}
+
+lazy_static! {
+    static ref PERSISTENT_OUTPUTS: Mutex<HashMap<(String, TextureKey), Texture>> =
+        Mutex::new(HashMap::new());
+}
+
+// Releases the textures kept by `OutputLoadOp::Load`; passes start over from zero.
+pub fn reset_persistent_outputs() {
+    PERSISTENT_OUTPUTS.lock().unwrap().clear();
+}
+
+// The output of `pass_name` preserved across runs, and the load op to write it with;
+// textures which were just created get cleared instead.
+pub(crate) fn persistent_output_texture(
+    pass_name: &str,
+    key: TextureKey,
+) -> (Texture, OutputLoadOp) {
+    let mut outputs = PERSISTENT_OUTPUTS.lock().unwrap();
+    if let Some(tex) = outputs.get(&(pass_name.to_owned(), key)) {
+        return (tex.clone(), OutputLoadOp::Load);
+    }
+
+    let tex = crate::backend::texture::create_texture(key);
+    outputs.insert((pass_name.to_owned(), key), tex.clone());
+    (tex, OutputLoadOp::Clear([0.0; 4]))
+}
+
+// Integer formats take the color as integers rather than as float bits.
+pub(crate) fn clear_color_value(format: vk::Format, color: [f32; 4]) -> vk::ClearColorValue {
+    use vk::Format as F;
+
+    match format {
+        F::R8_UINT
+        | F::R8G8_UINT
+        | F::R8G8B8A8_UINT
+        | F::R16_UINT
+        | F::R16G16_UINT
+        | F::R16G16B16A16_UINT
+        | F::R32_UINT
+        | F::R32G32_UINT
+        | F::R32G32B32A32_UINT => vk::ClearColorValue {
+            uint32: [
+                color[0] as u32,
+                color[1] as u32,
+                color[2] as u32,
+                color[3] as u32,
+            ],
+        },
+        F::R8_SINT
+        | F::R8G8_SINT
+        | F::R8G8B8A8_SINT
+        | F::R16_SINT
+        | F::R16G16_SINT
+        | F::R16G16B16A16_SINT
+        | F::R32_SINT
+        | F::R32G32_SINT
+        | F::R32G32B32A32_SINT => vk::ClearColorValue {
+            int32: [
+                color[0] as i32,
+                color[1] as i32,
+                color[2] as i32,
+                color[3] as i32,
+            ],
+        },
+        _ => vk::ClearColorValue { float32: color },
+    }
+}
@@ -0,0 +1,400 @@
+// Geometry correction and edge blending of the final output, for projection mapping.
+//
+// A grid of control points describes where the content lands in the output, either
+// as a piecewise bilinear mesh or as a single Bezier surface. The grid is tessellated
+// and rasterized on the CPU into a warp map, which a compute pass then uses to resample
+// the final image. Calibration files are plain text and get reloaded on change:
+//
+//   mode bezier
+//   grid 4 4
+//   point 0.0 0.0        # `grid` width * height points, row-major, in output UV
+//   ...
+//   blend 0.1 0 0 0      # edge blend widths: left, right, top, bottom
+//   blend_power 2
+//   mask my_crate::masks/left.png
+
+use crate::blob::rendertoy_asset_path;
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{
+    load_tex_impl, load_tex_with_params, make_placeholder_rgba8_tex, TexGamma, TexParams, Texture,
+};
+use crate::Vec2;
+use ash::vk;
+use snoozy::*;
+use std::sync::Mutex;
+
+const WARP_MAP_SIZE: u32 = 512;
+const TESSELLATION: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarpMode {
+    Mesh,
+    Bezier,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WarpCalibration {
+    pub mode: WarpMode,
+    pub grid_size: (u32, u32),
+    pub points: Vec<Vec2>,
+    pub blend_widths: [f32; 4],
+    pub blend_power: f32,
+    pub mask: Option<String>,
+}
+
+impl WarpCalibration {
+    pub fn identity(mode: WarpMode, grid_size: (u32, u32)) -> Self {
+        let (w, h) = (grid_size.0.max(2), grid_size.1.max(2));
+        let points = (0..h)
+            .flat_map(|y| {
+                (0..w).map(move |x| Vec2::new(x as f32 / (w - 1) as f32, y as f32 / (h - 1) as f32))
+            })
+            .collect();
+
+        Self {
+            mode,
+            grid_size: (w, h),
+            points,
+            blend_widths: [0.0; 4],
+            blend_power: 2.0,
+            mask: None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut res = Self::identity(WarpMode::Mesh, (2, 2));
+        let mut points = Vec::new();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let args: Vec<&str> = tokens.collect();
+            let parse_floats = |count: usize| -> Result<Vec<f32>> {
+                if args.len() != count {
+                    bail!("Line {}: expected {} values", line_idx + 1, count);
+                }
+                args.iter()
+                    .map(|a| {
+                        a.parse::<f32>()
+                            .map_err(|e| format_err!("Line {}: {}", line_idx + 1, e))
+                    })
+                    .collect()
+            };
+
+            match keyword {
+                "mode" => {
+                    res.mode = match args.get(0) {
+                        Some(&"mesh") => WarpMode::Mesh,
+                        Some(&"bezier") => WarpMode::Bezier,
+                        _ => bail!("Line {}: expected `mesh` or `bezier`", line_idx + 1),
+                    }
+                }
+                "grid" => {
+                    let size = parse_floats(2)?;
+                    res.grid_size = ((size[0] as u32).max(2), (size[1] as u32).max(2));
+                }
+                "point" => {
+                    let p = parse_floats(2)?;
+                    points.push(Vec2::new(p[0], p[1]));
+                }
+                "blend" => {
+                    let w = parse_floats(4)?;
+                    res.blend_widths = [w[0], w[1], w[2], w[3]];
+                }
+                "blend_power" => res.blend_power = parse_floats(1)?[0],
+                "mask" => res.mask = args.get(0).map(|s| (*s).to_owned()),
+                _ => bail!("Line {}: unknown keyword `{}`", line_idx + 1, keyword),
+            }
+        }
+
+        if points.is_empty() {
+            res.points = Self::identity(res.mode, res.grid_size).points;
+        } else if points.len() == (res.grid_size.0 * res.grid_size.1) as usize {
+            res.points = points;
+        } else {
+            bail!(
+                "Expected {} points for a {}x{} grid, got {}",
+                res.grid_size.0 * res.grid_size.1,
+                res.grid_size.0,
+                res.grid_size.1,
+                points.len()
+            );
+        }
+
+        Ok(res)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        text += match self.mode {
+            WarpMode::Mesh => "mode mesh\n",
+            WarpMode::Bezier => "mode bezier\n",
+        };
+        text += &format!("grid {} {}\n", self.grid_size.0, self.grid_size.1);
+        for p in self.points.iter() {
+            text += &format!("point {} {}\n", p.x(), p.y());
+        }
+        let b = self.blend_widths;
+        text += &format!("blend {} {} {} {}\n", b[0], b[1], b[2], b[3]);
+        text += &format!("blend_power {}\n", self.blend_power);
+        if let Some(ref mask) = self.mask {
+            text += &format!("mask {}\n", mask);
+        }
+        text
+    }
+
+    fn point(&self, x: u32, y: u32) -> Vec2 {
+        self.points[(y * self.grid_size.0 + x) as usize]
+    }
+
+    // Output position of content UV (u, v)
+    fn evaluate(&self, u: f32, v: f32) -> Vec2 {
+        let (w, h) = self.grid_size;
+        match self.mode {
+            WarpMode::Mesh => {
+                let fx = u * (w - 1) as f32;
+                let fy = v * (h - 1) as f32;
+                let x0 = (fx as u32).min(w - 2);
+                let y0 = (fy as u32).min(h - 2);
+                let tx = fx - x0 as f32;
+                let ty = fy - y0 as f32;
+
+                let top = lerp(self.point(x0, y0), self.point(x0 + 1, y0), tx);
+                let bottom = lerp(self.point(x0, y0 + 1), self.point(x0 + 1, y0 + 1), tx);
+                lerp(top, bottom, ty)
+            }
+            WarpMode::Bezier => {
+                let mut res = Vec2::zero();
+                for y in 0..h {
+                    let by = bernstein(h - 1, y, v);
+                    for x in 0..w {
+                        res += self.point(x, y) * (by * bernstein(w - 1, x, u));
+                    }
+                }
+                res
+            }
+        }
+    }
+}
+
+fn lerp(a: Vec2, b: Vec2, t: f32) -> Vec2 {
+    a + (b - a) * t
+}
+
+fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.x() * b.y() - a.y() * b.x()
+}
+
+fn bernstein(n: u32, i: u32, t: f32) -> f32 {
+    let mut binomial = 1.0;
+    for k in 0..i {
+        binomial = binomial * (n - k) as f32 / (k + 1) as f32;
+    }
+    binomial * t.powi(i as i32) * (1.0 - t).powi((n - i) as i32)
+}
+
+// Rasterizes the tessellated grid; each texel holds the content UV displayed there,
+// and coverage in the alpha channel.
+fn build_warp_map(calibration: &WarpCalibration) -> Vec<[f32; 4]> {
+    let size = WARP_MAP_SIZE as usize;
+    let mut texels = vec![[0.0f32; 4]; size * size];
+
+    let vertex = |x: u32, y: u32| {
+        let uv = Vec2::new(
+            x as f32 / TESSELLATION as f32,
+            y as f32 / TESSELLATION as f32,
+        );
+        (
+            calibration.evaluate(uv.x(), uv.y()) * WARP_MAP_SIZE as f32,
+            uv,
+        )
+    };
+
+    let mut raster_triangle = |tri: [(Vec2, Vec2); 3]| {
+        let [(p0, uv0), (p1, uv1), (p2, uv2)] = tri;
+        let area = cross(p1 - p0, p2 - p0);
+        if area.abs() < 1e-10 {
+            return;
+        }
+
+        let min = p0.min(p1).min(p2);
+        let max = p0.max(p1).max(p2);
+        let x_range = (min.x().max(0.0) as usize)..(max.x().ceil().min(size as f32) as usize);
+        let y_range = (min.y().max(0.0) as usize)..(max.y().ceil().min(size as f32) as usize);
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = cross(p2 - p1, p - p1) / area;
+                let w1 = cross(p0 - p2, p - p2) / area;
+                let w2 = 1.0 - w0 - w1;
+
+                if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
+                    let uv = uv0 * w0 + uv1 * w1 + uv2 * w2;
+                    texels[y * size + x] = [uv.x(), uv.y(), 0.0, 1.0];
+                }
+            }
+        }
+    };
+
+    for y in 0..TESSELLATION {
+        for x in 0..TESSELLATION {
+            let v00 = vertex(x, y);
+            let v10 = vertex(x + 1, y);
+            let v01 = vertex(x, y + 1);
+            let v11 = vertex(x + 1, y + 1);
+            raster_triangle([v00, v10, v11]);
+            raster_triangle([v00, v11, v01]);
+        }
+    }
+
+    texels
+}
+
+#[derive(Default)]
+struct OutputWarpState {
+    calibration: Option<WarpCalibration>,
+    file_path: Option<String>,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref OUTPUT_WARP: Mutex<OutputWarpState> = Mutex::new(Default::default());
+}
+
+// Replaces the active calibration; `None` disables the warp stage.
+pub fn set_output_warp_calibration(calibration: Option<WarpCalibration>) {
+    let triggers = {
+        let mut state = OUTPUT_WARP.lock().unwrap();
+        if state.calibration == calibration {
+            return;
+        }
+        state.calibration = calibration;
+        std::mem::replace(&mut state.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+pub fn output_warp_calibration() -> Option<WarpCalibration> {
+    OUTPUT_WARP.lock().unwrap().calibration.clone()
+}
+
+// Loads a calibration file, and keeps reloading it whenever it changes on disk.
+pub fn load_output_warp_calibration(path: &str) -> Result<()> {
+    let calibration = WarpCalibration::parse(&std::fs::read_to_string(path)?)?;
+    OUTPUT_WARP.lock().unwrap().file_path = Some(path.to_owned());
+    set_output_warp_calibration(Some(calibration));
+
+    let reload_path = path.to_owned();
+    crate::backend::file::watch_file(path, move || {
+        match std::fs::read_to_string(&reload_path)
+            .map_err(failure::Error::from)
+            .and_then(|text| WarpCalibration::parse(&text))
+        {
+            Ok(calibration) => set_output_warp_calibration(Some(calibration)),
+            Err(err) => tracing::error!("Failed to reload {}: {}", reload_path, err),
+        }
+    });
+
+    Ok(())
+}
+
+// Writes the active calibration back to the file it was loaded from.
+pub fn save_output_warp_calibration() -> Result<()> {
+    let (calibration, path) = {
+        let state = OUTPUT_WARP.lock().unwrap();
+        (state.calibration.clone(), state.file_path.clone())
+    };
+
+    match (calibration, path) {
+        (Some(calibration), Some(path)) => Ok(std::fs::write(path, calibration.to_text())?),
+        _ => Err(format_err!("No calibration file loaded")),
+    }
+}
+
+pub(crate) fn is_output_warp_enabled() -> bool {
+    OUTPUT_WARP.lock().unwrap().calibration.is_some()
+}
+
+// Returns the active calibration, and invalidates the calling op when it changes.
+fn get_calibration(ctx: &Context) -> WarpCalibration {
+    let mut state = OUTPUT_WARP.lock().unwrap();
+    state
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+    state
+        .calibration
+        .clone()
+        .unwrap_or_else(|| WarpCalibration::identity(WarpMode::Mesh, (2, 2)))
+}
+
+#[snoozy]
+pub async fn output_warp_map_tex_snoozy(ctx: Context) -> Result<Texture> {
+    let calibration = get_calibration(&ctx);
+    let texels = build_warp_map(&calibration);
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            texels.as_ptr() as *const u8,
+            texels.len() * std::mem::size_of::<[f32; 4]>(),
+        )
+    };
+
+    load_tex_impl(
+        bytes,
+        (WARP_MAP_SIZE, WARP_MAP_SIZE),
+        vk::Format::R32G32B32A32_SFLOAT,
+    )
+}
+
+// Applies the active calibration to `tex`. Used on the final output when a calibration
+// is loaded, e.g. via `--warp-calibration`.
+#[snoozy]
+pub async fn warp_output_tex_snoozy(mut ctx: Context, tex: &SnoozyRef<Texture>) -> Result<Texture> {
+    let key = ctx
+        .get(tex)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let calibration = get_calibration(&ctx);
+
+    let mask_tex = match calibration.mask {
+        Some(ref mask) => load_tex_with_params(
+            rendertoy_asset_path("", mask),
+            TexParams {
+                gamma: TexGamma::Linear,
+            },
+        ),
+        None => make_placeholder_rgba8_tex([255u8; 4]),
+    };
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/output_warp.glsl").to_owned(),
+        "output_warp.glsl".to_owned(),
+    );
+
+    let b = calibration.blend_widths;
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                inputTex: tex.clone(),
+                warpTex: output_warp_map_tex(),
+                maskTex: mask_tex,
+                blend_widths: (b[0], b[1], b[2], b[3]),
+                blend_power: calibration.blend_power,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
@@ -1,14 +1,14 @@
 use cargo_metadata::MetadataCommand;
 use snoozy::*;
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 #[derive(Debug)]
 pub struct CargoPackageMap {
     pub deps: HashMap<String, String>,
 }
 
-#[snoozy]
-pub async fn load_cargo_package_map_snoozy(_ctx: Context) -> Result<CargoPackageMap> {
+fn read_cargo_package_map() -> CargoPackageMap {
     let metadata = MetadataCommand::new()
         .manifest_path("./Cargo.toml")
         .exec()
@@ -32,7 +32,56 @@ pub async fn load_cargo_package_map_snoozy(_ctx: Context) -> Result<CargoPackage
         })
         .collect();
 
-    Ok(CargoPackageMap { deps })
+    CargoPackageMap { deps }
+}
+
+#[snoozy]
+pub async fn load_cargo_package_map_snoozy(_ctx: Context) -> Result<CargoPackageMap> {
+    Ok(read_cargo_package_map())
+}
+
+#[derive(Default)]
+struct AssetNamespaceOverrides {
+    roots: HashMap<String, String>,
+    invalidation_triggers: HashMap<String, Vec<Box<dyn Fn() + Send + Sync>>>,
+}
+
+lazy_static! {
+    static ref ASSET_NAMESPACE_OVERRIDES: Mutex<AssetNamespaceOverrides> =
+        Mutex::new(Default::default());
+    // For `get_cargo_package_dep_path_untracked`; read once
+    static ref UNTRACKED_PACKAGE_MAP: Mutex<Option<CargoPackageMap>> = Mutex::new(None);
+}
+
+// Makes assets in `namespace::` load from `root` (a directory containing an `assets` folder)
+// instead of the Cargo package of that name. The namespace doesn't need to exist as a package,
+// so this can also introduce aliases such as `effects@b`. Pass `None` to remove the override.
+//
+// Anything which was loaded from the namespace gets reloaded.
+pub fn set_asset_namespace_override(namespace: &str, root: Option<&str>) {
+    let triggers = {
+        let mut overrides = ASSET_NAMESPACE_OVERRIDES.lock().unwrap();
+        let prev = if let Some(root) = root {
+            overrides
+                .roots
+                .insert(namespace.to_owned(), root.to_owned())
+        } else {
+            overrides.roots.remove(namespace)
+        };
+
+        if prev.as_ref().map(String::as_str) == root {
+            return;
+        }
+
+        overrides
+            .invalidation_triggers
+            .remove(namespace)
+            .unwrap_or_default()
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
 }
 
 // Explicitly not serializable, so that it doesn't end up auto-cached
@@ -43,6 +92,19 @@ pub async fn get_cargo_package_dep_path_snoozy(
     mut ctx: Context,
     package: &String,
 ) -> Result<CargoDependencyPath> {
+    {
+        let mut overrides = ASSET_NAMESPACE_OVERRIDES.lock().unwrap();
+        overrides
+            .invalidation_triggers
+            .entry(package.clone())
+            .or_default()
+            .push(Box::new(ctx.get_invalidation_trigger()));
+
+        if let Some(root) = overrides.roots.get(package) {
+            return Ok(CargoDependencyPath(root.clone()));
+        }
+    }
+
     let map = ctx.get(load_cargo_package_map()).await?;
 
     if let Some(path) = map.deps.get(package) {
@@ -51,3 +113,18 @@ pub async fn get_cargo_package_dep_path_snoozy(
         Err(format_err!("Package not found: {}", *package))
     }
 }
+
+// Like `get_cargo_package_dep_path`, for use outside of ops. Nothing gets notified when
+// namespace overrides change.
+pub(crate) fn get_cargo_package_dep_path_untracked(package: &str) -> Result<String> {
+    if let Some(root) = ASSET_NAMESPACE_OVERRIDES.lock().unwrap().roots.get(package) {
+        return Ok(root.clone());
+    }
+
+    let mut map = UNTRACKED_PACKAGE_MAP.lock().unwrap();
+    map.get_or_insert_with(read_cargo_package_map)
+        .deps
+        .get(package)
+        .cloned()
+        .ok_or_else(|| format_err!("Package not found: {}", package))
+}
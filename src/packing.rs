@@ -0,0 +1,109 @@
+// Helpers for packing data into compact buffers. Use together with `upload_array_buffer`,
+// and read the results via `float16_t` / `uint8_t` in shaders (see `DeviceCaps`).
+
+// IEEE 754 binary16, rounding to nearest even.
+pub fn f32_to_f16_bits(x: f32) -> u16 {
+    let x = x.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let man = x & 0x007f_ffff;
+
+    if exp == 0xff {
+        // Inf or NaN. Keep NaNs quiet.
+        return sign | 0x7c00 | if man != 0 { 0x0200 } else { 0 };
+    }
+
+    let half_exp = exp - 127 + 15;
+    if half_exp >= 0x1f {
+        // Too large; flush to infinity
+        return sign | 0x7c00;
+    }
+
+    if half_exp <= 0 {
+        // Denormal in half precision, or zero
+        let shift = (14 - half_exp) as u32;
+        if shift > 24 {
+            return sign;
+        }
+
+        let man = man | 0x0080_0000;
+        let half_man = man >> shift;
+        let round_bit = 1u32 << (shift - 1);
+        let rem = man & ((round_bit << 1) - 1);
+        let rounded = if rem > round_bit || (rem == round_bit && (half_man & 1) != 0) {
+            half_man + 1
+        } else {
+            half_man
+        };
+        return sign | rounded as u16;
+    }
+
+    let mut bits = ((half_exp as u32) << 10) | (man >> 13);
+    let rem = man & 0x1fff;
+
+    // A carry out of the mantissa correctly bumps the exponent.
+    if rem > 0x1000 || (rem == 0x1000 && (bits & 1) != 0) {
+        bits += 1;
+    }
+
+    sign | bits as u16
+}
+
+// Exact; every binary16 value is representable as an f32.
+pub fn f16_bits_to_f32(x: u16) -> f32 {
+    let sign = ((x as u32) & 0x8000) << 16;
+    let exp = ((x >> 10) & 0x1f) as u32;
+    let man = (x & 0x03ff) as u32;
+
+    let bits = match exp {
+        0 if man == 0 => sign,
+        0 => {
+            // Denormal; normalize the mantissa
+            let shift = man.leading_zeros() - 21;
+            sign | ((113 - shift) << 23) | ((man << shift) & 0x03ff) << 13
+        }
+        0x1f => sign | 0x7f80_0000 | (man << 13),
+        _ => sign | ((exp + 127 - 15) << 23) | (man << 13),
+    };
+
+    f32::from_bits(bits)
+}
+
+pub fn pack_f16(values: &[f32]) -> Vec<u16> {
+    values.iter().copied().map(f32_to_f16_bits).collect()
+}
+
+pub fn pack_unorm8(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .map(|v| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8)
+        .collect()
+}
+
+#[test]
+fn test_f32_to_f16_bits() {
+    assert_eq!(f32_to_f16_bits(0.0), 0x0000);
+    assert_eq!(f32_to_f16_bits(-0.0), 0x8000);
+    assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+    assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+    assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16_bits(1.0e6), 0x7c00);
+    assert_eq!(f32_to_f16_bits(2.0f32.powi(-24)), 0x0001);
+    assert_eq!(f32_to_f16_bits(std::f32::INFINITY), 0x7c00);
+    assert_eq!(f32_to_f16_bits(std::f32::NAN) & 0x7e00, 0x7e00);
+}
+
+#[test]
+fn test_f16_bits_to_f32() {
+    assert_eq!(f16_bits_to_f32(0x0000), 0.0);
+    assert_eq!(f16_bits_to_f32(0x3c00), 1.0);
+    assert_eq!(f16_bits_to_f32(0xc000), -2.0);
+    assert_eq!(f16_bits_to_f32(0x7bff), 65504.0);
+    assert_eq!(f16_bits_to_f32(0x0001), 2.0f32.powi(-24));
+    assert_eq!(f16_bits_to_f32(0x7c00), std::f32::INFINITY);
+    assert!(f16_bits_to_f32(0x7e00).is_nan());
+
+    for bits in 0..0x7c00u16 {
+        assert_eq!(f32_to_f16_bits(f16_bits_to_f32(bits)), bits);
+    }
+}
@@ -0,0 +1,104 @@
+// 360 degree panoramas: the graph is evaluated once per cube face, and the faces
+// are then resampled into an equirectangular image. Write the result out with
+// `save_tex_png`, once per frame for video.
+
+use crate::camera::CameraMatrices;
+use crate::math::*;
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// Forward and up vectors of each face; must match `equirect_from_cube.glsl`
+fn cube_face_basis(face: usize) -> (Vec3, Vec3) {
+    match face {
+        0 => (Vec3::unit_x(), Vec3::unit_y()),
+        1 => (-Vec3::unit_x(), Vec3::unit_y()),
+        2 => (Vec3::unit_y(), Vec3::unit_z()),
+        3 => (-Vec3::unit_y(), -Vec3::unit_z()),
+        4 => (Vec3::unit_z(), Vec3::unit_y()),
+        5 => (-Vec3::unit_z(), Vec3::unit_y()),
+        _ => panic!("Invalid cube face {}", face),
+    }
+}
+
+// 90 degree square camera looking out of `face` from `position`.
+pub fn cube_face_camera_matrices(position: Vec3, face: usize, near_dist: f32) -> CameraMatrices {
+    let (forward, up) = cube_face_basis(face);
+    let right = forward.cross(up);
+
+    let rotation = Mat4::from_cols(
+        right.extend(0.0),
+        up.extend(0.0),
+        (-forward).extend(0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    );
+
+    // Same infinite reverse-Z projection as `FirstPersonCamera`, at a 90 degree FOV
+    let view_to_clip = Mat4::from_cols(
+        Vec4::new(1.0, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, -1.0),
+        Vec4::new(0.0, 0.0, near_dist, 0.0),
+    );
+    let clip_to_view = Mat4::from_cols(
+        Vec4::new(1.0, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0 / near_dist),
+        Vec4::new(0.0, 0.0, -1.0, 0.0),
+    );
+
+    CameraMatrices {
+        view_to_clip,
+        clip_to_view,
+        world_to_view: rotation.transpose() * Mat4::from_translation(-position),
+        view_to_world: Mat4::from_translation(position) * rotation,
+    }
+}
+
+// Builds the graph once per cube face. `build` receives the camera of each face,
+// and should render a square image. See also `cube_from_faces_tex`.
+pub fn build_cube_faces(
+    position: Vec3,
+    near_dist: f32,
+    build: impl Fn(CameraMatrices) -> SnoozyRef<Texture>,
+) -> Vec<SnoozyRef<Texture>> {
+    (0..6)
+        .map(|face| build(cube_face_camera_matrices(position, face, near_dist)))
+        .collect()
+}
+
+// Resamples the six faces from `build_cube_faces` into a `width` by `width / 2` panorama.
+#[snoozy]
+pub async fn equirect_from_cube_faces_tex_snoozy(
+    mut ctx: Context,
+    faces: &Vec<SnoozyRef<Texture>>,
+    width: &u32,
+) -> Result<Texture> {
+    if faces.len() != 6 {
+        bail!("Expected 6 cube faces, got {}", faces.len());
+    }
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/equirect_from_cube.glsl").to_owned(),
+        "equirect_from_cube.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            TextureKey::new(*width, *width / 2, vk::Format::R16G16B16A16_SFLOAT),
+            cs,
+            shader_uniforms!(
+                texFace0: faces[0].clone(),
+                texFace1: faces[1].clone(),
+                texFace2: faces[2].clone(),
+                texFace3: faces[3].clone(),
+                texFace4: faces[4].clone(),
+                texFace5: faces[5].clone(),
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
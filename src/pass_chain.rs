@@ -0,0 +1,100 @@
+// Builder for linear filter chains, where every pass reads the output of the previous one:
+//
+//   PassChain::new(tex)
+//       .then(load_cs(asset!("shaders/blur_h.glsl")), shader_uniforms!(radius: 4.0f32))
+//       .then(load_cs(asset!("shaders/blur_v.glsl")), shader_uniforms!(radius: 4.0f32))
+//       .output()
+//
+// Passes get their input as `inputTex`. Intermediate textures are created with the key
+// of the input, unless changed through `then_keyed`.
+
+use crate::shader::{compute_tex, ComputeShader, ShaderUniformHolder};
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+// How the output key of a pass derives from that of its input.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChainedKey {
+    // Divides the input resolution, rounding up
+    pub res_div: (u32, u32),
+    pub format: Option<i32>,
+}
+
+impl Default for ChainedKey {
+    fn default() -> Self {
+        Self {
+            res_div: (1, 1),
+            format: None,
+        }
+    }
+}
+
+impl ChainedKey {
+    pub fn res_div(mut self, x: u32, y: u32) -> Self {
+        self.res_div = (x, y);
+        self
+    }
+
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.format = Some(format.as_raw());
+        self
+    }
+
+    fn apply(&self, key: TextureKey) -> TextureKey {
+        let mut key = key.res_div_round_up(self.res_div.0, self.res_div.1);
+        if let Some(format) = self.format {
+            key.format = format;
+        }
+        key
+    }
+}
+
+#[derive(Clone)]
+pub struct PassChain {
+    output: SnoozyRef<Texture>,
+}
+
+impl PassChain {
+    pub fn new(input: SnoozyRef<Texture>) -> Self {
+        Self { output: input }
+    }
+
+    pub fn then(self, cs: SnoozyRef<ComputeShader>, uniforms: Vec<ShaderUniformHolder>) -> Self {
+        self.then_keyed(cs, uniforms, ChainedKey::default())
+    }
+
+    pub fn then_keyed(
+        self,
+        cs: SnoozyRef<ComputeShader>,
+        uniforms: Vec<ShaderUniformHolder>,
+        output_key: ChainedKey,
+    ) -> Self {
+        Self {
+            output: chain_pass_tex(self.output, cs, uniforms, output_key),
+        }
+    }
+
+    // The output of the last pass, or the input if there are none.
+    pub fn output(self) -> SnoozyRef<Texture> {
+        self.output
+    }
+}
+
+// A single link of a `PassChain`; the output key is only known once the input is evaluated.
+#[snoozy]
+pub async fn chain_pass_tex_snoozy(
+    mut ctx: Context,
+    input: &SnoozyRef<Texture>,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    output_key: &ChainedKey,
+) -> Result<Texture> {
+    let key = output_key.apply(ctx.get(input).await?.key);
+
+    let mut uniforms = uniforms.clone();
+    uniforms.push(ShaderUniformHolder::new("inputTex", input.clone()));
+
+    let tex = ctx.get(compute_tex(key, cs.clone(), uniforms)).await?;
+    Ok((*tex).clone())
+}
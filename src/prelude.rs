@@ -0,0 +1,43 @@
+// The common subset of the API, for getting a shader on screen without hunting for
+// names. The rest of the crate is still there for everything else:
+//
+//   use rendertoy::prelude::*;
+//
+//   fn main() {
+//       Rendertoy::builder().window(1280, 720).run(|frame| {
+//           compute_tex(
+//               frame.output_key(Format::R16G16B16A16_SFLOAT),
+//               load_cs(asset!("shaders/hello.glsl")),
+//               shader_uniforms!(:frame.uniforms()),
+//           )
+//       });
+//   }
+//
+// With `shaders/hello.glsl` along the lines of:
+//
+//   layout(rgba16f) uniform image2D outputTex;
+//   layout(std140) uniform globals {
+//       float time;
+//       float time_delta;
+//       vec4 mouse;
+//       vec4 frame_size;
+//   };
+//
+//   layout (local_size_x = 8, local_size_y = 8) in;
+//   void main() {
+//       vec2 uv = (vec2(gl_GlobalInvocationID.xy) + 0.5) * frame_size.zw;
+//       imageStore(outputTex, ivec2(gl_GlobalInvocationID.xy), vec4(uv, sin(time), 1));
+//   }
+
+pub use crate::blob::{rendertoy_asset_path, AssetPath};
+pub use crate::buffer::{upload_array_buffer, Buffer};
+pub use crate::math::*;
+#[cfg(feature = "window")]
+pub use crate::rendertoy::{FrameState, Rendertoy, RendertoyBuilder, RendertoyConfig};
+pub use crate::shader::{
+    compute_tex, load_cs, raster_tex, ShaderUniformBundle, ShaderUniformHolder,
+};
+pub use crate::texture::{load_tex, Texture, TextureKey};
+pub use crate::{asset, shader_uniform_bundle, shader_uniforms};
+pub use ash::{vk, vk::Format};
+pub use snoozy::{Result, SnoozyRef};
@@ -0,0 +1,172 @@
+// Per-frame, persistently mapped buffer which readbacks get copied into, rather than
+// creating and mapping a staging buffer for each one. Regions are handed out linearly,
+// and recycled once the frame's fence is signaled, so across the frames in flight it
+// works as a ring. Readbacks which don't fit get a dedicated staging buffer instead.
+
+use crate::vulkan::vk;
+use ash::vk;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const READBACK_RING_SIZE: usize = 4 << 20;
+
+// Enough for any texel size, as required by image to buffer copies
+const REGION_ALIGNMENT: usize = 16;
+
+pub struct ReadbackRing {
+    buffer: vk::Buffer,
+    allocation: vk_mem::Allocation,
+    mapped_ptr: *mut u8,
+    write_head: AtomicUsize,
+}
+
+unsafe impl Send for ReadbackRing {}
+unsafe impl Sync for ReadbackRing {}
+
+impl ReadbackRing {
+    pub(crate) fn new(allocator: &vk_mem::Allocator) -> Self {
+        let (buffer, allocation) = create_readback_buffer(allocator, READBACK_RING_SIZE);
+        let mapped_ptr = allocator
+            .map_memory(&allocation)
+            .expect("mapping the readback ring failed");
+
+        Self {
+            buffer,
+            allocation,
+            mapped_ptr,
+            write_head: AtomicUsize::new(0),
+        }
+    }
+
+    // Falls back to a dedicated buffer when the ring is full.
+    pub(crate) fn allocate(&self, size_bytes: usize) -> ReadbackRegion {
+        let alloc_size = (size_bytes + REGION_ALIGNMENT - 1) & !(REGION_ALIGNMENT - 1);
+        let offset = self.write_head.fetch_add(alloc_size, Ordering::Relaxed);
+
+        if offset + size_bytes <= READBACK_RING_SIZE {
+            ReadbackRegion {
+                buffer: self.buffer,
+                offset,
+                size_bytes,
+                ptr: unsafe { self.mapped_ptr.add(offset) },
+                dedicated: None,
+            }
+        } else {
+            tracing::debug!("Readback ring full; allocating {} bytes", size_bytes);
+            let vk = vk();
+            let (buffer, allocation) = create_readback_buffer(&vk.allocator, size_bytes);
+            let ptr = vk
+                .allocator
+                .map_memory(&allocation)
+                .expect("mapping a readback buffer failed");
+
+            ReadbackRegion {
+                buffer,
+                offset: 0,
+                size_bytes,
+                ptr,
+                dedicated: Some(allocation),
+            }
+        }
+    }
+
+    // Must only be called once the frame which last used this ring has finished executing,
+    // and before its regions are read.
+    pub(crate) fn invalidate(&self, allocator: &vk_mem::Allocator) {
+        let used = self
+            .write_head
+            .load(Ordering::Relaxed)
+            .min(READBACK_RING_SIZE);
+        if used > 0 {
+            allocator.invalidate_allocation(&self.allocation, 0, used);
+        }
+    }
+
+    // Recycles all regions; they must have been read by now.
+    pub(crate) fn reset(&self) {
+        self.write_head.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ReadbackRing {
+    fn drop(&mut self) {
+        let vk = vk();
+        vk.allocator
+            .unmap_memory(&self.allocation)
+            .expect("unmap_memory");
+        vk.allocator
+            .destroy_buffer(self.buffer, &self.allocation)
+            .unwrap();
+    }
+}
+
+fn create_readback_buffer(
+    allocator: &vk_mem::Allocator,
+    size_bytes: usize,
+) -> (vk::Buffer, vk_mem::Allocation) {
+    let mem_info = vk_mem::AllocationCreateInfo {
+        usage: vk_mem::MemoryUsage::GpuToCpu,
+        ..Default::default()
+    };
+
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(size_bytes.max(1) as u64)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .build();
+
+    let (buffer, allocation, _allocation_info) = allocator
+        .create_buffer(&buffer_info, &mem_info)
+        .expect("vma::create_buffer");
+
+    (buffer, allocation)
+}
+
+// Destination of a single readback copy, valid until the frame it was allocated in
+// gets recycled.
+pub(crate) struct ReadbackRegion {
+    pub buffer: vk::Buffer,
+    pub offset: usize,
+    size_bytes: usize,
+    ptr: *const u8,
+    dedicated: Option<vk_mem::Allocation>,
+}
+
+unsafe impl Send for ReadbackRegion {}
+unsafe impl Sync for ReadbackRegion {}
+
+impl ReadbackRegion {
+    // Must only be called once the frame has finished executing. Trailing bytes which
+    // don't fill a whole `T` are zero-padded.
+    pub(crate) fn read<T: Copy + Default>(&self) -> Vec<T> {
+        if let Some(allocation) = self.dedicated.as_ref() {
+            vk().allocator
+                .invalidate_allocation(allocation, 0, self.size_bytes);
+        }
+
+        let len = (self.size_bytes + size_of::<T>() - 1) / size_of::<T>();
+        let mut contents = vec![T::default(); len];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.ptr,
+                contents.as_mut_ptr() as *mut u8,
+                self.size_bytes,
+            );
+        }
+        contents
+    }
+}
+
+impl Drop for ReadbackRegion {
+    fn drop(&mut self) {
+        if let Some(allocation) = self.dedicated.take() {
+            let vk = vk();
+            vk.allocator
+                .unmap_memory(&allocation)
+                .expect("unmap_memory");
+            vk.allocator
+                .destroy_buffer(self.buffer, &allocation)
+                .unwrap();
+        }
+    }
+}
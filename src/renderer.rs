@@ -1,7 +1,23 @@
+use crate::background_compute;
+use crate::edit_preview;
+use crate::external_commands::{
+    record_external_commands, ExternalCommandsContext, ExternalCommandsPoint,
+};
+use crate::frame_budget;
+use crate::frame_telemetry;
 use crate::gpu_debugger;
 use crate::gpu_profiler::{self, GpuProfilerStats};
+use crate::gpu_workload;
+use crate::op_metrics;
+use crate::resource_lifetime;
 use crate::shader;
+use crate::shader_ab;
+use crate::shader_cache;
+use crate::shader_compile_queue;
+use crate::subgraph_process;
+use crate::texture_history;
 use crate::vulkan::*;
+use crate::workgroup_autotune;
 use ash::version::DeviceV1_0;
 use ash::vk;
 use std::sync::Arc;
@@ -22,11 +38,11 @@ pub enum RenderFrameStatus {
 impl Renderer {
     pub fn new(
         window: Arc<winit::Window>,
-        graphics_debugging: bool,
+        validation: ValidationOptions,
         vsync: bool,
         device_index: usize,
     ) -> Self {
-        initialize_vulkan_backend(&window, graphics_debugging, vsync, device_index);
+        initialize_vulkan_backend(&window, validation, vsync, device_index);
 
         let (present_descriptor_sets, present_pipeline) =
             Self::create_present_descriptor_sets_and_pipeline();
@@ -80,7 +96,15 @@ impl Renderer {
         vk_state().end_frame();
 
         gpu_profiler::end_frame();
+        op_metrics::end_frame();
+        frame_telemetry::end_frame();
         gpu_debugger::end_frame();
+        background_compute::end_frame();
+        gpu_workload::end_frame();
+        shader_compile_queue::end_frame();
+        workgroup_autotune::end_frame();
+        shader_cache::end_frame();
+        resource_lifetime::end_frame();
 
         self.gpu_profiler_stats = Some(gpu_profiler::get_stats());
         RenderFrameStatus::Ok
@@ -118,6 +142,16 @@ impl Renderer {
                     .with_discard(true),
                 );
 
+                record_external_commands(
+                    ExternalCommandsPoint::BeforeGraph,
+                    &ExternalCommandsContext {
+                        device: &vk.device,
+                        cb: vk_state().current_frame().command_buffer.lock().unwrap().cb,
+                        final_texture: None,
+                        swapchain_image: None,
+                    },
+                );
+
                 let (final_texture_view, gui_texture_view) = callback(self);
 
                 let cb = vk_state().current_frame().command_buffer.lock().unwrap().cb;
@@ -195,6 +229,16 @@ impl Renderer {
                     );
                 }
 
+                record_external_commands(
+                    ExternalCommandsPoint::BeforePresent,
+                    &ExternalCommandsContext {
+                        device: &vk.device,
+                        cb,
+                        final_texture: None,
+                        swapchain_image: Some(present_image),
+                    },
+                );
+
                 record_image_barrier(
                     &vk.device,
                     cb,
@@ -209,10 +253,26 @@ impl Renderer {
 
         crate::vulkan::end_render_frame(&fs);
 
+        #[cfg(feature = "openxr")]
+        crate::xr::end_frame();
+
         vk_state().end_frame();
 
         gpu_profiler::end_frame();
+        op_metrics::end_frame();
+        frame_telemetry::end_frame();
+        frame_budget::end_frame();
+        edit_preview::end_frame();
+        shader_ab::end_frame();
+        subgraph_process::end_frame();
+        texture_history::end_frame();
         gpu_debugger::end_frame();
+        background_compute::end_frame();
+        gpu_workload::end_frame();
+        shader_compile_queue::end_frame();
+        workgroup_autotune::end_frame();
+        shader_cache::end_frame();
+        resource_lifetime::end_frame();
 
         self.gpu_profiler_stats = Some(gpu_profiler::get_stats());
         RenderFrameStatus::Ok
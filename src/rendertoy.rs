@@ -1,15 +1,21 @@
-use crate::gpu_debugger;
+use crate::external_commands::{ExternalCommandsContext, ExternalCommandsPoint};
+use crate::frame_step::FrameStep;
+use crate::gpu_debugger::{self, TexelKind};
 use crate::gpu_profiler::GpuProfilerStats;
 use crate::gui::ImGuiBackend;
 use crate::keyboard::*;
 use crate::renderer::{RenderFrameStatus, Renderer};
+use crate::shader::{ShaderUniformBundle, ShaderUniformHolder};
+use crate::shader_uniforms;
 use crate::texture::{Texture, TextureKey};
+use crate::vk_render_device::ValidationOptions;
 use crate::vulkan;
 use crate::Vec2;
 use ash::vk;
 use clap::ArgMatches;
 use imgui::im_str;
 use snoozy::{get_snapshot, OpaqueSnoozyRef, Result, SnoozyRef};
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
@@ -21,12 +27,15 @@ struct RendertoyState {
     keyboard: KeyboardState,
     selected_debug_name: Option<String>,
     locked_debug_name: Option<String>,
+    show_debugged_depth: bool,
     last_frame_instant: std::time::Instant,
     dt: f32,
     show_gui: bool,
     average_frame_time: f32,
     frame_time_display_cooldown: f32,
     dump_next_frame_dot_graph: bool,
+    // Re-displayed while frame stepping is halted
+    last_frame_tex: Option<SnoozyRef<Texture>>,
     initialization_instant: std::time::Instant,
     time_to_first_frame: Option<std::time::Duration>,
 }
@@ -71,6 +80,49 @@ pub struct FrameState<'a> {
     pub keys: &'a KeyboardState,
     pub window_size_pixels: (u32, u32),
     pub dt: f32,
+    // Playback time in seconds; see `time_control`
+    pub time: f32,
+}
+
+impl<'a> FrameState<'a> {
+    // Mouse position normalized to [0, 1] over the window
+    pub fn mouse_uv(&self) -> Vec2 {
+        Vec2::new(
+            self.mouse.pos.x() / self.window_size_pixels.0.max(1) as f32,
+            self.mouse.pos.y() / self.window_size_pixels.1.max(1) as f32,
+        )
+    }
+
+    // A texture the size of the window
+    pub fn output_key(&self, format: vk::Format) -> TextureKey {
+        TextureKey::new(self.window_size_pixels.0, self.window_size_pixels.1, format)
+    }
+
+    // Frame inputs for shaders, as `time`, `time_delta`, `mouse` and `frame_size`:
+    //
+    //   float time;
+    //   float time_delta;
+    //   vec4 mouse;         // UV, then the left and right buttons as 0 or 1
+    //   vec4 frame_size;    // Pixels, then their reciprocals
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        let mouse_uv = self.mouse_uv();
+        let (width, height) = (
+            self.window_size_pixels.0.max(1) as f32,
+            self.window_size_pixels.1.max(1) as f32,
+        );
+
+        shader_uniforms!(
+            time: self.time,
+            time_delta: self.dt,
+            mouse: (
+                mouse_uv.x(),
+                mouse_uv.y(),
+                (self.mouse.button_mask & 1) as f32,
+                ((self.mouse.button_mask >> 2) & 1) as f32,
+            ),
+            frame_size: (width, height, 1.0 / width, 1.0 / height),
+        )
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -78,8 +130,81 @@ pub struct RendertoyConfig {
     pub width: u32,
     pub height: u32,
     pub vsync: bool,
-    pub graphics_debugging: bool,
+    pub validation: ValidationOptions,
     pub device_index: usize,
+    pub shader_instrumentation: bool,
+    pub deterministic_math: bool,
+    pub xr: bool,
+}
+
+impl Default for RendertoyConfig {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            vsync: true,
+            validation: if cfg!(debug_assertions) {
+                ValidationOptions::standard()
+            } else {
+                ValidationOptions::default()
+            },
+            device_index: 0,
+            shader_instrumentation: false,
+            deterministic_math: false,
+            xr: false,
+        }
+    }
+}
+
+// Sets up a `Rendertoy` in code, rather than from the command line:
+//
+//   Rendertoy::builder().window(1280, 720).run(|frame| {
+//       compute_tex(
+//           frame.output_key(Format::R16G16B16A16_SFLOAT),
+//           load_cs(asset!("shaders/hello.glsl")),
+//           shader_uniforms!(:frame.uniforms()),
+//       )
+//   });
+pub struct RendertoyBuilder {
+    cfg: RendertoyConfig,
+}
+
+impl RendertoyBuilder {
+    pub fn window(mut self, width: u32, height: u32) -> Self {
+        self.cfg.width = width;
+        self.cfg.height = height;
+        self
+    }
+
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.cfg.vsync = vsync;
+        self
+    }
+
+    pub fn validation(mut self, validation: ValidationOptions) -> Self {
+        self.cfg.validation = validation;
+        self
+    }
+
+    pub fn device_index(mut self, device_index: usize) -> Self {
+        self.cfg.device_index = device_index;
+        self
+    }
+
+    pub fn config(mut self, cfg: RendertoyConfig) -> Self {
+        self.cfg = cfg;
+        self
+    }
+
+    pub fn build(self) -> Rendertoy {
+        Rendertoy::new_with_config(self.cfg)
+    }
+
+    // Opens the window, and presents the texture returned by `callback` every frame,
+    // until the window is closed.
+    pub fn run(self, callback: impl FnMut(&FrameState) -> SnoozyRef<Texture>) {
+        self.build().draw_forever(callback)
+    }
 }
 
 fn parse_resolution(s: &str) -> Result<(u32, u32)> {
@@ -111,19 +236,30 @@ impl RendertoyConfig {
             })
             .unwrap_or(true);
 
-        let graphics_debugging = !matches.is_present("ndebug");
+        let validation = ValidationOptions {
+            standard: !matches.is_present("ndebug"),
+            gpu_assisted: matches.is_present("gpu-validation"),
+            best_practices: matches.is_present("best-practices"),
+        };
 
         let device_index = matches
             .value_of("device-index")
             .map(|val| FromStr::from_str(val).expect("Failed to parse device index"))
             .unwrap_or(0);
 
+        let shader_instrumentation = matches.is_present("instrument-shaders");
+        let deterministic_math = matches.is_present("deterministic");
+        let xr = matches.is_present("xr");
+
         RendertoyConfig {
             width,
             height,
             vsync,
-            graphics_debugging,
+            validation,
             device_index,
+            shader_instrumentation,
+            deterministic_math,
+            xr,
         }
     }
 }
@@ -146,12 +282,25 @@ impl Rendertoy {
             .expect("window");
         let window = Arc::new(window);
 
-        let mut renderer = Renderer::new(
-            window.clone(),
-            cfg.graphics_debugging,
-            cfg.vsync,
-            cfg.device_index,
+        crate::shader_instrumentation::set_shader_instrumentation_enabled(
+            cfg.shader_instrumentation,
         );
+        crate::deterministic_math::set_deterministic_math_enabled(cfg.deterministic_math);
+
+        #[cfg(feature = "openxr")]
+        {
+            if cfg.xr {
+                crate::xr::initialize_instance().expect("OpenXR initialization failed");
+            }
+        }
+        #[cfg(not(feature = "openxr"))]
+        assert!(!cfg.xr, "Rendertoy was built without the `openxr` feature");
+
+        let mut renderer =
+            Renderer::new(window.clone(), cfg.validation, cfg.vsync, cfg.device_index);
+
+        #[cfg(feature = "openxr")]
+        crate::xr::create_session().expect("OpenXR session creation failed");
 
         let mut imgui = imgui::Context::create();
         let mut imgui_backend = ImGuiBackend::new(&window, &mut imgui);
@@ -176,12 +325,14 @@ impl Rendertoy {
                 keyboard: KeyboardState::new(),
                 selected_debug_name: None,
                 locked_debug_name: None,
+                show_debugged_depth: false,
                 last_frame_instant: std::time::Instant::now(),
                 dt: 0.0,
                 show_gui: true,
                 average_frame_time: 0.0,
                 frame_time_display_cooldown: 0.0,
                 dump_next_frame_dot_graph: false,
+                last_frame_tex: None,
                 initialization_instant: std::time::Instant::now(),
                 time_to_first_frame: None,
             },
@@ -194,6 +345,13 @@ impl Rendertoy {
         }
     }
 
+    // Configured in code; see `RendertoyBuilder`. `new` takes the command line instead.
+    pub fn builder() -> RendertoyBuilder {
+        RendertoyBuilder {
+            cfg: RendertoyConfig::default(),
+        }
+    }
+
     pub fn new() -> Rendertoy {
         let matches = clap::App::new("Rendertoy")
             .version("1.0")
@@ -221,8 +379,144 @@ impl Rendertoy {
                     .long("ndebug")
                     .help("Disable graphics debugging"),
             )
+            .arg(
+                clap::Arg::with_name("gpu-validation")
+                    .long("gpu-validation")
+                    .help("Enable GPU-assisted validation, e.g. for descriptor indexing bugs"),
+            )
+            .arg(
+                clap::Arg::with_name("best-practices")
+                    .long("best-practices")
+                    .help("Enable best practices validation"),
+            )
+            .arg(
+                clap::Arg::with_name("instrument-shaders")
+                    .long("instrument-shaders")
+                    .help("Insert bounds and NaN checks into shaders"),
+            )
+            .arg(
+                clap::Arg::with_name("deterministic")
+                    .long("deterministic")
+                    .help("Compile shaders for bit-reproducible output, with fixed RNG seeds"),
+            )
+            .arg(
+                clap::Arg::with_name("xr")
+                    .long("xr")
+                    .help("Output to an OpenXR headset; expects side-by-side stereo frames"),
+            )
+            .arg(
+                clap::Arg::with_name("warp-calibration")
+                    .long("warp-calibration")
+                    .help("Warp and edge-blend the output according to a calibration file")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("edit-preview")
+                    .long("edit-preview")
+                    .help("Render preview passes at reduced resolution while editing"),
+            )
+            .arg(
+                clap::Arg::with_name("hot-swap-shaders")
+                    .long("hot-swap-shaders")
+                    .help("Keep rendering with the old version of shaders while edits compile"),
+            )
+            .arg(
+                clap::Arg::with_name("step")
+                    .long("step")
+                    .help("Start halted, and advance one frame per press of F10"),
+            )
+            .arg(
+                clap::Arg::with_name("record")
+                    .long("record")
+                    .help("Encode the output to an H.264 video file, e.g. capture.mp4")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("record-fps")
+                    .long("record-fps")
+                    .help("Frame rate of the recorded video; 60 by default")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("telemetry")
+                    .long("telemetry")
+                    .help("Record frame timings and memory usage to a CSV or JSON file")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("telemetry-seconds")
+                    .long("telemetry-seconds")
+                    .help("Quit after recording telemetry for this many seconds")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("sync-master")
+                    .long("sync-master")
+                    .help("Send tweaks and time to comma-separated client addresses")
+                    .takes_value(true),
+            )
+            .arg(
+                clap::Arg::with_name("sync-client")
+                    .long("sync-client")
+                    .help("Receive tweaks and time from a master on the given address")
+                    .takes_value(true),
+            )
             .get_matches();
 
+        if let Some(clients) = matches.value_of("sync-master") {
+            let clients = clients
+                .split(',')
+                .map(|addr| FromStr::from_str(addr.trim()).expect("Failed to parse client address"))
+                .collect();
+            crate::net_sync::start_net_sync_master("0.0.0.0:0".parse().unwrap(), clients)
+                .expect("start_net_sync_master");
+        }
+
+        if let Some(addr) = matches.value_of("sync-client") {
+            let addr = FromStr::from_str(addr).expect("Failed to parse sync address");
+            crate::net_sync::start_net_sync_client(addr).expect("start_net_sync_client");
+        }
+
+        if matches.is_present("edit-preview") {
+            crate::edit_preview::set_edit_preview_enabled(true);
+        }
+
+        if matches.is_present("hot-swap-shaders") {
+            crate::shader_hot_swap::set_shader_hot_swap_enabled(true);
+        }
+
+        if matches.is_present("step") {
+            crate::frame_step::set_frame_stepping_enabled(true);
+        }
+
+        if let Some(path) = matches.value_of("record") {
+            let fps = matches
+                .value_of("record-fps")
+                .map(|val| {
+                    FromStr::from_str(val).expect("Failed to parse the recording frame rate")
+                })
+                .unwrap_or(60);
+            crate::video_capture::start_video_capture(
+                path,
+                crate::video_capture::VideoCodec::H264,
+                fps,
+            );
+        }
+
+        if let Some(path) = matches.value_of("telemetry") {
+            let duration = matches.value_of("telemetry-seconds").map(|val| {
+                std::time::Duration::from_secs_f32(
+                    FromStr::from_str(val).expect("Failed to parse telemetry duration"),
+                )
+            });
+            crate::frame_telemetry::start_frame_telemetry(path, duration);
+        }
+
+        if let Some(path) = matches.value_of("warp-calibration") {
+            crate::output_warp::load_output_warp_calibration(path)
+                .expect("load_output_warp_calibration");
+        }
+
         Self::new_with_config(RendertoyConfig::from_args(&matches))
     }
 
@@ -264,6 +558,20 @@ impl Rendertoy {
                             if input.state == ElementState::Pressed {
                                 self.state.show_gui = !self.state.show_gui;
                             }
+                        } else if input.virtual_keycode == Some(VirtualKeyCode::F9) {
+                            if input.state == ElementState::Pressed {
+                                crate::frame_step::set_frame_stepping_enabled(
+                                    !crate::frame_step::is_frame_stepping_enabled(),
+                                );
+                            }
+                        } else if input.virtual_keycode == Some(VirtualKeyCode::F10) {
+                            if input.state == ElementState::Pressed {
+                                crate::frame_step::step_frame();
+                            }
+                        } else if input.virtual_keycode == Some(VirtualKeyCode::F12) {
+                            if input.state == ElementState::Pressed {
+                                crate::screenshot::request_screenshot();
+                            }
                         } else {
                             keyboard_events.push(*input);
                         }
@@ -342,6 +650,20 @@ impl Rendertoy {
                     {
                         state.dump_next_frame_dot_graph =
                             ui.button(im_str!("Dump frame.dot"), [0.0, 0.0]);
+
+                        let mut stepping = crate::frame_step::is_frame_stepping_enabled();
+                        if ui.checkbox(im_str!("Single-step (F9)"), &mut stepping) {
+                            crate::frame_step::set_frame_stepping_enabled(stepping);
+                        }
+                        if stepping {
+                            ui.same_line(0.0);
+                            if ui.button(im_str!("Step (F10)"), [0.0, 0.0]) {
+                                crate::frame_step::step_frame();
+                            }
+                        }
+                        if ui.button(im_str!("Screenshot (F12)"), [0.0, 0.0]) {
+                            crate::screenshot::request_screenshot();
+                        }
                         ui.spacing();
 
                         if ui
@@ -358,6 +680,50 @@ impl Rendertoy {
                                     &currently_debugged_texture,
                                 );
                             }
+
+                            if currently_debugged_texture.is_some() {
+                                ui.checkbox(im_str!("Show depth"), &mut state.show_debugged_depth);
+                                if let Some(probe) = gpu_debugger::last_texel_probe() {
+                                    ui.text(probe.describe());
+                                }
+                            }
+                        }
+
+                        let metrics = crate::op_metrics::op_metric_stats();
+                        if !metrics.is_empty() && ui.collapsing_header(im_str!("Metrics")).build() {
+                            for metric in metrics {
+                                ui.text(format!(
+                                    "{}: {} (avg {:.2})",
+                                    metric.name, metric.last, metric.avg
+                                ));
+                            }
+                        }
+
+                        let ab_results = crate::shader_ab::shader_ab_results();
+                        if !ab_results.is_empty()
+                            && ui.collapsing_header(im_str!("Shader A/B")).build()
+                        {
+                            for result in ab_results {
+                                ui.text(result.describe());
+                            }
+                        }
+
+                        if let Some(calibration) = crate::output_warp::output_warp_calibration() {
+                            if ui.collapsing_header(im_str!("Output warp")).build() {
+                                RendertoyState::draw_output_warp_editor(&ui, calibration);
+                            }
+                        }
+
+                        let compile_progress =
+                            crate::shader_compile_queue::shader_compile_progress();
+                        if compile_progress.pending + compile_progress.compiling > 0 {
+                            ui.text(format!(
+                                "Compiling shaders: {}/{}",
+                                compile_progress.completed,
+                                compile_progress.completed
+                                    + compile_progress.compiling
+                                    + compile_progress.pending
+                            ));
                         }
 
                         crate::warnings::with_drain_warnings(|warnings| {
@@ -395,8 +761,14 @@ impl Rendertoy {
                 imgui_backend.create_graphics_resources();
             }
 
-            running = self.next_frame();
+            running = self.next_frame() && !crate::frame_telemetry::is_frame_telemetry_finished();
+        }
+
+        if let Err(err) = crate::frame_telemetry::finish_frame_telemetry() {
+            tracing::error!("Failed to write frame telemetry: {}", err);
         }
+
+        crate::video_capture::stop_video_capture();
     }
 }
 
@@ -437,28 +809,101 @@ impl RendertoyState {
             );
         }
 
+        #[cfg(feature = "openxr")]
+        crate::xr::begin_frame();
+
+        let frame_dt = match crate::frame_step::next_frame_step() {
+            FrameStep::Run => Some(self.dt),
+            FrameStep::Step(dt) => Some(dt),
+            // Something has to be drawn before there's a frame to halt on
+            FrameStep::Halt if self.last_frame_tex.is_none() => Some(0.0),
+            FrameStep::Halt => None,
+        };
+
+        gpu_debugger::set_debugged_texture(
+            self.get_currently_debugged_texture(),
+            self.show_debugged_depth,
+        );
+
+        let time = match frame_dt {
+            // Synchronized clients follow the master's clock instead of their own
+            Some(dt) => match crate::net_sync::net_sync_time() {
+                Some(time) => crate::time_control::follow_time(time),
+                None => crate::time_control::advance_time(dt),
+            },
+            None => crate::time_control::playback_time(),
+        };
+        crate::net_sync::publish_playback_time(time, crate::time_control::time_rate());
+
         let state = FrameState {
             mouse: &self.mouse_state,
             keys: &self.keyboard,
             window_size_pixels,
-            dt: self.dt,
+            dt: frame_dt.unwrap_or(0.0),
+            time: time as f32,
         };
 
-        let tex = callback(&state);
-
-        let final_texture = {
-            let tex = tex.clone();
-            self.rt.try_lock().unwrap().block_on(async move {
-                let snapshot = get_snapshot(move |f| {
-                    tokio::task::spawn(async move {
-                        f();
-                    });
-                });
-                let final_texture: Texture = (*snapshot.get(tex).await).clone();
-                final_texture.view
-            })
+        // Halted frames skip the callback, so time and inputs stand still
+        let tex = match frame_dt {
+            Some(_) => {
+                let tex = callback(&state);
+                let tex = if crate::output_warp::is_output_warp_enabled() {
+                    crate::output_warp::warp_output_tex(tex)
+                } else {
+                    tex
+                };
+                self.last_frame_tex = Some(tex.clone());
+                tex
+            }
+            None => self.last_frame_tex.clone().unwrap(),
         };
 
+        let final_texture = self.evaluate(tex.clone());
+
+        #[cfg(feature = "openxr")]
+        crate::xr::submit_stereo_texture(&final_texture);
+
+        {
+            let vk = vulkan::vk();
+            let vk_state = vulkan::vk_state();
+            let cb = vk_state.current_frame().command_buffer.lock().unwrap();
+            crate::external_commands::record_external_commands(
+                ExternalCommandsPoint::AfterGraph,
+                &ExternalCommandsContext {
+                    device: &vk.device,
+                    cb: cb.cb,
+                    final_texture: Some(&final_texture),
+                    swapchain_image: None,
+                },
+            );
+        }
+
+        if crate::video_capture::is_video_capture_active() {
+            let packed = self.evaluate(crate::video_capture::pack_video_frame(
+                tex.clone(),
+                &final_texture.key,
+            ));
+            crate::video_capture::capture_video_frame(
+                &packed,
+                (final_texture.key.width, final_texture.key.height),
+            );
+        }
+
+        if let Some(format) = crate::screenshot::take_screenshot_request() {
+            let packed = self.evaluate(crate::texture::pack_tex_for_saving(
+                tex.clone(),
+                &final_texture.key,
+                format,
+            ));
+            crate::screenshot::save_screenshot(
+                &packed,
+                (final_texture.key.width, final_texture.key.height),
+                format,
+            );
+        }
+
+        let final_texture = final_texture.view;
+
         if self.time_to_first_frame.is_none() {
             self.time_to_first_frame = Some(self.initialization_instant.elapsed());
         }
@@ -467,7 +912,7 @@ impl RendertoyState {
             self.dump_next_frame_dot_graph = false;
             let dot = generate_dot_graph_from_snoozy_ref(
                 tex.into(),
-                Some(&["compute_tex", "raster_tex"]),
+                Some(&["compute_tex", "raster_tex", "raster_chain_tex"]),
                 &[],
                 Some("rankdir = BT"),
             );
@@ -479,19 +924,64 @@ impl RendertoyState {
             file.write_all(dot.as_bytes()).expect("file.write_all");
         }
 
-        let debugged_texture = {
-            let mut debugged_texture: Option<vk::ImageView> = None;
-            gpu_debugger::with_textures(|data| {
-                debugged_texture = self
-                    .get_currently_debugged_texture()
-                    .and_then(|name| data.textures.get(&name).cloned());
-            });
-            debugged_texture
+        let debugged_texture = match gpu_debugger::displayed_texture() {
+            Some((tex, kind)) => {
+                // The probed texel is under the cursor, as the texture is stretched to the window
+                let uv = state.mouse_uv();
+                gpu_debugger::probe_texel((
+                    (uv.x().max(0.0) * tex.key.width as f32) as u32,
+                    (uv.y().max(0.0) * tex.key.height as f32) as u32,
+                ));
+
+                if kind == TexelKind::Float {
+                    Some(tex.view)
+                } else {
+                    Some(self.evaluate(gpu_debugger::debugged_tex_display()).view)
+                }
+            }
+            None => None,
         };
 
         debugged_texture.unwrap_or(final_texture)
     }
 
+    fn evaluate<T: Clone + Send + Sync + 'static>(&self, r: SnoozyRef<T>) -> T {
+        self.rt.try_lock().unwrap().block_on(async move {
+            let snapshot = get_snapshot(move |f| {
+                tokio::task::spawn(async move {
+                    f();
+                });
+            });
+            (*snapshot.get(r).await).clone()
+        })
+    }
+
+    fn draw_output_warp_editor(
+        ui: &imgui::Ui,
+        mut calibration: crate::output_warp::WarpCalibration,
+    ) {
+        for (i, point) in calibration.points.iter_mut().enumerate() {
+            let mut value = [point.x(), point.y()];
+            if ui.input_float2(&im_str!("Point {}", i), &mut value).build() {
+                *point = Vec2::new(value[0], value[1]);
+            }
+        }
+
+        ui.input_float4(im_str!("Blend L R T B"), &mut calibration.blend_widths)
+            .build();
+        ui.input_float(im_str!("Blend power"), &mut calibration.blend_power)
+            .build();
+
+        let save = ui.button(im_str!("Save calibration"), [0.0, 0.0]);
+        crate::output_warp::set_output_warp_calibration(Some(calibration));
+
+        if save {
+            if let Err(err) = crate::output_warp::save_output_warp_calibration() {
+                tracing::error!("Failed to save the warp calibration: {}", err);
+            }
+        }
+    }
+
     fn draw_profiling_stats(
         ui: &imgui::Ui,
         average_frame_time: f32,
@@ -511,9 +1001,51 @@ impl RendertoyState {
         ));
         //let mut total_time_ms = 0.0;
 
-        for (_scope_id, scope) in stats.scopes.iter() {
-            let text = format!("{}: {:.3}ms", scope.name, scope.average_duration_millis());
-            //let text = &scope.name;
+        // Group totals include nested groups
+        let mut group_totals: HashMap<&str, f64> = HashMap::new();
+        for scope in stats.scopes.values() {
+            if let Some(group) = scope.tags.group() {
+                for (i, _) in group.match_indices('/') {
+                    *group_totals.entry(&group[..i]).or_default() +=
+                        scope.average_duration_millis();
+                }
+                *group_totals.entry(group).or_default() += scope.average_duration_millis();
+            }
+        }
+
+        // Grouped passes go under their group headers, indented by depth
+        let mut scopes: Vec<_> = stats.scopes.values().collect();
+        scopes.sort_by(|a, b| (a.tags.group(), &a.name).cmp(&(b.tags.group(), &b.name)));
+
+        let mut open_groups: Vec<&str> = Vec::new();
+        for scope in scopes {
+            let group: Vec<&str> = scope
+                .tags
+                .group()
+                .map_or(Vec::new(), |g| g.split('/').collect());
+            let common = open_groups
+                .iter()
+                .zip(group.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            open_groups.truncate(common);
+
+            for component in &group[common..] {
+                let indent = "  ".repeat(open_groups.len());
+                open_groups.push(component);
+                let total = group_totals
+                    .get(open_groups.join("/").as_str())
+                    .copied()
+                    .unwrap_or_default();
+                ui.text(format!("{}{}: {:.3}ms", indent, component, total));
+            }
+
+            let text = format!(
+                "{}{}: {:.3}ms",
+                "  ".repeat(open_groups.len()),
+                scope.name,
+                scope.average_duration_millis()
+            );
 
             let style = if Some(&scope.name) == currently_debugged_texture.as_ref() {
                 Some(ui.push_style_color(imgui::StyleColor::Text, [1.0, 0.25, 0.0625, 1.0]))
@@ -594,7 +1126,6 @@ fn generate_dot_graph_from_snoozy_ref(
     };
 
     use petgraph::*;
-    use std::collections::HashMap;
 
     let mut node_indices: HashMap<usize, _> = HashMap::new();
     let root_name = get_node_name(&root.inner);
@@ -626,7 +1157,20 @@ fn generate_dot_graph_from_snoozy_ref(
         }
     }
 
-    let dot = crate::dot::Dot::new(&graph, dot_graph_attribs);
+    // Grouped passes are named e.g. `[GI/Diffuse] blur`; draw each group as a cluster
+    let mut clusters: HashMap<String, Vec<usize>> = HashMap::new();
+    for idx in graph.node_indices() {
+        let name = &graph[idx];
+        if let (true, Some(end)) = (name.starts_with('['), name.find("] ")) {
+            clusters
+                .entry(name[1..end].to_owned())
+                .or_default()
+                .push(idx.index());
+        }
+    }
+
+    let dot = crate::dot::Dot::new(&graph, dot_graph_attribs)
+        .with_clusters(clusters.into_iter().collect());
     format!("{}", dot)
 }
 
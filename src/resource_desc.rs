@@ -0,0 +1,150 @@
+// Stable, versioned serde representations of `TextureKey` and `BufferKey`, for saved
+// scene and graph files. The keys themselves derive `Serialize` for content hashing,
+// which follows their in-memory layout; these don't change along with it.
+//
+// Fields added in later versions come with defaults, so older files keep loading.
+// Unknown fields are ignored, so newer files load as far as they're understood.
+
+use crate::backend::texture::TextureType;
+use crate::buffer::BufferKey;
+use crate::texture::TextureKey;
+use snoozy::*;
+
+pub const RESOURCE_DESC_VERSION: u32 = 1;
+
+fn default_version() -> u32 {
+    1
+}
+
+fn default_extent() -> u32 {
+    1
+}
+
+fn default_tex_type() -> String {
+    "2d".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TextureDesc {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub width: u32,
+    #[serde(default = "default_extent")]
+    pub height: u32,
+    // Layer count for "2d_array"
+    #[serde(default = "default_extent")]
+    pub depth: u32,
+    // The raw `VkFormat`, which is stable across Vulkan versions
+    pub format: i32,
+    // "2d", "3d" or "2d_array"
+    #[serde(default = "default_tex_type")]
+    pub tex_type: String,
+}
+
+impl From<TextureKey> for TextureDesc {
+    fn from(key: TextureKey) -> Self {
+        let tex_type = match key.tex_type {
+            TextureType::Type2D => "2d",
+            TextureType::Type3D => "3d",
+            TextureType::Type2DArray => "2d_array",
+            TextureType::Cube => "cube",
+        };
+
+        Self {
+            version: RESOURCE_DESC_VERSION,
+            width: key.width,
+            height: key.height,
+            depth: key.depth,
+            format: key.format,
+            tex_type: tex_type.to_owned(),
+        }
+    }
+}
+
+impl TextureDesc {
+    pub fn to_key(&self) -> Result<TextureKey> {
+        warn_if_newer("texture", self.version);
+
+        let tex_type = match self.tex_type.as_str() {
+            "2d" => TextureType::Type2D,
+            "3d" => TextureType::Type3D,
+            "2d_array" => TextureType::Type2DArray,
+            "cube" => TextureType::Cube,
+            other => bail!("Unknown texture type {:?}", other),
+        };
+
+        if self.width == 0 || self.height == 0 || self.depth == 0 {
+            bail!(
+                "Invalid texture extent {}x{}x{}",
+                self.width,
+                self.height,
+                self.depth
+            );
+        }
+
+        if tex_type == TextureType::Cube && (self.width != self.height || self.depth != 6) {
+            bail!(
+                "Cube maps need six square faces; got {}x{}x{}",
+                self.width,
+                self.height,
+                self.depth
+            );
+        }
+
+        Ok(TextureKey {
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            format: self.format,
+            tex_type,
+            aliasable: false,
+            input_attachment: false,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BufferDesc {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub size_bytes: u64,
+    // The raw `VkFormat` of texel buffers
+    #[serde(default)]
+    pub texture_format: Option<i32>,
+}
+
+impl From<BufferKey> for BufferDesc {
+    fn from(key: BufferKey) -> Self {
+        Self {
+            version: RESOURCE_DESC_VERSION,
+            size_bytes: key.size_bytes as u64,
+            texture_format: key.texture_format,
+        }
+    }
+}
+
+impl BufferDesc {
+    pub fn to_key(&self) -> Result<BufferKey> {
+        warn_if_newer("buffer", self.version);
+
+        if self.size_bytes > std::usize::MAX as u64 {
+            bail!("Buffer size {} does not fit in memory", self.size_bytes);
+        }
+
+        Ok(BufferKey {
+            size_bytes: self.size_bytes as usize,
+            texture_format: self.texture_format,
+        })
+    }
+}
+
+fn warn_if_newer(what: &str, version: u32) {
+    if version > RESOURCE_DESC_VERSION {
+        tracing::warn!(
+            "Loading a version {} {} descriptor; only version {} is fully understood",
+            version,
+            what,
+            RESOURCE_DESC_VERSION
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// Capture of transient resource lifetimes: when each allocation gets created, handed out
+// from the pool, used by passes, and returned to the pool. Saved in the Chrome trace
+// event format, viewable in chrome://tracing or https://ui.perfetto.dev, with one track
+// per allocation. Gaps on a track are where the memory sat unused in the pool, and
+// resources with identical descriptions and disjoint lifetimes could have shared one.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+enum ResourceEventKind {
+    Acquire { desc: String, new: bool },
+    Release,
+    Use { pass: String },
+    FrameEnd { frame: u64 },
+}
+
+struct ResourceEvent {
+    allocation_id: u64,
+    time: Instant,
+    kind: ResourceEventKind,
+}
+
+struct ResourceLifetimeCapture {
+    enabled: bool,
+    start: Instant,
+    frame: u64,
+    events: Vec<ResourceEvent>,
+}
+
+lazy_static! {
+    static ref RESOURCE_LIFETIMES: Mutex<ResourceLifetimeCapture> =
+        Mutex::new(ResourceLifetimeCapture {
+            enabled: false,
+            start: Instant::now(),
+            frame: 0,
+            events: Vec::new(),
+        });
+}
+
+// Enabling discards anything captured previously.
+pub fn set_resource_lifetime_capture(enabled: bool) {
+    let mut capture = RESOURCE_LIFETIMES.lock().unwrap();
+    if enabled && !capture.enabled {
+        capture.start = Instant::now();
+        capture.frame = 0;
+        capture.events.clear();
+    }
+    capture.enabled = enabled;
+}
+
+fn record(allocation_id: u64, kind: impl FnOnce() -> ResourceEventKind) {
+    let mut capture = RESOURCE_LIFETIMES.lock().unwrap();
+    if capture.enabled {
+        capture.events.push(ResourceEvent {
+            allocation_id,
+            time: Instant::now(),
+            kind: kind(),
+        });
+    }
+}
+
+pub(crate) fn record_acquire(allocation_id: u64, desc: &dyn std::fmt::Debug, new: bool) {
+    record(allocation_id, || ResourceEventKind::Acquire {
+        desc: format!("{:?}", desc),
+        new,
+    });
+}
+
+pub(crate) fn record_release(allocation_id: u64) {
+    record(allocation_id, || ResourceEventKind::Release);
+}
+
+pub(crate) fn record_use(allocation_id: u64, pass: &str) {
+    record(allocation_id, || ResourceEventKind::Use {
+        pass: pass.to_owned(),
+    });
+}
+
+pub(crate) fn end_frame() {
+    let mut capture = RESOURCE_LIFETIMES.lock().unwrap();
+    if capture.enabled {
+        let frame = capture.frame;
+        capture.frame += 1;
+        capture.events.push(ResourceEvent {
+            allocation_id: 0,
+            time: Instant::now(),
+            kind: ResourceEventKind::FrameEnd { frame },
+        });
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+// Writes everything captured so far to `path`.
+pub fn save_resource_lifetime_trace(path: &str) -> std::io::Result<()> {
+    let capture = RESOURCE_LIFETIMES.lock().unwrap();
+    let mut descs = std::collections::HashMap::new();
+
+    let events: Vec<String> = capture
+        .events
+        .iter()
+        .map(|e| {
+            let ts = (e.time - capture.start).as_micros();
+            match &e.kind {
+                ResourceEventKind::Acquire { desc, new } => {
+                    descs.insert(e.allocation_id, desc.clone());
+                    format!(
+                        r#"{{"name":{},"ph":"B","ts":{},"pid":0,"tid":{},"args":{{"new":{}}}}}"#,
+                        json_string(desc),
+                        ts,
+                        e.allocation_id,
+                        new
+                    )
+                }
+                ResourceEventKind::Release => format!(
+                    r#"{{"ph":"E","ts":{},"pid":0,"tid":{}}}"#,
+                    ts, e.allocation_id
+                ),
+                ResourceEventKind::Use { pass } => format!(
+                    r#"{{"name":{},"ph":"i","s":"t","ts":{},"pid":0,"tid":{}}}"#,
+                    json_string(pass),
+                    ts,
+                    e.allocation_id
+                ),
+                ResourceEventKind::FrameEnd { frame } => format!(
+                    r#"{{"name":"Frame {}","ph":"i","s":"g","ts":{},"pid":0,"tid":0}}"#,
+                    frame, ts
+                ),
+            }
+        })
+        .collect();
+
+    // Name the tracks after the resources
+    let thread_names = descs.iter().map(|(id, desc)| {
+        format!(
+            r#"{{"name":"thread_name","ph":"M","pid":0,"tid":{},"args":{{"name":{}}}}}"#,
+            id,
+            json_string(&format!("#{} {}", id, desc))
+        )
+    });
+
+    let all_events: Vec<String> = events.into_iter().chain(thread_names).collect();
+    std::fs::write(
+        path,
+        format!("{{\"traceEvents\":[\n{}\n]}}\n", all_events.join(",\n")),
+    )
+}
@@ -0,0 +1,187 @@
+// Low-discrepancy sequences shared by temporal techniques, so that TAA, stochastic
+// sampling and such in user shaders all agree on which point of the sequence a frame uses.
+//
+// Frame `i` of a sequence cycling through `n` samples uses point `i % n + 1`, skipping
+// the first point, which is at the origin. `taa_jitter` is the same as the `Halton23`
+// sequence here, so these can be combined with the camera bundle freely.
+
+use crate::camera::halton;
+use crate::host_interop::{upload_tex_from_slice, HostImageLayout};
+use crate::math::*;
+use crate::shader::{ShaderUniformBundle, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleSequence {
+    // Halton with bases 2 and 3
+    Halton23,
+    // The first two dimensions of Sobol
+    Sobol,
+}
+
+fn sobol_2d(index: u32) -> (u32, u32) {
+    // The second dimension uses the primitive polynomial x + 1
+    let mut direction = 1u32 << 31;
+    let mut y = 0u32;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= direction;
+        }
+        i >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    (index.reverse_bits(), y)
+}
+
+// Keeps the 24 bits which fit in the mantissa, so that the result stays below 1.
+fn unorm_from_bits(x: u32) -> f32 {
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+impl SampleSequence {
+    // Point `index` of the sequence, in [0, 1).
+    pub fn point(self, index: u32) -> Vec2 {
+        match self {
+            SampleSequence::Halton23 => Vec2::new(halton(index, 2), halton(index, 3)),
+            SampleSequence::Sobol => {
+                let (x, y) = sobol_2d(index);
+                Vec2::new(unorm_from_bits(x), unorm_from_bits(y))
+            }
+        }
+    }
+
+    // Point used by `frame_index`, in [0, 1).
+    pub fn frame_point(self, frame_index: u32, sample_count: u32) -> Vec2 {
+        self.point(frame_index % sample_count.max(1) + 1)
+    }
+
+    // Frame index whose point preceded that of `frame_index`, modulo `sample_count`.
+    fn prev_frame_index(frame_index: u32, sample_count: u32) -> u32 {
+        let n = sample_count.max(1);
+        (frame_index % n + n - 1) % n
+    }
+
+    // Subpixel offset of `frame_index`, in [-0.5, 0.5] pixels.
+    pub fn jitter(self, frame_index: u32, sample_count: u32) -> Vec2 {
+        self.frame_point(frame_index, sample_count) - Vec2::new(0.5, 0.5)
+    }
+
+    // Per-frame uniforms for shaders sharing the sequence:
+    // * `jitter_offset`: this frame's subpixel offset in xy, and the previous frame's in zw
+    // * `jitter_point`: this frame's point in [0, 1)
+    // * `jitter_sample_index`: index of this frame's texel in `sample_sequence_lut`
+    // * `jitter_sample_count`
+    pub fn uniforms(self, frame_index: u32, sample_count: u32) -> ShaderUniformBundle {
+        let sample_count = sample_count.max(1);
+        let jitter = self.jitter(frame_index, sample_count);
+        let prev_jitter = self.jitter(
+            Self::prev_frame_index(frame_index, sample_count),
+            sample_count,
+        );
+        let point = self.frame_point(frame_index, sample_count);
+
+        shader_uniforms!(
+            jitter_offset: (jitter.x(), jitter.y(), prev_jitter.x(), prev_jitter.y()),
+            jitter_point: (point.x(), point.y(), 0.0f32, 0.0f32),
+            jitter_sample_index: frame_index % sample_count,
+            jitter_sample_count: sample_count,
+        )
+    }
+}
+
+// A `sample_count` x 1 `R32G32_SFLOAT` texture of the points used by consecutive frames,
+// in [0, 1), for shaders which look up more than the current frame's point.
+#[snoozy]
+pub async fn sample_sequence_lut_snoozy(
+    _ctx: Context,
+    sequence: &SampleSequence,
+    sample_count: &u32,
+) -> Result<Texture> {
+    let sample_count = (*sample_count).max(1);
+    let points: Vec<[f32; 2]> = (0..sample_count)
+        .map(|i| {
+            let p = sequence.frame_point(i, sample_count);
+            [p.x(), p.y()]
+        })
+        .collect();
+
+    upload_tex_from_slice(
+        &points,
+        &HostImageLayout::packed(sample_count, 1, vk::Format::R32G32_SFLOAT),
+    )
+}
+
+#[cfg(test)]
+fn assert_points_eq(sequence: SampleSequence, expected: &[(f32, f32)]) {
+    for (index, &(x, y)) in expected.iter().enumerate() {
+        let p = sequence.point(index as u32);
+        assert!(
+            (p.x() - x).abs() < 1e-6 && (p.y() - y).abs() < 1e-6,
+            "{:?} point {}: got ({}, {}), expected ({}, {})",
+            sequence,
+            index,
+            p.x(),
+            p.y(),
+            x,
+            y
+        );
+    }
+}
+
+#[test]
+fn test_halton23_points() {
+    assert_points_eq(
+        SampleSequence::Halton23,
+        &[
+            (0.0, 0.0),
+            (0.5, 1.0 / 3.0),
+            (0.25, 2.0 / 3.0),
+            (0.75, 1.0 / 9.0),
+            (0.125, 4.0 / 9.0),
+            (0.625, 7.0 / 9.0),
+        ],
+    );
+}
+
+#[test]
+fn test_sobol_points() {
+    assert_points_eq(
+        SampleSequence::Sobol,
+        &[
+            (0.0, 0.0),
+            (0.5, 0.5),
+            (0.25, 0.75),
+            (0.75, 0.25),
+            (0.125, 0.625),
+            (0.625, 0.125),
+        ],
+    );
+}
+
+#[test]
+fn test_prev_frame_index_wraps() {
+    for &sample_count in &[1, 2, 3, 5, 8, 16] {
+        assert_eq!(
+            SampleSequence::prev_frame_index(0, sample_count),
+            sample_count - 1
+        );
+        assert_eq!(
+            SampleSequence::prev_frame_index(sample_count, sample_count),
+            sample_count - 1
+        );
+        for frame_index in 1..sample_count {
+            assert_eq!(
+                SampleSequence::prev_frame_index(frame_index, sample_count),
+                frame_index - 1
+            );
+        }
+    }
+
+    assert_eq!(SampleSequence::prev_frame_index(u32::max_value(), 3), 2);
+    assert_eq!(SampleSequence::prev_frame_index(7, 0), 0);
+}
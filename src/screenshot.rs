@@ -0,0 +1,75 @@
+// Screenshots of the windowed render loop, taken with F12 or `request_screenshot`. The
+// output is read back once the GPU is done with the frame, and written out on a separate
+// thread, so taking one doesn't stall rendering. For saving textures from within the
+// graph, see `save_tex`.
+
+use crate::buffer::{read_back_buffer, Buffer};
+use crate::texture::{write_image_file, ImageFileFormat};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct ScreenshotState {
+    dir: PathBuf,
+    format: ImageFileFormat,
+    requested: bool,
+}
+
+lazy_static! {
+    static ref SCREENSHOT: Mutex<ScreenshotState> = Mutex::new(ScreenshotState {
+        dir: PathBuf::from("screenshots"),
+        format: ImageFileFormat::Png,
+        requested: false,
+    });
+}
+
+// Saves the next frame shown in the window.
+pub fn request_screenshot() {
+    SCREENSHOT.lock().unwrap().requested = true;
+}
+
+// `screenshots` in the working directory by default; created when needed.
+pub fn set_screenshot_dir(dir: &str) {
+    SCREENSHOT.lock().unwrap().dir = PathBuf::from(dir);
+}
+
+// PNG by default.
+pub fn set_screenshot_format(format: ImageFileFormat) {
+    SCREENSHOT.lock().unwrap().format = format;
+}
+
+// Called every frame by the render loop; returns the format to save this frame as, if any.
+pub(crate) fn take_screenshot_request() -> Option<ImageFileFormat> {
+    let mut state = SCREENSHOT.lock().unwrap();
+    if std::mem::replace(&mut state.requested, false) {
+        Some(state.format)
+    } else {
+        None
+    }
+}
+
+// Writes out the output of `pack_tex_for_saving` once its readback completes.
+pub(crate) fn save_screenshot(packed: &Buffer, size: (u32, u32), format: ImageFileFormat) {
+    let dir = SCREENSHOT.lock().unwrap().dir.clone();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_millis())
+        .unwrap_or_default();
+    let extension = match format {
+        ImageFileFormat::Png => "png",
+        ImageFileFormat::Exr => "exr",
+    };
+    let path = dir.join(format!("screenshot-{}.{}", timestamp, extension));
+    let readback = read_back_buffer(packed);
+
+    std::thread::spawn(move || {
+        let res = futures::executor::block_on(readback).and_then(|texels| {
+            std::fs::create_dir_all(&dir)?;
+            write_image_file(&path.to_string_lossy(), &texels, size, format)
+        });
+
+        if let Err(err) = res {
+            tracing::error!("Failed to save a screenshot to {}: {}", path.display(), err);
+        }
+    });
+}
@@ -1,15 +1,37 @@
+use crate::background_compute;
 use crate::blob::*;
 use crate::buffer::{Buffer, BufferKey};
+use crate::coord_convention;
+use crate::deterministic_math;
+use crate::dry_run;
 use crate::gpu_debugger;
+use crate::gpu_profiler;
+use crate::gpu_workload;
+use crate::math::{Mat3, Mat4};
+use crate::occlusion_query::OcclusionQuery;
+use crate::op_tags::OpTags;
+use crate::output_load_op::{self, OutputLoadOp};
+use crate::resource_lifetime;
+use crate::shader_cache;
+use crate::shader_compile_queue;
+use crate::shader_hot_swap;
+use crate::shader_instrumentation;
+use crate::shader_source;
+use crate::spirv_opt::{self, SpirvOptPreset};
 use crate::texture::{Texture, TextureKey};
+use crate::uniform_folding;
 use crate::vulkan::*;
-use ash::version::DeviceV1_0;
+use crate::workgroup_autotune;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk::Handle;
 use ash::{vk, Device};
 use relative_path::{RelativePath, RelativePathBuf};
 use shader_prepper;
 use snoozy::futures::future::{try_join_all, BoxFuture, FutureExt};
 use snoozy::*;
+use spirv_reflect::types::variable::ReflectBlockVariable;
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
 macro_rules! def_shader_uniform_types {
     (@resolved_type SnoozyRef<ShaderUniformBundle>) => {
@@ -58,12 +80,22 @@ pub enum ResolvedShaderUniformValue {
     Int32(i32),
     Usize(usize),
     Ivec2((i32, i32)),
+    Vec2((f32, f32)),
+    Vec3((f32, f32, f32)),
     Vec4((f32, f32, f32, f32)),
+    Mat3(Mat3),
+    Mat4(Mat4),
+    Float32Array(Vec<f32>),
+    Int32Array(Vec<i32>),
+    Vec4Array(Vec<[f32; 4]>),
     Texture(Texture),
     Buffer(Buffer),
     Bundle(ResolvedShaderUniformBundle),
     RwTexture(Texture),
     RwBuffer(Buffer),
+    Tags(OpTags),
+    OcclusionQuery(OcclusionQuery),
+    OutputLoadOp(OutputLoadOp),
 }
 
 def_shader_uniform_types! {
@@ -71,14 +103,31 @@ def_shader_uniform_types! {
     Uint32(u32),
     Int32(i32),
     Ivec2((i32, i32)),
+    Vec2((f32, f32)),
+    Vec3((f32, f32, f32)),
     Vec4((f32, f32, f32, f32)),
+    // Column-major; transposed for `row_major` matrices
+    Mat3(Mat3),
+    Mat4(Mat4),
+    // Fixed-size arrays in uniform blocks, e.g. `float weights[8]`. Elements past the end
+    // of the shader's array are dropped, and missing ones are zero.
+    Float32Array(Vec<f32>),
+    Int32Array(Vec<i32>),
+    Vec4Array(Vec<[f32; 4]>),
     Bundle(ShaderUniformBundle),
     Float32Asset(SnoozyRef<f32>),
     Uint32Asset(SnoozyRef<u32>),
     UsizeAsset(SnoozyRef<usize>),
+    Vec2Asset(SnoozyRef<(f32, f32)>),
+    Vec3Asset(SnoozyRef<(f32, f32, f32)>),
+    Mat3Asset(SnoozyRef<Mat3>),
+    Mat4Asset(SnoozyRef<Mat4>),
     TextureAsset(SnoozyRef<Texture>),
     BufferAsset(SnoozyRef<Buffer>),
     BundleAsset(SnoozyRef<ShaderUniformBundle>),
+    Tags(OpTags),
+    OcclusionQuery(OcclusionQuery),
+    OutputLoadOp(OutputLoadOp),
 }
 
 impl ShaderUniformValue {
@@ -92,7 +141,20 @@ impl ShaderUniformValue {
                 ShaderUniformValue::Uint32(v) => Ok(ResolvedShaderUniformValue::Uint32(*v)),
                 ShaderUniformValue::Int32(v) => Ok(ResolvedShaderUniformValue::Int32(*v)),
                 ShaderUniformValue::Ivec2(v) => Ok(ResolvedShaderUniformValue::Ivec2(*v)),
+                ShaderUniformValue::Vec2(v) => Ok(ResolvedShaderUniformValue::Vec2(*v)),
+                ShaderUniformValue::Vec3(v) => Ok(ResolvedShaderUniformValue::Vec3(*v)),
                 ShaderUniformValue::Vec4(v) => Ok(ResolvedShaderUniformValue::Vec4(*v)),
+                ShaderUniformValue::Mat3(v) => Ok(ResolvedShaderUniformValue::Mat3(*v)),
+                ShaderUniformValue::Mat4(v) => Ok(ResolvedShaderUniformValue::Mat4(*v)),
+                ShaderUniformValue::Float32Array(v) => {
+                    Ok(ResolvedShaderUniformValue::Float32Array(v.clone()))
+                }
+                ShaderUniformValue::Int32Array(v) => {
+                    Ok(ResolvedShaderUniformValue::Int32Array(v.clone()))
+                }
+                ShaderUniformValue::Vec4Array(v) => {
+                    Ok(ResolvedShaderUniformValue::Vec4Array(v.clone()))
+                }
                 ShaderUniformValue::Bundle(v) => Ok(ResolvedShaderUniformValue::Bundle(
                     resolve(ctx.clone(), v.clone()).await?,
                 )),
@@ -105,6 +167,18 @@ impl ShaderUniformValue {
                 ShaderUniformValue::UsizeAsset(v) => {
                     Ok(ResolvedShaderUniformValue::Usize(*ctx.get(v).await?))
                 }
+                ShaderUniformValue::Vec2Asset(v) => {
+                    Ok(ResolvedShaderUniformValue::Vec2(*ctx.get(v).await?))
+                }
+                ShaderUniformValue::Vec3Asset(v) => {
+                    Ok(ResolvedShaderUniformValue::Vec3(*ctx.get(v).await?))
+                }
+                ShaderUniformValue::Mat3Asset(v) => {
+                    Ok(ResolvedShaderUniformValue::Mat3(*ctx.get(v).await?))
+                }
+                ShaderUniformValue::Mat4Asset(v) => {
+                    Ok(ResolvedShaderUniformValue::Mat4(*ctx.get(v).await?))
+                }
                 ShaderUniformValue::TextureAsset(v) => Ok(ResolvedShaderUniformValue::Texture(
                     (*ctx.get(v).await?).clone(),
                 )),
@@ -114,6 +188,13 @@ impl ShaderUniformValue {
                 ShaderUniformValue::BundleAsset(v) => Ok(ResolvedShaderUniformValue::Bundle(
                     resolve(ctx.clone(), (*ctx.get(v).await?).clone()).await?,
                 )),
+                ShaderUniformValue::Tags(v) => Ok(ResolvedShaderUniformValue::Tags(v.clone())),
+                ShaderUniformValue::OcclusionQuery(v) => {
+                    Ok(ResolvedShaderUniformValue::OcclusionQuery(v.clone()))
+                }
+                ShaderUniformValue::OutputLoadOp(v) => {
+                    Ok(ResolvedShaderUniformValue::OutputLoadOp(*v))
+                }
             }
         }
         .boxed()
@@ -157,6 +238,10 @@ impl ShaderUniformHolder {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub async fn resolve(&self, ctx: Context) -> Result<ResolvedShaderUniformHolder> {
         Ok(ResolvedShaderUniformHolder {
             name: self.name.clone(),
@@ -168,6 +253,86 @@ impl ShaderUniformHolder {
     }
 }
 
+// Per-binding LOD controls. Samplers are shared, so these are applied by the shaders:
+// every texture `foo` comes with a `vec4 foo_lod` of (bias, min_lod, max_lod, 0), as well as
+// a `uint foo_mip_count`; see `apply_texture_lod` in `sampling.inc`.
+//
+// Override the defaults by passing `foo_lod: TextureLod { .. }` next to the texture.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureLod {
+    pub bias: f32,
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for TextureLod {
+    fn default() -> Self {
+        Self {
+            bias: 0.0,
+            min_lod: 0.0,
+            // Same as VK_LOD_CLAMP_NONE
+            max_lod: 1000.0,
+        }
+    }
+}
+
+impl From<TextureLod> for ShaderUniformValue {
+    fn from(v: TextureLod) -> ShaderUniformValue {
+        ShaderUniformValue::Vec4((v.bias, v.min_lod, v.max_lod, 0.0))
+    }
+}
+
+// Per-binding edge handling for texel loads, which don't go through a sampler: every
+// texture `foo` comes with an `ivec2 foo_wrap` of the mode of each axis; see `wrap_texel`
+// in `texture_wrap.inc`. Clamped by default.
+//
+// Override it by passing `foo_wrap: TextureWrap::Repeat` next to the texture,
+// or `(TextureWrap::Repeat, TextureWrap::Clamp)` for different modes per axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureWrap {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+impl TextureWrap {
+    // Values of the `TEXTURE_WRAP_*` defines
+    fn shader_value(self) -> i32 {
+        match self {
+            TextureWrap::Clamp => 0,
+            TextureWrap::Repeat => 1,
+            TextureWrap::Mirror => 2,
+        }
+    }
+
+    // The shared sampler which filters with the same edge handling, if there is one
+    pub fn sampler_name(self) -> Option<&'static str> {
+        match self {
+            TextureWrap::Clamp => Some("linear_clamp_sampler"),
+            TextureWrap::Repeat => Some("linear_sampler"),
+            TextureWrap::Mirror => None,
+        }
+    }
+}
+
+impl Default for TextureWrap {
+    fn default() -> Self {
+        TextureWrap::Clamp
+    }
+}
+
+impl From<TextureWrap> for ShaderUniformValue {
+    fn from(v: TextureWrap) -> ShaderUniformValue {
+        ShaderUniformValue::Ivec2((v.shader_value(), v.shader_value()))
+    }
+}
+
+impl From<(TextureWrap, TextureWrap)> for ShaderUniformValue {
+    fn from(v: (TextureWrap, TextureWrap)) -> ShaderUniformValue {
+        ShaderUniformValue::Ivec2((v.0.shader_value(), v.1.shader_value()))
+    }
+}
+
 pub type ShaderUniformBundle = Vec<ShaderUniformHolder>;
 pub type ResolvedShaderUniformBundle = Vec<ResolvedShaderUniformHolder>;
 
@@ -218,12 +383,16 @@ macro_rules! shader_uniform_bundle {
     }
 }
 
+// Cheap to clone; shared with hot-swapped versions of the shader.
+#[derive(Clone)]
 pub struct ComputeShader {
     pub name: String,
     pipeline: ComputePipeline,
-    spirv_reflection: spirv_reflect::ShaderModule,
+    spirv_reflection: Arc<spirv_reflect::ShaderModule>,
     descriptor_set_layout_info: DescriptorSetLayoutInfo,
     local_size: (u32, u32, u32),
+    // Pipelines specialized for each workgroup size being autotuned
+    workgroup_variants: Vec<([u32; 3], ComputePipeline)>,
 }
 
 unsafe impl Send for ComputeShader {}
@@ -251,18 +420,29 @@ impl<'a> shader_prepper::IncludeProvider for ShaderIncludeProvider {
         include_context: &Self::IncludeContext,
     ) -> Result<(String, Self::IncludeContext)> {
         let asset_path: AssetPath = if let Some(crate_end) = path.find("::") {
-            let crate_name = path.chars().take(crate_end).collect();
+            let mut crate_name: String = path.chars().take(crate_end).collect();
             let asset_name = path.chars().skip(crate_end + 2).collect();
 
+            // Files loaded through a namespace alias (`effects@b`) keep using the alias
+            // when including from the original namespace (`effects::`)
+            if include_context
+                .crate_name
+                .starts_with(&format!("{}@", crate_name))
+            {
+                crate_name = include_context.crate_name.clone();
+            }
+
             AssetPath {
                 crate_name,
                 asset_name,
+                identity: None,
             }
         } else {
             if let Some('/') = path.chars().next() {
                 AssetPath {
                     crate_name: include_context.crate_name.clone(),
                     asset_name: path.chars().skip(1).collect(),
+                    identity: None,
                 }
             } else {
                 let mut folder: RelativePathBuf = include_context.asset_name.clone().into();
@@ -270,11 +450,16 @@ impl<'a> shader_prepper::IncludeProvider for ShaderIncludeProvider {
                 AssetPath {
                     crate_name: include_context.crate_name.clone(),
                     asset_name: folder.join(path).as_str().to_string(),
+                    identity: None,
                 }
             }
         };
 
         RelativePath::new(path);
+        if let Some(text) = shader_source::resolve_shader_asset_override(&self.ctx, &asset_path)? {
+            return Ok((text, asset_path));
+        }
+
         let blob =
             snoozy::futures::executor::block_on(self.ctx.get(&load_blob(asset_path.clone())))?;
         String::from_utf8(blob.contents.clone())
@@ -283,12 +468,127 @@ impl<'a> shader_prepper::IncludeProvider for ShaderIncludeProvider {
     }
 }
 
-fn get_shader_text(source: &[shader_prepper::SourceChunk]) -> String {
-    let preamble =
+// Preprocesses a shader compiled from a string as if it were the file `name` in
+// rendertoy's `shaders/` assets, so that built-in shaders can include its headers,
+// e.g. `#include "cube_map.inc"`.
+fn preprocess_shader_string(
+    ctx: &Context,
+    source: &str,
+    name: &str,
+) -> Result<Vec<shader_prepper::SourceChunk>> {
+    struct StringIncludeProvider {
+        root_source: Option<String>,
+        files: ShaderIncludeProvider,
+    }
+
+    impl shader_prepper::IncludeProvider for StringIncludeProvider {
+        type IncludeContext = AssetPath;
+
+        fn get_include(
+            &mut self,
+            path: &str,
+            include_context: &Self::IncludeContext,
+        ) -> Result<(String, Self::IncludeContext)> {
+            // The first request is for the string itself
+            match self.root_source.take() {
+                Some(source) => Ok((source, include_context.clone())),
+                None => self.files.get_include(path, include_context),
+            }
+        }
+    }
+
+    let file_name = std::path::Path::new(name)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    Ok(shader_prepper::process_file(
+        name,
+        &mut StringIncludeProvider {
+            root_source: Some(source.to_owned()),
+            files: ShaderIncludeProvider { ctx: ctx.clone() },
+        },
+        AssetPath {
+            crate_name: env!("CARGO_PKG_NAME").to_owned(),
+            asset_name: format!("shaders/{}", file_name),
+            identity: None,
+        },
+    )?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ShaderKind {
+    Compute,
+    Vertex,
+    Fragment,
+}
+
+#[cfg(feature = "shaderc")]
+impl From<ShaderKind> for shaderc::ShaderKind {
+    fn from(kind: ShaderKind) -> Self {
+        match kind {
+            ShaderKind::Compute => shaderc::ShaderKind::Compute,
+            ShaderKind::Vertex => shaderc::ShaderKind::Vertex,
+            ShaderKind::Fragment => shaderc::ShaderKind::Fragment,
+        }
+    }
+}
+
+pub(crate) fn spirv_from_bytes(bytes: &[u8]) -> Result<Vec<u32>> {
+    if bytes.len() % 4 != 0 {
+        bail!("SPIR-V size must be a multiple of 4 bytes");
+    }
+
+    let spirv: Vec<u32> = bytes
+        .chunks(4)
+        .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+
+    if spirv.first() != Some(&0x07230203) {
+        bail!("Not a SPIR-V binary");
+    }
+
+    Ok(spirv)
+}
+
+// Precompiled shaders skip preprocessing and compilation, and are the only kind
+// which can be loaded without the `shaderc` feature.
+async fn load_spirv_asset(ctx: &mut Context, path: &AssetPath) -> Result<Vec<u32>> {
+    let blob = ctx.get(&load_blob(path.clone())).await?;
+    spirv_from_bytes(&blob.contents).map_err(|err| format_err!("{}: {}", path, err))
+}
+
+fn get_shader_text(
+    shader_name: &str,
+    source: &[shader_prepper::SourceChunk],
+    shader_kind: ShaderKind,
+) -> String {
+    let instrumented = shader_instrumentation::is_shader_instrumented(shader_name, shader_kind);
+
+    let mut preamble =
         "#version 430\n#extension GL_EXT_samplerless_texture_functions : require\n".to_string();
+    preamble += &vk()
+        .caps
+        .glsl_preamble(source.iter().map(|s| s.source.as_str()));
+    preamble += &coord_convention::coord_convention().glsl_preamble();
+    preamble += &deterministic_math::glsl_preamble(shader_kind);
+    if instrumented {
+        preamble += &shader_instrumentation::glsl_preamble(shader_kind);
+    }
+
+    let runtime_arrays = if instrumented {
+        shader_instrumentation::runtime_array_names(source.iter().map(|s| s.source.as_str()))
+    } else {
+        Default::default()
+    };
 
     let mod_sources = source.iter().enumerate().map(|(i, s)| {
-        let s = format!("#line 0 {}\n", i + 1) + &s.source;
+        let s = if instrumented {
+            format!("#line 0 {}\n", i + 1)
+                + &shader_instrumentation::instrument_glsl(&s.source, &runtime_arrays)
+        } else {
+            format!("#line 0 {}\n", i + 1) + &s.source
+        };
         s
     });
     let mod_sources = std::iter::once(preamble).chain(mod_sources);
@@ -297,42 +597,199 @@ fn get_shader_text(source: &[shader_prepper::SourceChunk]) -> String {
     mod_sources.join("")
 }
 
+// Failed compiles keep using the last SPIR-V which compiled fine, so hot-reloading
+// a broken edit doesn't take the pipeline down. Shaders which never compiled use
+// the fallback for their stage, if one is registered.
 fn shaderc_compile_glsl(
+    ctx: &Context,
     shader_name: &str,
+    source_key: &str,
     source: &[shader_prepper::SourceChunk],
-    shader_kind: shaderc::ShaderKind,
-) -> Result<shaderc::CompilationArtifact> {
-    let source = get_shader_text(source);
-    shaderc_compile_glsl_str(shader_name, &source, shader_kind)
+    shader_kind: ShaderKind,
+) -> Result<Vec<u32>> {
+    let chunks = source
+        .iter()
+        .map(|chunk| shader_source::ShaderSourceChunk {
+            file: chunk.file.clone(),
+            source: chunk.source.clone(),
+            line_offset: chunk.line_offset,
+        })
+        .collect();
+    let text = shader_source::resolve_shader_source(
+        ctx,
+        source_key,
+        chunks,
+        get_shader_text(shader_name, source, shader_kind),
+    )?;
+
+    let options_id = shaderc_options_id(shader_name);
+    if let Some(spirv) = shader_cache::load_cached_spirv(&text, shader_kind, options_id) {
+        shader_source::record_shader_diagnostics(source_key, "");
+        shader_source::record_good_spirv(source_key, &spirv);
+        return Ok(spirv);
+    }
+
+    let err = match compile_glsl_str(shader_name, &text, shader_kind) {
+        Ok(spirv) => {
+            shader_cache::store_cached_spirv(&text, shader_kind, options_id, &spirv);
+            shader_source::record_shader_diagnostics(source_key, "");
+            shader_source::record_good_spirv(source_key, &spirv);
+            return Ok(spirv);
+        }
+        Err(err) => err,
+    };
+
+    // Report errors against the files they're in, rather than the concatenated text
+    let log = shader_source::record_shader_diagnostics(source_key, &err.to_string());
+    let err = format_err!("{}", log);
+
+    if let Some(spirv) = shader_source::last_good_spirv(source_key) {
+        crate::rtoy_show_warning(format!(
+            "{} failed to compile; keeping the previous version:\n{}",
+            source_key, err
+        ));
+        return Ok(spirv);
+    }
+
+    if let Some(fallback) = shader_source::fallback_shader_source(shader_kind) {
+        crate::rtoy_show_warning(format!(
+            "{} failed to compile; using the fallback shader:\n{}",
+            source_key, err
+        ));
+        let fallback = [shader_prepper::SourceChunk {
+            source: fallback,
+            file: "fallback".to_owned(),
+            line_offset: 0,
+        }];
+        return compile_glsl_str(
+            shader_name,
+            &get_shader_text(shader_name, &fallback, shader_kind),
+            shader_kind,
+        );
+    }
+
+    Err(err)
+}
+
+// Part of the SPIR-V cache keys; must change along with the options below.
+fn shaderc_options_id(shader_name: &str) -> String {
+    let preset = spirv_opt::spirv_opt_preset(shader_name);
+    let optimizer = if preset != SpirvOptPreset::None && spirv_opt::is_spirv_opt_available() {
+        ";spirv_opt"
+    } else {
+        ""
+    };
+
+    format!(
+        "EP=main;opt={}{};debug_info;auto_bind_uniforms",
+        preset.id(),
+        optimizer
+    )
+}
+
+// Compiles with shaderc, then optimizes with `spirv-opt` where available.
+fn compile_glsl_str(shader_name: &str, source: &str, shader_kind: ShaderKind) -> Result<Vec<u32>> {
+    let preset = spirv_opt::spirv_opt_preset(shader_name);
+    let external_opt = preset != SpirvOptPreset::None && spirv_opt::is_spirv_opt_available();
+
+    let compile_start = std::time::Instant::now();
+    let spirv = shaderc_compile_glsl_str(
+        shader_name,
+        source,
+        shader_kind,
+        if external_opt {
+            SpirvOptPreset::None
+        } else {
+            preset
+        },
+    )?;
+    let compile_ms = compile_start.elapsed().as_secs_f64() * 1000.0;
+
+    let mut event = spirv_opt::ShaderCompileEvent {
+        shader: shader_name.to_owned(),
+        preset,
+        compile_ms,
+        optimize_ms: None,
+        unoptimized_size_bytes: None,
+        size_bytes: spirv.len() * 4,
+    };
+
+    let spirv = if external_opt {
+        let optimize_start = std::time::Instant::now();
+        match spirv_opt::run_spirv_opt(&spirv, preset) {
+            Ok(optimized) => {
+                event.optimize_ms = Some(optimize_start.elapsed().as_secs_f64() * 1000.0);
+                event.unoptimized_size_bytes = Some(event.size_bytes);
+                event.size_bytes = optimized.len() * 4;
+                optimized
+            }
+            Err(err) => {
+                tracing::warn!("Could not optimize {}: {}", shader_name, err);
+                spirv
+            }
+        }
+    } else {
+        spirv
+    };
+
+    spirv_opt::record_compile_event(event);
+    Ok(spirv)
 }
 
+#[cfg(feature = "shaderc")]
 fn shaderc_compile_glsl_str(
     shader_name: &str,
     source: &str,
-    shader_kind: shaderc::ShaderKind,
-) -> Result<shaderc::CompilationArtifact> {
-    let mut compiler = shaderc::Compiler::new().unwrap();
-    let mut options = shaderc::CompileOptions::new().unwrap();
+    shader_kind: ShaderKind,
+    preset: SpirvOptPreset,
+) -> Result<Vec<u32>> {
+    let mut compiler = shaderc::Compiler::new()
+        .ok_or_else(|| format_err!("Could not create a shaderc compiler"))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| format_err!("Could not create shaderc compile options"))?;
     options.add_macro_definition("EP", Some("main"));
-    options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    options.set_optimization_level(match preset {
+        SpirvOptPreset::None => shaderc::OptimizationLevel::Zero,
+        SpirvOptPreset::Performance => shaderc::OptimizationLevel::Performance,
+        SpirvOptPreset::Size => shaderc::OptimizationLevel::Size,
+    });
     options.set_generate_debug_info();
     options.set_auto_bind_uniforms(true);
     let binary_result = compiler
         .compile_into_spirv(
             source,
-            shader_kind,
+            shader_kind.into(),
             shader_name,
             "main",
             Some(&options),
         )
-        //.expect(&format!("{}::compile_into_spirv", shader_name));
-        ?;
+        .map_err(|err| match err {
+            // Just the log, so that its lines can be mapped back to the source files
+            shaderc::Error::CompilationError(_, log) => format_err!("{}", log),
+            err => format_err!("{}: {}", shader_name, err),
+        })?;
+
+    if binary_result.as_binary().first() != Some(&0x07230203) {
+        bail!("{}: shaderc produced invalid SPIR-V", shader_name);
+    }
 
-    assert_eq!(Some(&0x07230203), binary_result.as_binary().first());
+    Ok(binary_result.as_binary().to_vec())
+}
 
-    Ok(binary_result)
+#[cfg(not(feature = "shaderc"))]
+fn shaderc_compile_glsl_str(
+    shader_name: &str,
+    _source: &str,
+    _shader_kind: ShaderKind,
+    _preset: SpirvOptPreset,
+) -> Result<Vec<u32>> {
+    bail!(
+        "Cannot compile {}: built without the `shaderc` feature, so only .spv shaders load",
+        shader_name
+    )
 }
 
+#[derive(Clone)]
 pub struct ComputePipeline {
     pub pipeline_layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
@@ -349,15 +806,31 @@ fn reflect_spirv_shader(shader_code: &[u32]) -> Result<spirv_reflect::ShaderModu
     convert_spirv_reflect_err(spirv_reflect::ShaderModule::load_u32_data(shader_code))
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct DescriptorSetLayoutInfo {
     all_layouts: Vec<vk::DescriptorSetLayout>,
     dynamic_layouts: Vec<vk::DescriptorSetLayout>,
     dynamic_layout_indices: Vec<usize>,
+    // A single range covering the push constants of all stages, so that they can be
+    // pushed in one go.
+    push_constant_range: Option<vk::PushConstantRange>,
 }
 
 impl DescriptorSetLayoutInfo {
     fn append(&mut self, other: &mut Self) {
+        self.push_constant_range = match (self.push_constant_range, other.push_constant_range) {
+            (Some(a), Some(b)) => {
+                let offset = a.offset.min(b.offset);
+                let end = (a.offset + a.size).max(b.offset + b.size);
+                Some(vk::PushConstantRange {
+                    stage_flags: a.stage_flags | b.stage_flags,
+                    offset,
+                    size: end - offset,
+                })
+            }
+            (a, b) => a.or(b),
+        };
+
         let all_layouts_offset = self.all_layouts.len();
         self.all_layouts.append(&mut other.all_layouts);
         self.dynamic_layouts.append(&mut other.dynamic_layouts);
@@ -371,6 +844,175 @@ impl DescriptorSetLayoutInfo {
     }
 }
 
+// Everything which goes into a descriptor set layout. Layouts are shared between all shaders
+// with the same signature, which keeps pipeline layouts compatible across shaders,
+// and avoids creating new layouts whenever a shader is reloaded.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DescriptorBindingSignature {
+    binding: u32,
+    descriptor_type: i32,
+    descriptor_count: u32,
+    stage_flags: u32,
+    binding_flags: u32,
+    immutable_sampler: u64,
+}
+
+type SetLayoutSignature = Vec<DescriptorBindingSignature>;
+
+// Shader module and pipeline layout handles, and the specialized workgroup size
+type ComputePipelineKey = (u64, u64, Option<[u32; 3]>);
+
+// Set layout handles, and the stage flags, offset and size of the push constant range
+type PipelineLayoutKey = (Vec<u64>, Option<(u32, u32, u32)>);
+
+lazy_static! {
+    static ref DESCRIPTOR_SET_LAYOUT_CACHE: Mutex<HashMap<SetLayoutSignature, vk::DescriptorSetLayout>> =
+        Mutex::new(HashMap::new());
+    static ref PIPELINE_LAYOUT_CACHE: Mutex<HashMap<PipelineLayoutKey, vk::PipelineLayout>> =
+        Mutex::new(HashMap::new());
+    // Keyed by a hash and the length of the final SPIR-V
+    static ref SHADER_MODULE_CACHE: Mutex<HashMap<(u64, usize), vk::ShaderModule>> =
+        Mutex::new(HashMap::new());
+    static ref COMPUTE_PIPELINE_CACHE: Mutex<HashMap<ComputePipelineKey, vk::Pipeline>> =
+        Mutex::new(HashMap::new());
+}
+
+// Graphs built programmatically often load the same shader many times over, e.g. through
+// `load_cs_from_string` with differing names, or copies of a file under different paths.
+// Those then share one module, and for compute, one pipeline per workgroup size.
+fn get_or_create_shader_module(device: &Device, code: &[u32]) -> vk::ShaderModule {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    code.hash(&mut hasher);
+    let key = (hasher.finish(), code.len());
+
+    let mut cache = SHADER_MODULE_CACHE.lock().unwrap();
+    *cache.entry(key).or_insert_with(|| unsafe {
+        device
+            .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(code), None)
+            .expect("Shader module error")
+    })
+}
+
+fn get_or_create_descriptor_set_layout(
+    device: &Device,
+    bindings: &[vk::DescriptorSetLayoutBinding],
+    binding_flags: &[vk::DescriptorBindingFlagsEXT],
+) -> vk::DescriptorSetLayout {
+    let signature: SetLayoutSignature = bindings
+        .iter()
+        .zip(binding_flags.iter())
+        .map(|(b, flags)| DescriptorBindingSignature {
+            binding: b.binding,
+            descriptor_type: b.descriptor_type.as_raw(),
+            descriptor_count: b.descriptor_count,
+            stage_flags: b.stage_flags.as_raw(),
+            binding_flags: flags.as_raw(),
+            immutable_sampler: if b.p_immutable_samplers.is_null() {
+                0
+            } else {
+                unsafe { (*b.p_immutable_samplers).as_raw() }
+            },
+        })
+        .collect();
+
+    let mut cache = DESCRIPTOR_SET_LAYOUT_CACHE.lock().unwrap();
+    *cache.entry(signature).or_insert_with(|| {
+        let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::builder()
+            .binding_flags(binding_flags)
+            .build();
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::builder()
+                        .bindings(bindings)
+                        .push_next(&mut binding_flags)
+                        .build(),
+                    None,
+                )
+                .unwrap()
+        }
+    })
+}
+
+// Set layouts come from `get_or_create_descriptor_set_layout`, so equal lists of layouts
+// and push constant ranges mean equal pipeline layouts.
+fn get_or_create_pipeline_layout(
+    device: &Device,
+    layout_info: &DescriptorSetLayoutInfo,
+) -> vk::PipelineLayout {
+    let key: PipelineLayoutKey = (
+        layout_info.all_layouts.iter().map(|l| l.as_raw()).collect(),
+        layout_info
+            .push_constant_range
+            .map(|r| (r.stage_flags.as_raw(), r.offset, r.size)),
+    );
+
+    let push_constant_ranges: Vec<_> = layout_info.push_constant_range.iter().copied().collect();
+
+    let mut cache = PIPELINE_LAYOUT_CACHE.lock().unwrap();
+    *cache.entry(key).or_insert_with(|| unsafe {
+        device
+            .create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(&layout_info.all_layouts)
+                    .push_constant_ranges(&push_constant_ranges)
+                    .build(),
+                None,
+            )
+            .unwrap()
+    })
+}
+
+// Push constant bytes used by a shader, from its first member to the end of its last one.
+// Members of `layout(push_constant)` blocks are set from uniforms by name, just like
+// those of uniform buffers, but without a descriptor per dispatch.
+fn reflect_push_constant_range(
+    refl: &spirv_reflect::ShaderModule,
+    stage_flags: vk::ShaderStageFlags,
+) -> std::result::Result<Option<vk::PushConstantRange>, &'static str> {
+    let mut range: Option<(u32, u32)> = None;
+
+    for block in refl.enumerate_push_constant_blocks(Some("main"))?.iter() {
+        let mut members = Vec::new();
+        uniform_block_leaves(&block.members, "", &mut members);
+
+        for (_, member) in members {
+            let start = member.absolute_offset;
+            let end = member.absolute_offset + member.size;
+            range = Some(match range {
+                Some((a, b)) => (a.min(start), b.max(end)),
+                None => (start, end),
+            });
+        }
+    }
+
+    Ok(range.map(|(start, end)| vk::PushConstantRange {
+        stage_flags,
+        offset: start,
+        size: end - start,
+    }))
+}
+
+// Push constants are written along with the uniform buffers, by `update_descriptor_sets`.
+unsafe fn record_push_constants(
+    device: &Device,
+    cb: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    layout_info: &DescriptorSetLayoutInfo,
+    push_constants: &[u8],
+) {
+    if let Some(range) = layout_info.push_constant_range {
+        device.cmd_push_constants(
+            cb,
+            pipeline_layout,
+            range.stage_flags,
+            range.offset,
+            &push_constants[range.offset as usize..(range.offset + range.size) as usize],
+        );
+    }
+}
+
 fn generate_descriptor_set_layouts(
     refl: &spirv_reflect::ShaderModule,
     stage_flags: vk::ShaderStageFlags,
@@ -479,10 +1121,22 @@ fn generate_descriptor_set_layouts(
                     &mut bindings,
                     &mut binding_flags,
                 ),
+                ReflectDescriptorType::InputAttachment => create_binding(
+                    vk::DescriptorType::INPUT_ATTACHMENT,
+                    binding,
+                    &mut bindings,
+                    &mut binding_flags,
+                ),
                 ReflectDescriptorType::Sampler => {
                     let sampler_index = match binding.name.as_str() {
                         "linear_sampler" => crate::vulkan::SAMPLER_LINEAR,
                         "linear_clamp_sampler" => crate::vulkan::SAMPLER_LINEAR_CLAMP,
+                        "shadow_less_sampler" => crate::vulkan::SAMPLER_SHADOW_LESS,
+                        "shadow_less_equal_sampler" => crate::vulkan::SAMPLER_SHADOW_LESS_OR_EQUAL,
+                        "shadow_greater_sampler" => crate::vulkan::SAMPLER_SHADOW_GREATER,
+                        "shadow_greater_equal_sampler" => {
+                            crate::vulkan::SAMPLER_SHADOW_GREATER_OR_EQUAL
+                        }
                         _ => return Err("Unrecognized sampler name"), // TODO: better error
                     };
                     immutable_samplers.push(vk.samplers[sampler_index]);
@@ -505,21 +1159,8 @@ fn generate_descriptor_set_layouts(
             }
         }
 
-        let mut binding_flags = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT::builder()
-            .binding_flags(&binding_flags)
-            .build();
-
-        let descriptor_set_layout = unsafe {
-            vk.device
-                .create_descriptor_set_layout(
-                    &vk::DescriptorSetLayoutCreateInfo::builder()
-                        .bindings(&bindings)
-                        .push_next(&mut binding_flags)
-                        .build(),
-                    None,
-                )
-                .unwrap()
-        };
+        let descriptor_set_layout =
+            get_or_create_descriptor_set_layout(&vk.device, &bindings, &binding_flags);
 
         all_layouts.push(descriptor_set_layout);
         is_dynamic.push(is_set_dynamic);
@@ -539,50 +1180,127 @@ fn generate_descriptor_set_layouts(
         all_layouts,
         dynamic_layouts,
         dynamic_layout_indices,
+        push_constant_range: reflect_push_constant_range(refl, stage_flags)?,
     })
 }
 
+// Filled in by the driver during pipeline creation, if it supports it.
+struct PipelineCreationFeedback {
+    pipeline: vk::PipelineCreationFeedbackEXT,
+    stages: Vec<vk::PipelineCreationFeedbackEXT>,
+}
+
+impl PipelineCreationFeedback {
+    fn new(stage_count: usize) -> Self {
+        Self {
+            pipeline: Default::default(),
+            stages: vec![Default::default(); stage_count],
+        }
+    }
+
+    // `cpu_time` is reported instead when the driver didn't provide feedback.
+    fn report(&self, name: &str, cpu_time: std::time::Duration) {
+        let (duration_ms, cache_hit) = if self
+            .pipeline
+            .flags
+            .contains(vk::PipelineCreationFeedbackFlagsEXT::VALID)
+        {
+            let cache_hit = self
+                .pipeline
+                .flags
+                .contains(vk::PipelineCreationFeedbackFlagsEXT::APPLICATION_PIPELINE_CACHE_HIT);
+            (self.pipeline.duration as f64 / 1_000_000.0, Some(cache_hit))
+        } else {
+            (cpu_time.as_secs_f64() * 1000.0, None)
+        };
+
+        gpu_profiler::report_pipeline_creation(name, duration_ms, cache_hit);
+    }
+}
+
+// `local_size` overrides the workgroup size of shaders which declare it through
+// specialization constants 0..2.
 fn create_compute_pipeline(
+    name: &str,
     device: &Device,
-    descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    layout_info: &DescriptorSetLayoutInfo,
     shader_code: &[u32],
+    local_size: Option<[u32; 3]>,
 ) -> Result<ComputePipeline> {
     use std::ffi::CString;
 
     let shader_entry_name = CString::new("main").unwrap();
 
-    let layout_create_info =
-        vk::PipelineLayoutCreateInfo::builder().set_layouts(&descriptor_set_layouts);
+    let shader_module = get_or_create_shader_module(device, shader_code);
+    let pipeline_layout = get_or_create_pipeline_layout(device, layout_info);
+
+    let cache_key = (shader_module.as_raw(), pipeline_layout.as_raw(), local_size);
+    if let Some(pipeline) = COMPUTE_PIPELINE_CACHE.lock().unwrap().get(&cache_key) {
+        return Ok(ComputePipeline {
+            pipeline_layout,
+            pipeline: *pipeline,
+        });
+    }
 
     unsafe {
-        let shader_module = device
-            .create_shader_module(
-                &vk::ShaderModuleCreateInfo::builder().code(&shader_code),
-                None,
-            )
-            .unwrap();
+        let specialization_entries: Vec<vk::SpecializationMapEntry> = (0..3)
+            .map(|i| vk::SpecializationMapEntry {
+                constant_id: i,
+                offset: i * 4,
+                size: 4,
+            })
+            .collect();
+        let specialization_data: Vec<u8> = local_size
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect();
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specialization_entries)
+            .data(&specialization_data);
 
-        let stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
+        let mut stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
             .module(shader_module)
             .stage(vk::ShaderStageFlags::COMPUTE)
             .name(&shader_entry_name);
+        if local_size.is_some() {
+            stage_create_info = stage_create_info.specialization_info(&specialization_info);
+        }
 
-        let pipeline_layout = device
-            .create_pipeline_layout(&layout_create_info, None)
-            .unwrap();
+        let mut feedback = PipelineCreationFeedback::new(1);
+        let mut feedback_info = vk::PipelineCreationFeedbackCreateInfoEXT::builder()
+            .pipeline_creation_feedback(&mut feedback.pipeline)
+            .pipeline_stage_creation_feedbacks(&mut feedback.stages);
 
-        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+        let mut pipeline_info = vk::ComputePipelineCreateInfo::builder()
             .stage(stage_create_info.build())
             .layout(pipeline_layout);
+        if vk().caps.pipeline_creation_feedback {
+            pipeline_info = pipeline_info.push_next(&mut feedback_info);
+        }
 
-        // TODO: pipeline cache
+        let creation_start = std::time::Instant::now();
         let pipeline = device
-            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+            .create_compute_pipelines(
+                shader_cache::pipeline_cache(),
+                &[pipeline_info.build()],
+                None,
+            )
             .expect("pipeline")[0];
+        feedback.report(name, creation_start.elapsed());
+        shader_cache::pipeline_cache_updated();
+        vk().set_debug_object_name(pipeline, name);
+
+        // Another op might have created the same pipeline in the meantime
+        let mut cache = COMPUTE_PIPELINE_CACHE.lock().unwrap();
+        let cached = *cache.entry(cache_key).or_insert(pipeline);
+        if cached != pipeline {
+            device.destroy_pipeline(pipeline, None);
+        }
 
         Ok(ComputePipeline {
             pipeline_layout,
-            pipeline,
+            pipeline: cached,
         })
     }
 }
@@ -608,6 +1326,90 @@ fn get_cs_local_size_from_spirv(spirv: &[u32]) -> Result<(u32, u32, u32)> {
     bail!("Could not find a ExecutionMode SPIR-V op");
 }
 
+// Whether the workgroup size is given by specialization constants 0..2, e.g. via
+// `layout(local_size_x_id = 0, local_size_y_id = 1) in;`, which is what workgroup size
+// variants override. Ids 0..2 must not be used by anything else.
+fn cs_local_size_from_spec_constants(spirv: &[u32]) -> Result<bool> {
+    use rspirv::dr::Operand;
+    use spirv_headers::{BuiltIn, Decoration, Op};
+
+    let mut loader = rspirv::dr::Loader::new();
+    rspirv::binary::parse_words(spirv, &mut loader)
+        .map_err(|err| format_err!("Could not parse SPIR-V: {:?}", err))?;
+    let module = loader.module();
+
+    let mut spec_ids: HashMap<u32, u32> = HashMap::new();
+    let mut workgroup_size_id = None;
+    for inst in module.global_inst_iter() {
+        if inst.class.opcode != Op::Decorate {
+            continue;
+        }
+        let (id, decoration, value) = match inst.operands[..] {
+            [Operand::IdRef(id), Operand::Decoration(decoration), ref value] => {
+                (id, decoration, value)
+            }
+            _ => continue,
+        };
+        match (decoration, value) {
+            (Decoration::SpecId, Operand::LiteralInt32(spec_id)) => {
+                spec_ids.insert(id, *spec_id);
+            }
+            (Decoration::BuiltIn, Operand::BuiltIn(BuiltIn::WorkgroupSize)) => {
+                workgroup_size_id = Some(id);
+            }
+            _ => {}
+        }
+    }
+
+    let components: Vec<u32> = if let Some(workgroup_size_id) = workgroup_size_id {
+        // Overrides the `LocalSize` execution mode
+        let composite = module.global_inst_iter().find(|inst| {
+            inst.class.opcode == Op::SpecConstantComposite
+                && inst.result_id == Some(workgroup_size_id)
+        });
+        match composite {
+            Some(composite) => composite
+                .operands
+                .iter()
+                .filter_map(|operand| match operand {
+                    Operand::IdRef(id) => Some(*id),
+                    _ => None,
+                })
+                .collect(),
+            None => return Ok(false),
+        }
+    } else {
+        let local_size_id = Operand::ExecutionMode(spirv_headers::ExecutionMode::LocalSizeId);
+        let local_size_id = module.global_inst_iter().find(|inst| {
+            inst.class.opcode == Op::ExecutionModeId && inst.operands.get(1) == Some(&local_size_id)
+        });
+        match local_size_id {
+            Some(inst) => inst
+                .operands
+                .iter()
+                .skip(2)
+                .filter_map(|operand| match operand {
+                    Operand::IdRef(id) => Some(*id),
+                    _ => None,
+                })
+                .collect(),
+            None => return Ok(false),
+        }
+    };
+
+    if components.len() != 3 {
+        return Ok(false);
+    }
+
+    let specialized_components = components
+        .iter()
+        .enumerate()
+        .filter(|(i, id)| spec_ids.get(id) == Some(&(*i as u32)))
+        .count();
+    let used_ids = spec_ids.values().filter(|spec_id| **spec_id < 3).count();
+    Ok(specialized_components > 0 && specialized_components == used_ids)
+}
+
 // Pack descriptor sets so that they use small consecutive integers, e.g. sets [0, 5, 31] become [0, 1, 2]
 fn compact_descriptor_sets(refl: &mut spirv_reflect::ShaderModule, set_idx_offset: u32) -> u32 {
     let entry = Some("main");
@@ -624,11 +1426,9 @@ fn compact_descriptor_sets(refl: &mut spirv_reflect::ShaderModule, set_idx_offse
     set_count as u32
 }
 
-fn load_cs_impl(name: String, source: &[shader_prepper::SourceChunk]) -> Result<ComputeShader> {
+fn load_cs_impl(ctx: &Context, name: String, spirv: Vec<u32>) -> Result<ComputeShader> {
     let refl = {
-        let spirv = shaderc_compile_glsl(&name, source, shaderc::ShaderKind::Compute)?;
-
-        let mut refl = reflect_spirv_shader(spirv.as_binary())?;
+        let mut refl = reflect_spirv_shader(&spirv)?;
         compact_descriptor_sets(&mut refl, 0);
         refl
     };
@@ -644,62 +1444,129 @@ fn load_cs_impl(name: String, source: &[shader_prepper::SourceChunk]) -> Result<
 
     let vk = vk();
     let pipeline = create_compute_pipeline(
+        &name,
         &vk.device,
-        &descriptor_set_layout_info.all_layouts,
+        &descriptor_set_layout_info,
         &spirv_binary,
+        None,
     )?;
 
-    Ok(ComputeShader {
-        name,
-        pipeline,
-        spirv_reflection: refl,
-        descriptor_set_layout_info,
+    let mut candidates = snoozy::futures::executor::block_on(ctx.clone().get(
+        workgroup_autotune::workgroup_autotune_candidates(name.clone()),
+    ))?
+    .to_vec();
+    if !candidates.is_empty() && !cs_local_size_from_spec_constants(&spirv_binary)? {
+        // Overriding the constants would change something else entirely
+        tracing::warn!(
+            "Not autotuning {}: its workgroup size isn't given by specialization constants 0-2",
+            name
+        );
+        candidates.clear();
+    }
+
+    let workgroup_variants = candidates
+        .iter()
+        .map(|&size| {
+            create_compute_pipeline(
+                &name,
+                &vk.device,
+                &descriptor_set_layout_info,
+                &spirv_binary,
+                Some(size),
+            )
+            .map(|pipeline| (size, pipeline))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ComputeShader {
+        name,
+        pipeline,
+        spirv_reflection: Arc::new(refl),
+        descriptor_set_layout_info,
         local_size,
+        workgroup_variants,
     })
 }
 
+// The file stem, as used in profiler scopes and per-shader settings.
+fn shader_name_from_path(path: &AssetPath) -> String {
+    std::path::Path::new(&path.asset_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or("unknown".to_string())
+}
+
+fn hot_swap_key(kind: &str, path: &AssetPath) -> String {
+    format!("{}:{}", kind, path)
+}
+
+// Keeps returning the previous version while edits compile when shader hot swapping
+// is enabled; see `shader_hot_swap`.
 #[snoozy]
-pub async fn load_cs_snoozy(ctx: Context, path: &AssetPath) -> Result<ComputeShader> {
+pub async fn load_cs_snoozy(mut ctx: Context, path: &AssetPath) -> Result<ComputeShader> {
+    if !shader_hot_swap::is_shader_hot_swap_enabled() {
+        return Ok((*ctx.get(compile_cs(path.clone())).await?).clone());
+    }
+
+    shader_hot_swap::hot_swap(&mut ctx, hot_swap_key("cs", path), compile_cs(path.clone())).await
+}
+
+// Like `load_cs`, but always waits for the latest version.
+#[snoozy]
+pub async fn compile_cs_snoozy(mut ctx: Context, path: &AssetPath) -> Result<ComputeShader> {
+    let name = shader_name_from_path(path);
+
+    if path.asset_name.ends_with(".spv") {
+        let spirv = load_spirv_asset(&mut ctx, path).await?;
+        let res = load_cs_impl(&ctx, name, spirv)?;
+        shader_hot_swap::note_compiled(&hot_swap_key("cs", path));
+        return Ok(res);
+    }
+
+    // Acquired before preprocessing, so that queued compiles see the latest sources
+    let _compile_slot = shader_compile_queue::acquire_shader_compile_slot(&name).await;
+
     let source = shader_prepper::process_file(
         &path.asset_name,
         &mut ShaderIncludeProvider { ctx: ctx.clone() },
         AssetPath {
             crate_name: path.crate_name.clone(),
             asset_name: String::new(),
+            identity: None,
         },
     )?;
 
-    let name = std::path::Path::new(&path.asset_name)
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or("unknown".to_string());
-
-    load_cs_impl(name, &source)
+    let spirv = shaderc_compile_glsl(&ctx, &name, &path.to_string(), &source, ShaderKind::Compute)?;
+    let res = load_cs_impl(&ctx, name, spirv)?;
+    shader_hot_swap::note_compiled(&hot_swap_key("cs", path));
+    Ok(res)
 }
 
+// Includes resolve as if the shader were the file `name` in rendertoy's `shaders/` assets.
 #[snoozy]
 pub async fn load_cs_from_string_snoozy(
-    _ctx: Context,
+    ctx: Context,
     source: &String,
     name: &String,
 ) -> Result<ComputeShader> {
-    let source = [shader_prepper::SourceChunk {
-        source: source.clone(),
-        file: "no-file".to_owned(),
-        line_offset: 0,
-    }];
+    let source = preprocess_shader_string(&ctx, source, name)?;
 
+    let source_key = name.clone();
     let name = std::path::Path::new(&name)
         .file_stem()
         .map(|s| s.to_string_lossy().to_string())
         .unwrap_or("unknown".to_string());
 
-    load_cs_impl(name, &source)
+    let spirv = shaderc_compile_glsl(&ctx, &name, &source_key, &source, ShaderKind::Compute)?;
+    load_cs_impl(&ctx, name, spirv)
 }
 
+#[derive(Clone)]
 pub struct RasterSubShader {
     //module: spirv_reflect::ShaderModule, // Note: spirv_reflect::ShaderModule should not be Clone! It uses a Drop which will corrupt heap if cloned
-    spirv: shaderc::CompilationArtifact,
+    // The file stem, as for compute shaders
+    name: String,
+    spirv: Vec<u32>,
     stage_flags: vk::ShaderStageFlags,
 }
 
@@ -716,577 +1583,1802 @@ impl Drop for RasterSubShader {
 }
 
 #[snoozy]
-pub async fn load_vs_snoozy(ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+pub async fn load_vs_snoozy(mut ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+    if !shader_hot_swap::is_shader_hot_swap_enabled() {
+        return Ok((*ctx.get(compile_vs(path.clone())).await?).clone());
+    }
+
+    shader_hot_swap::hot_swap(&mut ctx, hot_swap_key("vs", path), compile_vs(path.clone())).await
+}
+
+#[snoozy]
+pub async fn compile_vs_snoozy(mut ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+    if path.asset_name.ends_with(".spv") {
+        let res = RasterSubShader {
+            name: shader_name_from_path(path),
+            spirv: load_spirv_asset(&mut ctx, path).await?,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+        };
+        shader_hot_swap::note_compiled(&hot_swap_key("vs", path));
+        return Ok(res);
+    }
+
+    let _compile_slot = shader_compile_queue::acquire_shader_compile_slot(&path.asset_name).await;
+
     let source = shader_prepper::process_file(
         &path.asset_name,
         &mut ShaderIncludeProvider { ctx: ctx.clone() },
         AssetPath {
             crate_name: path.crate_name.clone(),
             asset_name: String::new(),
+            identity: None,
         },
     )?;
 
-    let name = "vs"; // TODO
-    let spirv = shaderc_compile_glsl(&name, &source, shaderc::ShaderKind::Vertex)?;
+    let name = shader_name_from_path(path);
+    let spirv = shaderc_compile_glsl(&ctx, &name, &path.to_string(), &source, ShaderKind::Vertex)?;
 
+    shader_hot_swap::note_compiled(&hot_swap_key("vs", path));
     Ok(RasterSubShader {
+        name,
         spirv,
         stage_flags: vk::ShaderStageFlags::VERTEX,
     })
 }
 
 #[snoozy]
-pub async fn load_ps_snoozy(ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+pub async fn load_ps_snoozy(mut ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+    if !shader_hot_swap::is_shader_hot_swap_enabled() {
+        return Ok((*ctx.get(compile_ps(path.clone())).await?).clone());
+    }
+
+    shader_hot_swap::hot_swap(&mut ctx, hot_swap_key("ps", path), compile_ps(path.clone())).await
+}
+
+#[snoozy]
+pub async fn compile_ps_snoozy(mut ctx: Context, path: &AssetPath) -> Result<RasterSubShader> {
+    if path.asset_name.ends_with(".spv") {
+        let res = RasterSubShader {
+            name: shader_name_from_path(path),
+            spirv: load_spirv_asset(&mut ctx, path).await?,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        };
+        shader_hot_swap::note_compiled(&hot_swap_key("ps", path));
+        return Ok(res);
+    }
+
+    let _compile_slot = shader_compile_queue::acquire_shader_compile_slot(&path.asset_name).await;
+
     let source = shader_prepper::process_file(
         &path.asset_name,
         &mut ShaderIncludeProvider { ctx: ctx.clone() },
         AssetPath {
             crate_name: path.crate_name.clone(),
             asset_name: String::new(),
+            identity: None,
         },
     )?;
 
-    let name = "ps"; // TODO
-    let spirv = shaderc_compile_glsl(&name, &source, shaderc::ShaderKind::Fragment)?;
+    let name = shader_name_from_path(path);
+    let spirv = shaderc_compile_glsl(
+        &ctx,
+        &name,
+        &path.to_string(),
+        &source,
+        ShaderKind::Fragment,
+    )?;
 
+    shader_hot_swap::note_compiled(&hot_swap_key("ps", path));
     Ok(RasterSubShader {
+        name,
         spirv,
         stage_flags: vk::ShaderStageFlags::FRAGMENT,
     })
 }
 
+fn compile_raster_sub_shader_from_string(
+    ctx: &Context,
+    source: &str,
+    name: &str,
+    shader_kind: ShaderKind,
+    stage_flags: vk::ShaderStageFlags,
+) -> Result<RasterSubShader> {
+    let source = preprocess_shader_string(ctx, source, name)?;
+
+    let shader_name = std::path::Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or("unknown".to_string());
+
+    let spirv = shaderc_compile_glsl(ctx, &shader_name, name, &source, shader_kind)?;
+    Ok(RasterSubShader {
+        name: shader_name,
+        spirv,
+        stage_flags,
+    })
+}
+
+// Like `load_cs_from_string`, for raster pipelines.
+#[snoozy]
+pub async fn load_vs_from_string_snoozy(
+    ctx: Context,
+    source: &String,
+    name: &String,
+) -> Result<RasterSubShader> {
+    compile_raster_sub_shader_from_string(
+        &ctx,
+        source,
+        name,
+        ShaderKind::Vertex,
+        vk::ShaderStageFlags::VERTEX,
+    )
+}
+
+#[snoozy]
+pub async fn load_ps_from_string_snoozy(
+    ctx: Context,
+    source: &String,
+    name: &String,
+) -> Result<RasterSubShader> {
+    compile_raster_sub_shader_from_string(
+        &ctx,
+        source,
+        name,
+        ShaderKind::Fragment,
+        vk::ShaderStageFlags::FRAGMENT,
+    )
+}
+
 pub struct RasterPipeline {
+    // Names of the shaders, e.g. `mesh_vs + lit_ps`
+    name: String,
     pipeline: vk::Pipeline,
-    //shaders: Vec<RasterSubShader>,
+    // Kept for recompiling the pipeline into merged render passes
+    shaders: Vec<RasterSubShader>,
+    desc: RasterPipelineDesc,
+    // Uniforms bound to `subpassInput`s; see `try_merge_raster_pass`
+    input_attachments: Vec<String>,
     shader_refl: Vec<spirv_reflect::ShaderModule>,
     descriptor_set_layout_info: DescriptorSetLayoutInfo,
     pipeline_layout: vk::PipelineLayout,
     render_pass: vk::RenderPass,
+    // Compatible with `render_pass`, but loading the previous color contents.
+    // Only created for single-pass pipelines.
+    load_render_pass: vk::RenderPass,
     framebuffer: vk::Framebuffer,
+    // Set for pipelines from `make_raster_depth_pipeline`
+    depth_only: Option<DepthRasterDesc>,
+    vertex_layout: VertexLayout,
 }
 
 unsafe impl Send for RasterPipeline {}
 unsafe impl Sync for RasterPipeline {}
 
-#[snoozy]
-pub async fn make_raster_pipeline_snoozy(
-    mut ctx: Context,
-    shaders_in: &Vec<SnoozyRef<RasterSubShader>>,
-) -> Result<RasterPipeline> {
-    use std::ffi::CString;
+const RASTER_COLOR_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+// Depth test of `raster_depth_tex` passes. Shadow lookups into the result use the same
+// comparison, via the sampler from `sampler_name`, e.g.
+//
+//   uniform sampler shadow_less_equal_sampler;
+//   float lit = texture(sampler2DShadow(shadowTex, shadow_less_equal_sampler), vec3(uv, depth));
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub enum DepthCompare {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
 
-    let mut shaders = Vec::with_capacity(shaders_in.len());
-    for a in shaders_in.iter() {
-        shaders.push(ctx.get(&*a).await?);
+impl DepthCompare {
+    fn vk_compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthCompare::Less => vk::CompareOp::LESS,
+            DepthCompare::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+            DepthCompare::Greater => vk::CompareOp::GREATER,
+            DepthCompare::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+        }
     }
 
-    let surface_format = vk::Format::R32G32B32A32_SFLOAT;
-    //let (width, height) = vk().swapchain_size_pixels();
-    let width = 1;
-    let height = 1;
-
-    let renderpass_attachments = [
-        vk::AttachmentDescription {
-            format: surface_format,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::STORE,
-            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            ..Default::default()
-        },
-        vk::AttachmentDescription {
-            format: vk::Format::D32_SFLOAT,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            initial_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
-            final_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
-            ..Default::default()
-        },
-    ];
-    let color_attachment_refs = [vk::AttachmentReference {
-        attachment: 0,
-        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    }];
-    let depth_attachment_ref = vk::AttachmentReference {
-        attachment: 1,
-        layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
-    };
-    let dependencies = [vk::SubpassDependency {
-        src_subpass: vk::SUBPASS_EXTERNAL,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
-            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        ..Default::default()
-    }];
+    // The value nothing passes the test against
+    fn clear_depth(self) -> f32 {
+        match self {
+            DepthCompare::Less | DepthCompare::LessOrEqual => 1.0,
+            DepthCompare::Greater | DepthCompare::GreaterOrEqual => 0.0,
+        }
+    }
 
-    let subpasses = [vk::SubpassDescription::builder()
-        .color_attachments(&color_attachment_refs)
-        .depth_stencil_attachment(&depth_attachment_ref)
-        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .build()];
+    pub fn sampler_name(self) -> &'static str {
+        match self {
+            DepthCompare::Less => "shadow_less_sampler",
+            DepthCompare::LessOrEqual => "shadow_less_equal_sampler",
+            DepthCompare::Greater => "shadow_greater_sampler",
+            DepthCompare::GreaterOrEqual => "shadow_greater_equal_sampler",
+        }
+    }
+}
 
-    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
-        .attachments(&renderpass_attachments)
-        .subpasses(&subpasses)
-        .dependencies(&dependencies);
+// Matches the depth test of regular raster passes; see `coord_convention.rs`
+impl Default for DepthCompare {
+    fn default() -> Self {
+        match coord_convention::coord_convention().depth_compare_op() {
+            vk::CompareOp::GREATER_OR_EQUAL => DepthCompare::GreaterOrEqual,
+            _ => DepthCompare::LessOrEqual,
+        }
+    }
+}
 
-    let vk = vk();
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub enum BlendMode {
+    Opaque,
+    // Straight alpha: `src * src.a + dst * (1 - src.a)`
+    Alpha,
+    PremultipliedAlpha,
+    Additive,
+    Multiply,
+}
 
-    unsafe {
-        let render_pass = vk
-            .device
-            .create_render_pass(&render_pass_create_info, None)
-            .unwrap();
+impl BlendMode {
+    fn vk_attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        use vk::BlendFactor as F;
+
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => (F::ONE, F::ZERO, F::ONE, F::ZERO),
+            BlendMode::Alpha => (
+                F::SRC_ALPHA,
+                F::ONE_MINUS_SRC_ALPHA,
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::PremultipliedAlpha => (
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+                F::ONE,
+                F::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (F::ONE, F::ONE, F::ONE, F::ONE),
+            BlendMode::Multiply => (F::DST_COLOR, F::ZERO, F::DST_ALPHA, F::ZERO),
+        };
 
-        let mut descriptor_set_layout_info = DescriptorSetLayoutInfo::default();
-        let mut shader_modules_code = Vec::new();
-        let mut shader_refl = Vec::with_capacity(shaders.len());
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: (self != BlendMode::Opaque) as u32,
+            src_color_blend_factor: src_color,
+            dst_color_blend_factor: dst_color,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src_alpha,
+            dst_alpha_blend_factor: dst_alpha,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
+}
 
-        // TODO: more efficient concat
-        {
-            let mut dset_offset = 0u32;
-            for s in shaders.iter() {
-                let mut refl = reflect_spirv_shader(s.spirv.as_binary())?;
-                dset_offset += compact_descriptor_sets(&mut refl, dset_offset);
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
 
-                let mut shader_descriptor_set_info = convert_spirv_reflect_err(
-                    generate_descriptor_set_layouts(&refl, s.stage_flags),
-                )?;
+// Line and point topologies draw the mesh indices as such. Point sizes are set
+// by vertex shaders via `gl_PointSize`.
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub enum PrimitiveTopology {
+    TriangleList,
+    TriangleStrip,
+    LineList,
+    LineStrip,
+    PointList,
+}
 
-                shader_modules_code.push(refl.get_code());
-                shader_refl.push(refl);
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub enum VertexFormat {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Uint,
+    // Four bytes normalized to [0, 1], e.g. colors
+    Unorm8x4,
+}
 
-                descriptor_set_layout_info.append(&mut shader_descriptor_set_info);
-            }
+impl VertexFormat {
+    fn vk_format(self) -> vk::Format {
+        match self {
+            VertexFormat::Float => vk::Format::R32_SFLOAT,
+            VertexFormat::Vec2 => vk::Format::R32G32_SFLOAT,
+            VertexFormat::Vec3 => vk::Format::R32G32B32_SFLOAT,
+            VertexFormat::Vec4 => vk::Format::R32G32B32A32_SFLOAT,
+            VertexFormat::Uint => vk::Format::R32_UINT,
+            VertexFormat::Unorm8x4 => vk::Format::R8G8B8A8_UNORM,
         }
+    }
+}
 
-        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&descriptor_set_layout_info.all_layouts)
-            .build();
-        let pipeline_layout = vk
-            .device
-            .create_pipeline_layout(&layout_create_info, None)
-            .unwrap();
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub struct VertexAttribute {
+    // `layout(location = ..)` in the vertex shader
+    pub location: u32,
+    pub format: VertexFormat,
+    // In bytes, from the start of each element
+    pub offset: u32,
+}
+
+// A vertex buffer, read from the buffer uniform named `buffer` in the scope of each draw.
+#[derive(Eq, PartialEq, Hash, Clone, Serialize, Debug)]
+pub struct VertexBinding {
+    pub buffer: String,
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+    // Advanced per instance rather than per vertex; see `mesh_instance_count`
+    pub per_instance: bool,
+}
+
+// Vertex inputs of raster pipelines. Empty by default, with vertex shaders pulling
+// the attributes out of storage buffers themselves.
+#[derive(Eq, PartialEq, Hash, Clone, Serialize, Debug, Default)]
+pub struct VertexLayout {
+    pub bindings: Vec<VertexBinding>,
+}
+
+impl VertexLayout {
+    // One tightly packed buffer per attribute, at consecutive locations.
+    pub fn separate(buffers: &[(&str, VertexFormat)]) -> Self {
+        Self {
+            bindings: buffers
+                .iter()
+                .enumerate()
+                .map(|(location, (buffer, format))| VertexBinding {
+                    buffer: (*buffer).to_owned(),
+                    stride: vertex_format_size(*format),
+                    attributes: vec![VertexAttribute {
+                        location: location as u32,
+                        format: *format,
+                        offset: 0,
+                    }],
+                    per_instance: false,
+                })
+                .collect(),
+        }
+    }
 
-        let shader_entry_name = CString::new("main").unwrap();
-        let shader_stage_create_infos: Vec<_> = shaders
+    // Adds a per-instance buffer, with `attributes` tightly packed in order. A `mat4`
+    // takes four `Vec4` attributes at consecutive locations.
+    pub fn with_instance_buffer(
+        mut self,
+        buffer: &str,
+        attributes: &[(u32, VertexFormat)],
+    ) -> Self {
+        let mut offset = 0;
+        let attributes = attributes
             .iter()
-            .enumerate()
-            .map(|(sub_shader_idx, sub_shader)| {
-                let code = &shader_modules_code[sub_shader_idx];
-                let shader_info = vk::ShaderModuleCreateInfo::builder().code(code);
-                let shader_module = vk
-                    .device
-                    .create_shader_module(&shader_info, None)
-                    .expect("Shader module error");
-
-                vk::PipelineShaderStageCreateInfo {
-                    module: shader_module,
-                    p_name: shader_entry_name.as_ptr(),
-                    stage: sub_shader.stage_flags,
-                    ..Default::default()
-                }
+            .map(|&(location, format)| {
+                let attr = VertexAttribute {
+                    location,
+                    format,
+                    offset,
+                };
+                offset += vertex_format_size(format);
+                attr
             })
             .collect();
 
-        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo {
-            vertex_attribute_description_count: 0,
-            p_vertex_attribute_descriptions: std::ptr::null(),
-            vertex_binding_description_count: 0,
-            p_vertex_binding_descriptions: std::ptr::null(),
-            ..Default::default()
-        };
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
-
-        let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
-            .viewport_count(1)
-            .scissor_count(1);
+        self.bindings.push(VertexBinding {
+            buffer: buffer.to_owned(),
+            stride: offset,
+            attributes,
+            per_instance: true,
+        });
+        self
+    }
 
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            cull_mode: ash::vk::CullModeFlags::BACK,
-            ..Default::default()
-        };
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            ..Default::default()
-        };
-        let noop_stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            ..Default::default()
-        };
-        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
-            depth_test_enable: 1,
-            depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
-            front: noop_stencil_state,
-            back: noop_stencil_state,
-            max_depth_bounds: 1.0,
-            ..Default::default()
-        };
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::all(),
-        }];
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op(vk::LogicOp::CLEAR)
-            .attachments(&color_blend_attachment_states);
-
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info =
-            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
-
-        let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stage_create_infos)
-            .vertex_input_state(&vertex_input_state_info)
-            .input_assembly_state(&vertex_input_assembly_state_info)
-            .viewport_state(&viewport_state_info)
-            .rasterization_state(&rasterization_info)
-            .multisample_state(&multisample_state_info)
-            .depth_stencil_state(&depth_state_info)
-            .color_blend_state(&color_blend_state)
-            .dynamic_state(&dynamic_state_info)
-            .layout(pipeline_layout)
-            .render_pass(render_pass);
-
-        let graphics_pipelines = vk
-            .device
-            .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[graphic_pipeline_info.build()],
-                None,
-            )
-            .expect("Unable to create graphics pipeline");
+    // The buffers from `upload_raster_mesh`:
+    //
+    //   layout(location = 0) in vec3 position;
+    //   layout(location = 1) in vec3 normal;
+    //   layout(location = 2) in vec2 uv;
+    //   layout(location = 3) in vec4 color;
+    //   layout(location = 4) in vec4 tangent;
+    pub fn raster_mesh() -> Self {
+        let mut layout = Self::separate(&[
+            ("mesh_vertex_buf", VertexFormat::Vec3),
+            ("mesh_normal_buf", VertexFormat::Vec3),
+            ("mesh_uv_buf", VertexFormat::Vec2),
+            ("mesh_color_buf", VertexFormat::Vec4),
+            ("mesh_tangent_buf", VertexFormat::Vec4),
+        ]);
+
+        // Positions are followed by packed normals, as used for vertex pulling
+        layout.bindings[0].stride = 16;
+        layout
+    }
 
-        let framebuffer = {
-            let color_formats = [surface_format];
-            let color_attachment = vk::FramebufferAttachmentImageInfoKHR::builder()
-                .width(width as _)
-                .height(height as _)
-                .flags(vk::ImageCreateFlags::MUTABLE_FORMAT)
-                .layer_count(1)
-                .view_formats(&color_formats)
-                .usage(
-                    vk::ImageUsageFlags::SAMPLED
-                        | vk::ImageUsageFlags::TRANSFER_DST
-                        | vk::ImageUsageFlags::STORAGE
-                        | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                )
-                .build();
-            let depth_attachment = vk::FramebufferAttachmentImageInfoKHR::builder()
-                .width(width as _)
-                .height(height as _)
-                .layer_count(1)
-                .view_formats(&[vk::Format::D32_SFLOAT])
-                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-                .build();
-            let attachments = [color_attachment, depth_attachment];
-            let mut imageless_desc = vk::FramebufferAttachmentsCreateInfoKHR::builder()
-                .attachment_image_infos(&attachments);
-            let mut fbo_desc = vk::FramebufferCreateInfo::builder()
-                .flags(vk::FramebufferCreateFlags::IMAGELESS_KHR)
-                .render_pass(render_pass)
-                .width(width as _)
-                .height(height as _)
-                .layers(1)
-                .push_next(&mut imageless_desc);
-            fbo_desc.attachment_count = 2;
-            vk.device.create_framebuffer(&fbo_desc, None)?
-        };
+    fn validate(&self) -> Result<()> {
+        let mut locations = HashSet::new();
+        for binding in self.bindings.iter() {
+            for attr in binding.attributes.iter() {
+                if !locations.insert(attr.location) {
+                    bail!("Vertex attribute location {} used twice", attr.location);
+                }
+                if attr.offset + vertex_format_size(attr.format) > binding.stride {
+                    bail!(
+                        "Vertex attribute at location {} doesn't fit in the {} byte stride of {}",
+                        attr.location,
+                        binding.stride,
+                        binding.buffer
+                    );
+                }
+            }
+        }
 
-        let graphic_pipeline = graphics_pipelines[0];
-        Ok(RasterPipeline {
-            pipeline: graphic_pipeline,
-            //shaders: shaders,
-            shader_refl,
-            descriptor_set_layout_info,
-            pipeline_layout,
-            render_pass,
-            framebuffer,
-        })
+        Ok(())
     }
 }
 
-pub enum FlattenedUniformEvent {
-    SetUniform {
-        name: String,
-        payload: ResolvedShaderUniformPayload,
-    },
-    EnterScope,
-    LeaveScope,
+fn vertex_format_size(format: VertexFormat) -> u32 {
+    match format {
+        VertexFormat::Float | VertexFormat::Uint | VertexFormat::Unorm8x4 => 4,
+        VertexFormat::Vec2 => 8,
+        VertexFormat::Vec3 => 12,
+        VertexFormat::Vec4 => 16,
+    }
 }
 
-fn flatten_uniforms(
-    mut uniforms: Vec<ResolvedShaderUniformHolder>,
-    sink: &mut impl FnMut(FlattenedUniformEvent),
-) {
-    // Do non-bundle values first so that they become visible to bundle handlers
-    for uniform in uniforms.iter_mut() {
-        let warn_if_unreferenced = uniform.payload.warn_if_unreferenced;
+// Fixed function state of raster pipelines. The default is what mesh passes used before
+// this was configurable: opaque, culling back faces, with the standard depth test.
+#[derive(Eq, PartialEq, Hash, Clone, Serialize, Debug)]
+pub struct RasterPipelineDesc {
+    // Per color attachment, or a single mode for all of them
+    pub blend: Vec<BlendMode>,
+    pub cull_mode: CullMode,
+    pub depth_test: bool,
+    // Only takes effect with `depth_test`
+    pub depth_write: bool,
+    pub depth_compare: DepthCompare,
+    pub topology: PrimitiveTopology,
+    pub vertex_layout: VertexLayout,
+}
 
-        match uniform.payload.value {
-            ResolvedShaderUniformValue::Bundle(_) => {}
-            ResolvedShaderUniformValue::Texture(ref value)
-            | ResolvedShaderUniformValue::RwTexture(ref value) => {
-                let name = std::mem::replace(&mut uniform.name, String::new());
+impl Default for RasterPipelineDesc {
+    fn default() -> Self {
+        Self {
+            blend: vec![BlendMode::Opaque],
+            cull_mode: CullMode::Back,
+            depth_test: true,
+            depth_write: true,
+            depth_compare: DepthCompare::default(),
+            topology: PrimitiveTopology::TriangleList,
+            vertex_layout: VertexLayout::default(),
+        }
+    }
+}
 
-                let tex_size_uniform = (
-                    value.key.width as f32,
-                    value.key.height as f32,
-                    1f32 / value.key.width as f32,
-                    1f32 / value.key.height as f32,
-                );
+impl RasterPipelineDesc {
+    // Depth tested, but not written, e.g. for transparent surfaces
+    pub fn transparent(blend: BlendMode) -> Self {
+        Self {
+            blend: vec![blend],
+            cull_mode: CullMode::None,
+            depth_write: false,
+            ..Default::default()
+        }
+    }
 
-                sink(FlattenedUniformEvent::SetUniform {
-                    name: name.clone() + "_size",
-                    payload: ResolvedShaderUniformPayload {
-                        value: ResolvedShaderUniformValue::Vec4(tex_size_uniform),
-                        warn_if_unreferenced: false,
-                    },
-                });
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = vec![blend];
+        self
+    }
 
-                sink(FlattenedUniformEvent::SetUniform {
-                    name,
-                    payload: match &uniform.payload.value {
-                        ResolvedShaderUniformValue::Texture(ref value) => {
-                            ResolvedShaderUniformPayload {
-                                value: ResolvedShaderUniformValue::Texture(value.clone()),
-                                warn_if_unreferenced,
-                            }
-                        }
-                        ResolvedShaderUniformValue::RwTexture(ref value) => {
-                            ResolvedShaderUniformPayload {
-                                value: ResolvedShaderUniformValue::RwTexture(value.clone()),
-                                warn_if_unreferenced,
-                            }
-                        }
-                        _ => panic!(),
-                    },
-                });
-            }
-            _ => {
-                let name = std::mem::replace(&mut uniform.name, String::new());
-                let value = std::mem::replace(
-                    &mut uniform.payload.value,
-                    ResolvedShaderUniformValue::Int32(0),
-                );
+    pub fn with_blend_per_attachment(mut self, blend: Vec<BlendMode>) -> Self {
+        self.blend = blend;
+        self
+    }
 
-                sink(FlattenedUniformEvent::SetUniform {
-                    name,
-                    payload: ResolvedShaderUniformPayload {
-                        value,
-                        warn_if_unreferenced,
-                    },
-                });
-            }
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_depth(mut self, test: bool, write: bool, compare: DepthCompare) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare = compare;
+        self
+    }
+
+    pub fn with_topology(mut self, topology: PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_vertex_layout(mut self, vertex_layout: VertexLayout) -> Self {
+        self.vertex_layout = vertex_layout;
+        self
+    }
+
+    // No depth, and no culling, as used by the later stages of raster chains
+    fn fullscreen() -> Self {
+        Self {
+            cull_mode: CullMode::None,
+            depth_test: false,
+            depth_write: false,
+            ..Default::default()
         }
     }
 
-    // Now process bundles
-    for uniform in uniforms.into_iter() {
-        match uniform.payload.value {
-            ResolvedShaderUniformValue::Bundle(bundle) => {
-                sink(FlattenedUniformEvent::EnterScope);
-                flatten_uniforms(bundle, sink);
-                sink(FlattenedUniformEvent::LeaveScope);
-            }
-            _ => {}
+    fn validate(&self, color_attachment_count: usize) -> Result<()> {
+        if self.blend.len() > 1 && self.blend.len() != color_attachment_count {
+            bail!(
+                "Got {} blend modes for {} color attachments",
+                self.blend.len(),
+                color_attachment_count
+            );
+        }
+
+        let blends = self.blend.iter().any(|b| *b != BlendMode::Opaque);
+        if blends
+            && color_attachment_count > 0
+            && !format_supports(
+                RASTER_COLOR_FORMAT,
+                vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND,
+            )
+        {
+            bail!(
+                "The device can't blend into {:?} render targets",
+                RASTER_COLOR_FORMAT
+            );
+        }
+
+        if self.depth_write && !self.depth_test {
+            bail!("Depth writes need the depth test to be enabled");
         }
+
+        self.vertex_layout.validate()
     }
-}
 
-struct DescritorSetUpdateResult {
-    dynamic_offsets: Vec<u32>,
-    all_buffers_descriptor_set_idx: Vec<usize>,
-    all_textures_descriptor_set_idx: Vec<usize>,
+    fn blend_attachment_states(
+        &self,
+        color_attachment_count: usize,
+    ) -> Vec<vk::PipelineColorBlendAttachmentState> {
+        (0..color_attachment_count)
+            .map(|i| {
+                self.blend
+                    .get(i)
+                    .or_else(|| self.blend.first())
+                    .copied()
+                    .unwrap_or(BlendMode::Opaque)
+                    .vk_attachment_state()
+            })
+            .collect()
+    }
 }
 
-trait DescriptorSetSlice {
-    fn get_at_idx(&self, idx: usize) -> std::result::Result<vk::DescriptorSet, &'static str>;
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Debug)]
+pub struct DepthRasterDesc {
+    // One of `D16_UNORM`, `X8_D24_UNORM_PACK32` or `D32_SFLOAT`
+    pub format: i32,
+    pub compare: DepthCompare,
 }
 
-impl DescriptorSetSlice for Vec<Option<vk::DescriptorSet>> {
-    fn get_at_idx(&self, idx: usize) -> std::result::Result<vk::DescriptorSet, &'static str> {
-        match self[idx] {
-            Some(d) => Ok(d),
-            None => Err("Not a dynamic descriptor set"),
+impl DepthRasterDesc {
+    pub fn new(format: vk::Format) -> Self {
+        Self {
+            format: format.as_raw(),
+            compare: DepthCompare::default(),
         }
     }
-}
 
-trait UniformParamSource {
-    fn len(&self) -> usize;
-    fn get(&mut self, name: &str) -> Option<&ResolvedShaderUniformValue>;
+    pub fn with_compare(mut self, compare: DepthCompare) -> Self {
+        self.compare = compare;
+        self
+    }
 }
 
-fn update_descriptor_sets<'a>(
-    device: &Device,
-    refl: impl Iterator<Item = &'a spirv_reflect::ShaderModule>,
-    descriptor_sets: &impl DescriptorSetSlice,
-    uniforms: &mut impl UniformParamSource,
-) -> std::result::Result<DescritorSetUpdateResult, &'static str> {
-    use std::cell::RefCell;
-
-    let mut ds_offsets = Vec::new();
+// Creates a render pass with `subpass_count` chained subpasses. Each subpass writes
+// its own color attachment, and every subpass after the first one reads the output
+// of its predecessor as an input attachment. Only the first subpass uses depth.
+//
+// Attachment layout: [color 0, .., color N-1, depth, external input]. Unless
+// `store_intermediates` is set, intermediate colors are not stored, so on tile-based GPUs
+// they can stay in tile memory for the whole chain. With `external_input`, the first
+// subpass reads an attachment written by an earlier pass instead.
+fn create_raster_render_pass(
+    subpass_count: usize,
+    color_load_op: vk::AttachmentLoadOp,
+    store_intermediates: bool,
+    external_input: bool,
+) -> Result<vk::RenderPass> {
+    assert!(subpass_count > 0);
+
+    // Loaded attachments get transitioned by the barrier before the pass
+    let color_initial_layout = if color_load_op == vk::AttachmentLoadOp::LOAD {
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::UNDEFINED
+    };
 
-    #[derive(Default)]
-    pub struct Cache {
-        ds_image_info: RefCell<Vec<[vk::DescriptorImageInfo; 1]>>,
-        ds_buffer_info: RefCell<Vec<[vk::DescriptorBufferInfo; 1]>>,
-        ds_buffer_views: RefCell<Vec<[vk::BufferView; 1]>>,
-        ds_writes: RefCell<Vec<vk::WriteDescriptorSet>>,
+    let mut renderpass_attachments = Vec::with_capacity(subpass_count + 2);
+    for i in 0..subpass_count {
+        let is_last = i + 1 == subpass_count;
+        renderpass_attachments.push(vk::AttachmentDescription {
+            format: RASTER_COLOR_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: color_load_op,
+            store_op: if is_last || store_intermediates {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            },
+            initial_layout: color_initial_layout,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        });
+    }
+    renderpass_attachments.push(vk::AttachmentDescription {
+        format: vk::Format::D32_SFLOAT,
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        initial_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+        final_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+        ..Default::default()
+    });
+    let external_input_idx = renderpass_attachments.len();
+    if external_input {
+        // Transitioned for sampling by the barrier before the pass, like other inputs
+        renderpass_attachments.push(vk::AttachmentDescription {
+            format: RASTER_COLOR_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        });
     }
 
-    // TODO: cache those in thread-locals
-    thread_local! {
-        pub static CACHE: Cache = Default::default();
+    let color_attachment_refs: Vec<_> = (0..subpass_count)
+        .map(|i| {
+            [vk::AttachmentReference {
+                attachment: i as u32,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }]
+        })
+        .collect();
+    let input_attachment_refs: Vec<_> = (0..subpass_count)
+        .map(|i| {
+            [vk::AttachmentReference {
+                attachment: if i == 0 {
+                    external_input_idx as u32
+                } else {
+                    (i - 1) as u32
+                },
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }]
+        })
+        .collect();
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: subpass_count as u32,
+        layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+    };
+
+    let mut dependencies = vec![vk::SubpassDependency {
+        src_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ..Default::default()
+    }];
+    for i in 1..subpass_count {
+        dependencies.push(vk::SubpassDependency {
+            src_subpass: (i - 1) as u32,
+            dst_subpass: i as u32,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            dependency_flags: vk::DependencyFlags::BY_REGION,
+        });
     }
 
-    let mut all_buffers_descriptor_set_idx = Vec::new();
-    let mut all_textures_descriptor_set_idx = Vec::new();
+    let subpasses: Vec<_> = (0..subpass_count)
+        .map(|i| {
+            let mut desc = vk::SubpassDescription::builder()
+                .color_attachments(&color_attachment_refs[i])
+                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
 
-    CACHE.with(|cache| -> std::result::Result<(), &'static str> {
-        let mut ds_image_info = cache.ds_image_info.borrow_mut();
-        let mut ds_buffer_info = cache.ds_buffer_info.borrow_mut();
-        let mut ds_buffer_views = cache.ds_buffer_views.borrow_mut();
-        let mut ds_writes = cache.ds_writes.borrow_mut();
+            if 0 == i {
+                desc = desc.depth_stencil_attachment(&depth_attachment_ref);
+            }
+            if 0 != i || external_input {
+                desc = desc.input_attachments(&input_attachment_refs[i]);
+            }
+            desc.build()
+        })
+        .collect();
 
-        ds_image_info.clear();
-        ds_image_info.reserve(uniforms.len());
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&renderpass_attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
 
-        ds_buffer_info.clear();
-        ds_buffer_info.reserve(uniforms.len());
+    Ok(unsafe {
+        vk().device
+            .create_render_pass(&render_pass_create_info, None)?
+    })
+}
 
-        ds_buffer_views.clear();
-        ds_buffer_views.reserve(uniforms.len());
+// A single subpass writing `color_count` attachments, followed by depth.
+fn create_raster_mrt_render_pass(color_count: usize) -> Result<vk::RenderPass> {
+    assert!(color_count > 0);
 
-        ds_writes.clear();
-        ds_writes.reserve(uniforms.len());
+    let mut renderpass_attachments: Vec<_> = (0..color_count)
+        .map(|_| vk::AttachmentDescription {
+            format: RASTER_COLOR_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        })
+        .collect();
+    renderpass_attachments.push(vk::AttachmentDescription {
+        format: vk::Format::D32_SFLOAT,
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        initial_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+        final_layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+        ..Default::default()
+    });
 
-        let vk_state = vk_state();
-        let vk_frame = vk_state.current_frame();
+    let color_attachment_refs: Vec<_> = (0..color_count)
+        .map(|i| vk::AttachmentReference {
+            attachment: i as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect();
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: color_count as u32,
+        layout: vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+    };
 
-        for refl in refl {
-            let entry = Some("main");
-            for descriptor_set in refl.enumerate_descriptor_sets(entry)?.iter() {
-                for binding in descriptor_set.bindings.iter() {
-                    use spirv_reflect::types::descriptor::ReflectDescriptorType;
+    let dependencies = [vk::SubpassDependency {
+        src_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ..Default::default()
+    }];
 
-                    match binding.descriptor_type {
-                        ReflectDescriptorType::UniformBuffer => {
-                            let buffer_bytes = binding.block.size as usize;
-                            let (buffer_handle, buffer_offset, buffer_contents) = vk_frame
-                                .uniforms
-                                .allocate(buffer_bytes)
-                                .expect("failed to allocate uniform buffer");
+    let subpasses = [vk::SubpassDescription::builder()
+        .color_attachments(&color_attachment_refs)
+        .depth_stencil_attachment(&depth_attachment_ref)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .build()];
 
-                            for member in binding.block.members.iter() {
-                                if let Some(value) = uniforms.get(&member.name) {
-                                    let dst_mem = &mut buffer_contents[member.absolute_offset
-                                        as usize
-                                        ..(member.absolute_offset + member.size) as usize];
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&renderpass_attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
 
-                                    match value {
-                                        ResolvedShaderUniformValue::Float32(value) => {
-                                            dst_mem.copy_from_slice(&(*value).to_ne_bytes());
-                                        }
-                                        ResolvedShaderUniformValue::Uint32(value) => {
-                                            dst_mem.copy_from_slice(&(*value).to_ne_bytes());
-                                        }
-                                        ResolvedShaderUniformValue::Int32(value) => {
-                                            dst_mem.copy_from_slice(&(*value).to_ne_bytes());
-                                        }
-                                        ResolvedShaderUniformValue::Ivec2(value) => {
-                                            dst_mem.copy_from_slice(unsafe {
-                                                std::slice::from_raw_parts(
-                                                    std::mem::transmute(&value.0 as *const i32),
-                                                    2 * 4,
-                                                )
-                                            });
-                                        }
-                                        ResolvedShaderUniformValue::Vec4(value) => {
-                                            dst_mem.copy_from_slice(unsafe {
-                                                std::slice::from_raw_parts(
-                                                    std::mem::transmute(&value.0 as *const f32),
-                                                    4 * 4,
-                                                )
-                                            });
-                                        }
-                                        _ => {
-                                            dbg!(member);
-                                            unimplemented!();
-                                        }
-                                    }
-                                }
-                            }
+    Ok(unsafe {
+        vk().device
+            .create_render_pass(&render_pass_create_info, None)?
+    })
+}
 
-                            let buffer_info = [vk::DescriptorBufferInfo::builder()
-                                .buffer(buffer_handle)
-                                .range(buffer_bytes as u64)
-                                .build()];
-                            ds_buffer_info.push(buffer_info);
-                            let buffer_info = ds_buffer_info.last().unwrap();
+// A single subpass writing just depth, which gets stored for sampling.
+fn create_raster_depth_render_pass(format: vk::Format) -> Result<vk::RenderPass> {
+    let renderpass_attachments = [vk::AttachmentDescription {
+        format,
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        store_op: vk::AttachmentStoreOp::STORE,
+        initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ..Default::default()
+    }];
 
-                            ds_offsets.push(buffer_offset as u32);
-                            ds_writes.push(
-                                vk::WriteDescriptorSet::builder()
-                                    .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
-                                    .dst_binding(binding.binding)
-                                    .dst_array_element(0)
-                                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
-                                    .buffer_info(buffer_info)
-                                    .build(),
-                            );
-                        }
-                        ReflectDescriptorType::SampledImage => {
-                            if let Some(ResolvedShaderUniformValue::Texture(value)) =
-                                uniforms.get(&binding.name)
-                            {
-                                let image_info = [vk::DescriptorImageInfo::builder()
-                                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                                    .image_view(value.view)
-                                    .build()];
-                                ds_image_info.push(image_info);
-                                let image_info = ds_image_info.last().unwrap();
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
 
-                                ds_writes.push(
-                                    vk::WriteDescriptorSet::builder()
-                                        .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
-                                        .dst_binding(binding.binding)
-                                        .dst_array_element(0)
-                                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
-                                        .image_info(image_info)
-                                        .build(),
-                                )
-                            } else {
-                                if "all_textures" == binding.name {
-                                    // Updated elsewhere. Do note the set idx so we can assign the descriptor set afterwards.
-                                    all_textures_descriptor_set_idx.push(binding.set as usize);
-                                } else {
-                                    // TODO
-                                    panic!("Could not find resource to bind {}", binding.name);
-                                }
+    let dependencies = [vk::SubpassDependency {
+        src_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        ..Default::default()
+    }];
+
+    let subpasses = [vk::SubpassDescription::builder()
+        .depth_stencil_attachment(&depth_attachment_ref)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .build()];
+
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&renderpass_attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+
+    Ok(unsafe {
+        vk().device
+            .create_render_pass(&render_pass_create_info, None)?
+    })
+}
+
+fn find_input_attachment_names(refl: &[spirv_reflect::ShaderModule]) -> Result<Vec<String>> {
+    use spirv_reflect::types::descriptor::ReflectDescriptorType;
+
+    let mut names = Vec::new();
+    for refl in refl {
+        for descriptor_set in
+            convert_spirv_reflect_err(refl.enumerate_descriptor_sets(Some("main")))?.iter()
+        {
+            for binding in descriptor_set.bindings.iter() {
+                if let ReflectDescriptorType::InputAttachment = binding.descriptor_type {
+                    names.push(binding.name.clone());
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+unsafe fn create_raster_pipeline(
+    shaders: &[impl std::ops::Deref<Target = RasterSubShader>],
+    render_pass: vk::RenderPass,
+    subpass: u32,
+    color_attachment_count: usize,
+    desc: &RasterPipelineDesc,
+) -> Result<RasterPipeline> {
+    use std::ffi::CString;
+
+    desc.validate(color_attachment_count)?;
+
+    let vk = vk();
+
+    let mut descriptor_set_layout_info = DescriptorSetLayoutInfo::default();
+    let mut shader_modules_code = Vec::new();
+    let mut shader_refl = Vec::with_capacity(shaders.len());
+
+    // TODO: more efficient concat
+    {
+        let mut dset_offset = 0u32;
+        for s in shaders.iter() {
+            let mut refl = reflect_spirv_shader(&s.spirv)?;
+            dset_offset += compact_descriptor_sets(&mut refl, dset_offset);
+
+            let mut shader_descriptor_set_info =
+                convert_spirv_reflect_err(generate_descriptor_set_layouts(&refl, s.stage_flags))?;
+
+            shader_modules_code.push(refl.get_code());
+            shader_refl.push(refl);
+
+            descriptor_set_layout_info.append(&mut shader_descriptor_set_info);
+        }
+    }
+
+    let pipeline_layout = get_or_create_pipeline_layout(&vk.device, &descriptor_set_layout_info);
+
+    let shader_entry_name = CString::new("main").unwrap();
+    let shader_stage_create_infos: Vec<_> = shaders
+        .iter()
+        .enumerate()
+        .map(|(sub_shader_idx, sub_shader)| {
+            let code = &shader_modules_code[sub_shader_idx];
+            let shader_module = get_or_create_shader_module(&vk.device, code);
+
+            vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: shader_entry_name.as_ptr(),
+                stage: sub_shader.stage_flags,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let vertex_binding_descriptions: Vec<_> = desc
+        .vertex_layout
+        .bindings
+        .iter()
+        .enumerate()
+        .map(|(i, binding)| vk::VertexInputBindingDescription {
+            binding: i as u32,
+            stride: binding.stride,
+            input_rate: if binding.per_instance {
+                vk::VertexInputRate::INSTANCE
+            } else {
+                vk::VertexInputRate::VERTEX
+            },
+        })
+        .collect();
+    let vertex_attribute_descriptions: Vec<_> = desc
+        .vertex_layout
+        .bindings
+        .iter()
+        .enumerate()
+        .flat_map(|(i, binding)| {
+            binding
+                .attributes
+                .iter()
+                .map(move |attr| vk::VertexInputAttributeDescription {
+                    location: attr.location,
+                    binding: i as u32,
+                    format: attr.format.vk_format(),
+                    offset: attr.offset,
+                })
+        })
+        .collect();
+    let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&vertex_binding_descriptions)
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions);
+    let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+        topology: match desc.topology {
+            PrimitiveTopology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+            PrimitiveTopology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+            PrimitiveTopology::LineList => vk::PrimitiveTopology::LINE_LIST,
+            PrimitiveTopology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+            PrimitiveTopology::PointList => vk::PrimitiveTopology::POINT_LIST,
+        },
+        ..Default::default()
+    };
+
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        polygon_mode: vk::PolygonMode::FILL,
+        cull_mode: match desc.cull_mode {
+            CullMode::None => vk::CullModeFlags::NONE,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::Back => vk::CullModeFlags::BACK,
+        },
+        ..Default::default()
+    };
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+        rasterization_samples: vk::SampleCountFlags::TYPE_1,
+        ..Default::default()
+    };
+    let noop_stencil_state = vk::StencilOpState {
+        fail_op: vk::StencilOp::KEEP,
+        pass_op: vk::StencilOp::KEEP,
+        depth_fail_op: vk::StencilOp::KEEP,
+        compare_op: vk::CompareOp::ALWAYS,
+        ..Default::default()
+    };
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: desc.depth_test as u32,
+        depth_write_enable: desc.depth_write as u32,
+        depth_compare_op: desc.depth_compare.vk_compare_op(),
+        front: noop_stencil_state,
+        back: noop_stencil_state,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+    let color_blend_attachment_states = desc.blend_attachment_states(color_attachment_count);
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op(vk::LogicOp::CLEAR)
+        .attachments(&color_blend_attachment_states);
+
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+
+    let mut feedback = PipelineCreationFeedback::new(shader_stage_create_infos.len());
+    let mut feedback_info = vk::PipelineCreationFeedbackCreateInfoEXT::builder()
+        .pipeline_creation_feedback(&mut feedback.pipeline)
+        .pipeline_stage_creation_feedbacks(&mut feedback.stages);
+
+    let mut graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(subpass);
+    if vk.caps.pipeline_creation_feedback {
+        graphic_pipeline_info = graphic_pipeline_info.push_next(&mut feedback_info);
+    }
+
+    let creation_start = std::time::Instant::now();
+    let graphics_pipelines = vk
+        .device
+        .create_graphics_pipelines(
+            shader_cache::pipeline_cache(),
+            &[graphic_pipeline_info.build()],
+            None,
+        )
+        .expect("Unable to create graphics pipeline");
+    let name = shaders
+        .iter()
+        .map(|shader| shader.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" + ");
+    feedback.report(&name, creation_start.elapsed());
+    shader_cache::pipeline_cache_updated();
+    vk.set_debug_object_name(graphics_pipelines[0], &name);
+
+    Ok(RasterPipeline {
+        name,
+        pipeline: graphics_pipelines[0],
+        shaders: shaders.iter().map(|s| (**s).clone()).collect(),
+        desc: desc.clone(),
+        input_attachments: find_input_attachment_names(&shader_refl)?,
+        shader_refl,
+        descriptor_set_layout_info,
+        pipeline_layout,
+        render_pass,
+        load_render_pass: vk::RenderPass::null(),
+        framebuffer: vk::Framebuffer::null(),
+        depth_only: None,
+        vertex_layout: desc.vertex_layout.clone(),
+    })
+}
+
+// `RasterPipelineDesc::default()` for opaque, depth tested meshes.
+#[snoozy]
+pub async fn make_raster_pipeline_snoozy(
+    mut ctx: Context,
+    shaders_in: &Vec<SnoozyRef<RasterSubShader>>,
+    desc: &RasterPipelineDesc,
+) -> Result<RasterPipeline> {
+    let mut shaders = Vec::with_capacity(shaders_in.len());
+    for a in shaders_in.iter() {
+        shaders.push(ctx.get(&*a).await?);
+    }
+
+    let surface_format = RASTER_COLOR_FORMAT;
+    //let (width, height) = vk().swapchain_size_pixels();
+    let width = 1;
+    let height = 1;
+
+    let vk = vk();
+
+    unsafe {
+        let render_pass = create_raster_render_pass(1, vk::AttachmentLoadOp::CLEAR, false, false)?;
+        let mut pipeline = create_raster_pipeline(&shaders, render_pass, 0, 1, desc)?;
+        pipeline.load_render_pass =
+            create_raster_render_pass(1, vk::AttachmentLoadOp::LOAD, false, false)?;
+
+        pipeline.framebuffer = {
+            let color_formats = [surface_format];
+            let color_attachment = vk::FramebufferAttachmentImageInfoKHR::builder()
+                .width(width as _)
+                .height(height as _)
+                .flags(vk::ImageCreateFlags::MUTABLE_FORMAT)
+                .layer_count(1)
+                .view_formats(&color_formats)
+                // Must match the usage `allocate_payload` gives `raster_tex` outputs
+                .usage(
+                    vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST
+                        | vk::ImageUsageFlags::STORAGE
+                        | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+                )
+                .build();
+            let depth_attachment = vk::FramebufferAttachmentImageInfoKHR::builder()
+                .width(width as _)
+                .height(height as _)
+                .layer_count(1)
+                .view_formats(&[vk::Format::D32_SFLOAT])
+                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+                .build();
+            let attachments = [color_attachment, depth_attachment];
+            let mut imageless_desc = vk::FramebufferAttachmentsCreateInfoKHR::builder()
+                .attachment_image_infos(&attachments);
+            let mut fbo_desc = vk::FramebufferCreateInfo::builder()
+                .flags(vk::FramebufferCreateFlags::IMAGELESS_KHR)
+                .render_pass(render_pass)
+                .width(width as _)
+                .height(height as _)
+                .layers(1)
+                .push_next(&mut imageless_desc);
+            fbo_desc.attachment_count = 2;
+            vk.device.create_framebuffer(&fbo_desc, None)?
+        };
+
+        Ok(pipeline)
+    }
+}
+
+// A chain of raster pipelines recorded as subpasses of a single render pass.
+// The first stage rasterizes meshes just like `raster_tex`; every following stage
+// is a fullscreen pass which reads the previous stage via `subpassInput inputTex`.
+// Consecutive `raster_tex` passes reading each other via `subpassInput` get merged
+// into such chains automatically, see `try_merge_raster_pass`.
+pub struct RasterChainPipeline {
+    render_pass: vk::RenderPass,
+    stages: Vec<RasterPipeline>,
+}
+
+#[snoozy]
+pub async fn make_raster_chain_pipeline_snoozy(
+    mut ctx: Context,
+    stages_in: &Vec<Vec<SnoozyRef<RasterSubShader>>>,
+) -> Result<RasterChainPipeline> {
+    if stages_in.is_empty() {
+        bail!("A raster chain needs at least one stage");
+    }
+
+    let render_pass =
+        create_raster_render_pass(stages_in.len(), vk::AttachmentLoadOp::CLEAR, false, false)?;
+
+    let mut stages = Vec::with_capacity(stages_in.len());
+    for (subpass, shaders_in) in stages_in.iter().enumerate() {
+        let mut shaders = Vec::with_capacity(shaders_in.len());
+        for a in shaders_in.iter() {
+            shaders.push(ctx.get(&*a).await?);
+        }
+
+        stages.push(unsafe {
+            create_raster_pipeline(
+                &shaders,
+                render_pass,
+                subpass as u32,
+                1,
+                &if subpass == 0 {
+                    RasterPipelineDesc::default()
+                } else {
+                    RasterPipelineDesc::fullscreen()
+                },
+            )?
+        });
+    }
+
+    Ok(RasterChainPipeline {
+        render_pass,
+        stages,
+    })
+}
+
+// A raster pipeline writing `color_count` render targets, for use with `raster_tex_n`.
+// Pixel shaders declare them as `layout(location = N) out vec4`.
+#[snoozy]
+pub async fn make_raster_pipeline_n_snoozy(
+    mut ctx: Context,
+    shaders_in: &Vec<SnoozyRef<RasterSubShader>>,
+    color_count: &u32,
+    desc: &RasterPipelineDesc,
+) -> Result<RasterPipeline> {
+    if *color_count == 0 {
+        bail!("A raster pipeline needs at least one render target");
+    }
+
+    let mut shaders = Vec::with_capacity(shaders_in.len());
+    for a in shaders_in.iter() {
+        shaders.push(ctx.get(&*a).await?);
+    }
+
+    let render_pass = create_raster_mrt_render_pass(*color_count as usize)?;
+    unsafe { create_raster_pipeline(&shaders, render_pass, 0, *color_count as usize, desc) }
+}
+
+// A raster pipeline without color targets, for use with `raster_depth_tex`. The pixel
+// shader can be left out, unless it needs to discard pixels, e.g. for alpha testing.
+#[snoozy]
+pub async fn make_raster_depth_pipeline_snoozy(
+    mut ctx: Context,
+    shaders_in: &Vec<SnoozyRef<RasterSubShader>>,
+    desc: &DepthRasterDesc,
+) -> Result<RasterPipeline> {
+    let format = vk::Format::from_raw(desc.format);
+    if !crate::backend::texture::is_depth_format(format) {
+        bail!("{:?} is not a depth format", format);
+    }
+
+    let mut shaders = Vec::with_capacity(shaders_in.len());
+    for a in shaders_in.iter() {
+        shaders.push(ctx.get(&*a).await?);
+    }
+
+    // Must match the depth textures, which may use a format fallback
+    let render_pass =
+        create_raster_depth_render_pass(crate::backend::texture::find_supported_format(format))?;
+    let mut pipeline = unsafe {
+        create_raster_pipeline(
+            &shaders,
+            render_pass,
+            0,
+            0,
+            &RasterPipelineDesc::default().with_depth(true, true, desc.compare),
+        )?
+    };
+    pipeline.depth_only = Some(*desc);
+
+    Ok(pipeline)
+}
+
+pub enum FlattenedUniformEvent {
+    SetUniform {
+        name: String,
+        payload: ResolvedShaderUniformPayload,
+    },
+    EnterScope,
+    LeaveScope,
+}
+
+// Removes the tags passed among `uniforms`, including those in bundles.
+fn take_op_tags(uniforms: &mut Vec<ResolvedShaderUniformHolder>) -> OpTags {
+    let mut tags = OpTags::default();
+    uniforms.retain(|u| match &u.payload.value {
+        ResolvedShaderUniformValue::Tags(t) => {
+            tags.extend(t.clone());
+            false
+        }
+        _ => true,
+    });
+
+    // Bundles are a nested scope; their groups go inside ours
+    let mut bundle_tags = OpTags::default();
+    for uniform in uniforms.iter_mut() {
+        if let ResolvedShaderUniformValue::Bundle(ref mut bundle) = uniform.payload.value {
+            bundle_tags.extend(take_op_tags(bundle));
+        }
+    }
+
+    tags.extend_nested(bundle_tags);
+    tags
+}
+
+// Removes the output load op passed among `uniforms`. Only the outermost scope is checked,
+// as bundles shouldn't decide what happens to the output of the passes using them.
+fn take_output_load_op(uniforms: &mut Vec<ResolvedShaderUniformHolder>) -> OutputLoadOp {
+    let mut load_op = OutputLoadOp::default();
+    uniforms.retain(|u| match u.payload.value {
+        ResolvedShaderUniformValue::OutputLoadOp(op) => {
+            load_op = op;
+            false
+        }
+        _ => true,
+    });
+    load_op
+}
+
+// Uniforms in named bundles get the bundle name as a prefix, so that several instances
+// of a bundle can be passed to one shader. Values get a dotted name, e.g. `light.intensity`,
+// matching a `light` struct in a uniform block. Textures and buffers can't be placed in
+// structs in GLSL, so they're joined with an underscore instead, e.g. `light_shadowTex`.
+fn flatten_uniforms(
+    mut uniforms: Vec<ResolvedShaderUniformHolder>,
+    sink: &mut dyn FnMut(FlattenedUniformEvent),
+) {
+    let explicit_names: HashSet<String> = uniforms.iter().map(|u| u.name.clone()).collect();
+
+    // Do non-bundle values first so that they become visible to bundle handlers
+    for uniform in uniforms.iter_mut() {
+        let warn_if_unreferenced = uniform.payload.warn_if_unreferenced;
+
+        match uniform.payload.value {
+            ResolvedShaderUniformValue::Bundle(_)
+            | ResolvedShaderUniformValue::Tags(_)
+            | ResolvedShaderUniformValue::OutputLoadOp(_) => {}
+            ResolvedShaderUniformValue::Texture(ref value)
+            | ResolvedShaderUniformValue::RwTexture(ref value) => {
+                let name = std::mem::replace(&mut uniform.name, String::new());
+
+                let tex_size_uniform = (
+                    value.key.width as f32,
+                    value.key.height as f32,
+                    1f32 / value.key.width as f32,
+                    1f32 / value.key.height as f32,
+                );
+
+                sink(FlattenedUniformEvent::SetUniform {
+                    name: name.clone() + "_size",
+                    payload: ResolvedShaderUniformPayload {
+                        value: ResolvedShaderUniformValue::Vec4(tex_size_uniform),
+                        warn_if_unreferenced: false,
+                    },
+                });
+
+                // Textures don't have mip chains yet
+                sink(FlattenedUniformEvent::SetUniform {
+                    name: name.clone() + "_mip_count",
+                    payload: ResolvedShaderUniformPayload {
+                        value: ResolvedShaderUniformValue::Uint32(1),
+                        warn_if_unreferenced: false,
+                    },
+                });
+
+                if !explicit_names.contains(&(name.clone() + "_lod")) {
+                    let TextureLod {
+                        bias,
+                        min_lod,
+                        max_lod,
+                    } = TextureLod::default();
+
+                    sink(FlattenedUniformEvent::SetUniform {
+                        name: name.clone() + "_lod",
+                        payload: ResolvedShaderUniformPayload {
+                            value: ResolvedShaderUniformValue::Vec4((bias, min_lod, max_lod, 0.0)),
+                            warn_if_unreferenced: false,
+                        },
+                    });
+                }
+
+                if !explicit_names.contains(&(name.clone() + "_wrap")) {
+                    let wrap = TextureWrap::default().shader_value();
+
+                    sink(FlattenedUniformEvent::SetUniform {
+                        name: name.clone() + "_wrap",
+                        payload: ResolvedShaderUniformPayload {
+                            value: ResolvedShaderUniformValue::Ivec2((wrap, wrap)),
+                            warn_if_unreferenced: false,
+                        },
+                    });
+                }
+
+                sink(FlattenedUniformEvent::SetUniform {
+                    name,
+                    payload: match &uniform.payload.value {
+                        ResolvedShaderUniformValue::Texture(ref value) => {
+                            ResolvedShaderUniformPayload {
+                                value: ResolvedShaderUniformValue::Texture(value.clone()),
+                                warn_if_unreferenced,
                             }
                         }
-                        ReflectDescriptorType::StorageImage => {
-                            if let Some(ResolvedShaderUniformValue::RwTexture(value)) =
-                                uniforms.get(&binding.name)
-                            {
+                        ResolvedShaderUniformValue::RwTexture(ref value) => {
+                            ResolvedShaderUniformPayload {
+                                value: ResolvedShaderUniformValue::RwTexture(value.clone()),
+                                warn_if_unreferenced,
+                            }
+                        }
+                        _ => panic!(),
+                    },
+                });
+            }
+            _ => {
+                let name = std::mem::replace(&mut uniform.name, String::new());
+                let value = std::mem::replace(
+                    &mut uniform.payload.value,
+                    ResolvedShaderUniformValue::Int32(0),
+                );
+
+                sink(FlattenedUniformEvent::SetUniform {
+                    name,
+                    payload: ResolvedShaderUniformPayload {
+                        value,
+                        warn_if_unreferenced,
+                    },
+                });
+            }
+        }
+    }
+
+    // Now process bundles
+    for uniform in uniforms.into_iter() {
+        match uniform.payload.value {
+            ResolvedShaderUniformValue::Bundle(bundle) => {
+                sink(FlattenedUniformEvent::EnterScope);
+                if uniform.name.is_empty() {
+                    flatten_uniforms(bundle, sink);
+                } else {
+                    let scope = uniform.name;
+                    flatten_uniforms(bundle, &mut |e| match e {
+                        FlattenedUniformEvent::SetUniform { name, payload } => {
+                            let separator = match payload.value {
+                                ResolvedShaderUniformValue::Texture(_)
+                                | ResolvedShaderUniformValue::RwTexture(_)
+                                | ResolvedShaderUniformValue::Buffer(_)
+                                | ResolvedShaderUniformValue::RwBuffer(_) => '_',
+                                _ => '.',
+                            };
+                            sink(FlattenedUniformEvent::SetUniform {
+                                name: format!("{}{}{}", scope, separator, name),
+                                payload,
+                            })
+                        }
+                        e => sink(e),
+                    });
+                }
+                sink(FlattenedUniformEvent::LeaveScope);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct DescritorSetUpdateResult {
+    dynamic_offsets: Vec<u32>,
+    // Contents of push constant blocks, starting at offset zero
+    push_constants: Vec<u8>,
+    all_buffers_descriptor_set_idx: Vec<usize>,
+    all_textures_descriptor_set_idx: Vec<usize>,
+}
+
+trait DescriptorSetSlice {
+    fn get_at_idx(&self, idx: usize) -> std::result::Result<vk::DescriptorSet, &'static str>;
+}
+
+impl DescriptorSetSlice for Vec<Option<vk::DescriptorSet>> {
+    fn get_at_idx(&self, idx: usize) -> std::result::Result<vk::DescriptorSet, &'static str> {
+        match self[idx] {
+            Some(d) => Ok(d),
+            None => Err("Not a dynamic descriptor set"),
+        }
+    }
+}
+
+// Leaf members of a uniform block. Members of structs are named by their path,
+// e.g. `light.intensity`, as set by named bundles.
+fn uniform_block_leaves<'a>(
+    members: &'a [ReflectBlockVariable],
+    prefix: &str,
+    leaves: &mut Vec<(String, &'a ReflectBlockVariable)>,
+) {
+    for member in members {
+        let name = format!("{}{}", prefix, member.name);
+        if member.members.is_empty() {
+            leaves.push((name, member));
+        } else {
+            uniform_block_leaves(&member.members, &(name + "."), leaves);
+        }
+    }
+}
+
+// Elements go `stride` bytes apart, which is 16 in std140 even for scalars.
+fn write_uniform_array(
+    dst_mem: &mut [u8],
+    member: &ReflectBlockVariable,
+    elements: impl Iterator<Item = Vec<u8>>,
+) -> std::result::Result<(), String> {
+    let stride = member.array.stride as usize;
+    if stride == 0 {
+        return Err("is not an array".to_owned());
+    }
+
+    for b in dst_mem.iter_mut() {
+        *b = 0;
+    }
+
+    for (i, element) in elements.take(dst_mem.len() / stride).enumerate() {
+        if element.len() > stride {
+            return Err(format!(
+                "has elements of {} bytes, but {} bytes were provided",
+                stride,
+                element.len()
+            ));
+        }
+        dst_mem[i * stride..i * stride + element.len()].copy_from_slice(&element);
+    }
+
+    Ok(())
+}
+
+fn write_uniform_bytes(dst_mem: &mut [u8], bytes: &[u8]) -> std::result::Result<(), String> {
+    if bytes.len() != dst_mem.len() {
+        return Err(format!(
+            "is {} bytes in the shader, but {} bytes were provided",
+            dst_mem.len(),
+            bytes.len()
+        ));
+    }
+
+    dst_mem.copy_from_slice(bytes);
+    Ok(())
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|c| c.to_ne_bytes().to_vec())
+        .collect()
+}
+
+// Bytes of a matrix with `columns` of `rows` floats each, laid out for `member`. Columns
+// go `matrix.stride` bytes apart, so those of a `mat3` are padded to 16 bytes. Matrices
+// declared `row_major` get transposed.
+fn matrix_bytes(member: &ReflectBlockVariable, cols: &[f32], rows: usize) -> Vec<u8> {
+    use spirv_reflect::types::variable::ReflectDecorationFlags;
+
+    let columns = cols.len() / rows;
+    let row_major = member
+        .decoration_flags
+        .contains(ReflectDecorationFlags::ROW_MAJOR);
+    let (vectors, vector_len) = if row_major {
+        (rows, columns)
+    } else {
+        (columns, rows)
+    };
+
+    let stride = (member.numeric.matrix.stride as usize).max(vector_len * 4);
+    let mut bytes = vec![0u8; vectors * stride];
+    for v in 0..vectors {
+        for i in 0..vector_len {
+            let value = if row_major {
+                cols[i * rows + v]
+            } else {
+                cols[v * rows + i]
+            };
+            let offset = v * stride + i * 4;
+            bytes[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+    bytes
+}
+
+// Writes a scalar, vector, matrix or array member of a uniform block or push constant
+// block. Values which don't match the reflected layout of the member are rejected.
+fn write_uniform_value(
+    dst_mem: &mut [u8],
+    member: &ReflectBlockVariable,
+    value: &ResolvedShaderUniformValue,
+) -> std::result::Result<(), String> {
+    match value {
+        ResolvedShaderUniformValue::Float32(value) => {
+            write_uniform_bytes(dst_mem, &value.to_ne_bytes())
+        }
+        ResolvedShaderUniformValue::Uint32(value) => {
+            write_uniform_bytes(dst_mem, &value.to_ne_bytes())
+        }
+        ResolvedShaderUniformValue::Int32(value) => {
+            write_uniform_bytes(dst_mem, &value.to_ne_bytes())
+        }
+        // GLSL has no 64-bit integers without extensions
+        ResolvedShaderUniformValue::Usize(value) => {
+            if *value as u32 as usize != *value {
+                return Err(format!(
+                    "can't hold {}, which doesn't fit in 32 bits",
+                    value
+                ));
+            }
+            write_uniform_bytes(dst_mem, &(*value as u32).to_ne_bytes())
+        }
+        ResolvedShaderUniformValue::Ivec2(value) => {
+            let bytes: Vec<u8> = [value.0, value.1]
+                .iter()
+                .flat_map(|c| c.to_ne_bytes().to_vec())
+                .collect();
+            write_uniform_bytes(dst_mem, &bytes)
+        }
+        ResolvedShaderUniformValue::Vec2(value) => {
+            write_uniform_bytes(dst_mem, &f32_bytes(&[value.0, value.1]))
+        }
+        ResolvedShaderUniformValue::Vec3(value) => {
+            write_uniform_bytes(dst_mem, &f32_bytes(&[value.0, value.1, value.2]))
+        }
+        ResolvedShaderUniformValue::Vec4(value) => {
+            write_uniform_bytes(dst_mem, &f32_bytes(&[value.0, value.1, value.2, value.3]))
+        }
+        ResolvedShaderUniformValue::Mat3(value) => {
+            write_uniform_bytes(dst_mem, &matrix_bytes(member, &value.to_cols_array(), 3))
+        }
+        ResolvedShaderUniformValue::Mat4(value) => {
+            write_uniform_bytes(dst_mem, &matrix_bytes(member, &value.to_cols_array(), 4))
+        }
+        ResolvedShaderUniformValue::Float32Array(values) => write_uniform_array(
+            dst_mem,
+            member,
+            values.iter().map(|v| v.to_ne_bytes().to_vec()),
+        ),
+        ResolvedShaderUniformValue::Int32Array(values) => write_uniform_array(
+            dst_mem,
+            member,
+            values.iter().map(|v| v.to_ne_bytes().to_vec()),
+        ),
+        ResolvedShaderUniformValue::Vec4Array(values) => {
+            write_uniform_array(dst_mem, member, values.iter().map(|v| f32_bytes(v)))
+        }
+        _ => Err("has a type which can't be placed in a uniform buffer".to_owned()),
+    }
+}
+
+trait UniformParamSource {
+    fn len(&self) -> usize;
+    fn get(&mut self, name: &str) -> Option<&ResolvedShaderUniformValue>;
+}
+
+// Binding e.g. a 2D array texture to an `image2D` is invalid usage, and tends to silently
+// read garbage rather than fail; point it out instead. Cube maps are sampled as cubes, but
+// written as 2D arrays.
+fn warn_on_image_dim_mismatch(
+    binding: &spirv_reflect::types::descriptor::ReflectDescriptorBinding,
+    key: &TextureKey,
+    storage: bool,
+) {
+    use crate::backend::texture::TextureType;
+    use spirv_reflect::types::image::ReflectDimension;
+
+    let arrayed = binding.image.arrayed != 0;
+    let matches = match binding.image.dim {
+        ReflectDimension::Type2d => match key.tex_type {
+            TextureType::Type2D => !arrayed,
+            TextureType::Type2DArray => arrayed,
+            TextureType::Cube => storage && arrayed,
+            TextureType::Type3D => false,
+        },
+        ReflectDimension::Type3d => key.tex_type == TextureType::Type3D,
+        ReflectDimension::Cube => key.tex_type == TextureType::Cube && !storage && !arrayed,
+        // Not something textures can be created as, so nothing to check against
+        _ => true,
+    };
+
+    if !matches {
+        crate::rtoy_show_warning(format!(
+            "{} is declared as {:?}{}, but the bound texture is {:?}",
+            binding.name,
+            binding.image.dim,
+            if arrayed { " array" } else { "" },
+            key.tex_type
+        ));
+    }
+}
+
+fn update_descriptor_sets<'a>(
+    device: &Device,
+    refl: impl Iterator<Item = &'a spirv_reflect::ShaderModule>,
+    descriptor_sets: &impl DescriptorSetSlice,
+    uniforms: &mut impl UniformParamSource,
+) -> std::result::Result<DescritorSetUpdateResult, &'static str> {
+    use std::cell::RefCell;
+
+    let mut ds_offsets = Vec::new();
+    let mut push_constants = Vec::new();
+
+    #[derive(Default)]
+    pub struct Cache {
+        ds_image_info: RefCell<Vec<[vk::DescriptorImageInfo; 1]>>,
+        ds_buffer_info: RefCell<Vec<[vk::DescriptorBufferInfo; 1]>>,
+        ds_buffer_views: RefCell<Vec<[vk::BufferView; 1]>>,
+        ds_writes: RefCell<Vec<vk::WriteDescriptorSet>>,
+    }
+
+    // TODO: cache those in thread-locals
+    thread_local! {
+        pub static CACHE: Cache = Default::default();
+    }
+
+    let mut all_buffers_descriptor_set_idx = Vec::new();
+    let mut all_textures_descriptor_set_idx = Vec::new();
+
+    CACHE.with(|cache| -> std::result::Result<(), &'static str> {
+        let mut ds_image_info = cache.ds_image_info.borrow_mut();
+        let mut ds_buffer_info = cache.ds_buffer_info.borrow_mut();
+        let mut ds_buffer_views = cache.ds_buffer_views.borrow_mut();
+        let mut ds_writes = cache.ds_writes.borrow_mut();
+
+        ds_image_info.clear();
+        ds_image_info.reserve(uniforms.len());
+
+        ds_buffer_info.clear();
+        ds_buffer_info.reserve(uniforms.len());
+
+        ds_buffer_views.clear();
+        ds_buffer_views.reserve(uniforms.len());
+
+        ds_writes.clear();
+        ds_writes.reserve(uniforms.len());
+
+        let vk_state = vk_state();
+        let vk_frame = vk_state.current_frame();
+
+        for refl in refl {
+            let entry = Some("main");
+
+            for block in refl.enumerate_push_constant_blocks(entry)?.iter() {
+                let mut members = Vec::new();
+                uniform_block_leaves(&block.members, "", &mut members);
+
+                for (member_name, member) in members {
+                    let start = member.absolute_offset as usize;
+                    let end = (member.absolute_offset + member.size) as usize;
+                    if push_constants.len() < end {
+                        push_constants.resize(end, 0u8);
+                    }
+
+                    if let Some(value) = uniforms.get(&member_name) {
+                        if let Err(err) =
+                            write_uniform_value(&mut push_constants[start..end], member, value)
+                        {
+                            crate::rtoy_show_warning(format!("Uniform {} {}", member_name, err));
+                        }
+                    }
+                }
+            }
+
+            for descriptor_set in refl.enumerate_descriptor_sets(entry)?.iter() {
+                for binding in descriptor_set.bindings.iter() {
+                    use spirv_reflect::types::descriptor::ReflectDescriptorType;
+
+                    match binding.descriptor_type {
+                        ReflectDescriptorType::UniformBuffer => {
+                            let buffer_bytes = binding.block.size as usize;
+                            let (buffer_handle, buffer_offset, buffer_contents) = vk_frame
+                                .uniforms
+                                .allocate(buffer_bytes)
+                                .expect("failed to allocate uniform buffer");
+
+                            let mut members = Vec::new();
+                            uniform_block_leaves(&binding.block.members, "", &mut members);
+
+                            for (member_name, member) in members {
+                                if let Some(value) = uniforms.get(&member_name) {
+                                    let dst_mem = &mut buffer_contents[member.absolute_offset
+                                        as usize
+                                        ..(member.absolute_offset + member.size) as usize];
+
+                                    if let Err(err) = write_uniform_value(dst_mem, member, value) {
+                                        crate::rtoy_show_warning(format!(
+                                            "Uniform {} {}",
+                                            member_name, err
+                                        ));
+                                    }
+                                }
+                            }
+
+                            let buffer_info = [vk::DescriptorBufferInfo::builder()
+                                .buffer(buffer_handle)
+                                .range(buffer_bytes as u64)
+                                .build()];
+                            ds_buffer_info.push(buffer_info);
+                            let buffer_info = ds_buffer_info.last().unwrap();
+
+                            ds_offsets.push(buffer_offset as u32);
+                            ds_writes.push(
+                                vk::WriteDescriptorSet::builder()
+                                    .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
+                                    .dst_binding(binding.binding)
+                                    .dst_array_element(0)
+                                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                                    .buffer_info(buffer_info)
+                                    .build(),
+                            );
+                        }
+                        ReflectDescriptorType::SampledImage => {
+                            if let Some(ResolvedShaderUniformValue::Texture(value)) =
+                                uniforms.get(&binding.name)
+                            {
+                                warn_on_image_dim_mismatch(binding, &value.key, false);
+                                let image_info = [vk::DescriptorImageInfo::builder()
+                                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                                    .image_view(value.view)
+                                    .build()];
+                                ds_image_info.push(image_info);
+                                let image_info = ds_image_info.last().unwrap();
+
+                                ds_writes.push(
+                                    vk::WriteDescriptorSet::builder()
+                                        .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
+                                        .dst_binding(binding.binding)
+                                        .dst_array_element(0)
+                                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                                        .image_info(image_info)
+                                        .build(),
+                                )
+                            } else {
+                                if "all_textures" == binding.name {
+                                    // Updated elsewhere. Do note the set idx so we can assign the descriptor set afterwards.
+                                    all_textures_descriptor_set_idx.push(binding.set as usize);
+                                } else {
+                                    // TODO
+                                    panic!("Could not find resource to bind {}", binding.name);
+                                }
+                            }
+                        }
+                        ReflectDescriptorType::StorageImage => {
+                            if let Some(ResolvedShaderUniformValue::RwTexture(value)) =
+                                uniforms.get(&binding.name)
+                            {
+                                warn_on_image_dim_mismatch(binding, &value.key, true);
+                                if value.storage_view == vk::ImageView::null() {
+                                    return Err("Texture view format can't be used for storage");
+                                }
                                 let image_info = [vk::DescriptorImageInfo::builder()
                                     .image_layout(vk::ImageLayout::GENERAL)
                                     .image_view(value.storage_view)
@@ -1294,537 +3386,2348 @@ fn update_descriptor_sets<'a>(
                                 ds_image_info.push(image_info);
                                 let image_info = ds_image_info.last().unwrap();
 
-                                ds_writes.push(
-                                    vk::WriteDescriptorSet::builder()
-                                        .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
-                                        .dst_binding(binding.binding)
-                                        .dst_array_element(0)
-                                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                                        .image_info(image_info)
-                                        .build(),
-                                )
-                            } else {
-                                // TODO
-                                panic!("Could not find resource to bind {}", binding.name);
-                            }
-                        }
-                        ReflectDescriptorType::StorageBuffer => {
-                            match uniforms
-                                .get(&binding.type_description.as_ref().unwrap().type_name)
-                            {
-                                Some(ResolvedShaderUniformValue::Buffer(value))
-                                | Some(ResolvedShaderUniformValue::RwBuffer(value)) => {
-                                    let buffer_info = [vk::DescriptorBufferInfo::builder()
-                                        .buffer(value.buffer)
-                                        .range(vk::WHOLE_SIZE)
-                                        .build()];
-                                    ds_buffer_info.push(buffer_info);
-                                    let buffer_info = ds_buffer_info.last().unwrap();
+                                ds_writes.push(
+                                    vk::WriteDescriptorSet::builder()
+                                        .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
+                                        .dst_binding(binding.binding)
+                                        .dst_array_element(0)
+                                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                                        .image_info(image_info)
+                                        .build(),
+                                )
+                            } else {
+                                // TODO
+                                panic!("Could not find resource to bind {}", binding.name);
+                            }
+                        }
+                        ReflectDescriptorType::InputAttachment => {
+                            if let Some(ResolvedShaderUniformValue::Texture(value)) =
+                                uniforms.get(&binding.name)
+                            {
+                                let image_info = [vk::DescriptorImageInfo::builder()
+                                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                                    .image_view(value.rt_view)
+                                    .build()];
+                                ds_image_info.push(image_info);
+                                let image_info = ds_image_info.last().unwrap();
+
+                                ds_writes.push(
+                                    vk::WriteDescriptorSet::builder()
+                                        .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
+                                        .dst_binding(binding.binding)
+                                        .dst_array_element(0)
+                                        .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                                        .image_info(image_info)
+                                        .build(),
+                                )
+                            } else {
+                                // TODO
+                                panic!("Could not find resource to bind {}", binding.name);
+                            }
+                        }
+                        ReflectDescriptorType::StorageBuffer
+                            if binding.type_description.as_ref().unwrap().type_name
+                                == shader_instrumentation::RECORD_BUFFER_BLOCK_NAME =>
+                        {
+                            let buffer_info = [vk::DescriptorBufferInfo::builder()
+                                .buffer(vk_frame.shader_asan.buffer)
+                                .range(vk::WHOLE_SIZE)
+                                .build()];
+                            ds_buffer_info.push(buffer_info);
+                            let buffer_info = ds_buffer_info.last().unwrap();
+
+                            ds_writes.push(
+                                vk::WriteDescriptorSet::builder()
+                                    .dst_set(descriptor_sets.get_at_idx(binding.set as usize)?)
+                                    .dst_binding(binding.binding)
+                                    .dst_array_element(0)
+                                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                                    .buffer_info(buffer_info)
+                                    .build(),
+                            );
+                        }
+                        ReflectDescriptorType::StorageBuffer => {
+                            match uniforms
+                                .get(&binding.type_description.as_ref().unwrap().type_name)
+                            {
+                                Some(ResolvedShaderUniformValue::Buffer(value))
+                                | Some(ResolvedShaderUniformValue::RwBuffer(value)) => {
+                                    let buffer_info = [vk::DescriptorBufferInfo::builder()
+                                        .buffer(value.buffer)
+                                        .range(vk::WHOLE_SIZE)
+                                        .build()];
+                                    ds_buffer_info.push(buffer_info);
+                                    let buffer_info = ds_buffer_info.last().unwrap();
+
+                                    /*println!(
+                                        "Updating storage buffer at {}:{}",
+                                        binding.set, binding.binding
+                                    );*/
+
+                                    ds_writes.push(
+                                        vk::WriteDescriptorSet::builder()
+                                            .dst_set(
+                                                descriptor_sets.get_at_idx(binding.set as usize)?,
+                                            )
+                                            .dst_binding(binding.binding)
+                                            .dst_array_element(0)
+                                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                                            .buffer_info(buffer_info)
+                                            .build(),
+                                    );
+                                }
+                                _ => {
+                                    panic!(binding
+                                        .type_description
+                                        .as_ref()
+                                        .unwrap()
+                                        .type_name
+                                        .clone());
+                                }
+                            }
+                        }
+                        ReflectDescriptorType::Sampler => {
+                            // Immutable. Nothing to do.
+                            /*println!(
+                                "Skipping sampler update at {}:{}",
+                                binding.set, binding.binding
+                            );*/
+                        }
+                        ReflectDescriptorType::UniformTexelBuffer => {
+                            match uniforms.get(&binding.name) {
+                                Some(ResolvedShaderUniformValue::Buffer(value))
+                                | Some(ResolvedShaderUniformValue::RwBuffer(value)) => {
+                                    ds_buffer_views.push([value.view]);
+                                    let buffer_view = ds_buffer_views.last().unwrap();
+
+                                    ds_writes.push(
+                                        vk::WriteDescriptorSet::builder()
+                                            .dst_set(
+                                                descriptor_sets.get_at_idx(binding.set as usize)?,
+                                            )
+                                            .dst_binding(binding.binding)
+                                            .dst_array_element(0)
+                                            .descriptor_type(
+                                                vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                                            )
+                                            .texel_buffer_view(buffer_view)
+                                            .build(),
+                                    );
+                                }
+                                _ => {
+                                    if "all_buffers" == binding.name {
+                                        // Updated elsewhere. Do note the set idx so we can assign the descriptor set afterwards.
+                                        all_buffers_descriptor_set_idx.push(binding.set as usize);
+                                    } else {
+                                        // TODO
+                                        panic!("Could not find resource to bind {}", binding.name);
+                                    }
+                                }
+                            }
+                        }
+                        _ => {
+                            dbg!(&binding);
+                        }
+                    }
+                }
+            }
+        }
+
+        //dbg!(&ds_writes);
+        //dbg!(&descriptor_sets);
+        if !ds_writes.is_empty() {
+            unsafe { device.update_descriptor_sets(&ds_writes, &[]) };
+        }
+
+        Ok(())
+    })?;
+
+    Ok(DescritorSetUpdateResult {
+        dynamic_offsets: ds_offsets,
+        push_constants,
+        all_buffers_descriptor_set_idx,
+        all_textures_descriptor_set_idx,
+    })
+}
+
+fn format_supports(format: vk::Format, features: vk::FormatFeatureFlags) -> bool {
+    let vk = vk();
+    unsafe {
+        vk.instance
+            .get_physical_device_format_properties(vk.pdevice, format)
+            .optimal_tiling_features
+            .contains(features)
+    }
+}
+
+// Checks that the uniforms cover the members of a uniform or push constant block.
+fn validate_uniform_block(
+    block: &ReflectBlockVariable,
+    uniforms: &mut impl UniformParamSource,
+    issues: &mut Vec<String>,
+) {
+    let mut members = Vec::new();
+    uniform_block_leaves(&block.members, "", &mut members);
+
+    for (member_name, member) in members {
+        let array_len = match uniforms.get(&member_name) {
+            Some(ResolvedShaderUniformValue::Float32Array(values)) => Some(values.len()),
+            Some(ResolvedShaderUniformValue::Int32Array(values)) => Some(values.len()),
+            Some(ResolvedShaderUniformValue::Vec4Array(values)) => Some(values.len()),
+            _ => None,
+        };
+
+        if let Some(array_len) = array_len {
+            let stride = member.array.stride as usize;
+            if stride == 0 {
+                issues.push(format!("{} is not an array", member_name));
+            } else if array_len * stride > member.size as usize {
+                issues.push(format!(
+                    "{} has {} elements, but the shader's array only fits {}",
+                    member_name,
+                    array_len,
+                    member.size as usize / stride
+                ));
+            }
+            continue;
+        }
+
+        let size = match uniforms.get(&member_name) {
+            Some(ResolvedShaderUniformValue::Float32(_))
+            | Some(ResolvedShaderUniformValue::Uint32(_))
+            | Some(ResolvedShaderUniformValue::Int32(_))
+            | Some(ResolvedShaderUniformValue::Usize(_)) => 4,
+            Some(ResolvedShaderUniformValue::Ivec2(_))
+            | Some(ResolvedShaderUniformValue::Vec2(_)) => 8,
+            Some(ResolvedShaderUniformValue::Vec3(_)) => 12,
+            Some(ResolvedShaderUniformValue::Vec4(_)) => 16,
+            Some(ResolvedShaderUniformValue::Mat3(v)) => {
+                matrix_bytes(member, &v.to_cols_array(), 3).len() as u32
+            }
+            Some(ResolvedShaderUniformValue::Mat4(v)) => {
+                matrix_bytes(member, &v.to_cols_array(), 4).len() as u32
+            }
+            Some(_) => {
+                issues.push(format!(
+                    "{} has a type which can't be placed in a uniform buffer",
+                    member_name
+                ));
+                continue;
+            }
+            None => {
+                issues.push(format!("{} is not provided", member_name));
+                continue;
+            }
+        };
+
+        if size != member.size {
+            issues.push(format!(
+                "{} is {} bytes in the shader, but {} bytes were provided",
+                member_name, member.size, size
+            ));
+        }
+    }
+}
+
+// Dry-run counterpart of `update_descriptor_sets`: reports bindings which would
+// fail to resolve, instead of writing any descriptors.
+fn validate_descriptor_bindings<'a>(
+    refl: impl Iterator<Item = &'a spirv_reflect::ShaderModule>,
+    uniforms: &mut impl UniformParamSource,
+) -> std::result::Result<Vec<String>, &'static str> {
+    use spirv_reflect::types::descriptor::ReflectDescriptorType;
+
+    let mut issues = Vec::new();
+
+    for refl in refl {
+        for block in refl.enumerate_push_constant_blocks(Some("main"))?.iter() {
+            validate_uniform_block(block, uniforms, &mut issues);
+        }
+
+        for descriptor_set in refl.enumerate_descriptor_sets(Some("main"))?.iter() {
+            for binding in descriptor_set.bindings.iter() {
+                match binding.descriptor_type {
+                    ReflectDescriptorType::UniformBuffer => {
+                        validate_uniform_block(&binding.block, uniforms, &mut issues);
+                    }
+                    ReflectDescriptorType::SampledImage
+                    | ReflectDescriptorType::InputAttachment => match uniforms.get(&binding.name) {
+                        Some(ResolvedShaderUniformValue::Texture(tex)) => {
+                            let format = vk::Format::from_raw(tex.key.format);
+                            if !format_supports(format, vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+                                issues.push(format!(
+                                    "{} uses {:?}, which can't be sampled",
+                                    binding.name, format
+                                ));
+                            }
+                        }
+                        Some(_) => issues.push(format!("{} must be a texture", binding.name)),
+                        None if binding.name == "all_textures" => {}
+                        None => issues.push(format!("{} is not provided", binding.name)),
+                    },
+                    ReflectDescriptorType::StorageImage => match uniforms.get(&binding.name) {
+                        Some(ResolvedShaderUniformValue::RwTexture(tex)) => {
+                            let format = crate::backend::texture::get_storage_compatible_format(
+                                vk::Format::from_raw(tex.key.format),
+                            );
+                            if !format_supports(format, vk::FormatFeatureFlags::STORAGE_IMAGE) {
+                                issues.push(format!(
+                                    "{} uses {:?}, which can't be used as a storage image",
+                                    binding.name, format
+                                ));
+                            }
+                        }
+                        Some(_) => {
+                            issues.push(format!("{} must be a writable texture", binding.name))
+                        }
+                        None => issues.push(format!("{} is not provided", binding.name)),
+                    },
+                    ReflectDescriptorType::StorageBuffer => {
+                        let type_name = &binding.type_description.as_ref().unwrap().type_name;
+                        if type_name == shader_instrumentation::RECORD_BUFFER_BLOCK_NAME {
+                            continue;
+                        }
+
+                        match uniforms.get(type_name) {
+                            Some(ResolvedShaderUniformValue::Buffer(_))
+                            | Some(ResolvedShaderUniformValue::RwBuffer(_)) => {}
+                            Some(_) => issues.push(format!("{} must be a buffer", type_name)),
+                            None => issues.push(format!("{} is not provided", type_name)),
+                        }
+                    }
+                    ReflectDescriptorType::UniformTexelBuffer => {
+                        match uniforms.get(&binding.name) {
+                            Some(ResolvedShaderUniformValue::Buffer(_))
+                            | Some(ResolvedShaderUniformValue::RwBuffer(_)) => {}
+                            Some(_) => issues.push(format!("{} must be a buffer", binding.name)),
+                            None if binding.name == "all_buffers" => {}
+                            None => issues.push(format!("{} is not provided", binding.name)),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+pub struct ResolvedShaderUniformPayload {
+    value: ResolvedShaderUniformValue,
+    warn_if_unreferenced: bool,
+}
+
+// Provide access to uniforms, but also record which ones are being requested,
+// so that we can issue warnings about any unreferenced ones.
+struct TrackedUniformParamSource {
+    uniforms: HashMap<String, ResolvedShaderUniformPayload>,
+    requested: HashSet<String>,
+}
+
+impl UniformParamSource for TrackedUniformParamSource {
+    fn len(&self) -> usize {
+        self.uniforms.len()
+    }
+
+    fn get(&mut self, name: &str) -> Option<&ResolvedShaderUniformValue> {
+        self.requested.insert(name.to_owned());
+        self.uniforms.get(name).map(|v| &v.value)
+    }
+}
+
+impl TrackedUniformParamSource {
+    // Only resources which the shader actually referenced count as used.
+    fn report_resource_uses(&self, pass: &str) {
+        for name in self.requested.iter() {
+            let allocation_id = match self.uniforms.get(name).map(|u| &u.value) {
+                Some(ResolvedShaderUniformValue::Texture(tex))
+                | Some(ResolvedShaderUniformValue::RwTexture(tex)) => tex.allocation_id(),
+                Some(ResolvedShaderUniformValue::Buffer(buf))
+                | Some(ResolvedShaderUniformValue::RwBuffer(buf)) => buf.allocation_id(),
+                _ => continue,
+            };
+            resource_lifetime::record_use(allocation_id, pass);
+        }
+    }
+
+    fn report_unreferenced_uniform_warnings(self, shader_name: &str) {
+        let requested = self.requested;
+        let unreferenced = self
+            .uniforms
+            .into_iter()
+            .filter_map(move |(name, payload)| {
+                if !payload.warn_if_unreferenced || requested.contains(&name) {
+                    None
+                } else {
+                    Some(name)
+                }
+            });
+
+        for name in unreferenced {
+            crate::rtoy_show_warning(format!("Unreferenced uniform {}::{}", shader_name, name));
+        }
+    }
+}
+
+enum ComputeOutputResource {
+    Texture(Texture),
+    Buffer(Buffer),
+}
+
+struct ComputeOutput {
+    resource: ComputeOutputResource,
+    load_op: OutputLoadOp,
+}
+
+impl ComputeOutput {
+    pub fn new_texture(tex: &Texture) -> Self {
+        Self {
+            resource: ComputeOutputResource::Texture(tex.clone()),
+            load_op: OutputLoadOp::Discard,
+        }
+    }
+
+    pub fn new_buffer(buf: &Buffer) -> Self {
+        Self {
+            resource: ComputeOutputResource::Buffer(buf.clone()),
+            load_op: OutputLoadOp::Discard,
+        }
+    }
+
+    pub fn mutate_texture(tex: &Texture) -> Self {
+        Self {
+            resource: ComputeOutputResource::Texture(tex.clone()),
+            load_op: OutputLoadOp::Load,
+        }
+    }
+
+    pub fn with_load_op(mut self, load_op: OutputLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+}
+
+async fn compute_common(
+    mut ctx: Context,
+    thread_count: [u32; 3],
+    cs: &SnoozyRef<ComputeShader>,
+    mut uniforms: Vec<ResolvedShaderUniformHolder>,
+    outputs: &[ComputeOutput],
+    indirect_args: Option<(Buffer, u64)>,
+) -> Result<()> {
+    let cs = ctx.get(cs).await?;
+    let tags = take_op_tags(&mut uniforms);
+    let pass_name = tags.tagged_name(&cs.name);
+    ctx.set_debug_name(&pass_name);
+    shader_compile_queue::mark_shader_used(&cs.name);
+
+    let (vk, vk_state) = vk_all();
+    let vk_frame = vk_state.current_frame();
+
+    let mut flattened_uniforms: HashMap<String, ResolvedShaderUniformPayload> = HashMap::new();
+    flatten_uniforms(uniforms, &mut |e| {
+        if let FlattenedUniformEvent::SetUniform { name, payload } = e {
+            flattened_uniforms.insert(name, payload);
+        }
+    });
+
+    flattened_uniforms.insert(
+        deterministic_math::RNG_SEED_UNIFORM_NAME.to_owned(),
+        ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::Uint32(deterministic_math::rng_seed()),
+            warn_if_unreferenced: false,
+        },
+    );
+
+    if shader_instrumentation::is_any_shader_instrumented() {
+        flattened_uniforms.insert(
+            shader_instrumentation::PASS_ID_UNIFORM_NAME.to_owned(),
+            ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::Uint32(
+                    vk_frame.shader_asan.register_pass(&cs.name),
+                ),
+                warn_if_unreferenced: false,
+            },
+        );
+    }
+
+    let mut uniform_source = TrackedUniformParamSource {
+        uniforms: flattened_uniforms,
+        requested: HashSet::new(),
+    };
+
+    if dry_run::is_dry_run_enabled(&ctx).await? {
+        let mut issues = validate_descriptor_bindings(
+            std::iter::once(&*cs.spirv_reflection),
+            &mut uniform_source,
+        )
+        .map_err(|err| format_err!("{}", err))?;
+
+        if indirect_args.is_none() {
+            let local_size = [cs.local_size.0, cs.local_size.1, cs.local_size.2];
+            let max_group_count = vk.device_properties.limits.max_compute_work_group_count;
+            for axis in 0..3 {
+                let group_count = (thread_count[axis] + local_size[axis] - 1) / local_size[axis];
+                if thread_count[axis] == 0 {
+                    issues.push(format!("Dispatch size is zero along axis {}", axis));
+                } else if group_count > max_group_count[axis] {
+                    issues.push(format!(
+                        "Dispatch of {} groups along axis {} exceeds the device limit of {}",
+                        group_count, axis, max_group_count[axis]
+                    ));
+                }
+            }
+        }
+
+        uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+        dry_run::report_dry_run_pass(&pass_name, issues);
+        return Ok(());
+    }
+
+    let (descriptor_sets, ds_update_result) = unsafe {
+        let descriptor_sets = {
+            let layout_info = &cs.descriptor_set_layout_info;
+            let descriptor_pool = vk_frame.descriptor_pool.lock().unwrap();
+            let dynamic_sets = vk.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(*descriptor_pool)
+                    .set_layouts(&layout_info.dynamic_layouts)
+                    .build(),
+            )?;
+            drop(descriptor_pool);
+
+            let mut sets = vec![None; layout_info.all_layouts.len()];
+            for (src, dst) in layout_info.dynamic_layout_indices.iter().enumerate() {
+                sets[*dst] = Some(dynamic_sets[src]);
+            }
+
+            sets
+        };
+
+        let ds_update_result = update_descriptor_sets(
+            &vk.device,
+            std::iter::once(&*cs.spirv_reflection),
+            &descriptor_sets,
+            &mut uniform_source,
+        )
+        .unwrap();
+
+        (descriptor_sets, ds_update_result)
+    };
+
+    let cb = vk_frame.command_buffer.lock().unwrap();
+    let cb: vk::CommandBuffer = cb.cb;
+
+    unsafe {
+        let output_images: HashSet<vk::Image> = outputs
+            .iter()
+            .filter_map(|output| match &output.resource {
+                ComputeOutputResource::Texture(texture) => Some(texture.image),
+                ComputeOutputResource::Buffer(_) => None,
+            })
+            .collect();
+
+        for payload in uniform_source.uniforms.values() {
+            match &payload.value {
+                ResolvedShaderUniformValue::Texture(texture) => record_image_transition(
+                    &vk.device,
+                    cb,
+                    texture.image,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                    false,
+                ),
+                // Storage images written in place; outputs are handled below
+                ResolvedShaderUniformValue::RwTexture(texture)
+                    if !output_images.contains(&texture.image) =>
+                {
+                    record_image_transition(
+                        &vk.device,
+                        cb,
+                        texture.image,
+                        vk_sync::AccessType::ComputeShaderWrite,
+                        false,
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        vk.begin_debug_label(cb, &pass_name);
+        for output in outputs {
+            match &output.resource {
+                ComputeOutputResource::Texture(texture) => {
+                    vk.set_debug_object_name(texture.image, &pass_name)
+                }
+                ComputeOutputResource::Buffer(buffer) => {
+                    vk.set_debug_object_name(buffer.buffer, &pass_name)
+                }
+            }
+        }
+
+        for output in outputs {
+            match &output.resource {
+                ComputeOutputResource::Texture(texture) => match output.load_op {
+                    OutputLoadOp::Discard => {
+                        record_image_transition(
+                            &vk.device,
+                            cb,
+                            texture.image,
+                            vk_sync::AccessType::ComputeShaderWrite,
+                            true,
+                        );
+                    }
+                    OutputLoadOp::Clear(color) => {
+                        record_image_transition(
+                            &vk.device,
+                            cb,
+                            texture.image,
+                            vk_sync::AccessType::TransferWrite,
+                            true,
+                        );
+
+                        vk.device.cmd_clear_color_image(
+                            cb,
+                            texture.image,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &output_load_op::clear_color_value(
+                                vk::Format::from_raw(texture.key.format),
+                                color,
+                            ),
+                            &[vk::ImageSubresourceRange {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                base_mip_level: 0,
+                                level_count: vk::REMAINING_MIP_LEVELS,
+                                base_array_layer: 0,
+                                layer_count: vk::REMAINING_ARRAY_LAYERS,
+                            }],
+                        );
+
+                        record_image_barrier(
+                            &vk.device,
+                            cb,
+                            ImageBarrier::new(
+                                texture.image,
+                                vk_sync::AccessType::TransferWrite,
+                                vk_sync::AccessType::ComputeShaderWrite,
+                            ),
+                        );
+                    }
+                    OutputLoadOp::Load => {
+                        record_image_transition(
+                            &vk.device,
+                            cb,
+                            texture.image,
+                            vk_sync::AccessType::ComputeShaderWrite,
+                            false,
+                        );
+                    }
+                },
+                ComputeOutputResource::Buffer(_) => {
+                    //todo!();
+                }
+            }
+        }
+
+        let mut descriptor_sets = descriptor_sets;
+
+        for idx in ds_update_result.all_buffers_descriptor_set_idx.iter() {
+            descriptor_sets[*idx] = Some(vk_state.bindless_buffers_descriptor_set);
+        }
+
+        for idx in ds_update_result.all_textures_descriptor_set_idx.iter() {
+            descriptor_sets[*idx] = Some(vk_state.bindless_images_descriptor_set);
+        }
+
+        let descriptor_sets: Vec<_> = descriptor_sets.into_iter().map(Option::unwrap).collect();
+
+        let folded_pipeline = if cs.workgroup_variants.is_empty() {
+            let name = cs.name.clone();
+            let layout_info = cs.descriptor_set_layout_info.clone();
+            uniform_folding::folded_pipeline(
+                &cs.name,
+                cs.pipeline.pipeline.as_raw(),
+                &pass_name,
+                || cs.spirv_reflection.get_code(),
+                |uniform_name| {
+                    uniform_source
+                        .uniforms
+                        .get(uniform_name)
+                        .and_then(|payload| uniform_value_words(&payload.value))
+                },
+                move |spirv| {
+                    create_compute_pipeline(&name, &vk().device, &layout_info, &spirv, None)
+                },
+            )
+        } else {
+            None
+        };
+
+        let (pipeline, local_size, profiler_scope) = if let Some(ref pipeline) = folded_pipeline {
+            (pipeline, cs.local_size, None)
+        } else if cs.workgroup_variants.is_empty() {
+            (&cs.pipeline, cs.local_size, None)
+        } else {
+            let sizes: Vec<[u32; 3]> = cs.workgroup_variants.iter().map(|v| v.0).collect();
+            let (idx, profiler_scope) =
+                workgroup_autotune::select_workgroup_variant(&cs.name, &sizes);
+            let (size, pipeline) = &cs.workgroup_variants[idx];
+            (pipeline, (size[0], size[1], size[2]), profiler_scope)
+        };
+
+        vk.device
+            .cmd_bind_pipeline(cb, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+        vk.device.cmd_bind_descriptor_sets(
+            cb,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.pipeline_layout,
+            0,
+            &descriptor_sets,
+            &ds_update_result.dynamic_offsets,
+        );
+        record_push_constants(
+            &vk.device,
+            cb,
+            pipeline.pipeline_layout,
+            &cs.descriptor_set_layout_info,
+            &ds_update_result.push_constants,
+        );
+
+        // Autotuning scopes keep their names, as they're looked up by those
+        let query_id = crate::gpu_profiler::create_tagged_gpu_query(
+            profiler_scope.as_deref().unwrap_or(&pass_name),
+            &tags,
+        );
+        let vk_query_idx = vk_frame.profiler_data.get_query_id(query_id);
+
+        vk.device.cmd_write_timestamp(
+            cb,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk_frame.profiler_data.query_pool,
+            vk_query_idx * 2 + 0,
+        );
+
+        if let Some((indirect_buf, indirect_off)) = indirect_args {
+            vk.device
+                .cmd_dispatch_indirect(cb, indirect_buf.buffer, indirect_off as u64);
+        } else {
+            let group_count = [
+                (thread_count[0] + local_size.0 - 1) / local_size.0,
+                (thread_count[1] + local_size.1 - 1) / local_size.1,
+                (thread_count[2] + local_size.2 - 1) / local_size.2,
+            ];
+
+            // The same shader can run in several places, e.g. on different mips
+            let output_keys: Vec<String> = outputs
+                .iter()
+                .map(|output| match output.resource {
+                    ComputeOutputResource::Texture(ref tex) => format!("{:?}", tex.key),
+                    ComputeOutputResource::Buffer(ref buf) => format!("{:?}", buf.key),
+                })
+                .collect();
+            gpu_workload::report_pass_workload(
+                &format!("{} -> {}", pass_name, output_keys.join(", ")),
+                &pass_name,
+                group_count[0] as u64
+                    * local_size.0 as u64
+                    * group_count[1] as u64
+                    * local_size.1 as u64
+                    * group_count[2] as u64
+                    * local_size.2 as u64,
+            );
+
+            vk.device
+                .cmd_dispatch(cb, group_count[0], group_count[1], group_count[2]);
+        }
+
+        vk.device.cmd_write_timestamp(
+            cb,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk_frame.profiler_data.query_pool,
+            vk_query_idx * 2 + 1,
+        );
+        vk.end_debug_label(cb);
+
+        for output in outputs {
+            match &output.resource {
+                ComputeOutputResource::Texture(texture) => {
+                    record_image_barrier(
+                        &vk.device,
+                        cb,
+                        ImageBarrier::new(
+                            texture.image,
+                            vk_sync::AccessType::ComputeShaderWrite,
+                            vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                        ),
+                    );
+                }
+                ComputeOutputResource::Buffer(_) => {
+                    let global_barrier = vk_sync::GlobalBarrier {
+                        previous_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
+                        next_accesses: &[vk_sync::AccessType::General],
+                    };
+
+                    vk_sync::cmd::pipeline_barrier(
+                        vk.device.fp_v1_0(),
+                        cb,
+                        Some(global_barrier),
+                        &[],
+                        &[],
+                    );
+                }
+            }
+        }
+    }
+
+    uniform_source.report_resource_uses(&pass_name);
+    uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+
+    for output in outputs {
+        if let ComputeOutputResource::Texture(texture) = &output.resource {
+            gpu_debugger::report_texture(&pass_name, texture);
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Raw contents of scalar and vector uniforms, for `uniform_folding`.
+fn uniform_value_words(value: &ResolvedShaderUniformValue) -> Option<Vec<u32>> {
+    Some(match *value {
+        ResolvedShaderUniformValue::Float32(v) => vec![v.to_bits()],
+        ResolvedShaderUniformValue::Uint32(v) => vec![v],
+        ResolvedShaderUniformValue::Int32(v) => vec![v as u32],
+        ResolvedShaderUniformValue::Ivec2((x, y)) => vec![x as u32, y as u32],
+        ResolvedShaderUniformValue::Vec2((x, y)) => vec![x.to_bits(), y.to_bits()],
+        ResolvedShaderUniformValue::Vec3((x, y, z)) => vec![x.to_bits(), y.to_bits(), z.to_bits()],
+        ResolvedShaderUniformValue::Vec4((x, y, z, w)) => {
+            vec![x.to_bits(), y.to_bits(), z.to_bits(), w.to_bits()]
+        }
+        _ => return None,
+    })
+}
+
+#[snoozy]
+pub async fn compute_tex_snoozy(
+    ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let thread_count = [key.width, key.height, key.depth];
+    compute_tex_impl(ctx, key, thread_count, cs, uniforms, None).await
+}
+
+// Like `compute_tex`, but dispatching `thread_count` threads regardless of the output size,
+// e.g. a 1D dispatch over particles which splats into the output. Dispatches without
+// an output texture are done with `compute_buf`.
+#[snoozy]
+pub async fn compute_tex_with_dispatch_snoozy(
+    ctx: Context,
+    key: &TextureKey,
+    thread_count: &[u32; 3],
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    compute_tex_impl(ctx, key, *thread_count, cs, uniforms, None).await
+}
+
+// Like `compute_tex`, but with the group count read from a `VkDispatchIndirectCommand`
+// at `indirect_off` bytes into `indirect_buf`, e.g. as written by a culling pass
+// with `compute_buf`.
+#[snoozy]
+pub async fn compute_tex_indirect_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    indirect_buf: &SnoozyRef<Buffer>,
+    indirect_off: &u64,
+) -> Result<Texture> {
+    let indirect_buf = (*ctx.get(indirect_buf).await?).clone();
+    let indirect_args = Some((indirect_buf, *indirect_off));
+    let thread_count = [key.width, key.height, key.depth];
+
+    compute_tex_impl(ctx, key, thread_count, cs, uniforms, indirect_args).await
+}
+
+async fn compute_tex_impl(
+    mut ctx: Context,
+    key: &TextureKey,
+    thread_count: [u32; 3],
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    indirect_args: Option<(Buffer, u64)>,
+) -> Result<Texture> {
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    let (output_tex, load_op) = match take_output_load_op(&mut uniforms) {
+        OutputLoadOp::Load => {
+            // Persistent outputs are found by the tagged pass name
+            let tags = take_op_tags(&mut uniforms);
+            let pass_name = tags.tagged_name(&ctx.get(cs).await?.name);
+            uniforms.push(ResolvedShaderUniformHolder {
+                name: String::new(),
+                payload: ResolvedShaderUniformPayload {
+                    value: ResolvedShaderUniformValue::Tags(tags),
+                    warn_if_unreferenced: false,
+                },
+            });
+
+            output_load_op::persistent_output_texture(&pass_name, *key)
+        }
+        load_op => (crate::backend::texture::create_texture(*key), load_op),
+    };
+
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputTex".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
+            warn_if_unreferenced: true,
+        },
+    });
+
+    compute_common(
+        ctx,
+        thread_count,
+        cs,
+        uniforms,
+        &[ComputeOutput::new_texture(&output_tex).with_load_op(load_op)],
+        indirect_args,
+    )
+    .await?;
+
+    Ok(output_tex)
+}
+
+// Storage images named like `outputFooTex`, other than `outputTex` itself, are additional
+// outputs, along with the format they declare, e.g. `layout(rg16f) image2D outputNormalTex`.
+fn declared_output_textures(cs: &ComputeShader) -> Result<Vec<(String, Option<vk::Format>)>> {
+    use spirv_reflect::types::descriptor::ReflectDescriptorType;
+
+    let mut outputs = Vec::new();
+    for descriptor_set in
+        convert_spirv_reflect_err(cs.spirv_reflection.enumerate_descriptor_sets(Some("main")))?
+    {
+        for binding in descriptor_set.bindings.iter() {
+            let name = &binding.name;
+            let is_storage_image = match binding.descriptor_type {
+                ReflectDescriptorType::StorageImage => true,
+                _ => false,
+            };
+
+            if is_storage_image
+                && name != "outputTex"
+                && name.starts_with("output")
+                && name.ends_with("Tex")
+            {
+                outputs.push((
+                    name.clone(),
+                    vk_format_from_image_format(binding.image.image_format),
+                ));
+            }
+        }
+    }
+
+    outputs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(outputs)
+}
+
+// `None` for images without a format qualifier.
+fn vk_format_from_image_format(
+    format: spirv_reflect::types::image::ReflectImageFormat,
+) -> Option<vk::Format> {
+    use spirv_reflect::types::image::ReflectImageFormat as R;
+    use vk::Format as F;
+
+    Some(match format {
+        R::Undefined => return None,
+        R::RGBA32_FLOAT => F::R32G32B32A32_SFLOAT,
+        R::RGBA16_FLOAT => F::R16G16B16A16_SFLOAT,
+        R::R32_FLOAT => F::R32_SFLOAT,
+        R::RGBA8 => F::R8G8B8A8_UNORM,
+        R::RGBA8_SNORM => F::R8G8B8A8_SNORM,
+        R::RG32_FLOAT => F::R32G32_SFLOAT,
+        R::RG16_FLOAT => F::R16G16_SFLOAT,
+        R::R11G11B10_FLOAT => F::B10G11R11_UFLOAT_PACK32,
+        R::R16_FLOAT => F::R16_SFLOAT,
+        R::RGBA16 => F::R16G16B16A16_UNORM,
+        R::RGB10A2 => F::A2B10G10R10_UNORM_PACK32,
+        R::RG16 => F::R16G16_UNORM,
+        R::RG8 => F::R8G8_UNORM,
+        R::R16 => F::R16_UNORM,
+        R::R8 => F::R8_UNORM,
+        R::RGBA16_SNORM => F::R16G16B16A16_SNORM,
+        R::RG16_SNORM => F::R16G16_SNORM,
+        R::RG8_SNORM => F::R8G8_SNORM,
+        R::R16_SNORM => F::R16_SNORM,
+        R::R8_SNORM => F::R8_SNORM,
+        R::RGBA32_INT => F::R32G32B32A32_SINT,
+        R::RGBA16_INT => F::R16G16B16A16_SINT,
+        R::RGBA8_INT => F::R8G8B8A8_SINT,
+        R::R32_INT => F::R32_SINT,
+        R::RG32_INT => F::R32G32_SINT,
+        R::RG16_INT => F::R16G16_SINT,
+        R::RG8_INT => F::R8G8_SINT,
+        R::R16_INT => F::R16_SINT,
+        R::R8_INT => F::R8_SINT,
+        R::RGBA32_UINT => F::R32G32B32A32_UINT,
+        R::RGBA16_UINT => F::R16G16B16A16_UINT,
+        R::RGBA8_UINT => F::R8G8B8A8_UINT,
+        R::R32_UINT => F::R32_UINT,
+        R::RGB10A2_UINT => F::A2B10G10R10_UINT_PACK32,
+        R::RG32_UINT => F::R32G32_UINT,
+        R::RG16_UINT => F::R16G16_UINT,
+        R::RG8_UINT => F::R8G8_UINT,
+        R::R16_UINT => F::R16_UINT,
+        R::R8_UINT => F::R8_UINT,
+    })
+}
+
+// Outputs of `compute_tex_outputs`, `compute_tex_n` and `raster_tex_n` by their names
+// in the shader.
+#[derive(Clone)]
+pub struct ComputeTexOutputs(pub Vec<(String, Texture)>);
+
+// Like `compute_tex`, but also creates any additional outputs the shader declares
+// (see `declared_output_textures`), with the size of `key`. Images without a format
+// qualifier get the format of `key`.
+#[snoozy]
+pub async fn compute_tex_outputs_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<ComputeTexOutputs> {
+    let declared = declared_output_textures(&*ctx.get(cs).await?)?;
+
+    let mut outputs = vec![(
+        "outputTex".to_owned(),
+        crate::backend::texture::create_texture(*key),
+    )];
+    for (name, format) in declared {
+        let key = format.map_or(*key, |format| key.with_format(format));
+        outputs.push((name, crate::backend::texture::create_texture(key)));
+    }
+
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    for (name, tex) in outputs.iter() {
+        uniforms.push(ResolvedShaderUniformHolder {
+            name: name.clone(),
+            payload: ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::RwTexture(tex.clone()),
+                warn_if_unreferenced: name == "outputTex",
+            },
+        });
+    }
 
-                                    /*println!(
-                                        "Updating storage buffer at {}:{}",
-                                        binding.set, binding.binding
-                                    );*/
+    let compute_outputs: Vec<ComputeOutput> = outputs
+        .iter()
+        .map(|(_, tex)| ComputeOutput::new_texture(tex))
+        .collect();
 
-                                    ds_writes.push(
-                                        vk::WriteDescriptorSet::builder()
-                                            .dst_set(
-                                                descriptor_sets.get_at_idx(binding.set as usize)?,
-                                            )
-                                            .dst_binding(binding.binding)
-                                            .dst_array_element(0)
-                                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                                            .buffer_info(buffer_info)
-                                            .build(),
-                                    );
-                                }
-                                _ => {
-                                    panic!(binding
-                                        .type_description
-                                        .as_ref()
-                                        .unwrap()
-                                        .type_name
-                                        .clone());
-                                }
-                            }
-                        }
-                        ReflectDescriptorType::Sampler => {
-                            // Immutable. Nothing to do.
-                            /*println!(
-                                "Skipping sampler update at {}:{}",
-                                binding.set, binding.binding
-                            );*/
-                        }
-                        ReflectDescriptorType::UniformTexelBuffer => {
-                            match uniforms.get(&binding.name) {
-                                Some(ResolvedShaderUniformValue::Buffer(value))
-                                | Some(ResolvedShaderUniformValue::RwBuffer(value)) => {
-                                    ds_buffer_views.push([value.view]);
-                                    let buffer_view = ds_buffer_views.last().unwrap();
+    compute_common(
+        ctx,
+        [key.width, key.height, key.depth],
+        cs,
+        uniforms,
+        &compute_outputs,
+        None,
+    )
+    .await?;
+
+    Ok(ComputeTexOutputs(outputs))
+}
+
+#[snoozy]
+pub async fn compute_tex_output_snoozy(
+    mut ctx: Context,
+    outputs: &SnoozyRef<ComputeTexOutputs>,
+    name: &String,
+) -> Result<Texture> {
+    let outputs = ctx.get(outputs).await?;
+    outputs
+        .0
+        .iter()
+        .find(|(output_name, _)| output_name == name)
+        .map(|(_, tex)| tex.clone())
+        .ok_or_else(|| format_err!("The shader has no output named {}", name))
+}
+
+// All outputs of `compute_tex_outputs` as a bundle, so new ones can be passed along
+// to later passes without changes on the host side.
+#[snoozy]
+pub async fn compute_tex_bundle_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<ShaderUniformBundle> {
+    let outputs_ref = compute_tex_outputs(*key, cs.clone(), uniforms.clone());
+    let outputs = ctx.get(&outputs_ref).await?;
+
+    Ok(outputs
+        .0
+        .iter()
+        .map(|(name, _)| {
+            ShaderUniformHolder::new(name, compute_tex_output(outputs_ref.clone(), name.clone()))
+        })
+        .collect())
+}
+
+// Like `compute_tex`, but writing `outputTex0` to `outputTexN` with one key each.
+// The dispatch covers the first key. Outputs are named like the images in the shader,
+// so `compute_tex_output` gets them by name.
+#[snoozy]
+pub async fn compute_tex_n_snoozy(
+    ctx: Context,
+    keys: &Vec<TextureKey>,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<ComputeTexOutputs> {
+    let first_key = *keys
+        .first()
+        .ok_or_else(|| format_err!("compute_tex_n needs at least one output"))?;
+
+    let outputs: Vec<(String, Texture)> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            (
+                format!("outputTex{}", i),
+                crate::backend::texture::create_texture(*key),
+            )
+        })
+        .collect();
+
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    for (name, tex) in outputs.iter() {
+        uniforms.push(ResolvedShaderUniformHolder {
+            name: name.clone(),
+            payload: ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::RwTexture(tex.clone()),
+                warn_if_unreferenced: true,
+            },
+        });
+    }
+
+    let compute_outputs: Vec<ComputeOutput> = outputs
+        .iter()
+        .map(|(_, tex)| ComputeOutput::new_texture(tex))
+        .collect();
+
+    compute_common(
+        ctx,
+        [first_key.width, first_key.height, first_key.depth],
+        cs,
+        uniforms,
+        &compute_outputs,
+        None,
+    )
+    .await?;
+
+    Ok(ComputeTexOutputs(outputs))
+}
+
+#[snoozy]
+pub async fn compute_buf_snoozy(
+    ctx: Context,
+    key: &BufferKey,
+    thread_count: &[u32; 3],
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Buffer> {
+    let output_buf = crate::backend::buffer::create_buffer(*key);
+
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputBuf".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwBuffer(output_buf.clone()),
+            warn_if_unreferenced: true,
+        },
+    });
+
+    compute_common(
+        ctx,
+        *thread_count,
+        cs,
+        uniforms,
+        &[ComputeOutput::new_buffer(&output_buf)],
+        None,
+    )
+    .await?;
+
+    Ok(output_buf)
+}
+
+#[snoozy]
+pub async fn recompute_tex_snoozy(
+    mut ctx: Context,
+    output_tex: &SnoozyRef<Texture>,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let output_tex = (*ctx.get(output_tex).await?).clone();
+
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputTex".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
+            warn_if_unreferenced: true,
+        },
+    });
+
+    let key = output_tex.key;
+    compute_common(
+        ctx,
+        [key.width, key.height, key.depth],
+        cs,
+        uniforms,
+        &[ComputeOutput::mutate_texture(&output_tex)],
+        None,
+    )
+    .await?;
+
+    Ok(output_tex)
+}
+
+// Like `recompute_tex`, but the dispatch is rate-limited to the background compute budget.
+// Frames over budget skip the dispatch, and return the texture unchanged.
+#[snoozy]
+pub async fn recompute_tex_background_snoozy(
+    mut ctx: Context,
+    output_tex: &SnoozyRef<Texture>,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let output_tex = (*ctx.get(output_tex).await?).clone();
+    let cs_name = ctx.get(cs).await?.name.clone();
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+
+    // Profiler scopes are named after the tags as well
+    let tags = take_op_tags(&mut uniforms);
+    if !background_compute::try_begin_background_pass(&tags.tagged_name(&cs_name)) {
+        // Out of budget; the texture is left as-is, and the pass retried next frame.
+        background_compute::retry_next_frame(Box::new(ctx.get_invalidation_trigger()));
+        return Ok(output_tex);
+    }
+
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: String::new(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::Tags(tags),
+            warn_if_unreferenced: false,
+        },
+    });
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputTex".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
+            warn_if_unreferenced: true,
+        },
+    });
+
+    let key = output_tex.key;
+    compute_common(
+        ctx,
+        [key.width, key.height, key.depth],
+        cs,
+        uniforms,
+        &[ComputeOutput::mutate_texture(&output_tex)],
+        None,
+    )
+    .await?;
+
+    Ok(output_tex)
+}
+
+#[snoozy]
+pub async fn recompute_indirect_tex_snoozy(
+    mut ctx: Context,
+    output_tex: &SnoozyRef<Texture>,
+    cs: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    indirect_buf: &SnoozyRef<Buffer>,
+    indirect_off: &u64,
+) -> Result<Texture> {
+    let output_tex = (*ctx.get(output_tex).await?).clone();
+    let indirect_buf = (*ctx.get(indirect_buf).await?).clone();
+
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputTex".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
+            warn_if_unreferenced: true,
+        },
+    });
+
+    let key = output_tex.key;
+    compute_common(
+        ctx,
+        [key.width, key.height, key.depth],
+        cs,
+        uniforms,
+        &[ComputeOutput::mutate_texture(&output_tex)],
+        Some((indirect_buf, *indirect_off)),
+    )
+    .await?;
+
+    Ok(output_tex)
+}
 
-                                    ds_writes.push(
-                                        vk::WriteDescriptorSet::builder()
-                                            .dst_set(
-                                                descriptor_sets.get_at_idx(binding.set as usize)?,
-                                            )
-                                            .dst_binding(binding.binding)
-                                            .dst_array_element(0)
-                                            .descriptor_type(
-                                                vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
-                                            )
-                                            .texel_buffer_view(buffer_view)
-                                            .build(),
-                                    );
-                                }
-                                _ => {
-                                    if "all_buffers" == binding.name {
-                                        // Updated elsewhere. Do note the set idx so we can assign the descriptor set afterwards.
-                                        all_buffers_descriptor_set_idx.push(binding.set as usize);
-                                    } else {
-                                        // TODO
-                                        panic!("Could not find resource to bind {}", binding.name);
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            dbg!(&binding);
-                        }
-                    }
-                }
+// A framebuffer destroyed at the end of the current frame.
+unsafe fn create_frame_framebuffer(
+    render_pass: vk::RenderPass,
+    key: &TextureKey,
+    attachments: &[vk::ImageView],
+) -> Result<vk::Framebuffer> {
+    let (vk, vk_state) = vk_all();
+
+    let fbo_desc = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .width(key.width as _)
+        .height(key.height as _)
+        .layers(1)
+        .attachments(attachments);
+    let fbo = vk.device.create_framebuffer(&fbo_desc, None)?;
+
+    vk_state
+        .current_frame()
+        .frame_cleanup
+        .lock()
+        .unwrap()
+        .push(Box::new(move |vk| {
+            vk.device.destroy_framebuffer(fbo, None);
+        }));
+
+    Ok(fbo)
+}
+
+// Barriers can't be recorded inside of render passes, so textures sampled or written
+// as storage images by the draws get transitioned before, wherever they are in the uniform tree.
+// The pass's `attachments`, including ones read by later subpasses, are transitioned
+// when it begins instead.
+fn record_uniform_texture_transitions(
+    device: &Device,
+    cb: vk::CommandBuffer,
+    uniforms: &[ResolvedShaderUniformHolder],
+    attachments: &[vk::Image],
+) {
+    for uniform in uniforms {
+        match &uniform.payload.value {
+            ResolvedShaderUniformValue::Texture(texture)
+                if !attachments.contains(&texture.image) =>
+            {
+                record_image_transition(
+                    device,
+                    cb,
+                    texture.image,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                    false,
+                )
+            }
+            ResolvedShaderUniformValue::RwTexture(texture)
+                if !attachments.contains(&texture.image) =>
+            {
+                record_image_transition(
+                    device,
+                    cb,
+                    texture.image,
+                    vk_sync::AccessType::AnyShaderWrite,
+                    false,
+                )
+            }
+            ResolvedShaderUniformValue::Bundle(bundle) => {
+                record_uniform_texture_transitions(device, cb, bundle, attachments)
             }
+            _ => {}
         }
+    }
+}
 
-        //dbg!(&ds_writes);
-        //dbg!(&descriptor_sets);
-        if !ds_writes.is_empty() {
-            unsafe { device.update_descriptor_sets(&ds_writes, &[]) };
-        }
+unsafe fn begin_raster_render_pass(
+    cb: vk::CommandBuffer,
+    render_pass: vk::RenderPass,
+    imageless_framebuffer: vk::Framebuffer,
+    key: &TextureKey,
+    color_attachments: &[&Texture],
+    // One per color attachment
+    load_ops: &[OutputLoadOp],
+    external_input: Option<&Texture>,
+) -> Result<()> {
+    let (vk, vk_state) = vk_all();
 
-        Ok(())
-    })?;
+    let mut clear_values = Vec::with_capacity(color_attachments.len() + 2);
+    let mut texture_attachments = Vec::with_capacity(color_attachments.len() + 2);
 
-    Ok(DescritorSetUpdateResult {
-        dynamic_offsets: ds_offsets,
-        all_buffers_descriptor_set_idx,
-        all_textures_descriptor_set_idx,
-    })
-}
+    assert_eq!(color_attachments.len(), load_ops.len());
+    for (tex, &load_op) in color_attachments.iter().zip(load_ops.iter()) {
+        record_image_transition(
+            &vk.device,
+            cb,
+            tex.image,
+            vk_sync::AccessType::ColorAttachmentWrite,
+            load_op != OutputLoadOp::Load,
+        );
 
-pub struct ResolvedShaderUniformPayload {
-    value: ResolvedShaderUniformValue,
-    warn_if_unreferenced: bool,
-}
+        // Ignored by passes which load their attachments
+        let clear_color = match load_op {
+            OutputLoadOp::Clear(color) => color,
+            _ => [0.0, 0.0, 0.0, 0.0],
+        };
+        clear_values.push(vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: clear_color,
+            },
+        });
+        texture_attachments.push(tex.rt_view);
+    }
 
-// Provide access to uniforms, but also record which ones are being requested,
-// so that we can issue warnings about any unreferenced ones.
-struct TrackedUniformParamSource {
-    uniforms: HashMap<String, ResolvedShaderUniformPayload>,
-    requested: HashSet<String>,
+    clear_values.push(vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: coord_convention::coord_convention().depth_far(),
+            stencil: 0,
+        },
+    });
+    texture_attachments.push(vk_state.depth_image_view);
+
+    if let Some(tex) = external_input {
+        // Not cleared, but clear values are indexed by attachment
+        clear_values.push(vk::ClearValue::default());
+        texture_attachments.push(tex.rt_view);
+    }
+
+    let mut pass_attachment_desc =
+        vk::RenderPassAttachmentBeginInfoKHR::builder().attachments(&texture_attachments);
+
+    const USE_IMAGELESS: bool = false;
+
+    let framebuffer = if USE_IMAGELESS {
+        imageless_framebuffer
+    } else {
+        // HACK; must not do this, but validation layers are broken with IMAGELESS_KHR
+        create_frame_framebuffer(render_pass, key, &texture_attachments)?
+    };
+
+    let mut pass_begin_desc = vk::RenderPassBeginInfo::builder()
+        .render_pass(render_pass)
+        .framebuffer(framebuffer)
+        .render_area(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: key.width as _,
+                height: key.height as _,
+            },
+        })
+        .clear_values(&clear_values);
+
+    if USE_IMAGELESS {
+        pass_begin_desc = pass_begin_desc.push_next(&mut pass_attachment_desc)
+    }
+
+    vk.device
+        .cmd_begin_render_pass(cb, &pass_begin_desc, vk::SubpassContents::INLINE);
+
+    Ok(())
 }
 
-impl UniformParamSource for TrackedUniformParamSource {
-    fn len(&self) -> usize {
-        self.uniforms.len()
+unsafe fn bind_raster_pipeline(
+    cb: vk::CommandBuffer,
+    raster_pipe: &RasterPipeline,
+    key: &TextureKey,
+    uniform_source: &mut TrackedUniformParamSource,
+) -> Result<()> {
+    let (vk, vk_state) = vk_all();
+    let vk_frame = vk_state.current_frame();
+
+    let (descriptor_sets, ds_update_result) = {
+        let descriptor_sets = {
+            let layout_info = &raster_pipe.descriptor_set_layout_info;
+            let descriptor_pool = vk_frame.descriptor_pool.lock().unwrap();
+            let dynamic_sets = vk.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(*descriptor_pool)
+                    .set_layouts(&layout_info.dynamic_layouts)
+                    .build(),
+            )?;
+            drop(descriptor_pool);
+
+            let mut sets = vec![None; layout_info.all_layouts.len()];
+            for (src, dst) in layout_info.dynamic_layout_indices.iter().enumerate() {
+                sets[*dst] = Some(dynamic_sets[src]);
+            }
+
+            sets
+        };
+
+        let ds_update_result = update_descriptor_sets(
+            &vk.device,
+            raster_pipe.shader_refl.iter(),
+            &descriptor_sets,
+            uniform_source,
+        )
+        .unwrap();
+
+        (descriptor_sets, ds_update_result)
+    };
+
+    let mut descriptor_sets = descriptor_sets;
+
+    for idx in ds_update_result.all_buffers_descriptor_set_idx.iter() {
+        descriptor_sets[*idx] = Some(vk_state.bindless_buffers_descriptor_set);
     }
 
-    fn get(&mut self, name: &str) -> Option<&ResolvedShaderUniformValue> {
-        self.requested.insert(name.to_owned());
-        self.uniforms.get(name).map(|v| &v.value)
+    for idx in ds_update_result.all_textures_descriptor_set_idx.iter() {
+        descriptor_sets[*idx] = Some(vk_state.bindless_images_descriptor_set);
     }
+
+    let descriptor_sets: Vec<_> = descriptor_sets.into_iter().map(Option::unwrap).collect();
+
+    vk.device
+        .cmd_bind_pipeline(cb, vk::PipelineBindPoint::GRAPHICS, raster_pipe.pipeline);
+
+    vk.device.cmd_set_viewport(
+        cb,
+        0,
+        &[coord_convention::coord_convention().viewport(key.width, key.height)],
+    );
+    vk.device.cmd_set_scissor(
+        cb,
+        0,
+        &[vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: key.width as _,
+                height: key.height as _,
+            },
+        }],
+    );
+
+    vk.device.cmd_bind_descriptor_sets(
+        cb,
+        vk::PipelineBindPoint::GRAPHICS,
+        raster_pipe.pipeline_layout,
+        0,
+        &descriptor_sets,
+        &ds_update_result.dynamic_offsets,
+    );
+    record_push_constants(
+        &vk.device,
+        cb,
+        raster_pipe.pipeline_layout,
+        &raster_pipe.descriptor_set_layout_info,
+        &ds_update_result.push_constants,
+    );
+
+    Ok(())
 }
 
-impl TrackedUniformParamSource {
-    fn report_unreferenced_uniform_warnings(self, shader_name: &str) {
-        let requested = self.requested;
-        let unreferenced = self
-            .uniforms
-            .into_iter()
-            .filter_map(move |(name, payload)| {
-                if !payload.warn_if_unreferenced || requested.contains(&name) {
-                    None
-                } else {
-                    Some(name)
+// Walks the uniform tree, and issues an indexed draw for every scope which
+// defines both `mesh_index_buf` and `mesh_index_count`. Draws are instanced
+// `mesh_instance_count` times, if set in the scope or one enclosing it.
+//
+// Scopes within one setting `mesh_indirect_buf` are drawn indirectly instead, with
+// `mesh_indirect_draw_count` (default 1) `VkDrawIndexedIndirectCommand`s starting
+// `mesh_indirect_offset` bytes in. More than one draw needs `multiDrawIndirect`.
+fn record_raster_mesh_draws(
+    cb: vk::CommandBuffer,
+    raster_pipe: &RasterPipeline,
+    key: &TextureKey,
+    pass_name: &str,
+    uniforms: Vec<ResolvedShaderUniformHolder>,
+) -> TrackedUniformParamSource {
+    let (vk, vk_state) = vk_all();
+    let vk_frame = vk_state.current_frame();
+
+    #[derive(Default)]
+    struct MeshDrawData {
+        index_buffer: Option<vk::Buffer>,
+        index_count: Option<u32>,
+        instance_count: Option<u32>,
+        // `VkDrawIndexedIndirectCommand`s; the offset is in bytes
+        indirect_buffer: Option<vk::Buffer>,
+        indirect_offset: Option<u32>,
+        indirect_draw_count: Option<u32>,
+        occlusion_query: Option<String>,
+    }
+
+    let mut mesh_stack = vec![MeshDrawData::default()];
+    let vertex_layout = &raster_pipe.vertex_layout;
+
+    let mut flattened_uniforms: HashMap<String, ResolvedShaderUniformPayload> = HashMap::new();
+    flattened_uniforms.insert(
+        deterministic_math::RNG_SEED_UNIFORM_NAME.to_owned(),
+        ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::Uint32(deterministic_math::rng_seed()),
+            warn_if_unreferenced: false,
+        },
+    );
+    if shader_instrumentation::is_any_shader_instrumented() {
+        flattened_uniforms.insert(
+            shader_instrumentation::PASS_ID_UNIFORM_NAME.to_owned(),
+            ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::Uint32(
+                    vk_frame.shader_asan.register_pass(pass_name),
+                ),
+                warn_if_unreferenced: false,
+            },
+        );
+    }
+
+    let mut uniform_source = TrackedUniformParamSource {
+        uniforms: flattened_uniforms,
+        requested: HashSet::new(),
+    };
+
+    flatten_uniforms(uniforms, &mut |e| match e {
+        FlattenedUniformEvent::SetUniform { name, mut payload } => {
+            match payload.value {
+                ResolvedShaderUniformValue::OcclusionQuery(ref query) => {
+                    mesh_stack.last_mut().unwrap().occlusion_query = Some(query.0.clone());
+                    return;
+                }
+                ResolvedShaderUniformValue::Buffer(ref buf) if name == "mesh_index_buf" => {
+                    mesh_stack.last_mut().unwrap().index_buffer = Some(buf.buffer);
+                    payload.warn_if_unreferenced = false;
+                }
+                ResolvedShaderUniformValue::Uint32(value) if name == "mesh_index_count" => {
+                    mesh_stack.last_mut().unwrap().index_count = Some(value);
+                    payload.warn_if_unreferenced = false;
+                }
+                ResolvedShaderUniformValue::Uint32(value) if name == "mesh_instance_count" => {
+                    mesh_stack.last_mut().unwrap().instance_count = Some(value);
+                    payload.warn_if_unreferenced = false;
+                }
+                ResolvedShaderUniformValue::Buffer(ref buf) if name == "mesh_indirect_buf" => {
+                    mesh_stack.last_mut().unwrap().indirect_buffer = Some(buf.buffer);
+                    payload.warn_if_unreferenced = false;
                 }
+                ResolvedShaderUniformValue::Uint32(value) if name == "mesh_indirect_offset" => {
+                    mesh_stack.last_mut().unwrap().indirect_offset = Some(value);
+                    payload.warn_if_unreferenced = false;
+                }
+                ResolvedShaderUniformValue::Uint32(value) if name == "mesh_indirect_draw_count" => {
+                    mesh_stack.last_mut().unwrap().indirect_draw_count = Some(value);
+                    payload.warn_if_unreferenced = false;
+                }
+                // Meshes come with all their attributes, e.g. for depth-only passes
+                // which don't need most of them
+                _ if name.starts_with("mesh_") => {
+                    payload.warn_if_unreferenced = false;
+                }
+                // Vertex buffers aren't referenced by the shaders themselves
+                _ if vertex_layout.bindings.iter().any(|b| b.buffer == name) => {
+                    payload.warn_if_unreferenced = false;
+                }
+                _ => {}
+            }
+
+            uniform_source.uniforms.insert(name, payload);
+        }
+        FlattenedUniformEvent::EnterScope => {
+            // Queries, instance counts and indirect args cover the draws of nested scopes
+            // too, as meshes are usually bundles of their own
+            let parent = mesh_stack.last().unwrap();
+            mesh_stack.push(MeshDrawData {
+                occlusion_query: parent.occlusion_query.clone(),
+                instance_count: parent.instance_count,
+                indirect_buffer: parent.indirect_buffer,
+                indirect_offset: parent.indirect_offset,
+                indirect_draw_count: parent.indirect_draw_count,
+                ..Default::default()
             });
+        }
+        FlattenedUniformEvent::LeaveScope => {
+            let mesh = mesh_stack.pop().unwrap();
+            let instance_count = mesh.instance_count.unwrap_or(1);
+            // Indirect draws take their index and instance counts from the args instead
+            let has_draw_args = mesh.indirect_buffer.is_some() || mesh.index_count.is_some();
+            if has_draw_args && instance_count > 0 {
+                let index_count = mesh.index_count.unwrap_or(0);
+                if let Some(index_buffer) = mesh.index_buffer {
+                    let vertex_buffers = match find_vertex_buffers(vertex_layout, &uniform_source) {
+                        Ok(buffers) => buffers,
+                        Err(err) => {
+                            crate::rtoy_show_warning(format!("{}: {}", pass_name, err));
+                            return;
+                        }
+                    };
 
-        for name in unreferenced {
-            crate::rtoy_show_warning(format!("Unreferenced uniform {}::{}", shader_name, name));
+                    unsafe {
+                        bind_raster_pipeline(cb, raster_pipe, key, &mut uniform_source)
+                            .expect("bind_raster_pipeline");
+                        vk.device
+                            .cmd_bind_index_buffer(cb, index_buffer, 0, vk::IndexType::UINT32);
+                        if !vertex_buffers.is_empty() {
+                            let offsets = vec![0; vertex_buffers.len()];
+                            vk.device
+                                .cmd_bind_vertex_buffers(cb, 0, &vertex_buffers, &offsets);
+                        }
+
+                        let occlusion_queries = &vk_frame.occlusion_queries;
+                        let query = mesh
+                            .occlusion_query
+                            .as_ref()
+                            .and_then(|name| occlusion_queries.begin_query(&vk.device, cb, name));
+                        if let Some(indirect_buffer) = mesh.indirect_buffer {
+                            vk.device.cmd_draw_indexed_indirect(
+                                cb,
+                                indirect_buffer,
+                                mesh.indirect_offset.unwrap_or(0) as vk::DeviceSize,
+                                mesh.indirect_draw_count.unwrap_or(1),
+                                std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+                            );
+                        } else {
+                            vk.device
+                                .cmd_draw_indexed(cb, index_count, instance_count, 0, 0, 0);
+                        }
+                        if let Some(query) = query {
+                            occlusion_queries.end_query(&vk.device, cb, query);
+                        }
+                        //println!("-------");
+                    }
+                }
+            }
         }
-    }
-}
+    });
 
-enum ComputeOutputResource {
-    Texture(Texture),
-    Buffer(Buffer),
+    uniform_source
 }
 
-struct ComputeOutput {
-    resource: ComputeOutputResource,
-    discard: bool,
+// Buffers for each binding of `vertex_layout`, as currently set in the uniform scope.
+fn find_vertex_buffers(
+    vertex_layout: &VertexLayout,
+    uniform_source: &TrackedUniformParamSource,
+) -> Result<Vec<vk::Buffer>> {
+    vertex_layout
+        .bindings
+        .iter()
+        .map(
+            |binding| match uniform_source.uniforms.get(&binding.buffer) {
+                Some(ResolvedShaderUniformPayload {
+                    value: ResolvedShaderUniformValue::Buffer(ref buf),
+                    ..
+                }) => Ok(buf.buffer),
+                Some(_) => Err(format_err!(
+                    "Vertex buffer {} is not a buffer",
+                    binding.buffer
+                )),
+                None => Err(format_err!(
+                    "No vertex buffer {} for a draw",
+                    binding.buffer
+                )),
+            },
+        )
+        .collect()
 }
 
-impl ComputeOutput {
-    pub fn new_texture(tex: &Texture) -> Self {
-        Self {
-            resource: ComputeOutputResource::Texture(tex.clone()),
-            discard: true,
+// Raster passes get validated against the uniforms of all scopes at once,
+// as the draws themselves are not walked.
+fn validate_raster_dry_run(
+    pass_name: &str,
+    stages: &[&RasterPipeline],
+    key: &TextureKey,
+    uniforms: Vec<ResolvedShaderUniformHolder>,
+) -> Result<()> {
+    let mut uniform_source = TrackedUniformParamSource {
+        uniforms: HashMap::new(),
+        requested: HashSet::new(),
+    };
+    flatten_uniforms(uniforms, &mut |e| {
+        if let FlattenedUniformEvent::SetUniform { name, payload } = e {
+            uniform_source.uniforms.insert(name, payload);
         }
-    }
-
-    pub fn new_buffer(buf: &Buffer) -> Self {
-        Self {
-            resource: ComputeOutputResource::Buffer(buf.clone()),
-            discard: true,
+    });
+
+    let mut issues = Vec::new();
+    let format = vk::Format::from_raw(key.format);
+    if crate::backend::texture::is_depth_format(format) {
+        if !format_supports(format, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT) {
+            issues.push(format!("{:?} can't be used as a depth attachment", format));
         }
+    } else if !format_supports(format, vk::FormatFeatureFlags::COLOR_ATTACHMENT) {
+        issues.push(format!("{:?} can't be used as a color attachment", format));
+    }
+    if key.width == 0 || key.height == 0 {
+        issues.push(format!("Render target is {}x{}", key.width, key.height));
     }
 
-    pub fn mutate_texture(tex: &Texture) -> Self {
-        Self {
-            resource: ComputeOutputResource::Texture(tex.clone()),
-            discard: false,
+    for (stage_idx, stage) in stages.iter().enumerate() {
+        if stage_idx > 0 && !uniform_source.uniforms.contains_key("inputTex") {
+            // Bound by the chain itself; any texture will do for validation.
+            let placeholder = crate::backend::texture::create_texture(*key);
+            uniform_source.uniforms.insert(
+                "inputTex".to_owned(),
+                ResolvedShaderUniformPayload {
+                    value: ResolvedShaderUniformValue::Texture(placeholder),
+                    warn_if_unreferenced: false,
+                },
+            );
         }
+
+        issues.extend(
+            validate_descriptor_bindings(stage.shader_refl.iter(), &mut uniform_source)
+                .map_err(|err| format_err!("{}", err))?,
+        );
     }
+
+    dry_run::report_dry_run_pass(pass_name, issues);
+    Ok(())
 }
 
-async fn compute_common(
+// A pass whose pixel shader reads the previous pass's output via `subpassInput`, with
+// `input_attachment_index = 0`, becomes a subpass of that pass's render pass, as long
+// as both have the same size and the later one doesn't depth test.
+#[snoozy]
+pub async fn raster_tex_snoozy(
     mut ctx: Context,
-    thread_count: [u32; 3],
-    cs: &SnoozyRef<ComputeShader>,
-    uniforms: Vec<ResolvedShaderUniformHolder>,
-    outputs: &[ComputeOutput],
-    indirect_args: Option<(Buffer, u64)>,
-) -> Result<()> {
-    let cs = ctx.get(cs).await?;
-    ctx.set_debug_name(&cs.name);
-
-    let (vk, vk_state) = vk_all();
-    let vk_frame = vk_state.current_frame();
+    key: &TextureKey,
+    raster_pipe: &SnoozyRef<RasterPipeline>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let raster_pipe = ctx.get(raster_pipe).await?;
 
-    let mut flattened_uniforms: HashMap<String, ResolvedShaderUniformPayload> = HashMap::new();
-    flatten_uniforms(uniforms, &mut |e| {
-        if let FlattenedUniformEvent::SetUniform { name, payload } = e {
-            flattened_uniforms.insert(name, payload);
-        }
-    });
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    let load_op = take_output_load_op(&mut uniforms);
+    let pass_name = take_op_tags(&mut uniforms).tagged_name("mesh_raster");
+    ctx.set_debug_name(&pass_name);
+
+    if load_op == OutputLoadOp::Load && !raster_pipe.input_attachments.is_empty() {
+        bail!(
+            "{}: passes reading `subpassInput`s can't load their previous output",
+            pass_name
+        );
+    }
 
-    let mut uniform_source = TrackedUniformParamSource {
-        uniforms: flattened_uniforms,
-        requested: HashSet::new(),
+    let (output_tex, load_op) = match load_op {
+        OutputLoadOp::Load => output_load_op::persistent_output_texture(&pass_name, *key),
+        // Possibly read by a merged pass, see `try_merge_raster_pass`
+        load_op => (
+            crate::backend::texture::create_texture(key.with_input_attachment()),
+            load_op,
+        ),
     };
+    uniforms.push(ResolvedShaderUniformHolder {
+        name: "outputTex".to_owned(),
+        payload: ResolvedShaderUniformPayload {
+            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
+            warn_if_unreferenced: false,
+        },
+    });
 
-    let (descriptor_sets, ds_update_result) = unsafe {
-        let descriptor_sets = {
-            let layout_info = &cs.descriptor_set_layout_info;
-            let descriptor_pool = vk_frame.descriptor_pool.lock().unwrap();
-            let dynamic_sets = vk.device.allocate_descriptor_sets(
-                &vk::DescriptorSetAllocateInfo::builder()
-                    .descriptor_pool(*descriptor_pool)
-                    .set_layouts(&layout_info.dynamic_layouts)
-                    .build(),
-            )?;
-            drop(descriptor_pool);
+    //println!("---- raster_tex: ----");
 
-            let mut sets = vec![None; layout_info.all_layouts.len()];
-            for (src, dst) in layout_info.dynamic_layout_indices.iter().enumerate() {
-                sets[*dst] = Some(dynamic_sets[src]);
-            }
+    if dry_run::is_dry_run_enabled(&ctx).await? {
+        validate_raster_dry_run(&pass_name, &[&*raster_pipe], key, uniforms)?;
+        return Ok(output_tex);
+    }
 
-            sets
-        };
+    let vk_frame = vk_state().current_frame();
+
+    // Recorded once the command buffer is next locked by anything else, or once the next
+    // pass can't be merged into its render pass.
+    let cb = vk_frame.command_buffer.lock_without_flush().unwrap();
+    try_merge_raster_pass(
+        cb.cb,
+        PendingRasterPass {
+            pass_name,
+            key: *key,
+            raster_pipe,
+            uniforms,
+            output_tex: output_tex.clone(),
+            load_op,
+        },
+    );
 
-        let ds_update_result = update_descriptor_sets(
-            &vk.device,
-            std::iter::once(&cs.spirv_reflection),
-            &descriptor_sets,
-            &mut uniform_source,
-        )
-        .unwrap();
+    Ok(output_tex)
+}
 
-        (descriptor_sets, ds_update_result)
-    };
+// A `raster_tex` pass whose recording is deferred, so that the passes following it
+// can be merged into its render pass.
+struct PendingRasterPass {
+    pass_name: String,
+    key: TextureKey,
+    raster_pipe: Arc<RasterPipeline>,
+    uniforms: Vec<ResolvedShaderUniformHolder>,
+    output_tex: Texture,
+    load_op: OutputLoadOp,
+}
 
-    let cb = vk_frame.command_buffer.lock().unwrap();
-    let cb: vk::CommandBuffer = cb.cb;
+impl PendingRasterPass {
+    // The texture bound to the pipeline's `subpassInput`, if it has one
+    fn input_attachment(&self) -> Result<Option<&Texture>> {
+        let name = match self.raster_pipe.input_attachments.as_slice() {
+            [] => return Ok(None),
+            [name] => name,
+            _ => bail!(
+                "{}: only one `subpassInput` per raster pass is supported",
+                self.pass_name
+            ),
+        };
 
-    unsafe {
-        for output in outputs {
-            match &output.resource {
-                ComputeOutputResource::Texture(texture) => {
-                    if output.discard {
-                        record_image_barrier(
-                            &vk.device,
-                            cb,
-                            ImageBarrier::new(
-                                texture.image,
-                                vk_sync::AccessType::Nothing,
-                                vk_sync::AccessType::ComputeShaderWrite,
-                            )
-                            .with_discard(true),
-                        );
-                    } else {
-                        record_image_barrier(
-                            &vk.device,
-                            cb,
-                            ImageBarrier::new(
-                                texture.image,
-                                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-                                vk_sync::AccessType::ComputeShaderWrite,
-                            ),
-                        );
-                    }
-                }
-                ComputeOutputResource::Buffer(_) => {
-                    //todo!();
+        for uniform in self.uniforms.iter() {
+            match &uniform.payload.value {
+                ResolvedShaderUniformValue::Texture(tex) if uniform.name == *name => {
+                    return Ok(Some(tex))
                 }
+                _ => {}
             }
         }
 
-        let mut descriptor_sets = descriptor_sets;
+        bail!(
+            "{}: the `subpassInput` {} must be bound to a texture in the outermost scope",
+            self.pass_name,
+            name
+        )
+    }
+}
 
-        for idx in ds_update_result.all_buffers_descriptor_set_idx.iter() {
-            descriptor_sets[*idx] = Some(vk_state.bindless_buffers_descriptor_set);
-        }
+lazy_static! {
+    // Consecutive `raster_tex` passes, each one reading the previous one's output
+    // via `subpassInput`. Flushed by `FrameCommandBuffer::lock`.
+    static ref PENDING_RASTER_PASSES: Mutex<Vec<PendingRasterPass>> = Mutex::new(Vec::new());
+    // Keyed by the pipelines of the merged passes, and whether the first one
+    // reads an external input attachment
+    static ref MERGED_RASTER_PIPELINES: Mutex<HashMap<(Vec<u64>, bool), Arc<RasterChainPipeline>>> =
+        Mutex::new(HashMap::new());
+}
 
-        for idx in ds_update_result.all_textures_descriptor_set_idx.iter() {
-            descriptor_sets[*idx] = Some(vk_state.bindless_images_descriptor_set);
+fn collect_uniform_images(uniforms: &[ResolvedShaderUniformHolder], images: &mut Vec<vk::Image>) {
+    for uniform in uniforms {
+        match &uniform.payload.value {
+            ResolvedShaderUniformValue::Texture(tex) => images.push(tex.image),
+            ResolvedShaderUniformValue::RwTexture(tex) => images.push(tex.image),
+            ResolvedShaderUniformValue::Bundle(bundle) => collect_uniform_images(bundle, images),
+            _ => {}
         }
+    }
+}
 
-        let descriptor_sets: Vec<_> = descriptor_sets.into_iter().map(Option::unwrap).collect();
+// A pass can become the next subpass of the pending render pass if its `subpassInput`
+// reads the last pending output, and it uses no other pending output, as those are
+// attachments of the same render pass. Only the first subpass has depth, so the rest
+// can't depth test.
+fn can_merge_raster_pass(pending: &[PendingRasterPass], pass: &PendingRasterPass) -> bool {
+    let tail = match pending.last() {
+        Some(tail) => tail,
+        None => return false,
+    };
 
-        vk.device
-            .cmd_bind_pipeline(cb, vk::PipelineBindPoint::COMPUTE, cs.pipeline.pipeline);
-        vk.device.cmd_bind_descriptor_sets(
-            cb,
-            vk::PipelineBindPoint::COMPUTE,
-            cs.pipeline.pipeline_layout,
-            0,
-            &descriptor_sets,
-            &ds_update_result.dynamic_offsets,
-        );
+    if tail.load_op == OutputLoadOp::Load
+        || pass.load_op == OutputLoadOp::Load
+        || pass.raster_pipe.desc.depth_test
+        || (pass.key.width, pass.key.height) != (tail.key.width, tail.key.height)
+    {
+        return false;
+    }
 
-        let query_id = crate::gpu_profiler::create_gpu_query(&cs.name);
-        let vk_query_idx = vk_frame.profiler_data.get_query_id(query_id);
+    match pass.input_attachment() {
+        Ok(Some(input)) if input.image == tail.output_tex.image => {}
+        _ => return false,
+    }
 
-        vk.device.cmd_write_timestamp(
-            cb,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk_frame.profiler_data.query_pool,
-            vk_query_idx * 2 + 0,
-        );
+    let mut images = Vec::new();
+    collect_uniform_images(&pass.uniforms, &mut images);
+    images
+        .iter()
+        .filter(|image| pending.iter().any(|p| p.output_tex.image == **image))
+        .count()
+        == 1
+}
 
-        if let Some((indirect_buf, indirect_off)) = indirect_args {
-            vk.device
-                .cmd_dispatch_indirect(cb, indirect_buf.buffer, indirect_off as u64);
-        } else {
-            vk.device.cmd_dispatch(
-                cb,
-                (thread_count[0] + cs.local_size.0 - 1) / cs.local_size.0,
-                (thread_count[1] + cs.local_size.1 - 1) / cs.local_size.1,
-                (thread_count[2] + cs.local_size.2 - 1) / cs.local_size.2,
-            );
-        }
+// Appends `pass` to the pending render pass if possible. Otherwise the pending passes
+// get recorded into `cb` first, and `pass` starts a new render pass.
+fn try_merge_raster_pass(cb: vk::CommandBuffer, pass: PendingRasterPass) {
+    let mut pending = PENDING_RASTER_PASSES.lock().unwrap();
+    if !pending.is_empty() && !can_merge_raster_pass(&pending, &pass) {
+        record_raster_passes(cb, std::mem::take(&mut *pending));
+    }
+    pending.push(pass);
+}
 
-        vk.device.cmd_write_timestamp(
-            cb,
-            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk_frame.profiler_data.query_pool,
-            vk_query_idx * 2 + 1,
-        );
+// Called with the frame command buffer locked, before anything else gets recorded into it.
+pub(crate) fn flush_pending_raster_passes(cb: vk::CommandBuffer) {
+    let pending = std::mem::take(&mut *PENDING_RASTER_PASSES.lock().unwrap());
+    if !pending.is_empty() {
+        record_raster_passes(cb, pending);
+    }
+}
 
-        for output in outputs {
-            match &output.resource {
-                ComputeOutputResource::Texture(texture) => {
-                    record_image_barrier(
-                        &vk.device,
-                        cb,
-                        ImageBarrier::new(
-                            texture.image,
-                            vk_sync::AccessType::ComputeShaderWrite,
-                            vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
-                        ),
-                    );
-                }
-                ComputeOutputResource::Buffer(_) => {
-                    let global_barrier = vk_sync::GlobalBarrier {
-                        previous_accesses: &[vk_sync::AccessType::ComputeShaderWrite],
-                        next_accesses: &[vk_sync::AccessType::General],
-                    };
+// Errors belong to passes which have already returned, so they are reported as warnings.
+fn record_raster_passes(cb: vk::CommandBuffer, passes: Vec<PendingRasterPass>) {
+    let pass_names: Vec<String> = passes.iter().map(|p| p.pass_name.clone()).collect();
+    if let Err(err) = record_raster_passes_impl(cb, passes) {
+        crate::rtoy_show_warning(format!("{}: {}", pass_names.join(" | "), err));
+    }
+}
 
-                    vk_sync::cmd::pipeline_barrier(
-                        vk.device.fp_v1_0(),
-                        cb,
-                        Some(global_barrier),
-                        &[],
-                        &[],
-                    );
-                }
-            }
+fn record_raster_passes_impl(cb: vk::CommandBuffer, passes: Vec<PendingRasterPass>) -> Result<()> {
+    let external_input = passes[0].input_attachment()?.cloned();
+    if let Some(input) = external_input.as_ref() {
+        if !input.key.input_attachment {
+            bail!("the `subpassInput` must be bound to the output of a `raster_tex` pass");
+        }
+        if (input.key.width, input.key.height) != (passes[0].key.width, passes[0].key.height) {
+            bail!("the `subpassInput` must have the same size as the output");
         }
     }
 
-    uniform_source.report_unreferenced_uniform_warnings(&cs.name);
-
-    for output in outputs {
-        if let ComputeOutputResource::Texture(texture) = &output.resource {
-            gpu_debugger::report_texture(&cs.name, texture.view);
-            break;
-        }
+    if passes.len() == 1 && external_input.is_none() {
+        return record_raster_pass(cb, passes.into_iter().next().unwrap());
     }
 
-    Ok(())
-}
+    let chain = get_or_create_merged_raster_pipeline(&passes, external_input.is_some())?;
+    let vk = vk();
 
-#[snoozy]
-pub async fn compute_tex_snoozy(
-    ctx: Context,
-    key: &TextureKey,
-    cs: &SnoozyRef<ComputeShader>,
-    uniforms: &Vec<ShaderUniformHolder>,
-) -> Result<Texture> {
-    let output_tex = crate::backend::texture::create_texture(*key);
+    let attachments: Vec<&Texture> = passes.iter().map(|p| &p.output_tex).collect();
+    let attachment_images: Vec<vk::Image> = attachments.iter().map(|t| t.image).collect();
+    let load_ops: Vec<OutputLoadOp> = passes.iter().map(|p| p.load_op).collect();
+    let merged_name = passes
+        .iter()
+        .map(|p| p.pass_name.as_str())
+        .collect::<Vec<_>>()
+        .join(" | ");
 
-    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
-    uniforms.push(ResolvedShaderUniformHolder {
-        name: "outputTex".to_owned(),
-        payload: ResolvedShaderUniformPayload {
-            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
-            warn_if_unreferenced: true,
-        },
-    });
+    unsafe {
+        vk.begin_debug_label(cb, &merged_name);
+        for pass in passes.iter() {
+            vk.set_debug_object_name(pass.output_tex.image, &pass.pass_name);
+            record_uniform_texture_transitions(&vk.device, cb, &pass.uniforms, &attachment_images);
+        }
 
-    compute_common(
-        ctx,
-        [key.width, key.height, key.depth],
-        cs,
-        uniforms,
-        &[ComputeOutput::new_texture(&output_tex)],
-        None,
-    )
-    .await?;
+        // Imageless framebuffers are only created for single-pass pipelines.
+        begin_raster_render_pass(
+            cb,
+            chain.render_pass,
+            vk::Framebuffer::null(),
+            &passes[0].key,
+            &attachments,
+            &load_ops,
+            external_input.as_ref(),
+        )?;
+    }
 
-    Ok(output_tex)
-}
+    let mut recorded = Vec::with_capacity(passes.len());
+    for (subpass, (pass, stage)) in passes.into_iter().zip(chain.stages.iter()).enumerate() {
+        unsafe {
+            if subpass > 0 {
+                vk.device.cmd_next_subpass(cb, vk::SubpassContents::INLINE);
+            }
+            vk.begin_debug_label(cb, &pass.pass_name);
+        }
+        let uniform_source =
+            record_raster_mesh_draws(cb, stage, &pass.key, &pass.pass_name, pass.uniforms);
+        unsafe {
+            vk.end_debug_label(cb);
+        }
+        recorded.push((
+            pass.pass_name,
+            pass.key,
+            pass.output_tex,
+            stage,
+            uniform_source,
+        ));
+    }
 
-#[snoozy]
-pub async fn compute_buf_snoozy(
-    ctx: Context,
-    key: &BufferKey,
-    thread_count: &[u32; 3],
-    cs: &SnoozyRef<ComputeShader>,
-    uniforms: &Vec<ShaderUniformHolder>,
-) -> Result<Buffer> {
-    let output_buf = crate::backend::buffer::create_buffer(*key);
+    unsafe {
+        vk.device.cmd_end_render_pass(cb);
+        vk.end_debug_label(cb);
 
-    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
-    uniforms.push(ResolvedShaderUniformHolder {
-        name: "outputBuf".to_owned(),
-        payload: ResolvedShaderUniformPayload {
-            value: ResolvedShaderUniformValue::RwBuffer(output_buf.clone()),
-            warn_if_unreferenced: true,
-        },
-    });
+        for image in attachment_images.iter() {
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    *image,
+                    vk_sync::AccessType::ColorAttachmentWrite,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                ),
+            );
+        }
+    }
 
-    compute_common(
-        ctx,
-        *thread_count,
-        cs,
-        uniforms,
-        &[ComputeOutput::new_buffer(&output_buf)],
-        None,
-    )
-    .await?;
+    // Only the first subpass uses depth
+    gpu_debugger::capture_raster_depth(cb, &recorded[0].0, &recorded[0].1);
+    for (pass_name, key, output_tex, stage, uniform_source) in recorded {
+        resource_lifetime::record_use(output_tex.allocation_id(), &pass_name);
+        uniform_source.report_resource_uses(&pass_name);
+        uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+        let workload_id = format!(
+            "{} ({}, merged into {}) -> {:?}",
+            pass_name, stage.name, merged_name, key
+        );
+        gpu_workload::report_pass_workload(
+            &workload_id,
+            &pass_name,
+            key.width as u64 * key.height as u64,
+        );
+        gpu_debugger::report_texture(&pass_name, &output_tex);
+    }
 
-    Ok(output_buf)
+    Ok(())
 }
 
-#[snoozy]
-pub async fn recompute_tex_snoozy(
-    mut ctx: Context,
-    output_tex: &SnoozyRef<Texture>,
-    cs: &SnoozyRef<ComputeShader>,
-    uniforms: &Vec<ShaderUniformHolder>,
-) -> Result<Texture> {
-    let output_tex = (*ctx.get(output_tex).await?).clone();
+// Recompiles the pipelines of `passes` for subpasses of one render pass.
+fn get_or_create_merged_raster_pipeline(
+    passes: &[PendingRasterPass],
+    external_input: bool,
+) -> Result<Arc<RasterChainPipeline>> {
+    let cache_key = (
+        passes
+            .iter()
+            .map(|p| p.raster_pipe.pipeline.as_raw())
+            .collect::<Vec<_>>(),
+        external_input,
+    );
+    if let Some(chain) = MERGED_RASTER_PIPELINES.lock().unwrap().get(&cache_key) {
+        return Ok(chain.clone());
+    }
 
-    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
-    uniforms.push(ResolvedShaderUniformHolder {
-        name: "outputTex".to_owned(),
-        payload: ResolvedShaderUniformPayload {
-            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
-            warn_if_unreferenced: true,
-        },
+    let render_pass = create_raster_render_pass(
+        passes.len(),
+        vk::AttachmentLoadOp::CLEAR,
+        true,
+        external_input,
+    )?;
+
+    let mut stages = Vec::with_capacity(passes.len());
+    for (subpass, pass) in passes.iter().enumerate() {
+        let shaders: Vec<&RasterSubShader> = pass.raster_pipe.shaders.iter().collect();
+        stages.push(unsafe {
+            create_raster_pipeline(
+                &shaders,
+                render_pass,
+                subpass as u32,
+                1,
+                &pass.raster_pipe.desc,
+            )?
+        });
+    }
+
+    let chain = Arc::new(RasterChainPipeline {
+        render_pass,
+        stages,
     });
+    Ok(MERGED_RASTER_PIPELINES
+        .lock()
+        .unwrap()
+        .entry(cache_key)
+        .or_insert(chain)
+        .clone())
+}
 
-    let key = output_tex.key;
-    compute_common(
-        ctx,
-        [key.width, key.height, key.depth],
-        cs,
+// A single pass, in its own render pass.
+fn record_raster_pass(cb: vk::CommandBuffer, pass: PendingRasterPass) -> Result<()> {
+    let PendingRasterPass {
+        pass_name,
+        key,
+        raster_pipe,
         uniforms,
-        &[ComputeOutput::mutate_texture(&output_tex)],
-        None,
-    )
-    .await?;
+        output_tex,
+        load_op,
+    } = pass;
+    let key = &key;
+    let vk = vk();
 
-    Ok(output_tex)
+    unsafe {
+        vk.begin_debug_label(cb, &pass_name);
+        vk.set_debug_object_name(output_tex.image, &pass_name);
+
+        record_uniform_texture_transitions(&vk.device, cb, &uniforms, &[output_tex.image]);
+        begin_raster_render_pass(
+            cb,
+            if load_op == OutputLoadOp::Load {
+                raster_pipe.load_render_pass
+            } else {
+                raster_pipe.render_pass
+            },
+            raster_pipe.framebuffer,
+            key,
+            &[&output_tex],
+            &[load_op],
+            None,
+        )?;
+    }
+
+    let uniform_source = record_raster_mesh_draws(cb, &raster_pipe, key, &pass_name, uniforms);
+
+    unsafe {
+        vk.device.cmd_end_render_pass(cb);
+        vk.end_debug_label(cb);
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                output_tex.image,
+                vk_sync::AccessType::ColorAttachmentWrite,
+                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            ),
+        );
+    };
+
+    gpu_debugger::capture_raster_depth(cb, &pass_name, key);
+    resource_lifetime::record_use(output_tex.allocation_id(), &pass_name);
+    uniform_source.report_resource_uses(&pass_name);
+    uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+    let workload_id = format!("{} ({}) -> {:?}", pass_name, raster_pipe.name, key);
+    gpu_workload::report_pass_workload(
+        &workload_id,
+        &pass_name,
+        key.width as u64 * key.height as u64,
+    );
+    gpu_debugger::report_texture(&pass_name, &output_tex);
+
+    Ok(())
 }
 
+// Like `raster_tex`, but rendering to one target per key, with a pipeline from
+// `make_raster_pipeline_n`. Targets are named `outputTex0` to `outputTexN`, and must all
+// have the same size.
 #[snoozy]
-pub async fn recompute_indirect_tex_snoozy(
+pub async fn raster_tex_n_snoozy(
     mut ctx: Context,
-    output_tex: &SnoozyRef<Texture>,
-    cs: &SnoozyRef<ComputeShader>,
+    keys: &Vec<TextureKey>,
+    raster_pipe: &SnoozyRef<RasterPipeline>,
     uniforms: &Vec<ShaderUniformHolder>,
-    indirect_buf: &SnoozyRef<Buffer>,
-    indirect_off: &u64,
-) -> Result<Texture> {
-    let output_tex = (*ctx.get(output_tex).await?).clone();
-    let indirect_buf = (*ctx.get(indirect_buf).await?).clone();
+) -> Result<ComputeTexOutputs> {
+    let key = *keys
+        .first()
+        .ok_or_else(|| format_err!("raster_tex_n needs at least one render target"))?;
+    if keys
+        .iter()
+        .any(|k| k.width != key.width || k.height != key.height)
+    {
+        bail!("The render targets of raster_tex_n must all have the same size");
+    }
+
+    let raster_pipe = ctx.get(raster_pipe).await?;
+    let outputs: Vec<(String, Texture)> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            (
+                format!("outputTex{}", i),
+                crate::backend::texture::create_texture(*key),
+            )
+        })
+        .collect();
 
     let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
-    uniforms.push(ResolvedShaderUniformHolder {
-        name: "outputTex".to_owned(),
-        payload: ResolvedShaderUniformPayload {
-            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
-            warn_if_unreferenced: true,
-        },
-    });
+    let pass_name = take_op_tags(&mut uniforms).tagged_name("mesh_raster_mrt");
+    ctx.set_debug_name(&pass_name);
+    for (name, tex) in outputs.iter() {
+        uniforms.push(ResolvedShaderUniformHolder {
+            name: name.clone(),
+            payload: ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::RwTexture(tex.clone()),
+                warn_if_unreferenced: false,
+            },
+        });
+    }
 
-    let key = output_tex.key;
-    compute_common(
-        ctx,
-        [key.width, key.height, key.depth],
-        cs,
-        uniforms,
-        &[ComputeOutput::mutate_texture(&output_tex)],
-        Some((indirect_buf, *indirect_off)),
-    )
-    .await?;
+    if dry_run::is_dry_run_enabled(&ctx).await? {
+        validate_raster_dry_run(&pass_name, &[&*raster_pipe], &key, uniforms)?;
+        return Ok(ComputeTexOutputs(outputs));
+    }
 
-    Ok(output_tex)
+    let (vk, vk_state) = vk_all();
+    let vk_frame = vk_state.current_frame();
+
+    let cb = vk_frame.command_buffer.lock().unwrap();
+    let cb: vk::CommandBuffer = cb.cb;
+
+    unsafe {
+        vk.begin_debug_label(cb, &pass_name);
+        for (_, tex) in outputs.iter() {
+            vk.set_debug_object_name(tex.image, &pass_name);
+        }
+
+        let attachments: Vec<&Texture> = outputs.iter().map(|(_, tex)| tex).collect();
+        let attachment_images: Vec<vk::Image> = attachments.iter().map(|t| t.image).collect();
+        record_uniform_texture_transitions(&vk.device, cb, &uniforms, &attachment_images);
+        begin_raster_render_pass(
+            cb,
+            raster_pipe.render_pass,
+            vk::Framebuffer::null(),
+            &key,
+            &attachments,
+            &vec![OutputLoadOp::Discard; attachments.len()],
+            None,
+        )?;
+    }
+
+    let uniform_source = record_raster_mesh_draws(cb, &raster_pipe, &key, &pass_name, uniforms);
+
+    unsafe {
+        vk.device.cmd_end_render_pass(cb);
+        vk.end_debug_label(cb);
+
+        for (_, tex) in outputs.iter() {
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    tex.image,
+                    vk_sync::AccessType::ColorAttachmentWrite,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                ),
+            );
+        }
+    };
+
+    gpu_debugger::capture_raster_depth(cb, &pass_name, &key);
+    for (_, tex) in outputs.iter() {
+        resource_lifetime::record_use(tex.allocation_id(), &pass_name);
+    }
+    uniform_source.report_resource_uses(&pass_name);
+    uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+    let workload_id = format!("{} ({}) -> {:?}", pass_name, raster_pipe.name, key);
+    gpu_workload::report_pass_workload(
+        &workload_id,
+        &pass_name,
+        key.width as u64 * key.height as u64,
+    );
+    gpu_debugger::report_texture(&pass_name, &outputs[0].1);
+
+    Ok(ComputeTexOutputs(outputs))
 }
 
+// Renders just the depth of meshes, e.g. for shadow maps, with a pipeline from
+// `make_raster_depth_pipeline`. `key` must use the format of the pipeline. The result
+// can be sampled as a regular texture, or with the pipeline's `DepthCompare::sampler_name`.
 #[snoozy]
-pub async fn raster_tex_snoozy(
+pub async fn raster_depth_tex_snoozy(
     mut ctx: Context,
     key: &TextureKey,
     raster_pipe: &SnoozyRef<RasterPipeline>,
     uniforms: &Vec<ShaderUniformHolder>,
 ) -> Result<Texture> {
-    let output_tex = crate::backend::texture::create_texture(*key);
     let raster_pipe = ctx.get(raster_pipe).await?;
+    let desc = raster_pipe.depth_only.ok_or_else(|| {
+        format_err!("raster_depth_tex needs a pipeline from make_raster_depth_pipeline")
+    })?;
+    if key.format != desc.format {
+        bail!(
+            "raster_depth_tex got a {:?} key for a {:?} pipeline",
+            vk::Format::from_raw(key.format),
+            vk::Format::from_raw(desc.format)
+        );
+    }
 
     let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
-    uniforms.push(ResolvedShaderUniformHolder {
-        name: "outputTex".to_owned(),
-        payload: ResolvedShaderUniformPayload {
-            value: ResolvedShaderUniformValue::RwTexture(output_tex.clone()),
-            warn_if_unreferenced: false,
-        },
-    });
+    let pass_name = take_op_tags(&mut uniforms).tagged_name("mesh_raster_depth");
+    ctx.set_debug_name(&pass_name);
 
-    //println!("---- raster_tex: ----");
+    let output_tex = crate::backend::texture::create_texture(*key);
+
+    if dry_run::is_dry_run_enabled(&ctx).await? {
+        validate_raster_dry_run(&pass_name, &[&*raster_pipe], key, uniforms)?;
+        return Ok(output_tex);
+    }
 
     let (vk, vk_state) = vk_all();
     let vk_frame = vk_state.current_frame();
@@ -1833,61 +5736,31 @@ pub async fn raster_tex_snoozy(
     let cb: vk::CommandBuffer = cb.cb;
 
     unsafe {
-        record_image_barrier(
+        vk.begin_debug_label(cb, &pass_name);
+        vk.set_debug_object_name(output_tex.image, &pass_name);
+
+        record_uniform_texture_transitions(&vk.device, cb, &uniforms, &[output_tex.image]);
+        record_image_aspect_barrier(
             &vk.device,
             cb,
+            vk::ImageAspectFlags::DEPTH,
             ImageBarrier::new(
                 output_tex.image,
-                vk_sync::AccessType::Nothing,
-                vk_sync::AccessType::ColorAttachmentWrite,
+                tracked_image_access(output_tex.image),
+                vk_sync::AccessType::DepthStencilAttachmentWrite,
             )
             .with_discard(true),
         );
 
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 0.0],
-                },
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 0.0,
-                    stencil: 0,
-                },
+        let framebuffer =
+            create_frame_framebuffer(raster_pipe.render_pass, key, &[output_tex.rt_view])?;
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: desc.compare.clear_depth(),
+                stencil: 0,
             },
-        ];
-
-        let texture_attachments = [output_tex.rt_view, vk_state.depth_image_view];
-        let mut pass_attachment_desc =
-            vk::RenderPassAttachmentBeginInfoKHR::builder().attachments(&texture_attachments);
-
-        const USE_IMAGELESS: bool = false;
-
-        let framebuffer = if USE_IMAGELESS {
-            raster_pipe.framebuffer
-        } else {
-            // HACK; must not do this, but validation layers are broken with IMAGELESS_KHR
-            let fbo_desc = vk::FramebufferCreateInfo::builder()
-                .render_pass(raster_pipe.render_pass)
-                .width(key.width as _)
-                .height(key.height as _)
-                .layers(1)
-                .attachments(&texture_attachments);
-            let fbo = vk.device.create_framebuffer(&fbo_desc, None)?;
-
-            vk_frame
-                .frame_cleanup
-                .lock()
-                .unwrap()
-                .push(Box::new(move |vk| {
-                    vk.device.destroy_framebuffer(fbo, None);
-                }));
-
-            fbo
-        };
-
-        let mut pass_begin_desc = vk::RenderPassBeginInfo::builder()
+        }];
+        let pass_begin_desc = vk::RenderPassBeginInfo::builder()
             .render_pass(raster_pipe.render_pass)
             .framebuffer(framebuffer)
             .render_area(vk::Rect2D {
@@ -1898,151 +5771,127 @@ pub async fn raster_tex_snoozy(
                 },
             })
             .clear_values(&clear_values);
-
-        if USE_IMAGELESS {
-            pass_begin_desc = pass_begin_desc.push_next(&mut pass_attachment_desc)
-        }
-
         vk.device
             .cmd_begin_render_pass(cb, &pass_begin_desc, vk::SubpassContents::INLINE);
     }
 
-    let flush_draw = |uniform_source: &mut TrackedUniformParamSource| -> Result<()> {
-        unsafe {
-            let (descriptor_sets, ds_update_result) = {
-                let descriptor_sets = {
-                    let layout_info = &raster_pipe.descriptor_set_layout_info;
-                    let descriptor_pool = vk_frame.descriptor_pool.lock().unwrap();
-                    let dynamic_sets = vk.device.allocate_descriptor_sets(
-                        &vk::DescriptorSetAllocateInfo::builder()
-                            .descriptor_pool(*descriptor_pool)
-                            .set_layouts(&layout_info.dynamic_layouts)
-                            .build(),
-                    )?;
-                    drop(descriptor_pool);
-
-                    let mut sets = vec![None; layout_info.all_layouts.len()];
-                    for (src, dst) in layout_info.dynamic_layout_indices.iter().enumerate() {
-                        sets[*dst] = Some(dynamic_sets[src]);
-                    }
-
-                    sets
-                };
+    let uniform_source = record_raster_mesh_draws(cb, &raster_pipe, key, &pass_name, uniforms);
 
-                let ds_update_result = update_descriptor_sets(
-                    &vk.device,
-                    raster_pipe.shader_refl.iter(),
-                    &descriptor_sets,
-                    uniform_source,
-                )
-                .unwrap();
+    unsafe {
+        vk.device.cmd_end_render_pass(cb);
+        vk.end_debug_label(cb);
 
-                (descriptor_sets, ds_update_result)
-            };
+        record_image_aspect_barrier(
+            &vk.device,
+            cb,
+            vk::ImageAspectFlags::DEPTH,
+            ImageBarrier::new(
+                output_tex.image,
+                vk_sync::AccessType::DepthStencilAttachmentWrite,
+                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            ),
+        );
+    };
 
-            let mut descriptor_sets = descriptor_sets;
+    resource_lifetime::record_use(output_tex.allocation_id(), &pass_name);
+    uniform_source.report_resource_uses(&pass_name);
+    uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+    let workload_id = format!("{} ({}) -> {:?}", pass_name, raster_pipe.name, key);
+    gpu_workload::report_pass_workload(
+        &workload_id,
+        &pass_name,
+        key.width as u64 * key.height as u64,
+    );
+    gpu_debugger::report_texture(&pass_name, &output_tex);
 
-            for idx in ds_update_result.all_buffers_descriptor_set_idx.iter() {
-                descriptor_sets[*idx] = Some(vk_state.bindless_buffers_descriptor_set);
-            }
+    Ok(output_tex)
+}
 
-            for idx in ds_update_result.all_textures_descriptor_set_idx.iter() {
-                descriptor_sets[*idx] = Some(vk_state.bindless_images_descriptor_set);
+// Like `raster_tex`, but runs all stages of a `RasterChainPipeline` within one render pass.
+// Stages after the first one draw a fullscreen triangle, and see the uniforms
+// of the outermost scope, plus `inputTex` bound to the previous stage's output.
+#[snoozy]
+pub async fn raster_chain_tex_snoozy(
+    mut ctx: Context,
+    key: &TextureKey,
+    chain: &SnoozyRef<RasterChainPipeline>,
+    uniforms: &Vec<ShaderUniformHolder>,
+) -> Result<Texture> {
+    let chain = ctx.get(chain).await?;
+    let stage_textures: Vec<Texture> = chain
+        .stages
+        .iter()
+        .enumerate()
+        .map(|(stage_idx, _)| {
+            // Only intermediate stages are read as input attachments
+            if stage_idx + 1 < chain.stages.len() {
+                crate::backend::texture::create_texture(key.with_input_attachment())
+            } else {
+                crate::backend::texture::create_texture(*key)
             }
+        })
+        .collect();
+    let output_tex = stage_textures.last().unwrap().clone();
 
-            let descriptor_sets: Vec<_> = descriptor_sets.into_iter().map(Option::unwrap).collect();
+    let mut uniforms = resolve(ctx.clone(), uniforms.clone()).await?;
+    let pass_name = take_op_tags(&mut uniforms).tagged_name("mesh_raster_chain");
+    ctx.set_debug_name(&pass_name);
 
-            vk.device
-                .cmd_bind_pipeline(cb, vk::PipelineBindPoint::GRAPHICS, raster_pipe.pipeline);
+    if dry_run::is_dry_run_enabled(&ctx).await? {
+        let stages: Vec<&RasterPipeline> = chain.stages.iter().collect();
+        validate_raster_dry_run(&pass_name, &stages, key, uniforms)?;
+        return Ok(output_tex);
+    }
 
-            vk.device.cmd_set_viewport(
-                cb,
-                0,
-                &[vk::Viewport {
-                    x: 0.0,
-                    y: (key.height as f32),
-                    width: key.width as _,
-                    height: -(key.height as f32),
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                }],
-            );
-            vk.device.cmd_set_scissor(
-                cb,
-                0,
-                &[vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: vk::Extent2D {
-                        width: key.width as _,
-                        height: key.height as _,
-                    },
-                }],
-            );
+    let (vk, vk_state) = vk_all();
+    let vk_frame = vk_state.current_frame();
 
-            vk.device.cmd_bind_descriptor_sets(
-                cb,
-                vk::PipelineBindPoint::GRAPHICS,
-                raster_pipe.pipeline_layout,
-                0,
-                &descriptor_sets,
-                &ds_update_result.dynamic_offsets,
-            );
+    let cb = vk_frame.command_buffer.lock().unwrap();
+    let cb: vk::CommandBuffer = cb.cb;
 
-            Ok(())
+    unsafe {
+        vk.begin_debug_label(cb, &pass_name);
+        for tex in stage_textures.iter() {
+            vk.set_debug_object_name(tex.image, &pass_name);
         }
-    };
 
-    #[derive(Default)]
-    struct MeshDrawData {
-        index_buffer: Option<vk::Buffer>,
-        index_count: Option<u32>,
+        let attachments: Vec<&Texture> = stage_textures.iter().collect();
+        let attachment_images: Vec<vk::Image> = attachments.iter().map(|t| t.image).collect();
+        record_uniform_texture_transitions(&vk.device, cb, &uniforms, &attachment_images);
+        // Imageless framebuffers are only created for single-pass pipelines.
+        begin_raster_render_pass(
+            cb,
+            chain.render_pass,
+            vk::Framebuffer::null(),
+            key,
+            &attachments,
+            &vec![OutputLoadOp::Discard; attachments.len()],
+            None,
+        )?;
     }
 
-    let mut mesh_stack = vec![MeshDrawData::default()];
-
-    let flattened_uniforms: HashMap<String, ResolvedShaderUniformPayload> = HashMap::new();
-    let mut uniform_source = TrackedUniformParamSource {
-        uniforms: flattened_uniforms,
-        requested: HashSet::new(),
-    };
+    let mut uniform_source =
+        record_raster_mesh_draws(cb, &chain.stages[0], key, &pass_name, uniforms);
 
-    flatten_uniforms(uniforms, &mut |e| match e {
-        FlattenedUniformEvent::SetUniform { name, mut payload } => {
-            match payload.value {
-                ResolvedShaderUniformValue::Buffer(ref buf) if name == "mesh_index_buf" => {
-                    mesh_stack.last_mut().unwrap().index_buffer = Some(buf.buffer);
-                    payload.warn_if_unreferenced = false;
-                }
-                ResolvedShaderUniformValue::Uint32(value) if name == "mesh_index_count" => {
-                    mesh_stack.last_mut().unwrap().index_count = Some(value);
-                    payload.warn_if_unreferenced = false;
-                }
-                _ => {}
-            }
+    for (stage_idx, stage) in chain.stages.iter().enumerate().skip(1) {
+        uniform_source.uniforms.insert(
+            "inputTex".to_owned(),
+            ResolvedShaderUniformPayload {
+                value: ResolvedShaderUniformValue::Texture(stage_textures[stage_idx - 1].clone()),
+                warn_if_unreferenced: false,
+            },
+        );
 
-            uniform_source.uniforms.insert(name, payload);
-        }
-        FlattenedUniformEvent::EnterScope => {
-            mesh_stack.push(Default::default());
-        }
-        FlattenedUniformEvent::LeaveScope => {
-            let mesh = mesh_stack.pop().unwrap();
-            if let Some(index_count) = mesh.index_count {
-                if let Some(index_buffer) = mesh.index_buffer {
-                    unsafe {
-                        flush_draw(&mut uniform_source).expect("flush_draw");
-                        vk.device
-                            .cmd_bind_index_buffer(cb, index_buffer, 0, vk::IndexType::UINT32);
-                        vk.device.cmd_draw_indexed(cb, index_count as _, 1, 0, 0, 0);
-                        //println!("-------");
-                    }
-                }
-            }
+        unsafe {
+            vk.device.cmd_next_subpass(cb, vk::SubpassContents::INLINE);
+            bind_raster_pipeline(cb, stage, key, &mut uniform_source)?;
+            vk.device.cmd_draw(cb, 3, 1, 0, 0);
         }
-    });
+    }
 
     unsafe {
         vk.device.cmd_end_render_pass(cb);
+        vk.end_debug_label(cb);
 
         record_image_barrier(
             &vk.device,
@@ -2055,8 +5904,18 @@ pub async fn raster_tex_snoozy(
         );
     };
 
-    uniform_source.report_unreferenced_uniform_warnings("mesh_raster");
-    gpu_debugger::report_texture("mesh_raster", output_tex.view);
+    gpu_debugger::capture_raster_depth(cb, &pass_name, key);
+    resource_lifetime::record_use(output_tex.allocation_id(), &pass_name);
+    uniform_source.report_resource_uses(&pass_name);
+    uniform_source.report_unreferenced_uniform_warnings(&pass_name);
+    let stage_names: Vec<&str> = chain.stages.iter().map(|s| s.name.as_str()).collect();
+    let workload_id = format!("{} ({}) -> {:?}", pass_name, stage_names.join(" | "), key);
+    gpu_workload::report_pass_workload(
+        &workload_id,
+        &pass_name,
+        key.width as u64 * key.height as u64,
+    );
+    gpu_debugger::report_texture(&pass_name, &output_tex);
 
     Ok(output_tex)
 }
@@ -0,0 +1,274 @@
+// Performance A/B testing of two shader variants of the same pass, e.g. before and after
+// an optimization:
+//
+//   let tex = ab_compute_tex("blur".to_owned(), key, blur_old, blur_new, uniforms, mode);
+//
+// Both variants are timed by the GPU profiler, their passes tagged `ab=<test>/a` and
+// `ab=<test>/b`, and per-frame timings are collected into `shader_ab_results`. Once there
+// are enough samples, it tells whether `b` is faster or slower than `a`, with a 95%
+// confidence interval, rather than leaving it to eyeballed frame rates.
+//
+// Like workgroup autotuning, this only gathers samples from passes which get recorded
+// every frame, i.e. whose inputs change every frame.
+
+use crate::compare::{compare_tex, CompareMode};
+use crate::gpu_profiler;
+use crate::op_tags::op_tags;
+use crate::shader::{compute_tex, ComputeShader, ShaderUniformHolder};
+use crate::texture::{Texture, TextureKey};
+use snoozy::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+const AB_TAG: &str = "ab";
+// Per variant; older samples are dropped, so results follow recent changes
+const MAX_SAMPLE_COUNT: usize = 512;
+// Needed per variant before the comparison is considered meaningful
+const MIN_SAMPLE_COUNT: usize = 30;
+// Two-sided 95% confidence
+const Z_95: f64 = 1.96;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum ShaderAbMode {
+    // One variant per frame, switching every frame. Both run under the same conditions,
+    // but the output flickers if they differ.
+    AlternateFrames,
+    // Both variants every frame, the left half of the output showing `a`, and the right
+    // half `b`, converted to `R16G16B16A16_SFLOAT`. Handy for checking that the output
+    // didn't change; `b` may benefit from caches warmed up by `a` though.
+    SplitScreen,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderAbVariantStats {
+    pub sample_count: usize,
+    pub avg_ms: f64,
+    pub median_ms: f64,
+    pub std_dev_ms: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderAbResult {
+    pub name: String,
+    pub a: ShaderAbVariantStats,
+    pub b: ShaderAbVariantStats,
+    // 95% confidence interval of the difference of `b` over `a`, relative to `a`; e.g.
+    // (-0.12, -0.08) when `b` is 8% to 12% faster
+    pub relative_difference: (f64, f64),
+    // Whether both variants have enough samples, and the interval excludes zero
+    pub significant: bool,
+}
+
+impl ShaderAbResult {
+    pub fn describe(&self) -> String {
+        let (lo, hi) = self.relative_difference;
+        let verdict =
+            if self.a.sample_count < MIN_SAMPLE_COUNT || self.b.sample_count < MIN_SAMPLE_COUNT {
+                "collecting samples".to_owned()
+            } else if self.significant {
+                let faster = hi < 0.0;
+                format!(
+                    "b is {:.1}% to {:.1}% {}",
+                    lo.abs().min(hi.abs()) * 100.0,
+                    lo.abs().max(hi.abs()) * 100.0,
+                    if faster { "faster" } else { "slower" }
+                )
+            } else {
+                format!(
+                    "no significant difference ({:+.1}% to {:+.1}%)",
+                    lo * 100.0,
+                    hi * 100.0
+                )
+            };
+
+        format!(
+            "{}: a {:.3}ms ±{:.3}, b {:.3}ms ±{:.3} ({} / {} samples); {}",
+            self.name,
+            self.a.avg_ms,
+            self.a.std_dev_ms,
+            self.b.avg_ms,
+            self.b.std_dev_ms,
+            self.a.sample_count,
+            self.b.sample_count,
+            verdict
+        )
+    }
+}
+
+#[derive(Default)]
+struct ShaderAbTest {
+    samples: [VecDeque<f64>; 2],
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+struct ShaderAbState {
+    tests: HashMap<String, ShaderAbTest>,
+    frame_index: u64,
+}
+
+lazy_static! {
+    static ref SHADER_AB: Mutex<ShaderAbState> = Mutex::new(ShaderAbState {
+        tests: HashMap::new(),
+        frame_index: 0,
+    });
+}
+
+// Renders `key` with `cs_a` and `cs_b` as described at the top of this file. Both variants
+// get the same `uniforms`.
+#[snoozy]
+pub async fn ab_compute_tex_snoozy(
+    mut ctx: Context,
+    test: &String,
+    key: &TextureKey,
+    cs_a: &SnoozyRef<ComputeShader>,
+    cs_b: &SnoozyRef<ComputeShader>,
+    uniforms: &Vec<ShaderUniformHolder>,
+    mode: &ShaderAbMode,
+) -> Result<Texture> {
+    let variant_tex = |cs: &SnoozyRef<ComputeShader>, variant: &str| {
+        let mut uniforms = uniforms.clone();
+        let tag = format!("{}={}/{}", AB_TAG, test, variant);
+        uniforms.push(ShaderUniformHolder::new(
+            "ab_tags",
+            op_tags(&[tag.as_str()]),
+        ));
+        compute_tex(*key, cs.clone(), uniforms)
+    };
+
+    let use_b = {
+        let mut state = SHADER_AB.lock().unwrap();
+        let frame_index = state.frame_index;
+        let ab_test = state.tests.entry(test.clone()).or_default();
+        if *mode == ShaderAbMode::AlternateFrames {
+            // Re-evaluated every frame to switch between the variants
+            ab_test
+                .invalidation_triggers
+                .push(Box::new(ctx.get_invalidation_trigger()));
+        }
+        frame_index % 2 == 1
+    };
+
+    let tex = match mode {
+        ShaderAbMode::AlternateFrames if use_b => ctx.get(variant_tex(cs_b, "b")).await?,
+        ShaderAbMode::AlternateFrames => ctx.get(variant_tex(cs_a, "a")).await?,
+        ShaderAbMode::SplitScreen => {
+            let (a, b) = (variant_tex(cs_a, "a"), variant_tex(cs_b, "b"));
+            ctx.get(compare_tex(a, b, CompareMode::Wipe, 0.5)).await?
+        }
+    };
+
+    Ok((*tex).clone())
+}
+
+fn variant_stats(samples: &VecDeque<f64>) -> ShaderAbVariantStats {
+    let count = samples.len();
+    if count == 0 {
+        return ShaderAbVariantStats {
+            sample_count: 0,
+            avg_ms: 0.0,
+            median_ms: 0.0,
+            std_dev_ms: 0.0,
+        };
+    }
+
+    let avg = samples.iter().sum::<f64>() / count as f64;
+    let variance = if count > 1 {
+        samples.iter().map(|s| (s - avg) * (s - avg)).sum::<f64>() / (count - 1) as f64
+    } else {
+        0.0
+    };
+
+    let mut sorted: Vec<f64> = samples.iter().copied().collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    ShaderAbVariantStats {
+        sample_count: count,
+        avg_ms: avg,
+        median_ms: sorted[count / 2],
+        std_dev_ms: variance.sqrt(),
+    }
+}
+
+// Welch's comparison of the means, which doesn't assume the variants vary equally. With
+// the sample counts involved, the normal approximation of the t-distribution is plenty.
+fn compare_variants(name: &str, test: &ShaderAbTest) -> ShaderAbResult {
+    let a = variant_stats(&test.samples[0]);
+    let b = variant_stats(&test.samples[1]);
+
+    let enough_samples = a.sample_count >= MIN_SAMPLE_COUNT && b.sample_count >= MIN_SAMPLE_COUNT;
+    let (relative_difference, significant) = if enough_samples && a.avg_ms > 0.0 {
+        let std_err = (a.std_dev_ms * a.std_dev_ms / a.sample_count as f64
+            + b.std_dev_ms * b.std_dev_ms / b.sample_count as f64)
+            .sqrt();
+        let diff = b.avg_ms - a.avg_ms;
+        let (lo, hi) = (diff - Z_95 * std_err, diff + Z_95 * std_err);
+        ((lo / a.avg_ms, hi / a.avg_ms), lo > 0.0 || hi < 0.0)
+    } else {
+        ((0.0, 0.0), false)
+    };
+
+    ShaderAbResult {
+        name: name.to_owned(),
+        a,
+        b,
+        relative_difference,
+        significant,
+    }
+}
+
+// Comparisons of all tests seen so far, sorted by name.
+pub fn shader_ab_results() -> Vec<ShaderAbResult> {
+    let state = SHADER_AB.lock().unwrap();
+    let mut res: Vec<_> = state
+        .tests
+        .iter()
+        .map(|(name, test)| compare_variants(name, test))
+        .collect();
+    res.sort_by(|a, b| a.name.cmp(&b.name));
+    res
+}
+
+// Drops the samples of `test`, e.g. after editing one of its shaders.
+pub fn reset_shader_ab_test(test: &str) {
+    if let Some(test) = SHADER_AB.lock().unwrap().tests.get_mut(test) {
+        for samples in test.samples.iter_mut() {
+            samples.clear();
+        }
+    }
+}
+
+pub(crate) fn end_frame() {
+    let durations = gpu_profiler::last_frame_durations_by_tag(AB_TAG);
+
+    let triggers: Vec<_> = {
+        let mut state = SHADER_AB.lock().unwrap();
+        state.frame_index += 1;
+
+        for (value, ms) in durations {
+            let mut parts = value.rsplitn(2, '/');
+            let (variant, test) = match (parts.next(), parts.next()) {
+                (Some("a"), Some(test)) => (0, test),
+                (Some("b"), Some(test)) => (1, test),
+                _ => continue,
+            };
+
+            if let Some(test) = state.tests.get_mut(test) {
+                let samples = &mut test.samples[variant];
+                if samples.len() == MAX_SAMPLE_COUNT {
+                    samples.pop_front();
+                }
+                samples.push_back(ms);
+            }
+        }
+
+        state
+            .tests
+            .values_mut()
+            .flat_map(|test| test.invalidation_triggers.drain(..).collect::<Vec<_>>())
+            .collect()
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
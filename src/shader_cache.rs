@@ -0,0 +1,219 @@
+// On-disk caches of compiled SPIR-V and of the Vulkan pipeline cache. Cache keys include
+// the shader compiler version and options, and for pipelines the device and driver
+// version, so updating either doesn't silently pick up stale artifacts.
+//
+// Disabled until a directory is set via `set_shader_cache_dir`.
+
+use crate::shader::{spirv_from_bytes, ShaderKind};
+use crate::vulkan::vk;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Compiler output can change between shaderc releases even if the SPIR-V version doesn't.
+// Keep in step with the exact version Cargo.toml asks for.
+#[cfg(feature = "shaderc")]
+const SHADERC_VERSION: &str = "shaderc 0.6.2";
+#[cfg(not(feature = "shaderc"))]
+const SHADERC_VERSION: &str = "no shaderc";
+
+struct ShaderCacheState {
+    dir: Option<PathBuf>,
+    pipeline_cache: Option<vk::PipelineCache>,
+    // Pipelines were created since the pipeline cache was last written
+    pipeline_cache_dirty: bool,
+}
+
+lazy_static! {
+    static ref SHADER_CACHE: Mutex<ShaderCacheState> = Mutex::new(ShaderCacheState {
+        dir: None,
+        pipeline_cache: None,
+        pipeline_cache_dirty: false,
+    });
+}
+
+// Should be called before any shaders are loaded, since the pipeline cache
+// is only read from disk once.
+pub fn set_shader_cache_dir(dir: Option<String>) -> std::io::Result<()> {
+    if let Some(dir) = dir.as_ref() {
+        std::fs::create_dir_all(dir)?;
+    }
+    SHADER_CACHE.lock().unwrap().dir = dir.map(PathBuf::from);
+    Ok(())
+}
+
+// Deletes everything in the cache directory. The in-memory pipeline cache is kept,
+// and written out again when new pipelines get created.
+pub fn purge_shader_caches() -> std::io::Result<()> {
+    let dir = match SHADER_CACHE.lock().unwrap().dir.clone() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    tracing::info!("Purged shader caches in {:?}", dir);
+    Ok(())
+}
+
+// Identifies the device and driver, for caches of anything device-specific.
+pub(crate) fn device_cache_id() -> String {
+    let props = &vk().device_properties;
+    let name = unsafe { std::ffi::CStr::from_ptr(props.device_name.as_ptr()) };
+    format!(
+        "{} (driver {:x})",
+        name.to_string_lossy(),
+        props.driver_version
+    )
+}
+
+fn cache_file_path(name: &str) -> Option<PathBuf> {
+    SHADER_CACHE
+        .lock()
+        .unwrap()
+        .dir
+        .as_ref()
+        .map(|dir| dir.join(name))
+}
+
+// 64-bit FNV-1a. Unlike `DefaultHasher`, guaranteed not to change between Rust releases,
+// which would orphan the whole cache.
+pub(crate) fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for part in parts {
+        // Length-prefixed, so that moving bytes between parts changes the hash
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn spirv_cache_path(text: &str, shader_kind: ShaderKind, options_id: &str) -> Option<PathBuf> {
+    let shader_kind = format!("{:?}", shader_kind);
+    let hash = fnv1a(&[
+        text.as_bytes(),
+        shader_kind.as_bytes(),
+        options_id.as_bytes(),
+        SHADERC_VERSION.as_bytes(),
+    ]);
+
+    cache_file_path(&format!("{:016x}.spv", hash))
+}
+
+// `options_id` must change whenever the compile options do.
+pub(crate) fn load_cached_spirv(
+    text: &str,
+    shader_kind: ShaderKind,
+    options_id: &str,
+) -> Option<Vec<u32>> {
+    let bytes = std::fs::read(spirv_cache_path(text, shader_kind, options_id)?).ok()?;
+    spirv_from_bytes(&bytes).ok()
+}
+
+pub(crate) fn store_cached_spirv(
+    text: &str,
+    shader_kind: ShaderKind,
+    options_id: &str,
+    spirv: &[u32],
+) {
+    if let Some(path) = spirv_cache_path(text, shader_kind, options_id) {
+        let bytes: Vec<u8> = spirv
+            .iter()
+            .flat_map(|w| w.to_ne_bytes().to_vec())
+            .collect();
+        if let Err(err) = std::fs::write(&path, bytes) {
+            tracing::warn!("Could not write {:?}: {}", path, err);
+        }
+    }
+}
+
+fn pipeline_cache_path() -> Option<PathBuf> {
+    let props = &vk().device_properties;
+    let uuid: String = props
+        .pipeline_cache_uuid
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    cache_file_path(&format!(
+        "pipelines_{:x}_{:x}_{:x}_{}.bin",
+        props.vendor_id, props.device_id, props.driver_version, uuid
+    ))
+}
+
+// Shared by all pipelines; created on first use, from the on-disk copy if there is one.
+pub(crate) fn pipeline_cache() -> vk::PipelineCache {
+    let path = pipeline_cache_path();
+
+    let mut state = SHADER_CACHE.lock().unwrap();
+    if let Some(cache) = state.pipeline_cache {
+        return cache;
+    }
+
+    let initial_data = path
+        .and_then(|path| std::fs::read(path).ok())
+        .unwrap_or_default();
+
+    let cache = unsafe {
+        vk().device
+            .create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo::builder()
+                    .initial_data(&initial_data)
+                    .build(),
+                None,
+            )
+            .unwrap()
+    };
+
+    state.pipeline_cache = Some(cache);
+    cache
+}
+
+// Call after creating pipelines. The cache is written to disk at the end of the frame.
+pub(crate) fn pipeline_cache_updated() {
+    SHADER_CACHE.lock().unwrap().pipeline_cache_dirty = true;
+}
+
+pub(crate) fn end_frame() {
+    let dirty = std::mem::replace(
+        &mut SHADER_CACHE.lock().unwrap().pipeline_cache_dirty,
+        false,
+    );
+    if dirty {
+        write_pipeline_cache();
+    }
+}
+
+fn write_pipeline_cache() {
+    let path = match pipeline_cache_path() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let data = match unsafe { vk().device.get_pipeline_cache_data(pipeline_cache()) } {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!("Could not get the pipeline cache data: {:?}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = std::fs::write(&path, data) {
+        tracing::warn!("Could not write {:?}: {}", path, err);
+    }
+}
+
+#[test]
+fn test_fnv1a() {
+    assert_eq!(fnv1a(&[]), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(fnv1a(&[b"ab", b"c"]), fnv1a(&[b"ab", b"c"]));
+    assert_ne!(fnv1a(&[b"ab", b"c"]), fnv1a(&[b"a", b"bc"]));
+}
@@ -0,0 +1,202 @@
+// Throttling for shader compilation. Switching branches can touch dozens of shaders
+// at once, and compiling all of them in parallel starves everything else.
+//
+// Recompiles wait until file changes have settled, so that a burst of edits results
+// in a single compile per shader. Compiles then run a limited number at a time,
+// with shaders dispatched in the last couple of frames going first.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShaderCompileProgress {
+    pub pending: usize,
+    pub compiling: usize,
+    // Since the queue was last empty
+    pub completed: usize,
+}
+
+struct Waiter {
+    ticket: u64,
+    visible: bool,
+    waker: Option<Waker>,
+}
+
+struct ShaderCompileQueue {
+    max_concurrency: usize,
+    settle_time: Duration,
+    last_file_change: Option<Instant>,
+    settle_timer_pending: bool,
+    next_ticket: u64,
+    waiting: Vec<Waiter>,
+    compiling: usize,
+    completed: usize,
+    used_this_frame: HashSet<String>,
+    used_last_frame: HashSet<String>,
+}
+
+impl ShaderCompileQueue {
+    fn is_next(&self, ticket: u64) -> bool {
+        self.waiting
+            .iter()
+            .min_by_key(|w| (!w.visible, w.ticket))
+            .map_or(false, |w| w.ticket == ticket)
+    }
+
+    fn set_waker(&mut self, ticket: u64, waker: &Waker) {
+        if let Some(waiter) = self.waiting.iter_mut().find(|w| w.ticket == ticket) {
+            waiter.waker = Some(waker.clone());
+        }
+    }
+
+    fn wake_all(&mut self) {
+        for waiter in self.waiting.iter_mut() {
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref SHADER_COMPILE_QUEUE: Mutex<ShaderCompileQueue> = Mutex::new(ShaderCompileQueue {
+        max_concurrency: 4,
+        settle_time: Duration::from_millis(200),
+        last_file_change: None,
+        settle_timer_pending: false,
+        next_ticket: 0,
+        waiting: Vec::new(),
+        compiling: 0,
+        completed: 0,
+        used_this_frame: HashSet::new(),
+        used_last_frame: HashSet::new(),
+    });
+}
+
+pub fn set_shader_compile_concurrency(max_concurrency: usize) {
+    let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+    queue.max_concurrency = max_concurrency.max(1);
+    queue.wake_all();
+}
+
+// How long after the last file change recompiles are held back.
+pub fn set_shader_recompile_settle_ms(ms: u32) {
+    SHADER_COMPILE_QUEUE.lock().unwrap().settle_time = Duration::from_millis(ms as u64);
+}
+
+pub fn shader_compile_progress() -> ShaderCompileProgress {
+    let queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+    ShaderCompileProgress {
+        pending: queue.waiting.len(),
+        compiling: queue.compiling,
+        completed: queue.completed,
+    }
+}
+
+pub(crate) fn notify_asset_changed() {
+    SHADER_COMPILE_QUEUE.lock().unwrap().last_file_change = Some(Instant::now());
+}
+
+pub(crate) fn mark_shader_used(name: &str) {
+    let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+    if !queue.used_this_frame.contains(name) {
+        queue.used_this_frame.insert(name.to_owned());
+    }
+}
+
+pub(crate) fn end_frame() {
+    let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+    queue.used_last_frame = std::mem::replace(&mut queue.used_this_frame, HashSet::new());
+}
+
+// Held for the duration of a compile.
+pub(crate) struct ShaderCompileSlot;
+
+impl Drop for ShaderCompileSlot {
+    fn drop(&mut self) {
+        let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+        queue.compiling -= 1;
+        queue.completed += 1;
+        queue.wake_all();
+    }
+}
+
+pub(crate) struct AcquireShaderCompileSlot {
+    ticket: u64,
+}
+
+// `name` is that of the shader, as reported by the compute passes using it.
+pub(crate) fn acquire_shader_compile_slot(name: &str) -> AcquireShaderCompileSlot {
+    let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+    if queue.waiting.is_empty() && queue.compiling == 0 {
+        queue.completed = 0;
+    }
+
+    let ticket = queue.next_ticket;
+    queue.next_ticket += 1;
+
+    let visible = queue.used_this_frame.contains(name) || queue.used_last_frame.contains(name);
+    queue.waiting.push(Waiter {
+        ticket,
+        visible,
+        waker: None,
+    });
+
+    AcquireShaderCompileSlot { ticket }
+}
+
+impl Future for AcquireShaderCompileSlot {
+    type Output = ShaderCompileSlot;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<ShaderCompileSlot> {
+        let ticket = self.ticket;
+        let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+
+        if let Some(last_change) = queue.last_file_change {
+            let elapsed = last_change.elapsed();
+            if elapsed < queue.settle_time {
+                queue.set_waker(ticket, cx.waker());
+
+                if !queue.settle_timer_pending {
+                    queue.settle_timer_pending = true;
+                    let remaining = queue.settle_time - elapsed;
+                    std::thread::spawn(move || {
+                        std::thread::sleep(remaining);
+                        let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+                        queue.settle_timer_pending = false;
+                        queue.wake_all();
+                    });
+                }
+
+                return Poll::Pending;
+            }
+        }
+
+        if queue.compiling < queue.max_concurrency && queue.is_next(ticket) {
+            queue.waiting.retain(|w| w.ticket != ticket);
+            queue.compiling += 1;
+            // The next waiter might fit in as well
+            queue.wake_all();
+            Poll::Ready(ShaderCompileSlot)
+        } else {
+            queue.set_waker(ticket, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for AcquireShaderCompileSlot {
+    fn drop(&mut self) {
+        // No-op if the slot was acquired; otherwise let the next waiter through.
+        let mut queue = SHADER_COMPILE_QUEUE.lock().unwrap();
+        let len = queue.waiting.len();
+        queue.waiting.retain(|w| w.ticket != self.ticket);
+        if queue.waiting.len() != len {
+            queue.wake_all();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// Blue-green shader reloading, for live use where a frame must never wait on a compile.
+//
+// With hot swapping enabled, `load_cs`, `load_vs` and `load_ps` keep returning the last
+// successfully compiled version of a shader while edits get compiled in the background.
+// The new version replaces the old one only once it's fully built, pipelines and
+// descriptor set layouts included, and passes using it are then re-run. Only the first
+// load of a shader waits for its compile; failed compiles leave the old version in place.
+//
+// `compile_cs` and friends always wait for the latest version instead.
+
+use snoozy::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+static HOT_SWAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct HotSwapSlot {
+    // The version being handed out, and the compile generation it came from
+    current: Option<(u64, Box<dyn Any + Send + Sync>)>,
+    // Bumped by every finished compile
+    compiled_generation: u64,
+    refresh: Option<Arc<dyn Fn() + Send + Sync>>,
+    refreshing: bool,
+    refresh_again: bool,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref HOT_SWAP_SLOTS: Mutex<HashMap<String, HotSwapSlot>> = Mutex::new(HashMap::new());
+}
+
+// Shaders loaded before enabling this keep waiting on their compiles.
+pub fn set_shader_hot_swap_enabled(enabled: bool) {
+    HOT_SWAP_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_shader_hot_swap_enabled() -> bool {
+    HOT_SWAP_ENABLED.load(Ordering::Relaxed)
+}
+
+// Called by compile ops once they've produced a new version.
+pub(crate) fn note_compiled(key: &str) {
+    let mut slots = HOT_SWAP_SLOTS.lock().unwrap();
+    slots.entry(key.to_owned()).or_default().compiled_generation += 1;
+}
+
+// Starts background compiles of any shaders affected by a file change.
+pub(crate) fn notify_asset_changed() {
+    let refreshes: Vec<_> = {
+        let mut slots = HOT_SWAP_SLOTS.lock().unwrap();
+        slots
+            .values_mut()
+            .filter_map(|slot| {
+                if slot.refreshing {
+                    slot.refresh_again = true;
+                    None
+                } else {
+                    slot.refreshing = slot.refresh.is_some();
+                    slot.refresh.clone()
+                }
+            })
+            .collect()
+    };
+
+    for refresh in refreshes {
+        refresh();
+    }
+}
+
+// The current version of the shader behind `key`, as compiled by `compiled`.
+pub(crate) async fn hot_swap<T>(ctx: &mut Context, key: String, compiled: SnoozyRef<T>) -> Result<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let current = {
+        let mut slots = HOT_SWAP_SLOTS.lock().unwrap();
+        let slot = slots.entry(key.clone()).or_default();
+        slot.invalidation_triggers
+            .push(Box::new(ctx.get_invalidation_trigger()));
+
+        if slot.refresh.is_none() {
+            slot.refresh = Some(refresh_fn(
+                key.clone(),
+                compiled.clone(),
+                tokio::runtime::Handle::current(),
+            ));
+        }
+
+        slot.current
+            .as_ref()
+            .and_then(|(_, value)| value.downcast_ref::<T>())
+            .cloned()
+    };
+
+    if let Some(current) = current {
+        return Ok(current);
+    }
+
+    // Nothing to show yet, so the first load has to wait
+    let value = (*ctx.get(compiled).await?).clone();
+
+    let mut slots = HOT_SWAP_SLOTS.lock().unwrap();
+    let slot = slots.entry(key).or_default();
+    slot.current = Some((slot.compiled_generation, Box::new(value.clone())));
+    Ok(value)
+}
+
+fn refresh_fn<T>(
+    key: String,
+    compiled: SnoozyRef<T>,
+    runtime: tokio::runtime::Handle,
+) -> Arc<dyn Fn() + Send + Sync>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    Arc::new(move || {
+        let key = key.clone();
+        let compiled = compiled.clone();
+
+        runtime.spawn(async move {
+            let snapshot = get_snapshot(move |f| {
+                tokio::task::spawn(async move {
+                    f();
+                });
+            });
+            let value = (*snapshot.get(compiled).await).clone();
+            finish_refresh(&key, Box::new(value));
+        });
+    })
+}
+
+fn finish_refresh(key: &str, value: Box<dyn Any + Send + Sync>) {
+    let (triggers, refresh) = {
+        let mut slots = HOT_SWAP_SLOTS.lock().unwrap();
+        let slot = slots.entry(key.to_owned()).or_default();
+        slot.refreshing = false;
+
+        // Unrelated file changes leave the shader as it was
+        let is_newer = slot.current.as_ref().map_or(true, |(generation, _)| {
+            *generation < slot.compiled_generation
+        });
+
+        let triggers = if is_newer {
+            tracing::info!("Swapping in the new version of {}", key);
+            slot.current = Some((slot.compiled_generation, value));
+            std::mem::replace(&mut slot.invalidation_triggers, Vec::new())
+        } else {
+            Vec::new()
+        };
+
+        let refresh = if slot.refresh_again {
+            slot.refresh_again = false;
+            slot.refreshing = true;
+            slot.refresh.clone()
+        } else {
+            None
+        };
+
+        (triggers, refresh)
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+
+    if let Some(refresh) = refresh {
+        refresh();
+    }
+}
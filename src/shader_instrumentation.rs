@@ -0,0 +1,615 @@
+// Debug compile mode which rewrites image and texel fetch accesses, as well as indexing
+// of runtime-sized storage buffer arrays in user GLSL, to validate coordinates, indices
+// and stored values. Violations are appended to a per-frame record buffer, read back once
+// the frame's fence is signaled, and reported as warnings.
+//
+// Rather than paying for it everywhere, instrumentation can be enabled for individual
+// shaders via `set_shader_robustness` while chasing down bad reads.
+//
+// This only reports out of bounds accesses; it doesn't make them safe. The rewritten accesses
+// are clamped so one bad coordinate doesn't trigger a cascade of reports, but sampled reads,
+// descriptor indexing and fixed-size arrays are left as they are, and neither
+// robustBufferAccess nor VK_EXT_robustness2 gets enabled.
+//
+// Vertex and fragment shaders are only instrumented if the device supports storage writes
+// from their stage, as the records are written to a storage buffer.
+
+use crate::shader::ShaderKind;
+use crate::vulkan::vk;
+use ash::version::DeviceV1_0;
+use ash::{vk, Device};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static INSTRUMENTATION_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_shader_instrumentation_enabled(enabled: bool) {
+    INSTRUMENTATION_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_shader_instrumentation_enabled() -> bool {
+    INSTRUMENTATION_ENABLED.load(Ordering::Relaxed)
+}
+
+lazy_static! {
+    static ref ROBUST_SHADERS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// Instruments just the shader `shader_name`, as in profiler scopes. Applies to shaders
+// compiled afterwards, e.g. upon the next edit. Despite the name, accesses are only
+// reported, not made safe; see above.
+pub fn set_shader_robustness(shader_name: &str, enabled: bool) {
+    let mut shaders = ROBUST_SHADERS.lock().unwrap();
+    if enabled {
+        shaders.insert(shader_name.to_owned());
+    } else {
+        shaders.remove(shader_name);
+    }
+}
+
+pub(crate) fn is_shader_instrumented(shader_name: &str, shader_kind: ShaderKind) -> bool {
+    let stage_supported = match shader_kind {
+        ShaderKind::Compute => true,
+        ShaderKind::Vertex => vk().caps.vertex_pipeline_stores_and_atomics,
+        ShaderKind::Fragment => vk().caps.fragment_stores_and_atomics,
+    };
+
+    stage_supported
+        && (is_shader_instrumentation_enabled()
+            || ROBUST_SHADERS.lock().unwrap().contains(shader_name))
+}
+
+// Whether passes need to provide the instrumentation uniforms.
+pub(crate) fn is_any_shader_instrumented() -> bool {
+    is_shader_instrumentation_enabled() || !ROBUST_SHADERS.lock().unwrap().is_empty()
+}
+
+pub(crate) const PASS_ID_UNIFORM_NAME: &str = "rtoy_asan_pass_id";
+pub(crate) const RECORD_BUFFER_BLOCK_NAME: &str = "rtoy_shader_asan";
+
+const MAX_RECORDS: usize = 1024;
+
+// pass id, violation kind, invocation id xyz
+const RECORD_WORDS: usize = 5;
+
+const VIOLATION_OUT_OF_BOUNDS: u32 = 1;
+const VIOLATION_NAN_OR_INF: u32 = 2;
+const VIOLATION_BUFFER_OUT_OF_BOUNDS: u32 = 3;
+
+// Sizes to check `texelFetch` coordinates against, by the type of the texture. The extra
+// argument is the LOD, or the sample index of multisampled textures.
+fn glsl_texel_size_functions() -> String {
+    const TYPES: [(&str, &str, &str); 7] = [
+        ("1D", "int", "textureSize(t, arg)"),
+        ("2D", "ivec2", "textureSize(t, arg)"),
+        ("3D", "ivec3", "textureSize(t, arg)"),
+        ("1DArray", "ivec2", "textureSize(t, arg)"),
+        ("2DArray", "ivec3", "textureSize(t, arg)"),
+        ("2DMS", "ivec2", "textureSize(t)"),
+        ("2DMSArray", "ivec3", "textureSize(t)"),
+    ];
+
+    let mut res = String::new();
+    for prefix in ["", "i", "u"].iter() {
+        for kind in ["sampler", "texture"].iter() {
+            for (dim, size_type, size) in TYPES.iter() {
+                res += &format!(
+                    "{} rtoy_asan_texel_size({}{}{} t, int arg) {{ return {}; }}\n",
+                    size_type, prefix, kind, dim, size
+                );
+            }
+        }
+    }
+    res
+}
+
+pub(crate) fn glsl_preamble(shader_kind: ShaderKind) -> String {
+    let invocation_id = match shader_kind {
+        ShaderKind::Compute => "gl_GlobalInvocationID",
+        ShaderKind::Fragment => "uvec3(gl_FragCoord.xy, 0)",
+        ShaderKind::Vertex => "uvec3(gl_VertexIndex, gl_InstanceIndex, 0)",
+    };
+
+    format!(
+        r#"
+layout(std430) buffer {block_name} {{
+    uint rtoy_asan_record_count;
+    uint rtoy_asan_records[];
+}};
+layout(std140) uniform rtoy_shader_asan_params {{
+    uint {pass_id};
+}};
+
+void rtoy_asan_report(uint kind) {{
+    uint idx = atomicAdd(rtoy_asan_record_count, 1);
+    if (idx < {max_records}) {{
+        uvec3 invocation = {invocation_id};
+        rtoy_asan_records[idx * {record_words} + 0] = {pass_id};
+        rtoy_asan_records[idx * {record_words} + 1] = kind;
+        rtoy_asan_records[idx * {record_words} + 2] = invocation.x;
+        rtoy_asan_records[idx * {record_words} + 3] = invocation.y;
+        rtoy_asan_records[idx * {record_words} + 4] = invocation.z;
+    }}
+}}
+
+int rtoy_asan_coord(int p, int size) {{
+    if (p < 0 || p >= size) {{
+        rtoy_asan_report({oob});
+        return clamp(p, 0, size - 1);
+    }}
+    return p;
+}}
+
+ivec2 rtoy_asan_coord(ivec2 p, ivec2 size) {{
+    if (any(lessThan(p, ivec2(0))) || any(greaterThanEqual(p, size))) {{
+        rtoy_asan_report({oob});
+        return clamp(p, ivec2(0), size - 1);
+    }}
+    return p;
+}}
+
+ivec3 rtoy_asan_coord(ivec3 p, ivec3 size) {{
+    if (any(lessThan(p, ivec3(0))) || any(greaterThanEqual(p, size))) {{
+        rtoy_asan_report({oob});
+        return clamp(p, ivec3(0), size - 1);
+    }}
+    return p;
+}}
+
+vec4 rtoy_asan_value(vec4 v) {{
+    if (any(isnan(v)) || any(isinf(v))) {{
+        rtoy_asan_report({nan});
+    }}
+    return v;
+}}
+
+ivec4 rtoy_asan_value(ivec4 v) {{ return v; }}
+uvec4 rtoy_asan_value(uvec4 v) {{ return v; }}
+
+int rtoy_asan_index(int i, int len) {{
+    if (i < 0 || i >= len) {{
+        rtoy_asan_report({buffer_oob});
+        return clamp(i, 0, max(len - 1, 0));
+    }}
+    return i;
+}}
+
+uint rtoy_asan_index(uint i, int len) {{
+    if (i >= uint(len)) {{
+        rtoy_asan_report({buffer_oob});
+        return uint(max(len - 1, 0));
+    }}
+    return i;
+}}
+
+{texel_size_functions}"#,
+        block_name = RECORD_BUFFER_BLOCK_NAME,
+        pass_id = PASS_ID_UNIFORM_NAME,
+        max_records = MAX_RECORDS,
+        record_words = RECORD_WORDS,
+        invocation_id = invocation_id,
+        oob = VIOLATION_OUT_OF_BOUNDS,
+        nan = VIOLATION_NAN_OR_INF,
+        buffer_oob = VIOLATION_BUFFER_OUT_OF_BOUNDS,
+        texel_size_functions = glsl_texel_size_functions(),
+    )
+}
+
+const INSTRUMENTED_FUNCTIONS: [&str; 3] = ["imageLoad", "imageStore", "texelFetch"];
+
+lazy_static! {
+    static ref BUFFER_BLOCK: Regex = Regex::new(r"\bbuffer\s+\w+\s*\{([^}]*)\}").unwrap();
+    static ref RUNTIME_ARRAY_MEMBER: Regex = Regex::new(r"(\w+)\s*\[\s*\]\s*;").unwrap();
+}
+
+// Names of runtime-sized arrays in storage buffer blocks, across all of a shader's sources.
+pub(crate) fn runtime_array_names<'a>(sources: impl Iterator<Item = &'a str>) -> HashSet<String> {
+    let mut res = HashSet::new();
+    for source in sources {
+        for block in BUFFER_BLOCK.captures_iter(source) {
+            for member in RUNTIME_ARRAY_MEMBER.captures_iter(&block[1]) {
+                res.insert(member[1].to_owned());
+            }
+        }
+    }
+    res
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+enum Access {
+    Call(&'static str),
+    // Indexing of a runtime array, with the expression naming it, e.g. `particles.data`
+    Index(String),
+}
+
+// Find the next instrumented access, returning its byte offset, the length of the
+// identifier, and what kind of access it is.
+fn find_next_access(
+    source: &str,
+    runtime_arrays: &HashSet<String>,
+) -> Option<(usize, usize, Access)> {
+    let mut prev_char = None;
+    for (i, c) in source.char_indices() {
+        let starts_ident = is_ident_char(c)
+            && !c.is_ascii_digit()
+            && !prev_char.map(is_ident_char).unwrap_or(false);
+        prev_char = Some(c);
+        if !starts_ident {
+            continue;
+        }
+
+        let rest = &source[i..];
+        let ident_len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+        let ident = &rest[..ident_len];
+        let after = rest[ident_len..].trim_start();
+
+        if after.starts_with('(') {
+            if let Some(name) = INSTRUMENTED_FUNCTIONS.iter().find(|name| **name == ident) {
+                return Some((i, ident_len, Access::Call(name)));
+            }
+        } else if after.starts_with('[') && runtime_arrays.contains(ident) {
+            // Members of named block instances need the instance for `length()`
+            let path_start = source[..i]
+                .trim_end_matches(|c| is_ident_char(c) || c == '.')
+                .len();
+            let path = &source[path_start..i + ident_len];
+            // Paths starting after an indexing or a call aren't handled
+            let after_expression = source[..path_start].ends_with(|c| c == ']' || c == ')');
+            if !path.starts_with('.') && !after_expression {
+                return Some((i, ident_len, Access::Index(path.to_owned())));
+            }
+        }
+    }
+    None
+}
+
+// Split the bracketed argument list at the start of `source`, opened by `open`.
+// Returns the trimmed arguments and the number of bytes consumed, including the closing bracket.
+fn split_args(source: &str, open: char) -> Option<(Vec<&str>, usize)> {
+    let open = source.find(open)?;
+    let mut depth = 0;
+    let mut args = Vec::new();
+    let mut arg_start = open + 1;
+
+    for (i, c) in source[open..].char_indices() {
+        let i = i + open;
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if 0 == depth {
+                    args.push(source[arg_start..i].trim());
+                    return Some((args, i + 1));
+                }
+            }
+            ',' if 1 == depth => {
+                args.push(source[arg_start..i].trim());
+                arg_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn rewrite_call(name: &str, args: &[String]) -> Option<String> {
+    match (name, args.len()) {
+        ("imageLoad", n) if n >= 2 => Some(format!(
+            "imageLoad({img}, rtoy_asan_coord({p}, imageSize({img})){rest})",
+            img = args[0],
+            p = args[1],
+            rest = args[2..]
+                .iter()
+                .map(|a| format!(", {}", a))
+                .collect::<String>()
+        )),
+        ("imageStore", n) if n >= 3 => Some(format!(
+            "imageStore({img}, rtoy_asan_coord({p}, imageSize({img})){mid}, rtoy_asan_value({v}))",
+            img = args[0],
+            p = args[1],
+            mid = args[2..n - 1]
+                .iter()
+                .map(|a| format!(", {}", a))
+                .collect::<String>(),
+            v = args[n - 1]
+        )),
+        ("texelFetch", 2) => Some(format!(
+            "texelFetch({buf}, rtoy_asan_coord({i}, textureSize({buf})))",
+            buf = args[0],
+            i = args[1]
+        )),
+        // The last argument is the LOD, or the sample of multisampled textures
+        ("texelFetch", 3) => Some(format!(
+            "texelFetch({tex}, rtoy_asan_coord({p}, rtoy_asan_texel_size({tex}, {arg})), {arg})",
+            tex = args[0],
+            p = args[1],
+            arg = args[2]
+        )),
+        _ => None,
+    }
+}
+
+// Wrap coordinates of image/texel accesses and indices of `runtime_arrays` in bounds checks,
+// and stored values in NaN/Inf checks.
+pub(crate) fn instrument_glsl(source: &str, runtime_arrays: &HashSet<String>) -> String {
+    let mut res = String::with_capacity(source.len() * 2);
+    let mut rest = source;
+
+    while let Some((pos, ident_len, access)) = find_next_access(rest, runtime_arrays) {
+        res.push_str(&rest[..pos + ident_len]);
+        let after_ident = &rest[pos + ident_len..];
+
+        let rewritten = match access {
+            Access::Call(name) => split_args(after_ident, '(').and_then(|(args, consumed)| {
+                let args: Vec<String> = args
+                    .into_iter()
+                    .map(|arg| instrument_glsl(arg, runtime_arrays))
+                    .collect();
+                rewrite_call(name, &args).map(|call| (call[name.len()..].to_owned(), consumed))
+            }),
+            Access::Index(path) => match split_args(after_ident, '[') {
+                // Declarations, as in `uint data[];`, have no index
+                Some((ref args, consumed)) if args.len() == 1 && !args[0].is_empty() => Some((
+                    format!(
+                        "[rtoy_asan_index({}, {}.length())]",
+                        instrument_glsl(args[0], runtime_arrays),
+                        path
+                    ),
+                    consumed,
+                )),
+                _ => None,
+            },
+        };
+
+        if let Some((rewritten, consumed)) = rewritten {
+            res.push_str(&rewritten);
+            rest = &after_ident[consumed..];
+        } else {
+            rest = after_ident;
+        }
+    }
+
+    res.push_str(rest);
+    res
+}
+
+pub struct ShaderAsanBuffer {
+    pub(crate) buffer: vk::Buffer,
+    allocation: vk_mem::Allocation,
+    pass_names: Mutex<Vec<String>>,
+}
+
+impl ShaderAsanBuffer {
+    pub(crate) fn new(allocator: &vk_mem::Allocator) -> Self {
+        let mem_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            ..Default::default()
+        };
+
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(((1 + MAX_RECORDS * RECORD_WORDS) * 4) as u64)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+
+        let (buffer, allocation, _allocation_info) = allocator
+            .create_buffer(&buffer_info, &mem_info)
+            .expect("vma::create_buffer");
+
+        Self {
+            buffer,
+            allocation,
+            pass_names: Default::default(),
+        }
+    }
+
+    pub(crate) fn register_pass(&self, name: &str) -> u32 {
+        let mut pass_names = self.pass_names.lock().unwrap();
+        let id = pass_names.len() as u32;
+        pass_names.push(name.to_owned());
+        id
+    }
+
+    pub(crate) fn begin_frame(&self, device: &Device, cb: vk::CommandBuffer) {
+        self.pass_names.lock().unwrap().clear();
+
+        if !is_any_shader_instrumented() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_fill_buffer(cb, self.buffer, 0, 4, 0);
+        }
+
+        let global_barrier = vk_sync::GlobalBarrier {
+            previous_accesses: &[vk_sync::AccessType::TransferWrite],
+            next_accesses: &[vk_sync::AccessType::General],
+        };
+
+        vk_sync::cmd::pipeline_barrier(device.fp_v1_0(), cb, Some(global_barrier), &[], &[]);
+    }
+
+    pub(crate) fn finish_frame(&self, device: &Device, cb: vk::CommandBuffer) {
+        if !is_any_shader_instrumented() {
+            return;
+        }
+
+        let global_barrier = vk_sync::GlobalBarrier {
+            previous_accesses: &[vk_sync::AccessType::General],
+            next_accesses: &[vk_sync::AccessType::HostRead],
+        };
+
+        vk_sync::cmd::pipeline_barrier(device.fp_v1_0(), cb, Some(global_barrier), &[], &[]);
+    }
+
+    // Must only be called once the frame which last used this buffer has finished executing.
+    // Reports each frame's violations once, however many times it's called.
+    pub(crate) fn report_previous_violations(&self, allocator: &vk_mem::Allocator) {
+        if !is_any_shader_instrumented() {
+            return;
+        }
+
+        let pass_names = std::mem::take(&mut *self.pass_names.lock().unwrap());
+        if pass_names.is_empty() {
+            return;
+        }
+
+        let mapped_ptr = allocator
+            .map_memory(&self.allocation)
+            .expect("mapping a shader instrumentation buffer failed")
+            as *const u32;
+
+        let records = unsafe {
+            let record_count = (*mapped_ptr as usize).min(MAX_RECORDS);
+            std::slice::from_raw_parts(mapped_ptr.add(1), record_count * RECORD_WORDS).to_owned()
+        };
+
+        allocator
+            .unmap_memory(&self.allocation)
+            .expect("unmapping a shader instrumentation buffer failed");
+
+        for text in violation_reports(&pass_names, &records) {
+            tracing::warn!("{}", text);
+            crate::rtoy_show_warning(text);
+        }
+    }
+}
+
+// Collapses per-invocation records into one report per pass and violation kind.
+fn violation_reports(pass_names: &[String], records: &[u32]) -> Vec<String> {
+    let mut violations: HashMap<(u32, u32), ([u32; 3], usize)> = HashMap::new();
+    for record in records.chunks_exact(RECORD_WORDS) {
+        violations
+            .entry((record[0], record[1]))
+            .or_insert(([record[2], record[3], record[4]], 0))
+            .1 += 1;
+    }
+
+    let mut reports = Vec::new();
+    for ((pass_id, kind), (invocation, hits)) in violations {
+        let pass_name = pass_names
+            .get(pass_id as usize)
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        let kind = match kind {
+            VIOLATION_OUT_OF_BOUNDS => "out-of-bounds access",
+            VIOLATION_NAN_OR_INF => "NaN/Inf stored",
+            VIOLATION_BUFFER_OUT_OF_BOUNDS => "out-of-bounds buffer index",
+            _ => "unknown violation",
+        };
+
+        reports.push(format!(
+            "Shader instrumentation: {} in {} (first invocation {:?}, {} hits)",
+            kind, pass_name, invocation, hits
+        ));
+    }
+    reports.sort();
+    reports
+}
+
+impl Drop for ShaderAsanBuffer {
+    fn drop(&mut self) {
+        vk().allocator
+            .destroy_buffer(self.buffer, &self.allocation)
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_instrument_glsl() {
+    let source = "layout(std430) buffer particles_buf { vec4 particles[]; } pb;\n\
+                  buffer counts_buf { uint counts[]; };\n\
+                  void main() { pb.particles[i] = texelFetch(ms, p, counts[j]); }";
+    let runtime_arrays = runtime_array_names(std::iter::once(source));
+    assert!(runtime_arrays.contains("particles") && runtime_arrays.contains("counts"));
+
+    let res = instrument_glsl(source, &runtime_arrays);
+    assert!(res.contains("vec4 particles[];"));
+    assert!(res.contains("pb.particles[rtoy_asan_index(i, pb.particles.length())] ="));
+    assert!(res.contains(
+        "texelFetch(ms, rtoy_asan_coord(p, rtoy_asan_texel_size(ms, \
+         counts[rtoy_asan_index(j, counts.length())])), \
+         counts[rtoy_asan_index(j, counts.length())])"
+    ));
+}
+
+#[test]
+fn test_violation_reports() {
+    let pass_names = ["blur".to_owned(), "resolve".to_owned()];
+    let records = [
+        [1, VIOLATION_OUT_OF_BOUNDS, 3, 4, 0],
+        [1, VIOLATION_OUT_OF_BOUNDS, 5, 4, 0],
+        [0, VIOLATION_NAN_OR_INF, 1, 2, 0],
+    ]
+    .concat();
+    assert_eq!(
+        violation_reports(&pass_names, &records),
+        vec![
+            "Shader instrumentation: NaN/Inf stored in blur (first invocation [1, 2, 0], 1 hits)"
+                .to_owned(),
+            "Shader instrumentation: out-of-bounds access in resolve \
+             (first invocation [3, 4, 0], 2 hits)"
+                .to_owned(),
+        ]
+    );
+}
+
+// Needs a Vulkan device; run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn test_shader_robustness_reports_violations() {
+    use crate::headless::with_test_headless_compute;
+    use crate::shader::{compute_tex, load_cs_from_string};
+    use crate::shader_uniforms;
+    use crate::texture::TextureKey;
+
+    set_shader_instrumentation_enabled(false);
+    set_shader_robustness("robustness_test", true);
+
+    let source = "uniform texture2D inputTex;\n\
+                  layout(rgba16f) uniform restrict writeonly image2D outputTex;\n\
+                  layout (local_size_x = 8, local_size_y = 8) in;\n\
+                  void main() {\n\
+                      ivec2 pix = ivec2(gl_GlobalInvocationID.xy);\n\
+                      imageStore(outputTex, pix, texelFetch(inputTex, pix + ivec2(100, 0), 0));\n\
+                  }\n";
+    let key = TextureKey::new(16, 16, vk::Format::R16G16B16A16_SFLOAT);
+    let input = compute_tex(
+        key,
+        load_cs_from_string(
+            "layout(rgba16f) uniform restrict writeonly image2D outputTex;\n\
+             layout (local_size_x = 8, local_size_y = 8) in;\n\
+             void main() { imageStore(outputTex, ivec2(gl_GlobalInvocationID.xy), vec4(1)); }\n"
+                .to_owned(),
+            "robustness_test_input.glsl".to_owned(),
+        ),
+        shader_uniforms!(),
+    );
+    let output = compute_tex(
+        key,
+        load_cs_from_string(source.to_owned(), "robustness_test.glsl".to_owned()),
+        shader_uniforms!(inputTex: input),
+    );
+
+    let warnings = with_test_headless_compute(|compute| {
+        compute.eval(&output);
+        compute.take_warnings()
+    });
+    set_shader_robustness("robustness_test", false);
+
+    assert!(
+        warnings
+            .iter()
+            .any(|w| w.contains("out-of-bounds access in robustness_test ")),
+        "{:?}",
+        warnings
+    );
+    // Only the shader with robustness enabled is instrumented
+    assert!(!warnings.iter().any(|w| w.contains("robustness_test_input")));
+}
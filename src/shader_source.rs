@@ -0,0 +1,308 @@
+// Preprocessed shader text, as handed to the compiler: includes resolved, and
+// the preamble prepended. Tools such as shader editors can read it back, and
+// substitute edited text which then gets compiled in its place.
+
+use crate::blob::AssetPath;
+use crate::shader::ShaderKind;
+use snoozy::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// One file of a shader after include resolution, in the order it was concatenated.
+#[derive(Clone, Debug)]
+pub struct ShaderSourceChunk {
+    pub file: String,
+    pub source: String,
+    pub line_offset: usize,
+}
+
+// Compiler message mapped back to a line of one of the source chunks.
+#[derive(Clone, Debug)]
+pub struct ShaderDiagnostic {
+    // `None` when the compiler couldn't attribute the message to a chunk
+    pub file: Option<String>,
+    // 1-based, in the coordinates of the file on disk
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ShaderSourceEntry {
+    chunks: Vec<ShaderSourceChunk>,
+    diagnostics: Vec<ShaderDiagnostic>,
+    text: String,
+    last_good_spirv: Option<Vec<u32>>,
+    override_text: Option<String>,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+#[derive(Default)]
+struct AssetSourceOverride {
+    text: Option<String>,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+#[derive(Default)]
+struct FallbackShaders {
+    compute: Option<String>,
+    pixel: Option<String>,
+}
+
+lazy_static! {
+    static ref SHADER_SOURCES: Mutex<HashMap<String, ShaderSourceEntry>> =
+        Mutex::new(HashMap::new());
+    static ref FALLBACK_SHADERS: Mutex<FallbackShaders> = Mutex::new(Default::default());
+    static ref ASSET_SOURCE_OVERRIDES: Mutex<HashMap<String, AssetSourceOverride>> =
+        Mutex::new(HashMap::new());
+}
+
+// Keys of every shader compiled so far: `crate::path` for asset shaders, and
+// the name passed to `load_cs_from_string` otherwise.
+pub fn loaded_shader_sources() -> Vec<String> {
+    let mut keys: Vec<String> = SHADER_SOURCES.lock().unwrap().keys().cloned().collect();
+    keys.sort();
+    keys
+}
+
+// The text last produced by the preprocessor, regardless of any override.
+pub fn get_shader_source(key: &str) -> Option<String> {
+    SHADER_SOURCES
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(|entry| entry.text.clone())
+}
+
+pub fn get_shader_source_chunks(key: &str) -> Option<Vec<ShaderSourceChunk>> {
+    SHADER_SOURCES
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(|entry| entry.chunks.clone())
+}
+
+// Messages from the last compilation of `key`; empty if it succeeded.
+pub fn get_shader_diagnostics(key: &str) -> Vec<ShaderDiagnostic> {
+    SHADER_SOURCES
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(|entry| entry.diagnostics.clone())
+        .unwrap_or_default()
+}
+
+pub fn get_shader_source_override(key: &str) -> Option<String> {
+    SHADER_SOURCES
+        .lock()
+        .unwrap()
+        .get(key)
+        .and_then(|entry| entry.override_text.clone())
+}
+
+// Compiles `text` instead of the preprocessed source of `key` from now on; `None`
+// goes back to the original. The shader gets recompiled in either case.
+pub fn set_shader_source_override(key: &str, text: Option<String>) {
+    let triggers = {
+        let mut sources = SHADER_SOURCES.lock().unwrap();
+        let entry = sources.entry(key.to_owned()).or_default();
+        if entry.override_text == text {
+            return;
+        }
+        entry.override_text = text;
+        std::mem::replace(&mut entry.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Replaces the contents of a single shader file, as seen by every shader including it,
+// without touching the file on disk. Everything which included it gets recompiled.
+pub fn set_shader_asset_override(path: &AssetPath, text: String) {
+    set_shader_asset_override_impl(path, Some(text));
+}
+
+// Goes back to the on-disk version of the file.
+pub fn revert_shader_asset_override(path: &AssetPath) {
+    set_shader_asset_override_impl(path, None);
+}
+
+pub fn get_shader_asset_override(path: &AssetPath) -> Option<String> {
+    ASSET_SOURCE_OVERRIDES
+        .lock()
+        .unwrap()
+        .get(&path.to_string())
+        .and_then(|entry| entry.text.clone())
+}
+
+fn set_shader_asset_override_impl(path: &AssetPath, text: Option<String>) {
+    let triggers = {
+        let mut overrides = ASSET_SOURCE_OVERRIDES.lock().unwrap();
+        let entry = overrides.entry(path.to_string()).or_default();
+        if entry.text == text {
+            return;
+        }
+        entry.text = text;
+        std::mem::replace(&mut entry.invalidation_triggers, Vec::new())
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Overrides are tracked by ops of their own, which only get re-evaluated when the override
+// changes. Shaders depending on them get invalidated along with them, without registering
+// triggers of their own on every compile.
+#[snoozy]
+pub async fn shader_asset_override_snoozy(ctx: Context, path: &String) -> Result<Option<String>> {
+    let mut overrides = ASSET_SOURCE_OVERRIDES.lock().unwrap();
+    let entry = overrides.entry(path.clone()).or_default();
+    entry
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+    Ok(entry.text.clone())
+}
+
+#[snoozy]
+pub async fn shader_source_override_snoozy(ctx: Context, key: &String) -> Result<Option<String>> {
+    let mut sources = SHADER_SOURCES.lock().unwrap();
+    let entry = sources.entry(key.clone()).or_default();
+    entry
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+    Ok(entry.override_text.clone())
+}
+
+// Returns the override of `path` if any. The calling op gets invalidated when it changes.
+pub(crate) fn resolve_shader_asset_override(
+    ctx: &Context,
+    path: &AssetPath,
+) -> Result<Option<String>> {
+    let text = snoozy::futures::executor::block_on(
+        ctx.clone().get(shader_asset_override(path.to_string())),
+    )?;
+    Ok((*text).clone())
+}
+
+// Records the preprocessed `text` of `key`, and returns what should actually be compiled.
+// The calling op gets invalidated when the override changes.
+pub(crate) fn resolve_shader_source(
+    ctx: &Context,
+    key: &str,
+    chunks: Vec<ShaderSourceChunk>,
+    text: String,
+) -> Result<String> {
+    let override_text = snoozy::futures::executor::block_on(
+        ctx.clone().get(shader_source_override(key.to_owned())),
+    )?;
+
+    let mut sources = SHADER_SOURCES.lock().unwrap();
+    let entry = sources.entry(key.to_owned()).or_default();
+    entry.chunks = chunks;
+    entry.text = text;
+    Ok((*override_text)
+        .clone()
+        .unwrap_or_else(|| entry.text.clone()))
+}
+
+// Chunk `i` of the compiled text starts with `#line 0 {i + 1}`, so messages come out
+// as `{i + 1}:{line}: error: ...`, with lines counted from zero within the chunk.
+// Returns the messages as `file:line: error: ...` lines.
+pub(crate) fn record_shader_diagnostics(key: &str, compiler_output: &str) -> String {
+    let mut sources = SHADER_SOURCES.lock().unwrap();
+    let entry = sources.entry(key.to_owned()).or_default();
+    let chunks = &entry.chunks;
+
+    entry.diagnostics = compiler_output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, ':');
+            let location = match (parts.next(), parts.next(), parts.next()) {
+                (Some(chunk), Some(line), Some(message)) => {
+                    match (chunk.trim().parse::<usize>(), line.trim().parse::<usize>()) {
+                        (Ok(chunk), Ok(line)) if chunk >= 1 && chunk <= chunks.len() => {
+                            Some((&chunks[chunk - 1], line, message))
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+
+            match location {
+                Some((chunk, line, message)) => ShaderDiagnostic {
+                    file: Some(chunk.file.clone()),
+                    line: Some(chunk.line_offset + line + 1),
+                    message: message.trim().to_owned(),
+                },
+                None => ShaderDiagnostic {
+                    file: None,
+                    line: None,
+                    message: line.trim().to_owned(),
+                },
+            }
+        })
+        .collect();
+
+    entry
+        .diagnostics
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub(crate) fn record_good_spirv(key: &str, spirv: &[u32]) {
+    let mut sources = SHADER_SOURCES.lock().unwrap();
+    sources.entry(key.to_owned()).or_default().last_good_spirv = Some(spirv.to_vec());
+}
+
+pub(crate) fn last_good_spirv(key: &str) -> Option<Vec<u32>> {
+    SHADER_SOURCES
+        .lock()
+        .unwrap()
+        .get(key)
+        .and_then(|entry| entry.last_good_spirv.clone())
+}
+
+// Compiled in place of compute shaders which fail to compile on their first load.
+// Same conventions as `load_cs_from_string`. `None` makes such failures errors again.
+pub fn set_fallback_compute_shader(source: Option<String>) {
+    FALLBACK_SHADERS.lock().unwrap().compute = source;
+}
+
+// As `set_fallback_compute_shader`, but for pixel shaders.
+pub fn set_fallback_pixel_shader(source: Option<String>) {
+    FALLBACK_SHADERS.lock().unwrap().pixel = source;
+}
+
+// Shaders which fail to compile on their first load output magenta.
+pub fn enable_magenta_shader_fallbacks() {
+    set_fallback_compute_shader(Some(
+        include_str!("../assets/shaders/fallback_magenta_cs.glsl").to_owned(),
+    ));
+    set_fallback_pixel_shader(Some(
+        include_str!("../assets/shaders/fallback_magenta_ps.glsl").to_owned(),
+    ));
+}
+
+pub(crate) fn fallback_shader_source(shader_kind: ShaderKind) -> Option<String> {
+    let fallbacks = FALLBACK_SHADERS.lock().unwrap();
+    match shader_kind {
+        ShaderKind::Compute => fallbacks.compute.clone(),
+        ShaderKind::Fragment => fallbacks.pixel.clone(),
+        _ => None,
+    }
+}
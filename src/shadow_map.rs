@@ -0,0 +1,301 @@
+// Shadow maps of spot and directional lights, rendered into tiles of a single depth atlas:
+//
+//   let shadows = shadow_atlas(
+//       &[
+//           ShadowCaster::directional(sun_dir, Vec3::zero(), 50.0, 2048),
+//           ShadowCaster::spot(lamp_pos, lamp_dir, 60.0, 0.1, 512),
+//       ],
+//       ShadowAtlasDesc::new(4096),
+//       ShadowParams::default(),
+//       scene.clone(),
+//   );
+//   let lit = compute_tex(key, load_cs(asset!("shaders/lighting.glsl")), shader_uniforms!(
+//       :shadows.uniforms(),
+//   ));
+//
+// Shaders include `shaders/shadow_sampling.inc`, and call `shadow_visibility` with the index
+// of a caster. All casters are drawn in one depth-only pass; each gets a square tile of
+// its resolution, rounded up to a power of two. Casters which don't fit are scaled down.
+
+use crate::buffer::{upload_array_buffer, Buffer};
+use crate::coord_convention;
+use crate::math::*;
+use crate::shader::{
+    load_vs_from_string, make_raster_depth_pipeline, raster_depth_tex, DepthRasterDesc,
+    ShaderUniformBundle, ShaderUniformHolder,
+};
+use crate::shader_uniforms;
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowCaster {
+    // World to clip space of the shadow map, in the default coordinate convention
+    pub world_to_clip: Mat4,
+    // Width of the shadow map frustum in world units, at a clip space `w` of 1
+    pub unit_extent: f32,
+    pub resolution: u32,
+}
+
+impl ShadowCaster {
+    // Looking from `position` along `direction`, with a `cone_angle` degree field of view.
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        cone_angle: f32,
+        near_dist: f32,
+        resolution: u32,
+    ) -> Self {
+        let h = 1.0 / (0.5 * cone_angle.to_radians()).tan();
+
+        // Same infinite reverse-Z projection as `FirstPersonCamera`
+        let view_to_clip = Mat4::from_cols(
+            Vec4::new(h, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, h, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, -1.0),
+            Vec4::new(0.0, 0.0, near_dist, 0.0),
+        );
+
+        Self {
+            world_to_clip: view_to_clip * light_world_to_view(position, direction),
+            unit_extent: 2.0 / h,
+            resolution,
+        }
+    }
+
+    // Parallel light along `direction`, covering a box of `radius` around `center`.
+    // Occluders outside of the box don't cast shadows.
+    pub fn directional(direction: Vec3, center: Vec3, radius: f32, resolution: u32) -> Self {
+        // Reverse-Z from 1 at `radius` towards the light, to 0 at `radius` away from it
+        let view_to_clip = Mat4::from_cols(
+            Vec4::new(1.0 / radius, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0 / radius, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 0.5 / radius, 0.0),
+            Vec4::new(0.0, 0.0, 0.5, 1.0),
+        );
+
+        Self {
+            world_to_clip: view_to_clip * light_world_to_view(center, direction),
+            unit_extent: 2.0 * radius,
+            resolution,
+        }
+    }
+}
+
+fn light_world_to_view(position: Vec3, direction: Vec3) -> Mat4 {
+    let forward = direction.normalize();
+    let up = if forward.y().abs() > 0.99 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_y()
+    };
+    let right = forward.cross(up).normalize();
+    let up = right.cross(forward);
+
+    let rotation = Mat4::from_cols(
+        right.extend(0.0),
+        up.extend(0.0),
+        (-forward).extend(0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    );
+
+    rotation.transpose() * Mat4::from_translation(-position)
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShadowAtlasDesc {
+    pub size: u32,
+    // A depth format; see `DepthRasterDesc`
+    pub format: i32,
+}
+
+impl ShadowAtlasDesc {
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            format: vk::Format::D32_SFLOAT.as_raw(),
+        }
+    }
+
+    pub fn with_format(mut self, format: vk::Format) -> Self {
+        self.format = format.as_raw();
+        self
+    }
+}
+
+// Filtering of shadow lookups. Only used when sampling, so tweaking them
+// doesn't re-render the atlas.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct ShadowParams {
+    // Depth offset towards the light, in depth buffer units
+    pub depth_bias: f32,
+    // Offset of the lookup position along the surface normal, in shadow map texels
+    pub normal_offset: f32,
+    // Lookups filter (2 * pcf_radius + 1)^2 bilinear comparisons
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowParams {
+    fn default() -> Self {
+        Self {
+            depth_bias: 1e-5,
+            normal_offset: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+// Must match `ShadowCaster` in `shadow_caster.inc`
+#[derive(Clone, Copy, Abomonation, Serialize)]
+#[repr(C)]
+struct GpuShadowCaster {
+    world_to_clip: [f32; 16],
+    // Offset and size of the tile, in atlas UV
+    atlas_uv_rect: [f32; 4],
+    // World size of a texel at a clip space `w` of 1
+    texel_world_size: [f32; 4],
+}
+
+// Places `sizes`, which must be powers of two, in a `atlas_size` square. Tiles go in
+// decreasing size along a Z-order curve, which keeps every tile aligned to its size.
+// Returns the offset of each tile, or `None` if they don't fit.
+fn pack_atlas_tiles(sizes: &[u32], atlas_size: u32) -> Option<Vec<[u32; 2]>> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i]));
+
+    let mut offsets = vec![[0, 0]; sizes.len()];
+    let mut morton_offset = 0u64;
+    for i in order {
+        let (x, y) = morton_decode(morton_offset);
+        if x + sizes[i] > atlas_size || y + sizes[i] > atlas_size {
+            return None;
+        }
+
+        offsets[i] = [x, y];
+        morton_offset += sizes[i] as u64 * sizes[i] as u64;
+    }
+
+    Some(offsets)
+}
+
+fn morton_decode(code: u64) -> (u32, u32) {
+    let compact = |mut v: u64| {
+        let mut res = 0u32;
+        let mut bit = 0;
+        while v != 0 {
+            res |= ((v & 1) as u32) << bit;
+            v >>= 2;
+            bit += 1;
+        }
+        res
+    };
+
+    (compact(code), compact(code >> 1))
+}
+
+#[derive(Clone)]
+pub struct ShadowAtlas {
+    pub atlas: SnoozyRef<Texture>,
+    pub casters: SnoozyRef<Buffer>,
+    pub caster_count: u32,
+    pub params: ShadowParams,
+}
+
+impl ShadowAtlas {
+    // Named as expected by `shadow_sampling.inc`.
+    pub fn uniforms(&self) -> ShaderUniformBundle {
+        shader_uniforms!(shadow: shader_uniforms!(
+            atlas: self.atlas.clone(),
+            casters: self.casters.clone(),
+            caster_count: self.caster_count,
+            depth_bias: self.params.depth_bias,
+            normal_offset: self.params.normal_offset,
+            pcf_radius: self.params.pcf_radius,
+        ))
+    }
+}
+
+// Renders the shadow maps of `casters` into an atlas. `scene` is as from `upload_raster_scene`.
+pub fn shadow_atlas(
+    casters: &[ShadowCaster],
+    desc: ShadowAtlasDesc,
+    params: ShadowParams,
+    scene: ShaderUniformBundle,
+) -> ShadowAtlas {
+    let mut tile_sizes: Vec<u32> = casters
+        .iter()
+        .map(|c| c.resolution.max(1).next_power_of_two().min(desc.size))
+        .collect();
+
+    let tile_offsets = loop {
+        if let Some(offsets) = pack_atlas_tiles(&tile_sizes, desc.size) {
+            break offsets;
+        }
+        if tile_sizes.iter().all(|&size| size == 1) {
+            panic!(
+                "Can't fit {} shadow casters in any atlas of size {}",
+                casters.len(),
+                desc.size
+            );
+        }
+
+        crate::rtoy_show_warning(format!(
+            "{} shadow casters don't fit in a {}x{} atlas; halving their resolution",
+            casters.len(),
+            desc.size,
+            desc.size
+        ));
+        for size in tile_sizes.iter_mut() {
+            *size = (*size / 2).max(1);
+        }
+    };
+
+    let clip_adjustment = coord_convention::coord_convention().clip_adjustment();
+    let atlas_size = desc.size as f32;
+    let gpu_casters: Vec<GpuShadowCaster> = casters
+        .iter()
+        .zip(tile_sizes.iter().zip(tile_offsets.iter()))
+        .map(|(caster, (&size, offset))| GpuShadowCaster {
+            world_to_clip: (clip_adjustment * caster.world_to_clip).to_cols_array(),
+            atlas_uv_rect: [
+                offset[0] as f32 / atlas_size,
+                offset[1] as f32 / atlas_size,
+                size as f32 / atlas_size,
+                size as f32 / atlas_size,
+            ],
+            texel_world_size: [caster.unit_extent / size as f32, 0.0, 0.0, 0.0],
+        })
+        .collect();
+    let gpu_casters = upload_array_buffer(Box::new(gpu_casters));
+
+    let pipeline = make_raster_depth_pipeline(
+        vec![load_vs_from_string(
+            include_str!("../assets/shaders/shadow_vs.glsl").to_owned(),
+            "shadow_vs.glsl".to_owned(),
+        )],
+        DepthRasterDesc::new(vk::Format::from_raw(desc.format)),
+    );
+
+    // Each caster draws the scene in its own scope
+    let mut uniforms = shader_uniforms!(shadow_casters: gpu_casters.clone());
+    for caster_index in 0..casters.len() as u32 {
+        uniforms.extend(shader_uniforms!(: shader_uniforms!(
+            caster_index: caster_index,
+            :scene.clone(),
+        )));
+    }
+
+    let atlas = raster_depth_tex(
+        TextureKey::new(desc.size, desc.size, vk::Format::from_raw(desc.format)),
+        pipeline,
+        uniforms,
+    );
+
+    ShadowAtlas {
+        atlas,
+        casters: gpu_casters,
+        caster_count: casters.len() as u32,
+        params,
+    }
+}
@@ -0,0 +1,163 @@
+// SPIR-V optimization after shaderc, through `spirv-opt` from SPIRV-Tools, as shipped
+// with the Vulkan SDK. Presets can be picked per shader, with a global default. Where
+// `spirv-opt` can't be run, shaderc's built-in optimizer is used at the matching level,
+// and the optimization time is then part of the compile time.
+//
+// Deterministic math disables optimization, as it may reorder floating point math.
+// Presets apply to shaders compiled after they're set.
+
+use crate::deterministic_math;
+use snoozy::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpirvOptPreset {
+    None,
+    Performance,
+    Size,
+}
+
+impl SpirvOptPreset {
+    // Part of the SPIR-V cache keys
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            SpirvOptPreset::None => "zero",
+            SpirvOptPreset::Performance => "performance",
+            SpirvOptPreset::Size => "size",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShaderCompileEvent {
+    pub shader: String,
+    pub preset: SpirvOptPreset,
+    pub compile_ms: f64,
+    // Only known when optimizing with `spirv-opt`
+    pub optimize_ms: Option<f64>,
+    pub unoptimized_size_bytes: Option<usize>,
+    pub size_bytes: usize,
+}
+
+struct SpirvOptState {
+    default_preset: SpirvOptPreset,
+    presets: HashMap<String, SpirvOptPreset>,
+    spirv_opt_path: String,
+    spirv_opt_available: Option<bool>,
+    events: Vec<ShaderCompileEvent>,
+}
+
+lazy_static! {
+    static ref SPIRV_OPT: Mutex<SpirvOptState> = Mutex::new(SpirvOptState {
+        default_preset: SpirvOptPreset::Performance,
+        presets: HashMap::new(),
+        spirv_opt_path: "spirv-opt".to_owned(),
+        spirv_opt_available: None,
+        events: Vec::new(),
+    });
+}
+
+pub fn set_default_spirv_opt_preset(preset: SpirvOptPreset) {
+    SPIRV_OPT.lock().unwrap().default_preset = preset;
+}
+
+// `shader_name` as in profiler scopes, e.g. the file stem of compute shaders.
+// `None` reverts to the default preset.
+pub fn set_spirv_opt_preset(shader_name: &str, preset: Option<SpirvOptPreset>) {
+    let mut state = SPIRV_OPT.lock().unwrap();
+    match preset {
+        Some(preset) => state.presets.insert(shader_name.to_owned(), preset),
+        None => state.presets.remove(shader_name),
+    };
+}
+
+// `spirv-opt` is looked up in `PATH` by default.
+pub fn set_spirv_opt_path(path: &str) {
+    let mut state = SPIRV_OPT.lock().unwrap();
+    state.spirv_opt_path = path.to_owned();
+    state.spirv_opt_available = None;
+}
+
+// Returns every compile since the last call, in order.
+pub fn take_shader_compile_events() -> Vec<ShaderCompileEvent> {
+    std::mem::replace(&mut SPIRV_OPT.lock().unwrap().events, Vec::new())
+}
+
+pub(crate) fn spirv_opt_preset(shader_name: &str) -> SpirvOptPreset {
+    if deterministic_math::is_deterministic_math_enabled() {
+        return SpirvOptPreset::None;
+    }
+
+    let state = SPIRV_OPT.lock().unwrap();
+    state
+        .presets
+        .get(shader_name)
+        .copied()
+        .unwrap_or(state.default_preset)
+}
+
+pub(crate) fn is_spirv_opt_available() -> bool {
+    let mut state = SPIRV_OPT.lock().unwrap();
+    if let Some(available) = state.spirv_opt_available {
+        return available;
+    }
+
+    let available = Command::new(&state.spirv_opt_path)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_or(false, |status| status.success());
+
+    if !available {
+        tracing::warn!(
+            "Could not run {}; falling back to shaderc's optimizer",
+            state.spirv_opt_path
+        );
+    }
+
+    state.spirv_opt_available = Some(available);
+    available
+}
+
+pub(crate) fn run_spirv_opt(spirv: &[u32], preset: SpirvOptPreset) -> Result<Vec<u32>> {
+    let flag = match preset {
+        SpirvOptPreset::None => return Ok(spirv.to_vec()),
+        SpirvOptPreset::Performance => "-O",
+        SpirvOptPreset::Size => "-Os",
+    };
+
+    let path = SPIRV_OPT.lock().unwrap().spirv_opt_path.clone();
+    let mut child = Command::new(&path)
+        .args(&[flag, "-", "-o", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let bytes: Vec<u8> = spirv
+        .iter()
+        .flat_map(|w| w.to_ne_bytes().to_vec())
+        .collect();
+    // spirv-opt reads all of its input before writing any output
+    child.stdin.take().unwrap().write_all(&bytes)?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    crate::shader::spirv_from_bytes(&output.stdout)
+        .map_err(|err| format_err!("{} produced invalid SPIR-V: {}", path, err))
+}
+
+pub(crate) fn record_compile_event(event: ShaderCompileEvent) {
+    SPIRV_OPT.lock().unwrap().events.push(event);
+}
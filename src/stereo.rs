@@ -0,0 +1,131 @@
+// Stereo rendering: the graph is built once per eye, with each eye getting its own camera,
+// and the two results are then either composited for a regular display, or handed
+// to a headset as separate images.
+
+use crate::camera::{Camera, CameraMatrices};
+use crate::math::*;
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub const BOTH: [Eye; 2] = [Eye::Left, Eye::Right];
+}
+
+// Wraps a regular camera, offsetting each eye by half of the interpupillary distance.
+pub struct StereoCamera<CameraType: Camera> {
+    pub camera: CameraType,
+    // In world units; 0.064 for an average adult in a meter-scale scene
+    pub ipd: f32,
+}
+
+impl<CameraType: Camera> StereoCamera<CameraType> {
+    pub fn new(camera: CameraType, ipd: f32) -> Self {
+        Self { camera, ipd }
+    }
+
+    pub fn calc_eye_matrices(&self, eye: Eye) -> CameraMatrices {
+        let matrices = self.camera.calc_matrices();
+        let offset = match eye {
+            Eye::Left => -0.5 * self.ipd,
+            Eye::Right => 0.5 * self.ipd,
+        };
+
+        CameraMatrices {
+            view_to_world: matrices.view_to_world
+                * Mat4::from_translation(Vec3::new(offset, 0.0, 0.0)),
+            world_to_view: Mat4::from_translation(Vec3::new(-offset, 0.0, 0.0))
+                * matrices.world_to_view,
+            ..matrices
+        }
+    }
+}
+
+impl<CameraType: Camera> Camera for StereoCamera<CameraType> {
+    type InputType = CameraType::InputType;
+
+    fn update<InputType: Into<Self::InputType>>(&mut self, input: InputType) {
+        self.camera.update(input);
+    }
+
+    fn calc_matrices(&self) -> CameraMatrices {
+        self.camera.calc_matrices()
+    }
+}
+
+// Per-eye results, e.g. for submission to a headset.
+#[derive(Clone)]
+pub struct StereoTextures {
+    pub left: SnoozyRef<Texture>,
+    pub right: SnoozyRef<Texture>,
+}
+
+impl StereoTextures {
+    // Builds the graph once per eye.
+    pub fn build(build: impl Fn(Eye) -> SnoozyRef<Texture>) -> Self {
+        Self {
+            left: build(Eye::Left),
+            right: build(Eye::Right),
+        }
+    }
+
+    pub fn get(&self, eye: Eye) -> &SnoozyRef<Texture> {
+        match eye {
+            Eye::Left => &self.left,
+            Eye::Right => &self.right,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Abomonation)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+// Packs both eyes into one texture, for regular displays and 3D TVs.
+#[snoozy]
+pub async fn stereo_layout_tex_snoozy(
+    mut ctx: Context,
+    left: &SnoozyRef<Texture>,
+    right: &SnoozyRef<Texture>,
+    layout: &StereoLayout,
+) -> Result<Texture> {
+    let eye_key = ctx
+        .get(left)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let (key, layout_mode) = match layout {
+        StereoLayout::SideBySide => (eye_key.with_width(eye_key.width * 2), 0u32),
+        StereoLayout::TopBottom => (eye_key.with_height(eye_key.height * 2), 1u32),
+    };
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/stereo_layout.glsl").to_owned(),
+        "stereo_layout.glsl".to_owned(),
+    );
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                texLeft: left.clone(),
+                texRight: right.clone(),
+                layout_mode: layout_mode,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
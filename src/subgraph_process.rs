@@ -0,0 +1,342 @@
+// Experimental: running parts of the graph in separate processes, to split up gigantic
+// workloads, or so that an unstable experimental pass can crash without taking the viewer
+// down with it.
+//
+// The viewer starts each subgraph as a child process with `start_subgraph_process`, and
+// uses its outputs through `subgraph_process_tex`. The child calls `run_subgraph_process`,
+// which renders a frame of its outputs whenever the viewer asks for one, and passes them
+// back through shared memory files. The viewer only asks for the next frame once the
+// previous one is in, so slow subgraphs fall behind instead of stalling the viewer.
+// Processes which exit or crash get restarted; their outputs keep the last frame meanwhile.
+//
+// The version of `vk-mem` in use can't allocate exportable memory, so rather than Vulkan
+// external memory, textures go through host memory: read back in the child, written to
+// tmpfs where available, and uploaded again in the viewer.
+
+use crate::headless::HeadlessCompute;
+use crate::host_interop::{upload_tex_from_slice, HostImageLayout};
+use crate::texture::{Texture, TextureKey};
+use ash::vk;
+use snoozy::*;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+// Tells the child process where to write its outputs
+const EXCHANGE_DIR_ENV: &str = "RENDERTOY_SUBGRAPH_DIR";
+// Lines the child prints to its stdout once a frame is written; anything else gets logged
+const FRAME_DONE_PREFIX: &str = "rendertoy-subgraph-frame-done ";
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// Width, height and format
+const OUTPUT_HEADER_BYTES: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubgraphFrame {
+    pub index: u64,
+    // Playback time of the viewer; see `time_control`
+    pub time: f64,
+}
+
+struct SubgraphWorker {
+    child: Child,
+    stdin: ChildStdin,
+    frames_done: mpsc::Receiver<u64>,
+}
+
+struct SubgraphProcess {
+    program: String,
+    args: Vec<String>,
+    exchange_dir: PathBuf,
+    worker: Option<SubgraphWorker>,
+    restart_at: Option<Instant>,
+    frame_in_flight: bool,
+    next_frame: u64,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref SUBGRAPH_PROCESSES: Mutex<HashMap<String, SubgraphProcess>> =
+        Mutex::new(HashMap::new());
+}
+
+fn exchange_dir_for(name: &str) -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    let base = if shm.is_dir() {
+        shm.to_owned()
+    } else {
+        std::env::temp_dir()
+    };
+
+    base.join(format!("rendertoy-{}-{}", std::process::id(), name))
+}
+
+fn output_path(exchange_dir: &Path, output: &str) -> PathBuf {
+    exchange_dir.join(format!("{}.tex", output))
+}
+
+// Starts `program` as the subgraph `name`, replacing any process already running under it.
+// The process is expected to call `run_subgraph_process`.
+pub fn start_subgraph_process(name: &str, program: &str, args: &[&str]) -> Result<()> {
+    stop_subgraph_process(name);
+
+    let exchange_dir = exchange_dir_for(name);
+    std::fs::create_dir_all(&exchange_dir)?;
+
+    let mut process = SubgraphProcess {
+        program: program.to_owned(),
+        args: args.iter().map(|a| (*a).to_owned()).collect(),
+        exchange_dir,
+        worker: None,
+        restart_at: None,
+        frame_in_flight: false,
+        next_frame: 0,
+        invalidation_triggers: Vec::new(),
+    };
+    process.worker = Some(process.spawn(name)?);
+
+    SUBGRAPH_PROCESSES
+        .lock()
+        .unwrap()
+        .insert(name.to_owned(), process);
+    Ok(())
+}
+
+// Kills the process, and removes its outputs.
+pub fn stop_subgraph_process(name: &str) {
+    let process = SUBGRAPH_PROCESSES.lock().unwrap().remove(name);
+    if let Some(mut process) = process {
+        if let Some(mut worker) = process.worker.take() {
+            let _ = worker.child.kill();
+            let _ = worker.child.wait();
+        }
+        let _ = std::fs::remove_dir_all(&process.exchange_dir);
+    }
+}
+
+impl SubgraphProcess {
+    fn spawn(&self, name: &str) -> Result<SubgraphWorker> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env(EXCHANGE_DIR_ENV, &self.exchange_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format_err!("Could not run {}: {}", self.program, err))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, frames_done) = mpsc::channel();
+        let name = name.to_owned();
+
+        // Ends, disconnecting the channel, once the process exits
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+
+                if line.starts_with(FRAME_DONE_PREFIX) {
+                    if let Ok(frame) = line[FRAME_DONE_PREFIX.len()..].trim().parse::<u64>() {
+                        let _ = sender.send(frame);
+                    }
+                } else {
+                    tracing::info!("[{}] {}", name, line);
+                }
+            }
+        });
+
+        tracing::info!("Started subgraph process {}", name);
+
+        Ok(SubgraphWorker {
+            child,
+            stdin,
+            frames_done,
+        })
+    }
+
+    fn update(&mut self, name: &str, time: f64, triggers: &mut Vec<Box<dyn Fn() + Send + Sync>>) {
+        if self.worker.is_none() {
+            match self.restart_at {
+                Some(restart_at) if Instant::now() < restart_at => return,
+                _ => {}
+            }
+
+            match self.spawn(name) {
+                Ok(worker) => self.worker = Some(worker),
+                Err(err) => {
+                    tracing::error!("Failed to restart subgraph process {}: {}", name, err);
+                    self.restart_at = Some(Instant::now() + RESTART_DELAY);
+                    return;
+                }
+            }
+        }
+
+        let worker = self.worker.as_mut().unwrap();
+
+        let mut exited = false;
+        loop {
+            match worker.frames_done.try_recv() {
+                Ok(_) => {
+                    self.frame_in_flight = false;
+                    triggers.extend(self.invalidation_triggers.drain(..));
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    exited = true;
+                    break;
+                }
+            }
+        }
+
+        if !exited && !self.frame_in_flight {
+            let request = format!("frame {} {}\n", self.next_frame, time);
+            if worker.stdin.write_all(request.as_bytes()).is_ok() && worker.stdin.flush().is_ok() {
+                self.next_frame += 1;
+                self.frame_in_flight = true;
+            } else {
+                exited = true;
+            }
+        }
+
+        if exited {
+            let mut worker = self.worker.take().unwrap();
+            let _ = worker.child.kill();
+            match worker.child.wait() {
+                Ok(status) => tracing::error!("Subgraph process {} exited: {}", name, status),
+                Err(err) => tracing::error!("Subgraph process {} was lost: {}", name, err),
+            }
+
+            self.frame_in_flight = false;
+            self.restart_at = Some(Instant::now() + RESTART_DELAY);
+        }
+    }
+}
+
+// Called every frame of the viewer.
+pub(crate) fn end_frame() {
+    let mut triggers = Vec::new();
+    {
+        let mut processes = SUBGRAPH_PROCESSES.lock().unwrap();
+        let time = crate::time_control::playback_time();
+        for (name, process) in processes.iter_mut() {
+            process.update(name, time, &mut triggers);
+        }
+    }
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// The latest frame of `output` from the subgraph `process`. Black until the first one is in.
+#[snoozy]
+pub async fn subgraph_process_tex_snoozy(
+    ctx: Context,
+    process: &String,
+    output: &String,
+) -> Result<Texture> {
+    let path = {
+        let mut processes = SUBGRAPH_PROCESSES.lock().unwrap();
+        let process = processes
+            .get_mut(process)
+            .ok_or_else(|| format_err!("No subgraph process named {}", process))?;
+
+        process
+            .invalidation_triggers
+            .push(Box::new(ctx.get_invalidation_trigger()));
+        output_path(&process.exchange_dir, output)
+    };
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return upload_tex_from_slice(
+                &[0u8; 4],
+                &HostImageLayout::packed(1, 1, vk::Format::R8G8B8A8_UNORM),
+            );
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if data.len() < OUTPUT_HEADER_BYTES {
+        bail!("{} is truncated", path.display());
+    }
+
+    let header_field = |i: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[i * 4..i * 4 + 4]);
+        u32::from_le_bytes(bytes)
+    };
+    let layout = HostImageLayout::packed(
+        header_field(0),
+        header_field(1),
+        vk::Format::from_raw(header_field(2) as i32),
+    );
+
+    upload_tex_from_slice(&data[OUTPUT_HEADER_BYTES..], &layout)
+}
+
+// Whether this process was started by `start_subgraph_process`.
+pub fn is_subgraph_process() -> bool {
+    std::env::var_os(EXCHANGE_DIR_ENV).is_some()
+}
+
+// The main loop of a subgraph process: renders `frame`'s outputs, named as passed to
+// `subgraph_process_tex` in the viewer, whenever it asks for a frame. Returns once the
+// viewer goes away. Only single-layer 2D outputs are supported.
+//
+// Stdout is used to talk to the viewer; anything else printed there shows up in its log.
+pub fn run_subgraph_process(
+    compute: &mut HeadlessCompute,
+    mut frame: impl FnMut(&SubgraphFrame) -> Vec<(String, SnoozyRef<Texture>)>,
+) -> Result<()> {
+    let exchange_dir = PathBuf::from(
+        std::env::var_os(EXCHANGE_DIR_ENV)
+            .ok_or_else(|| format_err!("Not started by start_subgraph_process"))?,
+    );
+
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let request = match (parts.next(), parts.next(), parts.next()) {
+            (Some("frame"), Some(index), Some(time)) => SubgraphFrame {
+                index: index.parse()?,
+                time: time.parse()?,
+            },
+            _ => bail!("Unexpected request from the viewer: {}", line),
+        };
+
+        for (output, tex) in frame(&request) {
+            let key = compute.eval(&tex).key;
+            let texels: Vec<u8> = compute.read_texture(&tex)?;
+            write_output(&exchange_dir, &output, &key, &texels)?;
+        }
+
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{}{}", FRAME_DONE_PREFIX, request.index)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn write_output(exchange_dir: &Path, output: &str, key: &TextureKey, texels: &[u8]) -> Result<()> {
+    let mut data = Vec::with_capacity(OUTPUT_HEADER_BYTES + texels.len());
+    data.extend_from_slice(&key.width.to_le_bytes());
+    data.extend_from_slice(&key.height.to_le_bytes());
+    data.extend_from_slice(&(key.format as u32).to_le_bytes());
+    data.extend_from_slice(texels);
+
+    // Renamed into place, so the viewer never reads a partially written frame
+    let path = output_path(exchange_dir, output);
+    let tmp_path = path.with_extension("tex.tmp");
+    std::fs::write(&tmp_path, &data)?;
+    std::fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
@@ -1,4 +1,6 @@
-pub use crate::backend::texture::{Texture, TextureKey};
+pub use crate::backend::texture::{
+    set_texture_format_fallback, Texture, TextureKey, TextureViewDesc,
+};
 
 use crate::backend::{self};
 use crate::blob::{load_blob, AssetPath, Blob};
@@ -38,11 +40,15 @@ pub struct RawRgba8Image {
 
 #[snoozy(cache)]
 pub async fn load_raw_ldr_tex_snoozy(mut ctx: Context, path: &AssetPath) -> Result<RawRgba8Image> {
-    use image::GenericImageView;
-
     let blob = ctx.get(&load_blob(path.clone())).await?;
+    decode_ldr_image(&blob.contents)
+}
+
+#[cfg(feature = "image-codecs")]
+fn decode_ldr_image(bytes: &[u8]) -> Result<RawRgba8Image> {
+    use image::GenericImageView;
 
-    let image = image::load_from_memory(&*blob.contents)?;
+    let image = image::load_from_memory(bytes)?;
     let image_dimensions = image.dimensions();
     tracing::info!("Loaded image: {:?} {:?}", image_dimensions, image.color());
 
@@ -54,6 +60,11 @@ pub async fn load_raw_ldr_tex_snoozy(mut ctx: Context, path: &AssetPath) -> Resu
     })
 }
 
+#[cfg(not(feature = "image-codecs"))]
+fn decode_ldr_image(_bytes: &[u8]) -> Result<RawRgba8Image> {
+    bail!("Image loading needs the `image-codecs` feature")
+}
+
 fn load_ldr_tex(image: &RawRgba8Image, params: &TexParams) -> Result<Texture> {
     let internal_format = if params.gamma == TexGamma::Linear {
         vk::Format::R8G8B8A8_UNORM
@@ -68,6 +79,16 @@ pub fn load_tex_impl(
     image_data: &[u8],
     image_dimensions: (u32, u32),
     internal_format: vk::Format,
+) -> Result<Texture> {
+    upload_tex_impl(image_data, image_dimensions, 0, internal_format)
+}
+
+// `row_length` is in texels, with zero meaning tightly packed rows.
+pub(crate) fn upload_tex_impl(
+    image_data: &[u8],
+    image_dimensions: (u32, u32),
+    row_length: u32,
+    internal_format: vk::Format,
 ) -> Result<Texture> {
     use crate::vulkan::*;
     use ash::util::Align;
@@ -143,6 +164,7 @@ pub fn load_tex_impl(
         );
 
         let buffer_copy_regions = vk::BufferImageCopy::builder()
+            .buffer_row_length(row_length)
             .image_subresource(
                 vk::ImageSubresourceLayers::builder()
                     .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -179,6 +201,7 @@ pub fn load_tex_impl(
     Ok(res)
 }
 
+#[cfg(feature = "image-codecs")]
 fn load_hdr_tex(blob: &Blob, _params: &TexParams) -> Result<Texture> {
     let img = hdrldr::load(blob.contents.as_slice()).map_err(|e| format_err!("{:?}", e))?;
 
@@ -193,6 +216,86 @@ fn load_hdr_tex(blob: &Blob, _params: &TexParams) -> Result<Texture> {
     )
 }
 
+#[cfg(not(feature = "image-codecs"))]
+fn load_hdr_tex(_blob: &Blob, _params: &TexParams) -> Result<Texture> {
+    bail!("Image loading needs the `image-codecs` feature")
+}
+
+// RGBA `f32` texels of the first layer of an EXR, at its largest resolution level.
+// Files with just a Y channel are taken as grayscale.
+#[cfg(feature = "image-codecs")]
+pub(crate) fn decode_exr(bytes: &[u8]) -> Result<(Vec<f32>, (u32, u32))> {
+    use exr::prelude::*;
+
+    fn create_texels(resolution: Vec2<usize>) -> (usize, Vec<f32>) {
+        (
+            resolution.width(),
+            vec![0.0; resolution.width() * resolution.height() * 4],
+        )
+    }
+
+    let rgba = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |resolution, _| create_texels(resolution),
+            |(width, texels): &mut (usize, Vec<f32>),
+             pos: Vec2<usize>,
+             (r, g, b, a): (f32, f32, f32, f32)| {
+                let i = (pos.y() * *width + pos.x()) * 4;
+                texels[i..i + 4].copy_from_slice(&[r, g, b, a]);
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(std::io::Cursor::new(bytes));
+
+    let layer = match rgba {
+        Ok(image) => image.layer_data,
+        Err(_) => {
+            read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .specific_channels()
+                .required("Y")
+                .collect_pixels(
+                    |resolution, _| create_texels(resolution),
+                    |(width, texels): &mut (usize, Vec<f32>), pos: Vec2<usize>, (y,): (f32,)| {
+                        let i = (pos.y() * *width + pos.x()) * 4;
+                        texels[i..i + 4].copy_from_slice(&[y, y, y, 1.0]);
+                    },
+                )
+                .first_valid_layer()
+                .all_attributes()
+                .from_buffered(std::io::Cursor::new(bytes))
+                .map_err(|err| format_err!("Could not read the EXR: {}", err))?
+                .layer_data
+        }
+    };
+
+    let size = layer.size;
+    let (_, texels) = layer.channel_data.pixels;
+    Ok((texels, (size.width() as u32, size.height() as u32)))
+}
+
+#[cfg(not(feature = "image-codecs"))]
+pub(crate) fn decode_exr(_bytes: &[u8]) -> Result<(Vec<f32>, (u32, u32))> {
+    bail!("Image loading needs the `image-codecs` feature")
+}
+
+// Always linear, so `params.gamma` doesn't apply, just as with `.hdr` files.
+fn load_exr_tex(blob: &Blob, _params: &TexParams) -> Result<Texture> {
+    let (texels, dims) = decode_exr(&blob.contents)?;
+
+    tracing::info!("Loaded image: {}x{} EXR", dims.0, dims.1);
+
+    let data =
+        unsafe { std::slice::from_raw_parts(texels.as_ptr() as *const u8, texels.len() * 4) };
+    load_tex_impl(data, dims, Format::R32G32B32A32_SFLOAT)
+}
+
+// TODO: mip chains. Loaded images only get their top level so far, which is also
+// what the `_mip_count` uniforms report.
 #[snoozy]
 pub async fn load_tex_with_params_snoozy(
     mut ctx: Context,
@@ -202,6 +305,9 @@ pub async fn load_tex_with_params_snoozy(
     if path.asset_name.ends_with(".hdr") {
         let blob = ctx.get(&load_blob(path.clone())).await?;
         load_hdr_tex(&*blob, params)
+    } else if path.asset_name.ends_with(".exr") {
+        let blob = ctx.get(&load_blob(path.clone())).await?;
+        load_exr_tex(&*blob, params)
     } else {
         let raw_img = ctx.get(&load_raw_ldr_tex(path.clone())).await?;
         load_ldr_tex(&*raw_img, params)
@@ -218,3 +324,323 @@ pub async fn make_placeholder_rgba8_tex_snoozy(
 
     load_tex_impl(texel_value, image_dimensions, internal_format)
 }
+
+// Views the image of `tex` with a different format and/or swizzle, e.g. an `R8G8B8A8_UNORM`
+// texture as `R32_UINT` for packing. No copies are made, so writes through either texture
+// are visible in the other one. Views of other formats need `tex` to be created from
+// a key with `with_aliasing`.
+#[snoozy]
+pub async fn texture_view_tex_snoozy(
+    mut ctx: Context,
+    tex: &SnoozyRef<Texture>,
+    desc: &TextureViewDesc,
+) -> Result<Texture> {
+    let tex = ctx.get(tex).await?;
+    backend::texture::create_texture_view(&*tex, *desc).map_err(|err| format_err!("{}", err))
+}
+
+// Views a single layer of an array texture as a regular 2D one, e.g. to bind it to a pass
+// which reads only that layer. Bind the array texture itself to access all of its layers.
+#[snoozy]
+pub async fn texture_layer_tex_snoozy(
+    mut ctx: Context,
+    tex: &SnoozyRef<Texture>,
+    layer: &u32,
+) -> Result<Texture> {
+    let tex = ctx.get(tex).await?;
+    let desc = TextureViewDesc::new(vk::Format::from_raw(tex.key.format)).with_layer(*layer);
+    backend::texture::create_texture_view(&*tex, desc).map_err(|err| format_err!("{}", err))
+}
+
+// Returns a copy of the array texture `array`, with `layer` replaced by the contents of `src`.
+// Other layers are copied as they are, so e.g. a history of the last N frames can be kept
+// by writing each new frame to `frame_index % N`.
+#[snoozy]
+pub async fn write_texture_layer_tex_snoozy(
+    mut ctx: Context,
+    array: &SnoozyRef<Texture>,
+    layer: &u32,
+    src: &SnoozyRef<Texture>,
+) -> Result<Texture> {
+    use crate::vulkan::*;
+    use ash::version::DeviceV1_0;
+
+    let array = ctx.get(array).await?;
+    let src = ctx.get(src).await?;
+    let layer = *layer;
+    let key = array.key;
+
+    if layer >= key.array_layers() {
+        bail!(
+            "Layer {} is out of range; the texture has {}",
+            layer,
+            key.array_layers()
+        );
+    }
+
+    if (src.key.width, src.key.height, src.key.array_layers()) != (key.width, key.height, 1) {
+        bail!(
+            "Can't write a {}x{}x{} texture into a layer of a {}x{} array",
+            src.key.width,
+            src.key.height,
+            src.key.array_layers(),
+            key.width,
+            key.height
+        );
+    }
+
+    let res = backend::texture::create_texture(key);
+
+    // Both after any format fallbacks
+    let (array_format, src_format) = (
+        vk::Format::from_raw(res.key.format),
+        vk::Format::from_raw(src.key.format),
+    );
+    if src_format != array_format
+        && !backend::texture::alias_compatible_formats(array_format).contains(&src_format)
+    {
+        bail!(
+            "Can't copy {:?} into a layer of a {:?} texture",
+            src_format,
+            array_format
+        );
+    }
+
+    let layers = |base_array_layer, layer_count| {
+        vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_array_layer(base_array_layer)
+            .layer_count(layer_count)
+            .build()
+    };
+    let extent = vk::Extent3D {
+        width: key.width,
+        height: key.height,
+        depth: 1,
+    };
+
+    // Non-overlapping, so no barriers are needed between the copies
+    let mut array_regions = Vec::new();
+    for (base, count) in [(0, layer), (layer + 1, key.array_layers() - layer - 1)].iter() {
+        if *count > 0 {
+            array_regions.push(
+                vk::ImageCopy::builder()
+                    .src_subresource(layers(*base, *count))
+                    .dst_subresource(layers(*base, *count))
+                    .extent(extent)
+                    .build(),
+            );
+        }
+    }
+    let src_region = vk::ImageCopy::builder()
+        .src_subresource(layers(0, 1))
+        .dst_subresource(layers(layer, 1))
+        .extent(extent)
+        .build();
+
+    let (array_image, src_image, res_image) = (array.image, src.image, res.image);
+    vk_add_setup_command(move |vk, vk_frame| {
+        let cb = vk_frame.command_buffer.lock().unwrap();
+        let cb: vk::CommandBuffer = cb.cb;
+
+        for image in [array_image, src_image].iter().copied() {
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    image,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                    vk_sync::AccessType::TransferRead,
+                ),
+            );
+        }
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                res_image,
+                vk_sync::AccessType::Nothing,
+                vk_sync::AccessType::TransferWrite,
+            )
+            .with_discard(true),
+        );
+
+        unsafe {
+            if !array_regions.is_empty() {
+                vk.device.cmd_copy_image(
+                    cb,
+                    array_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    res_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &array_regions,
+                );
+            }
+
+            vk.device.cmd_copy_image(
+                cb,
+                src_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                res_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[src_region],
+            );
+        }
+
+        for image in [array_image, src_image].iter().copied() {
+            record_image_barrier(
+                &vk.device,
+                cb,
+                ImageBarrier::new(
+                    image,
+                    vk_sync::AccessType::TransferRead,
+                    vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+                ),
+            );
+        }
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                res_image,
+                vk_sync::AccessType::TransferWrite,
+                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            ),
+        );
+    });
+
+    Ok(res)
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Abomonation, Clone, Copy)]
+pub enum ImageFileFormat {
+    // 8-bit sRGB, clamped to [0, 1]
+    Png,
+    // 32-bit float, linear
+    Exr,
+}
+
+// Packs `tex` into a buffer of row-major texels, converted as written to `format`.
+pub(crate) fn pack_tex_for_saving(
+    tex: SnoozyRef<Texture>,
+    key: &TextureKey,
+    format: ImageFileFormat,
+) -> SnoozyRef<crate::buffer::Buffer> {
+    use crate::buffer::BufferKey;
+    use crate::shader::{compute_buf, load_cs_from_string, ShaderUniformHolder};
+    use crate::shader_uniforms;
+
+    let (cs, texel_bytes) = match format {
+        ImageFileFormat::Png => (
+            load_cs_from_string(
+                include_str!("../assets/shaders/pack_rgba8_srgb.glsl").to_owned(),
+                "pack_rgba8_srgb.glsl".to_owned(),
+            ),
+            4,
+        ),
+        ImageFileFormat::Exr => (
+            load_cs_from_string(
+                include_str!("../assets/shaders/pack_rgba32f.glsl").to_owned(),
+                "pack_rgba32f.glsl".to_owned(),
+            ),
+            16,
+        ),
+    };
+
+    compute_buf(
+        BufferKey::new((key.width * key.height * texel_bytes) as usize, None),
+        [key.width, key.height, 1],
+        cs,
+        shader_uniforms!(inputTex: tex),
+    )
+}
+
+// Writes the output of `pack_tex_for_saving` to `path`.
+pub(crate) fn write_image_file(
+    path: &str,
+    texels: &[u8],
+    (width, height): (u32, u32),
+    format: ImageFileFormat,
+) -> Result<()> {
+    match format {
+        ImageFileFormat::Png => write_rgba8_png(path, texels, width, height)?,
+        ImageFileFormat::Exr => {
+            let texels: Vec<f32> = texels
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            write_rgba_f32_exr(path, &texels, width, height)?;
+        }
+    }
+
+    tracing::info!("Saved {}", path);
+    Ok(())
+}
+
+// Writes `tex` out to `path` as `format`. The pixels are read back from the GPU, so as with
+// `image_metric`, this must not be awaited by the frame which renders `tex`.
+#[snoozy]
+pub async fn save_tex_snoozy(
+    mut ctx: Context,
+    tex: &SnoozyRef<Texture>,
+    path: &String,
+    format: &ImageFileFormat,
+) -> Result<()> {
+    let key = ctx.get(tex).await?.key;
+    let packed = ctx
+        .get(pack_tex_for_saving(tex.clone(), &key, *format))
+        .await?;
+
+    let texels = crate::buffer::read_back_buffer(&packed).await?;
+    write_image_file(path, &texels, (key.width, key.height), *format)
+}
+
+// Writes `tex` out as an sRGB PNG; see `save_tex`.
+#[snoozy]
+pub async fn save_tex_png_snoozy(
+    mut ctx: Context,
+    tex: &SnoozyRef<Texture>,
+    path: &String,
+) -> Result<()> {
+    ctx.get(save_tex(tex.clone(), path.clone(), ImageFileFormat::Png))
+        .await?;
+    Ok(())
+}
+
+#[cfg(feature = "image-codecs")]
+fn write_rgba8_png(path: &str, texels: &[u8], width: u32, height: u32) -> Result<()> {
+    image::save_buffer(path, texels, width, height, image::ColorType::RGBA(8))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "image-codecs"))]
+fn write_rgba8_png(_path: &str, _texels: &[u8], _width: u32, _height: u32) -> Result<()> {
+    bail!("Saving PNGs needs the `image-codecs` feature")
+}
+
+#[cfg(feature = "image-codecs")]
+pub(crate) fn write_rgba_f32_exr(
+    path: &str,
+    texels: &[f32],
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    exr::prelude::write_rgba_file(path, width as usize, height as usize, |x, y| {
+        let i = (y * width as usize + x) * 4;
+        (texels[i], texels[i + 1], texels[i + 2], texels[i + 3])
+    })
+    .map_err(|err| format_err!("Could not write {}: {}", path, err))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "image-codecs"))]
+pub(crate) fn write_rgba_f32_exr(
+    _path: &str,
+    _texels: &[f32],
+    _width: u32,
+    _height: u32,
+) -> Result<()> {
+    bail!("Saving EXRs needs the `image-codecs` feature")
+}
@@ -0,0 +1,159 @@
+// The previous frame's value of a texture, for temporal effects, e.g.
+//
+//   let history = history_tex("taa".to_owned(), key);
+//   let taa = keep_history_tex(
+//       "taa".to_owned(),
+//       compute_tex(key, cs, shader_uniforms!(inputTex: input, historyTex: history)),
+//   );
+//
+// `keep_history_tex` passes its texture through, and `history_tex` under the same name
+// returns it during the next frame. Nothing gets copied: the texture is just kept alive for
+// another frame, and left in the sampled state, as all pass outputs are. Until a texture
+// was kept, or if its size or format differs from `key`, the history is cleared to zero.
+//
+// Outputs written with `OutputLoadOp::Load` are the same texture every frame, so their
+// history would be their current value; they already keep their previous contents though.
+
+use crate::backend::texture::resolve_texture_key;
+use crate::output_load_op;
+use crate::texture::{Texture, TextureKey};
+use crate::vulkan::*;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use snoozy::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct TextureHistory {
+    // Kept during the frame in progress
+    pending: Option<Texture>,
+    // Returned by `history_tex`
+    previous: Option<Texture>,
+    invalidation_triggers: Vec<Box<dyn Fn() + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref TEXTURE_HISTORY: Mutex<HashMap<String, TextureHistory>> = Mutex::new(HashMap::new());
+}
+
+// Passes `tex` through, keeping it as the history of `name` for the next frame.
+#[snoozy]
+pub async fn keep_history_tex_snoozy(
+    mut ctx: Context,
+    name: &String,
+    tex: &SnoozyRef<Texture>,
+) -> Result<Texture> {
+    let tex = (*ctx.get(tex).await?).clone();
+    TEXTURE_HISTORY
+        .lock()
+        .unwrap()
+        .entry(name.clone())
+        .or_default()
+        .pending = Some(tex.clone());
+    Ok(tex)
+}
+
+// The texture last passed to `keep_history_tex` under `name` before this frame.
+#[snoozy]
+pub async fn history_tex_snoozy(ctx: Context, name: &String, key: &TextureKey) -> Result<Texture> {
+    let mut histories = TEXTURE_HISTORY.lock().unwrap();
+    let history = histories.entry(name.clone()).or_default();
+    history
+        .invalidation_triggers
+        .push(Box::new(ctx.get_invalidation_trigger()));
+
+    match &history.previous {
+        Some(tex) if tex.key == resolve_texture_key(*key) => Ok(tex.clone()),
+        _ => Ok(create_cleared_texture(*key)),
+    }
+}
+
+// Forgets all history, e.g. when jumping in time; passes start over from zero.
+pub fn reset_texture_history() {
+    let triggers: Vec<_> = {
+        let mut histories = TEXTURE_HISTORY.lock().unwrap();
+        histories
+            .values_mut()
+            .flat_map(|history| {
+                history.pending = None;
+                history.previous = None;
+                history.invalidation_triggers.drain(..).collect::<Vec<_>>()
+            })
+            .collect()
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Called at the end of every frame. Histories which weren't kept again keep their value,
+// as the op producing it didn't run, so its value didn't change.
+pub(crate) fn end_frame() {
+    let mut triggers = Vec::new();
+    {
+        let mut histories = TEXTURE_HISTORY.lock().unwrap();
+        for history in histories.values_mut() {
+            if let Some(tex) = history.pending.take() {
+                if history.previous.as_ref().map(|t| t.image) != Some(tex.image) {
+                    history.previous = Some(tex);
+                    triggers.extend(history.invalidation_triggers.drain(..));
+                }
+            }
+        }
+    }
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+fn create_cleared_texture(key: TextureKey) -> Texture {
+    let tex = crate::backend::texture::create_texture(key);
+    let (image, format) = (tex.image, vk::Format::from_raw(key.format));
+
+    vk_add_setup_command(move |vk, vk_frame| {
+        let cb = vk_frame.command_buffer.lock().unwrap();
+        let cb: vk::CommandBuffer = cb.cb;
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                image,
+                vk_sync::AccessType::Nothing,
+                vk_sync::AccessType::TransferWrite,
+            )
+            .with_discard(true),
+        );
+
+        unsafe {
+            vk.device.cmd_clear_color_image(
+                cb,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &output_load_op::clear_color_value(format, [0.0; 4]),
+                &[vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                }],
+            );
+        }
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                image,
+                vk_sync::AccessType::TransferWrite,
+                vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            ),
+        );
+    });
+
+    tex
+}
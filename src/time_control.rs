@@ -0,0 +1,112 @@
+// Playback time for animated shaders, exposed as `FrameState::time`. It advances by
+// the frame's delta time scaled by the speed, unless paused, and can be scrubbed
+// to any point, e.g. to step through an animation frame by frame.
+//
+// Temporal passes accumulating over frames can't follow a jump in time. With history
+// resets enabled, scrubbing releases the outputs kept by `OutputLoadOp::Load` and the
+// textures kept by `keep_history_tex`, and apps with their own accumulators can poll
+// `take_time_scrubbed`.
+
+use crate::output_load_op;
+use crate::texture_history;
+use std::sync::Mutex;
+
+struct TimeControlState {
+    time: f64,
+    paused: bool,
+    speed: f32,
+    reset_history_on_scrub: bool,
+    scrubbed: bool,
+}
+
+lazy_static! {
+    static ref TIME_CONTROL: Mutex<TimeControlState> = Mutex::new(TimeControlState {
+        time: 0.0,
+        paused: false,
+        speed: 1.0,
+        reset_history_on_scrub: false,
+        scrubbed: false,
+    });
+}
+
+pub fn pause_time() {
+    TIME_CONTROL.lock().unwrap().paused = true;
+}
+
+pub fn resume_time() {
+    TIME_CONTROL.lock().unwrap().paused = false;
+}
+
+pub fn is_time_paused() -> bool {
+    TIME_CONTROL.lock().unwrap().paused
+}
+
+// Jumps to `seconds`; works while paused too.
+pub fn set_time(seconds: f64) {
+    let reset_history = {
+        let mut state = TIME_CONTROL.lock().unwrap();
+        state.time = seconds;
+        state.scrubbed = true;
+        state.reset_history_on_scrub
+    };
+
+    if reset_history {
+        output_load_op::reset_persistent_outputs();
+        texture_history::reset_texture_history();
+    }
+}
+
+// Steps by `seconds` regardless of the speed, e.g. a frame at a time while paused.
+pub fn step_time(seconds: f64) {
+    let time = TIME_CONTROL.lock().unwrap().time;
+    set_time(time + seconds);
+}
+
+// Multiplies the delta time of frames; negative speeds play backwards. 1 by default.
+pub fn set_time_speed(speed: f32) {
+    TIME_CONTROL.lock().unwrap().speed = speed;
+}
+
+pub fn time_speed() -> f32 {
+    TIME_CONTROL.lock().unwrap().speed
+}
+
+// How many seconds playback time advances per second of wall clock time.
+pub(crate) fn time_rate() -> f32 {
+    let state = TIME_CONTROL.lock().unwrap();
+    if state.paused {
+        0.0
+    } else {
+        state.speed
+    }
+}
+
+// Off by default.
+pub fn set_reset_history_on_scrub(reset: bool) {
+    TIME_CONTROL.lock().unwrap().reset_history_on_scrub = reset;
+}
+
+// Playback time in seconds, as of the current frame.
+pub fn playback_time() -> f64 {
+    TIME_CONTROL.lock().unwrap().time
+}
+
+// Whether time was scrubbed since the last call.
+pub fn take_time_scrubbed() -> bool {
+    std::mem::replace(&mut TIME_CONTROL.lock().unwrap().scrubbed, false)
+}
+
+// Called once per frame with the wall clock delta time; returns the new playback time.
+pub(crate) fn advance_time(dt: f32) -> f64 {
+    let mut state = TIME_CONTROL.lock().unwrap();
+    if !state.paused {
+        state.time += (dt * state.speed) as f64;
+    }
+    state.time
+}
+
+// Takes the playback time from elsewhere, e.g. a net sync master, instead of advancing it.
+pub(crate) fn follow_time(seconds: f64) -> f64 {
+    TIME_CONTROL.lock().unwrap().time = seconds;
+    seconds
+}
@@ -0,0 +1,579 @@
+// Constant folding of uniforms which don't change, trading compile time for GPU time in
+// long-running installations:
+//
+//   set_uniform_folding_enabled(true);
+//   set_uniform_folding_frames(300);
+//
+// Compute passes then keep track of the scalar and vector members of their uniform blocks.
+// Once some stay the same for that many consecutive dispatches, a variant of the shader
+// with their values baked in as constants is compiled in the background. Dispatches use it
+// while the folded values still match, and go back to the regular pipeline as soon as they
+// don't, without waiting for anything.
+//
+// Tracked per shader and pass name. Passes whose folded values keep changing stop getting
+// new variants after a few. Shaders being autotuned aren't folded.
+
+use crate::shader::ComputePipeline;
+use crate::spirv_opt::{self, SpirvOptPreset};
+use rspirv::binary::Assemble;
+use rspirv::dr::{self, Operand};
+use snoozy::*;
+use spirv_headers::{Decoration, Op, StorageClass};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+const MAX_FOLDED_VARIANTS: usize = 4;
+
+enum VariantState {
+    Compiling,
+    Ready(ComputePipeline),
+    Failed,
+}
+
+struct FoldedVariant {
+    values: Vec<(String, Vec<u32>)>,
+    state: Arc<Mutex<VariantState>>,
+}
+
+impl FoldedVariant {
+    fn matches(&self, current: &HashMap<String, Vec<u32>>) -> bool {
+        self.values
+            .iter()
+            .all(|(name, value)| current.get(name) == Some(value))
+    }
+}
+
+struct PassFoldState {
+    // Handle of the regular pipeline the state was gathered for
+    pipeline_key: u64,
+    spirv: Arc<Vec<u32>>,
+    members: Vec<FoldableMember>,
+    last_values: HashMap<String, Vec<u32>>,
+    // Consecutive dispatches each member kept its value for
+    stable_counts: HashMap<String, u32>,
+    variants: Vec<FoldedVariant>,
+}
+
+struct UniformFoldingState {
+    enabled: bool,
+    frames: u32,
+    // By shader and pass name. Replaced when the shader is recompiled.
+    passes: HashMap<(String, String), PassFoldState>,
+}
+
+lazy_static! {
+    static ref UNIFORM_FOLDING: Mutex<UniformFoldingState> = Mutex::new(UniformFoldingState {
+        enabled: false,
+        frames: 120,
+        passes: HashMap::new(),
+    });
+}
+
+pub fn set_uniform_folding_enabled(enabled: bool) {
+    let mut state = UNIFORM_FOLDING.lock().unwrap();
+    state.enabled = enabled;
+    if !enabled {
+        state.passes.clear();
+    }
+}
+
+pub fn is_uniform_folding_enabled() -> bool {
+    UNIFORM_FOLDING.lock().unwrap().enabled
+}
+
+// How many consecutive dispatches a uniform needs to keep its value for to be folded.
+pub fn set_uniform_folding_frames(frames: u32) {
+    UNIFORM_FOLDING.lock().unwrap().frames = frames.max(1);
+}
+
+// The specialized pipeline to dispatch instead of the regular one, if there's one
+// for the current values. `uniform_words` returns the raw value of a uniform by name.
+pub(crate) fn folded_pipeline(
+    shader_name: &str,
+    pipeline_key: u64,
+    pass_name: &str,
+    spirv: impl FnOnce() -> Vec<u32>,
+    uniform_words: impl Fn(&str) -> Option<Vec<u32>>,
+    build: impl FnOnce(Vec<u32>) -> Result<ComputePipeline> + Send + 'static,
+) -> Option<ComputePipeline> {
+    let mut state = UNIFORM_FOLDING.lock().unwrap();
+    if !state.enabled {
+        return None;
+    }
+
+    let frames = state.frames;
+    let key = (shader_name.to_owned(), pass_name.to_owned());
+    let recompiled = state
+        .passes
+        .get(&key)
+        .map_or(false, |pass| pass.pipeline_key != pipeline_key);
+    if recompiled {
+        // Variants of the previous code would be stale
+        state.passes.remove(&key);
+    }
+
+    let pass = state.passes.entry(key).or_insert_with(|| {
+        let spirv = spirv();
+        PassFoldState {
+            pipeline_key,
+            members: foldable_members(&spirv).unwrap_or_default(),
+            spirv: Arc::new(spirv),
+            last_values: HashMap::new(),
+            stable_counts: HashMap::new(),
+            variants: Vec::new(),
+        }
+    });
+
+    let current: HashMap<String, Vec<u32>> = pass
+        .members
+        .iter()
+        .filter_map(|member| {
+            uniform_words(&member.name)
+                .filter(|words| words.len() == member.component_count)
+                .map(|words| (member.name.clone(), words))
+        })
+        .collect();
+
+    for (name, value) in current.iter() {
+        let count = pass.stable_counts.entry(name.clone()).or_insert(0);
+        if pass.last_values.get(name) == Some(value) {
+            *count += 1;
+        } else {
+            *count = 0;
+            pass.last_values.insert(name.clone(), value.clone());
+        }
+    }
+
+    let mut matched = false;
+    for variant in pass.variants.iter().filter(|v| v.matches(&current)) {
+        if let VariantState::Ready(ref pipeline) = *variant.state.lock().unwrap() {
+            return Some(pipeline.clone());
+        }
+        matched = true;
+    }
+
+    let mut values: Vec<(String, Vec<u32>)> = current
+        .into_iter()
+        .filter(|(name, _)| pass.stable_counts[name] >= frames)
+        .collect();
+    if matched || values.is_empty() || pass.variants.len() >= MAX_FOLDED_VARIANTS {
+        return None;
+    }
+    values.sort();
+
+    let variant_state = Arc::new(Mutex::new(VariantState::Compiling));
+    pass.variants.push(FoldedVariant {
+        values: values.clone(),
+        state: variant_state.clone(),
+    });
+
+    let spirv = pass.spirv.clone();
+    let shader_name = shader_name.to_owned();
+    let pass_name = pass_name.to_owned();
+    std::thread::spawn(move || {
+        let res = fold_uniforms(&spirv, &values).and_then(|spirv| {
+            let preset = spirv_opt::spirv_opt_preset(&shader_name);
+            if preset != SpirvOptPreset::None && spirv_opt::is_spirv_opt_available() {
+                build(spirv_opt::run_spirv_opt(&spirv, preset)?)
+            } else {
+                build(spirv)
+            }
+        });
+
+        *variant_state.lock().unwrap() = match res {
+            Ok(pipeline) => {
+                tracing::info!(
+                    "Specialized {} with {} folded uniforms",
+                    pass_name,
+                    values.len()
+                );
+                VariantState::Ready(pipeline)
+            }
+            Err(err) => {
+                tracing::warn!("Could not fold the uniforms of {}: {}", pass_name, err);
+                VariantState::Failed
+            }
+        };
+    });
+
+    None
+}
+
+struct FoldableMember {
+    name: String,
+    component_count: usize,
+}
+
+// What's known about the uniform blocks of a module
+struct BlockInfo {
+    // Block variable id -> struct type id
+    variables: HashMap<u32, u32>,
+    // (struct type id, member index) -> name
+    member_names: HashMap<(u32, u32), String>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    // 32 bit scalar and vector types -> component count, and the component type of vectors
+    value_types: HashMap<u32, (usize, Option<u32>)>,
+    int_constants: HashMap<u32, u32>,
+}
+
+impl BlockInfo {
+    fn new(module: &dr::Module) -> Self {
+        let mut blocks = HashSet::new();
+        let mut pointers = HashMap::new();
+        let mut res = BlockInfo {
+            variables: HashMap::new(),
+            member_names: HashMap::new(),
+            struct_members: HashMap::new(),
+            value_types: HashMap::new(),
+            int_constants: HashMap::new(),
+        };
+
+        for inst in module.global_inst_iter() {
+            let result_id = inst.result_id.unwrap_or(0);
+            match (inst.class.opcode, &inst.operands[..]) {
+                (Op::MemberName, [Operand::IdRef(ty), Operand::LiteralInt32(member), name]) => {
+                    let name = match name {
+                        Operand::LiteralString(name) => name,
+                        _ => continue,
+                    };
+                    res.member_names.insert((*ty, *member), name.clone());
+                }
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(ty), Operand::Decoration(Decoration::Block), ..],
+                ) => {
+                    blocks.insert(*ty);
+                }
+                (Op::TypeInt, [Operand::LiteralInt32(32), ..])
+                | (Op::TypeFloat, [Operand::LiteralInt32(32), ..]) => {
+                    res.value_types.insert(result_id, (1, None));
+                }
+                (Op::TypeVector, [Operand::IdRef(component), Operand::LiteralInt32(count)])
+                    if res.value_types.contains_key(component) =>
+                {
+                    res.value_types
+                        .insert(result_id, (*count as usize, Some(*component)));
+                }
+                (Op::TypeStruct, members) => {
+                    res.struct_members
+                        .insert(result_id, members.iter().filter_map(id_ref).collect());
+                }
+                (Op::TypePointer, [_, Operand::IdRef(pointee)]) => {
+                    pointers.insert(result_id, *pointee);
+                }
+                (Op::Constant, [Operand::LiteralInt32(value)]) => {
+                    res.int_constants.insert(result_id, *value);
+                }
+                (Op::Variable, [Operand::StorageClass(storage_class), ..]) => {
+                    if *storage_class != StorageClass::Uniform
+                        && *storage_class != StorageClass::PushConstant
+                    {
+                        continue;
+                    }
+                    let pointee = inst.result_type.and_then(|ty| pointers.get(&ty));
+                    if let Some(pointee) = pointee {
+                        if blocks.contains(pointee) {
+                            res.variables.insert(result_id, *pointee);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        res
+    }
+
+    // Name and type of a member of a block
+    fn member(&self, struct_id: u32, member: u32) -> Option<(&String, u32)> {
+        let name = self.member_names.get(&(struct_id, member))?;
+        let ty = *self.struct_members.get(&struct_id)?.get(member as usize)?;
+        Some((name, ty))
+    }
+}
+
+fn id_ref(operand: &Operand) -> Option<u32> {
+    match operand {
+        Operand::IdRef(id) => Some(*id),
+        _ => None,
+    }
+}
+
+fn load_module(spirv: &[u32]) -> Result<dr::Module> {
+    let mut loader = dr::Loader::new();
+    rspirv::binary::parse_words(spirv, &mut loader)
+        .map_err(|err| format_err!("Could not parse SPIR-V: {:?}", err))?;
+    Ok(loader.module())
+}
+
+fn foldable_members(spirv: &[u32]) -> Result<Vec<FoldableMember>> {
+    let module = load_module(spirv)?;
+    let info = BlockInfo::new(&module);
+
+    let mut res = Vec::new();
+    for struct_id in info.variables.values() {
+        let member_count = info.struct_members.get(struct_id).map_or(0, |m| m.len());
+        for member in 0..member_count as u32 {
+            if let Some((name, ty)) = info.member(*struct_id, member) {
+                if let Some((component_count, _)) = info.value_types.get(&ty) {
+                    res.push(FoldableMember {
+                        name: name.clone(),
+                        component_count: *component_count,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// Replaces loads of the named block members, or of their components, with `values`.
+fn fold_uniforms(spirv: &[u32], values: &[(String, Vec<u32>)]) -> Result<Vec<u32>> {
+    let mut module = load_module(spirv)?;
+    let info = BlockInfo::new(&module);
+    let values: HashMap<&str, &Vec<u32>> = values.iter().map(|(n, v)| (n.as_str(), v)).collect();
+
+    let mut bound = module
+        .header
+        .as_ref()
+        .ok_or_else(|| format_err!("SPIR-V module without a header"))?
+        .bound;
+    let mut new_constants: Vec<dr::Instruction> = Vec::new();
+
+    // Access chain id -> the value it points at, or one of its components
+    let mut chains: HashMap<u32, (&Vec<u32>, Option<usize>)> = HashMap::new();
+
+    let instructions = module
+        .functions
+        .iter_mut()
+        .flat_map(|f| f.basic_blocks.iter_mut())
+        .flat_map(|b| b.instructions.iter_mut());
+
+    for inst in instructions {
+        match inst.class.opcode {
+            Op::AccessChain | Op::InBoundsAccessChain => {
+                let indices: Vec<u32> = inst.operands.iter().filter_map(id_ref).collect();
+                let (base, member, component) = match indices[..] {
+                    [base, member] => (base, member, None),
+                    [base, member, component] => (base, member, Some(component)),
+                    _ => continue,
+                };
+
+                let struct_id = match info.variables.get(&base) {
+                    Some(struct_id) => *struct_id,
+                    None => continue,
+                };
+                let member = match info.int_constants.get(&member) {
+                    Some(member) => *member,
+                    None => continue,
+                };
+                let component = match component {
+                    Some(id) => match info.int_constants.get(&id) {
+                        Some(c) => Some(*c as usize),
+                        None => continue,
+                    },
+                    None => None,
+                };
+
+                if let (Some((name, _)), Some(result_id)) =
+                    (info.member(struct_id, member), inst.result_id)
+                {
+                    if let Some(value) = values.get(name.as_str()) {
+                        chains.insert(result_id, (value, component));
+                    }
+                }
+            }
+            Op::Load => {
+                let pointer = match inst.operands.first().and_then(id_ref) {
+                    Some(pointer) => pointer,
+                    None => continue,
+                };
+                let (value, component) = match chains.get(&pointer) {
+                    Some(chain) => *chain,
+                    None => continue,
+                };
+                let result_type = match inst.result_type {
+                    Some(result_type) => result_type,
+                    None => continue,
+                };
+
+                let words: Vec<u32> = match component {
+                    Some(c) => match value.get(c) {
+                        Some(word) => vec![*word],
+                        None => continue,
+                    },
+                    None => value.clone(),
+                };
+
+                let constant_id = match info.value_types.get(&result_type) {
+                    Some((count, None)) if *count == words.len() => {
+                        let id = bound;
+                        bound += 1;
+                        new_constants.push(dr::Instruction::new(
+                            Op::Constant,
+                            Some(result_type),
+                            Some(id),
+                            vec![Operand::LiteralInt32(words[0])],
+                        ));
+                        id
+                    }
+                    Some((count, Some(component_type))) if *count == words.len() => {
+                        let mut components = Vec::new();
+                        for word in words.iter() {
+                            components.push(Operand::IdRef(bound));
+                            new_constants.push(dr::Instruction::new(
+                                Op::Constant,
+                                Some(*component_type),
+                                Some(bound),
+                                vec![Operand::LiteralInt32(*word)],
+                            ));
+                            bound += 1;
+                        }
+
+                        let id = bound;
+                        bound += 1;
+                        new_constants.push(dr::Instruction::new(
+                            Op::ConstantComposite,
+                            Some(result_type),
+                            Some(id),
+                            components,
+                        ));
+                        id
+                    }
+                    _ => continue,
+                };
+
+                // The load's result now copies the constant, so its uses needn't change
+                *inst = dr::Instruction::new(
+                    Op::CopyObject,
+                    Some(result_type),
+                    inst.result_id,
+                    vec![Operand::IdRef(constant_id)],
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if new_constants.is_empty() {
+        bail!("No loads of the folded uniforms found");
+    }
+
+    module.types_global_values.extend(new_constants);
+    module.header.as_mut().unwrap().bound = bound;
+
+    Ok(module.assemble())
+}
+
+#[cfg(feature = "shaderc")]
+#[cfg(test)]
+fn compile_test_shader() -> Vec<u32> {
+    let source = r#"
+        #version 450
+        layout(local_size_x = 8) in;
+        layout(std140) uniform globals {
+            float scale;
+            vec4 tint;
+            uint count;
+        };
+        layout(std430) buffer out_buf { vec4 result[]; };
+        void main() {
+            uint i = gl_GlobalInvocationID.x;
+            if (i < count) {
+                result[i] = tint * scale + vec4(tint.y);
+            }
+        }
+    "#;
+
+    let mut compiler = shaderc::Compiler::new().unwrap();
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    options.set_generate_debug_info();
+    options.set_auto_bind_uniforms(true);
+    compiler
+        .compile_into_spirv(
+            source,
+            shaderc::ShaderKind::Compute,
+            "test.glsl",
+            "main",
+            Some(&options),
+        )
+        .unwrap()
+        .as_binary()
+        .to_vec()
+}
+
+#[cfg(feature = "shaderc")]
+#[test]
+fn test_foldable_members() {
+    let mut members: Vec<(String, usize)> = foldable_members(&compile_test_shader())
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.name, m.component_count))
+        .collect();
+    members.sort();
+
+    assert_eq!(
+        members,
+        vec![
+            ("count".to_owned(), 1),
+            ("scale".to_owned(), 1),
+            ("tint".to_owned(), 4)
+        ]
+    );
+}
+
+#[cfg(feature = "shaderc")]
+#[test]
+fn test_fold_uniforms() {
+    let spirv = compile_test_shader();
+    let tint: Vec<u32> = [0.5f32, 1.0, 2.0, 1.0]
+        .iter()
+        .map(|f| f.to_bits())
+        .collect();
+    let folded = fold_uniforms(
+        &spirv,
+        &[
+            ("scale".to_owned(), vec![3.0f32.to_bits()]),
+            ("tint".to_owned(), tint),
+        ],
+    )
+    .unwrap();
+
+    let module = load_module(&folded).unwrap();
+    let info = BlockInfo::new(&module);
+    let body: Vec<&dr::Instruction> = module
+        .functions
+        .iter()
+        .flat_map(|f| f.basic_blocks.iter())
+        .flat_map(|b| b.instructions.iter())
+        .collect();
+
+    assert!(body.iter().any(|inst| inst.class.opcode == Op::CopyObject));
+    assert!(module
+        .types_global_values
+        .iter()
+        .any(|inst| inst.class.opcode == Op::ConstantComposite));
+
+    // Only `count` is still read from the block
+    let block_chains: HashSet<u32> = body
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::AccessChain)
+        .filter(|inst| {
+            let base = inst.operands.first().and_then(id_ref);
+            base.map_or(false, |base| info.variables.contains_key(&base))
+        })
+        .filter_map(|inst| inst.result_id)
+        .collect();
+    let block_loads = body
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Load)
+        .filter(|inst| {
+            let pointer = inst.operands.first().and_then(id_ref);
+            pointer.map_or(false, |pointer| block_chains.contains(&pointer))
+        })
+        .count();
+    assert_eq!(block_loads, 1);
+
+    assert!(fold_uniforms(&spirv, &[("missing".to_owned(), vec![0])]).is_err());
+}
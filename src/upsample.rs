@@ -0,0 +1,59 @@
+// Depth-aware upsampling, for effects such as SSAO, GI or volumetrics rendered at reduced
+// resolution. Plain bilinear upsampling bleeds them across geometric edges; here, each of
+// the four nearest low resolution texels is weighted by how similar its depth, and
+// optionally normal, is to that of the full resolution pixel (joint bilateral upsampling).
+//
+// The effect is assumed to have sampled depth at the center of its texels, as with
+// `key.res_div_round_up(2, 2)` and point sampling of the full resolution depth.
+
+use crate::shader::{compute_tex, load_cs_from_string, ShaderUniformHolder};
+use crate::shader_uniforms;
+use crate::texture::Texture;
+use ash::vk;
+use snoozy::*;
+
+// A relative difference of 1/32 in view distance scales weights down by e
+const DEPTH_SHARPNESS: f32 = 32.0;
+const NORMAL_POWER: f32 = 8.0;
+
+// Upsamples `effect` to the size of `depth`, in `R16G16B16A16_SFLOAT`. `depth` is the
+// full resolution depth buffer, as per `coord_convention`, and `normals`, if any, hold
+// signed unit normals in `xyz`, in any space.
+#[snoozy]
+pub async fn depth_aware_upsample_tex_snoozy(
+    mut ctx: Context,
+    effect: &SnoozyRef<Texture>,
+    depth: &SnoozyRef<Texture>,
+    normals: &Option<SnoozyRef<Texture>>,
+) -> Result<Texture> {
+    let key = ctx
+        .get(depth)
+        .await?
+        .key
+        .with_format(vk::Format::R16G16B16A16_SFLOAT);
+
+    let cs = load_cs_from_string(
+        include_str!("../assets/shaders/depth_aware_upsample.glsl").to_owned(),
+        "depth_aware_upsample.glsl".to_owned(),
+    );
+
+    // Something needs to be bound either way
+    let normal_tex = normals.clone().unwrap_or_else(|| depth.clone());
+
+    let tex = ctx
+        .get(compute_tex(
+            key,
+            cs,
+            shader_uniforms!(
+                effectTex: effect.clone(),
+                depthTex: depth.clone(),
+                normalTex: normal_tex,
+                use_normals: normals.is_some() as u32,
+                depth_sharpness: DEPTH_SHARPNESS,
+                normal_power: NORMAL_POWER,
+            ),
+        ))
+        .await?;
+
+    Ok((*tex).clone())
+}
@@ -0,0 +1,239 @@
+// Encodes the output of the windowed render loop to a video file while it runs, so live
+// sessions can be captured without external recording software. Each frame is packed to
+// sRGB RGBA8 on the GPU, read back, and piped to `ffmpeg`, which encodes it to H.264 or
+// HEVC; the container follows from the file extension, e.g. `.mp4`. Vulkan Video isn't
+// exposed by the version of `ash` in use, so encoding always goes through `ffmpeg`.
+//
+// Videos have a fixed frame rate. Rendered frames are repeated or dropped to follow
+// the wall clock, so the video plays back in real time. Frames get written once their
+// readback completes, a few frames late; if the encoder falls behind, the render loop
+// waits for it.
+
+use crate::buffer::{read_back_buffer, Buffer};
+use crate::texture::{pack_tex_for_saving, ImageFileFormat, Texture, TextureKey};
+use futures::future::{BoxFuture, FutureExt};
+use snoozy::*;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+// Frames waiting to be encoded before the render loop blocks
+const MAX_QUEUED_FRAMES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+}
+
+impl VideoCodec {
+    fn ffmpeg_encoder(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::Hevc => "libx265",
+        }
+    }
+}
+
+struct VideoEncoder {
+    size: (u32, u32),
+    frames: mpsc::SyncSender<Vec<u8>>,
+    writer: JoinHandle<()>,
+}
+
+struct VideoCapture {
+    path: String,
+    codec: VideoCodec,
+    fps: u32,
+    start: Instant,
+    // Started with the first frame, once the size is known
+    encoder: Option<VideoEncoder>,
+    // Readbacks in flight, with the capture time of their frame in seconds
+    pending: VecDeque<(f64, BoxFuture<'static, Result<Vec<u8>>>)>,
+    frames_written: u64,
+    size_mismatch_reported: bool,
+}
+
+struct VideoCaptureState {
+    ffmpeg_path: String,
+    capture: Option<VideoCapture>,
+}
+
+lazy_static! {
+    static ref VIDEO_CAPTURE: Mutex<VideoCaptureState> = Mutex::new(VideoCaptureState {
+        ffmpeg_path: "ffmpeg".to_owned(),
+        capture: None,
+    });
+}
+
+// Finishes any capture in progress first.
+pub fn start_video_capture(path: &str, codec: VideoCodec, fps: u32) {
+    stop_video_capture();
+
+    VIDEO_CAPTURE.lock().unwrap().capture = Some(VideoCapture {
+        path: path.to_owned(),
+        codec,
+        fps: fps.max(1),
+        start: Instant::now(),
+        encoder: None,
+        pending: VecDeque::new(),
+        frames_written: 0,
+        size_mismatch_reported: false,
+    });
+}
+
+// Waits for the encoder to finish writing the file. Frames still being read back are lost.
+pub fn stop_video_capture() {
+    let capture = VIDEO_CAPTURE.lock().unwrap().capture.take();
+    if let Some(encoder) = capture.and_then(|capture| capture.encoder) {
+        finish_encoder(encoder);
+    }
+}
+
+pub fn is_video_capture_active() -> bool {
+    VIDEO_CAPTURE.lock().unwrap().capture.is_some()
+}
+
+// `ffmpeg` is looked up in `PATH` by default.
+pub fn set_ffmpeg_path(path: &str) {
+    VIDEO_CAPTURE.lock().unwrap().ffmpeg_path = path.to_owned();
+}
+
+// Row-major sRGB RGBA8 texels of `tex`, with the size of `key`, as the encoder takes them.
+pub(crate) fn pack_video_frame(tex: SnoozyRef<Texture>, key: &TextureKey) -> SnoozyRef<Buffer> {
+    pack_tex_for_saving(tex, key, ImageFileFormat::Png)
+}
+
+// Called every frame while capturing, with the output of `pack_video_frame`.
+pub(crate) fn capture_video_frame(packed: &Buffer, size: (u32, u32)) {
+    let mut state = VIDEO_CAPTURE.lock().unwrap();
+    let ffmpeg_path = state.ffmpeg_path.clone();
+    let capture = match state.capture.as_mut() {
+        Some(capture) => capture,
+        None => return,
+    };
+
+    if capture.encoder.is_none() {
+        match spawn_encoder(&ffmpeg_path, capture, size) {
+            Ok(encoder) => capture.encoder = Some(encoder),
+            Err(err) => {
+                tracing::error!("Failed to start the video encoder: {}", err);
+                state.capture = None;
+                return;
+            }
+        }
+    }
+
+    if let Err(err) = write_ready_frames(capture) {
+        tracing::error!("Video capture of {} failed: {}", capture.path, err);
+        let capture = state.capture.take().unwrap();
+        drop(state);
+        finish_encoder(capture.encoder.unwrap());
+        return;
+    }
+
+    // The encoder takes frames of a fixed size
+    if capture.encoder.as_ref().unwrap().size != size {
+        if !capture.size_mismatch_reported {
+            capture.size_mismatch_reported = true;
+            tracing::warn!(
+                "The output was resized; skipping frames not matching the video size of {:?}",
+                capture.encoder.as_ref().unwrap().size
+            );
+        }
+        return;
+    }
+
+    let time = capture.start.elapsed().as_secs_f64();
+    capture
+        .pending
+        .push_back((time, read_back_buffer(packed).boxed()));
+}
+
+fn spawn_encoder(
+    ffmpeg_path: &str,
+    capture: &VideoCapture,
+    size: (u32, u32),
+) -> Result<VideoEncoder> {
+    let mut child = Command::new(ffmpeg_path)
+        .args(&["-y", "-loglevel", "error"])
+        .args(&["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(&["-s", &format!("{}x{}", size.0, size.1)])
+        .args(&["-r", &capture.fps.to_string(), "-i", "-"])
+        .args(&[
+            "-c:v",
+            capture.codec.ffmpeg_encoder(),
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        // 4:2:0 chroma needs even dimensions
+        .args(&["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+        .arg(&capture.path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| format_err!("Could not run {}: {}", ffmpeg_path, err))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let (frames, receiver) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED_FRAMES);
+    let path = capture.path.clone();
+
+    let writer = std::thread::spawn(move || {
+        for frame in receiver {
+            if let Err(err) = stdin.write_all(&frame) {
+                tracing::error!("Failed to write to the video encoder: {}", err);
+                break;
+            }
+        }
+
+        // Closing the pipe lets ffmpeg finish the file
+        drop(stdin);
+        match child.wait() {
+            Ok(status) if status.success() => tracing::info!("Saved {}", path),
+            Ok(status) => tracing::error!("Encoding {} failed: {}", path, status),
+            Err(err) => tracing::error!("Encoding {} failed: {}", path, err),
+        }
+    });
+
+    tracing::info!("Capturing video to {}", capture.path);
+
+    Ok(VideoEncoder {
+        size,
+        frames,
+        writer,
+    })
+}
+
+fn write_ready_frames(capture: &mut VideoCapture) -> Result<()> {
+    let encoder = capture.encoder.as_ref().unwrap();
+
+    loop {
+        let frame = match capture.pending.front_mut() {
+            Some((_, readback)) => match readback.now_or_never() {
+                Some(frame) => frame?,
+                None => break,
+            },
+            None => break,
+        };
+        let (time, _) = capture.pending.pop_front().unwrap();
+
+        // Repeat or drop frames to follow the wall clock
+        let due_frames = (time * capture.fps as f64) as u64 + 1;
+        while capture.frames_written < due_frames {
+            encoder
+                .frames
+                .send(frame.clone())
+                .map_err(|_| format_err!("The video encoder has quit"))?;
+            capture.frames_written += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn finish_encoder(encoder: VideoEncoder) {
+    drop(encoder.frames);
+    let _ = encoder.writer.join();
+}
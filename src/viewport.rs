@@ -66,8 +66,10 @@ impl VieportConstantBuilder {
     }
 
     pub fn build(self) -> ViewConstants {
-        let view_to_clip = self.camera_matrices.view_to_clip;
-        let clip_to_view = self.camera_matrices.clip_to_view;
+        // Cameras produce matrices for the default convention
+        let clip_adjustment = crate::coord_convention::coord_convention().clip_adjustment();
+        let view_to_clip = clip_adjustment * self.camera_matrices.view_to_clip;
+        let clip_to_view = self.camera_matrices.clip_to_view * clip_adjustment;
 
         let mut res = ViewConstants {
             view_to_clip,
@@ -1,12 +1,16 @@
 //use ash::extensions::nv::RayTracing;
 use crate::gpu_profiler::GpuProfilerQueryId;
+use crate::occlusion_query::OcclusionQueryPool;
+use crate::readback_ring::ReadbackRing;
+use crate::shader_instrumentation::ShaderAsanBuffer;
 use crate::vk_render_device::*;
 use crate::vulkan::{vk, vk_add_setup_command, vk_all, with_vk_state_mut};
 use ash::extensions::khr::{Surface, Swapchain};
 use ash::version::{DeviceV1_0, InstanceV1_0};
 use ash::{vk, Device};
+use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Mutex;
+use std::sync::{LockResult, Mutex, MutexGuard};
 
 pub struct VkCommandBufferData {
     pub(crate) cb: vk::CommandBuffer,
@@ -21,6 +25,30 @@ impl Drop for VkCommandBufferData {
     }
 }
 
+// The frame's command buffer. `raster_tex` defers recording its passes, so that it can
+// merge them with following ones into one render pass; locking records them first,
+// so commands stay in the order of the ops which recorded them.
+pub struct FrameCommandBuffer(Mutex<VkCommandBufferData>);
+
+impl FrameCommandBuffer {
+    fn new(data: VkCommandBufferData) -> Self {
+        Self(Mutex::new(data))
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<VkCommandBufferData>> {
+        let guard = self.0.lock();
+        if let Ok(guard) = guard.as_ref() {
+            crate::shader::flush_pending_raster_passes(guard.cb);
+        }
+        guard
+    }
+
+    // For recording the deferred passes, and for resetting the command buffer
+    pub(crate) fn lock_without_flush(&self) -> LockResult<MutexGuard<VkCommandBufferData>> {
+        self.0.lock()
+    }
+}
+
 pub struct LinearUniformBuffer {
     write_head: std::sync::atomic::AtomicUsize,
     buffer: vk::Buffer,
@@ -281,9 +309,12 @@ impl VkProfilerData {
 pub struct VkFrameData {
     pub uniforms: LinearUniformBuffer,
     pub descriptor_pool: Mutex<vk::DescriptorPool>,
-    pub command_buffer: Mutex<VkCommandBufferData>,
+    pub command_buffer: FrameCommandBuffer,
     pub submit_done_fence: vk::Fence,
     pub profiler_data: VkProfilerData,
+    pub shader_asan: ShaderAsanBuffer,
+    pub occlusion_queries: OcclusionQueryPool,
+    pub readback: ReadbackRing,
     pub frame_cleanup: Mutex<Vec<Box<dyn Fn(&VkRenderDevice) + Send + Sync>>>,
 }
 
@@ -305,6 +336,14 @@ impl Drop for VkFrameData {
 
 pub const SAMPLER_LINEAR: usize = 0;
 pub const SAMPLER_LINEAR_CLAMP: usize = 1;
+// Depth comparison samplers for shadow maps, e.g. from `raster_depth_tex`
+pub const SAMPLER_SHADOW_LESS: usize = 2;
+pub const SAMPLER_SHADOW_LESS_OR_EQUAL: usize = 3;
+pub const SAMPLER_SHADOW_GREATER: usize = 4;
+pub const SAMPLER_SHADOW_GREATER_OR_EQUAL: usize = 5;
+
+// Frames in flight when there's no swapchain to dictate the count
+const HEADLESS_FRAME_COUNT: usize = 2;
 
 pub struct VkBackendState {
     pub swapchain: Option<VkSwapchain>,
@@ -565,10 +604,10 @@ pub(crate) fn create_swapchain(
 }
 
 impl VkBackendState {
+    // Without a window, frames are driven by `begin_headless_frame` instead of the swapchain.
     pub(crate) fn new(
         render_device: &VkRenderDevice,
-        window: &winit::Window,
-        _graphics_debugging: bool,
+        window: Option<&Window>,
         vsync: bool,
     ) -> Result<Self, Box<dyn Error>> {
         let device = &render_device.device;
@@ -580,29 +619,36 @@ impl VkBackendState {
 
         let allocator = &render_device.allocator;
 
-        let physical_dimensions = window
-            .get_inner_size()
-            .unwrap()
-            .to_physical(window.get_hidpi_factor());
+        let surface_resolution = window.map_or(vk::Extent2D::default(), window_physical_size);
 
         unsafe {
             let swapchain_create_info = VkSwapchainCreateInfo {
                 surface_format: surface_format,
-                surface_resolution: vk::Extent2D {
-                    width: physical_dimensions.width as u32,
-                    height: physical_dimensions.height as u32,
-                },
+                surface_resolution,
                 vsync,
             };
-            let swapchain = create_swapchain(
-                device,
-                pdevice,
-                &swapchain_loader,
-                &surface_loader,
-                surface,
-                swapchain_create_info,
-            )
-            .unwrap();
+            let swapchain = if window.is_some() {
+                Some(
+                    create_swapchain(
+                        device,
+                        pdevice,
+                        &swapchain_loader,
+                        &surface_loader,
+                        surface,
+                        swapchain_create_info,
+                    )
+                    .unwrap(),
+                )
+            } else {
+                None
+            };
+            let depth_resolution = swapchain.as_ref().map_or(
+                vk::Extent2D {
+                    width: 1,
+                    height: 1,
+                },
+                |swapchain| swapchain.surface_resolution,
+            );
 
             let bindless_buffers_descriptor_set =
                 VkRenderDevice::create_bindless_resource_descriptor_set(
@@ -619,15 +665,19 @@ impl VkBackendState {
                 .image_type(vk::ImageType::TYPE_2D)
                 .format(vk::Format::D32_SFLOAT)
                 .extent(vk::Extent3D {
-                    width: swapchain.surface_resolution.width,
-                    height: swapchain.surface_resolution.height,
+                    width: depth_resolution.width,
+                    height: depth_resolution.height,
                     depth: 1,
                 })
                 .mip_levels(1)
                 .array_layers(1)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+                // Copied out by the texture visualizer
+                .usage(
+                    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
+                )
                 .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
             let (depth_image, _allocation, _allocation_info) = allocator
@@ -674,7 +724,7 @@ impl VkBackendState {
             });
 
             let mut res = Self {
-                swapchain: Some(swapchain),
+                swapchain,
                 swapchain_acquired_semaphore_idx: 0,
                 swapchain_create_info,
                 frame_data: Vec::new(),
@@ -716,6 +766,43 @@ impl VkBackendState {
         })
     }
 
+    // Advances to the next set of frame data without involving a swapchain.
+    pub fn begin_headless_frame(&mut self) -> BeginFrameState {
+        let present_index = self
+            .current_frame_data_idx
+            .map_or(0, |idx| (idx + 1) % self.frame_data.len());
+        self.current_frame_data_idx = Some(present_index);
+
+        BeginFrameState {
+            present_index,
+            wait_semaphore: vk::Semaphore::null(),
+            signal_semaphore: vk::Semaphore::null(),
+        }
+    }
+
+    // Waits for the GPU to finish the current frame, and runs its cleanup callbacks,
+    // completing any readbacks recorded in it.
+    pub fn finish_headless_frame(&self) {
+        let vk = vk();
+        let vk_frame = self.current_frame();
+        unsafe {
+            vk.device
+                .wait_for_fences(&[vk_frame.submit_done_fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+        }
+
+        // Right away, rather than when the frame slot is next used
+        vk_frame
+            .shader_asan
+            .report_previous_violations(&vk.allocator);
+
+        vk_frame.readback.invalidate(&vk.allocator);
+        for f in vk_frame.frame_cleanup.lock().unwrap().drain(..) {
+            (f)(vk);
+        }
+        vk_frame.readback.reset();
+    }
+
     pub fn get_begin_frame_state(&self) -> BeginFrameState {
         let present_index = self.current_frame_data_idx.unwrap();
 
@@ -839,7 +926,14 @@ impl VkBackendState {
     }
 
     pub(crate) fn create_frame_data(&mut self, vk: &VkRenderDevice) {
-        self.frame_data = (0..self.swapchain.as_ref().unwrap().present_images.len())
+        let frame_count = self
+            .swapchain
+            .as_ref()
+            .map_or(HEADLESS_FRAME_COUNT, |swapchain| {
+                swapchain.present_images.len()
+            });
+
+        self.frame_data = (0..frame_count)
             .map(|_| {
                 let uniforms = LinearUniformBuffer::new(
                     1 << 20,
@@ -861,16 +955,22 @@ impl VkBackendState {
                 .expect("Create fence failed.");
 
                 let profiler_data = VkProfilerData::new(&vk.device, &vk.allocator);
+                let shader_asan = ShaderAsanBuffer::new(&vk.allocator);
+                let occlusion_queries = OcclusionQueryPool::new(&vk.device, &vk.allocator);
+                let readback = ReadbackRing::new(&vk.allocator);
 
                 VkFrameData {
                     uniforms,
                     descriptor_pool: Mutex::new(allocate_frame_descriptor_pool(&vk.device)),
-                    command_buffer: Mutex::new(allocate_frame_command_buffer(
+                    command_buffer: FrameCommandBuffer::new(allocate_frame_command_buffer(
                         &vk.device,
                         vk.present_queue_family_index,
                     )),
                     submit_done_fence,
                     profiler_data,
+                    shader_asan,
+                    occlusion_queries,
+                    readback,
                     frame_cleanup: Mutex::new(Default::default()),
                 }
             })
@@ -967,13 +1067,64 @@ pub enum BeginFrameErr {
     RecreateFramebuffer,
 }
 
+lazy_static! {
+    // How each image was last accessed, as of the barriers recorded so far. Buffers aren't
+    // tracked: compute passes writing them end with a global barrier instead.
+    static ref IMAGE_ACCESS: Mutex<HashMap<vk::Image, vk_sync::AccessType>> =
+        Mutex::new(HashMap::new());
+}
+
+// `Nothing` for images which haven't had a barrier yet.
+pub fn tracked_image_access(image: vk::Image) -> vk_sync::AccessType {
+    IMAGE_ACCESS
+        .lock()
+        .unwrap()
+        .get(&image)
+        .copied()
+        .unwrap_or(vk_sync::AccessType::Nothing)
+}
+
+// Reads which don't need a barrier after one of the same kind
+fn is_read_only_access(access: vk_sync::AccessType) -> bool {
+    match access {
+        vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer
+        | vk_sync::AccessType::ComputeShaderReadSampledImageOrUniformTexelBuffer
+        | vk_sync::AccessType::FragmentShaderReadSampledImageOrUniformTexelBuffer
+        | vk_sync::AccessType::TransferRead
+        | vk_sync::AccessType::HostRead => true,
+        _ => false,
+    }
+}
+
+// Like `record_image_barrier`, but from the tracked access of `image`, rather than a known
+// one. Skipped if the image is already being read the same way. Discarding still waits
+// for previous accesses, as transient images get reused across passes.
+pub fn record_image_transition(
+    device: &Device,
+    cb: vk::CommandBuffer,
+    image: vk::Image,
+    next_access: vk_sync::AccessType,
+    discard: bool,
+) {
+    let prev_access = tracked_image_access(image);
+    if !discard && prev_access == next_access && is_read_only_access(next_access) {
+        return;
+    }
+
+    record_image_barrier(
+        device,
+        cb,
+        ImageBarrier::new(image, prev_access, next_access).with_discard(discard),
+    );
+}
+
 pub fn record_image_barrier(device: &Device, cb: vk::CommandBuffer, barrier: ImageBarrier) {
     let range = vk::ImageSubresourceRange {
         aspect_mask: vk::ImageAspectFlags::COLOR,
         base_mip_level: 0,
         level_count: 1,
         base_array_layer: 0,
-        layer_count: 1,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
     };
 
     vk_sync::cmd::pipeline_barrier(
@@ -993,6 +1144,11 @@ pub fn record_image_barrier(device: &Device, cb: vk::CommandBuffer, barrier: Ima
             range,
         }],
     );
+
+    IMAGE_ACCESS
+        .lock()
+        .unwrap()
+        .insert(barrier.image, barrier.next_access);
 }
 
 pub fn record_image_aspect_barrier(
@@ -1006,7 +1162,7 @@ pub fn record_image_aspect_barrier(
         base_mip_level: 0,
         level_count: 1,
         base_array_layer: 0,
-        layer_count: 1,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
     };
 
     vk_sync::cmd::pipeline_barrier(
@@ -1026,6 +1182,11 @@ pub fn record_image_aspect_barrier(
             range,
         }],
     );
+
+    IMAGE_ACCESS
+        .lock()
+        .unwrap()
+        .insert(barrier.image, barrier.next_access);
 }
 
 impl Drop for VkBackendState {
@@ -1044,7 +1205,9 @@ impl Drop for VkBackendState {
             /*self.swapchain_loader
             .destroy_swapchain(self.swapchain.swapchain, None);*/
             vk.device.destroy_device(None);
-            vk.surface_loader.destroy_surface(vk.surface, None);
+            if vk.surface != vk::SurfaceKHR::null() {
+                vk.surface_loader.destroy_surface(vk.surface, None);
+            }
             if let Some(debug_report_loader) = vk.debug_report_loader.as_ref() {
                 debug_report_loader
                     .destroy_debug_report_callback(vk.debug_call_back.unwrap(), None);
@@ -1082,6 +1245,16 @@ pub fn begin_render_frame<F: FnOnce(&VkRenderDevice, usize, vk::Image, vk::Image
                 ),
             );
 
+            vk_state
+                .current_frame()
+                .shader_asan
+                .report_previous_violations(&vk.allocator);
+
+            vk_state
+                .current_frame()
+                .occlusion_queries
+                .report_previous_results(&vk.allocator);
+
             vk_state.map_uniforms();
         });
 
@@ -1091,16 +1264,18 @@ pub fn begin_render_frame<F: FnOnce(&VkRenderDevice, usize, vk::Image, vk::Image
 
             {
                 {
-                    let cb = vk_frame.command_buffer.lock().unwrap();
+                    let cb = vk_frame.command_buffer.lock_without_flush().unwrap();
 
                     vk.device
                         .reset_command_buffer(cb.cb, vk::CommandBufferResetFlags::RELEASE_RESOURCES)
                         .expect("Reset command buffer failed.");
                 }
 
+                vk_frame.readback.invalidate(&vk.allocator);
                 for f in vk_frame.frame_cleanup.lock().unwrap().drain(..) {
                     (f)(vk);
                 }
+                vk_frame.readback.reset();
 
                 {
                     let pool = vk_frame.descriptor_pool.lock().unwrap();
@@ -1114,13 +1289,15 @@ pub fn begin_render_frame<F: FnOnce(&VkRenderDevice, usize, vk::Image, vk::Image
                     .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
                 {
-                    let cb = vk_frame.command_buffer.lock().unwrap();
+                    let cb = vk_frame.command_buffer.lock_without_flush().unwrap();
                     let cb = cb.cb;
                     vk.device
                         .begin_command_buffer(cb, &command_buffer_begin_info)
                         .expect("Begin commandbuffer");
 
                     vk_frame.profiler_data.begin_frame(&vk.device, cb);
+                    vk_frame.shader_asan.begin_frame(&vk.device, cb);
+                    vk_frame.occlusion_queries.begin_frame(&vk.device, cb);
                 }
             }
 
@@ -1128,21 +1305,40 @@ pub fn begin_render_frame<F: FnOnce(&VkRenderDevice, usize, vk::Image, vk::Image
                 f(vk, vk_frame);
             }
 
-            let swapchain = vk_state.swapchain.as_ref().unwrap();
-            render_fn(
-                vk,
-                begin_frame_state.present_index,
-                swapchain.present_images[begin_frame_state.present_index],
-                swapchain.present_image_views[begin_frame_state.present_index],
-            );
+            // Headless frames have nothing to present to
+            if let Some(swapchain) = vk_state.swapchain.as_ref() {
+                render_fn(
+                    vk,
+                    begin_frame_state.present_index,
+                    swapchain.present_images[begin_frame_state.present_index],
+                    swapchain.present_image_views[begin_frame_state.present_index],
+                );
+            }
         }
     }
 }
 
 pub fn end_render_frame(begin_frame_state: &BeginFrameState) {
-    let wait_mask: &[vk::PipelineStageFlags] = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-    let wait_semaphores: &[vk::Semaphore] = &[begin_frame_state.wait_semaphore];
-    let signal_semaphores: &[vk::Semaphore] = &[begin_frame_state.signal_semaphore];
+    // Headless frames don't synchronize with a swapchain
+    let headless = begin_frame_state.wait_semaphore == vk::Semaphore::null();
+    let wait_mask: &[vk::PipelineStageFlags] = if headless {
+        &[]
+    } else {
+        &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT]
+    };
+    let wait_semaphores: &[vk::Semaphore] = if headless {
+        &[]
+    } else {
+        &[begin_frame_state.wait_semaphore]
+    };
+    let signal_semaphores: &[vk::Semaphore] = if headless {
+        &[]
+    } else {
+        &[begin_frame_state.signal_semaphore]
+    };
+
+    // Deferred raster passes write uniforms, so must be recorded while those are mapped
+    drop(vk_all().1.current_frame().command_buffer.lock().unwrap());
 
     unsafe {
         with_vk_state_mut(|vk| {
@@ -1157,6 +1353,8 @@ pub fn end_render_frame(begin_frame_state: &BeginFrameState) {
             let cb = cb.cb;
 
             vk_frame.profiler_data.finish_frame(&vk.device, cb);
+            vk_frame.shader_asan.finish_frame(&vk.device, cb);
+            vk_frame.occlusion_queries.finish_frame(&vk.device, cb);
 
             vk.device.end_command_buffer(cb).expect("End commandbuffer");
 
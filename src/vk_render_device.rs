@@ -1,38 +1,192 @@
 //use ash::extensions::nv::RayTracing;
+use crate::device_caps::DeviceCaps;
 use ash::extensions::{
-    ext::DebugReport,
+    ext::{DebugReport, DebugUtils},
     khr::{Surface, Swapchain},
 };
 use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0, InstanceV1_1};
 use ash::{vk, Device, Entry, Instance};
+use std::collections::HashSet;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_void};
+use std::sync::Mutex;
+
+// Which parts of the Khronos validation layer to enable. Any of them loads the layer,
+// and its messages get logged. The first of each kind is also shown as a warning.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ValidationOptions {
+    pub standard: bool,
+    // Instruments shaders to check descriptor indexing and buffer accesses on the GPU.
+    // Slow, and takes up one of the descriptor set slots.
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+}
+
+impl ValidationOptions {
+    pub fn standard() -> Self {
+        Self {
+            standard: true,
+            ..Default::default()
+        }
+    }
+
+    fn layer_enabled(&self) -> bool {
+        self.standard || self.gpu_assisted || self.best_practices
+    }
+
+    fn enabled_validation_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        features
+    }
+
+    // The layer runs the standard checks unless told otherwise
+    fn disabled_validation_features(&self) -> Vec<vk::ValidationFeatureDisableEXT> {
+        if !self.layer_enabled() || self.standard {
+            return Vec::new();
+        }
+
+        let mut features = vec![
+            vk::ValidationFeatureDisableEXT::THREAD_SAFETY,
+            vk::ValidationFeatureDisableEXT::API_PARAMETERS,
+            vk::ValidationFeatureDisableEXT::OBJECT_LIFETIMES,
+            vk::ValidationFeatureDisableEXT::UNIQUE_HANDLES,
+        ];
+
+        // GPU-assisted validation is part of the core checks
+        if !self.gpu_assisted {
+            features.push(vk::ValidationFeatureDisableEXT::CORE_CHECKS);
+            features.push(vk::ValidationFeatureDisableEXT::SHADERS);
+        }
+
+        features
+    }
+}
+
+lazy_static! {
+    // Message ids already shown as warnings
+    static ref SHOWN_VALIDATION_MESSAGES: Mutex<HashSet<(i32, String)>> =
+        Mutex::new(HashSet::new());
+}
 
 unsafe extern "system" fn vulkan_debug_callback(
-    _: vk::DebugReportFlagsEXT,
+    flags: vk::DebugReportFlagsEXT,
     _: vk::DebugReportObjectTypeEXT,
     _: u64,
     _: usize,
-    _: i32,
+    message_code: i32,
     _: *const c_char,
     p_message: *const c_char,
     _: *mut c_void,
 ) -> u32 {
-    tracing::error!("{:?}", CStr::from_ptr(p_message));
+    let message = CStr::from_ptr(p_message).to_string_lossy();
+
+    // Messages without an id are told apart by their text
+    let id = (
+        message_code,
+        if message_code == 0 {
+            message.to_string()
+        } else {
+            String::new()
+        },
+    );
+    if !SHOWN_VALIDATION_MESSAGES.lock().unwrap().insert(id) {
+        // Repeats, e.g. every frame; only logged in detail
+        tracing::debug!("{}", message);
+        return vk::FALSE;
+    }
+
+    if flags.contains(vk::DebugReportFlagsEXT::ERROR) {
+        tracing::error!("{}", message);
+    } else {
+        tracing::warn!("{}", message);
+    }
+
+    crate::warnings::rtoy_show_warning(format!("Vulkan validation: {}", message));
     vk::FALSE
 }
 
-fn extension_names(graphics_debugging: bool) -> Vec<*const i8> {
+fn extension_names(validation: &ValidationOptions, debug_utils: bool) -> Vec<*const i8> {
     let mut names = vec![vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr()];
 
-    if graphics_debugging {
+    if validation.layer_enabled() {
         names.push(DebugReport::name().as_ptr());
     }
 
+    if debug_utils {
+        names.push(DebugUtils::name().as_ptr());
+    }
+
+    // Provided by the validation layer
+    if !validation.enabled_validation_features().is_empty()
+        || !validation.disabled_validation_features().is_empty()
+    {
+        names.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+    }
+
     names
 }
 
+#[cfg(feature = "window")]
+pub(crate) type Window = winit::Window;
+
+// Without the `window` feature, there is never a window to pass in.
+#[cfg(not(feature = "window"))]
+pub(crate) enum Window {}
+
+#[cfg(feature = "window")]
+fn window_surface_extensions(window: &Window) -> Result<Vec<&'static CStr>, Box<dyn Error>> {
+    Ok(ash_window::enumerate_required_extensions(window)?)
+}
+
+#[cfg(not(feature = "window"))]
+fn window_surface_extensions(window: &Window) -> Result<Vec<&'static CStr>, Box<dyn Error>> {
+    match *window {}
+}
+
+#[cfg(feature = "window")]
+unsafe fn create_window_surface(
+    entry: &Entry,
+    instance: &Instance,
+    window: &Window,
+) -> Result<vk::SurfaceKHR, Box<dyn Error>> {
+    Ok(ash_window::create_surface(entry, instance, window, None)?)
+}
+
+#[cfg(not(feature = "window"))]
+unsafe fn create_window_surface(
+    _entry: &Entry,
+    _instance: &Instance,
+    window: &Window,
+) -> Result<vk::SurfaceKHR, Box<dyn Error>> {
+    match *window {}
+}
+
+#[cfg(feature = "window")]
+pub(crate) fn window_physical_size(window: &Window) -> vk::Extent2D {
+    let physical_dimensions = window
+        .get_inner_size()
+        .unwrap()
+        .to_physical(window.get_hidpi_factor());
+
+    vk::Extent2D {
+        width: physical_dimensions.width as u32,
+        height: physical_dimensions.height as u32,
+    }
+}
+
+#[cfg(not(feature = "window"))]
+pub(crate) fn window_physical_size(window: &Window) -> vk::Extent2D {
+    match *window {}
+}
+
 pub struct VkRenderDevice {
     pub entry: Entry,
     pub instance: Instance,
@@ -43,6 +197,8 @@ pub struct VkRenderDevice {
     pub swapchain_loader: Swapchain,
     pub debug_report_loader: Option<DebugReport>,
     pub debug_call_back: Option<vk::DebugReportCallbackEXT>,
+    // Pass labels and object names for capture tools, when the loader or a layer has it
+    pub debug_utils: Option<DebugUtils>,
 
     pub pdevice: vk::PhysicalDevice,
     pub present_queue_family_index: u32,
@@ -52,26 +208,46 @@ pub struct VkRenderDevice {
     pub surface_format: vk::SurfaceFormatKHR,
 
     pub allocator: vk_mem::Allocator,
-    pub samplers: [vk::Sampler; 2], // immutable
+    pub samplers: [vk::Sampler; 6], // immutable
+    pub caps: DeviceCaps,
 }
 
 impl VkRenderDevice {
+    // Without a window, no surface or swapchain is created, and any device
+    // with a compute queue will do.
     pub(crate) fn new(
-        window: &winit::Window,
-        graphics_debugging: bool,
+        window: Option<&Window>,
+        validation: ValidationOptions,
         device_index: usize,
     ) -> Result<Self, Box<dyn Error>> {
         unsafe {
             let entry = ash::Entry::new()?;
-            let surface_extensions = ash_window::enumerate_required_extensions(window)?;
+            let surface_extensions = if let Some(window) = window {
+                window_surface_extensions(window)?
+            } else {
+                Vec::new()
+            };
+
+            #[cfg(feature = "openxr")]
+            let xr_instance_extensions = crate::xr::required_vulkan_instance_extensions();
+            #[cfg(not(feature = "openxr"))]
+            let xr_instance_extensions: Vec<CString> = Vec::new();
+
+            let debug_utils_supported = entry
+                .enumerate_instance_extension_properties()
+                .unwrap_or_default()
+                .iter()
+                .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == DebugUtils::name());
+
             let instance_extensions = surface_extensions
                 .iter()
                 .map(|ext| ext.as_ptr())
-                .chain(extension_names(graphics_debugging).into_iter())
+                .chain(extension_names(&validation, debug_utils_supported).into_iter())
+                .chain(xr_instance_extensions.iter().map(|ext| ext.as_ptr()))
                 .collect::<Vec<_>>();
 
             let mut layer_names = Vec::new();
-            if graphics_debugging {
+            if validation.layer_enabled() {
                 layer_names.push(CString::new("VK_LAYER_KHRONOS_validation").unwrap());
             }
 
@@ -82,11 +258,21 @@ impl VkRenderDevice {
 
             let app_desc = vk::ApplicationInfo::builder().api_version(vk::make_version(1, 0, 0));
 
-            let instance_desc = vk::InstanceCreateInfo::builder()
+            let enabled_validation_features = validation.enabled_validation_features();
+            let disabled_validation_features = validation.disabled_validation_features();
+            let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+                .enabled_validation_features(&enabled_validation_features)
+                .disabled_validation_features(&disabled_validation_features);
+
+            let mut instance_desc = vk::InstanceCreateInfo::builder()
                 .application_info(&app_desc)
                 .enabled_layer_names(&layers_names_raw)
                 .enabled_extension_names(&instance_extensions);
 
+            if !enabled_validation_features.is_empty() || !disabled_validation_features.is_empty() {
+                instance_desc = instance_desc.push_next(&mut validation_features_info);
+            }
+
             let instance = entry.create_instance(&instance_desc, None)?;
 
             let debug_info = vk::DebugReportCallbackCreateInfoEXT::builder()
@@ -100,7 +286,7 @@ impl VkRenderDevice {
             let debug_report_loader;
             let debug_call_back;
 
-            if graphics_debugging {
+            if validation.layer_enabled() {
                 let loader = DebugReport::new(&entry, &instance);
                 debug_call_back = Some(
                     loader
@@ -113,22 +299,40 @@ impl VkRenderDevice {
                 debug_call_back = None;
             }
 
+            let debug_utils = if debug_utils_supported {
+                Some(DebugUtils::new(&entry, &instance))
+            } else {
+                None
+            };
+
             // Create a surface from winit window.
-            let surface = ash_window::create_surface(&entry, &instance, window, None)?;
+            let surface = if let Some(window) = window {
+                create_window_surface(&entry, &instance, window)?
+            } else {
+                vk::SurfaceKHR::null()
+            };
 
             let pdevices = instance
                 .enumerate_physical_devices()
                 .expect("Physical device error");
             let surface_loader = Surface::new(&entry, &instance);
+
+            // A headset dictates which device to use
+            #[cfg(feature = "openxr")]
+            let required_pdevice = crate::xr::required_vulkan_physical_device(&instance);
+            #[cfg(not(feature = "openxr"))]
+            let required_pdevice: Option<vk::PhysicalDevice> = None;
+
             let (pdevice, present_queue_family_index) = pdevices
                 .iter()
+                .filter(|pdevice| required_pdevice.map_or(true, |p| p == **pdevice))
                 .map(|pdevice| {
                     instance
                         .get_physical_device_queue_family_properties(*pdevice)
                         .iter()
                         .enumerate()
                         .filter_map(|(index, ref info)| {
-                            let supports_graphic_and_surface =
+                            let supports_graphic_and_surface = if window.is_some() {
                                 info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
                                     && surface_loader
                                         .get_physical_device_surface_support(
@@ -136,7 +340,10 @@ impl VkRenderDevice {
                                             index as u32,
                                             surface,
                                         )
-                                        .unwrap();
+                                        .unwrap()
+                            } else {
+                                info.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                            };
                             match supports_graphic_and_surface {
                                 true => Some((*pdevice, index)),
                                 _ => None,
@@ -157,8 +364,10 @@ impl VkRenderDevice {
 
             let device_memory_properties = instance.get_physical_device_memory_properties(pdevice);
 
-            let device_extension_names_raw = vec![
-                Swapchain::name().as_ptr(),
+            let caps = DeviceCaps::query(&entry, &instance, pdevice);
+            tracing::info!("Device caps: {:?}", caps);
+
+            let mut device_extension_names_raw = vec![
                 //RayTracing::name().as_ptr(),
                 vk::ExtDescriptorIndexingFn::name().as_ptr(),
                 vk::ExtScalarBlockLayoutFn::name().as_ptr(),
@@ -170,6 +379,16 @@ impl VkRenderDevice {
                 //vk::KhrImagelessFramebufferFn::name().as_ptr(),
                 vk::KhrImageFormatListFn::name().as_ptr(),
             ];
+            device_extension_names_raw.extend(caps.device_extension_names());
+            if window.is_some() {
+                device_extension_names_raw.push(Swapchain::name().as_ptr());
+            }
+
+            #[cfg(feature = "openxr")]
+            let xr_device_extensions = crate::xr::required_vulkan_device_extensions();
+            #[cfg(not(feature = "openxr"))]
+            let xr_device_extensions: Vec<CString> = Vec::new();
+            device_extension_names_raw.extend(xr_device_extensions.iter().map(|ext| ext.as_ptr()));
 
             let priorities = [1.0];
 
@@ -198,19 +417,52 @@ impl VkRenderDevice {
                     .imageless_framebuffer(true)
                     .build();
 
+            let mut cooperative_matrix = vk::PhysicalDeviceCooperativeMatrixFeaturesNV::builder()
+                .cooperative_matrix(caps.cooperative_matrix)
+                .build();
+
+            let mut cooperative_matrix_khr =
+                crate::device_caps::PhysicalDeviceCooperativeMatrixFeaturesKHR {
+                    cooperative_matrix: vk::TRUE,
+                    ..Default::default()
+                };
+
+            let mut storage_16bit = vk::PhysicalDevice16BitStorageFeatures::builder()
+                .storage_buffer16_bit_access(caps.storage_16bit)
+                .build();
+
+            let mut storage_8bit = vk::PhysicalDevice8BitStorageFeaturesKHR::builder()
+                .storage_buffer8_bit_access(caps.storage_8bit)
+                .build();
+
             let mut features2 = vk::PhysicalDeviceFeatures2::default();
             instance
                 .fp_v1_1()
                 .get_physical_device_features2(pdevice, &mut features2);
 
-            let device_create_info = vk::DeviceCreateInfo::builder()
+            let mut device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&queue_info)
                 .enabled_extension_names(&device_extension_names_raw)
                 .enabled_features(&features2.features)
                 .push_next(&mut scalar_block)
                 .push_next(&mut descriptor_indexing)
-                .push_next(&mut imageless_framebuffer)
-                .build();
+                .push_next(&mut imageless_framebuffer);
+
+            if caps.cooperative_matrix_khr {
+                device_create_info = device_create_info.push_next(&mut cooperative_matrix_khr);
+            } else if caps.cooperative_matrix {
+                device_create_info = device_create_info.push_next(&mut cooperative_matrix);
+            }
+
+            if caps.storage_16bit {
+                device_create_info = device_create_info.push_next(&mut storage_16bit);
+            }
+
+            if caps.storage_8bit {
+                device_create_info = device_create_info.push_next(&mut storage_8bit);
+            }
+
+            let device_create_info = device_create_info.build();
 
             let device: Device = instance
                 .create_device(pdevice, &device_create_info, None)
@@ -230,20 +482,27 @@ impl VkRenderDevice {
 
             let present_queue = device.get_device_queue(present_queue_family_index as u32, 0);
 
-            let surface_formats = surface_loader
-                .get_physical_device_surface_formats(pdevice, surface)
-                .unwrap();
-            let surface_format = surface_formats
-                .iter()
-                .map(|sfmt| match sfmt.format {
-                    vk::Format::UNDEFINED => vk::SurfaceFormatKHR {
-                        format: vk::Format::B8G8R8_UNORM,
-                        color_space: sfmt.color_space,
-                    },
-                    _ => sfmt.clone(),
-                })
-                .nth(0)
-                .expect("Unable to find suitable surface format.");
+            let surface_format = if window.is_some() {
+                let surface_formats = surface_loader
+                    .get_physical_device_surface_formats(pdevice, surface)
+                    .unwrap();
+                surface_formats
+                    .iter()
+                    .map(|sfmt| match sfmt.format {
+                        vk::Format::UNDEFINED => vk::SurfaceFormatKHR {
+                            format: vk::Format::B8G8R8_UNORM,
+                            color_space: sfmt.color_space,
+                        },
+                        _ => sfmt.clone(),
+                    })
+                    .nth(0)
+                    .expect("Unable to find suitable surface format.")
+            } else {
+                vk::SurfaceFormatKHR {
+                    format: vk::Format::B8G8R8A8_UNORM,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                }
+            };
 
             let swapchain_loader = Swapchain::new(&instance, &device);
 
@@ -283,6 +542,28 @@ impl VkRenderDevice {
                 )
                 .unwrap();
 
+            // In the order of the `SAMPLER_SHADOW_*` indices
+            let shadow_sampler = |compare_op| {
+                device
+                    .create_sampler(
+                        &vk::SamplerCreateInfo {
+                            mag_filter: vk::Filter::LINEAR,
+                            min_filter: vk::Filter::LINEAR,
+                            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                            max_anisotropy: 1.0,
+                            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+                            compare_enable: 1,
+                            compare_op,
+                            ..Default::default()
+                        },
+                        None,
+                    )
+                    .unwrap()
+            };
+
             Ok(Self {
                 entry,
                 instance,
@@ -296,14 +577,52 @@ impl VkRenderDevice {
                 present_queue,
                 swapchain_loader,
                 allocator,
-                samplers: [sampler_linear, sampler_linear_clamp],
+                samplers: [
+                    sampler_linear,
+                    sampler_linear_clamp,
+                    shadow_sampler(vk::CompareOp::LESS),
+                    shadow_sampler(vk::CompareOp::LESS_OR_EQUAL),
+                    shadow_sampler(vk::CompareOp::GREATER),
+                    shadow_sampler(vk::CompareOp::GREATER_OR_EQUAL),
+                ],
+                caps,
                 debug_call_back,
                 debug_report_loader,
+                debug_utils,
                 surface,
             })
         }
     }
 
+    // Labels the commands recorded until the matching `end_debug_label`.
+    pub(crate) unsafe fn begin_debug_label(&self, cb: vk::CommandBuffer, name: &str) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            let name = CString::new(name.replace('\0', "")).unwrap();
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+            debug_utils.cmd_begin_debug_utils_label(cb, &label);
+        }
+    }
+
+    pub(crate) unsafe fn end_debug_label(&self, cb: vk::CommandBuffer) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            debug_utils.cmd_end_debug_utils_label(cb);
+        }
+    }
+
+    // Objects can be renamed, e.g. transient textures whenever a pass takes them.
+    pub(crate) fn set_debug_object_name<T: vk::Handle>(&self, object: T, name: &str) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            let name = CString::new(name.replace('\0', "")).unwrap();
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+                .object_type(T::TYPE)
+                .object_handle(object.as_raw())
+                .object_name(&name);
+            unsafe {
+                let _ = debug_utils.debug_utils_set_object_name(self.device.handle(), &name_info);
+            }
+        }
+    }
+
     pub(crate) fn create_bindless_resource_descriptor_set(
         device: &Device,
         descriptor_type: vk::DescriptorType,
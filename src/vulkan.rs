@@ -9,9 +9,24 @@ mod vk_backend_internals {
     static mut VK_RENDER_DEVICE: Option<VkRenderDevice> = None;
     static mut VK_BACKEND_STATE: Option<RwLock<Arc<VkBackendState>>> = None;
 
+    #[cfg(feature = "window")]
     pub fn initialize_vulkan_backend(
         window: &winit::Window,
-        graphics_debugging: bool,
+        validation: ValidationOptions,
+        vsync: bool,
+        device_index: usize,
+    ) {
+        initialize_vulkan_backend_impl(Some(window), validation, vsync, device_index);
+    }
+
+    // Instance, device and queue only; frames are driven with `begin_headless_frame`.
+    pub fn initialize_headless_vulkan_backend(validation: ValidationOptions, device_index: usize) {
+        initialize_vulkan_backend_impl(None, validation, false, device_index);
+    }
+
+    fn initialize_vulkan_backend_impl(
+        window: Option<&Window>,
+        validation: ValidationOptions,
         vsync: bool,
         device_index: usize,
     ) {
@@ -20,10 +35,10 @@ mod vk_backend_internals {
             assert!(VK_BACKEND_STATE.is_none());
         }
 
-        let device = VkRenderDevice::new(window, graphics_debugging, device_index)
+        let device = VkRenderDevice::new(window, validation, device_index)
             .expect("VkRenderDevice creation failed");
-        let bs = VkBackendState::new(&device, window, graphics_debugging, vsync)
-            .expect("VkBackendState creation failed");
+        let bs =
+            VkBackendState::new(&device, window, vsync).expect("VkBackendState creation failed");
 
         unsafe {
             VK_RENDER_DEVICE = Some(device);
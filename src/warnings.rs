@@ -7,7 +7,7 @@ pub fn rtoy_show_warning(text: String) {
     RTOY_WARNINGS.lock().unwrap().push(text);
 }
 
-pub fn with_drain_warnings(callback: impl Fn(&mut Vec<String>)) {
+pub fn with_drain_warnings(mut callback: impl FnMut(&mut Vec<String>)) {
     let mut warnings = RTOY_WARNINGS.lock().unwrap();
     callback(&mut warnings);
     warnings.clear();
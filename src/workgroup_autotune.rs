@@ -0,0 +1,232 @@
+// Workgroup size autotuning. Shaders opt in by declaring their local size through
+// specialization constants 0..2, with the defaults used when not tuning, e.g.
+//
+//   layout(local_size_x_id = 0, local_size_y_id = 1, local_size_x = 8, local_size_y = 8) in;
+//
+// and by registering candidate sizes via `set_workgroup_autotune_candidates`. A pipeline
+// is then built per candidate, and dispatches cycle through them, each timed by the GPU
+// profiler as `<name> [XxYxZ]`. Once all candidates have enough samples, the fastest one
+// is used from then on, and remembered per device if a cache file is set.
+//
+// Only passes which get recorded every frame make progress while tuning.
+
+use crate::gpu_profiler;
+use crate::shader_cache;
+use snoozy::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct WorkgroupAutotuneState {
+    candidates: HashMap<String, Vec<[u32; 3]>>,
+    // Per device and driver, then shader
+    choices: HashMap<(String, String), [u32; 3]>,
+    next_variant: HashMap<String, usize>,
+    sample_count: usize,
+    cache_path: Option<String>,
+    invalidation_triggers: HashMap<String, Vec<Box<dyn Fn() + Send + Sync>>>,
+}
+
+lazy_static! {
+    static ref WORKGROUP_AUTOTUNE: Mutex<WorkgroupAutotuneState> =
+        Mutex::new(WorkgroupAutotuneState {
+            candidates: HashMap::new(),
+            choices: HashMap::new(),
+            next_variant: HashMap::new(),
+            sample_count: 16,
+            cache_path: None,
+            invalidation_triggers: HashMap::new(),
+        });
+}
+
+fn scope_name(shader_name: &str, size: [u32; 3]) -> String {
+    format!("{} [{}x{}x{}]", shader_name, size[0], size[1], size[2])
+}
+
+// Rebuilds the shader with a pipeline per candidate. Previous picks are kept if they're
+// among the candidates. An empty list disables tuning.
+pub fn set_workgroup_autotune_candidates(shader_name: &str, candidates: Vec<[u32; 3]>) {
+    let triggers = {
+        let mut state = WORKGROUP_AUTOTUNE.lock().unwrap();
+        state.next_variant.remove(shader_name);
+        state
+            .choices
+            .retain(|(_, shader), size| shader != shader_name || candidates.contains(size));
+        if candidates.is_empty() {
+            state.candidates.remove(shader_name);
+        } else {
+            state.candidates.insert(shader_name.to_owned(), candidates);
+        }
+        state
+            .invalidation_triggers
+            .remove(shader_name)
+            .unwrap_or_default()
+    };
+
+    for trigger in triggers {
+        trigger();
+    }
+}
+
+// Samples needed per candidate before picking one. Capped by `set_gpu_timing_history_len`.
+pub fn set_workgroup_autotune_sample_count(count: usize) {
+    WORKGROUP_AUTOTUNE.lock().unwrap().sample_count = count.max(1);
+}
+
+// Loads previously tuned sizes from `path`, and saves new ones there.
+pub fn set_workgroup_autotune_cache_path(path: &str) -> std::io::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut state = WORKGROUP_AUTOTUNE.lock().unwrap();
+    for line in text.lines() {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if let [device, shader, size] = parts.as_slice() {
+            let size: Vec<u32> = size
+                .split_whitespace()
+                .filter_map(|s| s.parse().ok())
+                .collect();
+            if let [x, y, z] = size.as_slice() {
+                state
+                    .choices
+                    .insert((device.to_string(), shader.to_string()), [*x, *y, *z]);
+            }
+        }
+    }
+
+    state.cache_path = Some(path.to_owned());
+    Ok(())
+}
+
+// The size picked for `shader_name` on the current device, if tuning has finished.
+pub fn workgroup_autotune_choice(shader_name: &str) -> Option<[u32; 3]> {
+    let key = (shader_cache::device_cache_id(), shader_name.to_owned());
+    WORKGROUP_AUTOTUNE
+        .lock()
+        .unwrap()
+        .choices
+        .get(&key)
+        .copied()
+}
+
+// The candidates for `shader_name`, as an op which gets invalidated when they change.
+// Shaders depending on it get rebuilt along with it, without registering triggers
+// of their own on every compile.
+#[snoozy]
+pub async fn workgroup_autotune_candidates_snoozy(
+    ctx: Context,
+    shader_name: &String,
+) -> Result<Vec<[u32; 3]>> {
+    let mut state = WORKGROUP_AUTOTUNE.lock().unwrap();
+    state
+        .invalidation_triggers
+        .entry(shader_name.clone())
+        .or_default()
+        .push(Box::new(ctx.get_invalidation_trigger()));
+    Ok(state
+        .candidates
+        .get(shader_name)
+        .cloned()
+        .unwrap_or_default())
+}
+
+// Picks one of `variants` for the next dispatch. While tuning, also returns the name
+// to profile the dispatch under.
+pub(crate) fn select_workgroup_variant(
+    shader_name: &str,
+    variants: &[[u32; 3]],
+) -> (usize, Option<String>) {
+    let key = (shader_cache::device_cache_id(), shader_name.to_owned());
+    let mut state = WORKGROUP_AUTOTUNE.lock().unwrap();
+
+    if let Some(choice) = state.choices.get(&key) {
+        if let Some(idx) = variants.iter().position(|v| v == choice) {
+            return (idx, None);
+        }
+    }
+
+    let next = state.next_variant.entry(key.1).or_insert(0);
+    let idx = *next % variants.len();
+    *next += 1;
+
+    (idx, Some(scope_name(shader_name, variants[idx])))
+}
+
+pub(crate) fn end_frame() {
+    if WORKGROUP_AUTOTUNE.lock().unwrap().candidates.is_empty() {
+        return;
+    }
+
+    let timings: HashMap<String, gpu_profiler::GpuPassTimingStats> =
+        gpu_profiler::gpu_pass_timing_stats()
+            .into_iter()
+            .map(|s| (s.name.clone(), s))
+            .collect();
+    // Scopes can't report more samples than their history holds
+    let history_len = gpu_profiler::gpu_timing_history_len();
+
+    let mut state = WORKGROUP_AUTOTUNE.lock().unwrap();
+    let required_samples = state.sample_count.min(history_len);
+
+    let device = shader_cache::device_cache_id();
+    let mut new_choices = Vec::new();
+
+    for (shader_name, candidates) in state.candidates.iter() {
+        let key = (device.clone(), shader_name.clone());
+        if state.choices.contains_key(&key) {
+            continue;
+        }
+
+        let candidate_timings: Option<Vec<(f64, [u32; 3])>> = candidates
+            .iter()
+            .map(|size| {
+                let stats = timings.get(&scope_name(shader_name, *size))?;
+                if stats.sample_count >= required_samples {
+                    Some((stats.avg_ms, *size))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        if let Some(candidate_timings) = candidate_timings {
+            let (avg_ms, size) = candidate_timings
+                .into_iter()
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+                .unwrap();
+
+            tracing::info!(
+                "Picked a workgroup size of {:?} for {} ({:.3}ms)",
+                size,
+                shader_name,
+                avg_ms
+            );
+            new_choices.push((key, size));
+        }
+    }
+
+    if new_choices.is_empty() {
+        return;
+    }
+
+    state.choices.extend(new_choices);
+
+    if let Some(path) = state.cache_path.as_ref() {
+        let text: String = state
+            .choices
+            .iter()
+            .map(|((device, shader), size)| {
+                format!(
+                    "{}\t{}\t{} {} {}\n",
+                    device, shader, size[0], size[1], size[2]
+                )
+            })
+            .collect();
+
+        if let Err(err) = std::fs::write(path, text) {
+            tracing::warn!("Could not save workgroup sizes to {}: {}", path, err);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+// Headset output via OpenXR. Enabled with the `openxr` feature, and at runtime via `--xr`.
+//
+// The OpenXR instance has to exist before Vulkan is initialized, as it dictates
+// the physical device and the extensions to use. Once the device is up, a session
+// is started, and every frame:
+//
+// * `begin_frame` waits for the headset's frame timing, and locates the eyes,
+//   which are then available via `xr_eye_matrices`;
+// * `submit_stereo_texture` copies the final image, laid out side-by-side
+//   as by `stereo_layout_tex`, into the per-eye swapchains;
+// * `end_frame` hands the images to the compositor after the frame has been submitted.
+
+use crate::camera::CameraMatrices;
+use crate::math::*;
+use crate::stereo::Eye;
+use crate::texture::Texture;
+use crate::vulkan::*;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use ash::vk::Handle;
+use openxr as xr;
+use snoozy::Result;
+use std::ffi::CString;
+use std::sync::Mutex;
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+struct EyeSwapchain {
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    resolution: (u32, u32),
+}
+
+struct XrSession {
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    space: xr::Space,
+    swapchains: Vec<EyeSwapchain>,
+    running: bool,
+}
+
+struct XrFrame {
+    state: xr::FrameState,
+    views: Vec<xr::View>,
+    images_acquired: bool,
+}
+
+struct XrState {
+    _entry: xr::Entry,
+    instance: xr::Instance,
+    system: xr::SystemId,
+    session: Option<XrSession>,
+    frame: Option<XrFrame>,
+    event_storage: xr::EventDataBuffer,
+}
+
+lazy_static! {
+    static ref XR: Mutex<Option<XrState>> = Mutex::new(None);
+}
+
+pub(crate) fn initialize_instance() -> Result<()> {
+    let entry = xr::Entry::load()?;
+
+    let available_extensions = entry.enumerate_extensions()?;
+    if !available_extensions.khr_vulkan_enable {
+        bail!("The OpenXR runtime doesn't support Vulkan");
+    }
+
+    let mut enabled_extensions = xr::ExtensionSet::default();
+    enabled_extensions.khr_vulkan_enable = true;
+
+    let instance = entry.create_instance(
+        &xr::ApplicationInfo {
+            application_name: "rendertoy",
+            application_version: 0,
+            engine_name: "rendertoy",
+            engine_version: 0,
+        },
+        &enabled_extensions,
+        &[],
+    )?;
+
+    let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+
+    // Must be queried before creating a session, even though we don't use the result
+    let _ = instance.graphics_requirements::<xr::Vulkan>(system)?;
+
+    *XR.lock().unwrap() = Some(XrState {
+        _entry: entry,
+        instance,
+        system,
+        session: None,
+        frame: None,
+        event_storage: xr::EventDataBuffer::new(),
+    });
+
+    Ok(())
+}
+
+fn split_extension_names(names: String) -> Vec<CString> {
+    names
+        .split_whitespace()
+        .map(|name| CString::new(name).unwrap())
+        .collect()
+}
+
+pub(crate) fn required_vulkan_instance_extensions() -> Vec<CString> {
+    XR.lock()
+        .unwrap()
+        .as_ref()
+        .map(|xr| {
+            split_extension_names(
+                xr.instance
+                    .vulkan_instance_extensions(xr.system)
+                    .expect("vulkan_instance_extensions"),
+            )
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn required_vulkan_device_extensions() -> Vec<CString> {
+    XR.lock()
+        .unwrap()
+        .as_ref()
+        .map(|xr| {
+            split_extension_names(
+                xr.instance
+                    .vulkan_device_extensions(xr.system)
+                    .expect("vulkan_device_extensions"),
+            )
+        })
+        .unwrap_or_default()
+}
+
+// The device which the headset is connected to.
+pub(crate) fn required_vulkan_physical_device(
+    instance: &ash::Instance,
+) -> Option<vk::PhysicalDevice> {
+    XR.lock().unwrap().as_ref().map(|xr| unsafe {
+        let pdevice = xr
+            .instance
+            .vulkan_graphics_device(xr.system, instance.handle().as_raw() as _)
+            .expect("vulkan_graphics_device");
+        vk::PhysicalDevice::from_raw(pdevice as _)
+    })
+}
+
+pub(crate) fn create_session() -> Result<()> {
+    let mut xr_state = XR.lock().unwrap();
+    let xr_state = match xr_state.as_mut() {
+        Some(xr_state) => xr_state,
+        None => return Ok(()),
+    };
+
+    let vk = vk();
+    let (session, frame_waiter, frame_stream) = unsafe {
+        xr_state.instance.create_session::<xr::Vulkan>(
+            xr_state.system,
+            &xr::vulkan::SessionCreateInfo {
+                instance: vk.instance.handle().as_raw() as _,
+                physical_device: vk.pdevice.as_raw() as _,
+                device: vk.device.handle().as_raw() as _,
+                queue_family_index: vk.present_queue_family_index,
+                queue_index: 0,
+            },
+        )?
+    };
+
+    let space =
+        session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+
+    let formats = session.enumerate_swapchain_formats()?;
+    let format = [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB]
+        .iter()
+        .copied()
+        .find(|f| formats.contains(&(f.as_raw() as u32)))
+        .ok_or_else(|| format_err!("No sRGB swapchain format supported by the OpenXR runtime"))?;
+
+    let swapchains = xr_state
+        .instance
+        .enumerate_view_configuration_views(xr_state.system, VIEW_TYPE)?
+        .iter()
+        .map(|view| -> Result<EyeSwapchain> {
+            let resolution = (
+                view.recommended_image_rect_width,
+                view.recommended_image_rect_height,
+            );
+            let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+                create_flags: xr::SwapchainCreateFlags::EMPTY,
+                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                    | xr::SwapchainUsageFlags::TRANSFER_DST,
+                format: format.as_raw() as u32,
+                sample_count: 1,
+                width: resolution.0,
+                height: resolution.1,
+                face_count: 1,
+                array_size: 1,
+                mip_count: 1,
+            })?;
+            let images = swapchain
+                .enumerate_images()?
+                .into_iter()
+                .map(vk::Image::from_raw)
+                .collect();
+
+            Ok(EyeSwapchain {
+                swapchain,
+                images,
+                resolution,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    xr_state.session = Some(XrSession {
+        session,
+        frame_waiter,
+        frame_stream,
+        space,
+        swapchains,
+        running: false,
+    });
+
+    Ok(())
+}
+
+fn handle_events(xr_state: &mut XrState) -> Result<()> {
+    let session = match xr_state.session.as_mut() {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+
+    while let Some(event) = xr_state.instance.poll_event(&mut xr_state.event_storage)? {
+        if let xr::Event::SessionStateChanged(e) = event {
+            tracing::info!("OpenXR session state: {:?}", e.state());
+            match e.state() {
+                xr::SessionState::READY => {
+                    session.session.begin(VIEW_TYPE)?;
+                    session.running = true;
+                }
+                xr::SessionState::STOPPING => {
+                    session.session.end()?;
+                    session.running = false;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Waits for the headset's next frame slot, and locates the eyes.
+pub(crate) fn begin_frame() {
+    let mut xr_state = XR.lock().unwrap();
+    let xr_state = match xr_state.as_mut() {
+        Some(xr_state) => xr_state,
+        None => return,
+    };
+
+    handle_events(xr_state).expect("OpenXR event handling failed");
+
+    let session = match xr_state.session.as_mut() {
+        Some(session) if session.running => session,
+        _ => return,
+    };
+
+    let state = session.frame_waiter.wait().expect("xrWaitFrame");
+    session.frame_stream.begin().expect("xrBeginFrame");
+
+    let views = if state.should_render {
+        session
+            .session
+            .locate_views(VIEW_TYPE, state.predicted_display_time, &session.space)
+            .expect("xrLocateViews")
+            .1
+    } else {
+        Vec::new()
+    };
+
+    xr_state.frame = Some(XrFrame {
+        state,
+        views,
+        images_acquired: false,
+    });
+}
+
+// Camera matrices of the eye as located for the current frame, if a headset is active.
+pub fn xr_eye_matrices(eye: Eye, near_dist: f32) -> Option<CameraMatrices> {
+    let xr_state = XR.lock().unwrap();
+    let view = xr_state
+        .as_ref()?
+        .frame
+        .as_ref()?
+        .views
+        .get(eye as usize)?
+        .clone();
+
+    let (l, r) = (view.fov.angle_left.tan(), view.fov.angle_right.tan());
+    let (d, u) = (view.fov.angle_down.tan(), view.fov.angle_up.tan());
+    let (w, h) = (2.0 / (r - l), 2.0 / (u - d));
+    let (x_off, y_off) = ((r + l) / (r - l), (u + d) / (u - d));
+
+    // Same infinite reverse-Z projection as `FirstPersonCamera`, but asymmetric
+    let view_to_clip = Mat4::from_cols(
+        Vec4::new(w, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, h, 0.0, 0.0),
+        Vec4::new(x_off, y_off, 0.0, -1.0),
+        Vec4::new(0.0, 0.0, near_dist, 0.0),
+    );
+    let clip_to_view = Mat4::from_cols(
+        Vec4::new(1.0 / w, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0 / h, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0 / near_dist),
+        Vec4::new(x_off / w, y_off / h, -1.0, 0.0),
+    );
+
+    let p = view.pose.position;
+    let o = view.pose.orientation;
+    let view_to_world = Mat4::from_translation(Vec3::new(p.x, p.y, p.z))
+        * Mat4::from_quat(Quat::from_xyzw(o.x, o.y, o.z, o.w));
+
+    Some(CameraMatrices {
+        view_to_clip,
+        clip_to_view,
+        world_to_view: view_to_world.inverse(),
+        view_to_world,
+    })
+}
+
+// Per-eye swapchain resolution; render the stereo pair at this size.
+pub fn xr_eye_resolution() -> Option<(u32, u32)> {
+    let xr_state = XR.lock().unwrap();
+    let session = xr_state.as_ref()?.session.as_ref()?;
+    session.swapchains.first().map(|sc| sc.resolution)
+}
+
+// Records copies of the left and right halves of `tex` into the eye swapchains.
+pub(crate) fn submit_stereo_texture(tex: &Texture) {
+    let mut xr_state = XR.lock().unwrap();
+    let xr_state = match xr_state.as_mut() {
+        Some(xr_state) => xr_state,
+        None => return,
+    };
+
+    let (session, frame) = match (xr_state.session.as_mut(), xr_state.frame.as_mut()) {
+        (Some(session), Some(frame)) if frame.state.should_render => (session, frame),
+        _ => return,
+    };
+
+    let (vk, vk_state) = vk_all();
+    let cb = vk_state.current_frame().command_buffer.lock().unwrap().cb;
+
+    record_image_barrier(
+        &vk.device,
+        cb,
+        ImageBarrier::new(
+            tex.image,
+            vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+            vk_sync::AccessType::TransferRead,
+        ),
+    );
+
+    let eye_width = (tex.key.width / 2) as i32;
+    for (eye_idx, eye) in session.swapchains.iter_mut().enumerate() {
+        let image_idx = eye
+            .swapchain
+            .acquire_image()
+            .expect("xrAcquireSwapchainImage");
+        eye.swapchain
+            .wait_image(xr::Duration::INFINITE)
+            .expect("xrWaitSwapchainImage");
+        let image = eye.images[image_idx as usize];
+
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                image,
+                vk_sync::AccessType::ColorAttachmentWrite,
+                vk_sync::AccessType::TransferWrite,
+            )
+            .with_discard(true),
+        );
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let src_x = eye_idx as i32 * eye_width;
+        let blit = vk::ImageBlit {
+            src_subresource: subresource,
+            src_offsets: [
+                vk::Offset3D {
+                    x: src_x,
+                    y: 0,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: src_x + eye_width,
+                    y: tex.key.height as i32,
+                    z: 1,
+                },
+            ],
+            dst_subresource: subresource,
+            dst_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: eye.resolution.0 as i32,
+                    y: eye.resolution.1 as i32,
+                    z: 1,
+                },
+            ],
+        };
+
+        unsafe {
+            vk.device.cmd_blit_image(
+                cb,
+                tex.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        // The runtime expects images to be released in the color attachment layout
+        record_image_barrier(
+            &vk.device,
+            cb,
+            ImageBarrier::new(
+                image,
+                vk_sync::AccessType::TransferWrite,
+                vk_sync::AccessType::ColorAttachmentWrite,
+            ),
+        );
+    }
+
+    record_image_barrier(
+        &vk.device,
+        cb,
+        ImageBarrier::new(
+            tex.image,
+            vk_sync::AccessType::TransferRead,
+            vk_sync::AccessType::AnyShaderReadSampledImageOrUniformTexelBuffer,
+        ),
+    );
+
+    frame.images_acquired = true;
+}
+
+// Must be called after the frame's command buffer has been submitted.
+pub(crate) fn end_frame() {
+    let mut xr_state = XR.lock().unwrap();
+    let xr_state = match xr_state.as_mut() {
+        Some(xr_state) => xr_state,
+        None => return,
+    };
+
+    let (session, frame) = match (xr_state.session.as_mut(), xr_state.frame.take()) {
+        (Some(session), Some(frame)) => (session, frame),
+        _ => return,
+    };
+
+    if !frame.images_acquired {
+        session
+            .frame_stream
+            .end(
+                frame.state.predicted_display_time,
+                xr::EnvironmentBlendMode::OPAQUE,
+                &[],
+            )
+            .expect("xrEndFrame");
+        return;
+    }
+
+    for eye in session.swapchains.iter_mut() {
+        eye.swapchain
+            .release_image()
+            .expect("xrReleaseSwapchainImage");
+    }
+
+    let views: Vec<_> = frame
+        .views
+        .iter()
+        .zip(session.swapchains.iter())
+        .map(|(view, eye)| {
+            xr::CompositionLayerProjectionView::new()
+                .pose(view.pose)
+                .fov(view.fov)
+                .sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(&eye.swapchain)
+                        .image_array_index(0)
+                        .image_rect(xr::Rect2Di {
+                            offset: xr::Offset2Di { x: 0, y: 0 },
+                            extent: xr::Extent2Di {
+                                width: eye.resolution.0 as i32,
+                                height: eye.resolution.1 as i32,
+                            },
+                        }),
+                )
+        })
+        .collect();
+
+    session
+        .frame_stream
+        .end(
+            frame.state.predicted_display_time,
+            xr::EnvironmentBlendMode::OPAQUE,
+            &[&xr::CompositionLayerProjection::new()
+                .space(&session.space)
+                .views(&views)],
+        )
+        .expect("xrEndFrame");
+}